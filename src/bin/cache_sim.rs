@@ -0,0 +1,56 @@
+//! Replay a block access trace against simulated caches.
+//!
+//! usage: cache_sim <trace file> <capacity>[,<capacity>...] [lru|fifo]
+//! Capacities accept K/M/G suffixes, e.g. `cache_sim trace.txt 64M,256M,1G lru`.
+
+use std::path::Path;
+use std::process::exit;
+use vectorkv::engine::block_trace::{read_trace, simulate, CachePolicy};
+
+fn parse_size(s: &str) -> Option<u64> {
+    let (num, mult) = match s.chars().last()? {
+        'K' | 'k' => (&s[..s.len() - 1], 1u64 << 10),
+        'M' | 'm' => (&s[..s.len() - 1], 1 << 20),
+        'G' | 'g' => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    num.parse::<u64>().ok().map(|n| n * mult)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} <trace file> <capacity>[,<capacity>...] [lru|fifo]", args[0]);
+        exit(2);
+    }
+    let policy = match args.get(3).map(String::as_str) {
+        None | Some("lru") => CachePolicy::Lru,
+        Some("fifo") => CachePolicy::Fifo,
+        Some(p) => {
+            eprintln!("unknown policy: {}", p);
+            exit(2);
+        }
+    };
+    let capacities: Vec<u64> = match args[2].split(',').map(parse_size).collect() {
+        Some(c) => c,
+        None => {
+            eprintln!("bad capacity list: {}", args[2]);
+            exit(2);
+        }
+    };
+
+    let records = match read_trace(Path::new(&args[1])) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("failed to read trace: {:?}", e);
+            exit(1);
+        }
+    };
+    let observed = records.iter().filter(|r| r.cache_hit).count() as f64 / records.len().max(1) as f64;
+    println!("{} accesses, observed hit ratio {:.4}", records.len(), observed);
+
+    for capacity in capacities {
+        let result = simulate(&records, capacity, policy);
+        println!("{:?} capacity={} hit_ratio={:.4}", policy, capacity, result.hit_ratio());
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::db::write_stall::WriteStallCause;
+use crate::util::Options;
+
+/// 一窗口里 flush 比这更频繁（次/分钟），说明 memtable 太小
+const FREQUENT_FLUSHES_PER_MIN: u64 = 4;
+
+/// Write buffer size and L0 triggers currently in effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TunedOptions {
+    pub write_buffer_size: usize,
+    pub level0_slowdown_writes_trigger: usize,
+    pub level0_stop_writes_trigger: usize,
+}
+
+/// 按观察到的负载自动调 write buffer 和 L0 触发值
+///
+/// 每 `auto_tune_interval_secs` 看一次这段时间里的 flush 次数、因 L0 / memtable 被拦的写和 compaction debt：
+/// - flush 太频繁、或 debt 积压：加大 write buffer（L0 文件更少更大，写放大更低）
+/// - L0 拦了写但 debt 不高（突发写入，compaction 跟得上）：调高 L0 触发值
+/// - 整个窗口风平浪静：往配置值回退
+///
+/// 都在 `auto_tune_*` 的范围内，每次调整打一行日志。没开 `auto_tune_options` 时一直是配置值。
+pub struct AutoTuner {
+    enabled: bool,
+    interval: Duration,
+    /// 配置的值，回退的目标
+    base: TunedOptions,
+    min_write_buffer_size: usize,
+    max_write_buffer_size: usize,
+    max_stop_trigger: usize,
+    /// debt 超过它算积压
+    debt_threshold: u64,
+    state: Mutex<TunerState>,
+}
+
+struct TunerState {
+    current: TunedOptions,
+    window_start: Instant,
+    flushes: u64,
+    l0_stalls: u64,
+    memtable_stalls: u64,
+}
+
+impl AutoTuner {
+    pub fn new(options: &Options) -> Self {
+        let min_write_buffer_size = options.auto_tune_min_write_buffer_size;
+        let max_write_buffer_size = options.auto_tune_max_write_buffer_size.max(min_write_buffer_size);
+        let mut base = TunedOptions {
+            write_buffer_size: options.write_buffer_size,
+            level0_slowdown_writes_trigger: options.level0_slowdown_writes_trigger,
+            level0_stop_writes_trigger: options.level0_stop_writes_trigger,
+        };
+        if options.auto_tune_options {
+            base.write_buffer_size = base.write_buffer_size.clamp(min_write_buffer_size, max_write_buffer_size);
+        }
+        Self {
+            enabled: options.auto_tune_options,
+            interval: Duration::from_secs(options.auto_tune_interval_secs.max(1)),
+            base,
+            min_write_buffer_size,
+            max_write_buffer_size,
+            max_stop_trigger: options.auto_tune_max_level0_stop_writes_trigger.max(base.level0_stop_writes_trigger),
+            debt_threshold: options.max_bytes_for_level_base,
+            state: Mutex::new(TunerState {
+                current: base,
+                window_start: Instant::now(),
+                flushes: 0,
+                l0_stalls: 0,
+                memtable_stalls: 0,
+            }),
+        }
+    }
+
+    pub fn current(&self) -> TunedOptions {
+        self.state.lock().unwrap().current
+    }
+
+    pub fn record_flush(&self) {
+        if self.enabled {
+            self.state.lock().unwrap().flushes += 1;
+        }
+    }
+
+    /// 一次写被判成 Delayed / Stopped
+    pub fn record_stall(&self, cause: WriteStallCause) {
+        if !self.enabled {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        match cause {
+            WriteStallCause::L0FileCount => state.l0_stalls += 1,
+            WriteStallCause::MemtableLimit => state.memtable_stalls += 1,
+        }
+    }
+
+    /// 到了评估时间就按这一窗口的观察调整一次；`compaction_debt` 只在评估时才算
+    pub fn maybe_tune(&self, compaction_debt: impl FnOnce() -> u64) {
+        if !self.enabled {
+            return;
+        }
+        if self.state.lock().unwrap().window_start.elapsed() < self.interval {
+            return;
+        }
+        // debt 要拿 VersionSet 锁，不在自己的锁里算
+        let debt = compaction_debt();
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.window_start.elapsed();
+        if elapsed < self.interval {
+            // 别的线程刚评估过
+            return;
+        }
+        let flushes_per_min = state.flushes * 60 / elapsed.as_secs().max(1);
+        let before = state.current;
+        let mut next = before;
+
+        if flushes_per_min > FREQUENT_FLUSHES_PER_MIN || state.memtable_stalls > 0 || debt >= self.debt_threshold {
+            next.write_buffer_size = (before.write_buffer_size * 2).min(self.max_write_buffer_size);
+        }
+        if state.l0_stalls > 0 && debt < self.debt_threshold {
+            let step = (before.level0_stop_writes_trigger / 4).max(1);
+            next.level0_stop_writes_trigger = (before.level0_stop_writes_trigger + step).min(self.max_stop_trigger);
+        }
+        if state.flushes == 0 && state.l0_stalls == 0 && state.memtable_stalls == 0 && debt == 0 {
+            next.write_buffer_size = (before.write_buffer_size / 2)
+                .max(self.base.write_buffer_size)
+                .max(self.min_write_buffer_size);
+            next.level0_stop_writes_trigger = (before.level0_stop_writes_trigger * 3 / 4)
+                .max(self.base.level0_stop_writes_trigger);
+        }
+        // slowdown 和 stop 之间保持配置的间距
+        let gap = self.base.level0_stop_writes_trigger.saturating_sub(self.base.level0_slowdown_writes_trigger);
+        next.level0_slowdown_writes_trigger = next.level0_stop_writes_trigger.saturating_sub(gap);
+
+        if next != before {
+            log::info!(
+                "auto-tune: write_buffer_size {} -> {}, level0_slowdown_writes_trigger {} -> {}, \
+                 level0_stop_writes_trigger {} -> {} ({} flushes, {} L0 stalls, {} memtable stalls in {}s, compaction debt {})",
+                before.write_buffer_size, next.write_buffer_size,
+                before.level0_slowdown_writes_trigger, next.level0_slowdown_writes_trigger,
+                before.level0_stop_writes_trigger, next.level0_stop_writes_trigger,
+                state.flushes, state.l0_stalls, state.memtable_stalls, elapsed.as_secs(), debt,
+            );
+        }
+        state.current = next;
+        state.window_start = Instant::now();
+        state.flushes = 0;
+        state.l0_stalls = 0;
+        state.memtable_stalls = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::OpenOptions;
+
+    fn tuner() -> AutoTuner {
+        let mut options = OpenOptions::default().options;
+        options.auto_tune_options = true;
+        AutoTuner::new(&options)
+    }
+
+    /// 把窗口开头往前挪一分钟，下次 maybe_tune 就会评估
+    fn end_window(tuner: &AutoTuner) {
+        let mut state = tuner.state.lock().unwrap();
+        state.window_start = Instant::now().checked_sub(Duration::from_secs(60)).unwrap();
+    }
+
+    #[test]
+    fn disabled_tuner_keeps_the_configured_values() {
+        let options = OpenOptions::default().options;
+        let tuner = AutoTuner::new(&options);
+        for _ in 0..100 {
+            tuner.record_flush();
+        }
+        tuner.record_stall(WriteStallCause::MemtableLimit);
+        end_window(&tuner);
+        tuner.maybe_tune(|| u64::MAX);
+        assert_eq!(tuner.current().write_buffer_size, options.write_buffer_size);
+        assert_eq!(tuner.current().level0_stop_writes_trigger, options.level0_stop_writes_trigger);
+    }
+
+    #[test]
+    fn frequent_flushes_grow_the_write_buffer_up_to_the_max() {
+        let tuner = tuner();
+        let base = tuner.current();
+        // 窗口没到不评估
+        tuner.maybe_tune(|| panic!("debt is only computed when the window ends"));
+
+        for round in 1..=4 {
+            for _ in 0..10 {
+                tuner.record_flush();
+            }
+            end_window(&tuner);
+            tuner.maybe_tune(|| 0);
+            let expected = (base.write_buffer_size << round).min(256 << 20);
+            assert_eq!(tuner.current().write_buffer_size, expected);
+        }
+        assert_eq!(tuner.current().level0_stop_writes_trigger, base.level0_stop_writes_trigger);
+    }
+
+    #[test]
+    fn l0_stalls_raise_the_triggers_and_quiet_windows_lower_them() {
+        let tuner = tuner();
+        let base = tuner.current();
+        let gap = base.level0_stop_writes_trigger - base.level0_slowdown_writes_trigger;
+
+        tuner.record_stall(WriteStallCause::L0FileCount);
+        end_window(&tuner);
+        tuner.maybe_tune(|| 0);
+        let raised = tuner.current();
+        assert_eq!(raised.level0_stop_writes_trigger, base.level0_stop_writes_trigger + base.level0_stop_writes_trigger / 4);
+        assert_eq!(raised.level0_stop_writes_trigger - raised.level0_slowdown_writes_trigger, gap);
+        assert_eq!(raised.write_buffer_size, base.write_buffer_size);
+
+        // debt 积压时是 compaction 跟不上，不再放宽 L0，改加大 write buffer
+        tuner.record_stall(WriteStallCause::L0FileCount);
+        end_window(&tuner);
+        tuner.maybe_tune(|| u64::MAX);
+        assert_eq!(tuner.current().level0_stop_writes_trigger, raised.level0_stop_writes_trigger);
+        assert_eq!(tuner.current().write_buffer_size, base.write_buffer_size * 2);
+
+        for _ in 0..4 {
+            end_window(&tuner);
+            tuner.maybe_tune(|| 0);
+        }
+        assert_eq!(tuner.current(), base);
+    }
+}
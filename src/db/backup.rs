@@ -0,0 +1,540 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::{DBError, DBImpl};
+use crate::util::{file_checksum, sync_dir};
+
+/// 一次 append 的默认大小
+const DEFAULT_CHUNK_SIZE: usize = 8 << 20;
+
+/// Where backups are stored.
+///
+/// Files are uploaded in chunks through `append` and become visible once
+/// `complete` is called, so an upload cut off halfway resumes from
+/// `uploaded_len` on the next backup instead of starting over.
+/// `LocalDirTarget` writes to a directory; `backup_s3::S3Target` (the `s3`
+/// feature) writes to an S3 bucket. Other stores plug in by implementing this
+/// trait.
+pub trait BackupTarget: Send + Sync {
+    /// Bytes of `name` stored so far, counting an unfinished upload; 0 if none.
+    fn uploaded_len(&self, name: &str) -> Result<u64, DBError>;
+
+    /// Whether `name` is fully uploaded.
+    fn is_complete(&self, name: &str) -> Result<bool, DBError>;
+
+    /// Append the next chunk of `name`; `offset` is always `uploaded_len(name)`.
+    fn append(&self, name: &str, offset: u64, data: &[u8]) -> Result<(), DBError>;
+
+    /// Finish the upload of `name`.
+    fn complete(&self, name: &str) -> Result<(), DBError>;
+
+    /// crc32c of the stored `name`, as the target sees it.
+    fn checksum(&self, name: &str) -> Result<u32, DBError>;
+
+    /// Names of the complete files starting with `prefix`.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, DBError>;
+
+    /// Up to `len` bytes of the complete file `name`, starting at `offset`.
+    fn read(&self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>, DBError>;
+
+    /// Remove the complete or unfinished file `name`; removing a missing file is not an error.
+    fn delete(&self, name: &str) -> Result<(), DBError>;
+
+    /// Preferred size of one `append`.
+    fn chunk_size(&self) -> usize {
+        DEFAULT_CHUNK_SIZE
+    }
+}
+
+/// A backup in a local (or mounted) directory.
+///
+/// An upload is written to `<name>.part` and renamed into place when complete.
+pub struct LocalDirTarget {
+    dir: PathBuf,
+}
+
+impl LocalDirTarget {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, DBError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn part_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", name))
+    }
+}
+
+impl BackupTarget for LocalDirTarget {
+    fn uploaded_len(&self, name: &str) -> Result<u64, DBError> {
+        for path in [self.path(name), self.part_path(name)] {
+            match fs::metadata(&path) {
+                Ok(m) => return Ok(m.len()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(0)
+    }
+
+    fn is_complete(&self, name: &str) -> Result<bool, DBError> {
+        Ok(self.path(name).exists())
+    }
+
+    fn append(&self, name: &str, offset: u64, data: &[u8]) -> Result<(), DBError> {
+        let part = self.part_path(name);
+        if let Some(parent) = part.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut f = OpenOptions::new().create(true).append(true).open(&part)?;
+        let len = f.metadata()?.len();
+        if len != offset {
+            return Err(DBError::InvalidArgument(format!(
+                "append to {} at offset {}, but {} bytes are uploaded",
+                name, offset, len
+            )));
+        }
+        f.write_all(data)?;
+        Ok(())
+    }
+
+    fn complete(&self, name: &str) -> Result<(), DBError> {
+        let part = self.part_path(name);
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // 空文件一次 append 都没有
+        let f = OpenOptions::new().create(true).append(true).open(&part)?;
+        f.sync_all()?;
+        fs::rename(&part, &path)?;
+        if let Some(parent) = path.parent() {
+            sync_dir(parent)?;
+        }
+        Ok(())
+    }
+
+    fn checksum(&self, name: &str) -> Result<u32, DBError> {
+        Ok(file_checksum(&self.path(name))?)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, DBError> {
+        let mut names = Vec::new();
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Ok(rel) = path.strip_prefix(&self.dir) else { continue };
+                let name = rel.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+                if name.starts_with(prefix) && !name.ends_with(".part") {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn read(&self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>, DBError> {
+        let mut f = File::open(self.path(name))?;
+        f.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity(len);
+        f.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), DBError> {
+        for path in [self.path(name), self.part_path(name)] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One file of a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub size: u64,
+    pub crc32c: u32,
+}
+
+/// `backups/<id>/META`: what one backup consists of.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupMeta {
+    /// Backup number, one more than any backup started before it in the target.
+    pub id: u64,
+    /// Last sequence contained in the backup.
+    #[serde(default)]
+    pub sequence: u64,
+    /// When the backup was taken, in unix milliseconds.
+    #[serde(default)]
+    pub created_at_ms: u64,
+    /// Writes after this sequence may have been only in the WAL when the
+    /// backup was taken; a point-in-time restore replays them from the archive.
+    #[serde(default)]
+    pub wal_applied_sequence: u64,
+    pub manifest: BackupFile,
+    pub files: Vec<BackupFile>,
+}
+
+/// Outcome of `DBImpl::backup_to`.
+#[derive(Debug, Clone, Default)]
+pub struct BackupStats {
+    pub backup_id: u64,
+    pub files_uploaded: usize,
+    /// Files the target already had from an earlier backup.
+    pub files_skipped: usize,
+    pub bytes_uploaded: u64,
+}
+
+/// Outcome of `DBImpl::restore_to_timestamp`.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreStats {
+    pub backup_id: u64,
+    /// The DB is restored up to and including this sequence.
+    pub sequence: u64,
+    pub files_restored: usize,
+    pub bytes_restored: u64,
+    /// Archived WAL batches written for the next open to replay.
+    pub wal_batches: usize,
+}
+
+/// Incremental backups of a DB into one `BackupTarget`, and restores from them.
+///
+/// SSTs are stored once under `shared/`, named by file number, checksum and
+/// size, and referenced by every backup that contains them, so a new backup
+/// only uploads the files created since the previous one. Calls on the same
+/// target must not run concurrently.
+pub struct BackupEngine {
+    target: Box<dyn BackupTarget>,
+}
+
+impl BackupEngine {
+    pub fn new(target: impl BackupTarget + 'static) -> Self {
+        Self { target: Box::new(target) }
+    }
+
+    /// Backups in the local directory `dir`.
+    pub fn open_local(dir: impl Into<PathBuf>) -> Result<Self, DBError> {
+        Ok(Self::new(LocalDirTarget::new(dir)?))
+    }
+
+    pub fn target(&self) -> &dyn BackupTarget {
+        self.target.as_ref()
+    }
+
+    /// Backs up the flushed state of `db`, see `DBImpl::backup_to`.
+    pub fn create_new_backup(&self, db: &DBImpl) -> Result<BackupStats, DBError> {
+        db.backup_to(self.target.as_ref())
+    }
+
+    /// Complete backups, oldest first.
+    pub fn backup_infos(&self) -> Result<Vec<BackupMeta>, DBError> {
+        list_backups(self.target.as_ref())
+    }
+
+    /// Deletes all but the newest `num_to_keep` backups and the SSTs only they
+    /// referenced. Returns how many backups were deleted.
+    pub fn purge_old_backups(&self, num_to_keep: usize) -> Result<usize, DBError> {
+        let backups = self.backup_infos()?;
+        let n = backups.len().saturating_sub(num_to_keep);
+        for meta in &backups[..n] {
+            self.delete_backup(meta.id)?;
+        }
+        Ok(n)
+    }
+
+    /// Deletes backup `backup_id` and the SSTs no other backup references.
+    ///
+    /// Its empty `STARTED` marker stays behind so the id is never handed out again.
+    pub fn delete_backup(&self, backup_id: u64) -> Result<(), DBError> {
+        let backups = self.backup_infos()?;
+        let meta = backups.iter()
+            .find(|m| m.id == backup_id)
+            .ok_or_else(|| DBError::NotFound(format!("backup {} not found", backup_id)))?;
+        // 先删 META：删到一半失败，剩下的也不会再被当成完整的备份
+        let dir = backup_dir(backup_id);
+        self.target.delete(&format!("{}/META", dir))?;
+        self.target.delete(&meta.manifest.name)?;
+
+        let still_used: HashSet<&str> = backups.iter()
+            .filter(|m| m.id != backup_id)
+            .flat_map(|m| m.files.iter().map(|f| f.name.as_str()))
+            .collect();
+        let mut deleted = 0;
+        for f in meta.files.iter().filter(|f| !still_used.contains(f.name.as_str())) {
+            self.target.delete(&f.name)?;
+            deleted += 1;
+        }
+        log::info!("deleted backup {} and {} SSTs only it referenced", backup_id, deleted);
+        Ok(())
+    }
+
+    /// Restores backup `backup_id` into an empty DB directory `db_dir`.
+    pub fn restore_from_backup(&self, backup_id: u64, db_dir: &str) -> Result<RestoreStats, DBError> {
+        DBImpl::restore_from_backup(db_dir, self.target.as_ref(), Some(backup_id))
+    }
+
+    /// Restores the newest backup into an empty DB directory `db_dir`.
+    pub fn restore_from_latest_backup(&self, db_dir: &str) -> Result<RestoreStats, DBError> {
+        DBImpl::restore_from_backup(db_dir, self.target.as_ref(), None)
+    }
+}
+
+/// 要备份的一个 SST
+pub(crate) struct SstToBackup {
+    pub path: PathBuf,
+    pub file_number: u64,
+    pub size: u64,
+    /// MANIFEST 里记的校验和；老文件没有，现算
+    pub crc32c: Option<u32>,
+}
+
+/// 共享的 SST 按 file number + 校验和 + 大小命名：不同 DB（或恢复后接着写的 DB）
+/// 用到同一个 file number 也不会被当成同一个文件跳过
+pub(crate) fn sst_name(file_number: u64, crc32c: u32, size: u64) -> String {
+    format!("shared/{:06}_{:08x}_{}.sst", file_number, crc32c, size)
+}
+
+/// 备份里的 SST 恢复到本地叫什么：`sst/000012.sst` 和 `shared/000012_<crc>_<size>.sst`
+/// 都是 `000012.sst`
+fn local_sst_name(name: &str) -> Option<String> {
+    let stem = Path::new(name).file_stem()?.to_str()?;
+    let number: u64 = stem.split('_').next()?.parse().ok()?;
+    Some(format!("{:06}.sst", number))
+}
+
+pub(crate) fn backup_dir(id: u64) -> String {
+    format!("backups/{:020}", id)
+}
+
+/// 新备份的 id：比 target 里所有备份目录（包括没写完 META 的）都大
+///
+/// 写完 `STARTED` 才算占住了这个 id，之后开始的备份会看到它；删备份时不删它，id 不会被重用
+fn claim_backup_id(target: &dyn BackupTarget) -> Result<u64, DBError> {
+    let newest = target.list("backups/")?
+        .iter()
+        .filter_map(|name| name.strip_prefix("backups/")?.split('/').next()?.parse::<u64>().ok())
+        .max()
+        .unwrap_or(0);
+    let id = newest + 1;
+    put(target, &format!("{}/STARTED", backup_dir(id)), Vec::new(), &mut BackupStats::default())?;
+    Ok(id)
+}
+
+/// 增量备份：target 里已经有的 SST 跳过（名字里带校验和，同名就是同一个文件），
+/// 其余的传上去并对照 MANIFEST 里的校验和校验；最后写这次的 MANIFEST 和 META
+pub(crate) fn run_backup(
+    target: &dyn BackupTarget,
+    sequence: u64,
+    wal_applied_sequence: u64,
+    ssts: Vec<SstToBackup>,
+    manifest: Vec<u8>,
+) -> Result<BackupStats, DBError> {
+    let created_at_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+    let id = claim_backup_id(target)?;
+    let mut stats = BackupStats { backup_id: id, ..Default::default() };
+    let mut files = Vec::with_capacity(ssts.len());
+    for sst in ssts {
+        let crc32c = match sst.crc32c {
+            Some(crc) => crc,
+            None => file_checksum(&sst.path)?,
+        };
+        let name = sst_name(sst.file_number, crc32c, sst.size);
+        if target.is_complete(&name)? {
+            stats.files_skipped += 1;
+        } else {
+            stats.bytes_uploaded += upload(target, &name, File::open(&sst.path)?, sst.size, crc32c)?;
+            stats.files_uploaded += 1;
+        }
+        files.push(BackupFile { name, size: sst.size, crc32c });
+    }
+
+    let dir = backup_dir(id);
+    let manifest = put(target, &format!("{}/MANIFEST", dir), manifest, &mut stats)?;
+    let meta = BackupMeta { id, sequence, created_at_ms, wal_applied_sequence, manifest, files };
+    let meta_bytes = serde_json::to_vec_pretty(&meta)
+        .map_err(|e| DBError::Other(format!("encode backup meta: {}", e)))?;
+    // META 最后写：有 META 的备份才是完整的
+    put(target, &format!("{}/META", dir), meta_bytes, &mut stats)?;
+    Ok(stats)
+}
+
+fn put(target: &dyn BackupTarget, name: &str, data: Vec<u8>, stats: &mut BackupStats) -> Result<BackupFile, DBError> {
+    let size = data.len() as u64;
+    let crc32c = crc32c::crc32c(&data);
+    if !target.is_complete(name)? {
+        stats.bytes_uploaded += upload(target, name, Cursor::new(data), size, crc32c)?;
+        stats.files_uploaded += 1;
+    }
+    Ok(BackupFile { name: name.to_string(), size, crc32c })
+}
+
+/// 从 target 已有的位置接着传，传完校验；返回这次传了多少字节
+fn upload<R: Read + Seek>(target: &dyn BackupTarget, name: &str, mut src: R, len: u64, expected: u32) -> Result<u64, DBError> {
+    let mut offset = target.uploaded_len(name)?;
+    if offset > len {
+        return Err(DBError::Corruption(format!(
+            "{} has {} bytes uploaded, more than the {} bytes of the file",
+            name, offset, len
+        )));
+    }
+    let resumed_at = offset;
+    if offset > 0 {
+        log::info!("resuming upload of {} at {} / {} bytes", name, offset, len);
+    }
+    src.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::with_capacity(target.chunk_size());
+    while offset < len {
+        buf.clear();
+        (&mut src).take(target.chunk_size() as u64).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            return Err(DBError::Corruption(format!("{} ended at {} of {} bytes", name, offset, len)));
+        }
+        target.append(name, offset, &buf)?;
+        offset += buf.len() as u64;
+    }
+    target.complete(name)?;
+
+    let actual = target.checksum(name)?;
+    if actual != expected {
+        return Err(DBError::Corruption(format!(
+            "checksum mismatch for uploaded {}: expected {:08x}, target has {:08x}",
+            name, expected, actual
+        )));
+    }
+    Ok(offset - resumed_at)
+}
+
+
+/// target 里所有完整的备份（有 META 的），按 id 从小到大
+pub fn list_backups(target: &dyn BackupTarget) -> Result<Vec<BackupMeta>, DBError> {
+    let mut backups = Vec::new();
+    for name in target.list("backups/")? {
+        if !name.ends_with("/META") {
+            continue;
+        }
+        let bytes = read_all(target, &name, None)?;
+        let mut meta: BackupMeta = serde_json::from_slice(&bytes)
+            .map_err(|e| DBError::Corruption(format!("decode backup meta {}: {}", name, e)))?;
+        // 早先的 META 没有 sequence，id 就是 sequence
+        if meta.sequence == 0 {
+            meta.sequence = meta.id;
+        }
+        backups.push(meta);
+    }
+    backups.sort_by_key(|m| m.id);
+    Ok(backups)
+}
+
+/// 把备份里的 SST 和 MANIFEST 下载到 `sst_dir` / `manifest_path`，逐个校验
+pub(crate) fn restore_files(
+    target: &dyn BackupTarget,
+    meta: &BackupMeta,
+    sst_dir: &Path,
+    manifest_path: &Path,
+) -> Result<RestoreStats, DBError> {
+    let mut stats = RestoreStats { backup_id: meta.id, ..Default::default() };
+    for f in &meta.files {
+        let file_name = local_sst_name(&f.name)
+            .ok_or_else(|| DBError::Corruption(format!("bad file name {} in backup {}", f.name, meta.id)))?;
+        download(target, f, &sst_dir.join(file_name))?;
+        stats.files_restored += 1;
+        stats.bytes_restored += f.size;
+    }
+    download(target, &meta.manifest, manifest_path)?;
+    stats.files_restored += 1;
+    stats.bytes_restored += meta.manifest.size;
+    Ok(stats)
+}
+
+fn download(target: &dyn BackupTarget, f: &BackupFile, dest: &Path) -> Result<(), DBError> {
+    let data = read_all(target, &f.name, Some(f.size))?;
+    let actual = crc32c::crc32c(&data);
+    if data.len() as u64 != f.size || actual != f.crc32c {
+        return Err(DBError::Corruption(format!(
+            "backup file {} is damaged: expected {} bytes with checksum {:08x}, got {} bytes with {:08x}",
+            f.name, f.size, f.crc32c, data.len(), actual
+        )));
+    }
+    let tmp = dest.with_extension("restore.tmp");
+    {
+        let mut out = File::create(&tmp)?;
+        out.write_all(&data)?;
+        out.sync_all()?;
+    }
+    fs::rename(&tmp, dest)?;
+    Ok(())
+}
+
+/// 按块读完 `name`；`size` 不知道时读到返回空为止
+fn read_all(target: &dyn BackupTarget, name: &str, size: Option<u64>) -> Result<Vec<u8>, DBError> {
+    let chunk = target.chunk_size();
+    let mut data = Vec::new();
+    loop {
+        if size.is_some_and(|s| data.len() as u64 >= s) {
+            break;
+        }
+        let buf = target.read(name, data.len() as u64, chunk)?;
+        if buf.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&buf);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_util::test_dir;
+
+    fn backup(target: &dyn BackupTarget, sequence: u64) -> u64 {
+        run_backup(target, sequence, sequence, Vec::new(), b"manifest".to_vec()).unwrap().backup_id
+    }
+
+    #[test]
+    fn backups_of_the_same_state_get_distinct_ids() {
+        let dir = test_dir("backup-ids");
+        let engine = BackupEngine::open_local(&dir).unwrap();
+
+        // 中间没有写入：sequence 一样，id 不能撞
+        assert_eq!(backup(engine.target(), 42), 1);
+        assert_eq!(backup(engine.target(), 42), 2);
+        let infos = engine.backup_infos().unwrap();
+        assert_eq!(infos.iter().map(|m| (m.id, m.sequence)).collect::<Vec<_>>(), vec![(1, 42), (2, 42)]);
+
+        // 删掉最新的那个，它的 id 也不会再发出去
+        engine.delete_backup(2).unwrap();
+        assert_eq!(backup(engine.target(), 43), 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_unfinished_backup_keeps_its_id() {
+        let dir = test_dir("backup-unfinished");
+        let engine = BackupEngine::open_local(&dir).unwrap();
+
+        // 占了 id 但没写 META：不算完整的备份，id 也不给别人
+        assert_eq!(claim_backup_id(engine.target()).unwrap(), 1);
+        assert!(engine.backup_infos().unwrap().is_empty());
+        assert_eq!(backup(engine.target(), 7), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
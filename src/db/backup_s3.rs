@@ -0,0 +1,342 @@
+//! Backups in an S3 bucket (the `s3` cargo feature).
+//!
+//! Every file is a multipart upload created with a full-object CRC32C
+//! checksum, one part per `append`. An upload cut off halfway is found again
+//! through `ListMultipartUploads` and resumed after its last part; once
+//! complete, `HeadObject` reports the checksum the backup verifies against.
+
+use std::future::Future;
+use std::sync::mpsc;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{ChecksumAlgorithm, ChecksumMode, ChecksumType, CompletedMultipartUpload, CompletedPart, Part};
+use aws_sdk_s3::Client;
+use tokio::runtime::Runtime;
+use crate::db::backup::BackupTarget;
+use crate::error::DBError;
+
+/// S3 除最后一个 part 外每个 part 至少 5MiB
+const MIN_PART_SIZE: usize = 5 << 20;
+const DEFAULT_PART_SIZE: usize = 8 << 20;
+
+/// A backup in an S3 bucket, under `prefix`.
+///
+/// Calls are blocking: the SDK runs on a small runtime owned by the target, so
+/// it can be used from plain threads and from inside another tokio runtime.
+pub struct S3Target {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    part_size: usize,
+    runtime: Runtime,
+}
+
+impl S3Target {
+    /// A target over an already configured SDK client.
+    pub fn new(client: Client, bucket: impl Into<String>, prefix: impl Into<String>) -> Result<Self, DBError> {
+        Ok(Self::with_runtime(client, bucket.into(), prefix.into(), s3_runtime()?))
+    }
+
+    fn with_runtime(client: Client, bucket: String, prefix: String, runtime: Runtime) -> Self {
+        Self { client, bucket, prefix, part_size: DEFAULT_PART_SIZE, runtime }
+    }
+
+    /// A target with credentials and region from the environment, as the AWS
+    /// CLI would find them.
+    pub fn from_env(bucket: impl Into<String>, prefix: impl Into<String>) -> Result<Self, DBError> {
+        let runtime = s3_runtime()?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        runtime.spawn(async move {
+            let _ = tx.send(aws_config::load_from_env().await);
+        });
+        let config = rx.recv().map_err(|_| DBError::Other("loading the AWS config was dropped".into()))?;
+        Ok(Self::with_runtime(Client::new(&config), bucket.into(), prefix.into(), runtime))
+    }
+
+    /// Size of each uploaded part; at least 5MiB, as S3 requires.
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// 在自己的 runtime 上跑，当前线程等结果；调用方在别的 runtime 里也不会 panic
+    fn call<T, F>(&self, f: F) -> Result<T, DBError>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T, DBError>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.runtime.spawn(async move {
+            let _ = tx.send(f.await);
+        });
+        rx.recv().map_err(|_| DBError::Other("S3 request was dropped".into()))?
+    }
+
+    /// `key` 还没完成的 multipart upload
+    fn find_upload(&self, key: &str) -> Result<Option<String>, DBError> {
+        let (client, bucket, key) = (self.client.clone(), self.bucket.clone(), key.to_string());
+        self.call(async move {
+            let out = client.list_multipart_uploads().bucket(&bucket).prefix(&key).send().await
+                .map_err(|e| s3_error("ListMultipartUploads", &key, e))?;
+            Ok(out.uploads().iter()
+                .find(|u| u.key() == Some(key.as_str()))
+                .and_then(|u| u.upload_id().map(str::to_string)))
+        })
+    }
+
+    fn find_or_create_upload(&self, key: &str) -> Result<String, DBError> {
+        if let Some(id) = self.find_upload(key)? {
+            return Ok(id);
+        }
+        let (client, bucket, key) = (self.client.clone(), self.bucket.clone(), key.to_string());
+        self.call(async move {
+            let out = client.create_multipart_upload()
+                .bucket(&bucket)
+                .key(&key)
+                .checksum_algorithm(ChecksumAlgorithm::Crc32C)
+                .checksum_type(ChecksumType::FullObject)
+                .send()
+                .await
+                .map_err(|e| s3_error("CreateMultipartUpload", &key, e))?;
+            out.upload_id()
+                .map(str::to_string)
+                .ok_or_else(|| DBError::Other(format!("S3 returned no upload id for {}", key)))
+        })
+    }
+
+    /// 已经传上去的 part，按 part number 排
+    fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<Part>, DBError> {
+        let (client, bucket, key, upload_id) = (self.client.clone(), self.bucket.clone(), key.to_string(), upload_id.to_string());
+        self.call(async move {
+            let mut parts = Vec::new();
+            let mut marker: Option<String> = None;
+            loop {
+                let out = client.list_parts()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .upload_id(&upload_id)
+                    .set_part_number_marker(marker.take())
+                    .send()
+                    .await
+                    .map_err(|e| s3_error("ListParts", &key, e))?;
+                parts.extend(out.parts().iter().cloned());
+                match out.next_part_number_marker() {
+                    Some(next) if out.is_truncated() == Some(true) => marker = Some(next.to_string()),
+                    _ => break,
+                }
+            }
+            parts.sort_by_key(|p| p.part_number());
+            Ok(parts)
+        })
+    }
+
+    fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, data: &[u8]) -> Result<(), DBError> {
+        let (client, bucket, key, upload_id) = (self.client.clone(), self.bucket.clone(), key.to_string(), upload_id.to_string());
+        let body = ByteStream::from(data.to_vec());
+        self.call(async move {
+            client.upload_part()
+                .bucket(&bucket)
+                .key(&key)
+                .upload_id(&upload_id)
+                .part_number(part_number)
+                .checksum_algorithm(ChecksumAlgorithm::Crc32C)
+                .body(body)
+                .send()
+                .await
+                .map_err(|e| s3_error("UploadPart", &key, e))?;
+            Ok(())
+        })
+    }
+
+    /// HeadObject：(大小, CRC32C)；对象不存在（可能还没 complete）时 None
+    fn head(&self, key: &str) -> Result<Option<(u64, Option<u32>)>, DBError> {
+        let (client, bucket, key) = (self.client.clone(), self.bucket.clone(), key.to_string());
+        self.call(async move {
+            match client.head_object().bucket(&bucket).key(&key).checksum_mode(ChecksumMode::Enabled).send().await {
+                Ok(out) => Ok(Some((
+                    out.content_length().unwrap_or(0) as u64,
+                    out.checksum_crc32_c().and_then(decode_crc32c),
+                ))),
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+                Err(e) => Err(s3_error("HeadObject", &key, e)),
+            }
+        })
+    }
+}
+
+impl BackupTarget for S3Target {
+    fn uploaded_len(&self, name: &str) -> Result<u64, DBError> {
+        let key = self.key(name);
+        if let Some((size, _)) = self.head(&key)? {
+            return Ok(size);
+        }
+        match self.find_upload(&key)? {
+            Some(id) => Ok(self.list_parts(&key, &id)?.iter().map(|p| p.size().unwrap_or(0) as u64).sum()),
+            None => Ok(0),
+        }
+    }
+
+    fn is_complete(&self, name: &str) -> Result<bool, DBError> {
+        Ok(self.head(&self.key(name))?.is_some())
+    }
+
+    fn append(&self, name: &str, offset: u64, data: &[u8]) -> Result<(), DBError> {
+        let key = self.key(name);
+        let upload_id = self.find_or_create_upload(&key)?;
+        let parts = self.list_parts(&key, &upload_id)?;
+        let uploaded: u64 = parts.iter().map(|p| p.size().unwrap_or(0) as u64).sum();
+        if uploaded != offset {
+            return Err(DBError::InvalidArgument(format!(
+                "append to {} at offset {}, but {} bytes are uploaded",
+                key, offset, uploaded
+            )));
+        }
+        self.upload_part(&key, &upload_id, parts.len() as i32 + 1, data)
+    }
+
+    fn complete(&self, name: &str) -> Result<(), DBError> {
+        let key = self.key(name);
+        let upload_id = self.find_or_create_upload(&key)?;
+        let mut parts = self.list_parts(&key, &upload_id)?;
+        // 空文件也要有一个 part 才能 complete
+        if parts.is_empty() {
+            self.upload_part(&key, &upload_id, 1, &[])?;
+            parts = self.list_parts(&key, &upload_id)?;
+        }
+        let completed: Vec<CompletedPart> = parts.iter()
+            .map(|p| CompletedPart::builder()
+                .set_part_number(p.part_number())
+                .set_e_tag(p.e_tag().map(str::to_string))
+                .set_checksum_crc32_c(p.checksum_crc32_c().map(str::to_string))
+                .build())
+            .collect();
+        let (client, bucket) = (self.client.clone(), self.bucket.clone());
+        self.call(async move {
+            client.complete_multipart_upload()
+                .bucket(&bucket)
+                .key(&key)
+                .upload_id(&upload_id)
+                .checksum_type(ChecksumType::FullObject)
+                .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(completed)).build())
+                .send()
+                .await
+                .map_err(|e| s3_error("CompleteMultipartUpload", &key, e))?;
+            Ok(())
+        })
+    }
+
+    fn checksum(&self, name: &str) -> Result<u32, DBError> {
+        let key = self.key(name);
+        self.head(&key)?
+            .ok_or_else(|| DBError::NotFound(format!("{} is not uploaded", key)))?
+            .1
+            .ok_or_else(|| DBError::Corruption(format!("{} has no CRC32C checksum", key)))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, DBError> {
+        let (client, bucket, key_prefix) = (self.client.clone(), self.bucket.clone(), self.key(prefix));
+        let keys = self.call(async move {
+            let mut keys = Vec::new();
+            let mut token: Option<String> = None;
+            loop {
+                let out = client.list_objects_v2()
+                    .bucket(&bucket)
+                    .prefix(&key_prefix)
+                    .set_continuation_token(token.take())
+                    .send()
+                    .await
+                    .map_err(|e| s3_error("ListObjectsV2", &key_prefix, e))?;
+                keys.extend(out.contents().iter().filter_map(|o| o.key().map(str::to_string)));
+                match out.next_continuation_token() {
+                    Some(next) if out.is_truncated() == Some(true) => token = Some(next.to_string()),
+                    _ => break,
+                }
+            }
+            Ok(keys)
+        })?;
+        Ok(keys.into_iter()
+            .filter_map(|k| k.strip_prefix(self.prefix.as_str()).map(str::to_string))
+            .collect())
+    }
+
+    fn read(&self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>, DBError> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let (client, bucket, key) = (self.client.clone(), self.bucket.clone(), self.key(name));
+        self.call(async move {
+            let out = client.get_object()
+                .bucket(&bucket)
+                .key(&key)
+                .range(format!("bytes={}-{}", offset, offset + len as u64 - 1))
+                .send()
+                .await
+                .map_err(|e| s3_error("GetObject", &key, e))?;
+            let body = out.body.collect().await
+                .map_err(|e| DBError::Other(format!("S3 GetObject {}: {}", key, e)))?;
+            Ok(body.into_bytes().to_vec())
+        })
+    }
+
+    fn delete(&self, name: &str) -> Result<(), DBError> {
+        let key = self.key(name);
+        // 没完成的 upload 也算这个文件，一起丢掉
+        if let Some(upload_id) = self.find_upload(&key)? {
+            let (client, bucket, key) = (self.client.clone(), self.bucket.clone(), key.clone());
+            self.call(async move {
+                client.abort_multipart_upload().bucket(&bucket).key(&key).upload_id(&upload_id).send().await
+                    .map_err(|e| s3_error("AbortMultipartUpload", &key, e))?;
+                Ok(())
+            })?;
+        }
+        let (client, bucket) = (self.client.clone(), self.bucket.clone());
+        self.call(async move {
+            client.delete_object().bucket(&bucket).key(&key).send().await
+                .map_err(|e| s3_error("DeleteObject", &key, e))?;
+            Ok(())
+        })
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.part_size
+    }
+}
+
+fn s3_runtime() -> Result<Runtime, DBError> {
+    Ok(tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("vectorkv-s3")
+        .enable_all()
+        .build()?)
+}
+
+fn s3_error<E: ProvideErrorMetadata + std::error::Error + 'static, R: std::fmt::Debug>(
+    op: &str,
+    key: &str,
+    e: aws_sdk_s3::error::SdkError<E, R>,
+) -> DBError {
+    DBError::Other(format!("S3 {} {}: {}", op, key, DisplayErrorContext(e)))
+}
+
+/// S3 报的 CRC32C 是大端 4 字节的 base64
+fn decode_crc32c(encoded: &str) -> Option<u32> {
+    let bytes = aws_smithy_types::base64::decode(encoded).ok()?;
+    Some(u32::from_be_bytes(bytes.as_slice().try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_is_read_as_big_endian_base64() {
+        assert_eq!(decode_crc32c(&aws_smithy_types::base64::encode(0x1234_5678u32.to_be_bytes())), Some(0x1234_5678));
+        // composite 校验和（带 -N 后缀）或者长度不对都不认
+        assert_eq!(decode_crc32c("EjRWeA==-3"), None);
+        assert_eq!(decode_crc32c(&aws_smithy_types::base64::encode([1u8, 2, 3])), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::DBError;
+use crate::engine::mem::{RangeTombstone, SequenceNumber};
+use crate::util::sync_file;
+
+/// 导出目录里描述这些 SST 的文件
+pub const EXPORT_METADATA_FILE: &str = "EXPORT.json";
+
+/// One SST of an exported column family.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedFile {
+    /// File name inside the export directory.
+    pub file_name: String,
+    pub level: usize,
+    pub file_size: u64,
+    /// Smallest and largest internal key of the file.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub crc32c: u32,
+}
+
+/// A `delete_range` tombstone of the exported column family. Tombstones live
+/// in the MANIFEST, not in the SSTs, so they travel in `EXPORT.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedRangeTombstone {
+    pub begin: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq: SequenceNumber,
+}
+
+impl From<&RangeTombstone> for ExportedRangeTombstone {
+    fn from(t: &RangeTombstone) -> Self {
+        Self { begin: t.begin.clone(), end: t.end.clone(), seq: t.seq }
+    }
+}
+
+impl From<&ExportedRangeTombstone> for RangeTombstone {
+    fn from(t: &ExportedRangeTombstone) -> Self {
+        RangeTombstone::new(&t.begin, &t.end, t.seq)
+    }
+}
+
+/// What `DBImpl::export_column_family` wrote, stored next to the SSTs as
+/// `EXPORT.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportMetadata {
+    pub cf_name: String,
+    /// No key in the files has a higher sequence; the importing DB moves its
+    /// own counter past it.
+    pub sequence: SequenceNumber,
+    pub files: Vec<ExportedFile>,
+    /// Older exports don't have the field.
+    #[serde(default)]
+    pub range_tombstones: Vec<ExportedRangeTombstone>,
+}
+
+impl ExportMetadata {
+    pub fn read(dir: &Path) -> Result<Self, DBError> {
+        let path = dir.join(EXPORT_METADATA_FILE);
+        let bytes = fs::read(&path)?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| DBError::Corruption(format!("decode {:?}: {}", path, e)))
+    }
+
+    pub(crate) fn write(&self, dir: &Path) -> Result<(), DBError> {
+        let path = dir.join(EXPORT_METADATA_FILE);
+        let bytes = serde_json::to_vec_pretty(self)
+            .map_err(|e| DBError::Other(format!("encode export metadata: {}", e)))?;
+        fs::write(&path, bytes)?;
+        sync_file(&path, false)?;
+        Ok(())
+    }
+}
@@ -1,20 +1,54 @@
-use std::fs::File;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{self, File};
 use std::io::BufWriter;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-use crate::db::db_iterator::DBIterator;
+use std::ops::{Bound, RangeBounds};
+use std::panic::Location;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::db::db_iterator::{DBIterator, DbRangeIter};
 use crate::db::db_trait::DB;
+use crate::db::fencing::{check_token, FencingToken};
+use crate::db::iterator_tracker::{IteratorTracker, TrackedIterator};
+use crate::db::event_listener::{BackgroundErrorReason, EventListener, FlushJobInfo, TableFileCreationInfo, TableFileCreationReason, TableFileDeletionInfo};
+use crate::db::memory_usage::MemoryUsage;
+use crate::db::quota::QuotaManager;
+use crate::db::snapshot::{Snapshot, SnapshotList};
+use crate::db::ttl;
+use crate::db::auto_tuner::{AutoTuner, TunedOptions};
+use crate::db::lock_manager::{self, LockManager};
+use crate::db::range_lock::{self, RangeLease, RangeLockTable};
+use crate::db::backup::{self, BackupStats, BackupTarget, RestoreStats, SstToBackup};
+use crate::db::sst_file_writer::{ExternalFile, IngestOptions};
+use crate::db::wal_filter::{WalFilter, WalProcessingOption};
+use crate::db::recovery::{RecoveryInfo, SequenceIssue};
+use crate::db::cf_export::{ExportedFile, ExportMetadata};
+use crate::db::read_sampler::{ReadSampler, ReadTuningReport};
+use crate::db::transaction_log::TransactionLogIterator;
+use crate::db::transaction::{Transaction, TransactionOptions};
+use crate::db::txn_spill::SpillFile;
+use crate::db::write_group::WriteGroup;
+use crate::db::write_stall::{self, WriteStallCause, WriteStallCondition, WriteStallController};
 use crate::engine::background::BackgroundWorker;
-use crate::engine::mem::{ColumnFamilyId, MemTable};
+use crate::engine::mem::{ColumnFamilyId, InternalKey, MemTable, SequenceNumber};
 use crate::engine::mem::MemTableSet;
-use crate::engine::sst::TableCache;
-use crate::engine::version::VersionSet;
-use crate::engine::wal::WalManager;
-use crate::engine::wal::write_batch::WriteBatch;
-use crate::engine::sst::block::BlockCache;
+use crate::engine::mem::memtable_set::CfType;
+use crate::engine::sst::{LookupResult, TableCache};
+use crate::engine::sst::sst_reader::assign_global_seqno;
+use crate::engine::sst::iterator::DBIterator as EngineIterator;
+use crate::engine::version::{full_merge, write_current, GetStats, JobKind, JobLog, JobRecord, ManifestWriter, MergeOperator, Version, VersionEdit, VersionPins, VersionRef, VersionSet};
+use crate::engine::wal::{wal_archive, WalManager, WalWriter};
+use crate::engine::wal::write_batch::{IdempotencyToken, IdempotencyWindow, WriteBatch, WriteBatchEntry};
+use crate::engine::sst::block::{BlockCache, BlockCacheListener, PinnedBlock};
 use crate::engine::sst::table_builder::TableBuilder;
 use crate::error::DBError;
-use crate::util::{load_db_config, DbConfig, DbConfigFile, OpenOptions, Options};
+use crate::util::constants::{SYSTEM_COLUMN_FAMILY_ID, USER_COLUMN_FAMILY_ID};
+use crate::util::{file_checksum, load_db_config, properties, ColumnFamilyOptions, CpuTimer, DbConfig, DbConfigFile, DbState, DefaultAllocator, MemoryAllocator, FIRST_MANIFEST, NUM_LEVELS, OpenOptions, Options, QuotaOptions, ReadOptions, OpenFiles, process_fd_limit, process_open_fds, Span, sst_file_name, sync_dir, sync_file, VectorIndexOptions, VectorOptions, WriteOptions};
+use crate::vector::{calibrate, decode_indexed_vector, embed_all, encode_vector, encode_vector_columns, CalibrationReport, Embedder, GraphPageCache, HnswIndex, KnnRequest, KnnResponse, Metric, SpillTarget, TopK, VectorIndexType, DEFAULT_EF_CANDIDATES};
+
+/// (column family, index name)；"" 是默认（不具名）索引
+type VectorIndexKey = (ColumnFamilyId, String);
 
 pub struct DBImpl {
     name: String,
@@ -26,73 +60,197 @@ pub struct DBImpl {
     version_set: Arc<Mutex<VersionSet>>,
     bg_worker: Arc<BackgroundWorker>,
     table_cache: Arc<TableCache>,
-}
 
-#[derive(Clone)]
-pub struct Snapshot {
-    pub seq: u64,
+    /// Serializes check-and-record of idempotent batches
+    idempotency_lock: Mutex<()>,
+    /// Highest term seen, persisted in TERM whenever it moves
+    replication_term: Mutex<u64>,
+    /// Last sequence of a batch written to the WAL
+    last_wal_sequence: AtomicU64,
+
+    /// Concurrent writers joined into one WAL record per group
+    write_group: WriteGroup,
+
+    /// Serializes conflict checks of committing transactions
+    txn_commit_lock: Mutex<()>,
+
+    /// Key locks held by transactions
+    lock_manager: LockManager,
+
+    /// Advisory range leases from `lock_range`, mirrored from the system CF
+    range_locks: RangeLockTable,
+
+    /// Per column family ops/bytes quotas
+    quotas: QuotaManager,
+
+    /// Snapshots handed out by `get_snapshot` and not yet released
+    snapshots: SnapshotList,
+
+    /// Iterators handed out by `new_iterator` and not yet dropped
+    iterators: Arc<IteratorTracker>,
+
+    /// Versions pinned by iterators and reads; obsolete SSTs are deleted through it
+    version_pins: Arc<VersionPins>,
+
+    /// Per column family embedders used by `put_document`
+    embedders: RwLock<HashMap<ColumnFamilyId, Arc<dyn Embedder>>>,
+
+    /// HNSW graphs of the vector indexes with `index = "hnsw"`, built on first search
+    vector_indexes: RwLock<HashMap<VectorIndexKey, Arc<RwLock<HnswIndex>>>>,
+
+    /// Pages of on-disk vector graphs
+    vector_graph_cache: Arc<GraphPageCache>,
+
+    /// Per column family write stall state, updated by `make_room_for_write`
+    write_stall: WriteStallController,
+
+    /// Write buffer size and L0 triggers, adjusted to the workload when `auto_tune_options` is set
+    auto_tuner: AutoTuner,
+
+    /// open 时重放 WAL 的结果
+    recovery_info: Mutex<RecoveryInfo>,
+    /// 按 `read_sample_rate` 抽样的 get / seek
+    read_sampler: Arc<ReadSampler>,
+
+    /// Set on open with `warmup_on_open` while there is compaction debt
+    warming_up: AtomicBool,
+
+    /// Notified whenever a memtable leaves `flushing`
+    flush_done: Condvar,
+
+    /// Last sequence applied by `apply_replicated`; flushes move the sequence counter past it
+    replicated_sequence: AtomicU64,
+    /// The DB has applied replicated batches, `replicated_sequence` is persisted on close
+    is_replica: AtomicBool,
+    /// 只有 `&self` 的路径（比如 get 里标记了文件）要排后台任务时用
+    weak_self: Weak<DBImpl>,
 }
 
 impl DB for DBImpl {
-    fn put(&self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<(),DBError> {
+    fn put(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<(),DBError> {
         let mut batch = WriteBatch::new();
         batch.put(cf, key, value);
-        self.write(batch)
+        self.write(opts, batch)
     }
 
-    fn delete(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<(),DBError> {
+    fn delete(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8]) -> Result<(),DBError> {
         let mut batch = WriteBatch::new();
         batch.delete(cf, key);
-        self.write(batch)
+        self.write(opts, batch)
     }
 
-    fn write(&self, batch: WriteBatch) -> Result<(),DBError> {
-        // 1. 写前限流
-        self.make_room_for_write(&batch)?;
-
-        let mut vs = self.version_set.lock().unwrap();
-        let base_seq = vs.allocate_sequence(batch.entries.len() as u64);
-        drop(vs);
+    fn merge(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8], operand: &[u8]) -> Result<(),DBError> {
+        let mut batch = WriteBatch::new();
+        batch.merge(cf, key, operand);
+        self.write(opts, batch)
+    }
 
-        // 2. 写 WAL
-        if self.options.enable_write_ahead_log {
-            self.wal_manager.append_sync(base_seq, &batch)?;
-        } else {
-            self.wal_manager.append_sync(base_seq, &batch)?;
+    fn delete_range(&self, opts: &WriteOptions, cf: ColumnFamilyId, begin: &[u8], end: &[u8]) -> Result<(),DBError> {
+        if begin >= end {
+            return Err(DBError::InvalidArgument("delete_range: begin must be < end".into()));
         }
+        let mut batch = WriteBatch::new();
+        batch.delete_range(cf, begin, end);
+        self.write(opts, batch)
+    }
 
-        // 3. 写入 MemTableSet
-        let mut mem = self.memtables.lock().unwrap();
-        mem.apply(base_seq, batch)?;
+    fn write(&self, opts: &WriteOptions, mut batch: WriteBatch) -> Result<(),DBError> {
+        let _span = Span::enter("write");
+        self.check_write(opts, &batch)?;
+
+        // 0. 配额：超额直接返回 Busy，让调用方重试
+        let mut usage: HashMap<ColumnFamilyId, (u64, u64)> = HashMap::new();
+        add_write_usage(&mut usage, &batch);
+        self.quotas.acquire_write(&usage, |cf| self.quota_options(cf))?;
+
+        // 幂等：同一 client 的重复 request 直接返回成功；
+        //    记录写进同一个 batch，和数据一起原子落盘
+        let _idempotency_guard = match batch.idempotency {
+            Some(token) => {
+                let guard = self.idempotency_lock.lock().unwrap();
+                let mut window = match self.idempotency_window(&token)? {
+                    Some(window) => match window.contains(token.request_id) {
+                        Some(true) => return Ok(()),
+                        Some(false) => window,
+                        None => return Err(DBError::InvalidArgument(format!(
+                            "request {} of client {} is too old to tell whether it was applied",
+                            token.request_id, token.client_id
+                        ))),
+                    },
+                    None => IdempotencyWindow::new(token.request_id),
+                };
+                window.insert(token.request_id);
+                batch.put(SYSTEM_COLUMN_FAMILY_ID, &token.record_key(), &window.encode());
+                Some(guard)
+            }
+            None => None,
+        };
+
+        // 1. 写前限流；warmup 期间 sync 写先等 compaction 追上
+        self.check_warmup(opts)?;
+        self.make_room_for_write(&batch)?;
+
+        // 2./3. 进写组：leader 把排着的 batch 拼成一个，分一段 sequence，
+        //    写一条 WAL record 再一起写进 MemTableSet，已建好的向量索引也在这时更新
+        //    disable_wal 的只写 memtable；不要 sync 的写完 WAL 不等 fsync
+        self.write_group.submit(batch, opts, |group, group_opts| self.commit_batch(group, group_opts))?;
 
         Ok(())
     }
 
-    fn get(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>,DBError> {
-        let mem =self.memtables.lock().unwrap();
-        let seq = self.version_set.lock().unwrap().current_sequence();
-        // 现在只查 MemTableSet，它内部会依次查 active → immutables
-        if let Some(v) = mem.get(cf, seq, key) {
-            return Ok(Some(v));
-        }
+    fn get(&self, opts: &ReadOptions, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>,DBError> {
+        self.get_with_options(cf, key, opts)
+    }
 
-        self.version_set.lock().unwrap().get(cf, key)
+    fn multi_get(&self, opts: &ReadOptions, cf: ColumnFamilyId, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>,DBError> {
+        self.multi_get_with_options(cf, keys, opts)
     }
 
-    fn flush(self: &Arc<Self>, cf: ColumnFamilyId) -> Result<(),DBError> {
+    fn flush(&self, cf: ColumnFamilyId) -> Result<(),DBError> {
         let mut mem = self.memtables.lock().unwrap();
-        let seq = self.version_set.lock().unwrap().next_sequence();
-        // freeze 返回的是 Arc<MemTable>
-        let imm = mem.freeze_active(cf, seq)?;
-        let db = Arc::clone(self);
+        let (seq, file_number) = {
+            let vs = self.version_set.lock().unwrap();
+            (vs.next_sequence()?, vs.new_file_number()?)
+        };
+        mem.freeze_active(cf, seq, file_number)?;
+        // 挪进 flushing 再交出去：同一个 memtable 只会被一个 flush 拿到，等 flush 的人也看得见它
+        let mut imm = VecDeque::new();
+        while let Some(t) = mem.pick_flush_candidate(cf) {
+            imm.push_back(t);
+        }
+        // 后台队列满了 schedule 会等，等的时候不能拿着 memtable 锁，flush 线程要用
+        drop(mem);
         // 交给后台 flush
-        self.bg_worker.schedule_flush(&db, imm);
+        self.schedule_flush(imm);
 
         Ok(())
     }
 
-    fn new_iterator(&self, cf: ColumnFamilyId) -> Box<dyn DBIterator> {
-        self.version_set.lock().unwrap().new_iterator(cf)
+    #[track_caller]
+    fn new_iterator(&self, opts: &ReadOptions, cf: ColumnFamilyId) -> Box<dyn DBIterator> {
+        let (inner, version) = Self::open_iterator(&self.memtables, &self.version_set, &self.version_pins, opts, cf);
+        let prefix_extractor = match self.version_set.lock().unwrap().column_family_by_id(cf) {
+            Ok(cfd) if opts.prefix_same_as_start => cfd.options(&self.options).prefix_extractor.clone(),
+            _ => None,
+        };
+        // refresh 时用同样的参数重新打开
+        let (memtables, version_set, version_pins, opts_clone) = (
+            Arc::clone(&self.memtables),
+            Arc::clone(&self.version_set),
+            Arc::clone(&self.version_pins),
+            opts.clone(),
+        );
+        let guard = self.iterators.register(cf, Location::caller());
+        Box::new(
+            TrackedIterator::new(inner, guard, self.max_iterator_age())
+                .with_version(version)
+                .with_bounds(opts.iterate_lower_bound.clone(), opts.iterate_upper_bound.clone())
+                .with_prefix_same_as_start(prefix_extractor)
+                .with_read_sampler(self.read_sampler())
+                .with_reopen(Box::new(move || {
+                    Self::open_iterator(&memtables, &version_set, &version_pins, &opts_clone, cf)
+                })),
+        )
     }
 
     fn compact_range(
@@ -100,72 +258,132 @@ impl DB for DBImpl {
         cf: ColumnFamilyId,
         begin: Option<&[u8]>,
         end: Option<&[u8]>,
-    ) -> Result<()> {
-        self.bg_worker.schedule_compaction(cf, begin, end)
+    ) -> Result<(), DBError> {
+        self.schedule_marked_compaction(cf, begin, end);
+        Ok(())
     }
 
+    #[track_caller]
     fn get_snapshot(&self) -> Snapshot {
-        Snapshot {
-            seq: self.version_set.lock().unwrap().latest_sequence(),
-        }
+        // 各 CF 当时的 Version 跟着 snapshot 一起 pin 住：之后被 compaction 替换掉的文件
+        // 里还有 snapshot 能看到的旧版本
+        let (seq, versions) = {
+            let vs = self.version_set.lock().unwrap();
+            let versions = vs.column_families()
+                .into_iter()
+                .map(|cf| (cf, self.version_pins.pin(vs.current_version(cf))))
+                .collect();
+            (vs.current_sequence(), versions)
+        };
+        self.snapshots.register(seq, Location::caller(), versions);
+        self.check_snapshot_pressure();
+        Snapshot { seq }
     }
 
-    fn release_snapshot(&self, _snapshot: Snapshot) {
-        // Rust 自动 drop，无需人工干预
+    fn release_snapshot(&self, snapshot: Snapshot) {
+        self.snapshots.release(snapshot.seq);
     }
 
     fn flush_memtable(&self, mem: Arc<dyn MemTable>) -> Result<(),DBError> {
-        // 1️⃣ 创建 SST 文件
         let cf = mem.cf_id();
-        let mut vs = self.version_set.lock().unwrap();
-        let file_number = vs.new_file_number();
-        let file_path = self.db_config.sst_path(file_number);
-        let file = File::create(&file_path)?;
-        let cfd = vs.column_family_by_id(cf)
-            .ok_or_else(|| DBError::InvalidColumnFamily(format!("CF id {} not found", cf)))?;
-        let cf_options = cfd.options(&self.options);
-
+        let result = self.write_memtable(Arc::clone(&mem));
+        {
+            let mut tables = self.memtables.lock().unwrap();
+            match &result {
+                Ok(()) => tables.finish_flush(cf, &mem),
+                // 没装进 Version：放回 immutables，数据还读得到，之后再 flush
+                Err(_) => tables.abort_flush(cf, &mem),
+            }
+        }
+        self.flush_done.notify_all();
+        result
+    }
 
-        // 2️⃣ TableBuilder
-        let mut builder = TableBuilder::from_options(
-            file_number,
-            BufWriter::new(file),
-            &cf_options,
-        );
+    fn get_property(&self, cf: ColumnFamilyId, name: &str) -> Option<String> {
+        // DB 级别的属性，与 cf 无关
+        match name {
+            properties::NUM_SNAPSHOTS => return Some(self.snapshots.count().to_string()),
+            properties::OLDEST_SNAPSHOT_TIME => {
+                let age = self.snapshots.oldest_age().map_or(0, |d| d.as_secs());
+                return Some(age.to_string());
+            }
+            properties::OLDEST_SNAPSHOT_SEQUENCE => {
+                return Some(self.snapshots.oldest_seq().unwrap_or(0).to_string());
+            }
+            properties::OLDEST_ITERATORS => return Some(self.oldest_iterators()),
+            properties::NUM_PINNED_VERSIONS => return Some(self.version_pins.pinned_versions().to_string()),
+            properties::NUM_DEFERRED_OBSOLETE_FILES => return Some(self.version_pins.deferred_files().to_string()),
+            properties::COMPACTION_DEBT_BYTES => return Some(self.compaction_debt(cf).to_string()),
+            properties::BACKGROUND_QUEUE_DEPTH => return Some(self.bg_worker.queue_depth().to_string()),
+            properties::BACKGROUND_JOBS_DROPPED => return Some(self.bg_worker.shed_jobs().0.to_string()),
+            properties::BACKGROUND_JOBS_COALESCED => return Some(self.bg_worker.shed_jobs().1.to_string()),
+            properties::NUM_QUEUED_COMPACTIONS => return Some(self.bg_worker.pending_compactions(cf).0.to_string()),
+            properties::NUM_OPEN_FILES => return Some(self.open_files().total().to_string()),
+            properties::OPEN_FILES => return serde_json::to_string(&self.open_files()).ok(),
+            _ => {}
+        }
 
-        // 3️⃣ 遍历 memtable
-        for (key, value) in mem.iter() {
-            builder.add(key, value);
+        if name == properties::VECTOR_INDEX_STATS {
+            return Some(self.vector_index_stats(cf));
+        }
+        if name == properties::READ_TUNING_REPORT {
+            return self.read_tuning_report(cf).ok().and_then(|r| serde_json::to_string(&r).ok());
         }
 
-        // 4️⃣ finish -> 写 footer
-        builder.finish()?;
+        let stats = self.version_set.lock().unwrap().cf_statistics(cf)?;
+        stats.get_property(name).map(|v| v.to_string())
+    }
 
-        // 5️⃣ 安装到 VersionSet (LSM)
-        vs.install_table(
-            cf,
-            cfd.cf_type,
-            file_number,
-            &file_path,
-            mem.smallest_key(),
-            mem.largest_key(),
-        )?;
+    fn create_column_family(&self, name: &str, options: ColumnFamilyOptions) -> Result<ColumnFamilyId,DBError> {
+        if name.is_empty() {
+            return Err(DBError::InvalidArgument("column family name is empty".into()));
+        }
+        // 锁顺序：memtables -> version_set，新 CF 的 memtable 和 Version 一起出现
+        let mut memtables = self.memtables.lock().unwrap();
+        let mut vs = self.version_set.lock().unwrap();
+        let cf = vs.create_column_family(name, CfType::User, Some(options))?;
+        memtables.add_cf(cf, vs.current_sequence());
+        Ok(cf)
+    }
 
+    fn drop_column_family(&self, name: &str) -> Result<(),DBError> {
+        let cf = self.column_family_id(name)?;
+        let files = self.remove_column_family(cf)?;
+        self.purge_files(&files);
         Ok(())
     }
 }
 
 impl DBImpl {
     pub fn open(path: &str) -> Result<Arc<Self>, DBError> {
+        Self::open_inner(path, None, None)
+    }
+
+    /// Opens with `options` (see `Options::builder`) instead of the config
+    /// file in `path`.
+    pub fn open_with_options(path: &str, options: OpenOptions) -> Result<Arc<Self>, DBError> {
+        Self::open_inner(path, Some(options), None)
+    }
+
+    /// Like `open`, passing every replayed WAL record through `filter` first.
+    pub fn open_with_wal_filter(path: &str, filter: &dyn WalFilter) -> Result<Arc<Self>, DBError> {
+        Self::open_inner(path, None, Some(filter))
+    }
+
+    fn open_inner(path: &str, open_opts: Option<OpenOptions>, wal_filter: Option<&dyn WalFilter>) -> Result<Arc<Self>, DBError> {
         let db_path = PathBuf::from(path);
 
         // =========================================================
         // 0️⃣ Build OpenOptions (Default + config file)
         // =========================================================
 
-        let open_opts = match load_db_config(&db_path) {
-            Ok(file_cfg) => file_cfg.to_open_options(),
-            Err(_) => OpenOptions::default(),
+        let from_config_file = open_opts.is_none();
+        let open_opts = match open_opts {
+            Some(open_opts) => open_opts,
+            None => match load_db_config(&db_path) {
+                Ok(file_cfg) => file_cfg.to_open_options(),
+                Err(_) => OpenOptions::default(),
+            },
         };
 
         // =========================================================
@@ -176,14 +394,48 @@ impl DBImpl {
             DbConfig::from_open_options(db_path.clone(), &open_opts)
         );
 
+        // 建目录之前看：create_if_missing = false 时不能先把空目录建出来
+        let state = db_config.db_state();
+
+        // 什么都还没建之前报出来。选项之间的检查是后加的：已有 DB 的配置文件里
+        // 存的选项过不了时只打警告，照样打开，免得升级后老 DB 打不开
+        if let Err(e) = open_opts.validate() {
+            if from_config_file && matches!(state, DbState::Existing) {
+                log::warn!("{} is opened with options from its config file that fail validation: {:?}", path, e);
+            } else {
+                return Err(e);
+            }
+        }
+
+        match state {
+            DbState::Existing if open_opts.error_if_exists => {
+                return Err(DBError::InvalidDBState(format!("{} already holds a DB (error_if_exists)", path)));
+            }
+            DbState::Existing => {}
+            DbState::Missing if !open_opts.create_if_missing => {
+                return Err(DBError::InvalidDBState(format!("no DB at {} and create_if_missing is false", path)));
+            }
+            DbState::Missing => {}
+            // 建新的会把剩下的文件当垃圾删掉，打开又缺东西：两样都不做
+            DbState::Partial(what) => {
+                return Err(DBError::InvalidDBState(format!(
+                    "{} holds an incomplete DB ({}); restore it from a backup or remove the directory", path, what
+                )));
+            }
+        }
+
         // Create required directories
         db_config.create_dirs()?;
 
-        // Check whether DB creation is allowed
-        if !db_config.looks_like_existing_db()
-            && !open_opts.create_if_missing
-        {
-            return Err(DBError::Io("DB does not exist".into()));
+        // 上次 crash 时没写完的 compaction 输出
+        let orphans = db_config.remove_temp_sst_files()?;
+        if orphans > 0 {
+            log::info!("removed {} unfinished compaction outputs", orphans);
+        }
+        // 没提交的大事务落盘的写，crash 后没用了
+        let spills = db_config.remove_txn_spill_files()?;
+        if spills > 0 {
+            log::info!("removed {} transaction spill files", spills);
         }
 
         // =========================================================
@@ -204,9 +456,17 @@ impl DBImpl {
             .block_cache_shards
             .unwrap_or(16); // a safe recommended default
 
-        let block_cache = Arc::new(
+        let block_cache = Arc::new(if options.numa_aware_block_cache {
+            BlockCache::new_numa(cache_capacity, cache_shards)
+        } else {
             BlockCache::new(cache_capacity, cache_shards)
-        );
+        });
+
+        // 向量图的页单独一个 cache；图文件都是从 CF 数据重建的，旧的直接清掉
+        let vector_graph_cache = Arc::new(BlockCache::new(options.vector_graph_cache_size, cache_shards));
+        if db_config.vector_graph_dir().exists() {
+            fs::remove_dir_all(db_config.vector_graph_dir())?;
+        }
 
         // =========================================================
         // 4️⃣ Initialize filter policy (optional)
@@ -219,12 +479,20 @@ impl DBImpl {
         // 5️⃣ Initialize TableCache (using DbConfig)
         // =========================================================
 
+        // Block buffers and memtable arenas share one allocator; freed
+        // memtable arenas are recycled for the next active memtable.
+        let allocator: Arc<dyn MemoryAllocator> =
+            Arc::new(DefaultAllocator::new(options.max_write_buffer_number));
+
         let table_cache = Arc::new(
             TableCache::new(
-                &db_config.sst_dir,      // ✅ no longer use db_path directly
+                Arc::clone(&db_config),
                 block_cache.clone(),
                 filter_policy.clone(),
             )
+            .with_optimize_filters_for_hits(options.optimize_filters_for_hits)
+            .with_max_open_files(options.max_open_files)
+            .with_allocator(allocator.clone())
         );
 
         // =========================================================
@@ -236,12 +504,23 @@ impl DBImpl {
             table_cache.clone(),
         )?;
 
+        // Warm TableCache: open the hottest / bottommost files in parallel
+        // so the first reads after restart don't pay for footer + index IO.
+        if options.preload_tables_on_open > 0 || options.preload_bottom_level_on_open {
+            let files = versions.files_for_preload(
+                options.preload_tables_on_open,
+                options.preload_bottom_level_on_open,
+            );
+            table_cache.preload(&files, options.max_file_opening_threads);
+        }
+
         // =========================================================
         // 7️⃣ Initialize WAL (using DbConfig)
         // =========================================================
 
-        let wal = WalManager::open(
+        let wal = WalManager::open_with_archive(
             &db_config.wal_dir,
+            db_config.wal_archive_dir.as_deref(),
         )?;
 
         // =========================================================
@@ -251,13 +530,23 @@ impl DBImpl {
         let memtables = MemTableSet::new(
             versions.current_sequence(),
             versions.column_families().as_slice(),
-        );
+        )
+        .with_allocator(allocator);
 
         // =========================================================
         // 9️⃣ Construct DBImpl
         // =========================================================
 
-        let db = Arc::new(Self {
+        let version_pins = {
+            let table_cache = Arc::clone(&table_cache);
+            let db_config = Arc::clone(&db_config);
+            Arc::new(VersionPins::new(move |file_number| delete_sst(&table_cache, &db_config, file_number)))
+        };
+        let auto_tuner = AutoTuner::new(&options);
+        let read_sampler = Arc::new(ReadSampler::new(options.read_sample_rate));
+        let bg_worker = BackgroundWorker::new(&options);
+
+        let db = Arc::new_cyclic(|weak_self| Self {
             name: path.to_string(),
 
             // Two core components
@@ -269,49 +558,4570 @@ impl DBImpl {
             version_set: Arc::new(Mutex::new(versions)),
             memtables: Arc::new(Mutex::new(memtables)),
             wal_manager: wal,
-            bg_worker: Arc::new(BackgroundWorker::new()),
+            bg_worker: Arc::new(bg_worker),
+            idempotency_lock: Mutex::new(()),
+            replication_term: Mutex::new(0),
+            last_wal_sequence: AtomicU64::new(0),
+            quotas: QuotaManager::new(),
+            snapshots: SnapshotList::new(),
+            iterators: Arc::new(IteratorTracker::new()),
+            version_pins,
+            write_group: WriteGroup::new(),
+            txn_commit_lock: Mutex::new(()),
+            lock_manager: LockManager::new(),
+            range_locks: RangeLockTable::new(),
+            embedders: RwLock::new(HashMap::new()),
+            vector_indexes: RwLock::new(HashMap::new()),
+            vector_graph_cache,
+            write_stall: WriteStallController::new(),
+            auto_tuner,
+            recovery_info: Mutex::new(RecoveryInfo::default()),
+            read_sampler,
+            warming_up: AtomicBool::new(false),
+            flush_done: Condvar::new(),
+            replicated_sequence: AtomicU64::new(0),
+            is_replica: AtomicBool::new(false),
+            weak_self: weak_self.clone(),
         });
 
         // =========================================================
         // 🔟 WAL replay / crash recovery
         // =========================================================
 
-        db.recover()?;
+        {
+            let mut vs = db.version_set.lock().unwrap();
+            vs.set_version_pins(Arc::clone(&db.version_pins));
+            vs.set_live_wal(db.wal_manager.path().to_path_buf());
+            vs.set_snapshot_watermark(db.snapshots.watermark());
+        }
+
+        let (info, changed) = db.recover(wal_filter)?;
+        // close 时 WAL 会被清空，之前的进度在 REPLICATED 里；没正常关的话 WAL 里的都已经应用过
+        let replicated = db.db_config.read_replicated_sequence()?;
+        db.is_replica.store(replicated.is_some(), Ordering::Release);
+        db.replicated_sequence.store(replicated.unwrap_or(0).max(info.last_wal_sequence), Ordering::Release);
+        db.last_wal_sequence.store(info.last_wal_sequence.max(db.replicated_sequence()), Ordering::Release);
+        *db.recovery_info.lock().unwrap() = info;
+        if changed {
+            // 过滤掉 / 改过 seq 的记录在 WAL 里还是原样，落盘后清掉，下次打开不会再回来
+            let seq = db.flush_and_truncate_wal()?;
+            log::info!("recovery changed WAL records, flushed and truncated the WAL at sequence {}", seq);
+        }
+        *db.replication_term.lock().unwrap() = db.db_config.read_replication_term()?.unwrap_or(0);
+        db.load_range_locks()?;
+
+        // Files of column families dropped before a crash
+        let obsolete = db.version_set.lock().unwrap().take_obsolete_files();
+        db.bg_worker.schedule_purge(&db, obsolete);
+        // 上次运行留下的：compaction 的输入、没来得及装进 Version 的输出、旧的 MANIFEST / WAL
+        db.delete_obsolete_files();
+
+        db.start_warmup();
+
+        if db.options.ttl_sweep_interval_secs > 0 {
+            ttl::start_sweeper(
+                Arc::downgrade(&db),
+                Duration::from_secs(db.options.ttl_sweep_interval_secs),
+            );
+        }
+
+        if db.options.txn_expiration_sweep_interval_ms > 0 {
+            lock_manager::start_sweeper(
+                Arc::downgrade(&db),
+                Duration::from_millis(db.options.txn_expiration_sweep_interval_ms),
+            );
+        }
 
         Ok(db)
     }
 
+    /// `DB::get` with per-read options, e.g. skipping block checksum verification on hot paths.
+    pub fn get_with_options(&self, cf: ColumnFamilyId, key: &[u8], opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        let _span = Span::enter("get");
+        self.quotas.acquire_read(cf, |cf| self.quota_options(cf))?;
+        let value = self.get_internal_with_options(cf, key, opts)?;
+        if self.read_sampler.should_sample() {
+            self.read_sampler.record_get(cf, key, value.as_deref());
+        }
+        if let Some(v) = &value {
+            self.quotas.charge_read_bytes(cf, v.len() as u64);
+        }
+        Ok(value)
+    }
+
+    /// `DB::multi_get` with per-read options; block checksums and caching follow `opts`.
+    pub fn multi_get_with_options(&self, cf: ColumnFamilyId, keys: &[&[u8]], opts: &ReadOptions) -> Result<Vec<Option<Vec<u8>>>, DBError> {
+        let _span = Span::enter("multi_get");
 
+        self.quotas.acquire_reads(cf, keys.len() as u64, |cf| self.quota_options(cf))?;
 
-    fn recover(&self) -> Result<(),DBError> {
-        self.wal_manager.replay_batches(|base_seq, batch| {
-            self.memtables.lock().unwrap().apply(base_seq, batch)
-        })?;
+        // merge 的 key 要逐个往回收集 operand，不走批量路径
+        if self.version_set.lock().unwrap().merge_operator(cf).is_some() {
+            let values = keys.iter().map(|k| self.get_internal_with_options(cf, k, opts)).collect::<Result<Vec<_>, _>>()?;
+            let bytes: usize = values.iter().flatten().map(Vec::len).sum();
+            self.quotas.charge_read_bytes(cf, bytes as u64);
+            return Ok(values);
+        }
+
+        // 1. memtable：一次加锁查完所有 key；同时 pin 住 Version，
+        //    这样 memtable 和 SST 看到的是同一个时刻
+        let mut values: Vec<Option<Vec<u8>>> = vec![None; keys.len()];
+        // memtable 里已经有结论（找到或删了）的 key，不用再查 SST
+        let mut resolved = vec![false; keys.len()];
+        let snapshot = opts.snapshot.as_ref().map(|s| s.seq);
+        let (version, stats) = {
+            let mem = self.memtables.lock().unwrap();
+            let vs = self.version_set.lock().unwrap();
+            let seq = snapshot.unwrap_or_else(|| vs.current_sequence());
+            for (i, key) in keys.iter().enumerate() {
+                resolved[i] = match mem.lookup(cf, seq, key) {
+                    LookupResult::Found(v) => {
+                        values[i] = Some(v);
+                        true
+                    }
+                    LookupResult::Deleted => true,
+                    LookupResult::NotFound => mem.is_range_deleted(cf, seq, key),
+                };
+            }
+            let stats = vs
+                .cf_statistics(cf)
+                .ok_or_else(|| DBError::UnknownColumnFamily(cf.to_string()))?;
+            (self.version_pins.pin(vs.current_version(cf)), stats)
+        };
+
+        // 2. 剩下的按 key 排序后一起查 SST，同一个 data block 只读一次
+        let mut pending: Vec<usize> = (0..keys.len()).filter(|&i| !resolved[i]).collect();
+        pending.sort_by_key(|&i| keys[i]);
+        if !pending.is_empty() {
+            let sorted: Vec<&[u8]> = pending.iter().map(|&i| keys[i]).collect();
+            // snapshot 当时 pin 住的 Version 可能有已经被 compaction 换掉的文件，和当前的合起来读，同 get_at_sequence
+            let union = snapshot.and_then(|seq| self.snapshots.version(seq, cf)).and_then(|pinned| {
+                (!Arc::ptr_eq(pinned.version(), version.version())).then(|| {
+                    Version::union_of(
+                        &[Arc::clone(pinned.version()), Arc::clone(version.version())],
+                        Arc::clone(&self.table_cache),
+                    )
+                })
+            });
+            let found = match &union {
+                Some(union) => union.multi_get(&sorted, snapshot, &stats, opts)?,
+                None => version.multi_get(&sorted, snapshot, &stats, opts)?,
+            };
+            for (i, v) in pending.into_iter().zip(found) {
+                values[i] = v;
+            }
+        }
+
+        let bytes: usize = values.iter().flatten().map(Vec::len).sum();
+        self.quotas.charge_read_bytes(cf, bytes as u64);
+        Ok(values)
+    }
+
+    /// Drop a column family: log the drop to the MANIFEST, discard its memtables
+    /// and delete its SST files in the background.
+    pub fn drop_column_family_by_id(self: &Arc<Self>, cf: ColumnFamilyId) -> Result<(), DBError> {
+        let files = self.remove_column_family(cf)?;
+        self.bg_worker.schedule_purge(self, files);
         Ok(())
     }
 
-    fn make_room_for_write(&self, batch: &WriteBatch) -> Result<(),DBError> {
-        const MEMTABLE_MAX_BYTES: usize = 64 * 1024 * 1024;
-        const MAX_IMMUTABLES: usize = 4;
+    /// Id of the column family named `name`.
+    pub fn column_family_id(&self, name: &str) -> Result<ColumnFamilyId, DBError> {
+        self.version_set.lock().unwrap()
+            .column_family_by_name(name)
+            .map(|cfd| cfd.cf_id)
+            .ok_or_else(|| DBError::UnknownColumnFamily(name.to_string()))
+    }
 
-        let mut mem = self.memtables.lock().unwrap();
+    /// Id and name of every column family, system ones included, by id.
+    pub fn list_column_families(&self) -> Vec<(ColumnFamilyId, String)> {
+        let vs = self.version_set.lock().unwrap();
+        let mut cfs: Vec<_> = vs.column_families()
+            .into_iter()
+            .filter_map(|cf| vs.column_family_by_id(cf).ok().map(|cfd| (cf, cfd.name.clone())))
+            .collect();
+        cfs.sort();
+        cfs
+    }
 
-        for cf in batch.involved_cfs() {
-            let cf_tables = mem
-                .cfs
-                .get_mut(&cf)
-                .ok_or(DBError::InvalidArgument("unknown CF".into()))?;
+    /// 记下 drop、丢掉 memtable 和向量图，返回要删的 SST
+    fn remove_column_family(&self, cf: ColumnFamilyId) -> Result<Vec<u64>, DBError> {
+        if cf == SYSTEM_COLUMN_FAMILY_ID || cf == USER_COLUMN_FAMILY_ID {
+            return Err(DBError::InvalidColumnFamily(format!("column family {} cannot be dropped", cf)));
+        }
+        let mut memtables = self.memtables.lock().unwrap();
+        let files = self.version_set.lock().unwrap().drop_column_family(cf)?;
+        memtables.remove_cf(cf);
+        drop(memtables);
+
+        self.vector_indexes.write().unwrap().retain(|(c, _), _| *c != cf);
+        self.embedders.write().unwrap().remove(&cf);
+        self.quotas.remove(cf);
+        Ok(files)
+    }
+
+    /// Drop the SST files of `cf` that lie entirely inside `[begin, end)`, without compaction.
+    ///
+    /// Keys in the range that live in memtables or in files straddling the bounds are
+    /// kept. Older versions of a key in a lower level may become visible again if only
+    /// the newer file was dropped, so use it for data that is retired as a whole.
+    pub fn delete_files_in_range(
+        self: &Arc<Self>,
+        cf: ColumnFamilyId,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<(), DBError> {
+        let files = self.version_set.lock().unwrap().delete_files_in_range(cf, begin, end)?;
+        self.bg_worker.schedule_purge(self, files);
+        Ok(())
+    }
+
+    /// Delete every key of `cf` that starts with `prefix`.
+    ///
+    /// Writes a single range tombstone over the prefix; a prefix of only `0xff`
+    /// bytes has no upper bound and falls back to one point delete per live key.
+    /// With `drop_files`, SSTs lying entirely under the prefix are first dropped
+    /// via `delete_files_in_range`, which also reclaims their space right away.
+    pub fn delete_prefix(self: &Arc<Self>, cf: ColumnFamilyId, prefix: &[u8], drop_files: bool) -> Result<(), DBError> {
+        let end = prefix_successor(prefix);
+        if drop_files {
+            self.delete_files_in_range(cf, Some(prefix), end.as_deref())?;
+        }
+        if let Some(end) = end {
+            return self.delete_range(&self.default_write_options(), cf, prefix, &end);
+        }
+
+        let keys = self.user_keys_with_prefix(cf, prefix);
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::new();
+        for key in &keys {
+            batch.delete(cf, key);
+        }
+        self.write(&self.default_write_options(), batch)
+    }
+
+    /// Write `key` and schedule it for deletion by the TTL sweeper after `ttl`.
+    ///
+    /// The expiry is indexed in the system CF by (expiry, cf, key). A later plain
+    /// `put` does not clear it; call `put_with_ttl` again to move the deadline.
+    pub fn put_with_ttl(&self, cf: ColumnFamilyId, key: &[u8], value: &[u8], ttl: Duration) -> Result<(), DBError> {
+        let expiry = ttl::now_ms().saturating_add(ttl.as_millis() as u64);
+        let mut batch = WriteBatch::new();
+        batch.put(cf, key, value);
+        // 旧的索引项不用删，sweeper 会对照反向指针跳过
+        batch.put(SYSTEM_COLUMN_FAMILY_ID, &ttl::index_key(expiry, cf, key), &[]);
+        batch.put(SYSTEM_COLUMN_FAMILY_ID, &ttl::reverse_key(cf, key), &expiry.to_be_bytes());
+        self.write(&self.default_write_options(), batch)
+    }
+
+    /// Delete up to `limit` keys whose TTL expired at or before `now_ms`, oldest first.
+    ///
+    /// Returns how many index entries were processed; stale entries left behind by
+    /// a re-put count too, so a result equal to `limit` means there may be more.
+    /// The expiry is re-checked and the key deleted while other writers wait, so a
+    /// `put_with_ttl` that moves the deadline is never lost to a sweep in flight.
+    pub fn sweep_expired(&self, now_ms: u64, limit: usize) -> Result<usize, DBError> {
+        // 索引按过期时间排序：从头 seek，过了 now_ms 或者够 limit 条就停，不扫整个索引
+        let upper_bound = ttl::index_upper_bound(now_ms);
+        let mut index_keys = Vec::new();
+        for entry in self.iter_range(SYSTEM_COLUMN_FAMILY_ID, ttl::TTL_INDEX_PREFIX..upper_bound.as_slice()).take(limit) {
+            index_keys.push(entry?.0);
+        }
+        let expired: Vec<_> = index_keys.iter().filter_map(|k| Some((k, ttl::decode_index_key(k)?))).collect();
+        if expired.is_empty() {
+            return Ok(index_keys.len());
+        }
 
-            if cf_tables.active_memory_usage() >= MEMTABLE_MAX_BYTES {
-                let new_seq = self.version_set.lock().unwrap().next_sequence();
-                cf_tables.freeze_active(cf, new_seq);
+        // 过期删除是 DB 自己的写，不占 CF 的写配额；独占写入顺序之后就不能再等 flush 了
+        let opts = self.default_write_options();
+        let mut upper = WriteBatch::new();
+        for (index_key, (_, cf, key)) in &expired {
+            upper.delete(SYSTEM_COLUMN_FAMILY_ID, index_key);
+            upper.delete(*cf, key);
+            upper.delete(SYSTEM_COLUMN_FAMILY_ID, &ttl::reverse_key(*cf, key));
+        }
+        self.check_write(&opts, &upper)?;
+        self.make_room_for_write(&upper)?;
 
-                if let Some(imm) = cf_tables.pick_flush_candidate() {
-                    self.bg_worker.schedule_flush(cf, imm);
+        // 读反向指针和删 key 之间不能插进别的写：插进来的 put_with_ttl 换了过期时间，key 不能删
+        self.write_group.exclusive(|| {
+            let mut batch = WriteBatch::new();
+            for (index_key, (expiry, cf, key)) in &expired {
+                batch.delete(SYSTEM_COLUMN_FAMILY_ID, index_key);
+                let reverse = ttl::reverse_key(*cf, key);
+                let current = self.get_internal(SYSTEM_COLUMN_FAMILY_ID, &reverse)?;
+                if current.as_deref() == Some(&expiry.to_be_bytes()[..]) {
+                    batch.delete(*cf, key);
+                    batch.delete(SYSTEM_COLUMN_FAMILY_ID, &reverse);
                 }
             }
+            self.commit_batch(batch, &opts)
+        })?;
+        Ok(index_keys.len())
+    }
+
+    /// Store `vector` (and an opaque `payload`) under `key` for `knn_search`.
+    pub fn put_vector(&self, cf: ColumnFamilyId, key: &[u8], vector: &[f32], payload: &[u8]) -> Result<(), DBError> {
+        let dimension = self.vector_options(cf).default.dimension;
+        if dimension != 0 && vector.len() != dimension {
+            return Err(DBError::InvalidArgument(format!(
+                "vector has {} dimensions, column family expects {}",
+                vector.len(),
+                dimension
+            )));
+        }
+        self.put(&self.default_write_options(), cf, key, &encode_vector(vector, payload))
+    }
+
+    /// Store several named vectors of one entity (and an opaque `payload`) under `key`.
+    ///
+    /// Each column is searchable through the index of the same name in
+    /// `VectorOptions::indexes`; columns without an index are stored but not searchable.
+    pub fn put_vectors(
+        &self,
+        cf: ColumnFamilyId,
+        key: &[u8],
+        columns: &[(&str, &[f32])],
+        payload: &[u8],
+    ) -> Result<(), DBError> {
+        let opts = self.vector_options(cf);
+        for (name, vector) in columns {
+            if name.is_empty() {
+                return Err(DBError::InvalidArgument("vector column name must not be empty".into()));
+            }
+            let dimension = opts.indexes.get(*name).map_or(0, |o| o.dimension);
+            if dimension != 0 && vector.len() != dimension {
+                return Err(DBError::InvalidArgument(format!(
+                    "vector column {} has {} dimensions, index expects {}",
+                    name,
+                    vector.len(),
+                    dimension
+                )));
+            }
+        }
+        self.put(&self.default_write_options(), cf, key, &encode_vector_columns(columns, payload))
+    }
+
+    /// Use `embedder` to compute vectors for documents written to `cf` with `put_document`.
+    pub fn register_embedder(&self, cf: ColumnFamilyId, embedder: Arc<dyn Embedder>) {
+        self.embedders.write().unwrap().insert(cf, embedder);
+    }
+
+    fn embedder(&self, cf: ColumnFamilyId) -> Result<Arc<dyn Embedder>, DBError> {
+        self.embedders
+            .read()
+            .unwrap()
+            .get(&cf)
+            .cloned()
+            .ok_or_else(|| DBError::InvalidArgument(format!("no embedder registered for column family {}", cf)))
+    }
+
+    /// Embed `document` with the CF's embedder and store the vector together with
+    /// the document (as its payload) in a single write.
+    pub fn put_document(&self, cf: ColumnFamilyId, key: &[u8], document: &[u8]) -> Result<(), DBError> {
+        let vector = self.embedder(cf)?.embed(document)?;
+        self.put_vector(cf, key, &vector, document)
+    }
+
+    /// Like `put_document` for many documents: embeddings run on `embedding_threads`
+    /// threads, then everything is written in one batch.
+    pub fn put_documents(&self, cf: ColumnFamilyId, documents: &[(&[u8], &[u8])]) -> Result<(), DBError> {
+        let embedder = self.embedder(cf)?;
+        let docs: Vec<&[u8]> = documents.iter().map(|(_, d)| *d).collect();
+        let vectors = embed_all(embedder.as_ref(), &docs, self.options.embedding_threads)?;
+
+        let dimension = self.vector_options(cf).default.dimension;
+        let mut batch = WriteBatch::new();
+        for ((key, document), vector) in documents.iter().zip(&vectors) {
+            if dimension != 0 && vector.len() != dimension {
+                return Err(DBError::InvalidArgument(format!(
+                    "embedder {} returned {} dimensions, column family expects {}",
+                    embedder.name(),
+                    vector.len(),
+                    dimension
+                )));
+            }
+            batch.put(cf, key, &encode_vector(vector, document));
+        }
+        self.write(&self.default_write_options(), batch)
+    }
+
+    /// k-nearest-neighbour search over the vectors stored in `cf`.
+    ///
+    /// `request.index` picks a named index; the unnamed one covers `put_vector`
+    /// values. With `index = "hnsw"` the candidates come from the index's graph
+    /// (approximate, `ef_search` wide); otherwise, when the request overrides the
+    /// metric, or with `search.exhaustive`, every candidate key is scanned.
+    /// Values without a vector for the index, or with a different dimension than
+    /// the query, are skipped.
+    pub fn knn_search(&self, cf: ColumnFamilyId, request: &KnnRequest) -> Result<KnnResponse, DBError> {
+        let _span = Span::enter("knn_search");
+        let name = request.index.as_deref().unwrap_or("");
+        let opts = self.vector_index_options(cf, name)?;
+        let metric = request.metric.unwrap_or(opts.metric);
+
+        let use_index = opts.index == VectorIndexType::Hnsw && metric == opts.metric && !request.search.exhaustive;
+        if !use_index {
+            return self.knn_exhaustive(cf, name, request, metric);
+        }
+
+        let ef = request.search.ef_search.unwrap_or(opts.hnsw.ef_search).max(request.k + 1);
+        let response = self.knn_hnsw(cf, name, &opts, request, metric, ef)?;
+        if request.search.exhaustive_fallback && response.hits.len() < request.k {
+            return self.knn_exhaustive(cf, name, request, metric);
+        }
+        Ok(response)
+    }
+
+    fn knn_exhaustive(
+        &self,
+        cf: ColumnFamilyId,
+        name: &str,
+        request: &KnnRequest,
+        metric: Metric,
+    ) -> Result<KnnResponse, DBError> {
+        let mut top = TopK::new(request, metric)?;
+        let prefix = request.filter.as_ref().map_or(&b""[..], |f| f.scan_prefix());
+        for key in self.user_keys_with_prefix(cf, prefix) {
+            if request.filter.as_ref().is_some_and(|f| !f.matches(&key)) {
+                continue;
+            }
+            let Some(value) = self.get_internal(cf, &key)? else { continue };
+            let Some((vector, payload)) = decode_indexed_vector(&value, name) else { continue };
+            top.offer(&request.query, &key, &vector, request.with_payload.then_some(payload));
+        }
+        Ok(top.finish())
+    }
+
+    fn knn_hnsw(
+        &self,
+        cf: ColumnFamilyId,
+        name: &str,
+        opts: &VectorIndexOptions,
+        request: &KnnRequest,
+        metric: Metric,
+        ef: usize,
+    ) -> Result<KnnResponse, DBError> {
+        let mut top = TopK::new(request, metric)?;
+        let index = self.vector_index(cf, name, opts)?;
+        let index = index.read().unwrap();
+        for (key, vector, _) in index.search(&request.query, ef, ef)? {
+            if request.filter.as_ref().is_some_and(|f| !f.matches(&key)) {
+                continue;
+            }
+            let payload = match request.with_payload {
+                true => self
+                    .get_internal(cf, &key)?
+                    .and_then(|v| decode_indexed_vector(&v, name).map(|(_, p)| p.to_vec())),
+                false => None,
+            };
+            top.offer(&request.query, &key, &vector, payload.as_deref());
+        }
+        Ok(top.finish())
+    }
+
+    /// Measure recall and latency of one of `cf`'s vector indexes on `queries`
+    /// against exhaustive search and suggest the cheapest `SearchOptions`
+    /// reaching `target_recall`.
+    pub fn calibrate_vector_search(
+        &self,
+        cf: ColumnFamilyId,
+        index: Option<&str>,
+        queries: &[Vec<f32>],
+        k: usize,
+        target_recall: f64,
+    ) -> Result<CalibrationReport, DBError> {
+        let candidates: Vec<usize> = DEFAULT_EF_CANDIDATES.iter().map(|&ef| ef.max(k)).collect();
+        calibrate(queries, k, target_recall, &candidates, |request| {
+            let mut request = request.clone();
+            request.index = index.map(str::to_string);
+            self.knn_search(cf, &request)
+        })
+    }
+
+    /// Rebuild the HNSW graphs of `cf` from the column family's data in the background.
+    ///
+    /// Picks up vectors written with plain `put` and drops everything that was
+    /// overwritten or deleted; searches keep using the old graphs until the new
+    /// ones are swapped in.
+    pub fn optimize_vector_index(self: &Arc<Self>, cf: ColumnFamilyId) -> Result<(), DBError> {
+        if self.hnsw_indexes(cf).is_empty() {
+            return Err(DBError::InvalidArgument(format!("column family {} has no vector index", cf)));
         }
+        self.bg_worker.schedule_vector_index_rebuild(self, cf);
+        Ok(())
+    }
 
+    /// Full rebuild used by `optimize_vector_index`; runs on the background worker.
+    pub(crate) fn rebuild_vector_index(&self, cf: ColumnFamilyId) -> Result<(), DBError> {
+        for (name, opts) in self.hnsw_indexes(cf) {
+            let Some(index) = self.loaded_vector_index(cf, &name) else {
+                // 还没人查过：直接建，等同于第一次查询
+                self.vector_index(cf, &name, &opts)?;
+                continue;
+            };
+            index.write().unwrap().begin_rebuild();
+            match self.build_vector_index(cf, &name, &opts) {
+                Ok(rebuilt) => index.write().unwrap().finish_rebuild(rebuilt),
+                Err(e) => {
+                    index.write().unwrap().abort_rebuild();
+                    return Err(e);
+                }
+            }
+        }
         Ok(())
     }
+
+    /// The HNSW graph of index `name` of `cf`, built from a full scan the first time it's needed.
+    fn vector_index(
+        &self,
+        cf: ColumnFamilyId,
+        name: &str,
+        opts: &VectorIndexOptions,
+    ) -> Result<Arc<RwLock<HnswIndex>>, DBError> {
+        if let Some(index) = self.loaded_vector_index(cf, name) {
+            return Ok(index);
+        }
+        let built = Arc::new(RwLock::new(self.build_vector_index(cf, name, opts)?));
+        // 并发建图时以先插进去的为准
+        let mut indexes = self.vector_indexes.write().unwrap();
+        Ok(Arc::clone(indexes.entry((cf, name.to_string())).or_insert(built)))
+    }
+
+    fn loaded_vector_index(&self, cf: ColumnFamilyId, name: &str) -> Option<Arc<RwLock<HnswIndex>>> {
+        self.vector_indexes.read().unwrap().get(&(cf, name.to_string())).cloned()
+    }
+
+    fn build_vector_index(&self, cf: ColumnFamilyId, name: &str, opts: &VectorIndexOptions) -> Result<HnswIndex, DBError> {
+        let mut index = HnswIndex::new(opts.metric, opts.hnsw.clone());
+        if opts.on_disk {
+            index = index.with_spill(SpillTarget {
+                dir: self.db_config.vector_graph_dir(),
+                prefix: format!("{}-{}", cf, if name.is_empty() { "default" } else { name }),
+                cache: Arc::clone(&self.vector_graph_cache),
+            });
+        }
+        for key in self.user_keys_with_prefix(cf, b"") {
+            let Some(value) = self.get_internal(cf, &key)? else { continue };
+            let Some((vector, _)) = decode_indexed_vector(&value, name) else { continue };
+            index.insert(&key, vector);
+        }
+        if let Err(e) = index.spill() {
+            log::warn!("vector index {:?} of cf {} stays in memory: {:?}", name, cf, e);
+        }
+        Ok(index)
+    }
+
+    /// Index changes a write implies for the graphs that are already built.
+    ///
+    /// A put without a vector for an index removes the key from it, like a delete.
+    fn vector_index_updates(&self, batch: &WriteBatch) -> Vec<(VectorIndexKey, Vec<u8>, Option<Vec<f32>>)> {
+        let indexes = self.vector_indexes.read().unwrap();
+        if indexes.is_empty() {
+            return Vec::new();
+        }
+        let mut updates = Vec::new();
+        for entry in batch {
+            for (cf, name) in indexes.keys().filter(|(cf, _)| *cf == entry.cf()) {
+                let (key, vector) = match entry {
+                    WriteBatchEntry::Put { key, value, .. } => (key, decode_indexed_vector(value, name).map(|(v, _)| v)),
+                    WriteBatchEntry::Delete { key, .. } => (key, None),
+                    // 在 write 里整个图一起丢掉
+                    WriteBatchEntry::DeleteRange { .. } | WriteBatchEntry::Merge { .. } => continue,
+                };
+                updates.push(((*cf, name.clone()), key.to_vec(), vector));
+            }
+        }
+        updates
+    }
+
+    /// 已建好的 HNSW 图跟上 `batch`：删除立刻生效（打墓碑），新向量插入；
+    /// 范围删除 / merge 之后图里的向量对不上了，直接丢掉图，下次查询时重建
+    ///
+    /// 占着写入顺序、写完 memtable 后调：同一个 id 的 put / delete 按 sequence 的顺序进图，
+    /// 图和 KV 对得上
+    fn update_vector_indexes(&self, batch: &WriteBatch) {
+        self.apply_vector_index_updates(self.vector_index_updates(batch));
+        let rebuild_cfs: Vec<ColumnFamilyId> = batch.iter()
+            .filter(|e| matches!(e, WriteBatchEntry::DeleteRange { .. } | WriteBatchEntry::Merge { .. }))
+            .map(|e| e.cf())
+            .collect();
+        if !rebuild_cfs.is_empty() {
+            self.vector_indexes.write().unwrap().retain(|(cf, _), _| !rebuild_cfs.contains(cf));
+        }
+    }
+
+    fn apply_vector_index_updates(&self, updates: Vec<(VectorIndexKey, Vec<u8>, Option<Vec<f32>>)>) {
+        let mut touched: Vec<VectorIndexKey> = Vec::new();
+        for ((cf, name), key, vector) in updates {
+            let Some(index) = self.loaded_vector_index(cf, &name) else { continue };
+            let mut guard = index.write().unwrap();
+            match vector {
+                Some(v) => guard.insert(&key, v),
+                None => {
+                    guard.remove(&key);
+                }
+            }
+            if !touched.iter().any(|(c, n)| *c == cf && *n == name) {
+                touched.push((cf, name));
+            }
+        }
+        for (cf, name) in touched {
+            if let Some(index) = self.loaded_vector_index(cf, &name) {
+                self.maybe_compact_vector_index(cf, &name, &index);
+            }
+        }
+    }
+
+    /// Merge policy: once `rebuild_deleted_ratio` of the graph is dead, rebuild
+    /// it from its live nodes in the background; before that, relink around
+    /// every `repair_after_deletes` new tombstones.
+    fn maybe_compact_vector_index(&self, cf: ColumnFamilyId, name: &str, index: &Arc<RwLock<HnswIndex>>) {
+        let Ok(opts) = self.vector_index_options(cf, name) else { return };
+        let (due, repair_due) = {
+            let guard = index.read().unwrap();
+            let due = opts.rebuild_deleted_ratio > 0.0
+                && !guard.is_rebuilding()
+                && guard.deleted_ratio() > opts.rebuild_deleted_ratio;
+            let repair_due = opts.repair_after_deletes > 0 && guard.unrepaired() >= opts.repair_after_deletes;
+            (due, repair_due)
+        };
+        if repair_due && !due && index.write().unwrap().request_repair() {
+            self.bg_worker.schedule_vector_index_repair(index);
+        }
+        if due {
+            // 先标记上，避免排队期间每次写入都再排一个任务
+            index.write().unwrap().begin_rebuild();
+            self.bg_worker.schedule_vector_index_compaction(index);
+        }
+    }
+
+    /// JSON for `vectorkv.vector-index-stats`: one object per index of `cf`
+    /// ("" is the unnamed one). HNSW graphs that haven't been built yet report
+    /// `"built": false`.
+    fn vector_index_stats(&self, cf: ColumnFamilyId) -> String {
+        let opts = self.vector_options(cf);
+        let all = std::iter::once((String::new(), opts.default)).chain(opts.indexes);
+        let entries: Vec<serde_json::Value> = all
+            .map(|(name, o)| {
+                let mut entry = serde_json::json!({
+                    "name": name,
+                    "index": match o.index {
+                        VectorIndexType::Flat => "flat",
+                        VectorIndexType::Hnsw => "hnsw",
+                    },
+                    "metric": format!("{:?}", o.metric),
+                    "dimension": o.dimension,
+                });
+                if o.index == VectorIndexType::Hnsw {
+                    let stats = self.loaded_vector_index(cf, &name).map(|i| i.read().unwrap().stats());
+                    entry["built"] = stats.is_some().into();
+                    if let Some(stats) = stats {
+                        entry["hnsw"] = serde_json::to_value(stats).unwrap_or_default();
+                    }
+                }
+                entry
+            })
+            .collect();
+        serde_json::Value::Array(entries).to_string()
+    }
+
+    /// Vector settings of the column family's options group.
+    fn vector_options(&self, cf: ColumnFamilyId) -> VectorOptions {
+        match self.version_set.lock().unwrap().column_family_by_id(cf) {
+            Ok(cfd) => cfd.options(&self.options).vector.clone(),
+            Err(_) => VectorOptions::default(),
+        }
+    }
+
+    /// Settings of index `name` of `cf` ("" = the unnamed index).
+    fn vector_index_options(&self, cf: ColumnFamilyId, name: &str) -> Result<VectorIndexOptions, DBError> {
+        self.vector_options(cf)
+            .index(name)
+            .cloned()
+            .ok_or_else(|| DBError::InvalidArgument(format!("column family {} has no vector index named {}", cf, name)))
+    }
+
+    /// (name, options) of every HNSW index configured for `cf`.
+    fn hnsw_indexes(&self, cf: ColumnFamilyId) -> Vec<(String, VectorIndexOptions)> {
+        let opts = self.vector_options(cf);
+        std::iter::once((String::new(), opts.default))
+            .chain(opts.indexes)
+            .filter(|(_, o)| o.index == VectorIndexType::Hnsw)
+            .collect()
+    }
+
+    /// Hint that the files of `cf` overlapping `[begin, end]` should be compacted soon.
+    ///
+    /// Returns immediately. If any file was marked, a compaction of the range is
+    /// queued; it picks marked files before the ones `compaction_pri` would choose.
+    pub fn suggest_compact_range(
+        &self,
+        cf: ColumnFamilyId,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<(), DBError> {
+        let marked = self.version_set.lock().unwrap().suggest_compact_range(cf, begin, end)?;
+        if marked > 0 {
+            self.schedule_marked_compaction(cf, begin, end);
+        }
+        Ok(())
+    }
+
+    /// 标记了文件就排一次覆盖这段 key 的 compaction，不等下一次 flush 才被捡起来
+    fn schedule_marked_compaction(&self, cf: ColumnFamilyId, begin: Option<&[u8]>, end: Option<&[u8]>) {
+        // DB 正在析构时不用再排
+        if let Some(db) = self.weak_self.upgrade() {
+            self.bg_worker.schedule_range_compaction(&db, cf, begin, end);
+        }
+    }
+
+    /// 交给后台 flush；调用方不能拿着 memtable 锁，队列满时这里会等 flush 线程
+    fn schedule_flush(&self, imm: VecDeque<Arc<dyn MemTable>>) {
+        if let Some(db) = self.weak_self.upgrade() {
+            self.bg_worker.schedule_flush(&db, imm);
+        }
+    }
+
+    /// Read `key` as the DB was at sequence `seq`.
+    ///
+    /// Needs `time_travel_retention_secs` > 0; sequences older than the retention
+    /// window return `InvalidArgument`.
+    pub fn get_as_of(&self, cf: ColumnFamilyId, key: &[u8], seq: SequenceNumber) -> Result<Option<Vec<u8>>, DBError> {
+        // 超出 retention 窗口的先报错，不然 memtable 里碰巧还在的 key 会被读出来
+        let history = Arc::new(self.version_set.lock().unwrap().version_as_of(cf, seq)?);
+        self.read_at_sequence(cf, key, seq, Some(&history), &ReadOptions::default())
+    }
+
+    /// 读 `seq` 时刻的值（snapshot 读）
+    ///
+    /// `seq` 是活着的 snapshot 时，除了 current 还要读它 pin 住的 Version：compaction 只留
+    /// 每个 key 的最新版本，snapshot 能看到的旧版本在被替换掉的文件里。
+    /// 读 SST 时按 `opts` 决定是否校验 block、是否放进 block cache
+    pub(crate) fn get_at_sequence(&self, cf: ColumnFamilyId, key: &[u8], seq: SequenceNumber, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        let pinned = self.snapshots.version(seq, cf);
+        self.read_at_sequence(cf, key, seq, pinned.as_ref().map(|p| p.version()), opts)
+    }
+
+    /// get_at_sequence / get_as_of 共用：memtable（含范围墓碑）→ pin 住的 current，
+    /// 再加上 `older`（snapshot 或 time-travel 历史里的 Version）一起按 seq 读
+    fn read_at_sequence(
+        &self,
+        cf: ColumnFamilyId,
+        key: &[u8],
+        seq: SequenceNumber,
+        older: Option<&Arc<Version>>,
+        opts: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let mem = self.memtables.lock().unwrap();
+        match mem.lookup(cf, seq, key) {
+            LookupResult::Found(v) => return Ok(Some(v)),
+            LookupResult::Deleted => return Ok(None),
+            LookupResult::NotFound if mem.is_range_deleted(cf, seq, key) => return Ok(None),
+            LookupResult::NotFound => {}
+        }
+        let version = {
+            let vs = self.version_set.lock().unwrap();
+            self.version_pins.pin(vs.current_version(cf))
+        };
+        drop(mem);
+        match older {
+            Some(older) if !Arc::ptr_eq(older, version.version()) => {
+                let union = Version::union_of(
+                    &[Arc::clone(older), Arc::clone(version.version())],
+                    Arc::clone(&self.table_cache),
+                );
+                union.get_as_of(key, seq, opts)
+            }
+            _ => version.get_as_of(key, seq, opts),
+        }
+    }
+
+    /// Iterate a column family as it was at sequence `seq`.
+    pub fn iterator_as_of(&self, cf: ColumnFamilyId, seq: SequenceNumber) -> Result<Box<dyn crate::engine::sst::iterator::DBIterator>, DBError> {
+        self.version_set.lock().unwrap().iterator_as_of(cf, seq)
+    }
+
+    /// Like `get_snapshot`, but fails with `Busy` once `max_snapshots` are live.
+    pub fn try_get_snapshot(&self) -> Result<Snapshot, DBError> {
+        let max = self.options.max_snapshots;
+        if max > 0 && self.snapshots.count() >= max {
+            return Err(DBError::Busy(format!("too many live snapshots (max_snapshots = {})", max)));
+        }
+        Ok(<Self as DB>::get_snapshot(self))
+    }
+
+    /// Iterate the keys of `cf` in `range` as `(key, value)` pairs, in key order.
+    ///
+    /// A `std::iter::Iterator` over `new_iterator`, e.g.
+    /// `db.iter_range(cf, b"a".as_slice()..b"m".as_slice())` or `db.iter_range(cf, ..)`.
+    /// An error from the underlying iterator (such as `Expired` once the iterator is
+    /// older than `max_iterator_age_secs`) is yielded once and ends the iteration.
+    pub fn iter_range<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        cf: ColumnFamilyId,
+        range: R,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), DBError>> {
+        // iterate bounds 是 [lower, upper)；k 之后紧挨着的 key 是 k + 0x00
+        let successor = |k: &&[u8]| {
+            let mut next = k.to_vec();
+            next.push(0);
+            next
+        };
+        let lower = match range.start_bound() {
+            Bound::Included(k) => Some(k.to_vec()),
+            Bound::Excluded(k) => Some(successor(k)),
+            Bound::Unbounded => None,
+        };
+        let upper = match range.end_bound() {
+            Bound::Included(k) => Some(successor(k)),
+            Bound::Excluded(k) => Some(k.to_vec()),
+            Bound::Unbounded => None,
+        };
+        let opts = ReadOptions::default().with_iterate_bounds(lower.as_deref(), upper.as_deref());
+        DbRangeIter::new(self.new_iterator(&opts, cf))
+    }
+
+    /// memtable + 所有 level 的合并视图，和它读的（pin 住的）Version
+    ///
+    /// seq 和 get 一样取当前值（或 opts 里 snapshot 的），拿 memtable 时持锁，之后的写入不可见
+    fn open_iterator(
+        memtables: &Mutex<MemTableSet>,
+        version_set: &Mutex<VersionSet>,
+        version_pins: &Arc<VersionPins>,
+        opts: &ReadOptions,
+        cf: ColumnFamilyId,
+    ) -> (Box<dyn EngineIterator>, VersionRef) {
+        let mem = memtables.lock().unwrap();
+        let mem_iters = mem.internal_iterators(cf);
+        let mem_tombstones = mem.range_tombstones(cf);
+        let vs = version_set.lock().unwrap();
+        let seq = opts.snapshot.as_ref().map_or_else(|| vs.current_sequence(), |s| s.seq);
+        // iterator 活着期间它读的 SST 不能被删
+        let version = version_pins.pin(vs.current_version(cf));
+        (vs.new_iterator_with_memtables(cf, mem_iters, mem_tombstones, seq, opts), version)
+    }
+
+    fn max_iterator_age(&self) -> Option<Duration> {
+        match self.options.max_iterator_age_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// `vectorkv.oldest-iterators`：最老的若干 iterator / snapshot，从老到新
+    fn oldest_iterators(&self) -> String {
+        const LIMIT: usize = 16;
+        let mut handles: Vec<(Instant, serde_json::Value)> = Vec::new();
+        for it in self.iterators.oldest(LIMIT) {
+            handles.push((it.created, serde_json::json!({
+                "kind": "iterator",
+                "cf": it.cf,
+                "age_secs": it.created.elapsed().as_secs(),
+                "location": it.location.to_string(),
+            })));
+        }
+        for s in self.snapshots.oldest(LIMIT) {
+            handles.push((s.created, serde_json::json!({
+                "kind": "snapshot",
+                "seq": s.seq,
+                "age_secs": s.created.elapsed().as_secs(),
+                "location": s.location.to_string(),
+            })));
+        }
+        handles.sort_by_key(|(created, _)| *created);
+        handles.truncate(LIMIT);
+        serde_json::Value::Array(handles.into_iter().map(|(_, v)| v).collect()).to_string()
+    }
+
+    /// 最老的 snapshot 挡住墓碑回收太久时打警告；超过 max_iterator_age_secs 的直接摘掉
+    fn check_snapshot_pressure(&self) {
+        if let Some(max_age) = self.max_iterator_age() {
+            for s in self.snapshots.expire(max_age) {
+                log::warn!(
+                    "snapshot (seq {}) taken at {} is older than max_iterator_age_secs ({}s), no longer pinning old versions",
+                    s.seq,
+                    s.location,
+                    max_age.as_secs(),
+                );
+            }
+        }
+
+        let warn_after = self.options.snapshot_warn_age_secs;
+        if warn_after == 0 {
+            return;
+        }
+        if let Some(age) = self.snapshots.oldest_age() {
+            if age.as_secs() > warn_after {
+                log::warn!(
+                    "oldest snapshot (seq {}) has been live for {}s, holding back tombstone GC; {} snapshots live",
+                    self.snapshots.oldest_seq().unwrap_or(0),
+                    age.as_secs(),
+                    self.snapshots.count(),
+                );
+            }
+        }
+    }
+
+    /// Start logging every data block access to `path` (see `engine::block_trace`).
+    pub fn start_block_trace(&self, path: &Path) -> Result<(), DBError> {
+        self.table_cache.block_tracer().start(path)
+    }
+
+    /// Stop the block access trace and flush it to disk.
+    pub fn end_block_trace(&self) -> Result<(), DBError> {
+        self.table_cache.block_tracer().stop()
+    }
+
+    /// Flush and compaction jobs recorded in the JOBLOG, oldest first.
+    pub fn job_history(&self) -> Result<Vec<JobRecord>, DBError> {
+        JobLog::read_all(&self.db_config.job_log_path())
+    }
+
+    /// Memory held by memtables, the block cache and open table readers.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (active_memtables, immutable_memtables) = self.memtables.lock().unwrap().memory_usage();
+        let block_cache = self.table_cache.block_cache();
+        let (table_readers, index_blocks, filter_blocks) = self.table_cache.memory_usage();
+
+        MemoryUsage {
+            active_memtables,
+            immutable_memtables,
+            block_cache_usage: block_cache.usage_bytes(),
+            block_cache_capacity: block_cache.capacity_bytes(),
+            pinned_blocks: block_cache.pinned_usage_bytes(),
+            table_readers,
+            index_blocks,
+            filter_blocks,
+        }
+    }
+
+    fn get_internal(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_internal_with_options(cf, key, &ReadOptions::default())
+    }
+
+    fn get_internal_with_options(&self, cf: ColumnFamilyId, key: &[u8], opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        let mem =self.memtables.lock().unwrap();
+        let seq = match &opts.snapshot {
+            Some(snapshot) => snapshot.seq,
+            None => self.version_set.lock().unwrap().current_sequence(),
+        };
+        if let Some(op) = self.version_set.lock().unwrap().merge_operator(cf) {
+            return self.get_merged(&mem, cf, seq, key, op.as_ref(), opts);
+        }
+        if opts.snapshot.is_some() {
+            // 要读 snapshot 当时 pin 住的 Version，走按 seq 读的路径
+            drop(mem);
+            return self.get_at_sequence(cf, key, seq, opts);
+        }
+        // 现在只查 MemTableSet，它内部会依次查 active → immutables；最新版本是删除就不用查 SST
+        match mem.lookup(cf, seq, key) {
+            LookupResult::Found(v) => return Ok(Some(v)),
+            LookupResult::Deleted => return Ok(None),
+            // memtable 里的范围墓碑比 SST 里的所有版本都新
+            LookupResult::NotFound if mem.is_range_deleted(cf, seq, key) => return Ok(None),
+            LookupResult::NotFound => {}
+        }
+
+        // pin 住 Version 再放锁，读 SST 时不挡着 flush / compaction
+        let (version, stats) = {
+            let vs = self.version_set.lock().unwrap();
+            let stats = vs
+                .cf_statistics(cf)
+                .ok_or_else(|| DBError::NotFound(format!("column family {} not found", cf)))?;
+            (self.version_pins.pin(vs.current_version(cf)), stats)
+        };
+        drop(mem);
+        let mut seek = GetStats::default();
+        let value = version.get_with_seek_stats(key, &stats, opts, &mut seek)?;
+        // 白查太多次的文件交给 compaction，把这段 key 的读放大降下来
+        if let Some((level, file_number)) = version.update_stats(&seek) {
+            log::info!("cf {} L{} file {} used up its allowed seeks, marking for compaction", cf, level, file_number);
+            self.version_set.lock().unwrap().mark_file_for_compaction(cf, file_number);
+            if let Some((_, f)) = &seek.seek_file {
+                self.schedule_marked_compaction(cf, Some(&f.smallest_key), Some(&f.largest_key));
+            }
+        }
+        Ok(value)
+    }
+
+    /// 有 merge operator 的 CF：memtable 和 SST 里的 operand 从最近的 Put 开始依次合并
+    fn get_merged(
+        &self,
+        mem: &MemTableSet,
+        cf: ColumnFamilyId,
+        seq: SequenceNumber,
+        key: &[u8],
+        op: &dyn MergeOperator,
+        opts: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let mut operands = Vec::new();
+        let base = match mem.collect_merge_operands(cf, seq, key, &mut operands) {
+            Some(base) => base,
+            None => {
+                let version = {
+                    let vs = self.version_set.lock().unwrap();
+                    self.version_pins.pin(vs.current_version(cf))
+                };
+                version.collect_merge_operands(key, &mut operands, opts)?
+            }
+        };
+        if operands.is_empty() {
+            return Ok(base);
+        }
+        Ok(Some(full_merge(op, key, base.as_deref(), &operands)))
+    }
+
+    /// Install the merge operator `DB::merge` operands of `cf` are combined with.
+    ///
+    /// Not persisted: set it again after every open, before reading merged keys.
+    pub fn set_merge_operator(&self, cf: ColumnFamilyId, op: Arc<dyn MergeOperator + Send + Sync>) {
+        self.version_set.lock().unwrap().set_merge_operator(cf, op);
+    }
+
+    /// Quota settings of the column family's options group.
+    fn quota_options(&self, cf: ColumnFamilyId) -> QuotaOptions {
+        match self.version_set.lock().unwrap().column_family_by_id(cf) {
+            Ok(cfd) => cfd.options(&self.options).quota.clone(),
+            Err(_) => QuotaOptions::default(),
+        }
+    }
+
+    /// Request ids already applied for the token's client; `None` before its first request.
+    fn idempotency_window(&self, token: &IdempotencyToken) -> Result<Option<IdempotencyWindow>, DBError> {
+        // 内部读，不计入配额
+        let record = self.get_internal(SYSTEM_COLUMN_FAMILY_ID, &token.record_key())?;
+        Ok(record.as_deref().and_then(IdempotencyWindow::decode))
+    }
+
+    /// Last sequence of a batch written to the WAL: unlike the current
+    /// sequence it never counts sequences taken for anything but writes.
+    pub fn last_wal_sequence(&self) -> SequenceNumber {
+        self.last_wal_sequence.load(Ordering::Acquire)
+    }
+
+    /// Highest term this DB has led or followed; 0 if it never took part in
+    /// replication.
+    pub fn replication_term(&self) -> u64 {
+        *self.replication_term.lock().unwrap()
+    }
+
+    /// 见到更高的 term 时先落盘再更新；返回之前的 term
+    pub(crate) fn observe_replication_term(&self, term: u64) -> Result<u64, DBError> {
+        let mut current = self.replication_term.lock().unwrap();
+        let previous = *current;
+        if term > previous {
+            self.db_config.write_replication_term(term)?;
+            *current = term;
+        }
+        Ok(previous)
+    }
+
+    /// 开始当 leader：占一个比见过的都大的 term
+    pub(crate) fn next_replication_term(&self) -> Result<u64, DBError> {
+        let mut current = self.replication_term.lock().unwrap();
+        let term = *current + 1;
+        self.db_config.write_replication_term(term)?;
+        *current = term;
+        Ok(term)
+    }
+
+    /// Token covering every write acknowledged so far: hand it to the client
+    /// after a write returns.
+    pub fn fencing_token(&self) -> FencingToken {
+        FencingToken { term: self.replication_term(), sequence: self.last_wal_sequence() }
+    }
+
+    /// Checks that a read served by this DB is at least as new as `token`:
+    /// the DB has seen its term and written up to its sequence.
+    pub fn validate_fencing_token(&self, token: &FencingToken) -> Result<(), DBError> {
+        check_token(token, self.replication_term(), self.last_wal_sequence(), false)
+    }
+
+    /// 以 `prefix` 开头的 user key：memtable 里的（含已删除）加上 SST 里可见的
+    fn user_keys_with_prefix(&self, cf: ColumnFamilyId, prefix: &[u8]) -> BTreeSet<Vec<u8>> {
+        let mut keys = self.memtables.lock().unwrap().user_keys_with_prefix(cf, prefix);
+        let mut iter = self.version_set.lock().unwrap().new_iterator(cf);
+        iter.seek(prefix);
+        while iter.valid() {
+            match iter.key() {
+                Some(k) if k.starts_with(prefix) => keys.insert(k.to_vec()),
+                _ => break,
+            };
+            iter.next();
+        }
+        keys
+    }
+
+    /// Delete SST files that no Version references any more.
+    /// 删 SST；还被 iterator / get pin 着的文件等它们放掉后再删
+    pub(crate) fn purge_files(&self, file_numbers: &[u64]) {
+        self.version_pins.purge(file_numbers);
+    }
+
+    /// 删掉不再被任何 Version 引用的 SST 和旧的 MANIFEST / WAL，见 `VersionSet::delete_obsolete_files`
+    fn delete_obsolete_files(&self) {
+        if let Err(e) = self.version_set.lock().unwrap().delete_obsolete_files() {
+            log::warn!("failed to delete obsolete files: {:?}", e);
+        }
+    }
+
+
+
+    /// 重放 WAL，顺带检查 SST / WAL 里的 sequence 是否乱序；返回检查结果和
+    /// 重放的内容是否被 `wal_filter` 或 sequence 修复改过
+    fn recover(&self, wal_filter: Option<&dyn WalFilter>) -> Result<(RecoveryInfo, bool), DBError> {
+        let mut info = RecoveryInfo::default();
+        let dropped = {
+            let vs = self.version_set.lock().unwrap();
+            info.manifest_sequence = vs.current_sequence();
+            info.wal_applied_sequence = vs.wal_applied_sequence();
+            for cf in vs.column_families() {
+                for f in vs.current_version(cf).levels().iter().flatten() {
+                    // 只看首尾两条 key 的 seq，不读整个文件
+                    let seq = [&f.smallest_key, &f.largest_key].into_iter()
+                        .filter_map(|k| InternalKey::decode(k).ok())
+                        .map(|k| k.seq)
+                        .max()
+                        .unwrap_or(0);
+                    info.sst_max_sequence = info.sst_max_sequence.max(seq);
+                    if seq > info.manifest_sequence {
+                        info.sequence_issues.push(SequenceIssue::SstAheadOfManifest {
+                            cf,
+                            file_number: f.file_number,
+                            sequence: seq,
+                        });
+                    }
+                }
+            }
+            vs.dropped_column_families().clone()
+        };
+        let applied = info.wal_applied_sequence;
+        let repair = self.options.repair_sequence_numbers;
+        let mut changed = false;
+        let mut stopped = false;
+        self.wal_manager.replay_batches(|base_seq, batch| {
+            // close 时已经 flush 过、但 WAL 没来得及截断的记录
+            if stopped || (applied > 0 && base_seq + batch.len() as u64 <= applied + 1) {
+                info.batches_skipped += 1;
+                return Ok(());
+            }
+            let mut batch = match wal_filter.map(|f| (f, f.log_record(base_seq, &batch))) {
+                None | Some((_, WalProcessingOption::Continue)) => batch,
+                Some((f, WalProcessingOption::Skip)) => {
+                    log::info!("WAL filter {} skipped the record at sequence {}", f.name(), base_seq);
+                    info.batches_skipped += 1;
+                    changed = true;
+                    return Ok(());
+                }
+                Some((f, WalProcessingOption::Replace(new_batch))) => {
+                    // 后面的记录占着之后的 seq，替换的 batch 不能更长
+                    if new_batch.len() > batch.len() {
+                        return Err(DBError::InvalidArgument(format!(
+                            "WAL filter {} replaced the {}-entry record at sequence {} with {} entries",
+                            f.name(), batch.len(), base_seq, new_batch.len()
+                        )));
+                    }
+                    log::info!("WAL filter {} replaced the record at sequence {}", f.name(), base_seq);
+                    changed = true;
+                    new_batch
+                }
+                Some((f, WalProcessingOption::Stop)) => {
+                    log::info!("WAL filter {} stopped recovery at sequence {}", f.name(), base_seq);
+                    info.batches_skipped += 1;
+                    changed = true;
+                    stopped = true;
+                    return Ok(());
+                }
+            };
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            let mut base_seq = base_seq;
+            let previous_last_seq = info.last_wal_sequence;
+            if info.batches_replayed > 0 && base_seq <= previous_last_seq {
+                info.sequence_issues.push(SequenceIssue::WalOverlap {
+                    base_seq,
+                    last_seq: base_seq + batch.len() as u64 - 1,
+                    previous_last_seq,
+                });
+                if repair {
+                    // 挪到上一个 batch 之后；WAL 里还是旧的 seq，重放完要落盘并清空 WAL
+                    base_seq = previous_last_seq + 1;
+                    batch.set_sequence(base_seq);
+                    changed = true;
+                }
+            }
+            self.memtables.lock().unwrap().apply_for_recovery(base_seq, &batch, &dropped)?;
+            info.batches_replayed += 1;
+            info.last_wal_sequence = info.last_wal_sequence.max(base_seq + batch.len() as u64 - 1);
+            Ok(())
+        })?;
+
+        if info.last_wal_sequence > info.manifest_sequence {
+            info.sequence_issues.push(SequenceIssue::WalAheadOfManifest { last_seq: info.last_wal_sequence });
+        }
+        if !info.sequence_issues.is_empty() {
+            if repair {
+                self.version_set.lock().unwrap().advance_sequence(info.max_sequence())?;
+                info.repaired = true;
+                log::warn!("repaired {} sequence number issues: {:?}", info.sequence_issues.len(), info.sequence_issues);
+            } else {
+                log::warn!(
+                    "found {} sequence number issues, set repair_sequence_numbers to fix them: {:?}",
+                    info.sequence_issues.len(), info.sequence_issues
+                );
+            }
+        }
+        Ok((info, changed))
+    }
+
+    /// Shut the DB down cleanly.
+    ///
+    /// Waits for queued background work, then, unless `avoid_flush_during_shutdown`
+    /// is set, flushes every column family's memtables, records in the MANIFEST that
+    /// the WAL is fully applied and truncates it, so the next open replays nothing.
+    /// The DB must not be used after `close` returns.
+    pub fn close(&self) -> Result<(), DBError> {
+        self.bg_worker.shutdown();
+        if self.options.avoid_flush_during_shutdown {
+            return Ok(());
+        }
+
+        let applied = self.flush_and_truncate_wal()?;
+        log::info!("closed {} with every memtable flushed up to sequence {}", self.name, applied);
+        Ok(())
+    }
+
+    /// flush 所有 memtable，在 MANIFEST 里记下 WAL 已经全部落盘，再清空 WAL
+    fn flush_and_truncate_wal(&self) -> Result<SequenceNumber, DBError> {
+        self.flush_all_memtables()?;
+        let applied = {
+            let mut vs = self.version_set.lock().unwrap();
+            let seq = vs.current_sequence();
+            vs.mark_wal_applied(seq)?;
+            seq
+        };
+        if self.is_replica.load(Ordering::Acquire) {
+            // WAL 清空之后复制进度只在这里
+            self.db_config.write_replicated_sequence(self.replicated_sequence())?;
+        }
+        self.wal_manager.truncate()?;
+        Ok(applied)
+    }
+
+    /// 把 memtable 写成 L0 的 SST 装进 Version，见 `DB::flush_memtable`
+    fn write_memtable(&self, mem: Arc<dyn MemTable>) -> Result<(), DBError> {
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let cpu = CpuTimer::start();
+
+        // 1️⃣ 创建 SST 文件
+        let cf = mem.cf_id();
+
+        // 只有范围墓碑的 memtable：不写 SST，墓碑直接记进 MANIFEST
+        if mem.iter().next().is_none() {
+            let vs = self.version_set.lock().unwrap();
+            let cf_type = vs.column_family_by_id(cf)?.cf_type;
+            let mut edit = VersionEdit::new(cf, cf_type);
+            edit.range_tombstones = mem.range_tombstones();
+            if let Some(n) = mem.flush_file_number() {
+                vs.release_pending_output(n);
+            }
+            let queue = vs.manifest_queue();
+            drop(vs);
+            return queue.submit(&self.version_set, edit);
+        }
+
+        // 冻结时就分好了号，几个 flush 谁先跑完都不影响 L0 的新旧顺序
+        let file_number = match mem.flush_file_number() {
+            Some(n) => n,
+            None => self.version_set.lock().unwrap().new_file_number()?,
+        };
+        // flush 的输出都在 L0
+        let file_path = self.db_config.sst_path(0, file_number);
+        let mut info = FlushJobInfo { cf, file_number, file_path: file_path.clone(), ..Default::default() };
+        // listener 可能回调 DB，不能拿着 version_set 锁调
+        self.db_config.listeners().notify(|l| l.on_flush_begin(&info));
+        let mut vs = self.version_set.lock().unwrap();
+        let file = File::create(&file_path)?;
+        let cfd = vs.column_family_by_id(cf)?;
+        let cf_options = cfd.options(&self.options);
+
+
+        // 2️⃣ TableBuilder
+        let mut builder = TableBuilder::from_options(
+            file_number,
+            BufWriter::new(file),
+            &cf_options,
+        );
+
+        // 3️⃣ 遍历 memtable
+        let mut encoded = Vec::new();
+        for (key, value) in mem.iter() {
+            encoded.clear();
+            key.encode_to(&mut encoded);
+            builder.add(&encoded, value)?;
+        }
+
+        // 4️⃣ finish -> 写 footer
+        builder.finish()?;
+        // 文件和目录项都落盘后才能写进 MANIFEST，否则 crash 后 MANIFEST 指向一个不完整 / 不存在的文件
+        sync_file(&file_path, self.options.use_fsync)?;
+        if let Some(dir) = file_path.parent() {
+            sync_dir(dir)?;
+        }
+
+        // 5️⃣ 安装到 VersionSet (LSM)：走 MANIFEST 写入队列，和同时结束的 compaction 一起提交
+        let edit = vs.new_table_edit(
+            cf,
+            cfd.cf_type,
+            file_number,
+            &file_path,
+            mem.smallest_key(),
+            mem.largest_key(),
+            mem.range_tombstones(),
+        )?;
+        let queue = vs.manifest_queue();
+        drop(vs);
+        let result = queue.submit(&self.version_set, edit);
+        // 装进 Version 之后它就按活文件算了；没装上的这个文件也该被 GC 掉
+        self.version_set.lock().unwrap().release_pending_output(file_number);
+        result?;
+        self.auto_tuner.record_flush();
+        self.delete_obsolete_files();
+        let vs = self.version_set.lock().unwrap();
+
+        self.check_snapshot_pressure();
+
+        // 6️⃣ 记 JOBLOG
+        let mut record = JobRecord::new(JobKind::Flush, cf, started_at, started.elapsed());
+        record.output_files.push(file_number);
+        record.output_bytes = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+        record.cpu_micros = cpu.elapsed().as_micros() as u64;
+        info.file_size = record.output_bytes;
+        info.elapsed = started.elapsed();
+        if let Some(stats) = vs.cf_statistics(cf) {
+            stats.record_job(&record);
+        }
+        vs.job_log().append(record);
+        drop(vs);
+
+        let listeners = self.db_config.listeners();
+        let created = TableFileCreationInfo {
+            cf,
+            file_number,
+            file_path: file_path.clone(),
+            file_size: info.file_size,
+            level: 0,
+            reason: TableFileCreationReason::Flush,
+        };
+        listeners.notify(|l| l.on_table_file_created(&created));
+        listeners.notify(|l| l.on_flush_completed(&info));
+
+        Ok(())
+    }
+
+    /// 冻结每个 CF 的 active memtable，把所有 memtable 同步 flush 成 SST
+    fn flush_all_memtables(&self) -> Result<(), DBError> {
+        let cfs = self.version_set.lock().unwrap().column_families();
+        self.flush_memtables_of(&cfs)
+    }
+
+    /// 同上，只 flush `cfs` 的
+    pub(crate) fn flush_memtables_of(&self, cfs: &[ColumnFamilyId]) -> Result<(), DBError> {
+        let tables = {
+            let mut mem = self.memtables.lock().unwrap();
+            let vs = self.version_set.lock().unwrap();
+            let seq = vs.next_sequence()?;
+            let mut tables = Vec::new();
+            for &cf in cfs {
+                mem.freeze_active(cf, seq, vs.new_file_number()?)?;
+                while let Some(t) = mem.pick_flush_candidate(cf) {
+                    tables.push(t);
+                }
+            }
+            tables
+        };
+
+        for (i, t) in tables.iter().enumerate() {
+            if t.iter().next().is_some() || !t.range_tombstones().is_empty() {
+                if let Err(e) = self.flush_memtable(Arc::clone(t)) {
+                    // 还没轮到的放回去，不能一直挂在 flushing 里
+                    let mut mem = self.memtables.lock().unwrap();
+                    for rest in &tables[i + 1..] {
+                        mem.abort_flush(rest.cf_id(), rest);
+                    }
+                    drop(mem);
+                    self.flush_done.notify_all();
+                    return Err(e);
+                }
+                continue;
+            }
+            if let Some(n) = t.flush_file_number() {
+                self.version_set.lock().unwrap().release_pending_output(n);
+            }
+            self.memtables.lock().unwrap().finish_flush(t.cf_id(), t);
+        }
+        self.wait_for_flushes(cfs);
+        Ok(())
+    }
+
+    /// 等后台 flush 已经拿走的 memtable 装进 Version（或者失败放回 immutables）
+    fn wait_for_flushes(&self, cfs: &[ColumnFamilyId]) {
+        let mem = self.memtables.lock().unwrap();
+        let _mem = self.flush_done
+            .wait_while(mem, |mem| cfs.iter().any(|&cf| mem.num_flushing(cf) > 0))
+            .unwrap();
+    }
+
+    /// Creates a copy of this DB at `path` that can be opened and written on its own.
+    ///
+    /// Memtables are flushed first, waiting for ones a background flush already
+    /// picked up; the SST files are then hard-linked (copied when
+    /// `path` is on another filesystem) and the copy gets its own MANIFEST and an
+    /// empty WAL, so forking takes about as long as a flush however large the DB is.
+    /// Writes racing with the fork may or may not be in it. The fork uses the default
+    /// directory layout under `path`; a config file has to be put there separately.
+    pub fn fork(&self, path: &str) -> Result<(), DBError> {
+        let fork = DbConfig::from_open_options(PathBuf::from(path), &OpenOptions::default());
+        if fork.looks_like_existing_db() {
+            return Err(DBError::InvalidArgument(format!("{} already holds a DB", path)));
+        }
+
+        self.flush_all_memtables()?;
+        // 链接完之前这些 Version 的 SST 不能被 compaction 删掉
+        let (_versions, files, edits) = {
+            let vs = self.version_set.lock().unwrap();
+            let versions: Vec<_> = vs.column_families()
+                .into_iter()
+                .map(|cf| self.version_pins.pin(vs.current_version(cf)))
+                .collect();
+            let files: Vec<(usize, u64)> = versions.iter()
+                .flat_map(|v| v.levels().iter().enumerate()
+                    .flat_map(|(level, files)| files.iter().map(move |f| (level, f.file_number)))
+                    .collect::<Vec<_>>())
+                .collect();
+            (versions, files, vs.snapshot_edits())
+        };
+
+        fork.create_dirs()?;
+        let mut copied = 0;
+        for &(level, file_number) in &files {
+            let src = self.db_config.locate_sst(file_number)?;
+            let dst = fork.sst_path(level, file_number);
+            if let Err(e) = fs::hard_link(&src, &dst) {
+                log::debug!("hard link {:?} -> {:?} failed ({}), copying", src, dst, e);
+                fs::copy(&src, &dst)?;
+                copied += 1;
+            }
+        }
+        sync_dir(&fork.sst_dir)?;
+
+        let mut manifest = ManifestWriter::create_new(&fork.manifest_dir.join(FIRST_MANIFEST))?;
+        manifest.add_records(&edits)?;
+        drop(manifest);
+        write_current(&fork.db_path, FIRST_MANIFEST)?;
+        sync_dir(&fork.db_path)?;
+        log::info!(
+            "forked {} to {}: {} SST files shared, {} copied",
+            self.name, path, files.len() - copied, copied
+        );
+        Ok(())
+    }
+
+    /// Writes the SSTs of `cf` and an `EXPORT.json` describing them into `dir`,
+    /// for `create_column_family_with_import` on this or another DB.
+    ///
+    /// The column family's memtables are flushed first, waiting for ones a
+    /// background flush already picked up, then its SSTs are hard-linked (copied when `dir` is on another filesystem). Writes racing
+    /// with the export may or may not be in it. `dir` must not exist.
+    pub fn export_column_family(&self, cf: ColumnFamilyId, dir: impl AsRef<Path>) -> Result<ExportMetadata, DBError> {
+        let dir = dir.as_ref();
+        if dir.exists() {
+            return Err(DBError::InvalidArgument(format!("export dir {:?} already exists", dir)));
+        }
+        let cf_name = self.version_set.lock().unwrap().column_family_by_id(cf)?.name.clone();
+        self.flush_memtables_of(&[cf])?;
+
+        // 链接完之前这个 Version 的 SST 不能被 compaction 删掉
+        let (version, sequence) = {
+            let vs = self.version_set.lock().unwrap();
+            (self.version_pins.pin(vs.current_version(cf)), vs.current_sequence())
+        };
+        fs::create_dir_all(dir)?;
+        let result = (|| -> Result<ExportMetadata, DBError> {
+            let mut files = Vec::new();
+            for (level, f) in version.levels().iter().enumerate().flat_map(|(l, level)| level.iter().map(move |f| (l, f))) {
+                let src = self.db_config.locate_sst(f.file_number)?;
+                let file_name = sst_file_name(f.file_number);
+                let dst = dir.join(&file_name);
+                if fs::hard_link(&src, &dst).is_err() {
+                    fs::copy(&src, &dst)?;
+                    sync_file(&dst, false)?;
+                }
+                files.push(ExportedFile {
+                    file_name,
+                    level,
+                    file_size: f.file_size,
+                    smallest_key: f.smallest_key.clone(),
+                    largest_key: f.largest_key.clone(),
+                    crc32c: match f.file_checksum {
+                        Some(crc) => crc,
+                        None => file_checksum(&src)?,
+                    },
+                });
+            }
+            let range_tombstones = version.range_tombstones().iter().map(Into::into).collect();
+            let meta = ExportMetadata { cf_name, sequence, files, range_tombstones };
+            meta.write(dir)?;
+            sync_dir(dir)?;
+            Ok(meta)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        let meta = result?;
+        log::info!("exported column family {} ({} files) to {:?}", meta.cf_name, meta.files.len(), dir);
+        Ok(meta)
+    }
+
+    /// Creates column family `name` holding the SSTs that `export_column_family`
+    /// wrote into `dir`, at the same levels.
+    ///
+    /// The files are checked against their checksums and hard-linked (or
+    /// copied) into the DB, so `dir` can be removed afterwards. The DB's
+    /// sequence counter moves past the exported one, so later writes read as
+    /// newer than the imported data.
+    pub fn create_column_family_with_import(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+        dir: impl AsRef<Path>,
+    ) -> Result<ColumnFamilyId, DBError> {
+        let dir = dir.as_ref();
+        let meta = ExportMetadata::read(dir)?;
+        if name.is_empty() {
+            return Err(DBError::InvalidArgument("column family name is empty".into()));
+        }
+        if let Some(f) = meta.files.iter().find(|f| f.level >= NUM_LEVELS) {
+            return Err(DBError::Corruption(format!("exported file {} is at level {}", f.file_name, f.level)));
+        }
+
+        // 1. 校验后拷（或硬链接）进 DB 目录，分配 file number
+        let mut installed: Vec<(u64, PathBuf)> = Vec::with_capacity(meta.files.len());
+        let result = (|| {
+            for f in &meta.files {
+                let src = dir.join(&f.file_name);
+                let actual = file_checksum(&src)?;
+                if actual != f.crc32c {
+                    return Err(DBError::Corruption(format!(
+                        "exported file {:?}: expected checksum {:08x}, got {:08x}", src, f.crc32c, actual
+                    )));
+                }
+                let file_number = self.version_set.lock().unwrap().new_file_number()?;
+                let dest = self.db_config.sst_path(f.level, file_number);
+                installed.push((file_number, dest.clone()));
+                if fs::hard_link(&src, &dest).is_err() {
+                    fs::copy(&src, &dest)?;
+                }
+                sync_file(&dest, self.options.use_fsync)?;
+            }
+            let dirs: BTreeSet<&Path> = installed.iter().filter_map(|(_, dest)| dest.parent()).collect();
+            for dir in dirs {
+                sync_dir(dir)?;
+            }
+            self.install_imported_cf(name, options, &meta, &installed)
+        })();
+
+        let vs = self.version_set.lock().unwrap();
+        for (file_number, dest) in &installed {
+            vs.release_pending_output(*file_number);
+            if result.is_err() {
+                let _ = fs::remove_file(dest);
+            }
+        }
+        drop(vs);
+        let cf = result?;
+        log::info!("imported column family {} ({} files) from {:?} as {}", meta.cf_name, meta.files.len(), dir, name);
+        Ok(cf)
+    }
+
+    /// 建 CF、把 sequence 推过导出时的，再把文件装进它的 Version
+    fn install_imported_cf(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+        meta: &ExportMetadata,
+        installed: &[(u64, PathBuf)],
+    ) -> Result<ColumnFamilyId, DBError> {
+        let cf = {
+            // 锁顺序：memtables -> version_set，新 CF 的 memtable 和 Version 一起出现
+            let mut memtables = self.memtables.lock().unwrap();
+            let mut vs = self.version_set.lock().unwrap();
+            vs.advance_sequence(meta.sequence)?;
+            let cf = vs.create_column_family(name, CfType::User, Some(options))?;
+            memtables.add_cf(cf, vs.current_sequence());
+            cf
+        };
+
+        let mut edit = VersionEdit::new(cf, CfType::User);
+        for (f, (file_number, _)) in meta.files.iter().zip(installed) {
+            edit.add_file(f.level, *file_number, f.file_size, &f.smallest_key, &f.largest_key);
+            edit.set_file_checksum(*file_number, f.crc32c);
+        }
+        edit.range_tombstones = meta.range_tombstones.iter().map(Into::into).collect();
+        let queue = self.version_set.lock().unwrap().manifest_queue();
+        if let Err(e) = queue.submit(&self.version_set, edit) {
+            // 空 CF 留着没用，删掉；文件由调用方删
+            if let Err(drop_err) = self.remove_column_family(cf) {
+                log::warn!("failed to drop column family {} after a failed import: {:?}", name, drop_err);
+            }
+            return Err(e);
+        }
+        Ok(cf)
+    }
+
+    /// 写之前的检查：merge 要求 CF 配了 MergeOperator（否则读的时候没法合并），别人租下的范围不能写
+    fn check_write(&self, opts: &WriteOptions, batch: &WriteBatch) -> Result<(), DBError> {
+        {
+            let vs = self.version_set.lock().unwrap();
+            for entry in batch {
+                if let WriteBatchEntry::Merge { cf, .. } = entry {
+                    if vs.merge_operator(cf).is_none() {
+                        return Err(DBError::InvalidArgument(format!("column family {} has no merge operator", cf)));
+                    }
+                }
+            }
+        }
+        if self.options.enforce_range_locks {
+            self.check_range_locks(opts, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Commits a transaction whose write set was partly spilled to `spill`,
+    /// followed by the in-memory `tail`, without rebuilding it in memory.
+    ///
+    /// The chunks are read back one at a time and streamed into a single WAL
+    /// record, then applied to the memtables, while other writers wait. Recovery
+    /// sees the whole transaction or none of it.
+    pub(crate) fn write_spilled(&self, opts: &WriteOptions, spill: &SpillFile, tail: WriteBatch) -> Result<(), DBError> {
+        let _span = Span::enter("write");
+        // 1. 逐段检查、算配额、给 memtable 腾地方
+        let mut usage: HashMap<ColumnFamilyId, (u64, u64)> = HashMap::new();
+        let mut prepare = |chunk: &WriteBatch| -> Result<(), DBError> {
+            self.check_write(opts, chunk)?;
+            add_write_usage(&mut usage, chunk);
+            self.make_room_for_write(chunk)?;
+            Ok(())
+        };
+        spill.for_each_chunk(|chunk| prepare(&chunk))?;
+        prepare(&tail)?;
+        self.quotas.acquire_write(&usage, |cf| self.quota_options(cf))?;
+        self.check_warmup(opts)?;
+
+        // 2. 独占写入顺序：分一段 seq，整个写集流式写成一条 WAL record，再一段段进 memtable、
+        //    更新向量索引
+        let n = (spill.entries() + tail.len()) as u64;
+        let count = u32::try_from(n)
+            .map_err(|_| DBError::InvalidArgument(format!("transaction with {} writes is too large", n)))?;
+        self.write_group.exclusive(|| {
+            let base_seq = self.version_set.lock().unwrap().allocate_sequence_range(n)?;
+            if self.options.enable_write_ahead_log && !opts.disable_wal {
+                self.wal_manager.append_streamed(base_seq, count, opts.sync, |write| {
+                    spill.for_each_chunk(|chunk| write(chunk.entries_data()))?;
+                    write(tail.entries_data())
+                })?;
+                self.last_wal_sequence.fetch_max(base_seq + n.max(1) - 1, Ordering::AcqRel);
+            }
+            let mut seq = base_seq;
+            spill.for_each_chunk(|chunk| {
+                self.memtables.lock().unwrap().apply(seq, &chunk)?;
+                self.update_vector_indexes(&chunk);
+                seq += chunk.len() as u64;
+                Ok(())
+            })?;
+            self.memtables.lock().unwrap().apply(seq, &tail)?;
+            self.update_vector_indexes(&tail);
+            Ok(())
+        })
+    }
+
+    /// 给 `group` 分一段 sequence，写一条 WAL record 再写进 MemTableSet，已建好的向量索引跟着更新
+    ///
+    /// 调用方要占着写入顺序：写组的 leader，或者 `WriteGroup::exclusive` 里
+    fn commit_batch(&self, mut group: WriteBatch, opts: &WriteOptions) -> Result<(), DBError> {
+        let base_seq = self.version_set.lock().unwrap().allocate_sequence_range(group.len() as u64)?;
+        group.set_sequence(base_seq);
+        if self.options.enable_write_ahead_log && !opts.disable_wal {
+            if opts.sync {
+                self.wal_manager.append_sync(&group)?;
+            } else {
+                self.wal_manager.append_no_sync(&group)?;
+            }
+            self.last_wal_sequence.fetch_max(base_seq + (group.len() as u64).max(1) - 1, Ordering::AcqRel);
+        }
+        // 新占的字节已经记在各 CF 的 active 上，make_room_for_write 按它决定要不要切 memtable
+        self.memtables.lock().unwrap().apply(base_seq, &group)?;
+        self.update_vector_indexes(&group);
+        Ok(())
+    }
+
+    fn make_room_for_write(&self, batch: &WriteBatch) -> Result<(),DBError> {
+        const MAX_IMMUTABLES: usize = 4;
+
+        self.auto_tuner.maybe_tune(|| self.total_compaction_debt());
+        let tuned = self.auto_tuner.current();
+
+        let mut mem = self.memtables.lock().unwrap();
+        let mut changes = Vec::new();
+        let mut stopped = None;
+        let mut frozen = VecDeque::new();
+
+        for &cf in batch.involved_cfs() {
+            let active_usage = mem
+                .cfs
+                .get(&cf)
+                .ok_or(DBError::InvalidArgument("unknown CF".into()))?
+                .active_memory_usage();
+            if active_usage >= tuned.write_buffer_size {
+                let (new_seq, file_number) = {
+                    let vs = self.version_set.lock().unwrap();
+                    (vs.next_sequence()?, vs.new_file_number()?)
+                };
+                mem.freeze_active(cf, new_seq, file_number)?;
+                while let Some(t) = mem.pick_flush_candidate(cf) {
+                    frozen.push_back(t);
+                }
+            }
+
+            // 1. stall 判定：immutable 堆积 / L0 文件过多
+            let immutables = mem.num_immutables(cf);
+            let l0_files = self.version_set.lock().unwrap().current_version(cf).num_files(0);
+            let (condition, cause) = if immutables >= MAX_IMMUTABLES {
+                (WriteStallCondition::Stopped, Some(WriteStallCause::MemtableLimit))
+            } else if l0_files >= tuned.level0_stop_writes_trigger {
+                (WriteStallCondition::Stopped, Some(WriteStallCause::L0FileCount))
+            } else if immutables + 1 >= MAX_IMMUTABLES {
+                (WriteStallCondition::Delayed, Some(WriteStallCause::MemtableLimit))
+            } else if l0_files >= tuned.level0_slowdown_writes_trigger {
+                (WriteStallCondition::Delayed, Some(WriteStallCause::L0FileCount))
+            } else {
+                (WriteStallCondition::Normal, None)
+            };
+
+            if let Some(cause) = cause {
+                self.auto_tuner.record_stall(cause);
+            }
+            if let Some(info) = self.write_stall.update(cf, condition, cause) {
+                changes.push(info);
+            }
+            if condition == WriteStallCondition::Stopped && stopped.is_none() {
+                let reason = match cause {
+                    Some(WriteStallCause::L0FileCount) => format!("cf {} has {} L0 files", cf, l0_files),
+                    _ => format!("cf {} has {} memtables waiting for flush", cf, immutables),
+                };
+                stopped = Some(reason);
+            }
+        }
+        drop(mem);
+
+        // 队列满时 Block 策略会让 schedule 等到 flush 线程腾出位置，flush 线程要拿 memtable 锁，
+        // 所以放了锁再交出去
+        if !frozen.is_empty() {
+            self.schedule_flush(frozen);
+        }
+
+        // 2. 通知 listener（不持锁）
+        if !changes.is_empty() {
+            for info in &changes {
+                self.db_config.listeners().notify(|l| l.on_stall_conditions_changed(info));
+            }
+        }
+
+        // 3. 停写：直接拒绝，带上退避时间，让客户端退避而不是堵在服务端
+        match stopped {
+            Some(reason) => Err(DBError::WriteStall {
+                retry_after_ms: self.options.write_stall_retry_after_ms.saturating_mul(2),
+                reason,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Estimated bytes compaction still has to rewrite in `cf`
+    /// (see `Version::compaction_debt`).
+    pub fn compaction_debt(&self, cf: ColumnFamilyId) -> u64 {
+        let version = self.version_set.lock().unwrap().current_version(cf);
+        version.compaction_debt(
+            self.options.level0_file_num_compaction_trigger,
+            self.options.max_bytes_for_level_base,
+            self.options.max_bytes_for_level_multiplier,
+        )
+    }
+
+    /// Rewrite the live SSTs `file_numbers` of `cf` in place with the table settings of
+    /// `new_options` (compression, filter, block size, ...).
+    ///
+    /// Each file is run through compaction on its own and stays on its level, so files
+    /// written under old options can be migrated a few at a time instead of with a full
+    /// manual compaction. L0 files are rejected; compact L0 first.
+    pub fn rewrite_files(&self, cf: ColumnFamilyId, file_numbers: &[u64], new_options: &ColumnFamilyOptions) -> Result<(), DBError> {
+        VersionSet::rewrite_files(&self.version_set, cf, file_numbers, new_options)
+    }
+
+    /// 在当前线程把 `cf` 里和 [begin, end] 重叠的文件从 L0 开始逐层往下合并；
+    /// 后台的 `CompactionCommand` 跑的就是这个
+    pub(crate) fn compact_range_now(&self, cf: ColumnFamilyId, begin: Option<&[u8]>, end: Option<&[u8]>) -> Result<(), DBError> {
+        for level in 0..NUM_LEVELS - 1 {
+            VersionSet::compact_level_range(&self.version_set, cf, level, begin, end)?;
+        }
+        Ok(())
+    }
+
+    /// Bulk-load SSTs built with `SstFileWriter` into `cf`.
+    ///
+    /// Each file gets a new file number and is copied (or, with `move_files`, linked)
+    /// into the DB directory, then all files are installed with a single `VersionEdit`,
+    /// each on the lowest level where it overlaps nothing above it. If any file
+    /// overlaps existing data, or snapshots are held, the files get one fresh global
+    /// sequence number so they read as the newest version of their keys.
+    ///
+    /// The files must not overlap each other. Unflushed writes overlapping a file are
+    /// flushed first (`allow_blocking_flush`); writes are held back while the files are
+    /// installed.
+    pub fn ingest_external_file<P: AsRef<Path>>(
+        self: &Arc<Self>,
+        cf: ColumnFamilyId,
+        paths: &[P],
+        opts: &IngestOptions,
+    ) -> Result<(), DBError> {
+        const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+        const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let cf_type = self.version_set.lock().unwrap().column_family_by_id(cf)?.cf_type;
+
+        let mut files = paths.iter()
+            .map(|p| ExternalFile::inspect(p.as_ref().to_path_buf(), opts.verify_checksums))
+            .collect::<Result<Vec<_>, DBError>>()?;
+        files.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+        for pair in files.windows(2) {
+            if pair[1].overlaps(&pair[0].smallest, &pair[0].largest) {
+                return Err(DBError::InvalidArgument(format!(
+                    "external files {:?} and {:?} overlap", pair[0].path, pair[1].path
+                )));
+            }
+        }
+        let mut user_ranges = Vec::with_capacity(files.len());
+        for f in &files {
+            user_ranges.push((f.smallest_user_key()?, f.largest_user_key()?));
+        }
+        let overlaps_memtables = |mem: &MemTableSet| {
+            user_ranges.iter().any(|(s, l)| mem.overlaps_user_range(cf, s, l))
+        };
+
+        // 1. 没落盘的写和文件有交集：先 flush，等它们进 SST
+        if overlaps_memtables(&self.memtables.lock().unwrap()) {
+            if !opts.allow_blocking_flush {
+                return Err(DBError::InvalidArgument(format!(
+                    "external files overlap unflushed writes of column family {}", cf
+                )));
+            }
+            self.flush(cf)?;
+            let deadline = Instant::now() + FLUSH_TIMEOUT;
+            while overlaps_memtables(&self.memtables.lock().unwrap()) {
+                if Instant::now() >= deadline {
+                    return Err(DBError::Busy(format!("flush of column family {} did not finish in {:?}", cf, FLUSH_TIMEOUT)));
+                }
+                std::thread::sleep(FLUSH_POLL_INTERVAL);
+            }
+        }
+
+        // 2. 拷（或硬链接）进 DB 目录，分配 file number；先用临时名，定了层再改名
+        let mut installed: Vec<(u64, PathBuf)> = Vec::with_capacity(files.len());
+        let result = (|| {
+            for f in &files {
+                let file_number = self.version_set.lock().unwrap().new_file_number()?;
+                let dest = self.db_config.temp_sst_path(file_number);
+                installed.push((file_number, dest.clone()));
+                if !(opts.move_files && fs::hard_link(&f.path, &dest).is_ok()) {
+                    fs::copy(&f.path, &dest)?;
+                }
+            }
+            self.install_external_files(cf, cf_type, &files, &installed, opts)
+        })();
+
+        let vs = self.version_set.lock().unwrap();
+        for (file_number, dest) in &installed {
+            vs.release_pending_output(*file_number);
+            if result.is_err() {
+                let _ = fs::remove_file(dest);
+                if let Ok(path) = self.db_config.locate_sst(*file_number) {
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+        drop(vs);
+        result?;
+
+        if opts.move_files {
+            for f in &files {
+                if let Err(e) = fs::remove_file(&f.path) {
+                    log::warn!("failed to remove ingested file {:?}: {}", f.path, e);
+                }
+            }
+        }
+        // 图里没有这些向量，下次查询时重建
+        self.vector_indexes.write().unwrap().retain(|(c, _), _| *c != cf);
+        log::info!("ingested {} external files into column family {}", files.len(), cf);
+        Ok(())
+    }
+
+    /// 拿着 memtable 锁挑 level、分配 global seqno、写 MANIFEST，期间写不进来
+    fn install_external_files(
+        &self,
+        cf: ColumnFamilyId,
+        cf_type: CfType,
+        files: &[ExternalFile],
+        installed: &[(u64, PathBuf)],
+        opts: &IngestOptions,
+    ) -> Result<(), DBError> {
+        // 1. 改 global seqno、fsync、算校验和都在拿 memtable 锁之前做，写入不用等这些文件操作
+        let global_seqno = {
+            let vs = self.version_set.lock().unwrap();
+            if self.needs_global_seqno(&vs.current_version(cf), files)? {
+                if !opts.allow_global_seqno {
+                    return Err(DBError::InvalidArgument(format!(
+                        "external files overlap existing data of column family {} and allow_global_seqno is off", cf
+                    )));
+                }
+                Some(vs.next_sequence()?)
+            } else {
+                None
+            }
+        };
+        let mut checksums = Vec::with_capacity(installed.len());
+        for (_, temp) in installed {
+            if let Some(seqno) = global_seqno {
+                assign_global_seqno(temp, seqno)?;
+            }
+            sync_file(temp, self.options.use_fsync)?;
+            checksums.push(file_checksum(temp)?);
+        }
+
+        // 2. 拿着 memtable 锁装进 Version，这期间不会有和文件交叉的新写入
+        let memtables = self.memtables.lock().unwrap();
+        for f in files {
+            if memtables.overlaps_user_range(cf, &f.smallest_user_key()?, &f.largest_user_key()?) {
+                return Err(DBError::Busy(format!("writes to column family {} raced with the ingest", cf)));
+            }
+        }
+
+        let vs = self.version_set.lock().unwrap();
+        let version = vs.current_version(cf);
+        if global_seqno.is_none() && self.needs_global_seqno(&version, files)? {
+            // 改文件的时候来了 flush / DeleteRange / snapshot，seq 为 0 的文件会被它们盖住
+            return Err(DBError::Busy(format!("column family {} changed during the ingest", cf)));
+        }
+        let levels = version.levels();
+        let overlaps_level = |f: &ExternalFile, level: usize| {
+            levels[level].iter().any(|m| f.overlaps(&m.smallest_key, &m.largest_key))
+        };
+        // 和哪一层都不交的放最底层；否则放在第一个有交集的层之上（L0 有交集就只能进 L0）
+        let targets: Vec<usize> = files.iter()
+            .map(|f| match (0..NUM_LEVELS).find(|&level| overlaps_level(f, level)) {
+                Some(level) => level.saturating_sub(1),
+                None => NUM_LEVELS - 1,
+            })
+            .collect();
+
+        let mut edit = VersionEdit::new(cf, cf_type);
+        let mut dirs = BTreeSet::new();
+        for (((f, (file_number, temp)), level), checksum) in files.iter().zip(installed).zip(&targets).zip(checksums) {
+            let dest = self.db_config.sst_path(*level, *file_number);
+            fs::rename(temp, &dest)?;
+            self.db_config.file_resolver().record(*file_number, dest.clone());
+            if let Some(dir) = dest.parent() {
+                dirs.insert(dir.to_path_buf());
+            }
+            edit.add_file(*level, *file_number, f.file_size, &f.smallest, &f.largest);
+            edit.set_file_checksum(*file_number, checksum);
+        }
+        for dir in &dirs {
+            sync_dir(dir)?;
+        }
+        let queue = vs.manifest_queue();
+        drop(vs);
+        queue.submit(&self.version_set, edit)
+    }
+
+    /// 文件里的 key 以 seq 0 装进去会被已有的数据盖住：和已有文件交叉、落在之前的
+    /// DeleteRange 里，或者有 snapshot 在（它不该看到这些文件）
+    fn needs_global_seqno(&self, version: &Version, files: &[ExternalFile]) -> Result<bool, DBError> {
+        if self.snapshots.count() > 0 {
+            return Ok(true);
+        }
+        let levels = version.levels();
+        for f in files {
+            if levels.iter().flatten().any(|m| f.overlaps(&m.smallest_key, &m.largest_key)) {
+                return Ok(true);
+            }
+            let (smallest, largest) = (f.smallest_user_key()?, f.largest_user_key()?);
+            if version.range_tombstones().iter().any(|t| t.begin <= largest && smallest < t.end) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Compaction debt summed over all column families.
+    pub fn total_compaction_debt(&self) -> u64 {
+        let cfs = self.version_set.lock().unwrap().column_families();
+        cfs.into_iter().map(|cf| self.compaction_debt(cf)).sum()
+    }
+
+    /// Take an advisory lease on `[begin, end)` of `cf` for `ttl`.
+    ///
+    /// The lease is stored in the system CF, so it survives a restart, and fails with
+    /// `Busy` while another unexpired lease overlaps the range. Leases only block
+    /// writes when `enforce_range_locks` is on; a writer that holds one passes its id
+    /// in `WriteOptions::range_lease`.
+    pub fn lock_range(&self, cf: ColumnFamilyId, begin: &[u8], end: &[u8], ttl: Duration) -> Result<RangeLease, DBError> {
+        if begin >= end {
+            return Err(DBError::InvalidArgument("lock_range: begin must be < end".into()));
+        }
+        let now = ttl::now_ms();
+        self.range_locks.with_table(|next_id, leases| {
+            if let Some(held) = leases.values().find(|l| !l.is_expired(now) && l.overlaps(cf, begin, end)) {
+                return Err(DBError::Busy(format!(
+                    "range [{:?}, {:?}) of cf {} overlaps lease {} on [{:?}, {:?})",
+                    String::from_utf8_lossy(begin),
+                    String::from_utf8_lossy(end),
+                    cf,
+                    held.id,
+                    String::from_utf8_lossy(&held.begin),
+                    String::from_utf8_lossy(&held.end),
+                )));
+            }
+            let lease = RangeLease {
+                id: *next_id,
+                cf,
+                begin: begin.to_vec(),
+                end: end.to_vec(),
+                expires_at_ms: now.saturating_add(ttl.as_millis() as u64),
+            };
+            // 顺手把过期的租约清掉
+            let expired: Vec<u64> = leases.values().filter(|l| l.is_expired(now)).map(|l| l.id).collect();
+            let mut batch = WriteBatch::new();
+            for id in &expired {
+                batch.delete(SYSTEM_COLUMN_FAMILY_ID, &leases[id].record_key());
+            }
+            batch.put(SYSTEM_COLUMN_FAMILY_ID, &lease.record_key(), &lease.encode_value());
+            self.write(&self.default_write_options(), batch)?;
+
+            for id in expired {
+                leases.remove(&id);
+            }
+            *next_id += 1;
+            leases.insert(lease.id, lease.clone());
+            Ok(lease)
+        })
+    }
+
+    /// Extend `lease` to expire `ttl` from now. Fails with `Expired` if it already
+    /// expired or was released; take a new lease then.
+    pub fn renew_range_lock(&self, lease: &RangeLease, ttl: Duration) -> Result<RangeLease, DBError> {
+        let now = ttl::now_ms();
+        self.range_locks.with_table(|_, leases| {
+            let mut renewed = match leases.get(&lease.id) {
+                Some(l) if !l.is_expired(now) => l.clone(),
+                _ => return Err(DBError::Expired(format!("range lease {} expired or was released", lease.id))),
+            };
+            renewed.expires_at_ms = now.saturating_add(ttl.as_millis() as u64);
+            self.put(&self.default_write_options(), SYSTEM_COLUMN_FAMILY_ID, &renewed.record_key(), &renewed.encode_value())?;
+            leases.insert(renewed.id, renewed.clone());
+            Ok(renewed)
+        })
+    }
+
+    /// Release `lease`. Releasing an expired or unknown lease is not an error.
+    pub fn unlock_range(&self, lease: &RangeLease) -> Result<(), DBError> {
+        self.range_locks.with_table(|_, leases| {
+            if leases.remove(&lease.id).is_some() {
+                self.delete(&self.default_write_options(), SYSTEM_COLUMN_FAMILY_ID, &lease.record_key())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Unexpired range leases of `cf` (of every CF with `None`), oldest first.
+    pub fn range_locks(&self, cf: Option<ColumnFamilyId>) -> Vec<RangeLease> {
+        self.range_locks.live(cf, ttl::now_ms())
+    }
+
+    fn load_range_locks(&self) -> Result<(), DBError> {
+        let mut leases = Vec::new();
+        for key in self.user_keys_with_prefix(SYSTEM_COLUMN_FAMILY_ID, range_lock::RANGE_LOCK_PREFIX) {
+            // memtable 扫描会带上已删除的记录
+            if let Some(value) = self.get_internal(SYSTEM_COLUMN_FAMILY_ID, &key)? {
+                leases.push(RangeLease::decode(&key, &value)?);
+            }
+        }
+        self.range_locks.load(leases);
+        Ok(())
+    }
+
+    /// `enforce_range_locks`：batch 里落进别人租约的写返回 Busy
+    fn check_range_locks(&self, opts: &WriteOptions, batch: &WriteBatch) -> Result<(), DBError> {
+        let now = ttl::now_ms();
+        for entry in batch {
+            // 租约记录本身写在 system CF 里
+            let cf = entry.cf();
+            if cf == SYSTEM_COLUMN_FAMILY_ID {
+                continue;
+            }
+            let conflict = match entry {
+                WriteBatchEntry::DeleteRange { begin, end, .. } => {
+                    self.range_locks.conflict(cf, begin, Some(end), opts.range_lease, now)
+                }
+                WriteBatchEntry::Put { key, .. }
+                | WriteBatchEntry::Delete { key, .. }
+                | WriteBatchEntry::Merge { key, .. } => self.range_locks.conflict(cf, key, None, opts.range_lease, now),
+            };
+            if let Some(lease) = conflict {
+                return Err(DBError::Busy(format!(
+                    "cf {} range [{:?}, {:?}) is leased (lease {})",
+                    cf,
+                    String::from_utf8_lossy(&lease.begin),
+                    String::from_utf8_lossy(&lease.end),
+                    lease.id,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write options from the DB config (`write_sync`, `enable_write_ahead_log`), used by
+    /// writes that take no `WriteOptions` of their own.
+    pub fn default_write_options(&self) -> WriteOptions {
+        WriteOptions {
+            sync: self.options.write_sync,
+            disable_wal: !self.options.enable_write_ahead_log,
+            range_lease: None,
+        }
+    }
+
+    /// Incrementally back up the flushed state of the DB to `target`.
+    ///
+    /// SSTs the target already holds are skipped; new ones are uploaded (resuming
+    /// a previously interrupted upload) and verified against the checksums in
+    /// the MANIFEST. Data still in memtables is not included, flush first.
+    pub fn backup_to(&self, target: &dyn BackupTarget) -> Result<BackupStats, DBError> {
+        // 传完之前这些 Version 的 SST 不能被 compaction 删掉
+        let (sequence, _versions, ssts, manifest) = {
+            let vs = self.version_set.lock().unwrap();
+            let versions: Vec<_> = vs.column_families()
+                .into_iter()
+                .map(|cf| self.version_pins.pin(vs.current_version(cf)))
+                .collect();
+            let ssts = versions.iter()
+                .flat_map(|v| v.levels().iter().flatten())
+                .map(|f| Ok(SstToBackup {
+                    path: self.db_config.locate_sst(f.file_number)?,
+                    file_number: f.file_number,
+                    size: f.file_size,
+                    crc32c: f.file_checksum,
+                }))
+                .collect::<Result<Vec<_>, DBError>>()?;
+            // MANIFEST 只追加，在锁里读到的就是和这些 Version 对应的那一份
+            let manifest = std::fs::read(vs.manifest_path())?;
+            (vs.current_sequence(), versions, ssts, manifest)
+        };
+        let wal_applied = self.version_set.lock().unwrap().wal_applied_sequence();
+        let stats = backup::run_backup(target, sequence, wal_applied, ssts, manifest)?;
+        log::info!(
+            "backup {} done: {} files uploaded ({} bytes), {} already in the target",
+            stats.backup_id, stats.files_uploaded, stats.bytes_uploaded, stats.files_skipped
+        );
+        Ok(stats)
+    }
+
+    /// The write batches from sequence `seq` on, read from the WAL (and the WAL
+    /// archive, when `wal_archive_dir` is set), for change data capture.
+    ///
+    /// Without an archive only batches since the last `close` are available;
+    /// writes made with `disable_wal` never show up.
+    pub fn get_updates_since(&self, seq: SequenceNumber) -> Result<TransactionLogIterator, DBError> {
+        TransactionLogIterator::new(seq, self.db_config.wal_archive_dir.as_deref(), self.wal_manager.path())
+    }
+
+    /// File descriptors held by the DB and by the whole process.
+    pub fn open_files(&self) -> OpenFiles {
+        OpenFiles {
+            table_readers: self.table_cache.open_readers(),
+            wal: self.wal_manager.open_files(),
+            manifest: 1,
+            process_open: process_open_fds(),
+            process_limit: process_fd_limit(),
+            readers_closed_for_limit: self.table_cache.fd_evictions(),
+        }
+    }
+
+    /// Reports evictions from the data block cache, and pinned blocks that
+    /// eviction had to skip, to `listener`; `None` stops reporting.
+    pub fn set_block_cache_listener(&self, listener: Option<Arc<dyn BlockCacheListener>>) {
+        self.table_cache.block_cache().set_listener(listener);
+    }
+
+    /// Data blocks held by readers and iterators right now, which the block
+    /// cache can't evict; for finding what keeps it over capacity.
+    pub fn pinned_blocks(&self) -> Vec<PinnedBlock> {
+        self.table_cache.block_cache().pinned_blocks()
+    }
+
+    /// Resizes the block cache without a restart; with `shards`, also changes
+    /// its shard count (rounded up to a power of two). Shrinking evicts at
+    /// once; blocks in the old shards move over as they are read.
+    pub fn resize_block_cache(&self, capacity_bytes: usize, shards: Option<usize>) {
+        let cache = self.table_cache.block_cache();
+        if let Some(n) = shards {
+            cache.set_num_shards(n);
+        }
+        cache.set_capacity(capacity_bytes);
+    }
+
+    /// Sequence number of the last write.
+    pub fn latest_sequence_number(&self) -> SequenceNumber {
+        self.version_set.lock().unwrap().current_sequence()
+    }
+
+    /// Last sequence applied from a replication leader with `apply_replicated`.
+    ///
+    /// Unlike `latest_sequence_number` this doesn't move on flushes, so it is
+    /// what a follower resumes from.
+    pub fn replicated_sequence(&self) -> SequenceNumber {
+        self.replicated_sequence.load(Ordering::Acquire)
+    }
+
+    /// 装好 checkpoint 之后，从 checkpoint 的 seq 接着复制
+    pub(crate) fn set_replicated_sequence(&self, seq: SequenceNumber) -> Result<(), DBError> {
+        self.db_config.write_replicated_sequence(seq)?;
+        self.replicated_sequence.store(seq, Ordering::Release);
+        self.is_replica.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// 这之前的 batch 已经 flush 过，WAL 里不一定还有
+    pub(crate) fn wal_applied_sequence(&self) -> SequenceNumber {
+        self.version_set.lock().unwrap().wal_applied_sequence()
+    }
+
+    /// Applies a batch read from another DB's WAL (see `get_updates_since`)
+    /// under the sequence numbers it was written with, for replication.
+    ///
+    /// The batch goes into this DB's WAL and memtables and the sequence counter
+    /// moves to its last sequence. Progress is tracked in `replicated_sequence`,
+    /// persisted across restarts, not in the sequence counter which local
+    /// flushes also advance. A batch that is already applied is skipped; one
+    /// that starts inside the applied range fails with `InvalidArgument`.
+    /// Don't mix with local writes, they would take the same sequence numbers.
+    pub fn apply_replicated(&self, batch: WriteBatch) -> Result<(), DBError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let base_seq = batch.sequence();
+        let last_seq = base_seq + batch.len() as u64 - 1;
+        let current = self.replicated_sequence();
+        if last_seq <= current {
+            return Ok(());
+        }
+        if base_seq <= current {
+            return Err(DBError::InvalidArgument(format!(
+                "replicated batch {}..={} overlaps applied sequence {}", base_seq, last_seq, current
+            )));
+        }
+
+        self.make_room_for_write(&batch)?;
+        let vector_updates = self.vector_index_updates(&batch);
+        if self.options.enable_write_ahead_log {
+            self.wal_manager.append_no_sync(&batch)?;
+        }
+        // 先进 memtable 再推 seq，读的人看到新 seq 时数据已经在了
+        self.memtables.lock().unwrap().apply(base_seq, &batch)?;
+        self.version_set.lock().unwrap().advance_sequence(last_seq)?;
+        self.last_wal_sequence.fetch_max(last_seq, Ordering::AcqRel);
+        self.replicated_sequence.fetch_max(last_seq, Ordering::AcqRel);
+        if !self.is_replica.swap(true, Ordering::AcqRel) {
+            // 第一次：记下已经确定落盘的进度，之后 close 时更新；这个 batch 自己还在没 sync 的 WAL 里
+            self.db_config.write_replicated_sequence(current)?;
+        }
+        self.apply_vector_index_updates(vector_updates);
+        if batch.iter().any(|e| matches!(e, WriteBatchEntry::DeleteRange { .. } | WriteBatchEntry::Merge { .. })) {
+            self.vector_indexes.write().unwrap().clear();
+        }
+        Ok(())
+    }
+
+    /// Writes a consistent copy of the DB into `dir`, which can be opened as a
+    /// standalone DB.
+    ///
+    /// Live SSTs are hard-linked (copied when `dir` is on another filesystem),
+    /// the MANIFEST is copied as of the moment the SST list is taken, and the
+    /// WAL is flushed and copied so unflushed writes come along. Writes are not
+    /// stopped; the checkpoint holds at least everything acknowledged before the
+    /// call. `dir` must not exist. The config file is not copied, the copy opens
+    /// with the default layout.
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<(), DBError> {
+        let dir = dir.as_ref();
+        if dir.exists() {
+            return Err(DBError::InvalidArgument(format!("checkpoint dir {:?} already exists", dir)));
+        }
+        // 先写到旁边的临时目录，写完再 rename，中途失败不会留下半个 DB
+        let mut staging = dir.as_os_str().to_owned();
+        staging.push(".tmp");
+        let staging = PathBuf::from(staging);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let result = self.write_checkpoint(&staging);
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+        fs::rename(&staging, dir)?;
+        if let Some(parent) = dir.parent().filter(|p| !p.as_os_str().is_empty()) {
+            sync_dir(parent)?;
+        }
+        log::info!("checkpoint written to {:?}", dir);
+        Ok(())
+    }
+
+    fn write_checkpoint(&self, staging: &Path) -> Result<(), DBError> {
+        let cp = DbConfig::from_open_options(staging.to_path_buf(), &OpenOptions::default());
+        fs::create_dir_all(&cp.sst_dir)?;
+        fs::create_dir_all(&cp.manifest_dir)?;
+
+        // 链接完之前这些 Version 的 SST 不能被 compaction 删掉
+        let (_versions, ssts, manifest) = {
+            let vs = self.version_set.lock().unwrap();
+            let versions: Vec<_> = vs.column_families()
+                .into_iter()
+                .map(|cf| self.version_pins.pin(vs.current_version(cf)))
+                .collect();
+            let ssts = versions.iter()
+                .flat_map(|v| v.levels().iter().enumerate()
+                    .flat_map(|(level, files)| files.iter().map(move |f| (f.file_number, level))))
+                .collect::<BTreeMap<_, _>>();
+            let manifest = fs::read(vs.manifest_path())?;
+            (versions, ssts, manifest)
+        };
+
+        for (n, level) in ssts {
+            let src = self.db_config.locate_sst(n)?;
+            let dst = cp.sst_path(level, n);
+            // SST 写完就不再改，硬链接和拷贝一样；跨文件系统链不了才拷
+            if fs::hard_link(&src, &dst).is_err() {
+                fs::copy(&src, &dst)?;
+                sync_file(&dst, false)?;
+            }
+        }
+        sync_dir(&cp.sst_dir)?;
+
+        let manifest_path = cp.manifest_dir.join(FIRST_MANIFEST);
+        fs::write(&manifest_path, &manifest)?;
+        sync_file(&manifest_path, false)?;
+        sync_dir(&cp.manifest_dir)?;
+
+        // WAL 只在 close 时清空，拷到的是 MANIFEST 之后的全部写；和 SST 重叠的部分重放时覆盖同样的值
+        if let Some(parent) = cp.wal_dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.wal_manager.copy_to(&cp.wal_dir)?;
+
+        write_current(&cp.db_path, FIRST_MANIFEST)?;
+        sync_dir(&cp.db_path)?;
+        Ok(())
+    }
+
+    /// Restores the DB at `path` to how it was at `ts`.
+    ///
+    /// Downloads backup `backup_id` from `target` (or, with `None`, the latest one
+    /// taken at or before `ts`), then writes the archived WAL batches up to the
+    /// sequence reached at `ts` into the WAL, so the next `open` replays them. The
+    /// DB's config must set `wal_archive_dir`, and `path` must not hold a DB yet.
+    /// Batches still in the live WAL of the source DB reach the archive when it
+    /// is closed.
+    pub fn restore_to_timestamp(
+        path: &str,
+        target: &dyn BackupTarget,
+        backup_id: Option<u64>,
+        ts: SystemTime,
+    ) -> Result<RestoreStats, DBError> {
+        let db_path = PathBuf::from(path);
+        let open_opts = match load_db_config(&db_path) {
+            Ok(file_cfg) => file_cfg.to_open_options(),
+            Err(_) => OpenOptions::default(),
+        };
+        let db_config = DbConfig::from_open_options(db_path, &open_opts);
+        let archive_dir = db_config.wal_archive_dir.clone().ok_or_else(|| {
+            DBError::InvalidArgument("point-in-time restore needs wal_archive_dir".into())
+        })?;
+        if db_config.looks_like_existing_db() {
+            return Err(DBError::InvalidArgument(format!("{} already holds a DB", path)));
+        }
+
+        let ts_ms = ts.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let backups = backup::list_backups(target)?;
+        let meta = match backup_id {
+            Some(id) => backups.into_iter()
+                .find(|m| m.id == id)
+                .ok_or_else(|| DBError::NotFound(format!("backup {} not found", id)))?,
+            None => backups.into_iter()
+                .filter(|m| m.created_at_ms <= ts_ms)
+                .last()
+                .ok_or_else(|| DBError::NotFound(format!("no backup taken before {:?}", ts)))?,
+        };
+        if meta.created_at_ms > ts_ms {
+            return Err(DBError::InvalidArgument(format!(
+                "backup {} was taken after the restore point {:?}", meta.id, ts
+            )));
+        }
+
+        // 1. 备份里的 SST 和 MANIFEST；CURRENT 最后写，中途失败的目录不会被当成 DB 打开
+        let mut stats = Self::restore_backup_files(&db_config, target, &meta)?;
+
+        // 2. 备份时还只在 WAL 里的写和之后的写，到 ts 为止，从归档里抄进 WAL
+        let until = wal_archive::sequence_at(&archive_dir, ts)?;
+        // 和 WalManager::open 打开的是同一个路径
+        let mut wal = WalWriter::new(BufWriter::new(File::create(&db_config.wal_dir)?));
+        let mut first = None;
+        let mut last = meta.wal_applied_sequence;
+        for segment in wal_archive::archived_segments(&archive_dir)? {
+            wal_archive::read_wal_file(&segment, |base_seq, mut batch| {
+                if batch.is_empty() {
+                    return Ok(());
+                }
+                first.get_or_insert(base_seq);
+                let end = base_seq + batch.len() as u64 - 1;
+                if end <= meta.wal_applied_sequence || until.is_some_and(|u| base_seq > u) {
+                    return Ok(());
+                }
+                if let Some(u) = until.filter(|&u| end > u) {
+                    batch.truncate((u - base_seq + 1) as usize);
+                }
+                wal.append(batch.data())?;
+                last = last.max(base_seq + batch.len() as u64 - 1);
+                stats.wal_batches += 1;
+                Ok(())
+            })?;
+        }
+        wal.flush()?;
+        wal.into_inner().into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        // seq 可能因为 flush / 不写 WAL 的写入而跳号，对不上不一定丢了数据，只提醒
+        if first.map_or(true, |s| s > meta.wal_applied_sequence + 1) {
+            log::warn!(
+                "archived WAL in {:?} starts at {:?}, backup {} needs it from sequence {}; writes in between may be missing",
+                archive_dir, first, meta.id, meta.wal_applied_sequence + 1
+            );
+        }
+        stats.sequence = last;
+
+        write_current(&db_config.db_path, FIRST_MANIFEST)?;
+        sync_dir(&db_config.db_path)?;
+        log::info!(
+            "restored backup {} to {} up to sequence {} ({} files, {} WAL batches)",
+            meta.id, path, stats.sequence, stats.files_restored, stats.wal_batches
+        );
+        Ok(stats)
+    }
+
+    /// Restores backup `backup_id` from `target` (or, with `None`, the latest
+    /// one) into an empty DB at `path`. Writes that were only in the WAL when
+    /// the backup was taken are not included.
+    pub fn restore_from_backup(
+        path: &str,
+        target: &dyn BackupTarget,
+        backup_id: Option<u64>,
+    ) -> Result<RestoreStats, DBError> {
+        let db_path = PathBuf::from(path);
+        let open_opts = match load_db_config(&db_path) {
+            Ok(file_cfg) => file_cfg.to_open_options(),
+            Err(_) => OpenOptions::default(),
+        };
+        let db_config = DbConfig::from_open_options(db_path, &open_opts);
+        if db_config.looks_like_existing_db() {
+            return Err(DBError::InvalidArgument(format!("{} already holds a DB", path)));
+        }
+
+        let backups = backup::list_backups(target)?;
+        let meta = match backup_id {
+            Some(id) => backups.into_iter().find(|m| m.id == id),
+            None => backups.into_iter().last(),
+        }
+        .ok_or_else(|| DBError::NotFound(format!("backup {:?} not found", backup_id)))?;
+
+        let mut stats = Self::restore_backup_files(&db_config, target, &meta)?;
+        stats.sequence = meta.id;
+        write_current(&db_config.db_path, FIRST_MANIFEST)?;
+        sync_dir(&db_config.db_path)?;
+        log::info!(
+            "restored backup {} to {} ({} files, {} bytes)",
+            meta.id, path, stats.files_restored, stats.bytes_restored
+        );
+        Ok(stats)
+    }
+
+    /// 下载备份里的 SST 和 MANIFEST；CURRENT 由调用方最后写
+    fn restore_backup_files(
+        db_config: &DbConfig,
+        target: &dyn BackupTarget,
+        meta: &backup::BackupMeta,
+    ) -> Result<RestoreStats, DBError> {
+        fs::create_dir_all(&db_config.sst_dir)?;
+        fs::create_dir_all(&db_config.manifest_dir)?;
+        backup::restore_files(
+            target,
+            meta,
+            &db_config.sst_dir,
+            &db_config.manifest_dir.join(FIRST_MANIFEST),
+        )
+    }
+
+    /// Prefix length, bloom bits per key and block size suggested for `cf` from
+    /// the gets and seeks sampled so far (see `read_sample_rate`).
+    pub fn read_tuning_report(&self, cf: ColumnFamilyId) -> Result<ReadTuningReport, DBError> {
+        let vs = self.version_set.lock().unwrap();
+        let options = vs.column_family_by_id(cf)?.options(&self.options);
+        Ok(self.read_sampler.report(cf, options))
+    }
+
+    /// 没开采样时 iterator 不用带着它
+    fn read_sampler(&self) -> Option<Arc<ReadSampler>> {
+        (self.options.read_sample_rate > 0).then(|| Arc::clone(&self.read_sampler))
+    }
+
+    /// What the last `open` found while replaying the WAL, including sequence
+    /// number ranges that were out of order.
+    pub fn recovery_info(&self) -> RecoveryInfo {
+        self.recovery_info.lock().unwrap().clone()
+    }
+
+    /// Starts a transaction; its writes use `opts` on commit.
+    pub fn begin_transaction(&self, opts: WriteOptions, txn_options: TransactionOptions) -> Transaction<'_> {
+        Transaction::new(self, opts, txn_options)
+    }
+
+    pub(crate) fn txn_commit_lock(&self) -> &Mutex<()> {
+        &self.txn_commit_lock
+    }
+
+    pub(crate) fn lock_manager(&self) -> &LockManager {
+        &self.lock_manager
+    }
+
+    pub(crate) fn db_config(&self) -> &DbConfig {
+        &self.db_config
+    }
+
+    /// Write buffer size and L0 triggers currently used by the write path.
+    pub fn tuned_options(&self) -> TunedOptions {
+        self.auto_tuner.current()
+    }
+
+    /// Whether sync writes are still held back by the warmup after open.
+    pub fn is_warming_up(&self) -> bool {
+        self.warming_up.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the compaction debt is paid off (which also ends the
+    /// warmup), or fails with `Busy` after `timeout`.
+    pub fn wait_until_caught_up(&self, timeout: Duration) -> Result<(), DBError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(100);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let debt = self.total_compaction_debt();
+            if debt == 0 {
+                self.warming_up.store(false, Ordering::Release);
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(DBError::Busy(format!("{} bytes of compaction debt left after {:?}", debt, timeout)));
+            }
+            std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// open 时估算欠账；开了 warmup_on_open 就进入 warmup 并把欠账的 CF 排上 compaction
+    fn start_warmup(&self) {
+        let cfs = self.version_set.lock().unwrap().column_families();
+        let debts: Vec<(ColumnFamilyId, u64)> = cfs
+            .into_iter()
+            .map(|cf| (cf, self.compaction_debt(cf)))
+            .filter(|(_, debt)| *debt > 0)
+            .collect();
+        let total: u64 = debts.iter().map(|(_, debt)| debt).sum();
+        if total == 0 {
+            return;
+        }
+        log::info!("compaction debt on open: {} bytes in {} column families", total, debts.len());
+        if !self.options.warmup_on_open {
+            return;
+        }
+
+        self.warming_up.store(true, Ordering::Release);
+        for (cf, _) in debts {
+            if let Err(e) = self.compact_range(cf, None, None) {
+                log::warn!("failed to schedule warmup compaction of cf {}: {:?}", cf, e);
+            }
+        }
+    }
+
+    /// warmup 期间拒绝 sync 写；欠账还清了就结束 warmup
+    fn check_warmup(&self, opts: &WriteOptions) -> Result<(), DBError> {
+        if !self.is_warming_up() || !opts.sync {
+            return Ok(());
+        }
+        let debt = self.total_compaction_debt();
+        if debt == 0 {
+            self.warming_up.store(false, Ordering::Release);
+            return Ok(());
+        }
+        Err(DBError::WriteStall {
+            retry_after_ms: self.options.write_stall_retry_after_ms,
+            reason: format!("warming up, {} bytes of compaction debt", debt),
+        })
+    }
+
+    /// Registers a listener for DB events, next to those in `Options::listeners`.
+    pub fn add_listener(&self, listener: Arc<dyn EventListener>) {
+        self.db_config.listeners().add(listener);
+    }
+
+    /// 后台 flush / compaction 失败：记日志，告诉 listener
+    pub(crate) fn report_background_error(&self, reason: BackgroundErrorReason, error: &DBError) {
+        log::error!("background {:?} failed: {:?}", reason, error);
+        self.db_config.listeners().notify(|l| l.on_background_error(reason, error));
+    }
+
+    /// The worst write stall condition across column families, as of the last write.
+    pub fn write_stall_condition(&self) -> WriteStallCondition {
+        self.write_stall.worst()
+    }
+
+    /// How long clients should wait before the next write; None when writes
+    /// are not stalled.
+    pub fn retry_after_hint(&self) -> Option<Duration> {
+        write_stall::retry_after(self.write_stall.worst(), self.options.write_stall_retry_after_ms)
+    }
+}
+
+/// 第一个比所有以 `prefix` 开头的 key 都大的 key；prefix 全是 0xff（或为空）时没有上界
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// 从 table cache 里摘掉并删除一个 SST；文件已经不在了不算错
+fn delete_sst(table_cache: &TableCache, db_config: &DbConfig, file_number: u64) {
+    table_cache.evict(file_number);
+    let resolver = db_config.file_resolver();
+    let Ok(path) = resolver.resolve(file_number) else { return };
+    resolver.forget(file_number);
+    match std::fs::remove_file(&path) {
+        Ok(()) => {
+            let info = TableFileDeletionInfo { file_number, file_path: path.clone() };
+            db_config.listeners().notify(|l| l.on_table_file_deleted(&info));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("failed to delete {:?}: {}", path, e),
+    }
+}
+
+/// 配额按 CF 记的 (条数, 字节数)
+/// system CF 里是 DB 自己的记录（TTL 索引、租约、幂等窗口），不算进配额
+fn add_write_usage(usage: &mut HashMap<ColumnFamilyId, (u64, u64)>, batch: &WriteBatch) {
+    for entry in batch.iter().filter(|e| e.cf() != SYSTEM_COLUMN_FAMILY_ID) {
+        let u = usage.entry(entry.cf()).or_default();
+        u.0 += 1;
+        u.1 += entry.data_size() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::event_listener::CompactionJobInfo;
+    use crate::db::sst_file_writer::SstFileWriter;
+    use crate::engine::sst::block::{BloomFilterPolicy, FilterPolicy};
+    use crate::engine::sst::sst_reader::SstReader;
+    use crate::util::CompressionType;
+    use crate::util::test_util::{open_db, put_flushed, test_dir};
+
+    /// 每个测试一个干净的目录
+    #[test]
+    fn reopen_after_close_keeps_data() {
+        let dir = test_dir("reopen");
+        let path = dir.to_str().unwrap();
+
+        let db = DBImpl::open(path).unwrap();
+        db.put(&WriteOptions::default(), USER_COLUMN_FAMILY_ID, b"k", b"v").unwrap();
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert_eq!(db.get(&ReadOptions::default(), USER_COLUMN_FAMILY_ID, b"k").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn crash_before_current_is_written_counts_as_missing() {
+        let dir = test_dir("bare-manifest");
+        let db_config = DbConfig::from_open_options(dir.clone(), &OpenOptions::default());
+        db_config.create_dirs().unwrap();
+
+        // 建库写完 MANIFEST、还没写 CURRENT 时的样子
+        let mut manifest = ManifestWriter::create_new(&db_config.manifest_dir.join(FIRST_MANIFEST)).unwrap();
+        let mut edit = VersionEdit::new(USER_COLUMN_FAMILY_ID, CfType::User);
+        edit.is_cf_add = true;
+        manifest.add_record(&edit).unwrap();
+        drop(manifest);
+        assert_eq!(db_config.db_state(), DbState::Missing);
+
+        // 记过文件的 MANIFEST 不能当成没有
+        let mut manifest = ManifestWriter::open_existing(db_config.manifest_dir.join(FIRST_MANIFEST).to_str().unwrap()).unwrap();
+        let mut files = VersionEdit::new(USER_COLUMN_FAMILY_ID, CfType::User);
+        files.add_file(0, 7, 4096, b"a", b"z");
+        manifest.add_record(&files).unwrap();
+        drop(manifest);
+        assert!(matches!(db_config.db_state(), DbState::Partial(_)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tombstones_stop_lookups_in_memtables_and_sst() {
+        let (db, dir) = open_db("tombstone-lookup");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"k", b"old").unwrap();
+        put_flushed(&db, cf, b"other", b"v");
+
+        // 删除还在 memtable 里，旧值在 L0
+        db.delete(&w, cf, b"k").unwrap();
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), None);
+        assert_eq!(db.multi_get(&ReadOptions::default(), cf, &[b"k", b"other"]).unwrap(), vec![None, Some(b"v".to_vec())]);
+
+        // 删除在更新的 L0 文件里
+        db.flush_memtables_of(&[cf]).unwrap();
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), None);
+        assert_eq!(db.multi_get(&ReadOptions::default(), cf, &[b"k", b"other"]).unwrap(), vec![None, Some(b"v".to_vec())]);
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn update_iterator_tails_the_wal() {
+        let (db, dir) = open_db("tail-wal");
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+
+        db.put(&w, cf, b"a", b"1").unwrap();
+        let first = db.latest_sequence_number();
+        let mut log = db.get_updates_since(first).unwrap();
+        assert_eq!(log.next().unwrap().unwrap().0, first);
+        assert!(log.next().is_none());
+
+        // 跨 block 的 record 也要从停下的位置接着读
+        let big = vec![7u8; 40 * 1024];
+        db.put(&w, cf, b"b", &big).unwrap();
+        db.put(&w, cf, b"c", b"3").unwrap();
+        let seqs: Vec<SequenceNumber> = log.by_ref().map(|r| r.unwrap().0).collect();
+        assert_eq!(seqs, vec![first + 1, first + 2]);
+        assert!(log.next().is_none());
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replicated_sequence_is_not_moved_by_flushes() {
+        let (leader_dir, follower_dir) = (test_dir("repl-leader"), test_dir("repl-follower"));
+        let leader = DBImpl::open(leader_dir.to_str().unwrap()).unwrap();
+        let follower = DBImpl::open(follower_dir.to_str().unwrap()).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+        let mut log = leader.get_updates_since(leader.latest_sequence_number() + 1).unwrap();
+
+        leader.put(&w, cf, b"a", b"1").unwrap();
+        follower.apply_replicated(log.next().unwrap().unwrap().1).unwrap();
+        // follower 自己 flush 会推高本地 seq，但不能让 leader 后面的 batch 被当成已经应用过
+        follower.flush_memtables_of(&[cf]).unwrap();
+        assert!(follower.latest_sequence_number() > leader.latest_sequence_number());
+
+        leader.put(&w, cf, b"b", b"2").unwrap();
+        let (_, second) = log.next().unwrap().unwrap();
+        follower.apply_replicated(second.clone()).unwrap();
+        assert_eq!(follower.get(&r, cf, b"b").unwrap(), Some(b"2".to_vec()));
+        let applied = leader.latest_sequence_number();
+        assert_eq!(follower.replicated_sequence(), applied);
+
+        // 重启之后 WAL 空了，进度从 REPLICATED 读回来
+        follower.close().unwrap();
+        drop(follower);
+        let follower = DBImpl::open(follower_dir.to_str().unwrap()).unwrap();
+        assert_eq!(follower.replicated_sequence(), applied);
+        follower.apply_replicated(second).unwrap();
+        leader.put(&w, cf, b"c", b"3").unwrap();
+        follower.apply_replicated(log.next().unwrap().unwrap().1).unwrap();
+        assert_eq!(follower.get(&r, cf, b"c").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(follower.replicated_sequence(), leader.latest_sequence_number());
+
+        follower.close().unwrap();
+        leader.close().unwrap();
+        let _ = fs::remove_dir_all(&leader_dir);
+        let _ = fs::remove_dir_all(&follower_dir);
+    }
+
+    #[test]
+    fn flushes_finishing_out_of_order_keep_l0_order() {
+        let (db, dir) = open_db("flush-order");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        let mut frozen = Vec::new();
+        for value in [b"old", b"new"] {
+            db.put(&w, cf, b"k", value).unwrap();
+            let mut mem = db.memtables.lock().unwrap();
+            let vs = db.version_set.lock().unwrap();
+            mem.freeze_active(cf, vs.next_sequence().unwrap(), vs.new_file_number().unwrap()).unwrap();
+            frozen.push(mem.pick_flush_candidate(cf).unwrap());
+        }
+        // 新的 memtable 先 flush 完，老的后装进去也不能盖住它
+        for t in frozen.iter().rev() {
+            db.flush_memtable(Arc::clone(t)).unwrap();
+            db.memtables.lock().unwrap().finish_flush(cf, t);
+        }
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), Some(b"new".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fork_waits_for_flushes_in_progress() {
+        let (dir, fork_dir) = (test_dir("fork-src"), test_dir("fork-dst"));
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"k", b"v").unwrap();
+        // 后台 flush 已经把 memtable 拿进 flushing、还没写完时 fork
+        let table = {
+            let mut mem = db.memtables.lock().unwrap();
+            let vs = db.version_set.lock().unwrap();
+            mem.freeze_active(cf, vs.next_sequence().unwrap(), vs.new_file_number().unwrap()).unwrap();
+            mem.pick_flush_candidate(cf).unwrap()
+        };
+        let flusher = {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                db.flush_memtable(table).unwrap();
+            })
+        };
+        db.fork(fork_dir.to_str().unwrap()).unwrap();
+        flusher.join().unwrap();
+
+        let forked = DBImpl::open(fork_dir.to_str().unwrap()).unwrap();
+        assert_eq!(forked.get(&r, cf, b"k").unwrap(), Some(b"v".to_vec()));
+        forked.close().unwrap();
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&fork_dir);
+    }
+
+    #[test]
+    fn export_and_import_keep_range_tombstones() {
+        let (dir, export_dir) = (test_dir("export-src"), test_dir("export-dir"));
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"a", b"1").unwrap();
+        put_flushed(&db, cf, b"b", b"2");
+        // 墓碑只在 MANIFEST 里，SST 里 b 还在
+        db.delete_range(&WriteOptions::default(), cf, b"b", b"c").unwrap();
+        db.flush_memtables_of(&[cf]).unwrap();
+
+        let meta = db.export_column_family(cf, &export_dir).unwrap();
+        assert_eq!(meta.range_tombstones.len(), 1);
+        let imported = db.create_column_family_with_import("imported", ColumnFamilyOptions::default(), &export_dir).unwrap();
+        assert_eq!(db.get(&r, imported, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, imported, b"b").unwrap(), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&export_dir);
+    }
+
+    #[test]
+    fn ingest_under_flushed_range_tombstone_gets_a_global_seqno() {
+        let (db, dir) = open_db("ingest-tombstone");
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+
+        // 只有墓碑的 memtable flush 后墓碑只在 Version 里，没有和文件交叉的 SST
+        db.delete_range(&WriteOptions::default(), cf, b"a", b"z").unwrap();
+        db.flush_memtables_of(&[cf]).unwrap();
+
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("external.sst");
+        let mut writer = SstFileWriter::create(&path, &ColumnFamilyOptions::default()).unwrap();
+        writer.put(b"k", b"v").unwrap();
+        writer.finish().unwrap();
+        db.ingest_external_file(cf, &[&path], &IngestOptions::default()).unwrap();
+
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn spilled_transaction_is_written_as_one_wal_record() {
+        let (db, dir) = open_db("txn-spill");
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+        let since = db.latest_sequence_number() + 1;
+
+        let txn_options = TransactionOptions { spill_threshold_bytes: 4096, ..Default::default() };
+        let mut txn = db.begin_transaction(WriteOptions::default(), txn_options);
+        let value = vec![b'x'; 1000];
+        for i in 0..200u32 {
+            txn.put(cf, format!("k{:04}", i).as_bytes(), &value).unwrap();
+        }
+        assert!(txn.is_spilled());
+        txn.commit().unwrap();
+        assert_eq!(db.get(&r, cf, b"k0000").unwrap(), Some(value.clone()));
+        assert_eq!(db.get(&r, cf, b"k0199").unwrap(), Some(value.clone()));
+
+        // 跨好几个 block 的一条 record，重放时整个事务一起回来
+        let batches: Vec<WriteBatch> = db.get_updates_since(since).unwrap().map(|b| b.unwrap().1).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 200);
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(db.get(&r, cf, b"k0100").unwrap(), Some(value));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn write_batches_start_at_the_first_allocated_sequence() {
+        let (db, dir) = open_db("seq-range");
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+        let since = db.latest_sequence_number() + 1;
+
+        let mut batch = WriteBatch::new();
+        for k in [b"a", b"b", b"c"] {
+            batch.put(cf, k, b"1");
+        }
+        db.write(&w, batch).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(cf, b"d", b"2");
+        batch.put(cf, b"e", b"2");
+        db.write(&w, batch).unwrap();
+
+        // 两个 batch 的 seq 段首尾相接，不重叠
+        let seqs: Vec<u64> = db.get_updates_since(since).unwrap().map(|b| b.unwrap().0).collect();
+        assert_eq!(seqs, vec![since, since + 3]);
+        assert_eq!(db.latest_sequence_number(), since + 4);
+        drop(db);
+
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        assert!(db.recovery_info().sequence_issues.is_empty());
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn idempotent_writes_dedup_out_of_order_request_ids() {
+        use crate::engine::wal::write_batch::IDEMPOTENCY_WINDOW;
+        let (db, dir) = open_db("idempotency");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+        let write = |request_id: u64, key: &[u8], value: &[u8]| {
+            let mut batch = WriteBatch::new();
+            batch.put(cf, key, value);
+            batch.set_idempotency_token(7, request_id);
+            db.write(&w, batch)
+        };
+
+        write(5, b"a", b"5").unwrap();
+        // 比 5 小但没应用过的请求后到，照样要写进去
+        write(3, b"b", b"3").unwrap();
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), Some(b"3".to_vec()));
+
+        // 重试的 3 和 5 都不再应用
+        write(3, b"b", b"retry").unwrap();
+        write(5, b"a", b"retry").unwrap();
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), Some(b"5".to_vec()));
+
+        // 窗口往前滑，3 仍然记得；落出窗口的 id 判断不了，拒绝而不是猜
+        write(5 + IDEMPOTENCY_WINDOW - 2, b"c", b"1").unwrap();
+        write(3, b"b", b"retry").unwrap();
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), Some(b"3".to_vec()));
+        write(5 + IDEMPOTENCY_WINDOW, b"c", b"2").unwrap();
+        assert!(matches!(write(3, b"b", b"retry"), Err(DBError::InvalidArgument(_))));
+        write(4, b"d", b"4").unwrap_err();
+        assert_eq!(db.get(&r, cf, b"d").unwrap(), None);
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn column_family_options_survive_a_reopen() {
+        use crate::util::{CompactionStyle, FixedPrefixTransform};
+
+        let dir = test_dir("cf-options-reopen");
+        let path = dir.to_str().unwrap();
+
+        let db = DBImpl::open(path).unwrap();
+        let mut opts = ColumnFamilyOptions::default();
+        opts.target_file_size = 12345;
+        opts.compaction_style = CompactionStyle::Universal;
+        opts.table_options.filter_policy = Some(Arc::new(BloomFilterPolicy::new(7)));
+        opts.prefix_extractor = Some(Arc::new(FixedPrefixTransform::new(3)));
+        opts.vector.default.dimension = 8;
+        let cf = db.create_column_family("tuned", opts).unwrap();
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        {
+            let vs = db.version_set.lock().unwrap();
+            let reopened = vs.column_family_by_id(cf).unwrap().options(&db.options);
+            assert_eq!(reopened.target_file_size, 12345);
+            assert_eq!(reopened.compaction_style, CompactionStyle::Universal);
+            assert_eq!(reopened.vector.default.dimension, 8);
+            let filter = reopened.table_options.filter_policy.as_ref().unwrap();
+            assert_eq!((filter.name(), filter.bits_per_key()), ("custom.BloomFilter", Some(7)));
+            assert_eq!(reopened.prefix_extractor.as_ref().unwrap().name(), "vectorkv.FixedPrefix.3");
+        }
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn marking_files_queues_a_compaction() {
+        let dir = test_dir("mark-queues-compaction");
+        let path = dir.to_str().unwrap();
+
+        let db = DBImpl::open(path).unwrap();
+        let cf = USER_COLUMN_FAMILY_ID;
+        db.put(&WriteOptions::default(), cf, b"a", b"1").unwrap();
+        put_flushed(&db, cf, b"m", b"2");
+
+        // 停掉后台线程，排进去的任务留在队列里看得见
+        db.bg_worker.shutdown();
+        assert_eq!(db.bg_worker.pending_compactions(cf), (0, 0));
+
+        // 范围里没有文件：什么都不标记，也不排
+        db.suggest_compact_range(cf, Some(b"x"), Some(b"z")).unwrap();
+        assert_eq!(db.bg_worker.pending_compactions(cf), (0, 0));
+
+        db.suggest_compact_range(cf, Some(b"a"), Some(b"c")).unwrap();
+        assert!(!db.version_set.lock().unwrap().files_marked_for_compaction(cf).is_empty());
+        assert_eq!(db.bg_worker.pending_compactions(cf), (1, 0));
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// 把文件里 `needle` 的第一个字节改掉，模拟落盘后损坏
+    fn corrupt_bytes(path: &Path, needle: &[u8]) {
+        let mut data = fs::read(path).unwrap();
+        let at = data.windows(needle.len()).position(|w| w == needle).expect("needle not in file");
+        data[at] ^= 0x01;
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn multi_get_verifies_block_checksums() {
+        let (db, dir) = open_db("multi-get-checksum");
+        let cf = USER_COLUMN_FAMILY_ID;
+        put_flushed(&db, cf, b"k", b"q7Zx-unique-value-3Fm9");
+
+        let file_number = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        corrupt_bytes(&db.db_config.locate_sst(file_number).unwrap(), b"unique-value");
+
+        let checked = ReadOptions::default().with_fill_cache(false);
+        assert!(matches!(db.multi_get_with_options(cf, &[b"k"], &checked), Err(DBError::Corruption(_))));
+        let unchecked = checked.with_verify_checksums(false);
+        assert!(db.multi_get_with_options(cf, &[b"k"], &unchecked).is_ok());
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn multi_get_reads_as_of_the_snapshot() {
+        let (db, dir) = open_db("multi-get-snapshot");
+        let cf = USER_COLUMN_FAMILY_ID;
+        let w = WriteOptions::default();
+        db.put(&w, cf, b"sst", b"old").unwrap();
+        put_flushed(&db, cf, b"gone", b"old");
+        db.put(&w, cf, b"mem", b"old").unwrap();
+        let snapshot = db.get_snapshot();
+
+        // snapshot 之后的写：memtable 里和 SST 里的都不能被看到
+        db.put(&w, cf, b"sst", b"new").unwrap();
+        db.put(&w, cf, b"mem", b"new").unwrap();
+        db.delete(&w, cf, b"gone").unwrap();
+        db.put(&w, cf, b"later", b"new").unwrap();
+        let keys: [&[u8]; 4] = [b"sst", b"mem", b"gone", b"later"];
+        let old = vec![Some(b"old".to_vec()), Some(b"old".to_vec()), Some(b"old".to_vec()), None];
+
+        let at_snapshot = ReadOptions::default().with_snapshot(&snapshot);
+        assert_eq!(db.multi_get_with_options(cf, &keys, &at_snapshot).unwrap(), old);
+        db.flush_memtables_of(&[cf]).unwrap();
+        assert_eq!(db.multi_get_with_options(cf, &keys, &at_snapshot).unwrap(), old);
+        assert_eq!(
+            db.multi_get_with_options(cf, &keys, &ReadOptions::default()).unwrap(),
+            vec![Some(b"new".to_vec()), Some(b"new".to_vec()), None, Some(b"new".to_vec())]
+        );
+
+        db.release_snapshot(snapshot);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ingest_rejects_a_corrupted_external_file() {
+        let (db, dir) = open_db("ingest-checksum");
+        let cf = USER_COLUMN_FAMILY_ID;
+
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("external.sst");
+        let mut writer = SstFileWriter::create(&path, &ColumnFamilyOptions::default()).unwrap();
+        for i in 0..512u32 {
+            writer.put(format!("key{:04}", i).as_bytes(), format!("value-{:04}-payload", i).as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        // 坏在中间的 data block 里，只读首尾 key 时碰不到
+        corrupt_bytes(&path, b"value-0255-payload");
+
+        let err = db.ingest_external_file(cf, &[&path], &IngestOptions::default()).unwrap_err();
+        assert!(matches!(err, DBError::Corruption(_)), "{:?}", err);
+        assert_eq!(db.get(&ReadOptions::default(), cf, b"key0000").unwrap(), None);
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bottommost_compaction_retires_covered_range_tombstones() {
+        let (db, dir) = open_db("retire-tombstones");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        for k in [b"a", b"b", b"c"] {
+            db.put(&w, cf, k, b"v").unwrap();
+        }
+        db.flush_memtables_of(&[cf]).unwrap();
+        db.delete_range(&WriteOptions::default(), cf, b"a", b"c").unwrap();
+        db.flush_memtables_of(&[cf]).unwrap();
+        assert_eq!(db.version_set.lock().unwrap().current_version(cf).range_tombstones().len(), 1);
+
+        // L0 -> L1 就是最底层：a、b 随着合并丢掉，墓碑也跟着摘掉
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        let current = db.version_set.lock().unwrap().current_version(cf);
+        assert!(current.range_tombstones().is_empty());
+        assert_eq!(current.levels()[1].len(), 1);
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"c").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        assert!(db.version_set.lock().unwrap().current_version(cf).range_tombstones().is_empty());
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn range_tombstone_survives_compaction_under_a_snapshot() {
+        let (db, dir) = open_db("retire-tombstones-snapshot");
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+
+        put_flushed(&db, cf, b"a", b"v");
+        let snapshot = db.get_snapshot();
+        db.delete_range(&WriteOptions::default(), cf, b"a", b"b").unwrap();
+        db.flush_memtables_of(&[cf]).unwrap();
+
+        // snapshot 还要读墓碑之前的 a
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        assert_eq!(db.version_set.lock().unwrap().current_version(cf).range_tombstones().len(), 1);
+        let at = ReadOptions::default().with_snapshot(&snapshot);
+        assert_eq!(db.get(&at, cf, b"a").unwrap(), Some(b"v".to_vec()));
+        db.release_snapshot(snapshot);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sweep_deletes_expired_keys_and_skips_moved_deadlines() {
+        let (db, dir) = open_db("ttl-sweep");
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+
+        db.put_with_ttl(cf, b"gone", b"v", Duration::ZERO).unwrap();
+        db.put_with_ttl(cf, b"moved", b"v", Duration::ZERO).unwrap();
+        db.put_with_ttl(cf, b"moved", b"v2", Duration::from_secs(3600)).unwrap();
+
+        // 两条过期的索引项都处理了，被挪到一小时后的 key 留着
+        assert_eq!(db.sweep_expired(ttl::now_ms() + 1, 16).unwrap(), 2);
+        assert_eq!(db.get(&r, cf, b"gone").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"moved").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(db.sweep_expired(ttl::now_ms() + 1, 16).unwrap(), 0);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sweep_stops_at_the_limit_and_ignores_write_quotas() {
+        let (db, dir) = open_db("ttl-quota");
+        let mut options = ColumnFamilyOptions::default();
+        options.quota = QuotaOptions { write_ops_per_sec: 3, ..Default::default() };
+        let cf = db.create_column_family("limited", options).unwrap();
+        let r = ReadOptions::default();
+
+        for key in [b"a", b"b", b"c"] {
+            db.put_with_ttl(cf, key, b"v", Duration::ZERO).unwrap();
+        }
+        // 配额用完了，用户的写被拒，过期删除照样做
+        assert!(matches!(db.put(&WriteOptions::default(), cf, b"d", b"v"), Err(DBError::Busy(_))));
+        assert_eq!(db.sweep_expired(ttl::now_ms() + 1, 2).unwrap(), 2);
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"c").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(db.sweep_expired(ttl::now_ms() + 1, 2).unwrap(), 1);
+        assert_eq!(db.get(&r, cf, b"c").unwrap(), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn put_with_ttl_racing_a_sweep_is_never_lost() {
+        let (db, dir) = open_db("ttl-race");
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+
+        for round in 0..50u32 {
+            let key = round.to_be_bytes();
+            db.put_with_ttl(cf, &key, b"old", Duration::ZERO).unwrap();
+            let writer = {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || db.put_with_ttl(cf, &key, b"new", Duration::from_secs(3600)).unwrap())
+            };
+            db.sweep_expired(ttl::now_ms() + 1, 16).unwrap();
+            writer.join().unwrap();
+            // 不管新的 put 落在 sweep 之前还是之后，它的过期时间都还没到
+            assert_eq!(db.get(&r, cf, &key).unwrap(), Some(b"new".to_vec()));
+        }
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_put_and_delete_keep_the_hnsw_index_in_step_with_the_kv() {
+        let (db, dir) = open_db("vector-order");
+        let mut options = ColumnFamilyOptions::default();
+        options.vector.default.index = VectorIndexType::Hnsw;
+        // 后台重建 / repair 不掺和进来
+        options.vector.default.rebuild_deleted_ratio = 0.0;
+        options.vector.default.repair_after_deletes = 0;
+        let cf = db.create_column_family("vectors", options).unwrap();
+        db.put_vector(cf, b"seed", &[0.0, 0.0], b"").unwrap();
+        // 第一次查询建图，之后的写增量更新它
+        db.knn_search(cf, &KnnRequest::new(vec![0.0, 0.0], 1)).unwrap();
+
+        let writers: Vec<_> = (0..4u32).map(|t| {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || {
+                for i in 0..200u32 {
+                    if (i + t) % 2 == 0 {
+                        db.put_vector(cf, b"id", &[t as f32, i as f32], b"").unwrap();
+                    } else {
+                        db.delete(&WriteOptions::default(), cf, b"id").unwrap();
+                    }
+                }
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        let index = db.loaded_vector_index(cf, "").unwrap();
+        let index = index.read().unwrap();
+        match db.get(&ReadOptions::default(), cf, b"id").unwrap() {
+            Some(value) => {
+                let (vector, _) = decode_indexed_vector(&value, "").unwrap();
+                let live = index.live_entries().unwrap();
+                assert!(live.iter().any(|(k, v)| k == b"id" && *v == vector));
+            }
+            None => assert!(!index.contains(b"id")),
+        }
+        drop(index);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn snapshot_get_honors_read_options() {
+        let (db, dir) = open_db("snapshot-get-checksum");
+        let cf = USER_COLUMN_FAMILY_ID;
+        put_flushed(&db, cf, b"k", b"w4Tq-unique-value-8Lp2");
+        let snapshot = db.get_snapshot();
+
+        let file_number = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        corrupt_bytes(&db.db_config.locate_sst(file_number).unwrap(), b"unique-value");
+
+        // 走 get_at_sequence 的路径也要校验 block，不能把坏 block 当成没有这个 key
+        let checked = ReadOptions::default().with_snapshot(&snapshot).with_fill_cache(false);
+        assert!(matches!(db.get(&checked, cf, b"k"), Err(DBError::Corruption(_))));
+        let unchecked = checked.with_verify_checksums(false);
+        assert!(db.get(&unchecked, cf, b"k").unwrap().is_some());
+
+        db.release_snapshot(snapshot);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn obsolete_file_gc_leaves_shared_tier_dirs_alone() {
+        let dir = test_dir("gc-tier");
+        let tier = test_dir("gc-tier-shared");
+        fs::create_dir_all(&tier).unwrap();
+        // 别的 DB 放在共用 tier 里的文件，这个 DB 的 MANIFEST 里没有它
+        let foreign = tier.join(sst_file_name(424242));
+        fs::write(&foreign, b"not ours").unwrap();
+
+        let open = OpenOptions { sst_tier_dirs: vec![tier.clone()], ..OpenOptions::default() };
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open).unwrap();
+        let cf = USER_COLUMN_FAMILY_ID;
+        put_flushed(&db, cf, b"k", b"v");
+        let stray = db.db_config.sst_dir.join(sst_file_name(434343));
+        fs::write(&stray, b"orphan").unwrap();
+
+        db.delete_obsolete_files();
+        assert!(foreign.exists());
+        assert!(!stray.exists());
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&tier);
+    }
+
+    #[test]
+    fn invalid_config_file_options_only_warn_for_an_existing_db() {
+        let config = r#"{"options": {"level0_file_num_compaction_trigger": 8, "level0_slowdown_writes_trigger": 2}}"#;
+
+        let dir = test_dir("validate-existing");
+        DBImpl::open(dir.to_str().unwrap()).unwrap().close().unwrap();
+        fs::write(dir.join("config.json"), config).unwrap();
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        db.close().unwrap();
+
+        // 新建的 DB 照样报错
+        let fresh = test_dir("validate-fresh");
+        fs::create_dir_all(&fresh).unwrap();
+        fs::write(fresh.join("config.json"), config).unwrap();
+        assert!(matches!(DBImpl::open(fresh.to_str().unwrap()), Err(DBError::InvalidArgument(_))));
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&fresh);
+    }
+
+    #[test]
+    fn snapshot_pins_the_files_compaction_replaced() {
+        let (db, dir) = open_db("snapshot-pins-version");
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+
+        put_flushed(&db, cf, b"k", b"v1");
+        let old_file = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        let old_path = db.db_config.locate_sst(old_file).unwrap();
+        let snapshot = db.get_snapshot();
+        put_flushed(&db, cf, b"k", b"v2");
+
+        // compaction 只留 v2；v1 在被替换掉的文件里，snapshot 还 pin 着它
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        db.delete_obsolete_files();
+        assert!(!db.version_set.lock().unwrap().current_version(cf).all_file_numbers().contains(&old_file));
+        assert!(old_path.exists());
+        let at = ReadOptions::default().with_snapshot(&snapshot);
+        assert_eq!(db.get(&at, cf, b"k").unwrap(), Some(b"v1".to_vec()));
+
+        db.release_snapshot(snapshot);
+        db.delete_obsolete_files();
+        assert!(!old_path.exists());
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn delete_files_in_range_drops_only_files_inside_the_range() {
+        let (db, dir) = open_db("delete-files-in-range");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"a", b"1").unwrap();
+        put_flushed(&db, cf, b"b", b"1");
+        db.put(&w, cf, b"b2", b"1").unwrap();
+        put_flushed(&db, cf, b"x", b"1");
+        // 还在 memtable 里的不受影响
+        db.put(&w, cf, b"a2", b"1").unwrap();
+
+        // 第二个文件跨过了 end，整个留下
+        db.delete_files_in_range(cf, Some(b"a"), Some(b"c")).unwrap();
+        assert_eq!(db.version_set.lock().unwrap().current_version(cf).all_file_numbers().len(), 1);
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"b2").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, cf, b"a2").unwrap(), Some(b"1".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn delete_prefix_removes_only_keys_under_the_prefix() {
+        let (db, dir) = open_db("delete-prefix");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        for k in [b"p/1".as_slice(), b"p/2", b"p0", b"q", &[0xff, 1], &[0xff, 0xff]] {
+            db.put(&w, cf, k, b"v").unwrap();
+        }
+        db.flush_memtables_of(&[cf]).unwrap();
+        db.put(&w, cf, b"p/3", b"v").unwrap();
+
+        db.delete_prefix(cf, b"p/", false).unwrap();
+        for k in [b"p/1", b"p/2", b"p/3"] {
+            assert_eq!(db.get(&r, cf, k).unwrap(), None);
+        }
+        assert_eq!(db.get(&r, cf, b"p0").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(db.get(&r, cf, b"q").unwrap(), Some(b"v".to_vec()));
+
+        // 全 0xff 的前缀没有上界，逐个点删
+        db.delete_prefix(cf, &[0xff], true).unwrap();
+        assert_eq!(db.get(&r, cf, &[0xff, 1]).unwrap(), None);
+        assert_eq!(db.get(&r, cf, &[0xff, 0xff]).unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"q").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn get_as_of_reads_versions_compaction_replaced() {
+        let dir = test_dir("time-travel");
+        let mut open = OpenOptions::default();
+        open.options.time_travel_retention_secs = 3600;
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        put_flushed(&db, cf, b"k", b"v1");
+        let before = db.latest_sequence_number();
+        put_flushed(&db, cf, b"k", b"v2");
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        db.delete_obsolete_files();
+
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(db.get_as_of(cf, b"k", before).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(db.get_as_of(cf, b"k", db.latest_sequence_number()).unwrap(), Some(b"v2".to_vec()));
+        // memtable 里的范围墓碑只盖住它之后的读
+        let before_delete = db.latest_sequence_number();
+        db.delete_range(&WriteOptions::default(), cf, b"a", b"z").unwrap();
+        assert_eq!(db.get_as_of(cf, b"k", db.latest_sequence_number()).unwrap(), None);
+        assert_eq!(db.get_as_of(cf, b"k", before_delete).unwrap(), Some(b"v2".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        // 没开 retention 时直接拒绝
+        let (db, dir) = open_db("time-travel-off");
+        put_flushed(&db, cf, b"k", b"v");
+        assert!(matches!(db.get_as_of(cf, b"k", 1), Err(DBError::InvalidArgument(_))));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn try_get_snapshot_is_busy_at_max_snapshots() {
+        let dir = test_dir("max-snapshots");
+        let mut open = OpenOptions::default();
+        open.options.max_snapshots = 2;
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open).unwrap();
+
+        let a = db.try_get_snapshot().unwrap();
+        let b = db.try_get_snapshot().unwrap();
+        assert!(matches!(db.try_get_snapshot(), Err(DBError::Busy(_))));
+        db.release_snapshot(a);
+        let c = db.try_get_snapshot().unwrap();
+        db.release_snapshot(b);
+        db.release_snapshot(c);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn range_leases_block_other_writers_and_survive_a_reopen() {
+        let dir = test_dir("range-locks");
+        let mut open = OpenOptions::default();
+        open.options.enforce_range_locks = true;
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open.clone()).unwrap();
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+        let ttl = Duration::from_secs(600);
+
+        let lease = db.lock_range(cf, b"b", b"d", ttl).unwrap();
+        assert!(matches!(db.lock_range(cf, b"c", b"e", ttl), Err(DBError::Busy(_))));
+        assert!(matches!(db.lock_range(cf, b"d", b"b", ttl), Err(DBError::InvalidArgument(_))));
+        db.lock_range(cf, b"d", b"e", ttl).unwrap();
+
+        assert!(matches!(db.put(&w, cf, b"c", b"v"), Err(DBError::Busy(_))));
+        assert!(matches!(db.delete_range(&WriteOptions::default(), cf, b"a", b"z"), Err(DBError::Busy(_))));
+        db.put(&w, cf, b"a", b"v").unwrap();
+        let holder = WriteOptions::default().with_range_lease(lease.id);
+        db.put(&holder, cf, b"c", b"v").unwrap();
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open).unwrap();
+        assert_eq!(db.range_locks(Some(cf)).len(), 2);
+        assert!(matches!(db.put(&w, cf, b"c", b"v"), Err(DBError::Busy(_))));
+        db.unlock_range(&lease).unwrap();
+        db.put(&w, cf, b"c", b"v").unwrap();
+        assert!(matches!(db.renew_range_lock(&lease, ttl), Err(DBError::Expired(_))));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checkpoint_opens_as_a_standalone_db() {
+        let dir = test_dir("checkpoint-src");
+        let cp = test_dir("checkpoint-dst");
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        put_flushed(&db, cf, b"flushed", b"1");
+        db.put(&w, cf, b"in-wal", b"2").unwrap();
+        db.create_checkpoint(&cp).unwrap();
+        assert!(matches!(db.create_checkpoint(&cp), Err(DBError::InvalidArgument(_))));
+
+        // checkpoint 之后的写入不在里面
+        db.put(&w, cf, b"later", b"3").unwrap();
+        db.close().unwrap();
+
+        let copy = DBImpl::open(cp.to_str().unwrap()).unwrap();
+        assert_eq!(copy.get(&r, cf, b"flushed").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(copy.get(&r, cf, b"in-wal").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(copy.get(&r, cf, b"later").unwrap(), None);
+        copy.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&cp);
+    }
+
+    #[test]
+    fn incremental_backup_restores_into_an_empty_dir() {
+        let dir = test_dir("backup-src");
+        let restored = test_dir("backup-dst");
+        let backups = crate::db::backup::BackupEngine::open_local(test_dir("backup-target")).unwrap();
+        let db = DBImpl::open(dir.to_str().unwrap()).unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        put_flushed(&db, cf, b"a", b"1");
+        let first = backups.create_new_backup(&db).unwrap();
+        assert!(first.files_uploaded >= 1);
+
+        // 第二次只传新文件
+        put_flushed(&db, cf, b"b", b"2");
+        let second = backups.create_new_backup(&db).unwrap();
+        assert_eq!(second.files_uploaded, 1);
+        assert_eq!(second.files_skipped, first.files_uploaded);
+        db.close().unwrap();
+
+        assert_eq!(backups.purge_old_backups(1).unwrap(), 1);
+        let stats = backups.restore_from_latest_backup(restored.to_str().unwrap()).unwrap();
+        assert_eq!(stats.backup_id, second.backup_id);
+        let copy = DBImpl::open(restored.to_str().unwrap()).unwrap();
+        assert_eq!(copy.get(&r, cf, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(copy.get(&r, cf, b"b").unwrap(), Some(b"2".to_vec()));
+        copy.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&restored);
+        let _ = fs::remove_dir_all(test_dir("backup-target"));
+    }
+
+    struct DropKey(&'static [u8]);
+
+    impl WalFilter for DropKey {
+        fn log_record(&self, _sequence: SequenceNumber, batch: &WriteBatch) -> WalProcessingOption {
+            if batch.iter().any(|e| matches!(e, WriteBatchEntry::Put { key, .. } if key == b"stop")) {
+                return WalProcessingOption::Stop;
+            }
+            WalProcessingOption::Replace(crate::db::wal_filter::retain_entries(batch, |e| {
+                !matches!(e, WriteBatchEntry::Put { key, .. } if *key == self.0)
+            }))
+        }
+    }
+
+    #[test]
+    fn wal_filter_rewrites_records_on_replay() {
+        let (db, dir) = open_db("wal-filter");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        let mut batch = WriteBatch::new();
+        batch.put(cf, b"keep", b"1");
+        batch.put(cf, b"drop", b"1");
+        db.write(&w, batch).unwrap();
+        db.put(&w, cf, b"stop", b"1").unwrap();
+        db.put(&w, cf, b"after", b"1").unwrap();
+        // 不 close：下次 open 从 WAL 重放
+        drop(db);
+
+        let db = DBImpl::open_with_wal_filter(dir.to_str().unwrap(), &DropKey(b"drop")).unwrap();
+        assert_eq!(db.get(&r, cf, b"keep").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, cf, b"drop").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"stop").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"after").unwrap(), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn entries(it: &mut dyn DBIterator, forward: bool) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut out = Vec::new();
+        while it.valid() {
+            out.push((it.key().unwrap().to_vec(), it.value().unwrap().to_vec()));
+            if forward { it.next().unwrap() } else { it.prev().unwrap() }
+        }
+        out
+    }
+
+    fn kv(k: &[u8], v: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (k.to_vec(), v.to_vec())
+    }
+
+    #[test]
+    fn iterator_walks_backwards_over_memtables_and_sst() {
+        let (db, dir) = open_db("reverse-iter");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"a", b"old").unwrap();
+        db.put(&w, cf, b"b", b"1").unwrap();
+        put_flushed(&db, cf, b"c", b"1");
+        db.put(&w, cf, b"a", b"new").unwrap();
+        db.delete(&w, cf, b"b").unwrap();
+        db.put(&w, cf, b"d", b"1").unwrap();
+
+        let mut it = db.new_iterator(&r, cf);
+        it.seek_to_last();
+        assert_eq!(entries(it.as_mut(), false), vec![kv(b"d", b"1"), kv(b"c", b"1"), kv(b"a", b"new")]);
+
+        it.seek_for_prev(b"bb");
+        assert_eq!(it.key(), Some(b"a".as_slice()));
+        it.seek_for_prev(b"c");
+        assert_eq!(it.key(), Some(b"c".as_slice()));
+
+        // 换方向
+        it.seek(b"c");
+        it.prev().unwrap();
+        assert_eq!(it.value(), Some(b"new".as_slice()));
+        it.next().unwrap();
+        assert_eq!(entries(it.as_mut(), true), vec![kv(b"c", b"1"), kv(b"d", b"1")]);
+
+        // 有上界时 seek_to_last 停在界内
+        let bounded = ReadOptions::default().with_iterate_bounds(None, Some(b"d"));
+        let mut it = db.new_iterator(&bounded, cf);
+        it.seek_to_last();
+        assert_eq!(it.key(), Some(b"c".as_slice()));
+        drop(it);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn prefix_same_as_start_stops_at_the_end_of_the_prefix() {
+        use crate::util::FixedPrefixTransform;
+
+        let (db, dir) = open_db("prefix-iter");
+        let w = WriteOptions::default();
+        let mut opts = db.options.user_cf.clone();
+        opts.prefix_extractor = Some(Arc::new(FixedPrefixTransform::new(2)));
+        let cf = db.create_column_family("prefixed", opts).unwrap();
+
+        db.put(&w, cf, b"aa1", b"1").unwrap();
+        put_flushed(&db, cf, b"ab1", b"1");
+        db.put(&w, cf, b"aa2", b"1").unwrap();
+
+        let prefixed = ReadOptions::default().with_prefix_same_as_start(true);
+        let mut it = db.new_iterator(&prefixed, cf);
+        it.seek(b"aa");
+        assert_eq!(entries(it.as_mut(), true), vec![kv(b"aa1", b"1"), kv(b"aa2", b"1")]);
+        // 比前缀短的 target 不在 domain 里，不截断
+        it.seek(b"a");
+        assert_eq!(entries(it.as_mut(), true).len(), 3);
+        it.seek_to_first();
+        assert_eq!(entries(it.as_mut(), true).len(), 3);
+
+        let mut it = db.new_iterator(&ReadOptions::default(), cf);
+        it.seek(b"aa");
+        assert_eq!(entries(it.as_mut(), true).len(), 3);
+        drop(it);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn refresh_sees_new_writes_and_drops_the_old_version() {
+        let (db, dir) = open_db("iter-refresh");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        db.put(&w, cf, b"a", b"1").unwrap();
+        put_flushed(&db, cf, b"c", b"1");
+        let old_file = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        let old_path = db.db_config.locate_sst(old_file).unwrap();
+
+        let mut it = db.new_iterator(&r, cf);
+        it.seek_to_first();
+        put_flushed(&db, cf, b"b", b"1");
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        db.delete_obsolete_files();
+        // iterator 还 pin 着旧文件
+        assert!(old_path.exists());
+
+        it.refresh().unwrap();
+        assert_eq!(it.key(), Some(b"a".as_slice()));
+        assert_eq!(entries(it.as_mut(), true), vec![kv(b"a", b"1"), kv(b"b", b"1"), kv(b"c", b"1")]);
+        db.delete_obsolete_files();
+        assert!(!old_path.exists());
+
+        // 没定位过的 iterator 刷新后也还是没定位
+        let mut fresh = db.new_iterator(&r, cf);
+        fresh.refresh().unwrap();
+        assert!(!fresh.valid());
+        drop(fresh);
+        drop(it);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oldest_iterators_lists_live_iterators_and_snapshots() {
+        let (db, dir) = open_db("oldest-iterators");
+        let cf = USER_COLUMN_FAMILY_ID;
+        let listed = |db: &DBImpl| -> Vec<serde_json::Value> {
+            serde_json::from_str(&db.get_property(cf, properties::OLDEST_ITERATORS).unwrap()).unwrap()
+        };
+
+        let it = db.new_iterator(&ReadOptions::default(), cf);
+        let snapshot = db.get_snapshot();
+        let handles = listed(&db);
+        assert_eq!(handles.len(), 2);
+        assert_eq!(handles[0]["kind"], "iterator");
+        assert!(handles[0]["location"].as_str().unwrap().contains("db_impl.rs"));
+        assert_eq!(handles[1]["kind"], "snapshot");
+
+        drop(it);
+        db.release_snapshot(snapshot);
+        assert!(listed(&db).is_empty());
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn close_flushes_every_cf_and_truncates_the_wal() {
+        let dir = test_dir("close-truncates-wal");
+        let path = dir.to_str().unwrap();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+
+        let db = DBImpl::open(path).unwrap();
+        let other = db.create_column_family("other", db.options.user_cf.clone()).unwrap();
+        db.put(&w, USER_COLUMN_FAMILY_ID, b"k", b"1").unwrap();
+        db.put(&w, other, b"k", b"2").unwrap();
+        let seq = db.latest_sequence_number();
+        let wal = db.wal_manager.path().to_path_buf();
+        db.close().unwrap();
+        drop(db);
+        assert_eq!(fs::metadata(&wal).map(|m| m.len()).unwrap_or(0), 0);
+
+        let db = DBImpl::open(path).unwrap();
+        let info = db.recovery_info();
+        assert_eq!((info.batches_replayed, info.wal_applied_sequence), (0, seq));
+        assert_eq!(db.get(&r, USER_COLUMN_FAMILY_ID, b"k").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, other, b"k").unwrap(), Some(b"2".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        // avoid_flush_during_shutdown：WAL 留着，下次 open 重放
+        let dir = test_dir("close-keeps-wal");
+        let path = dir.to_str().unwrap();
+        let mut open = OpenOptions::default();
+        open.options.avoid_flush_during_shutdown = true;
+        let db = DBImpl::open_with_options(path, open.clone()).unwrap();
+        db.put(&w, USER_COLUMN_FAMILY_ID, b"k", b"1").unwrap();
+        db.close().unwrap();
+        drop(db);
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        assert_eq!(db.recovery_info().batches_replayed, 1);
+        assert_eq!(db.get(&r, USER_COLUMN_FAMILY_ID, b"k").unwrap(), Some(b"1".to_vec()));
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn disable_wal_writes_are_lost_on_a_crash() {
+        let dir = test_dir("disable-wal");
+        let path = dir.to_str().unwrap();
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+
+        let db = DBImpl::open(path).unwrap();
+        db.put(&WriteOptions::default(), cf, b"logged", b"1").unwrap();
+        let unlogged = WriteOptions { disable_wal: true, ..WriteOptions::default() };
+        db.put(&unlogged, cf, b"unlogged", b"1").unwrap();
+        let synced = WriteOptions { sync: true, ..WriteOptions::default() };
+        db.put(&synced, cf, b"synced", b"1").unwrap();
+        assert_eq!(db.get(&r, cf, b"unlogged").unwrap(), Some(b"1".to_vec()));
+        // 不 close，模拟进程崩溃
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert_eq!(db.get(&r, cf, b"logged").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, cf, b"synced").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&r, cf, b"unlogged").unwrap(), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        // enable_write_ahead_log = false 时不带 WriteOptions 的写也不进 WAL
+        let mut open = OpenOptions::default();
+        open.options.enable_write_ahead_log = false;
+        open.options.write_sync = true;
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        let defaults = db.default_write_options();
+        assert!(defaults.disable_wal && defaults.sync);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// 压不下去的 value，文件大小基本就是 value 的大小
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (0..len).map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (x >> 56) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn fifo_compaction_drops_the_oldest_files() {
+        use crate::util::CompactionStyle;
+
+        let (db, dir) = open_db("fifo");
+        db.bg_worker.shutdown();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+        let mut opts = db.options.user_cf.clone();
+        opts.compaction_style = CompactionStyle::Fifo;
+        opts.fifo.max_table_files_size = 20_000;
+        let cf = db.create_column_family("log", opts).unwrap();
+
+        // 每个文件 8KB 多一点，只留得下两个
+        for i in 0..4u64 {
+            db.put(&w, cf, format!("k{}", i).as_bytes(), &noise(8192, i)).unwrap();
+            db.flush_memtables_of(&[cf]).unwrap();
+        }
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        assert_eq!(db.version_set.lock().unwrap().current_version(cf).all_file_numbers().len(), 2);
+        assert_eq!(db.get(&r, cf, b"k0").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"k1").unwrap(), None);
+        assert_eq!(db.get(&r, cf, b"k3").unwrap(), Some(noise(8192, 3)));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn universal_compaction_merges_runs_into_the_last_level() {
+        use crate::util::CompactionStyle;
+
+        let (db, dir) = open_db("universal");
+        db.bg_worker.shutdown();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+        let mut opts = db.options.user_cf.clone();
+        opts.compaction_style = CompactionStyle::Universal;
+        let cf = db.create_column_family("tiered", opts).unwrap();
+
+        let trigger = db.options.level0_file_num_compaction_trigger;
+        for i in 0..trigger {
+            db.put(&w, cf, b"k", format!("v{}", i).as_bytes()).unwrap();
+            db.put(&w, cf, format!("k{}", i).as_bytes(), b"1").unwrap();
+            db.flush_memtables_of(&[cf]).unwrap();
+        }
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+
+        // 大小一样的 run：空间放大超了，全部合并进最后一层
+        let version = db.version_set.lock().unwrap().current_version(cf);
+        assert!(version.levels()[..NUM_LEVELS - 1].iter().all(|l| l.is_empty()));
+        assert_eq!(version.levels()[NUM_LEVELS - 1].len(), 1);
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), Some(format!("v{}", trigger - 1).into_bytes()));
+        assert_eq!(db.get(&r, cf, b"k0").unwrap(), Some(b"1".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pinned_files_are_deleted_when_the_last_reader_lets_go() {
+        let (db, dir) = open_db("version-pins");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        put_flushed(&db, cf, b"a", b"1");
+        let pinned_file = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        let pinned_path = db.db_config.locate_sst(pinned_file).unwrap();
+
+        let it = db.new_iterator(&r, cf);
+        let again = db.new_iterator(&r, cf);
+        assert_eq!(db.version_pins.pinned_versions(), 2);
+        put_flushed(&db, cf, b"b", b"1");
+        let unpinned_file = *db.version_set.lock().unwrap().current_version(cf).all_file_numbers().iter()
+            .find(|&&n| n != pinned_file)
+            .unwrap();
+        let unpinned_path = db.db_config.locate_sst(unpinned_file).unwrap();
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        db.delete_obsolete_files();
+
+        // 只有 iterator 读着的那个等着；get 用完就放掉
+        assert!(!unpinned_path.exists());
+        assert!(pinned_path.exists());
+        assert_eq!(db.version_pins.deferred_files(), 1);
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.version_pins.pinned_versions(), 2);
+
+        drop(it);
+        assert!(pinned_path.exists());
+        drop(again);
+        assert!(!pinned_path.exists());
+        assert_eq!((db.version_pins.pinned_versions(), db.version_pins.deferred_files()), (0, 0));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reserved_numbers_are_not_handed_out_again_after_a_crash() {
+        let dir = test_dir("reserved-ranges");
+        let path = dir.to_str().unwrap();
+
+        let db = DBImpl::open(path).unwrap();
+        // 发出去了但没有任何文件或 WAL 记录用到
+        let (file_number, seq) = {
+            let vs = db.version_set.lock().unwrap();
+            (vs.new_file_number().unwrap(), vs.next_sequence().unwrap())
+        };
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        {
+            let vs = db.version_set.lock().unwrap();
+            assert!(vs.new_file_number().unwrap() > file_number);
+            assert!(vs.next_sequence().unwrap() > seq);
+        }
+        db.put(&WriteOptions::default(), USER_COLUMN_FAMILY_ID, b"k", b"v").unwrap();
+        assert!(db.latest_sequence_number() > seq);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn per_level_layout_is_still_readable_as_flat() {
+        use crate::util::SstLayout;
+
+        let dir = test_dir("per-level-layout");
+        let path = dir.to_str().unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        let open = OpenOptions { sst_layout: SstLayout::PerLevel, ..OpenOptions::default() };
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        put_flushed(&db, cf, b"a", b"1");
+        let l0 = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        assert!(db.db_config.locate_sst(l0).unwrap().starts_with(db.db_config.sst_dir.join("L0")));
+
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        let l1 = db.version_set.lock().unwrap().current_version(cf).levels()[1][0].file_number;
+        assert!(db.db_config.locate_sst(l1).unwrap().starts_with(db.db_config.sst_dir.join("L1")));
+        db.close().unwrap();
+        drop(db);
+
+        // 换回默认布局：老文件照样找得到，新文件直接放在 sst_dir 下
+        let db = DBImpl::open(path).unwrap();
+        assert_eq!(db.get(&r, cf, b"a").unwrap(), Some(b"1".to_vec()));
+        put_flushed(&db, cf, b"b", b"1");
+        let flat = db.version_set.lock().unwrap().current_version(cf).levels()[0][0].file_number;
+        assert_eq!(db.db_config.locate_sst(flat).unwrap(), db.db_config.sst_dir.join(sst_file_name(flat)));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn compact_range_now_merges_the_range_down() {
+        let (db, dir) = open_db("compaction-command");
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+        for v in [b"1", b"2"] {
+            put_flushed(&db, cf, b"k", v);
+        }
+        assert_eq!(db.version_set.lock().unwrap().current_version(cf).levels()[0].len(), 2);
+
+        db.compact_range_now(cf, None, None).unwrap();
+        let levels = db.version_set.lock().unwrap().current_version(cf).levels();
+        assert!(levels[0].is_empty());
+        assert_eq!(levels.iter().flatten().count(), 1);
+        assert_eq!(db.get(&r, cf, b"k").unwrap(), Some(b"2".to_vec()));
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unfinished_compaction_outputs_are_removed_on_open() {
+        let dir = test_dir("temp-sst");
+        let path = dir.to_str().unwrap();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+        let temp_files = |db: &DBImpl| fs::read_dir(&db.db_config.sst_dir).unwrap()
+            .filter(|e| e.as_ref().unwrap().path().to_string_lossy().ends_with(".sst.tmp"))
+            .count();
+
+        let db = DBImpl::open(path).unwrap();
+        put_flushed(&db, cf, b"a", b"1");
+        put_flushed(&db, cf, b"b", b"1");
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        assert_eq!(temp_files(&db), 0);
+
+        // crash 时写了一半的输出
+        let orphan = db.db_config.temp_sst_path(999_999);
+        fs::write(&orphan, b"half a table").unwrap();
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert!(!orphan.exists());
+        assert_eq!(db.get(&r, cf, b"b").unwrap(), Some(b"1".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_flushes_all_reach_the_manifest() {
+        let dir = test_dir("manifest-queue");
+        let path = dir.to_str().unwrap();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+
+        let db = DBImpl::open(path).unwrap();
+        let cfs: Vec<ColumnFamilyId> = (0..4)
+            .map(|i| db.create_column_family(&format!("cf{}", i), db.options.user_cf.clone()).unwrap())
+            .collect();
+        std::thread::scope(|s| {
+            for &cf in &cfs {
+                let (db, w) = (&db, &w);
+                s.spawn(move || {
+                    for i in 0..3 {
+                        db.put(w, cf, format!("k{}", i).as_bytes(), b"v").unwrap();
+                        db.flush_memtables_of(&[cf]).unwrap();
+                    }
+                });
+            }
+        });
+        // 不 close：只靠 MANIFEST 重建
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        for &cf in &cfs {
+            assert_eq!(db.version_set.lock().unwrap().current_version(cf).all_file_numbers().len(), 3);
+            assert_eq!(db.get(&r, cf, b"k2").unwrap(), Some(b"v".to_vec()));
+        }
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[derive(Default)]
+    struct EventLog(Mutex<Vec<String>>);
+
+    impl EventListener for EventLog {
+        fn on_flush_begin(&self, info: &FlushJobInfo) {
+            self.0.lock().unwrap().push(format!("flush-begin {}", info.file_number));
+        }
+        fn on_flush_completed(&self, info: &FlushJobInfo) {
+            assert!(info.file_size > 0 && info.file_path.exists());
+            self.0.lock().unwrap().push(format!("flush {}", info.file_number));
+        }
+        fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+            let inputs: Vec<_> = info.input_files.iter().map(|(_, f)| f.to_string()).collect();
+            self.0.lock().unwrap().push(format!("compaction {} -> L{}", inputs.join(","), info.output_level));
+        }
+        fn on_table_file_created(&self, info: &TableFileCreationInfo) {
+            self.0.lock().unwrap().push(format!("created {} {:?}", info.file_number, info.reason));
+        }
+        fn on_table_file_deleted(&self, info: &TableFileDeletionInfo) {
+            assert!(!info.file_path.exists());
+            self.0.lock().unwrap().push(format!("deleted {}", info.file_number));
+        }
+    }
+
+    #[test]
+    fn listeners_see_flushes_compactions_and_deleted_files() {
+        let (db, dir) = open_db("listeners");
+        db.bg_worker.shutdown();
+        let log = Arc::new(EventLog::default());
+        db.add_listener(log.clone());
+        let (cf, w) = (USER_COLUMN_FAMILY_ID, WriteOptions::default());
+
+        let mut flushed = Vec::new();
+        for i in 0..2 {
+            db.put(&w, cf, b"k", format!("v{}", i).as_bytes()).unwrap();
+            db.flush_memtables_of(&[cf]).unwrap();
+            flushed.extend(db.version_set.lock().unwrap().current_version(cf).all_file_numbers());
+        }
+        flushed.sort();
+        flushed.dedup();
+        let (a, b) = (flushed[0], flushed[1]);
+        assert_eq!(std::mem::take(&mut *log.0.lock().unwrap()), vec![
+            format!("flush-begin {}", a), format!("created {} Flush", a), format!("flush {}", a),
+            format!("flush-begin {}", b), format!("created {} Flush", b), format!("flush {}", b),
+        ]);
+
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        db.delete_obsolete_files();
+        let events = std::mem::take(&mut *log.0.lock().unwrap());
+        let output = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        assert!(events.contains(&format!("deleted {}", a)));
+        assert!(events.contains(&format!("deleted {}", b)));
+        assert!(events.contains(&format!("created {} Compaction", output)));
+        assert!(events.iter().any(|e| e.starts_with("compaction ") && e.contains(&a.to_string()) && e.contains(&b.to_string())));
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn memory_usage_covers_memtables_cache_and_readers() {
+        let (db, dir) = open_db("memory-usage");
+        db.bg_worker.shutdown();
+        let (cf, w, r) = (USER_COLUMN_FAMILY_ID, WriteOptions::default(), ReadOptions::default());
+
+        let empty = db.memory_usage();
+        for i in 0..100u64 {
+            db.put(&w, cf, format!("k{:03}", i).as_bytes(), &noise(100, i)).unwrap();
+        }
+        let written = db.memory_usage();
+        assert!(written.active_memtables >= empty.active_memtables + 100 * 100);
+        assert_eq!(written.table_readers, 0);
+
+        db.flush_memtables_of(&[cf]).unwrap();
+        assert_eq!(db.get(&r, cf, b"k050").unwrap(), Some(noise(100, 50)));
+        let read = db.memory_usage();
+        assert!(read.active_memtables < written.active_memtables);
+        assert_eq!(read.table_readers, 1);
+        assert!(read.index_blocks > 0);
+        assert!(read.block_cache_usage > 0 && read.block_cache_usage <= read.block_cache_capacity);
+        assert_eq!(read.pinned_blocks, 0);
+        assert_eq!(read.total(), read.active_memtables + read.immutable_memtables
+            + read.block_cache_usage + read.index_blocks + read.filter_blocks);
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// 向量 = [文档长度, 0]
+    struct LengthEmbedder;
+
+    impl Embedder for LengthEmbedder {
+        fn name(&self) -> &str {
+            "length"
+        }
+
+        fn embed(&self, document: &[u8]) -> Result<Vec<f32>, DBError> {
+            Ok(vec![document.len() as f32, 0.0])
+        }
+    }
+
+    fn hit_keys(response: &KnnResponse) -> Vec<Vec<u8>> {
+        response.hits.iter().map(|h| h.key.clone()).collect()
+    }
+
+    #[test]
+    fn knn_search_over_documents_and_named_indexes() {
+        use crate::vector::KeyFilter;
+
+        let (db, dir) = open_db("knn");
+        db.bg_worker.shutdown();
+        let mut opts = db.options.user_cf.clone();
+        opts.vector.default.dimension = 2;
+        opts.vector.indexes.insert("img".into(), VectorIndexOptions {
+            dimension: 3,
+            index: VectorIndexType::Hnsw,
+            ..VectorIndexOptions::default()
+        });
+        let cf = db.create_column_family("docs", opts).unwrap();
+
+        assert!(db.put_document(cf, b"d0", b"x").is_err());
+        db.register_embedder(cf, Arc::new(LengthEmbedder));
+        db.put_document(cf, b"doc:a", b"one").unwrap();
+        db.put_documents(cf, &[(&b"doc:b"[..], &b"three"[..]), (&b"doc:c"[..], &b"seventeen"[..])]).unwrap();
+        db.put_vector(cf, b"raw:1", &[4.0, 0.0], b"").unwrap();
+        assert!(matches!(db.put_vector(cf, b"raw:2", &[1.0], b""), Err(DBError::InvalidArgument(_))));
+
+        let mut request = KnnRequest::new(vec![5.0, 0.0], 2);
+        request.with_payload = true;
+        let response = db.knn_search(cf, &request).unwrap();
+        assert_eq!(hit_keys(&response), vec![b"doc:b".to_vec(), b"raw:1".to_vec()]);
+        assert_eq!(response.hits[0].payload.as_deref(), Some(&b"three"[..]));
+
+        request.filter = Some(KeyFilter::parse("prefix:doc:").unwrap());
+        request.k = 3;
+        assert_eq!(hit_keys(&db.knn_search(cf, &request).unwrap()),
+            vec![b"doc:b".to_vec(), b"doc:a".to_vec(), b"doc:c".to_vec()]);
+
+        // 多列实体：单向量索引看不到它，img 索引只看 img 列
+        db.put_vectors(cf, b"e1", &[("img", &[1.0, 0.0, 0.0]), ("txt", &[9.0])], b"entity").unwrap();
+        db.put_vectors(cf, b"e2", &[("img", &[0.0, 1.0, 0.0])], b"").unwrap();
+        assert!(db.put_vectors(cf, b"e3", &[("img", &[1.0])], b"").is_err());
+        assert!(db.put_vectors(cf, b"e3", &[("", &[1.0])], b"").is_err());
+
+        let mut request = KnnRequest::new(vec![0.9, 0.1, 0.0], 1);
+        request.index = Some("img".into());
+        request.with_payload = true;
+        let response = db.knn_search(cf, &request).unwrap();
+        assert_eq!(hit_keys(&response), vec![b"e1".to_vec()]);
+        assert_eq!(response.hits[0].payload.as_deref(), Some(&b"entity"[..]));
+        request.search.exhaustive = true;
+        assert_eq!(hit_keys(&db.knn_search(cf, &request).unwrap()), vec![b"e1".to_vec()]);
+        request.index = Some("audio".into());
+        assert!(db.knn_search(cf, &request).is_err());
+
+        // 建过图的 HNSW 索引报出统计
+        let stats: serde_json::Value = serde_json::from_str(
+            &db.get_property(cf, properties::VECTOR_INDEX_STATS).unwrap()).unwrap();
+        let stats = stats.as_array().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0]["name"].as_str(), stats[0]["index"].as_str()), (Some(""), Some("flat")));
+        assert_eq!(stats[0]["dimension"], 2);
+        assert_eq!((stats[1]["name"].as_str(), stats[1]["built"].as_bool()), (Some("img"), Some(true)));
+        assert_eq!(stats[1]["hnsw"]["nodes"], 2);
+
+        let report = db.calibrate_vector_search(cf, Some("img"), &[vec![1.0, 0.0, 0.0]], 1, 0.9).unwrap();
+        assert_eq!(report.points.last().unwrap().recall, 1.0);
+        assert!(report.suggested.ef_search.is_some());
+
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn open_checks_what_is_already_on_disk() {
+        let dir = test_dir("open-state");
+        let path = dir.to_str().unwrap();
+        let no_create = Options::builder().create_if_missing(false).build().unwrap();
+        assert!(matches!(DBImpl::open_with_options(path, no_create.clone()), Err(DBError::InvalidDBState(_))));
+        // 打不开时也不能把空目录建出来
+        assert!(!dir.exists());
+
+        let db = DBImpl::open(path).unwrap();
+        db.put(&WriteOptions::default(), USER_COLUMN_FAMILY_ID, b"k", b"v").unwrap();
+        let current = db.db_config.current_path();
+        db.close().unwrap();
+        drop(db);
+
+        let exclusive = Options::builder().error_if_exists(true).build().unwrap();
+        assert!(matches!(DBImpl::open_with_options(path, exclusive), Err(DBError::InvalidDBState(_))));
+        let db = DBImpl::open_with_options(path, no_create.clone()).unwrap();
+        assert_eq!(db.get(&ReadOptions::default(), USER_COLUMN_FAMILY_ID, b"k").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+        drop(db);
+
+        // 丢了 CURRENT：不当成新库建，也不打开
+        fs::remove_file(&current).unwrap();
+        assert!(matches!(DBImpl::open(path), Err(DBError::InvalidDBState(_))));
+        assert!(matches!(DBImpl::open_with_options(path, no_create), Err(DBError::InvalidDBState(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn overlapping_wal_batches_are_reported_and_repaired() {
+        let dir = test_dir("seq-repair");
+        let path = dir.to_str().unwrap();
+        let (cf, r) = (USER_COLUMN_FAMILY_ID, ReadOptions::default());
+
+        let db = DBImpl::open(path).unwrap();
+        db.put(&WriteOptions::default(), cf, b"a", b"1").unwrap();
+        let seq = db.latest_sequence_number();
+        // 老版本写出来的 WAL：下一个 batch 和上一个共用 seq
+        let mut batch = WriteBatch::new();
+        batch.put(cf, b"b", b"1");
+        batch.put(cf, b"c", b"1");
+        batch.set_sequence(seq);
+        db.wal_manager.append_sync(&batch).unwrap();
+        // 不 close，下次 open 从 WAL 重放
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        let info = db.recovery_info();
+        assert!(info.sequence_issues.contains(&SequenceIssue::WalOverlap {
+            base_seq: seq,
+            last_seq: seq + 1,
+            previous_last_seq: seq,
+        }));
+        assert!(!info.repaired);
+        drop(db);
+
+        let mut open = OpenOptions::default();
+        open.options.repair_sequence_numbers = true;
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        let info = db.recovery_info();
+        assert!(info.repaired);
+        assert_eq!(info.last_wal_sequence, seq + 2);
+        for k in [b"a", b"b", b"c"] {
+            assert_eq!(db.get(&r, cf, k).unwrap(), Some(b"1".to_vec()));
+        }
+        // 新写的 seq 排在挪过的 batch 后面
+        db.put(&WriteOptions::default(), cf, b"d", b"1").unwrap();
+        assert!(db.latest_sequence_number() > seq + 2);
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert!(db.recovery_info().sequence_issues.is_empty());
+        assert_eq!(db.get(&r, cf, b"c").unwrap(), Some(b"1".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dropped_column_family_is_purged_and_skipped_on_replay() {
+        let dir = test_dir("drop-cf");
+        let path = dir.to_str().unwrap();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+
+        let db = DBImpl::open(path).unwrap();
+        let cf = db.create_column_family("doomed", db.options.user_cf.clone()).unwrap();
+        put_flushed(&db, cf, b"a", b"1");
+        let file = db.version_set.lock().unwrap().current_version(cf).all_file_numbers()[0];
+        let sst = db.db_config.locate_sst(file).unwrap();
+        // 只在 WAL 里的写：重放时跳过，seq 照样占着
+        db.put(&w, cf, b"b", b"1").unwrap();
+        db.put(&w, USER_COLUMN_FAMILY_ID, b"kept", b"1").unwrap();
+        db.drop_column_family_by_id(cf).unwrap();
+        assert!(matches!(db.drop_column_family_by_id(USER_COLUMN_FAMILY_ID), Err(DBError::InvalidColumnFamily(_))));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while sst.exists() {
+            assert!(Instant::now() < deadline, "dropped CF's SST was never purged");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let seq = db.latest_sequence_number();
+        // 不 close：下次 open 重放 WAL
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert!(db.column_family_id("doomed").is_err());
+        assert_eq!(db.get(&r, USER_COLUMN_FAMILY_ID, b"kept").unwrap(), Some(b"1".to_vec()));
+        assert!(db.latest_sequence_number() >= seq);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn preloading_opens_the_hottest_and_bottom_level_files() {
+        let dir = test_dir("preload");
+        let path = dir.to_str().unwrap();
+        let cf = USER_COLUMN_FAMILY_ID;
+
+        let db = DBImpl::open(path).unwrap();
+        for k in [b"a", b"b", b"c"] {
+            put_flushed(&db, cf, k, b"1");
+        }
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        put_flushed(&db, cf, b"d", b"1");
+        let version = db.version_set.lock().unwrap().current_version(cf);
+        let (l0, l1) = (version.levels()[0][0].file_number, version.levels()[1][0].file_number);
+        assert_eq!(version.files_for_preload(1, false), vec![l0]);
+        assert_eq!(version.files_for_preload(0, true), vec![l1]);
+        assert_eq!(version.files_for_preload(5, true), vec![l0, l1]);
+        db.close().unwrap();
+        drop(db);
+
+        let db = DBImpl::open(path).unwrap();
+        assert_eq!(db.table_cache.memory_usage().0, 0);
+        db.close().unwrap();
+        drop(db);
+
+        let mut open = OpenOptions::default();
+        open.options.preload_bottom_level_on_open = true;
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        assert_eq!(db.table_cache.memory_usage().0, 1);
+        assert_eq!(db.get(&ReadOptions::default(), cf, b"a").unwrap(), Some(b"1".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bottommost_outputs_skip_the_filter_block() {
+        let (db, dir) = open_db("filters-for-hits");
+        assert!(db.options.optimize_filters_for_hits);
+        let policy: Arc<dyn FilterPolicy> = Arc::new(BloomFilterPolicy::new(10));
+        let mut opts = db.options.user_cf.clone();
+        opts.table_options.filter_policy = Some(Arc::clone(&policy));
+        let cf = db.create_column_family("filtered", opts).unwrap();
+        let w = WriteOptions::default();
+
+        for i in 0..100u32 {
+            db.put(&w, cf, format!("k{:03}", i).as_bytes(), b"v").unwrap();
+        }
+        db.flush_memtables_of(&[cf]).unwrap();
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        put_flushed(&db, cf, b"k050", b"new");
+
+        let version = db.version_set.lock().unwrap().current_version(cf);
+        assert!(version.is_bottommost_level(1));
+        let filter_bytes = |file: u64| {
+            let path = db.db_config.locate_sst(file).unwrap();
+            SstReader::open(file, path, Arc::new(BlockCache::new(1 << 20, 1)), Some(Arc::clone(&policy)))
+                .unwrap()
+                .filter_memory_usage()
+        };
+        // flush 出来的 L0 文件照样有 filter
+        assert!(filter_bytes(version.levels()[0][0].file_number) > 0);
+        assert_eq!(filter_bytes(version.levels()[1][0].file_number), 0);
+        assert_eq!(db.get(&ReadOptions::default(), cf, b"k050").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(db.get(&ReadOptions::default(), cf, b"k051").unwrap(), Some(b"v".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rewrite_files_keeps_the_level_and_applies_new_table_options() {
+        let (db, dir) = open_db("rewrite-files");
+        let mut plain = db.options.user_cf.clone();
+        plain.compression = CompressionType::NoCompression;
+        let cf = db.create_column_family("plain", plain.clone()).unwrap();
+        let (w, r) = (WriteOptions::default(), ReadOptions::default());
+
+        let value = vec![b'x'; 200];
+        for i in 0..200u32 {
+            db.put(&w, cf, format!("k{:03}", i).as_bytes(), &value).unwrap();
+        }
+        db.flush_memtables_of(&[cf]).unwrap();
+        VersionSet::compact_level_range(&db.version_set, cf, 0, None, None).unwrap();
+        let old = Arc::clone(&db.version_set.lock().unwrap().current_version(cf).levels()[1][0]);
+
+        let mut zstd = plain;
+        zstd.compression = CompressionType::ZstdCompression;
+        db.rewrite_files(cf, &[old.file_number], &zstd).unwrap();
+        let version = db.version_set.lock().unwrap().current_version(cf);
+        let new = &version.levels()[1];
+        assert_eq!(new.len(), 1);
+        assert_ne!(new[0].file_number, old.file_number);
+        assert!(new[0].file_size < old.file_size / 2);
+        assert_eq!(db.get(&r, cf, b"k123").unwrap(), Some(value.clone()));
+
+        // 已经不在了的文件、L0 文件都不重写
+        assert!(matches!(db.rewrite_files(cf, &[old.file_number], &zstd), Err(DBError::NotFound(_))));
+        put_flushed(&db, cf, b"k999", b"v");
+        let l0 = db.version_set.lock().unwrap().current_version(cf).levels()[0][0].file_number;
+        assert!(db.rewrite_files(cf, &[l0], &zstd).is_err());
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn warmup_holds_sync_writes_until_the_compaction_debt_is_paid() {
+        let dir = test_dir("warmup");
+        let path = dir.to_str().unwrap();
+        let cf = USER_COLUMN_FAMILY_ID;
+
+        let mut open = OpenOptions::default();
+        open.options.level0_file_num_compaction_trigger = 8;
+        let db = DBImpl::open_with_options(path, open.clone()).unwrap();
+        for k in [b"a", b"b", b"c"] {
+            put_flushed(&db, cf, k, b"1");
+        }
+        assert_eq!(db.compaction_debt(cf), 0);
+        let version = db.version_set.lock().unwrap().current_version(cf);
+        // L0 文件数到了 trigger：整层都是欠账
+        assert_eq!(version.compaction_debt(3, u64::MAX, 10), version.level_bytes(0));
+        assert_eq!(version.compaction_debt(4, u64::MAX, 10), 0);
+        drop(version);
+        db.close().unwrap();
+        drop(db);
+
+        // 没开 warmup_on_open：只估算，不拦写
+        open.options.level0_file_num_compaction_trigger = 2;
+        let db = DBImpl::open_with_options(path, open.clone()).unwrap();
+        assert!(!db.is_warming_up());
+        db.close().unwrap();
+        drop(db);
+
+        open.options.warmup_on_open = true;
+        let db = DBImpl::open_with_options(path, open).unwrap();
+        db.wait_until_caught_up(Duration::from_secs(30)).unwrap();
+        assert!(!db.is_warming_up());
+        assert_eq!(db.get_property(cf, properties::COMPACTION_DEBT_BYTES).as_deref(), Some("0"));
+        let sync = WriteOptions { sync: true, ..WriteOptions::default() };
+        db.put(&sync, cf, b"d", b"1").unwrap();
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn open_files_counts_readers_wal_and_manifest() {
+        let dir = test_dir("open-files");
+        let archive = dir.join("archive");
+        let open = OpenOptions { wal_archive_dir: Some(archive), ..OpenOptions::default() };
+        let db = DBImpl::open_with_options(dir.to_str().unwrap(), open).unwrap();
+        let cf = USER_COLUMN_FAMILY_ID;
+        put_flushed(&db, cf, b"k", b"v");
+        assert_eq!(db.get(&ReadOptions::default(), cf, b"k").unwrap(), Some(b"v".to_vec()));
+
+        let files = db.open_files();
+        assert!(files.table_readers >= 1);
+        // 写着的 WAL 加上归档的索引
+        assert_eq!(files.wal, 2);
+        assert_eq!(files.manifest, 1);
+        assert_eq!(files.total(), files.table_readers + 3);
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn iter_range_honours_each_kind_of_bound() {
+        let (db, dir) = open_db("iter-range-bounds");
+        let cf = USER_COLUMN_FAMILY_ID;
+        let w = WriteOptions::default();
+        for k in [b"a", b"b", b"c"] {
+            db.put(&w, cf, k, k).unwrap();
+        }
+        db.flush_memtables_of(&[cf]).unwrap();
+        // 一半在 SST 里一半在 memtable 里
+        for k in [b"d", b"e"] {
+            db.put(&w, cf, k, k).unwrap();
+        }
+
+        let keys = |iter: &mut dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), DBError>>| {
+            iter.map(|kv| String::from_utf8(kv.unwrap().0).unwrap()).collect::<String>()
+        };
+        let (b, d) = (b"b".as_slice(), b"d".as_slice());
+        assert_eq!(keys(&mut db.iter_range(cf, b..d)), "bc");
+        assert_eq!(keys(&mut db.iter_range(cf, b..=d)), "bcd");
+        assert_eq!(keys(&mut db.iter_range(cf, ..d)), "abc");
+        assert_eq!(keys(&mut db.iter_range(cf, b..)), "bcde");
+        assert_eq!(keys(&mut db.iter_range(cf, ..=b)), "ab");
+        assert_eq!(keys(&mut db.iter_range(cf, (Bound::Excluded(b), Bound::Included(d)))), "cd");
+        assert_eq!(keys(&mut db.iter_range(cf, ..)), "abcde");
+        assert_eq!(keys(&mut db.iter_range(cf, d..b)), "");
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
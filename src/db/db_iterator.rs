@@ -10,6 +10,9 @@ pub trait DBIterator {
     /// 移动到指定 key
     fn seek(&mut self, key: &[u8]);
 
+    /// 移动到 <= key 的最后一个元素
+    fn seek_for_prev(&mut self, key: &[u8]);
+
     /// 是否有效（当前位置是否有值）
     fn valid(&self) -> bool;
 
@@ -22,6 +25,107 @@ pub trait DBIterator {
     /// 向前移动
     fn next(&mut self) -> Result<(),DBError>;
 
-    /// 向后移动（可选）
+    /// 向后移动
     fn prev(&mut self) -> Result<(),DBError>;
+
+    /// 换到当前最新的 Version 和 memtable 上（放掉旧 Version 的 pin），游标停在原来的 user key
+    /// （没了就是它后面那个）。没定位过的 iterator 刷新后还是没定位。
+    fn refresh(&mut self) -> Result<(), DBError> {
+        Ok(())
+    }
+
+    /// 没定位到 key 时区分 “走完了” 和 “出错了”（比如 iterator 过期）；默认不会出错
+    fn status(&self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+/// `DBIterator` 包成 `std::iter::Iterator`：第一次 next 时 seek_to_first，之后每次前进一条；
+/// 底层报错（比如 iterator 过期）交出一个 Err 后结束
+pub struct DbRangeIter {
+    inner: Box<dyn DBIterator>,
+    started: bool,
+    done: bool,
+}
+
+impl DbRangeIter {
+    pub fn new(inner: Box<dyn DBIterator>) -> Self {
+        Self { inner, started: false, done: false }
+    }
+}
+
+impl Iterator for DbRangeIter {
+    type Item = Result<(Vec<u8>, Vec<u8>), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            self.inner.seek_to_first();
+        } else if let Err(e) = self.inner.next() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        match (self.inner.key(), self.inner.value()) {
+            (Some(k), Some(v)) => Some(Ok((k.to_vec(), v.to_vec()))),
+            _ => {
+                self.done = true;
+                // 过期的 iterator key() 也是 None，不能当成走完了
+                self.inner.status().err().map(Err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::vec_iterator::VecDbIterator;
+
+    /// 交出 `live` 条之后就过期的 iterator：和 TrackedIterator 一样，过期后 key() 是 None
+    struct ExpiringIterator {
+        inner: VecDbIterator,
+        live: usize,
+        seen: usize,
+    }
+
+    impl DBIterator for ExpiringIterator {
+        fn seek_to_first(&mut self) { self.inner.seek_to_first() }
+        fn seek_to_last(&mut self) { self.inner.seek_to_last() }
+        fn seek(&mut self, key: &[u8]) { self.inner.seek(key) }
+        fn seek_for_prev(&mut self, key: &[u8]) { self.inner.seek_for_prev(key) }
+        fn valid(&self) -> bool { self.seen < self.live && self.inner.valid() }
+        fn key(&self) -> Option<&[u8]> { if self.valid() { self.inner.key() } else { None } }
+        fn value(&self) -> Option<&[u8]> { if self.valid() { self.inner.value() } else { None } }
+        fn next(&mut self) -> Result<(), DBError> {
+            self.seen += 1;
+            self.inner.next()
+        }
+        fn prev(&mut self) -> Result<(), DBError> { self.inner.prev() }
+        fn status(&self) -> Result<(), DBError> {
+            if self.seen < self.live { Ok(()) } else { Err(DBError::Expired("iterator expired".into())) }
+        }
+    }
+
+    fn entries(n: u8) -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0..n).map(|i| (vec![b'a' + i], vec![i])).collect()
+    }
+
+    #[test]
+    fn range_iter_yields_the_expiry_error_once() {
+        let inner = ExpiringIterator { inner: VecDbIterator::new(entries(3)), live: 1, seen: 0 };
+        let mut iter = DbRangeIter::new(Box::new(inner));
+        assert_eq!(iter.next().unwrap().unwrap(), (b"a".to_vec(), vec![0]));
+        assert!(matches!(iter.next(), Some(Err(DBError::Expired(_)))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn range_iter_ends_cleanly_when_exhausted() {
+        let inner = ExpiringIterator { inner: VecDbIterator::new(entries(2)), live: usize::MAX, seen: 0 };
+        let got: Vec<_> = DbRangeIter::new(Box::new(inner)).collect::<Result<_, _>>().unwrap();
+        assert_eq!(got, entries(2));
+    }
 }
@@ -4,17 +4,34 @@ use crate::db::snapshot::Snapshot;
 use crate::DBError;
 use crate::engine::mem::{ColumnFamilyId, MemTable};
 use crate::engine::wal::write_batch::WriteBatch;
+use crate::util::{ColumnFamilyOptions, ReadOptions, WriteOptions};
 
 pub trait DB: Send + Sync {
-    fn put(&self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<(),DBError>;
+    /// Writes `value` under `key`; `opts` picks whether it goes through the WAL and waits for fsync.
+    fn put(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<(),DBError>;
 
-    fn delete(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<(),DBError>;
+    fn delete(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8]) -> Result<(),DBError>;
 
-    fn write(&self, batch: WriteBatch) -> Result<(),DBError>;
+    /// Writes a merge operand for `key`; the column family must have a merge operator.
+    fn merge(&self, opts: &WriteOptions, cf: ColumnFamilyId, key: &[u8], operand: &[u8]) -> Result<(),DBError>;
 
-    fn get(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>,DBError>;
+    /// Deletes every key in `[begin, end)` with a single range tombstone.
+    fn delete_range(&self, opts: &WriteOptions, cf: ColumnFamilyId, begin: &[u8], end: &[u8]) -> Result<(),DBError>;
 
-    fn new_iterator(&self, cf: ColumnFamilyId) -> Box<dyn DBIterator>;
+    /// Applies `batch` atomically. With `opts.disable_wal` it only reaches the memtables;
+    /// without `opts.sync` it returns once the WAL record is written, before fsync.
+    fn write(&self, opts: &WriteOptions, batch: WriteBatch) -> Result<(),DBError>;
+
+    /// Reads `key`; with `opts.snapshot` set it sees the DB as of that snapshot.
+    fn get(&self, opts: &ReadOptions, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>,DBError>;
+
+    /// Looks up many keys at once, all as of the same `opts.snapshot` (or now);
+    /// values come back in the order of `keys`.
+    fn multi_get(&self, opts: &ReadOptions, cf: ColumnFamilyId, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>,DBError>;
+
+    /// Iterates `cf` as of `opts.snapshot` (or now), limited to
+    /// `[opts.iterate_lower_bound, opts.iterate_upper_bound)`.
+    fn new_iterator(&self, opts: &ReadOptions, cf: ColumnFamilyId) -> Box<dyn DBIterator>;
 
     fn flush(&self, cf: ColumnFamilyId) -> Result<(),DBError>;
 
@@ -30,4 +47,13 @@ pub trait DB: Send + Sync {
     fn release_snapshot(&self, snapshot: Snapshot);
 
     fn flush_memtable(&self, mem: Arc<dyn MemTable>) -> Result<(),DBError>;
+
+    /// Read a named property (see `util::properties`) of a column family.
+    fn get_property(&self, cf: ColumnFamilyId, name: &str) -> Option<String>;
+
+    /// Creates a user column family named `name` and returns its id.
+    fn create_column_family(&self, name: &str, options: ColumnFamilyOptions) -> Result<ColumnFamilyId,DBError>;
+
+    /// Drops the column family named `name` and deletes its SST files.
+    fn drop_column_family(&self, name: &str) -> Result<(),DBError>;
 }
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::DBError;
+use crate::db::write_stall::WriteStallInfo;
+use crate::engine::mem::ColumnFamilyId;
+
+/// Callbacks for DB events; register in `Options::listeners` or with
+/// `DBImpl::add_listener`.
+///
+/// Called on the thread that triggered the event (flush and compaction
+/// callbacks on background threads), with no DB lock held, so implementations
+/// may call back into the DB but should return quickly. Every method has an
+/// empty default.
+pub trait EventListener: Send + Sync {
+    /// A column family's write stall condition changed.
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+
+    /// A memtable is about to be written to `info.file_path`; size and
+    /// timing fields are not filled in yet.
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    /// A flushed SST is installed and readable.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// A compaction's output is installed and its inputs are no longer live.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// A new SST was written, by flush or compaction.
+    fn on_table_file_created(&self, _info: &TableFileCreationInfo) {}
+
+    /// An obsolete SST was deleted from disk.
+    fn on_table_file_deleted(&self, _info: &TableFileDeletionInfo) {}
+
+    /// A background flush or compaction failed. The job is dropped; a later
+    /// flush or compaction of the same column family retries the work.
+    fn on_background_error(&self, _reason: BackgroundErrorReason, _error: &DBError) {}
+}
+
+impl fmt::Debug for dyn EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventListener")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FlushJobInfo {
+    pub cf: ColumnFamilyId,
+    pub file_number: u64,
+    pub file_path: PathBuf,
+    pub file_size: u64,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompactionJobInfo {
+    pub cf: ColumnFamilyId,
+    /// (level, file number) of every input.
+    pub input_files: Vec<(usize, u64)>,
+    pub output_level: usize,
+    pub output_files: Vec<u64>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFileCreationReason {
+    Flush,
+    Compaction,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableFileCreationInfo {
+    pub cf: ColumnFamilyId,
+    pub file_number: u64,
+    pub file_path: PathBuf,
+    pub file_size: u64,
+    pub level: usize,
+    pub reason: TableFileCreationReason,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableFileDeletionInfo {
+    pub file_number: u64,
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundErrorReason {
+    Flush,
+    Compaction,
+}
+
+/// 注册了的 listener；DbConfig 里放一份，flush / compaction / 删文件的地方都拿得到
+#[derive(Default)]
+pub struct EventListeners {
+    listeners: RwLock<Vec<Arc<dyn EventListener>>>,
+}
+
+impl EventListeners {
+    pub fn new(listeners: Vec<Arc<dyn EventListener>>) -> Self {
+        Self { listeners: RwLock::new(listeners) }
+    }
+
+    pub fn add(&self, listener: Arc<dyn EventListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// 对每个 listener 调一次 `f`；先拷一份列表，回调里注册新 listener 不会死锁
+    pub fn notify(&self, f: impl Fn(&dyn EventListener)) {
+        let listeners = self.listeners.read().unwrap().clone();
+        for l in &listeners {
+            f(l.as_ref());
+        }
+    }
+}
+
+impl fmt::Debug for EventListeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventListeners({})", self.listeners.read().unwrap().len())
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::db::db_iterator::DBIterator;
+use crate::db::read_sampler::{ReadSampler, ScanSample};
+use crate::engine::mem::ColumnFamilyId;
+use crate::engine::sst::iterator::DBIterator as EngineIterator;
+use crate::engine::version::VersionRef;
+use crate::error::DBError;
+use crate::util::SliceTransform;
+
+/// An open iterator, as listed by the `vectorkv.oldest-iterators` property.
+#[derive(Debug, Clone)]
+pub struct IteratorInfo {
+    pub cf: ColumnFamilyId,
+    pub created: Instant,
+    /// Where `new_iterator` was called.
+    pub location: &'static Location<'static>,
+}
+
+/// 活着的 iterator：id 按创建顺序递增，所以第一个就是最老的
+#[derive(Default)]
+pub struct IteratorTracker {
+    next_id: AtomicU64,
+    live: Mutex<BTreeMap<u64, IteratorInfo>>,
+}
+
+impl IteratorTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(self: &Arc<Self>, cf: ColumnFamilyId, location: &'static Location<'static>) -> IteratorGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = IteratorInfo { cf, created: Instant::now(), location };
+        self.live.lock().unwrap().insert(id, info.clone());
+        IteratorGuard { tracker: Arc::clone(self), id, info }
+    }
+
+    pub fn count(&self) -> usize {
+        self.live.lock().unwrap().len()
+    }
+
+    /// 最老的 n 个，从老到新
+    pub fn oldest(&self, n: usize) -> Vec<IteratorInfo> {
+        self.live.lock().unwrap().values().take(n).cloned().collect()
+    }
+}
+
+/// iterator drop 时从 tracker 里摘掉
+pub struct IteratorGuard {
+    tracker: Arc<IteratorTracker>,
+    id: u64,
+    info: IteratorInfo,
+}
+
+impl IteratorGuard {
+    /// 同一个 iterator 重新登记，创建时间从现在算
+    fn renew(&self) -> IteratorGuard {
+        self.tracker.register(self.info.cf, self.info.location)
+    }
+}
+
+impl Drop for IteratorGuard {
+    fn drop(&mut self) {
+        self.tracker.live.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// 重新打开 inner iterator，refresh 用
+pub type ReopenIterator = Box<dyn Fn() -> (Box<dyn EngineIterator>, VersionRef)>;
+
+/// `DB::new_iterator` 返回的 iterator：登记在 tracker 里，超过 max_age 后失效
+pub struct TrackedIterator {
+    inner: Box<dyn EngineIterator>,
+    guard: IteratorGuard,
+    max_age: Option<Duration>,
+    /// iterator 读的 Version，drop 之前它的 SST 不会被删
+    _version: Option<VersionRef>,
+    /// `[lower, upper)` 之外的 key 看不见
+    lower: Option<Vec<u8>>,
+    upper: Option<Vec<u8>>,
+    /// `prefix_same_as_start`：seek 之后只看和 target 同前缀的 key
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    /// 上一次 seek 的 target 的前缀；seek_to_first / last 或 target 不在 domain 里时为 None
+    prefix: Option<Vec<u8>>,
+    reopen: Option<ReopenIterator>,
+    /// 开了 `read_sample_rate` 时抽样 seek
+    sampler: Option<Arc<ReadSampler>>,
+    /// 正在采样的这次 scan
+    scan: Option<ScanSample>,
+}
+
+impl TrackedIterator {
+    pub fn new(inner: Box<dyn EngineIterator>, guard: IteratorGuard, max_age: Option<Duration>) -> Self {
+        Self {
+            inner,
+            guard,
+            max_age,
+            _version: None,
+            lower: None,
+            upper: None,
+            prefix_extractor: None,
+            prefix: None,
+            reopen: None,
+            sampler: None,
+            scan: None,
+        }
+    }
+
+    pub fn with_version(mut self, version: VersionRef) -> Self {
+        self._version = Some(version);
+        self
+    }
+
+    pub fn with_bounds(mut self, lower: Option<Vec<u8>>, upper: Option<Vec<u8>>) -> Self {
+        self.lower = lower;
+        self.upper = upper;
+        self
+    }
+
+    pub fn with_reopen(mut self, reopen: ReopenIterator) -> Self {
+        self.reopen = Some(reopen);
+        self
+    }
+
+    pub fn with_prefix_same_as_start(mut self, extractor: Option<Arc<dyn SliceTransform>>) -> Self {
+        self.prefix_extractor = extractor;
+        self
+    }
+
+    pub fn with_read_sampler(mut self, sampler: Option<Arc<ReadSampler>>) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// 上一次采样的 scan 交出去，这次 seek 抽中了就开始新的
+    fn start_scan_sample(&mut self, target: &[u8]) {
+        let Some(sampler) = &self.sampler else { return };
+        if let Some(scan) = self.scan.take() {
+            sampler.record_scan(self.guard.info.cf, scan);
+        }
+        if sampler.should_sample() {
+            self.scan = Some(ScanSample::new(target));
+        }
+    }
+
+    fn visit_scan_sample(&mut self) {
+        if self.scan.is_none() || !self.valid() {
+            return;
+        }
+        if let (Some(scan), Some(k)) = (self.scan.as_mut(), self.inner.key()) {
+            scan.visit(k);
+        }
+    }
+
+    fn in_bounds(&self) -> bool {
+        let Some(k) = self.inner.key() else { return true };
+        self.lower.as_ref().map_or(true, |lower| k >= lower.as_slice())
+            && self.upper.as_ref().map_or(true, |upper| k < upper.as_slice())
+            && self.prefix.as_ref().map_or(true, |prefix| k.starts_with(prefix))
+    }
+
+    fn set_prefix(&mut self, target: Option<&[u8]>) {
+        self.prefix = match (&self.prefix_extractor, target) {
+            (Some(extractor), Some(t)) if extractor.in_domain(t) => Some(extractor.transform(t).to_vec()),
+            _ => None,
+        };
+    }
+
+    fn expired(&self) -> bool {
+        self.max_age.is_some_and(|max| self.guard.info.created.elapsed() > max)
+    }
+
+    fn check(&self) -> Result<(), DBError> {
+        if !self.expired() {
+            return Ok(());
+        }
+        Err(DBError::Expired(format!(
+            "iterator over cf {} created at {} is older than max_iterator_age_secs ({}s)",
+            self.guard.info.cf,
+            self.guard.info.location,
+            self.max_age.unwrap_or_default().as_secs(),
+        )))
+    }
+}
+
+impl DBIterator for TrackedIterator {
+    fn seek_to_first(&mut self) {
+        self.set_prefix(None);
+        match &self.lower {
+            Some(lower) => self.inner.seek(lower),
+            None => self.inner.seek_to_first(),
+        }
+    }
+
+    fn seek_to_last(&mut self) {
+        self.set_prefix(None);
+        match self.upper.clone() {
+            // upper 不含在内
+            Some(upper) => {
+                self.inner.seek_for_prev(&upper);
+                if self.inner.key() == Some(upper.as_slice()) {
+                    self.inner.prev();
+                }
+            }
+            None => self.inner.seek_to_last(),
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.start_scan_sample(key);
+        self.set_prefix(Some(key));
+        match &self.lower {
+            Some(lower) if key < lower.as_slice() => self.inner.seek(lower),
+            _ => self.inner.seek(key),
+        }
+        self.visit_scan_sample();
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        match &self.upper {
+            Some(upper) if key >= upper.as_slice() => self.seek_to_last(),
+            _ => self.inner.seek_for_prev(key),
+        }
+        self.set_prefix(Some(key));
+    }
+
+    fn valid(&self) -> bool {
+        !self.expired() && self.inner.valid() && self.in_bounds()
+    }
+
+    fn key(&self) -> Option<&[u8]> {
+        if self.valid() { self.inner.key() } else { None }
+    }
+
+    fn value(&self) -> Option<&[u8]> {
+        if self.valid() { self.inner.value() } else { None }
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        self.check()?;
+        self.inner.next();
+        self.visit_scan_sample();
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        self.check()?;
+        self.inner.prev();
+        Ok(())
+    }
+
+    fn status(&self) -> Result<(), DBError> {
+        self.check()
+    }
+
+    /// 过期的 iterator 也能 refresh：换了 Version 之后重新计时
+    fn refresh(&mut self) -> Result<(), DBError> {
+        let Some(reopen) = &self.reopen else { return Ok(()) };
+        let position = match self.inner.key() {
+            Some(k) if self.inner.valid() && self.in_bounds() => Some(k.to_vec()),
+            _ => None,
+        };
+        let (inner, version) = reopen();
+        self.inner = inner;
+        // 旧 Version 的 pin 在这里放掉
+        self._version = Some(version);
+        self.guard = self.guard.renew();
+        // 直接 seek inner：prefix_same_as_start 的前缀保持原来 seek 时的
+        if let Some(key) = position {
+            self.inner.seek(&key);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TrackedIterator {
+    fn drop(&mut self) {
+        if let (Some(sampler), Some(scan)) = (&self.sampler, self.scan.take()) {
+            sampler.record_scan(self.guard.info.cf, scan);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
+use crate::DBError;
+use crate::db::db_impl::DBImpl;
+use crate::engine::mem::ColumnFamilyId;
+
+pub type TransactionId = u64;
+
+struct LockHolder {
+    txn: TransactionId,
+    /// 持有者过期的时间点；过了之后别人可以抢，sweeper 也会回收
+    expires_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct LockTable {
+    held: HashMap<(ColumnFamilyId, Vec<u8>), LockHolder>,
+    /// 因过期被回收了锁的事务，commit 时返回 `Expired`
+    expired: HashSet<TransactionId>,
+}
+
+/// 事务的 key 锁
+///
+/// 一个 key 同时只有一个事务持有，同一个事务重复加锁直接成功。等锁最多等 `timeout`，
+/// 超时返回 `Busy`。持有者过期后，等锁的一方直接把它的锁全部回收（相当于回滚它）；
+/// 没人等的过期事务由 sweeper 回收。
+pub struct LockManager {
+    next_txn_id: AtomicU64,
+    table: Mutex<LockTable>,
+    released: Condvar,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self {
+            next_txn_id: AtomicU64::new(1),
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn new_txn_id(&self) -> TransactionId {
+        self.next_txn_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn lock(
+        &self,
+        txn: TransactionId,
+        expires_at: Option<Instant>,
+        cf: ColumnFamilyId,
+        key: &[u8],
+        timeout: Duration,
+    ) -> Result<(), DBError> {
+        let deadline = Instant::now() + timeout;
+        let lock_key = (cf, key.to_vec());
+        let mut table = self.table.lock().unwrap();
+        loop {
+            if table.expired.contains(&txn) {
+                return Err(DBError::Expired(format!("transaction {} expired", txn)));
+            }
+            let now = Instant::now();
+            match table.held.get(&lock_key) {
+                None => {
+                    table.held.insert(lock_key, LockHolder { txn, expires_at });
+                    return Ok(());
+                }
+                Some(h) if h.txn == txn => return Ok(()),
+                Some(h) if h.expires_at.is_some_and(|t| t <= now) => {
+                    let stale = h.txn;
+                    log::info!("transaction {} expired, releasing its locks for transaction {}", stale, txn);
+                    Self::expire(&mut table, stale);
+                    self.released.notify_all();
+                    continue;
+                }
+                Some(h) => {
+                    if now >= deadline {
+                        return Err(DBError::Busy(format!(
+                            "timed out waiting for the lock on key {:?} of cf {}, held by transaction {}",
+                            String::from_utf8_lossy(key),
+                            cf,
+                            h.txn
+                        )));
+                    }
+                    // 持有者过期时也要醒过来抢
+                    let wake = h.expires_at.map_or(deadline, |t| t.min(deadline));
+                    table = self.released.wait_timeout(table, wake - now).unwrap().0;
+                }
+            }
+        }
+    }
+
+    /// 放掉 `txn` 的所有锁，并忘掉它的过期标记（事务结束时调用）
+    pub fn unlock_all(&self, txn: TransactionId) {
+        let mut table = self.table.lock().unwrap();
+        table.held.retain(|_, h| h.txn != txn);
+        table.expired.remove(&txn);
+        drop(table);
+        self.released.notify_all();
+    }
+
+    pub fn is_expired(&self, txn: TransactionId) -> bool {
+        self.table.lock().unwrap().expired.contains(&txn)
+    }
+
+    /// 回收所有已过期事务的锁，返回回收了几个事务
+    pub fn sweep_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut table = self.table.lock().unwrap();
+        let stale: HashSet<TransactionId> = table.held.values()
+            .filter(|h| h.expires_at.is_some_and(|t| t <= now))
+            .map(|h| h.txn)
+            .collect();
+        for &txn in &stale {
+            Self::expire(&mut table, txn);
+        }
+        drop(table);
+        if !stale.is_empty() {
+            self.released.notify_all();
+        }
+        stale.len()
+    }
+
+    /// Number of keys currently locked by transactions.
+    pub fn num_locks(&self) -> usize {
+        self.table.lock().unwrap().held.len()
+    }
+
+    fn expire(table: &mut LockTable, txn: TransactionId) {
+        table.held.retain(|_, h| h.txn != txn);
+        table.expired.insert(txn);
+    }
+}
+
+/// 定期回收过期事务的锁
+pub(crate) fn start_sweeper(db: Weak<DBImpl>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let Some(db) = db.upgrade() else { break };
+        let n = db.lock_manager().sweep_expired();
+        if n > 0 {
+            log::info!("rolled back {} expired transactions", n);
+        }
+    });
+}
@@ -0,0 +1,37 @@
+/// Snapshot of the memory held by a DB, for capacity planning.
+///
+/// All sizes are in bytes and approximate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Arena bytes of the active memtables of all column families.
+    pub active_memtables: usize,
+    /// Arena bytes of memtables that are frozen or being flushed.
+    pub immutable_memtables: usize,
+
+    /// Bytes charged to the block cache.
+    pub block_cache_usage: usize,
+    /// Configured block cache capacity.
+    pub block_cache_capacity: usize,
+    /// Part of `block_cache_usage` held by live iterators/reads (cannot be evicted).
+    pub pinned_blocks: usize,
+
+    /// Number of open table readers in the table cache.
+    pub table_readers: usize,
+    /// Index blocks resident in open table readers.
+    pub index_blocks: usize,
+    /// Filter blocks resident in open table readers.
+    pub filter_blocks: usize,
+}
+
+impl MemoryUsage {
+    /// Total bytes: memtables + block cache + table reader residency.
+    ///
+    /// `pinned_blocks` is already part of `block_cache_usage` and not added again.
+    pub fn total(&self) -> usize {
+        self.active_memtables
+            + self.immutable_memtables
+            + self.block_cache_usage
+            + self.index_blocks
+            + self.filter_blocks
+    }
+}
@@ -1,5 +1,27 @@
 pub mod db_trait;
 pub mod db_impl;
+pub mod fencing;
 mod db_iterator;
 mod vec_iterator;
-mod snapshot;
+pub mod snapshot;
+mod iterator_tracker;
+pub mod quota;
+pub mod memory_usage;
+mod ttl;
+pub mod write_stall;
+mod write_group;
+pub mod auto_tuner;
+pub mod transaction;
+pub mod lock_manager;
+pub mod range_lock;
+mod txn_spill;
+pub mod event_listener;
+pub mod backup;
+#[cfg(feature = "s3")]
+pub mod backup_s3;
+pub mod sst_file_writer;
+pub mod wal_filter;
+pub mod recovery;
+pub mod cf_export;
+pub mod read_sampler;
+pub mod transaction_log;
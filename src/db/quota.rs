@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::engine::mem::ColumnFamilyId;
+use crate::error::DBError;
+use crate::util::QuotaOptions;
+
+/// 令牌桶：每秒补 `rate` 个，最多攒 1 秒的量。
+/// 允许透支（tokens < 0），下次请求要等桶补回正数才放行
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: u64) -> Option<Self> {
+        if rate_per_sec == 0 {
+            return None;
+        }
+        let rate = rate_per_sec as f64;
+        Some(Self {
+            rate,
+            capacity: rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        })
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// 单次请求超过桶容量时按容量判断，否则大请求永远过不去
+    fn can_consume(&mut self, n: u64) -> bool {
+        self.refill();
+        self.tokens >= (n as f64).min(self.capacity)
+    }
+
+    fn consume(&mut self, n: u64) {
+        self.tokens -= n as f64;
+    }
+}
+
+struct CfQuota {
+    /// 建桶时的设置
+    opts: QuotaOptions,
+    write_ops: Option<TokenBucket>,
+    write_bytes: Option<TokenBucket>,
+    read_ops: Option<TokenBucket>,
+    read_bytes: Option<TokenBucket>,
+}
+
+impl CfQuota {
+    fn new(opts: &QuotaOptions) -> Self {
+        Self {
+            opts: opts.clone(),
+            write_ops: TokenBucket::new(opts.write_ops_per_sec),
+            write_bytes: TokenBucket::new(opts.write_bytes_per_sec),
+            read_ops: TokenBucket::new(opts.read_ops_per_sec),
+            read_bytes: TokenBucket::new(opts.read_bytes_per_sec),
+        }
+    }
+}
+
+/// `cf` 的桶；设置和建桶时不一样了（CF 选项改了）就按新设置重建
+fn quota_of(quotas: &mut HashMap<ColumnFamilyId, CfQuota>, cf: ColumnFamilyId, opts: QuotaOptions) -> &mut CfQuota {
+    let q = quotas.entry(cf).or_insert_with(|| CfQuota::new(&opts));
+    if q.opts != opts {
+        *q = CfQuota::new(&opts);
+    }
+    q
+}
+
+fn can_consume(bucket: &mut Option<TokenBucket>, n: u64) -> bool {
+    bucket.as_mut().map_or(true, |b| b.can_consume(n))
+}
+
+fn consume(bucket: &mut Option<TokenBucket>, n: u64) {
+    if let Some(b) = bucket.as_mut() {
+        b.consume(n);
+    }
+}
+
+/// 每个 column family 的读写配额
+///
+/// 超额时返回 `DBError::Busy`，调用方退避后重试
+#[derive(Default)]
+pub struct QuotaManager {
+    quotas: Mutex<HashMap<ColumnFamilyId, CfQuota>>,
+}
+
+impl QuotaManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 一个 batch 对各 CF 的 (ops, bytes) 要么全部扣除，要么一个都不扣
+    ///
+    /// 按 `options_of` 给出的当前设置检查，设置变了桶会重建
+    pub fn acquire_write(
+        &self,
+        usage: &HashMap<ColumnFamilyId, (u64, u64)>,
+        options_of: impl Fn(ColumnFamilyId) -> QuotaOptions,
+    ) -> Result<(), DBError> {
+        let mut quotas = self.quotas.lock().unwrap();
+
+        for (&cf, &(ops, bytes)) in usage {
+            let q = quota_of(&mut quotas, cf, options_of(cf));
+            if !can_consume(&mut q.write_ops, ops) || !can_consume(&mut q.write_bytes, bytes) {
+                return Err(DBError::Busy(format!("write quota exceeded for column family {}", cf)));
+            }
+        }
+
+        for (cf, &(ops, bytes)) in usage {
+            let q = quotas.get_mut(cf).unwrap();
+            consume(&mut q.write_ops, ops);
+            consume(&mut q.write_bytes, bytes);
+        }
+        Ok(())
+    }
+
+    /// 读之前检查：ops 有余量，且 bytes 没有透支
+    pub fn acquire_read(
+        &self,
+        cf: ColumnFamilyId,
+        options_of: impl Fn(ColumnFamilyId) -> QuotaOptions,
+    ) -> Result<(), DBError> {
+        self.acquire_reads(cf, 1, options_of)
+    }
+
+    /// 同 acquire_read，一次扣 `ops` 个读操作（multi_get）
+    pub fn acquire_reads(
+        &self,
+        cf: ColumnFamilyId,
+        ops: u64,
+        options_of: impl Fn(ColumnFamilyId) -> QuotaOptions,
+    ) -> Result<(), DBError> {
+        let mut quotas = self.quotas.lock().unwrap();
+        let q = quota_of(&mut quotas, cf, options_of(cf));
+        if !can_consume(&mut q.read_ops, ops) || !can_consume(&mut q.read_bytes, 0) {
+            return Err(DBError::Busy(format!("read quota exceeded for column family {}", cf)));
+        }
+        consume(&mut q.read_ops, ops);
+        Ok(())
+    }
+
+    /// 读完按实际返回的字节数扣费（可能透支）
+    pub fn charge_read_bytes(&self, cf: ColumnFamilyId, bytes: u64) {
+        if let Some(q) = self.quotas.lock().unwrap().get_mut(&cf) {
+            consume(&mut q.read_bytes, bytes);
+        }
+    }
+
+    /// CF 被 drop 了，丢掉它的桶
+    pub fn remove(&self, cf: ColumnFamilyId) {
+        self.quotas.lock().unwrap().remove(&cf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_quota_rejects_when_exhausted() {
+        let qm = QuotaManager::new();
+        let opts = |_| QuotaOptions { write_ops_per_sec: 2, ..Default::default() };
+        let usage: HashMap<ColumnFamilyId, (u64, u64)> = HashMap::from([(0, (1, 10))]);
+
+        assert!(qm.acquire_write(&usage, opts).is_ok());
+        assert!(qm.acquire_write(&usage, opts).is_ok());
+        let err = qm.acquire_write(&usage, opts).unwrap_err();
+        assert!(err.is_retryable());
+
+        // 其他 CF 不受影响
+        let other: HashMap<ColumnFamilyId, (u64, u64)> = HashMap::from([(1, (1, 10))]);
+        assert!(qm.acquire_write(&other, opts).is_ok());
+    }
+
+    #[test]
+    fn buckets_follow_option_changes_and_drops() {
+        let qm = QuotaManager::new();
+        let limited = |_| QuotaOptions { read_ops_per_sec: 1, ..Default::default() };
+        qm.acquire_read(0, limited).unwrap();
+        assert!(qm.acquire_read(0, limited).is_err());
+
+        // 放开限制后按新设置重建，不再被旧桶卡住
+        qm.acquire_read(0, |_| QuotaOptions::default()).unwrap();
+        qm.acquire_read(0, limited).unwrap();
+        assert!(qm.acquire_read(0, limited).is_err());
+
+        qm.remove(0);
+        assert!(qm.quotas.lock().unwrap().is_empty());
+        qm.acquire_read(0, limited).unwrap();
+    }
+}
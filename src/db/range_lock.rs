@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::DBError;
+use crate::engine::mem::ColumnFamilyId;
+
+/// system CF 里的租约：prefix + cf(BE) + lease id(BE) -> expires_at_ms(BE) + begin_len(BE u32) + begin + end
+pub(crate) const RANGE_LOCK_PREFIX: &[u8] = b"__range_lock__/";
+
+/// An advisory lock on `[begin, end)` of a column family, held until
+/// `expires_at_ms` (unix millis) unless renewed or released.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeLease {
+    pub id: u64,
+    pub cf: ColumnFamilyId,
+    pub begin: Vec<u8>,
+    pub end: Vec<u8>,
+    pub expires_at_ms: u64,
+}
+
+impl RangeLease {
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at_ms <= now_ms
+    }
+
+    pub fn overlaps(&self, cf: ColumnFamilyId, begin: &[u8], end: &[u8]) -> bool {
+        self.cf == cf && self.begin.as_slice() < end && begin < self.end.as_slice()
+    }
+
+    pub fn covers(&self, cf: ColumnFamilyId, key: &[u8]) -> bool {
+        self.cf == cf && self.begin.as_slice() <= key && key < self.end.as_slice()
+    }
+
+    pub(crate) fn record_key(&self) -> Vec<u8> {
+        let mut k = Vec::with_capacity(RANGE_LOCK_PREFIX.len() + 12);
+        k.extend_from_slice(RANGE_LOCK_PREFIX);
+        k.extend_from_slice(&self.cf.to_be_bytes());
+        k.extend_from_slice(&self.id.to_be_bytes());
+        k
+    }
+
+    pub(crate) fn encode_value(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(12 + self.begin.len() + self.end.len());
+        v.extend_from_slice(&self.expires_at_ms.to_be_bytes());
+        v.extend_from_slice(&(self.begin.len() as u32).to_be_bytes());
+        v.extend_from_slice(&self.begin);
+        v.extend_from_slice(&self.end);
+        v
+    }
+
+    pub(crate) fn decode(key: &[u8], value: &[u8]) -> Result<Self, DBError> {
+        let corrupt = || DBError::Corruption(format!("bad range lock record {:?}", String::from_utf8_lossy(key)));
+        let rest = key.strip_prefix(RANGE_LOCK_PREFIX).filter(|r| r.len() == 12).ok_or_else(corrupt)?;
+        if value.len() < 12 {
+            return Err(corrupt());
+        }
+        let begin_len = u32::from_be_bytes(value[8..12].try_into().unwrap()) as usize;
+        if value.len() < 12 + begin_len {
+            return Err(corrupt());
+        }
+        Ok(Self {
+            id: u64::from_be_bytes(rest[4..].try_into().unwrap()),
+            cf: ColumnFamilyId::from_be_bytes(rest[..4].try_into().unwrap()),
+            begin: value[12..12 + begin_len].to_vec(),
+            end: value[12 + begin_len..].to_vec(),
+            expires_at_ms: u64::from_be_bytes(value[..8].try_into().unwrap()),
+        })
+    }
+}
+
+/// 内存里的租约表，和 system CF 里的记录一致；写路径检查用它，不用每次读 system CF
+///
+/// 过期的租约不再挡任何人，下一次 `lock_range` 时才从 system CF 里删掉。
+#[derive(Default)]
+pub struct RangeLockTable {
+    /// lease id -> lease；外层 Mutex 同时串行化 lock / renew / unlock 的读改写
+    leases: Mutex<(u64, HashMap<u64, RangeLease>)>,
+}
+
+impl RangeLockTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 打开 DB 时从 system CF 读回来的租约
+    pub fn load(&self, leases: Vec<RangeLease>) {
+        let mut table = self.leases.lock().unwrap();
+        for lease in leases {
+            table.0 = table.0.max(lease.id + 1);
+            table.1.insert(lease.id, lease);
+        }
+    }
+
+    /// 还没过期的租约
+    pub fn live(&self, cf: Option<ColumnFamilyId>, now_ms: u64) -> Vec<RangeLease> {
+        let table = self.leases.lock().unwrap();
+        let mut leases: Vec<RangeLease> = table.1.values()
+            .filter(|l| !l.is_expired(now_ms) && cf.map_or(true, |cf| l.cf == cf))
+            .cloned()
+            .collect();
+        leases.sort_by_key(|l| l.id);
+        leases
+    }
+
+    /// 在锁住的表上跑 `f`：(下一个 lease id, 所有租约)
+    pub(crate) fn with_table<T>(&self, f: impl FnOnce(&mut u64, &mut HashMap<u64, RangeLease>) -> T) -> T {
+        let mut table = self.leases.lock().unwrap();
+        let (next_id, leases) = &mut *table;
+        f(next_id, leases)
+    }
+
+    /// `[begin, end)` 上别人（不是 `holder`）持有的、没过期的租约
+    pub fn conflict(
+        &self,
+        cf: ColumnFamilyId,
+        begin: &[u8],
+        end: Option<&[u8]>,
+        holder: Option<u64>,
+        now_ms: u64,
+    ) -> Option<RangeLease> {
+        let table = self.leases.lock().unwrap();
+        table.1.values()
+            .find(|l| {
+                Some(l.id) != holder
+                    && !l.is_expired(now_ms)
+                    && match end {
+                        Some(end) => l.overlaps(cf, begin, end),
+                        None => l.covers(cf, begin),
+                    }
+            })
+            .cloned()
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+use crate::engine::mem::ColumnFamilyId;
+use crate::util::ColumnFamilyOptions;
+
+/// 每个 CF 留最近这么多次 scan 的公共前缀长度
+const MAX_SCAN_SAMPLES: usize = 4096;
+/// 样本少于这个数不给建议
+const MIN_SAMPLES: u64 = 100;
+/// 点查里这么多没找到，filter 值得多花内存
+const HIGH_MISS_RATIO: f64 = 0.3;
+const MIN_BLOCK_SIZE: usize = 4 << 10;
+const SCAN_BLOCK_SIZE: usize = 16 << 10;
+const MAX_BLOCK_SIZE: usize = 64 << 10;
+
+/// Filter and block settings suggested from sampled reads of one column
+/// family; see `DBImpl::read_tuning_report`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReadTuningReport {
+    pub sampled_gets: u64,
+    pub sampled_scans: u64,
+    /// Sampled gets that found nothing.
+    pub get_miss_ratio: f64,
+    pub avg_key_size: f64,
+    /// Average size of the values found by sampled gets.
+    pub avg_value_size: f64,
+    /// Keys visited per sampled seek.
+    pub avg_scan_length: f64,
+    /// Longest prefix that every sampled scan stays within, for a fixed-length
+    /// `prefix_extractor` with `prefix_same_as_start`. `None` when scans cross
+    /// prefixes or there are too few samples.
+    pub suggested_prefix_len: Option<usize>,
+    /// Bloom filter bits per key; `None` when there are too few gets.
+    pub suggested_bits_per_key: Option<usize>,
+    pub suggested_block_size: usize,
+    /// Why, and how the suggestions compare to the current options.
+    pub notes: Vec<String>,
+}
+
+#[derive(Default)]
+struct CfSamples {
+    gets: u64,
+    get_misses: u64,
+    get_key_bytes: u64,
+    get_value_bytes: u64,
+    scans: u64,
+    scan_keys: u64,
+    seek_key_bytes: u64,
+    /// 每次 scan 访问到的 key 和 seek target 的最短公共前缀
+    scan_prefixes: VecDeque<usize>,
+}
+
+/// 一次被采样的 seek：跟着 iterator 往后走，下一次 seek 或 iterator 销毁时交给 `ReadSampler`
+pub(crate) struct ScanSample {
+    seek_key: Vec<u8>,
+    common_prefix: usize,
+    keys: u64,
+}
+
+impl ScanSample {
+    pub fn new(seek_key: &[u8]) -> Self {
+        Self { seek_key: seek_key.to_vec(), common_prefix: seek_key.len(), keys: 0 }
+    }
+
+    /// iterator 停在了 `key`
+    pub fn visit(&mut self, key: &[u8]) {
+        let common = self.seek_key.iter().zip(key).take_while(|(a, b)| a == b).count();
+        self.common_prefix = self.common_prefix.min(common);
+        self.keys += 1;
+    }
+}
+
+/// 按 `read_sample_rate` 抽样 get 和 seek，攒出 `ReadTuningReport` 要的统计
+pub struct ReadSampler {
+    rate: u64,
+    counter: AtomicU64,
+    cfs: Mutex<HashMap<ColumnFamilyId, CfSamples>>,
+}
+
+impl ReadSampler {
+    pub fn new(rate: u32) -> Self {
+        Self { rate: rate as u64, counter: AtomicU64::new(0), cfs: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn should_sample(&self) -> bool {
+        self.rate > 0 && self.counter.fetch_add(1, Ordering::Relaxed) % self.rate == 0
+    }
+
+    pub fn record_get(&self, cf: ColumnFamilyId, key: &[u8], value: Option<&[u8]>) {
+        let mut cfs = self.cfs.lock().unwrap();
+        let s = cfs.entry(cf).or_default();
+        s.gets += 1;
+        s.get_key_bytes += key.len() as u64;
+        match value {
+            Some(v) => s.get_value_bytes += v.len() as u64,
+            None => s.get_misses += 1,
+        }
+    }
+
+    pub(crate) fn record_scan(&self, cf: ColumnFamilyId, scan: ScanSample) {
+        let mut cfs = self.cfs.lock().unwrap();
+        let s = cfs.entry(cf).or_default();
+        s.scans += 1;
+        s.scan_keys += scan.keys;
+        s.seek_key_bytes += scan.seek_key.len() as u64;
+        // 一个 key 都没走到的 scan 说明不了前缀
+        if scan.keys > 0 {
+            if s.scan_prefixes.len() == MAX_SCAN_SAMPLES {
+                s.scan_prefixes.pop_front();
+            }
+            s.scan_prefixes.push_back(scan.common_prefix);
+        }
+    }
+
+    pub fn report(&self, cf: ColumnFamilyId, options: &ColumnFamilyOptions) -> ReadTuningReport {
+        let cfs = self.cfs.lock().unwrap();
+        let empty = CfSamples::default();
+        let s = cfs.get(&cf).unwrap_or(&empty);
+        let ratio = |a: u64, b: u64| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+        let hits = s.gets - s.get_misses;
+
+        let mut report = ReadTuningReport {
+            sampled_gets: s.gets,
+            sampled_scans: s.scans,
+            get_miss_ratio: ratio(s.get_misses, s.gets),
+            avg_key_size: ratio(s.get_key_bytes + s.seek_key_bytes, s.gets + s.scans),
+            avg_value_size: ratio(s.get_value_bytes, hits),
+            avg_scan_length: ratio(s.scan_keys, s.scans),
+            ..Default::default()
+        };
+        if self.rate == 0 {
+            report.notes.push("read_sample_rate is 0, nothing is sampled".into());
+        }
+
+        // 前缀：scan 都不会走出去的最长前缀；prefix_same_as_start 下更长的前缀会把 scan 截断
+        if s.scans >= MIN_SAMPLES && !s.scan_prefixes.is_empty() {
+            let shortest = s.scan_prefixes.iter().copied().min().unwrap_or(0);
+            if shortest > 0 {
+                report.suggested_prefix_len = Some(shortest);
+                if options.prefix_extractor.is_none() {
+                    report.notes.push(format!(
+                        "scans stay within their first {} bytes; a fixed {}-byte prefix_extractor with prefix_same_as_start lets them skip SSTs through prefix filters",
+                        shortest, shortest
+                    ));
+                } else {
+                    report.notes.push(format!(
+                        "prefix_extractor is set; prefixes longer than {} bytes cut some sampled scans short", shortest
+                    ));
+                }
+            } else {
+                report.notes.push("sampled scans cross key prefixes, a prefix_extractor would not help them".into());
+            }
+        } else {
+            report.notes.push(format!("{} of {} scans sampled, too few to suggest a prefix length", s.scans, MIN_SAMPLES));
+        }
+
+        // filter：点查没找到的越多，假阳性越贵
+        if s.gets >= MIN_SAMPLES {
+            let bits = if report.get_miss_ratio >= HIGH_MISS_RATIO { 14 } else { 10 };
+            report.suggested_bits_per_key = Some(bits);
+            if options.table_options.filter_policy.is_none() {
+                report.notes.push(format!(
+                    "{:.0}% of sampled gets miss and no filter_policy is set; a {}-bit bloom filter skips most SSTs without the key",
+                    report.get_miss_ratio * 100.0, bits
+                ));
+            }
+        } else {
+            report.notes.push(format!("{} of {} gets sampled, too few to suggest a filter", s.gets, MIN_SAMPLES));
+        }
+
+        // block：scan 为主用大 block，点查为主用小 block；至少放得下几条记录
+        let scanned = s.scan_keys as f64;
+        let base = if scanned > s.gets as f64 { SCAN_BLOCK_SIZE } else { MIN_BLOCK_SIZE };
+        let entry = (report.avg_key_size + report.avg_value_size) as usize;
+        report.suggested_block_size = base.max((entry * 4).next_power_of_two()).min(MAX_BLOCK_SIZE);
+        if report.suggested_block_size != options.table_options.block_size {
+            report.notes.push(format!(
+                "block_size is {}, {} fits the sampled {}",
+                options.table_options.block_size,
+                report.suggested_block_size,
+                if base == SCAN_BLOCK_SIZE { "scan-heavy reads" } else { "point lookups" }
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(sampler: &ReadSampler, seek: &[u8], visited: &[&[u8]]) {
+        let mut sample = ScanSample::new(seek);
+        for key in visited {
+            sample.visit(key);
+        }
+        sampler.record_scan(0, sample);
+    }
+
+    #[test]
+    fn samples_one_read_in_rate() {
+        let sampler = ReadSampler::new(4);
+        assert_eq!((0..8).filter(|_| sampler.should_sample()).count(), 2);
+        let off = ReadSampler::new(0);
+        assert!((0..8).all(|_| !off.should_sample()));
+        assert!(off.report(0, &ColumnFamilyOptions::default()).notes.iter().any(|n| n.contains("read_sample_rate is 0")));
+    }
+
+    #[test]
+    fn scans_within_a_prefix_and_missing_gets_shape_the_suggestions() {
+        let sampler = ReadSampler::new(1);
+        for user in 0..MIN_SAMPLES {
+            let prefix = format!("user:{:02}:", user % 100);
+            let (a, b) = (format!("{}a", prefix), format!("{}b", prefix));
+            scan(&sampler, prefix.as_bytes(), &[a.as_bytes(), b.as_bytes()]);
+        }
+        // 没走到 key 的 scan 不算前缀
+        scan(&sampler, b"u", &[]);
+        for i in 0..MIN_SAMPLES {
+            let value = [0u8; 100];
+            sampler.record_get(0, b"user:00:a", if i % 2 == 0 { None } else { Some(&value) });
+        }
+
+        let report = sampler.report(0, &ColumnFamilyOptions::default());
+        assert_eq!(report.sampled_scans, MIN_SAMPLES + 1);
+        assert_eq!(report.sampled_gets, MIN_SAMPLES);
+        assert_eq!(report.get_miss_ratio, 0.5);
+        assert_eq!(report.avg_value_size, 100.0);
+        assert_eq!(report.suggested_prefix_len, Some(8));
+        assert_eq!(report.suggested_bits_per_key, Some(14));
+        // 走过的 key 比点查多：scan 为主
+        assert_eq!(report.suggested_block_size, SCAN_BLOCK_SIZE);
+        // 别的 CF 没有样本
+        let other = sampler.report(1, &ColumnFamilyOptions::default());
+        assert_eq!((other.suggested_prefix_len, other.suggested_bits_per_key), (None, None));
+    }
+
+    #[test]
+    fn scans_crossing_prefixes_get_no_prefix_suggestion() {
+        let sampler = ReadSampler::new(1);
+        for _ in 0..MIN_SAMPLES {
+            scan(&sampler, b"apple", &[b"apple", b"banana"]);
+        }
+        let report = sampler.report(0, &ColumnFamilyOptions::default());
+        assert_eq!(report.suggested_prefix_len, None);
+        assert!(report.notes.iter().any(|n| n.contains("cross key prefixes")));
+        assert_eq!(report.suggested_bits_per_key, None);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::engine::mem::{ColumnFamilyId, SequenceNumber};
+
+/// What `DBImpl::open` found while loading the MANIFEST and replaying the WAL;
+/// see `DBImpl::recovery_info`.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryInfo {
+    /// The sequence counter as restored from the MANIFEST.
+    pub manifest_sequence: SequenceNumber,
+    /// Highest sequence among the boundary keys of the live SSTs, a lower
+    /// bound of the highest sequence stored in them.
+    pub sst_max_sequence: SequenceNumber,
+    /// Batches up to this sequence were flushed before the last clean close.
+    pub wal_applied_sequence: SequenceNumber,
+    pub batches_replayed: usize,
+    /// Batches skipped as already flushed, or dropped by a `WalFilter`.
+    pub batches_skipped: usize,
+    /// Last sequence of the last replayed batch, 0 if none was replayed.
+    pub last_wal_sequence: SequenceNumber,
+    pub sequence_issues: Vec<SequenceIssue>,
+    /// `repair_sequence_numbers` was set and the issues were fixed.
+    pub repaired: bool,
+}
+
+/// A sequence number range that is out of order, see `RecoveryInfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceIssue {
+    /// A WAL batch starts at or before the last sequence of the batch before
+    /// it, so the two share sequence numbers. Repair moves it right after the
+    /// previous batch.
+    ///
+    /// WALs written before writes started at the first sequence of their
+    /// allocated range (they used the last one) report this for every batch
+    /// with more than one entry; open them once with `repair_sequence_numbers`.
+    WalOverlap {
+        base_seq: SequenceNumber,
+        last_seq: SequenceNumber,
+        previous_last_seq: SequenceNumber,
+    },
+    /// An SST holds a sequence beyond the MANIFEST's counter; new writes
+    /// would reuse it. Repair advances the counter.
+    SstAheadOfManifest {
+        cf: ColumnFamilyId,
+        file_number: u64,
+        sequence: SequenceNumber,
+    },
+    /// The WAL holds sequences beyond the MANIFEST's counter. Repair advances
+    /// the counter.
+    WalAheadOfManifest {
+        last_seq: SequenceNumber,
+    },
+}
+
+impl RecoveryInfo {
+    /// Highest sequence seen in the SSTs and the WAL.
+    pub fn max_sequence(&self) -> SequenceNumber {
+        self.sst_max_sequence.max(self.last_wal_sequence)
+    }
+}
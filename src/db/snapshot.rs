@@ -1,18 +1,171 @@
-use crate::db::db_impl::DBImpl;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::engine::mem::ColumnFamilyId;
+use crate::engine::version::VersionRef;
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Snapshot {
     pub seq: u64,
 }
 
-impl DBImpl {
-    fn get_snapshot(&self) -> Snapshot {
-        Snapshot {
-            seq: self.versions.lock().unwrap().latest_sequence(),
+/// A live snapshot, as listed by the `vectorkv.oldest-iterators` property.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotInfo {
+    pub seq: u64,
+    pub created: Instant,
+    /// Where `get_snapshot` was called.
+    pub location: &'static Location<'static>,
+}
+
+/// 一次 get_snapshot
+struct Registration {
+    created: Instant,
+    location: &'static Location<'static>,
+    /// 拿 snapshot 时各 CF 的 current；snapshot 活着，这些 Version 的 SST 就不会被删
+    versions: HashMap<ColumnFamilyId, VersionRef>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// seq -> 每次 get_snapshot 的创建时间、调用位置和 pin 住的 Version
+    live: BTreeMap<u64, Vec<Registration>>,
+    /// 因为超龄被摘掉、但调用方还没 release 的个数
+    expired: HashMap<u64, usize>,
+}
+
+/// 活着的 snapshot
+///
+/// 同一个 seq 可能被拿多次，release 时去掉最早的那一次
+pub struct SnapshotList {
+    inner: Mutex<Inner>,
+    /// 最老的活 snapshot 的 seq，没有时是 u64::MAX；和 compaction 共享（见 `watermark`）
+    oldest: Arc<AtomicU64>,
+}
+
+impl Default for SnapshotList {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            oldest: Arc::new(AtomicU64::new(u64::MAX)),
+        }
+    }
+}
+
+impl SnapshotList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 跟着 snapshot 的增删更新的最老 seq，compaction 比它老的旧版本 / 墓碑才能丢
+    pub fn watermark(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.oldest)
+    }
+
+    /// 在持有 inner 锁时调用，和 live 的变化保持先后
+    fn update_oldest(&self, inner: &Inner) {
+        let oldest = inner.live.keys().next().copied().unwrap_or(u64::MAX);
+        self.oldest.store(oldest, Ordering::Release);
+    }
+
+    pub fn register(
+        &self,
+        seq: u64,
+        location: &'static Location<'static>,
+        versions: HashMap<ColumnFamilyId, VersionRef>,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.live.entry(seq).or_default().push(Registration {
+            created: Instant::now(),
+            location,
+            versions,
+        });
+        self.update_oldest(&inner);
+    }
+
+    /// `cf` 在 seq 那个 snapshot 拿到时的 Version；过期摘掉或已经 release 的返回 None
+    pub fn version(&self, seq: u64, cf: ColumnFamilyId) -> Option<VersionRef> {
+        let inner = self.inner.lock().unwrap();
+        inner.live.get(&seq)?.iter().find_map(|r| r.versions.get(&cf).cloned())
+    }
+
+    pub fn release(&self, seq: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        // 先抵掉已经过期摘掉的那些
+        if let Some(n) = inner.expired.get_mut(&seq) {
+            *n -= 1;
+            if *n == 0 {
+                inner.expired.remove(&seq);
+            }
+            return;
+        }
+        if let Some(created) = inner.live.get_mut(&seq) {
+            created.remove(0);
+            if created.is_empty() {
+                inner.live.remove(&seq);
+            }
         }
+        self.update_oldest(&inner);
+    }
+
+    /// 摘掉比 max_age 老的 snapshot，它们不再挡住旧版本回收；返回被摘掉的
+    pub fn expire(&self, max_age: Duration) -> Vec<SnapshotInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut expired = Vec::new();
+        for (&seq, created) in inner.live.iter_mut() {
+            // 摘掉时一起放掉 pin 住的 Version
+            created.retain(|r| {
+                let keep = r.created.elapsed() <= max_age;
+                if !keep {
+                    expired.push(SnapshotInfo { seq, created: r.created, location: r.location });
+                }
+                keep
+            });
+        }
+        inner.live.retain(|_, created| !created.is_empty());
+        for s in &expired {
+            *inner.expired.entry(s.seq).or_default() += 1;
+        }
+        self.update_oldest(&inner);
+        expired
+    }
+
+    /// 最老的 n 个，从老到新
+    pub fn oldest(&self, n: usize) -> Vec<SnapshotInfo> {
+        let inner = self.inner.lock().unwrap();
+        let mut all: Vec<SnapshotInfo> = inner
+            .live
+            .iter()
+            .flat_map(|(&seq, created)| {
+                created.iter().map(move |r| SnapshotInfo { seq, created: r.created, location: r.location })
+            })
+            .collect();
+        all.sort_by_key(|s| s.created);
+        all.truncate(n);
+        all
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.lock().unwrap().live.values().map(Vec::len).sum()
+    }
+
+    /// 最老的 snapshot seq；比它新的墓碑 / 旧版本 compaction 才能丢
+    pub fn oldest_seq(&self) -> Option<u64> {
+        self.inner.lock().unwrap().live.keys().next().copied()
     }
 
-    fn release_snapshot(&self, _snapshot: Snapshot) {
-        // Rust 自动 drop，通常只做引用计数回收
+    /// 存活最久的 snapshot 的年龄（和 seq 最小的不一定是同一个）
+    pub fn oldest_age(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .unwrap()
+            .live
+            .values()
+            .flatten()
+            .map(|r| r.created)
+            .min()
+            .map(|created| created.elapsed())
     }
 }
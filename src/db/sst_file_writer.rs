@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::DBError;
+use crate::engine::mem::{InternalKey, ValueType};
+use crate::engine::sst::SstReader;
+use crate::engine::sst::block::BlockCache;
+use crate::engine::sst::iterator::InternalIterator;
+use crate::engine::sst::table_builder::TableBuilder;
+use crate::util::{sync_file, ColumnFamilyOptions, ReadOptions};
+
+/// Settings of one `DBImpl::ingest_external_file` call.
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    /// Hard-link the files into the DB directory and delete the originals once
+    /// they are installed, instead of copying them.
+    pub move_files: bool,
+    /// Files overlapping existing data (or ingested while snapshots are held)
+    /// get a fresh sequence number written into them so they read as newer
+    /// than everything already in the column family. Without it such an
+    /// ingest fails with `InvalidArgument`.
+    pub allow_global_seqno: bool,
+    /// Flush the column family first when unflushed writes overlap the files,
+    /// instead of failing with `InvalidArgument`.
+    pub allow_blocking_flush: bool,
+    /// Read every data block of the files and check its crc32c before
+    /// ingesting. Without it only the blocks read to find the first and last
+    /// key are read, and those unchecked.
+    pub verify_checksums: bool,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            move_files: false,
+            allow_global_seqno: true,
+            allow_blocking_flush: true,
+            verify_checksums: true,
+        }
+    }
+}
+
+impl IngestOptions {
+    pub fn with_move_files(mut self, move_files: bool) -> Self {
+        self.move_files = move_files;
+        self
+    }
+
+    pub fn with_allow_global_seqno(mut self, allow: bool) -> Self {
+        self.allow_global_seqno = allow;
+        self
+    }
+
+    pub fn with_allow_blocking_flush(mut self, allow: bool) -> Self {
+        self.allow_blocking_flush = allow;
+        self
+    }
+
+    pub fn with_verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+}
+
+/// What `SstFileWriter::finish` wrote.
+#[derive(Debug, Clone)]
+pub struct ExternalSstFileInfo {
+    pub path: PathBuf,
+    /// Smallest and largest user key in the file.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub num_entries: u64,
+    pub file_size: u64,
+}
+
+/// Builds an SST outside of any DB, for bulk loading with
+/// `DBImpl::ingest_external_file`.
+///
+/// Keys must be added in strictly increasing order. Every entry is written
+/// with sequence number 0; the ingest assigns the file a global sequence number
+/// when it has to read as newer than data already in the DB. Build the file
+/// with the options of the column family it is ingested into, so filters and
+/// prefix filters match.
+pub struct SstFileWriter {
+    path: PathBuf,
+    builder: TableBuilder<BufWriter<File>>,
+    smallest_key: Option<Vec<u8>>,
+    last_key: Option<Vec<u8>>,
+    num_entries: u64,
+    key_buf: Vec<u8>,
+}
+
+impl SstFileWriter {
+    pub fn create(path: impl Into<PathBuf>, options: &ColumnFamilyOptions) -> Result<Self, DBError> {
+        let path = path.into();
+        let file = File::create(&path)?;
+        // 外部文件还没有 file number，进 DB 时才分配
+        let builder = TableBuilder::from_options(0, BufWriter::new(file), options);
+        Ok(Self {
+            path,
+            builder,
+            smallest_key: None,
+            last_key: None,
+            num_entries: 0,
+            key_buf: Vec::new(),
+        })
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        self.add(key, value, ValueType::Put)
+    }
+
+    /// A tombstone for `key`; hides older versions of it once ingested.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.add(key, &[], ValueType::Delete)
+    }
+
+    /// A merge operand; the target column family needs a merge operator.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<(), DBError> {
+        self.add(key, operand, ValueType::Merge)
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8], value_type: ValueType) -> Result<(), DBError> {
+        if self.last_key.as_deref().is_some_and(|last| key <= last) {
+            return Err(DBError::InvalidKeyOrder(format!(
+                "key {:?} is not greater than the previous key",
+                String::from_utf8_lossy(key)
+            )));
+        }
+        self.key_buf.clear();
+        InternalKey::new(key.to_vec(), 0, value_type).encode_to(&mut self.key_buf);
+        self.builder.add(&self.key_buf, value)?;
+
+        if self.smallest_key.is_none() {
+            self.smallest_key = Some(key.to_vec());
+        }
+        self.last_key = Some(key.to_vec());
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    /// Entries added so far.
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    /// Writes the index, filters and footer and syncs the file. Fails with
+    /// `EmptyTable` when nothing was added.
+    pub fn finish(self) -> Result<ExternalSstFileInfo, DBError> {
+        let meta = self.builder.finish()?;
+        sync_file(&self.path, false)?;
+        Ok(ExternalSstFileInfo {
+            path: self.path,
+            smallest_key: self.smallest_key.unwrap_or_default(),
+            largest_key: self.last_key.unwrap_or_default(),
+            num_entries: self.num_entries,
+            file_size: meta.file_size,
+        })
+    }
+}
+
+/// 一个要 ingest 的外部 SST：首尾两条 internal key
+pub(crate) struct ExternalFile {
+    pub path: PathBuf,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    pub file_size: u64,
+}
+
+impl ExternalFile {
+    /// 打开一遍读出首尾 key；用自己的小 block cache，不占 DB 的
+    ///
+    /// `verify_checksums` 时先把每个 data block 读一遍校验 crc，坏文件在装进 DB 之前就报出来
+    pub fn inspect(path: PathBuf, verify_checksums: bool) -> Result<Self, DBError> {
+        let cache = Arc::new(BlockCache::new(1 << 20, 1));
+        let reader = Arc::new(SstReader::open(0, path.clone(), cache, None)?);
+        if verify_checksums {
+            reader.verify_checksums()?;
+        }
+        let opts = ReadOptions::default().with_verify_checksums(verify_checksums).with_fill_cache(false);
+        let mut it = reader.iter_with_options(&opts);
+        it.seek_to_first();
+        if !it.valid() {
+            return Err(DBError::EmptyTable(format!("external SST {:?} has no entries", path)));
+        }
+        let smallest = it.key().to_vec();
+        it.seek_to_last();
+        let largest = it.key().to_vec();
+        let file_size = std::fs::metadata(&path)?.len();
+        Ok(Self { path, smallest, largest, file_size })
+    }
+
+    pub fn smallest_user_key(&self) -> Result<Vec<u8>, DBError> {
+        Ok(InternalKey::decode(&self.smallest)?.user_key)
+    }
+
+    pub fn largest_user_key(&self) -> Result<Vec<u8>, DBError> {
+        Ok(InternalKey::decode(&self.largest)?.user_key)
+    }
+
+    /// 和 `[smallest, largest]` 有交集（都是 SST 里的原始 key）
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest.as_slice() <= largest && smallest <= self.largest.as_slice()
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use crate::DBError;
+use crate::db::db_impl::DBImpl;
+use crate::db::db_trait::DB;
+use crate::db::lock_manager::TransactionId;
+use crate::db::snapshot::Snapshot;
+use crate::db::txn_spill::SpillFile;
+use crate::engine::mem::ColumnFamilyId;
+use crate::engine::wal::write_batch::{WriteBatch, WriteBatchEntry};
+use crate::util::{ReadOptions, WriteOptions};
+
+/// What the reads inside a transaction see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    /// Every read sees the latest committed data.
+    #[default]
+    ReadCommitted,
+    /// Every read sees the DB as it was when the transaction began, and commit
+    /// fails with `Busy` if a key it read or wrote was changed since.
+    Snapshot,
+}
+
+/// Settings of one transaction.
+#[derive(Debug, Clone)]
+pub struct TransactionOptions {
+    /// How long to wait for a key locked by another transaction before failing with `Busy`.
+    pub lock_timeout: Duration,
+    /// After this long the transaction's locks may be taken away and its commit
+    /// fails with `Expired`. `None` never expires.
+    pub expiration: Option<Duration>,
+    /// Buffered writes beyond this many bytes are moved to a temp file until
+    /// commit. 0 keeps everything in memory.
+    pub spill_threshold_bytes: usize,
+    pub isolation: IsolationLevel,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        Self {
+            lock_timeout: Duration::from_secs(1),
+            expiration: None,
+            spill_threshold_bytes: 64 << 20,
+            isolation: IsolationLevel::ReadCommitted,
+        }
+    }
+}
+
+/// 事务
+///
+/// 写先攒在自己的 batch 里，读能看到自己没提交的写。要写的和 get_for_update 的 key 加锁
+/// （见 `LockManager`），事务之间互斥；同时记下第一次看到的值，commit 时对照当前值，
+/// 被普通写改了就返回 `Busy`，整个事务不生效。
+/// 过期的事务锁会被回收，之后加锁和 commit 都返回 `Expired`。
+///
+/// 攒的写超过 `spill_threshold_bytes` 就整段挪到临时文件（`SpillFile`），commit 时一段段读出来直接写进 WAL 和 memtable
+/// 拼成一个 batch 写，仍然是原子的。
+///
+/// `IsolationLevel::Snapshot` 的事务开始时拿一个 snapshot（登记在 DB 的 snapshot 列表里），
+/// 所有读都读它；track 的值也是 snapshot 时的，commit 时对照当前值，
+/// 所以 snapshot 之后被别人改过的 key 会让 commit 失败。
+pub struct Transaction<'a> {
+    db: &'a DBImpl,
+    id: TransactionId,
+    write_options: WriteOptions,
+    lock_timeout: Duration,
+    expires_at: Option<Instant>,
+    /// 还在内存里的写，接在 spill 之后
+    batch: WriteBatch,
+    batch_bytes: usize,
+    spill_threshold: usize,
+    spill: Option<SpillFile>,
+    /// 按 track 的先后顺序；savepoint 记的是长度
+    tracked: Vec<TrackedKey>,
+    tracked_index: HashSet<(ColumnFamilyId, Vec<u8>)>,
+    savepoints: Vec<SavePoint>,
+    /// snapshot 隔离时事务开始的那个 snapshot，事务结束时还回去
+    snapshot: Option<Snapshot>,
+}
+
+struct TrackedKey {
+    cf: ColumnFamilyId,
+    key: Vec<u8>,
+    /// 第一次读到的值
+    value: Option<Vec<u8>>,
+}
+
+/// `set_savepoint` 时的写条数（含 spill 的）和 tracked 的长度
+struct SavePoint {
+    writes: usize,
+    tracked_len: usize,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a DBImpl, write_options: WriteOptions, txn_options: TransactionOptions) -> Self {
+        let snapshot = match txn_options.isolation {
+            IsolationLevel::ReadCommitted => None,
+            IsolationLevel::Snapshot => Some(db.get_snapshot()),
+        };
+        Self {
+            db,
+            id: db.lock_manager().new_txn_id(),
+            write_options,
+            lock_timeout: txn_options.lock_timeout,
+            expires_at: txn_options.expiration.map(|ttl| Instant::now() + ttl),
+            batch: WriteBatch::new(),
+            batch_bytes: 0,
+            spill_threshold: txn_options.spill_threshold_bytes,
+            spill: None,
+            tracked: Vec::new(),
+            tracked_index: HashSet::new(),
+            savepoints: Vec::new(),
+            snapshot,
+        }
+    }
+
+    /// Reads `key`, seeing this transaction's own uncommitted writes first and
+    /// then the DB as the isolation level allows.
+    pub fn get(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match self.own_write(cf, key)? {
+            Some(v) => Ok(v),
+            None => self.read(cf, key),
+        }
+    }
+
+    /// Like `get`, but locks `key` until the transaction ends, and commit fails
+    /// with `Busy` if a plain write changes it first.
+    pub fn get_for_update(&mut self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.track(cf, key)?;
+        self.get(cf, key)
+    }
+
+    pub fn put(&mut self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        self.track(cf, key)?;
+        self.batch.put(cf, key, value);
+        self.batch_bytes += key.len() + value.len();
+        self.maybe_spill()
+    }
+
+    pub fn delete(&mut self, cf: ColumnFamilyId, key: &[u8]) -> Result<(), DBError> {
+        self.track(cf, key)?;
+        self.batch.delete(cf, key);
+        self.batch_bytes += key.len();
+        self.maybe_spill()
+    }
+
+    /// Remembers the current state; `rollback_to_savepoint` undoes everything after it.
+    /// Savepoints nest.
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push(SavePoint {
+            writes: self.num_writes(),
+            tracked_len: self.tracked.len(),
+        });
+    }
+
+    /// Undoes the writes and tracked reads since the latest savepoint and pops it.
+    ///
+    /// The keys read before the savepoint are then checked again: if one has
+    /// changed the transaction can no longer commit and `Busy` is returned right
+    /// away, so the caller can restart instead of doing more work first.
+    pub fn rollback_to_savepoint(&mut self) -> Result<(), DBError> {
+        let sp = self.savepoints.pop()
+            .ok_or_else(|| DBError::NotFound("no savepoint to roll back to".into()))?;
+        let spilled = self.spill.as_ref().map_or(0, |s| s.entries());
+        if sp.writes >= spilled {
+            self.batch.truncate(sp.writes - spilled);
+        } else if let Some(spill) = self.spill.as_mut() {
+            // savepoint 之后的写有一部分已经落盘了
+            self.batch = spill.truncate_to(sp.writes)?;
+        }
+        self.batch_bytes = self.batch.data_size();
+        for t in self.tracked.drain(sp.tracked_len..) {
+            self.tracked_index.remove(&(t.cf, t.key));
+        }
+        self.validate()
+    }
+
+    /// Drops all writes, tracked keys and savepoints and releases the locks.
+    pub fn rollback(&mut self) {
+        self.batch = WriteBatch::new();
+        self.batch_bytes = 0;
+        self.spill = None;
+        self.tracked.clear();
+        self.tracked_index.clear();
+        self.savepoints.clear();
+        self.db.lock_manager().unlock_all(self.id);
+    }
+
+    /// Checks the tracked keys and applies the writes as one batch; the locks are
+    /// released afterwards either way.
+    pub fn commit(mut self) -> Result<(), DBError> {
+        let _guard = self.db.txn_commit_lock().lock().unwrap();
+        if self.is_expired() {
+            return Err(DBError::Expired(format!("transaction {} expired", self.id)));
+        }
+        self.validate()?;
+        let batch = std::mem::take(&mut self.batch);
+        if let Some(spill) = self.spill.take() {
+            // spill 过的写集一段段读出来直接写，不在内存里拼回整个 batch
+            return self.db.write_spilled(&self.write_options, &spill, batch);
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.db.write(&self.write_options, batch)
+    }
+
+    pub fn id(&self) -> TransactionId {
+        self.id
+    }
+
+    /// Sequence the reads are pinned to under `IsolationLevel::Snapshot`.
+    pub fn snapshot_sequence(&self) -> Option<u64> {
+        self.snapshot.as_ref().map(|s| s.seq)
+    }
+
+    /// Whether the expiration passed; an expired transaction can only be rolled back.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Instant::now()) || self.db.lock_manager().is_expired(self.id)
+    }
+
+    /// Number of writes buffered in this transaction.
+    pub fn num_writes(&self) -> usize {
+        self.spill.as_ref().map_or(0, |s| s.entries()) + self.batch.len()
+    }
+
+    /// Whether part of the write set has been moved to a temp file.
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// 内存里攒的写超过阈值就追加到 spill 文件
+    fn maybe_spill(&mut self) -> Result<(), DBError> {
+        if self.spill_threshold == 0 || self.batch_bytes < self.spill_threshold {
+            return Ok(());
+        }
+        let spill = match self.spill.as_mut() {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::create(self.db.db_config().txn_spill_path(self.id))?),
+        };
+        spill.append(&self.batch)?;
+        self.batch = WriteBatch::new();
+        self.batch_bytes = 0;
+        Ok(())
+    }
+
+    /// 第一次碰到的 key 加锁并记下它当前的值
+    fn track(&mut self, cf: ColumnFamilyId, key: &[u8]) -> Result<(), DBError> {
+        if self.tracked_index.contains(&(cf, key.to_vec())) {
+            return Ok(());
+        }
+        self.db.lock_manager().lock(self.id, self.expires_at, cf, key, self.lock_timeout)?;
+        let value = self.read(cf, key)?;
+        self.tracked_index.insert((cf, key.to_vec()));
+        self.tracked.push(TrackedKey { cf, key: key.to_vec(), value });
+        Ok(())
+    }
+
+    /// 按隔离级别读 DB，不看自己的写
+    fn read(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match &self.snapshot {
+            Some(snapshot) => self.db.get_at_sequence(cf, key, snapshot.seq, &ReadOptions::default()),
+            None => self.db.get_with_options(cf, key, &ReadOptions::default()),
+        }
+    }
+
+    /// 每个 tracked key 的当前值都还是第一次读到的那个
+    fn validate(&self) -> Result<(), DBError> {
+        for t in &self.tracked {
+            let current = self.db.get_with_options(t.cf, &t.key, &ReadOptions::default())?;
+            if current != t.value {
+                return Err(DBError::Busy(format!(
+                    "key {:?} of cf {} was changed by another writer",
+                    String::from_utf8_lossy(&t.key),
+                    t.cf
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// 自己对 key 的最后一次写：Some(None) 是删了，None 是没写过。内存里没有再翻 spill 文件
+    fn own_write(&self, cf: ColumnFamilyId, key: &[u8]) -> Result<Option<Option<Vec<u8>>>, DBError> {
+        if let Some(v) = Self::last_write_in(&self.batch, cf, key) {
+            return Ok(Some(v));
+        }
+        let mut found = None;
+        if let Some(spill) = self.spill.as_ref().filter(|s| s.may_contain(cf, key)) {
+            spill.for_each_chunk(|chunk| {
+                if let Some(v) = Self::last_write_in(&chunk, cf, key) {
+                    found = Some(v);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(found)
+    }
+
+    fn last_write_in(batch: &WriteBatch, cf: ColumnFamilyId, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        // batch 只能正着遍历，取最后一条
+        batch.iter().filter_map(|e| match e {
+            WriteBatchEntry::Put { cf: c, key: k, value } if c == cf && k == key => Some(Some(value)),
+            WriteBatchEntry::Delete { cf: c, key: k } if c == cf && k == key => Some(None),
+            _ => None,
+        }).last().map(|v| v.map(<[u8]>::to_vec))
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.db.lock_manager().unlock_all(self.id);
+        if let Some(snapshot) = self.snapshot.take() {
+            self.db.release_snapshot(snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::util::constants::USER_COLUMN_FAMILY_ID;
+    use crate::util::test_util::open_db;
+
+    const CF: ColumnFamilyId = USER_COLUMN_FAMILY_ID;
+
+    fn open(name: &str) -> (Arc<DBImpl>, PathBuf) {
+        open_db(&format!("txn-{}", name))
+    }
+
+    fn get(db: &DBImpl, key: &[u8]) -> Option<Vec<u8>> {
+        db.get(&ReadOptions::default(), CF, key).unwrap()
+    }
+
+    #[test]
+    fn savepoints_nest() {
+        let (db, dir) = open("savepoints");
+        let mut txn = db.begin_transaction(WriteOptions::default(), TransactionOptions::default());
+
+        txn.put(CF, b"a", b"1").unwrap();
+        txn.set_savepoint();
+        txn.put(CF, b"b", b"1").unwrap();
+        txn.set_savepoint();
+        txn.put(CF, b"c", b"1").unwrap();
+        txn.delete(CF, b"a").unwrap();
+        assert_eq!(txn.get(CF, b"a").unwrap(), None);
+
+        txn.rollback_to_savepoint().unwrap();
+        assert_eq!(txn.num_writes(), 2);
+        assert_eq!(txn.get(CF, b"a").unwrap(), Some(b"1".to_vec()));
+        txn.rollback_to_savepoint().unwrap();
+        assert_eq!(txn.get(CF, b"b").unwrap(), None);
+        assert!(matches!(txn.rollback_to_savepoint(), Err(DBError::NotFound(_))));
+
+        txn.commit().unwrap();
+        assert_eq!(get(&db, b"a"), Some(b"1".to_vec()));
+        assert_eq!(get(&db, b"b"), None);
+        assert_eq!(get(&db, b"c"), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rollback_to_savepoint_reaches_into_the_spill_file() {
+        let (db, dir) = open("savepoint-spill");
+        let txn_options = TransactionOptions { spill_threshold_bytes: 64, ..Default::default() };
+        let mut txn = db.begin_transaction(WriteOptions::default(), txn_options);
+
+        txn.put(CF, b"keep", b"1").unwrap();
+        txn.set_savepoint();
+        for i in 0..20u32 {
+            txn.put(CF, format!("k{:02}", i).as_bytes(), &[b'x'; 16]).unwrap();
+        }
+        assert!(txn.is_spilled());
+        txn.rollback_to_savepoint().unwrap();
+        assert_eq!(txn.num_writes(), 1);
+        assert_eq!(txn.get(CF, b"k00").unwrap(), None);
+
+        txn.commit().unwrap();
+        assert_eq!(get(&db, b"keep"), Some(b"1".to_vec()));
+        assert_eq!(get(&db, b"k00"), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rollback_to_savepoint_fails_fast_when_an_earlier_read_changed() {
+        let (db, dir) = open("savepoint-validate");
+        let w = WriteOptions::default();
+        db.put(&w, CF, b"k", b"v1").unwrap();
+
+        let mut txn = db.begin_transaction(w.clone(), TransactionOptions::default());
+        assert_eq!(txn.get_for_update(CF, b"k").unwrap(), Some(b"v1".to_vec()));
+        txn.set_savepoint();
+        txn.put(CF, b"other", b"1").unwrap();
+        // 普通写不等事务锁
+        db.put(&w, CF, b"k", b"v2").unwrap();
+        assert!(matches!(txn.rollback_to_savepoint(), Err(DBError::Busy(_))));
+        assert!(matches!(txn.commit(), Err(DBError::Busy(_))));
+        assert_eq!(get(&db, b"other"), None);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lock_timeout_and_expiration() {
+        let (db, dir) = open("txn-expiration");
+        let short = TransactionOptions { lock_timeout: Duration::from_millis(10), ..Default::default() };
+
+        let mut holder = db.begin_transaction(WriteOptions::default(), TransactionOptions::default());
+        holder.put(CF, b"k", b"holder").unwrap();
+        let mut waiter = db.begin_transaction(WriteOptions::default(), short.clone());
+        assert!(matches!(waiter.put(CF, b"k", b"waiter"), Err(DBError::Busy(_))));
+        drop(holder);
+        waiter.put(CF, b"k", b"waiter").unwrap();
+        waiter.commit().unwrap();
+
+        // 过期的持有者：锁被后来的人拿走，它自己 commit 失败
+        let expiring = TransactionOptions { expiration: Some(Duration::from_millis(5)), ..Default::default() };
+        let mut stale = db.begin_transaction(WriteOptions::default(), expiring);
+        stale.put(CF, b"k", b"stale").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(stale.is_expired());
+        let mut next = db.begin_transaction(WriteOptions::default(), short);
+        next.put(CF, b"k", b"next").unwrap();
+        next.commit().unwrap();
+        assert!(matches!(stale.commit(), Err(DBError::Expired(_))));
+        assert_eq!(get(&db, b"k"), Some(b"next".to_vec()));
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn isolation_level_decides_what_reads_see() {
+        let (db, dir) = open("txn-isolation");
+        let w = WriteOptions::default();
+        db.put(&w, CF, b"k", b"v1").unwrap();
+
+        let snapshot = TransactionOptions { isolation: IsolationLevel::Snapshot, ..Default::default() };
+        let mut at_start = db.begin_transaction(w.clone(), snapshot);
+        let latest = db.begin_transaction(w.clone(), TransactionOptions::default());
+        assert!(at_start.snapshot_sequence().is_some());
+        assert!(latest.snapshot_sequence().is_none());
+        db.put(&w, CF, b"k", b"v2").unwrap();
+
+        assert_eq!(at_start.get(CF, b"k").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(latest.get(CF, b"k").unwrap(), Some(b"v2".to_vec()));
+
+        // 写的 key 在事务开始后被改过：snapshot 隔离下 commit 失败
+        at_start.put(CF, b"k", b"v3").unwrap();
+        assert!(matches!(at_start.commit(), Err(DBError::Busy(_))));
+        assert_eq!(get(&db, b"k"), Some(b"v2".to_vec()));
+        drop(latest);
+        db.close().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
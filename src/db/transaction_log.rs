@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::DBError;
+use crate::engine::mem::SequenceNumber;
+use crate::engine::wal::{wal_archive, WalReader, WriteBatch};
+use crate::engine::wal::format::BLOCK_SIZE;
+
+/// The write batches in the WAL from a given sequence on, returned by
+/// `DBImpl::get_updates_since`.
+///
+/// Reads the archived WAL segments (when `wal_archive_dir` is set) and then
+/// the live WAL, yielding `(first sequence, batch)` in write order. A batch
+/// that contains the requested sequence is yielded whole. `next` returns
+/// `None` at the end of what has been written so far; calling it again later
+/// picks up the batches written since, continuing from the byte offset it
+/// stopped at in the live WAL instead of re-reading the log.
+pub struct TransactionLogIterator {
+    since: SequenceNumber,
+    archive_dir: Option<PathBuf>,
+    /// 还没读的文件；最后一个是正在写的 WAL
+    files: VecDeque<PathBuf>,
+    live: PathBuf,
+    reader: Option<(WalReader<BufReader<File>>, bool)>,
+    /// 已经交出去的最大 seq，归档和 WAL 重叠时跳过重复的
+    last_seq: SequenceNumber,
+    /// 正在写的 WAL 里最后一条完整 record 之后的位置，下次从这里接着读
+    live_offset: u64,
+    /// 上一次已经读到了 WAL 末尾
+    at_tail: bool,
+}
+
+impl TransactionLogIterator {
+    pub(crate) fn new(
+        since: SequenceNumber,
+        archive_dir: Option<&Path>,
+        live_wal: &Path,
+    ) -> Result<Self, DBError> {
+        let files = Self::files_since(since, archive_dir, live_wal)?;
+        Ok(Self {
+            since,
+            archive_dir: archive_dir.map(Path::to_path_buf),
+            files,
+            live: live_wal.to_path_buf(),
+            reader: None,
+            last_seq: 0,
+            live_offset: 0,
+            at_tail: false,
+        })
+    }
+
+    fn files_since(
+        since: SequenceNumber,
+        archive_dir: Option<&Path>,
+        live_wal: &Path,
+    ) -> Result<VecDeque<PathBuf>, DBError> {
+        let mut files = VecDeque::new();
+        if let Some(dir) = archive_dir.filter(|d| d.exists()) {
+            for segment in wal_archive::archived_segments(dir)? {
+                // 归档文件名是里面最大的 seq，整个在 since 之前的不用读
+                let last = segment.file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.parse::<SequenceNumber>().ok());
+                if last.map_or(true, |last| last >= since) {
+                    files.push_back(segment);
+                }
+            }
+        }
+        files.push_back(live_wal.to_path_buf());
+        Ok(files)
+    }
+
+    /// 上次读到了 WAL 末尾：从记下的位置重新打开。返回 false 表示没有新东西可读
+    fn reopen_live(&mut self) -> Result<bool, DBError> {
+        let len = match std::fs::metadata(&self.live) {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if len == self.live_offset {
+            return Ok(false);
+        }
+        self.at_tail = false;
+        if len < self.live_offset {
+            // WAL 被截断过（内容进了归档）：从归档里 last_seq 之后接着找
+            let since = self.since.max(self.last_seq + 1);
+            self.files = Self::files_since(since, self.archive_dir.as_deref(), &self.live)?;
+            self.live_offset = 0;
+            return Ok(true);
+        }
+        let mut file = File::open(&self.live)?;
+        file.seek(SeekFrom::Start(self.live_offset - self.live_offset % BLOCK_SIZE as u64))?;
+        let reader = WalReader::resume_at(BufReader::new(file), self.live_offset);
+        self.reader = Some((reader, true));
+        Ok(true)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<(SequenceNumber, WriteBatch)>, DBError> {
+        loop {
+            if self.reader.is_none() && self.files.is_empty() {
+                if !self.at_tail || !self.reopen_live()? {
+                    return Ok(None);
+                }
+            }
+            if self.reader.is_none() {
+                let Some(path) = self.files.pop_front() else { return Ok(None) };
+                let is_live = path == self.live;
+                if is_live {
+                    self.live_offset = 0;
+                }
+                let file = match File::open(&path) {
+                    Ok(f) => f,
+                    // 读的时候被 DB 关掉时清理了
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                self.reader = Some((WalReader::new(BufReader::new(file)), is_live));
+            }
+            let (reader, is_live) = self.reader.as_mut().unwrap();
+            let payload = match reader.next_record() {
+                Ok(Some(payload)) => {
+                    if *is_live {
+                        self.live_offset = reader.offset();
+                    }
+                    payload
+                }
+                Ok(None) => {
+                    self.at_tail = *is_live;
+                    self.reader = None;
+                    if self.at_tail {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                // 正在写的 WAL 尾部还没写完的 record，到此为止
+                Err(_) if *is_live && reader.in_fragmented_record() => {
+                    self.at_tail = true;
+                    self.reader = None;
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+            let batch = WriteBatch::from_data(payload)?;
+            if batch.is_empty() {
+                continue;
+            }
+            let base_seq = batch.sequence();
+            let end = base_seq + batch.len() as u64 - 1;
+            if end < self.since || end <= self.last_seq {
+                continue;
+            }
+            self.last_seq = end;
+            return Ok(Some((base_seq, batch)));
+        }
+    }
+}
+
+impl Iterator for TransactionLogIterator {
+    type Item = Result<(SequenceNumber, WriteBatch), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_batch() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => None,
+            Err(e) => {
+                // 出错之后不再往下读
+                self.files.clear();
+                self.reader = None;
+                self.at_tail = false;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::wal::WalManager;
+
+    fn batch(seq: SequenceNumber, keys: &[&[u8]]) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        for k in keys {
+            batch.put(0, k, b"v");
+        }
+        batch.set_sequence(seq);
+        batch
+    }
+
+    fn seqs(log: &mut TransactionLogIterator) -> Vec<SequenceNumber> {
+        log.by_ref().map(|r| r.unwrap().0).collect()
+    }
+
+    #[test]
+    fn reads_archived_segments_then_the_live_wal() {
+        let dir = std::env::temp_dir().join(format!("vectorkv-txn-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (live, archive) = (dir.join("wal.log"), dir.join("archive"));
+        let wal = WalManager::open_with_archive(&live, Some(&archive)).unwrap();
+        wal.append_sync(&batch(1, &[b"a", b"b"])).unwrap();
+        wal.append_sync(&batch(3, &[b"c"])).unwrap();
+        wal.truncate().unwrap();
+        wal.append_sync(&batch(4, &[b"dddd"])).unwrap();
+
+        let open = |since| TransactionLogIterator::new(since, Some(&archive), &live).unwrap();
+        // 包含 since 的 batch 整个交出来
+        assert_eq!(seqs(&mut open(2)), vec![1, 3, 4]);
+        assert_eq!(seqs(&mut open(4)), vec![4]);
+        assert!(seqs(&mut open(5)).is_empty());
+        // 没开归档只能看到 WAL 里剩下的
+        assert_eq!(seqs(&mut TransactionLogIterator::new(1, None, &live).unwrap()), vec![4]);
+
+        // 跟到 WAL 尾之后 WAL 被截断：已经归档的不再交一遍
+        let mut log = open(1);
+        assert_eq!(seqs(&mut log), vec![1, 3, 4]);
+        wal.truncate().unwrap();
+        wal.append_sync(&batch(5, &[b"e"])).unwrap();
+        assert_eq!(seqs(&mut log), vec![5]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Weak;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::db::db_impl::DBImpl;
+use crate::engine::mem::ColumnFamilyId;
+
+/// system CF 里的过期索引：prefix + expiry_ms(BE) + cf(BE) + user_key，按过期时间有序
+pub(crate) const TTL_INDEX_PREFIX: &[u8] = b"__ttl__/";
+
+/// 反向指针：prefix + cf(BE) + user_key -> expiry_ms(BE)，同一个 key 多次 put_with_ttl 时以它为准
+const TTL_KEY_PREFIX: &[u8] = b"__ttl_key__/";
+
+/// 后台 sweeper 每轮最多删多少个 key
+pub(crate) const TTL_SWEEP_BATCH: usize = 1024;
+
+pub(crate) fn index_key(expiry_ms: u64, cf: ColumnFamilyId, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(TTL_INDEX_PREFIX.len() + 12 + key.len());
+    k.extend_from_slice(TTL_INDEX_PREFIX);
+    k.extend_from_slice(&expiry_ms.to_be_bytes());
+    k.extend_from_slice(&cf.to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+/// 过期时间 <= `now_ms` 的索引项都排在这个 key 前面
+pub(crate) fn index_upper_bound(now_ms: u64) -> Vec<u8> {
+    let mut k = TTL_INDEX_PREFIX.to_vec();
+    match now_ms.checked_add(1) {
+        Some(end) => k.extend_from_slice(&end.to_be_bytes()),
+        // 全都过期了：prefix 的下一个 key
+        None => *k.last_mut().unwrap() += 1,
+    }
+    k
+}
+
+/// -> (expiry_ms, cf, user_key)
+pub(crate) fn decode_index_key(k: &[u8]) -> Option<(u64, ColumnFamilyId, &[u8])> {
+    let rest = k.strip_prefix(TTL_INDEX_PREFIX)?;
+    if rest.len() < 12 {
+        return None;
+    }
+    let expiry = u64::from_be_bytes(rest[..8].try_into().unwrap());
+    let cf = ColumnFamilyId::from_be_bytes(rest[8..12].try_into().unwrap());
+    Some((expiry, cf, &rest[12..]))
+}
+
+pub(crate) fn reverse_key(cf: ColumnFamilyId, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(TTL_KEY_PREFIX.len() + 4 + key.len());
+    k.extend_from_slice(TTL_KEY_PREFIX);
+    k.extend_from_slice(&cf.to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 每 `interval` 扫一次过期索引；DB 关闭（Weak 升级失败）后线程退出
+pub(crate) fn start_sweeper(db: Weak<DBImpl>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let Some(db) = db.upgrade() else { break };
+        // 一轮删满了说明还有积压，不睡直接继续
+        loop {
+            match db.sweep_expired(now_ms(), TTL_SWEEP_BATCH) {
+                Ok(n) if n == TTL_SWEEP_BATCH => continue,
+                Ok(_) => break,
+                Err(e) => {
+                    log::warn!("TTL sweep failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
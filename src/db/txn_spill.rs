@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use crate::DBError;
+use crate::engine::mem::ColumnFamilyId;
+use crate::engine::wal::write_batch::{WriteBatch, WriteBatchEntry};
+
+/// 大事务攒不下的写落到这个临时文件里
+///
+/// 一段一段地追加，每段是一个编码过的 `WriteBatch`（4 字节长度前缀）。内存里只留 key，
+/// 读自己的写时用来判断要不要翻文件。事务结束（drop）时删掉。
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    /// 每段的起始 offset 和条数
+    chunks: Vec<(u64, usize)>,
+    len: u64,
+    entries: usize,
+    keys: HashSet<(ColumnFamilyId, Vec<u8>)>,
+}
+
+impl SpillFile {
+    pub fn create(path: PathBuf) -> Result<Self, DBError> {
+        let file = File::create(&path)?;
+        Ok(Self { path, file, chunks: Vec::new(), len: 0, entries: 0, keys: HashSet::new() })
+    }
+
+    /// 落盘的总条数
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    pub fn append(&mut self, batch: &WriteBatch) -> Result<(), DBError> {
+        let payload = batch.data();
+        self.file.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.file.write_all(payload)?;
+        self.chunks.push((self.len, batch.len()));
+        self.len += 4 + payload.len() as u64;
+        self.entries += batch.len();
+        for entry in batch {
+            if let WriteBatchEntry::Put { cf, key, .. } | WriteBatchEntry::Delete { cf, key } = entry {
+                self.keys.insert((cf, key.to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn may_contain(&self, cf: ColumnFamilyId, key: &[u8]) -> bool {
+        self.keys.contains(&(cf, key.to_vec()))
+    }
+
+    /// 按写入顺序逐段读回来
+    pub fn for_each_chunk(&self, mut f: impl FnMut(WriteBatch) -> Result<(), DBError>) -> Result<(), DBError> {
+        let mut r = BufReader::new(File::open(&self.path)?);
+        for _ in 0..self.chunks.len() {
+            let mut len = [0u8; 4];
+            r.read_exact(&mut len)?;
+            let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+            r.read_exact(&mut payload)?;
+            f(WriteBatch::from_data(payload)?)?;
+        }
+        Ok(())
+    }
+
+    /// 只留前 `entries` 条：跨过这个位置的段从文件里截掉，其中还要留下的部分返回给调用方放回内存
+    pub fn truncate_to(&mut self, entries: usize) -> Result<WriteBatch, DBError> {
+        let mut kept = WriteBatch::new();
+        if entries >= self.entries {
+            return Ok(kept);
+        }
+        // 第一个要截掉的段
+        let mut start = 0;
+        let mut idx = 0;
+        while idx < self.chunks.len() && start + self.chunks[idx].1 <= entries {
+            start += self.chunks[idx].1;
+            idx += 1;
+        }
+        let mut n = 0;
+        let mut keys = HashSet::new();
+        self.for_each_chunk(|mut batch| {
+            if n < idx {
+                for entry in &batch {
+                    if let WriteBatchEntry::Put { cf, key, .. } | WriteBatchEntry::Delete { cf, key } = entry {
+                        keys.insert((cf, key.to_vec()));
+                    }
+                }
+            } else if n == idx {
+                batch.truncate(entries - start);
+                kept = batch;
+            }
+            n += 1;
+            Ok(())
+        })?;
+
+        let offset = self.chunks[idx].0;
+        self.file.set_len(offset)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.chunks.truncate(idx);
+        self.len = offset;
+        self.entries = start;
+        self.keys = keys;
+        Ok(kept)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("failed to delete transaction spill file {:?}: {}", self.path, e);
+        }
+    }
+}
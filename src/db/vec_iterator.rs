@@ -39,6 +39,11 @@ impl DBIterator for VecDbIterator {
         }
     }
 
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        let n = self.data.partition_point(|(k, _)| k.as_slice() <= key);
+        self.index = n as isize - 1;
+    }
+
     fn valid(&self) -> bool {
         self.index >= 0 && (self.index as usize) < self.data.len()
     }
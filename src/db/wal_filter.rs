@@ -0,0 +1,48 @@
+use crate::engine::mem::SequenceNumber;
+use crate::engine::wal::write_batch::{WriteBatch, WriteBatchEntry};
+
+/// What recovery does with one WAL record, see `WalFilter`.
+#[derive(Debug, Clone)]
+pub enum WalProcessingOption {
+    /// Apply the record as written.
+    Continue,
+    /// Drop the record.
+    Skip,
+    /// Apply this batch instead. It may not hold more records than the
+    /// original, its records take the original's sequence numbers.
+    Replace(WriteBatch),
+    /// Drop this record and everything after it in the WAL.
+    Stop,
+}
+
+/// Inspects every WAL record replayed by `DBImpl::open_with_wal_filter`, e.g.
+/// to cut a known-bad write out after an application bug.
+///
+/// Records already flushed to SSTs before the crash are not passed in. When
+/// the filter changed anything, the recovered memtables are flushed and the
+/// WAL emptied before the open returns, so the dropped records stay gone on
+/// later opens.
+pub trait WalFilter: Send + Sync {
+    fn name(&self) -> &str {
+        "WalFilter"
+    }
+
+    /// Called with the record's first sequence number and its batch, in WAL order.
+    fn log_record(&self, sequence: SequenceNumber, batch: &WriteBatch) -> WalProcessingOption;
+}
+
+/// `batch` with only the entries `keep` accepts, e.g. to drop one column
+/// family's part of a record: `retain_entries(batch, |e| e.cf() != bad_cf)`.
+pub fn retain_entries(batch: &WriteBatch, mut keep: impl FnMut(&WriteBatchEntry<'_>) -> bool) -> WriteBatch {
+    let mut out = WriteBatch::new();
+    out.set_sequence(batch.sequence());
+    for entry in batch.iter().filter(|e| keep(e)) {
+        match entry {
+            WriteBatchEntry::Put { cf, key, value } => out.put(cf, key, value),
+            WriteBatchEntry::Delete { cf, key } => out.delete(cf, key),
+            WriteBatchEntry::Merge { cf, key, value } => out.merge(cf, key, value),
+            WriteBatchEntry::DeleteRange { cf, begin, end } => out.delete_range(cf, begin, end),
+        }
+    }
+    out
+}
@@ -0,0 +1,267 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex, PoisonError};
+use crate::DBError;
+use crate::engine::wal::write_batch::WriteBatch;
+use crate::util::WriteOptions;
+
+/// 一个写组最多拼这么多数据；单个更大的 batch 自己成一组
+const MAX_GROUP_BYTES: usize = 1 << 20;
+
+/// `DBImpl::write` 的写组（group commit）
+///
+/// 并发的写者把 batch 交进来排队。没有 leader 时，排着的线程里先醒的那个当 leader：
+/// 从队头开始把 batch 拼成一个（至少包含它自己的），分一段连续的 sequence，
+/// 写一条 WAL record、做一次 sync，再一起写进 memtable；其余线程等 leader 把结果交回来。
+///
+/// 写不写 WAL 不同的 batch 不拼在一起；同组里有一个要 sync，整组就 sync。
+pub struct WriteGroup {
+    state: Mutex<GroupState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct GroupState {
+    next_ticket: u64,
+    pending: VecDeque<(u64, WriteBatch, WriteOptions)>,
+    /// 被别的 leader 写完的 ticket -> 整组 commit 的结果
+    finished: HashMap<u64, Result<(), DBError>>,
+    leader_active: bool,
+}
+
+impl WriteGroup {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(GroupState::default()),
+            done: Condvar::new(),
+        }
+    }
+
+    /// 提交 `batch`，它和同组的 batch 一起经 `commit` 写完后返回。
+    ///
+    /// `commit` 只在当 leader 时被调用，拿到的是拼好的整组 batch 和整组的写选项；
+    /// 排在自己前面的 batch 不能和自己拼时会被调用多次。
+    pub fn submit<F>(&self, batch: WriteBatch, opts: &WriteOptions, mut commit: F) -> Result<(), DBError>
+    where
+        F: FnMut(WriteBatch, &WriteOptions) -> Result<(), DBError>,
+    {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push_back((ticket, batch, opts.clone()));
+
+        loop {
+            if let Some(result) = state.finished.remove(&ticket) {
+                return result;
+            }
+            if !state.leader_active {
+                break;
+            }
+            state = self.done.wait(state).unwrap();
+        }
+
+        // 当 leader：从队头一组一组地写，直到写完自己的 batch
+        state.leader_active = true;
+        let mut leader = Leadership { group: self, followers: Vec::new() };
+        loop {
+            let (group, group_opts, mut tickets) = Self::take_group(&mut state, ticket);
+            let mine = tickets.contains(&ticket);
+            tickets.retain(|t| *t != ticket);
+            leader.followers = tickets;
+            drop(state);
+
+            let result = commit(group, &group_opts);
+
+            state = self.state.lock().unwrap();
+            for t in leader.followers.drain(..) {
+                state.finished.insert(t, result.clone());
+            }
+            if mine {
+                // 写完的 follower 返回；还在排队的里面会有一个接着当 leader
+                drop(state);
+                drop(leader);
+                return result;
+            }
+            self.done.notify_all();
+        }
+    }
+
+    /// 不拼组、独占写入顺序跑 `commit`：spill 过的大事务要分段写 WAL 和 memtable，
+    /// 中间不能插进别的写
+    pub fn exclusive<T>(&self, commit: impl FnOnce() -> Result<T, DBError>) -> Result<T, DBError> {
+        let mut state = self.state.lock().unwrap();
+        while state.leader_active {
+            state = self.done.wait(state).unwrap();
+        }
+        state.leader_active = true;
+        drop(state);
+        let _leader = Leadership { group: self, followers: Vec::new() };
+        commit()
+    }
+
+    /// 从队头拿一组能拼在一起的 batch：拿到自己的 batch 且组够大、或者下一个写不写 WAL 不一样时停
+    fn take_group(state: &mut GroupState, ticket: u64) -> (WriteBatch, WriteOptions, Vec<u64>) {
+        let mut group = WriteBatch::new();
+        let mut group_opts: Option<WriteOptions> = None;
+        let mut group_bytes = 0usize;
+        let mut tickets = Vec::new();
+        while let Some((_, _, next_opts)) = state.pending.front() {
+            if let Some(o) = &mut group_opts {
+                if o.disable_wal != next_opts.disable_wal {
+                    break;
+                }
+                o.sync |= next_opts.sync;
+            } else {
+                group_opts = Some(next_opts.clone());
+            }
+            let (t, b, _) = state.pending.pop_front().unwrap();
+            group_bytes += b.data_size();
+            group.append(b);
+            tickets.push(t);
+            if tickets.contains(&ticket) && group_bytes >= MAX_GROUP_BYTES {
+                break;
+            }
+        }
+        (group, group_opts.unwrap_or_default(), tickets)
+    }
+}
+
+/// 当 leader 期间持有，drop 时放掉 leader 并叫醒排队的写者。
+/// `commit` panic 时也会 drop：正在写的那组 follower 拿到错误返回，后面的写者不会一直等下去
+struct Leadership<'a> {
+    group: &'a WriteGroup,
+    /// 正在 commit 的这组里别的写者的 ticket
+    followers: Vec<u64>,
+}
+
+impl Drop for Leadership<'_> {
+    fn drop(&mut self) {
+        let mut state = self.group.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for t in self.followers.drain(..) {
+            state.finished.insert(t, Err(DBError::Other("write group leader panicked during commit".into())));
+        }
+        state.leader_active = false;
+        drop(state);
+        self.group.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    /// 每次 commit 拿到的 (条数, sync, disable_wal)
+    type Commits = Arc<Mutex<Vec<(usize, bool, bool)>>>;
+
+    fn batch(key: &[u8]) -> WriteBatch {
+        let mut b = WriteBatch::new();
+        b.put(0, key, b"v");
+        b
+    }
+
+    fn record(commits: &Commits, fail: bool) -> impl FnMut(WriteBatch, &WriteOptions) -> Result<(), DBError> + '_ {
+        move |b, o| {
+            commits.lock().unwrap().push((b.len(), o.sync, o.disable_wal));
+            if fail { Err(DBError::WriteStall { retry_after_ms: 100, reason: "too many L0 files".into() }) } else { Ok(()) }
+        }
+    }
+
+    /// 先让一个 leader 卡在 commit 里，`others` 全部排进队列后再放行；返回每个写者的结果
+    fn queue_behind_a_leader(others: Vec<(WriteBatch, WriteOptions)>, fail: bool) -> (Vec<(usize, bool, bool)>, Vec<Result<(), DBError>>) {
+        let group = Arc::new(WriteGroup::new());
+        let commits: Commits = Arc::default();
+        let (release, wait) = mpsc::channel::<()>();
+
+        let leader = {
+            let (group, commits) = (Arc::clone(&group), Arc::clone(&commits));
+            thread::spawn(move || {
+                group.submit(batch(b"leader"), &WriteOptions::default(), |b, o| {
+                    wait.recv().unwrap();
+                    record(&commits, false)(b, o)
+                })
+            })
+        };
+        while !group.state.lock().unwrap().leader_active {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // 一个一个排进去，队列里的顺序就是 `others` 的顺序
+        let followers: Vec<_> = others.into_iter().enumerate().map(|(i, (b, o))| {
+            let (g, commits) = (Arc::clone(&group), Arc::clone(&commits));
+            let follower = thread::spawn(move || g.submit(b, &o, record(&commits, fail)));
+            while group.state.lock().unwrap().pending.len() <= i {
+                thread::sleep(Duration::from_millis(1));
+            }
+            follower
+        }).collect();
+        release.send(()).unwrap();
+
+        let mut results = vec![leader.join().unwrap()];
+        results.extend(followers.into_iter().map(|t| t.join().unwrap()));
+        let commits = commits.lock().unwrap().clone();
+        (commits, results)
+    }
+
+    #[test]
+    fn waiting_writers_are_committed_as_one_group() {
+        let sync = WriteOptions { sync: true, ..WriteOptions::default() };
+        let others = vec![
+            (batch(b"a"), WriteOptions::default()),
+            (batch(b"b"), sync),
+            (batch(b"c"), WriteOptions::default()),
+        ];
+        let (commits, results) = queue_behind_a_leader(others, false);
+
+        // 第一组只有 leader 自己；后面三个拼成一组，有一个要 sync 整组就 sync
+        assert_eq!(commits, vec![(1, false, false), (3, true, false)]);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn wal_and_no_wal_writes_are_not_grouped() {
+        let no_wal = WriteOptions { disable_wal: true, ..WriteOptions::default() };
+        let others = vec![
+            (batch(b"a"), WriteOptions::default()),
+            (batch(b"b"), no_wal.clone()),
+            (batch(b"c"), no_wal),
+        ];
+        let (commits, _) = queue_behind_a_leader(others, false);
+        assert_eq!(commits, vec![(1, false, false), (1, false, false), (2, false, true)]);
+    }
+
+    #[test]
+    fn a_failed_group_commit_fails_every_writer_in_it() {
+        let others = vec![
+            (batch(b"a"), WriteOptions::default()),
+            (batch(b"b"), WriteOptions::default()),
+        ];
+        let (commits, results) = queue_behind_a_leader(others, true);
+        assert_eq!(commits.len(), 2);
+        assert!(results[0].is_ok());
+        // follower 拿到的是 commit 原本的错误，还能按提示重试
+        for r in &results[1..] {
+            assert!(matches!(r, Err(DBError::WriteStall { retry_after_ms: 100, .. })), "{:?}", r);
+        }
+    }
+
+    #[test]
+    fn a_panicking_leader_releases_the_group() {
+        let group = Arc::new(WriteGroup::new());
+        let panicked = {
+            let group = Arc::clone(&group);
+            thread::spawn(move || {
+                let _ = group.submit(batch(b"a"), &WriteOptions::default(), |_, _| panic!("commit blew up"));
+            })
+            .join()
+        };
+        assert!(panicked.is_err());
+        assert!(!group.state.lock().unwrap().leader_active);
+
+        // 之后的写照常当 leader，exclusive 也一样
+        group.submit(batch(b"b"), &WriteOptions::default(), |_, _| Ok(())).unwrap();
+        let group2 = Arc::clone(&group);
+        assert!(thread::spawn(move || group2.exclusive(|| -> Result<(), DBError> { panic!("boom") })).join().is_err());
+        assert_eq!(group.exclusive(|| Ok(7)).unwrap(), 7);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::engine::mem::ColumnFamilyId;
+
+/// 写入压力等级，按 CF 计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    /// 还能写，但 flush / compaction 跟不上，客户端应该放慢
+    Delayed,
+    /// 拒绝写入，直到后台追上
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    /// 等待 flush 的 immutable memtable 太多
+    MemtableLimit,
+    /// L0 文件太多
+    L0FileCount,
+}
+
+/// Passed to `EventListener::on_stall_conditions_changed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    pub cf: ColumnFamilyId,
+    pub cause: Option<WriteStallCause>,
+    pub previous: WriteStallCondition,
+    pub current: WriteStallCondition,
+}
+
+/// 每个 CF 当前的 stall 状态；只在状态变化时产出事件
+#[derive(Default)]
+pub struct WriteStallController {
+    conditions: Mutex<HashMap<ColumnFamilyId, WriteStallCondition>>,
+}
+
+impl WriteStallController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记下 cf 的新状态，变化了才返回事件
+    pub fn update(
+        &self,
+        cf: ColumnFamilyId,
+        current: WriteStallCondition,
+        cause: Option<WriteStallCause>,
+    ) -> Option<WriteStallInfo> {
+        let mut conditions = self.conditions.lock().unwrap();
+        let previous = conditions.insert(cf, current).unwrap_or_default();
+        (previous != current).then_some(WriteStallInfo { cf, cause, previous, current })
+    }
+
+    pub fn condition(&self, cf: ColumnFamilyId) -> WriteStallCondition {
+        self.conditions.lock().unwrap().get(&cf).copied().unwrap_or_default()
+    }
+
+    /// 所有 CF 里最严重的
+    pub fn worst(&self) -> WriteStallCondition {
+        self.conditions.lock().unwrap().values().copied().max().unwrap_or_default()
+    }
+}
+
+/// 给客户端的退避建议：Delayed 用 base，Stopped 翻倍，Normal 没有
+pub fn retry_after(condition: WriteStallCondition, base_ms: u64) -> Option<Duration> {
+    match condition {
+        WriteStallCondition::Normal => None,
+        WriteStallCondition::Delayed => Some(Duration::from_millis(base_ms)),
+        WriteStallCondition::Stopped => Some(Duration::from_millis(base_ms.saturating_mul(2))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changes_are_reported() {
+        let controller = WriteStallController::new();
+        assert_eq!(controller.update(1, WriteStallCondition::Normal, None), None);
+
+        let info = controller.update(1, WriteStallCondition::Delayed, Some(WriteStallCause::L0FileCount)).unwrap();
+        assert_eq!((info.previous, info.current), (WriteStallCondition::Normal, WriteStallCondition::Delayed));
+        assert_eq!(controller.update(1, WriteStallCondition::Delayed, Some(WriteStallCause::L0FileCount)), None);
+
+        controller.update(2, WriteStallCondition::Stopped, Some(WriteStallCause::MemtableLimit));
+        assert_eq!(controller.condition(1), WriteStallCondition::Delayed);
+        assert_eq!(controller.condition(3), WriteStallCondition::Normal);
+        assert_eq!(controller.worst(), WriteStallCondition::Stopped);
+
+        let info = controller.update(2, WriteStallCondition::Normal, None).unwrap();
+        assert_eq!(info.previous, WriteStallCondition::Stopped);
+        assert_eq!(controller.worst(), WriteStallCondition::Delayed);
+    }
+
+    #[test]
+    fn stopped_waits_twice_as_long() {
+        assert_eq!(retry_after(WriteStallCondition::Normal, 100), None);
+        assert_eq!(retry_after(WriteStallCondition::Delayed, 100), Some(Duration::from_millis(100)));
+        assert_eq!(retry_after(WriteStallCondition::Stopped, 100), Some(Duration::from_millis(200)));
+    }
+}
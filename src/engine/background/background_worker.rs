@@ -1,55 +1,241 @@
-use std::collections::VecDeque;
-use std::sync::{Arc, Condvar, Mutex};
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread::{self, JoinHandle};
 use crate::{DBImpl, DB};
-use crate::engine::background::FlushMemTableCommand;
+use crate::engine::background::{CompactionCommand, CompactVectorIndexCommand, FlushMemTableCommand, PurgeFilesCommand, RebuildVectorIndexCommand, RepairVectorIndexCommand};
 use crate::engine::background::task::Command;
 use crate::engine::mem::{MemTable, SkipListMemTable};
 use crate::engine::sst::table_builder::TableBuilder;
+use crate::engine::mem::ColumnFamilyId;
+use crate::util::{pin_current_thread, JobQueueOverflow, NumaTopology, Options};
+use crate::vector::HnswIndex;
 
 
+thread_local! {
+    // 后台线程自己提交任务时不等队列空位，否则线程池可能全卡在提交上
+    static IN_BACKGROUND_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
 struct Inner {
-    queue: Mutex<VecDeque<Box<dyn Command>>>,
+    queue: Mutex<Queues>,
     cv: Condvar,
+    /// 队列里腾出了位置，等着提交的线程可以往里放了
+    space: Condvar,
     shutting_down: Mutex<bool>,
+    /// 排队任务数上限，0 不限
+    max_queue_len: usize,
+    overflow: JobQueueOverflow,
+    /// 同时跑的 compaction 总数上限，剩下的线程留给 flush 等任务
+    max_compactions: usize,
+    /// 单个 CF 同时跑的 compaction 上限
+    max_compactions_per_cf: usize,
+}
+
+#[derive(Default)]
+struct Queues {
+    /// flush / purge 等不属于某个 CF compaction 的任务，先到先跑
+    general: VecDeque<Box<dyn Command>>,
+    /// 每个 CF 排着的 compaction
+    compactions: HashMap<ColumnFamilyId, VecDeque<Box<dyn Command>>>,
+    /// 有 compaction 排着的 CF 的轮转顺序；跑过一个就挪到队尾
+    rotation: VecDeque<ColumnFamilyId>,
+    /// 每个 CF 正在跑的 compaction 数
+    running: HashMap<ColumnFamilyId, usize>,
+    /// 因为重复被丢掉的任务数
+    dropped: u64,
+    /// 并进排着的任务里的任务数
+    coalesced: u64,
+}
+
+impl Queues {
+    fn is_empty(&self) -> bool {
+        self.general.is_empty() && self.rotation.is_empty()
+    }
+
+    /// 排着（还没开始跑）的任务数
+    fn len(&self) -> usize {
+        self.general.len() + self.compactions.values().map(VecDeque::len).sum::<usize>()
+    }
+
+    fn queued_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Command>> {
+        self.general.iter_mut().chain(self.compactions.values_mut().flatten())
+    }
+
+    fn contains_duplicate(&mut self, key: &str) -> bool {
+        self.queued_mut().any(|t| t.dedup_key().as_deref() == Some(key))
+    }
+
+    fn coalescible(&mut self, key: (&'static str, ColumnFamilyId)) -> Option<&mut Box<dyn Command>> {
+        self.queued_mut().find(|t| t.coalesce_key() == Some(key))
+    }
+
+    /// 下一个能跑的任务：普通任务优先；compaction 按 CF 轮转，跳过已经跑满的 CF
+    fn pop_runnable(&mut self, max_compactions: usize, max_per_cf: usize) -> Option<(Box<dyn Command>, Option<ColumnFamilyId>)> {
+        if let Some(task) = self.general.pop_front() {
+            return Some((task, None));
+        }
+        if self.running.values().sum::<usize>() >= max_compactions {
+            return None;
+        }
+        let pos = self.rotation.iter().position(|cf| {
+            self.running.get(cf).copied().unwrap_or(0) < max_per_cf
+        })?;
+        let cf = self.rotation.remove(pos).unwrap();
+        let queue = self.compactions.get_mut(&cf).unwrap();
+        let task = queue.pop_front().unwrap();
+        if queue.is_empty() {
+            self.compactions.remove(&cf);
+        } else {
+            self.rotation.push_back(cf);
+        }
+        *self.running.entry(cf).or_insert(0) += 1;
+        Some((task, Some(cf)))
+    }
+
+    fn finish_compaction(&mut self, cf: ColumnFamilyId) {
+        if let Some(n) = self.running.get_mut(&cf) {
+            *n -= 1;
+            if *n == 0 {
+                self.running.remove(&cf);
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn new(max_compactions: usize, max_compactions_per_cf: usize, max_queue_len: usize, overflow: JobQueueOverflow) -> Self {
+        Self {
+            queue: Mutex::new(Queues::default()),
+            cv: Condvar::new(),
+            space: Condvar::new(),
+            shutting_down: Mutex::new(false),
+            max_queue_len,
+            overflow,
+            max_compactions,
+            max_compactions_per_cf,
+        }
+    }
 }
 
+/// 后台线程池
+///
+/// `max_background_flushes + max_background_compactions` 个线程。compaction 按 CF 分队列，
+/// 各 CF 轮流出任务，一个 CF 同时最多跑 `max_compactions_per_cf` 个，所有 CF 加起来最多
+/// `max_background_compactions` 个，所以一个 CF 的长 compaction 占不满全部线程，
+/// 其他 CF 的 compaction 和 flush 不会被饿死。
 pub struct BackgroundWorker {
     inner: Arc<Inner>,
-    handle: Option<JoinHandle<()>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl BackgroundWorker {
-    pub fn new(db: Arc<dyn DB>) -> Self{
-        Self::start()
+    pub fn new(options: &Options) -> Self {
+        let max_compactions = options.max_background_compactions.max(1);
+        let threads = options.max_background_flushes.max(1) + max_compactions;
+        Self::spawn(threads, options.pin_background_threads, Inner::new(
+            max_compactions,
+            options.max_compactions_per_cf.max(1),
+            options.max_background_queue_len,
+            options.background_queue_overflow,
+        ))
     }
-    
-    pub fn start() -> Self {
-        let inner = Arc::new(Inner {
-            queue: Mutex::new(VecDeque::new()),
-            cv: Condvar::new(),
-            shutting_down: Mutex::new(false),
-        });
 
-        let worker_inner = Arc::clone(&inner);
+    /// 队列不限长
+    pub fn start(threads: usize, max_compactions: usize, max_compactions_per_cf: usize) -> Self {
+        Self::spawn(threads, false, Inner::new(max_compactions, max_compactions_per_cf, 0, JobQueueOverflow::Block))
+    }
 
-        let handle = thread::spawn(move || {
-            Self::background_loop(worker_inner);
-        });
+    /// `pin`：第 i 个线程绑到第 i % nodes 个 NUMA 节点的 CPU 上
+    fn spawn(threads: usize, pin: bool, inner: Inner) -> Self {
+        let inner = Arc::new(inner);
+        let topology = NumaTopology::get();
+        let pin = pin && topology.num_nodes() > 1;
+
+        let handles = (0..threads.max(1))
+            .map(|i| {
+                let worker_inner = Arc::clone(&inner);
+                thread::spawn(move || {
+                    if pin {
+                        let node = i % topology.num_nodes();
+                        if let Err(e) = pin_current_thread(topology.cpus(node)) {
+                            log::warn!("cannot pin background thread {} to NUMA node {}: {}", i, node, e);
+                        }
+                    }
+                    Self::background_loop(worker_inner);
+                })
+            })
+            .collect();
 
         Self {
             inner,
-            handle: Some(handle),
+            handles: Mutex::new(handles),
         }
     }
 
+    /// 按 `background_queue_overflow` 先看能不能丢掉 / 并进排着的任务，再等到队列有空位放进去
     pub fn schedule_task(&self, task: Box<dyn Command>) {
         let mut queue = self.inner.queue.lock()
             .unwrap_or_else(|e| e.into_inner());
-        queue.push_back(task);
+        match self.inner.overflow {
+            JobQueueOverflow::Block => {}
+            JobQueueOverflow::DropDuplicates => {
+                if let Some(key) = task.dedup_key() {
+                    if queue.contains_duplicate(&key) {
+                        queue.dropped += 1;
+                        return;
+                    }
+                }
+            }
+            JobQueueOverflow::CoalescePerCf => {
+                if let Some(key) = task.coalesce_key() {
+                    if let Some(queued) = queue.coalescible(key) {
+                        queued.coalesce(task);
+                        queue.coalesced += 1;
+                        return;
+                    }
+                }
+            }
+        }
+
+        if self.inner.max_queue_len > 0 && !IN_BACKGROUND_WORKER.with(Cell::get) {
+            while queue.len() >= self.inner.max_queue_len && !*self.inner.shutting_down.lock().unwrap() {
+                queue = self.inner.space.wait(queue).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+
+        match task.compaction_cf() {
+            Some(cf) => {
+                if !queue.compactions.contains_key(&cf) {
+                    queue.rotation.push_back(cf);
+                }
+                queue.compactions.entry(cf).or_default().push_back(task);
+            }
+            None => queue.general.push_back(task),
+        }
         self.inner.cv.notify_one();
     }
 
+    /// Compactions of `cf` queued or running right now.
+    pub fn pending_compactions(&self, cf: ColumnFamilyId) -> (usize, usize) {
+        let queue = self.inner.queue.lock().unwrap_or_else(|e| e.into_inner());
+        (
+            queue.compactions.get(&cf).map_or(0, VecDeque::len),
+            queue.running.get(&cf).copied().unwrap_or(0),
+        )
+    }
+
+    /// Jobs waiting in the queue (not yet running).
+    pub fn queue_depth(&self) -> usize {
+        self.inner.queue.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Jobs dropped as duplicates and jobs coalesced into a queued one, since open.
+    pub fn shed_jobs(&self) -> (u64, u64) {
+        let queue = self.inner.queue.lock().unwrap_or_else(|e| e.into_inner());
+        (queue.dropped, queue.coalesced)
+    }
+
     pub fn schedule_flush(
         &self,
         db: &Arc<DBImpl>,
@@ -59,21 +245,59 @@ impl BackgroundWorker {
         self.schedule_task(cmd);
     }
 
+    pub fn schedule_purge(&self, db: &Arc<DBImpl>, file_numbers: Vec<u64>) {
+        if file_numbers.is_empty() {
+            return;
+        }
+        self.schedule_task(Box::new(PurgeFilesCommand::new(db, file_numbers)));
+    }
+
+    /// Compaction of `cf` over `[begin, end]`, e.g. after files there were marked for compaction.
+    pub fn schedule_range_compaction(
+        &self,
+        db: &Arc<DBImpl>,
+        cf: ColumnFamilyId,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) {
+        self.schedule_task(Box::new(CompactionCommand::new(db, cf, begin, end)));
+    }
+
+    pub fn schedule_vector_index_compaction(&self, index: &Arc<RwLock<HnswIndex>>) {
+        self.schedule_task(Box::new(CompactVectorIndexCommand::new(index)));
+    }
+
+    pub fn schedule_vector_index_repair(&self, index: &Arc<RwLock<HnswIndex>>) {
+        self.schedule_task(Box::new(RepairVectorIndexCommand::new(index)));
+    }
+
+    pub fn schedule_vector_index_rebuild(&self, db: &Arc<DBImpl>, cf: ColumnFamilyId) {
+        self.schedule_task(Box::new(RebuildVectorIndexCommand::new(db, cf)));
+    }
+
     fn background_loop(inner: Arc<Inner>) {
+        IN_BACKGROUND_WORKER.with(|w| w.set(true));
         loop {
-            let task_opt = {
+            let (cmd, cf) = {
                 let mut queue = inner.queue.lock().unwrap();
-                while queue.is_empty() {
-                    if *inner.shutting_down.lock().unwrap() {
+                loop {
+                    if queue.is_empty() && *inner.shutting_down.lock().unwrap() {
                         return;
                     }
+                    if let Some(next) = queue.pop_runnable(inner.max_compactions, inner.max_compactions_per_cf) {
+                        inner.space.notify_one();
+                        break next;
+                    }
                     queue = inner.cv.wait(queue).unwrap();
                 }
-                queue.pop_front()
             };
 
-            if let Some(cmd) = task_opt {
-                cmd.execute();
+            cmd.execute();
+
+            if let Some(cf) = cf {
+                inner.queue.lock().unwrap().finish_compaction(cf);
+                // 这个 CF 空出了名额，等着的线程可能有活干了
+                inner.cv.notify_all();
             }
         }
     }
@@ -85,11 +309,163 @@ impl BackgroundWorker {
         }
 
         self.inner.cv.notify_all();
+        self.inner.space.notify_all();
 
-        if let Some(handle) = self.handle.as_ref() {
+        for handle in self.handles.lock().unwrap().drain(..) {
             handle.join().unwrap();
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// 占住唯一的后台线程，直到收到信号
+    struct Gate(Mutex<mpsc::Receiver<()>>);
+
+    impl Command for Gate {
+        fn execute(&self) {
+            let _ = self.0.lock().unwrap().recv();
+        }
+    }
+
+    /// 跑的时候记下自己的 id；并进来的任务的 id 接在后面
+    struct Job {
+        cf: ColumnFamilyId,
+        ids: Vec<u32>,
+        ran: Arc<Mutex<Vec<Vec<u32>>>>,
+    }
 
+    impl Command for Job {
+        fn execute(&self) {
+            self.ran.lock().unwrap().push(self.ids.clone());
+        }
+
+        fn dedup_key(&self) -> Option<String> {
+            Some(format!("job:{}:{:?}", self.cf, self.ids))
+        }
+
+        fn coalesce_key(&self) -> Option<(&'static str, ColumnFamilyId)> {
+            Some(("job", self.cf))
+        }
+
+        fn coalesce(&mut self, other: Box<dyn Command>) {
+            let Ok(other) = (other as Box<dyn Any>).downcast::<Self>() else { return };
+            self.ids.extend(other.ids);
+        }
+    }
+
+    /// 一个线程的 worker，线程被 Gate 占着，之后提交的任务都排在队列里
+    fn blocked_worker(max_queue_len: usize, overflow: JobQueueOverflow) -> (BackgroundWorker, mpsc::Sender<()>) {
+        let (tx, rx) = mpsc::channel();
+        let worker = BackgroundWorker::spawn(1, false, Inner::new(1, 1, max_queue_len, overflow));
+        worker.schedule_task(Box::new(Gate(Mutex::new(rx))));
+        while worker.queue_depth() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        (worker, tx)
+    }
+
+    fn job(cf: ColumnFamilyId, id: u32, ran: &Arc<Mutex<Vec<Vec<u32>>>>) -> Box<dyn Command> {
+        Box::new(Job { cf, ids: vec![id], ran: Arc::clone(ran) })
+    }
+
+    #[test]
+    fn duplicates_are_dropped_while_queued() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let (worker, gate) = blocked_worker(0, JobQueueOverflow::DropDuplicates);
+        worker.schedule_task(job(0, 1, &ran));
+        worker.schedule_task(job(0, 1, &ran));
+        worker.schedule_task(job(0, 2, &ran));
+        assert_eq!(worker.queue_depth(), 2);
+        assert_eq!(worker.shed_jobs(), (1, 0));
+
+        gate.send(()).unwrap();
+        worker.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn jobs_of_the_same_cf_are_coalesced() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let (worker, gate) = blocked_worker(0, JobQueueOverflow::CoalescePerCf);
+        worker.schedule_task(job(0, 1, &ran));
+        worker.schedule_task(job(1, 2, &ran));
+        worker.schedule_task(job(0, 3, &ran));
+        assert_eq!(worker.queue_depth(), 2);
+        assert_eq!(worker.shed_jobs(), (0, 1));
+
+        gate.send(()).unwrap();
+        worker.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![vec![1, 3], vec![2]]);
+    }
+
+    #[test]
+    fn full_queue_blocks_the_submitter_until_there_is_room() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let (worker, gate) = blocked_worker(2, JobQueueOverflow::Block);
+        worker.schedule_task(job(0, 1, &ran));
+        worker.schedule_task(job(0, 2, &ran));
+
+        thread::scope(|s| {
+            let submitter = s.spawn(|| worker.schedule_task(job(0, 3, &ran)));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!submitter.is_finished());
+            assert_eq!(worker.queue_depth(), 2);
+            gate.send(()).unwrap();
+            submitter.join().unwrap();
+        });
+        worker.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![vec![1], vec![2], vec![3]]);
+    }
+
+    /// 只用来排队的 compaction 任务
+    struct Compaction(ColumnFamilyId, u32);
+
+    impl Command for Compaction {
+        fn execute(&self) {}
+
+        fn compaction_cf(&self) -> Option<ColumnFamilyId> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn compactions_rotate_over_cfs_within_the_limits() {
+        let (worker, gate) = blocked_worker(0, JobQueueOverflow::Block);
+        for (cf, id) in [(1, 1), (1, 2), (2, 3)] {
+            worker.schedule_task(Box::new(Compaction(cf, id)));
+        }
+        assert_eq!(worker.pending_compactions(1), (2, 0));
+
+        {
+            // 唯一的后台线程还被 Gate 占着，这里直接看出队顺序
+            let mut queue = worker.inner.queue.lock().unwrap();
+            let pop = |queue: &mut Queues, max_compactions, max_per_cf| {
+                queue.pop_runnable(max_compactions, max_per_cf).map(|(task, cf)| {
+                    let task = (task as Box<dyn Any>).downcast::<Compaction>().unwrap();
+                    assert_eq!(cf, Some(task.0));
+                    task.1
+                })
+            };
+            // cf 1 跑过一个就排到队尾，轮到 cf 2
+            assert_eq!(pop(&mut queue, 3, 2), Some(1));
+            assert_eq!(pop(&mut queue, 3, 2), Some(3));
+            // 总数到了上限；总数没满但 cf 1 自己跑满了
+            assert_eq!(pop(&mut queue, 2, 2), None);
+            assert_eq!(pop(&mut queue, 3, 1), None);
+            queue.finish_compaction(1);
+            assert_eq!(pop(&mut queue, 3, 1), Some(2));
+            queue.finish_compaction(1);
+            queue.finish_compaction(2);
+            assert!(queue.is_empty() && queue.running.is_empty());
+        }
+
+        gate.send(()).unwrap();
+        worker.shutdown();
+    }
+}
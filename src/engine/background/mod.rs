@@ -2,4 +2,4 @@ pub mod background_worker;
 mod task;
 
 pub use background_worker::BackgroundWorker;
-pub use task::FlushMemTableCommand;
+pub use task::{CompactionCommand, CompactVectorIndexCommand, FlushMemTableCommand, PurgeFilesCommand, RebuildVectorIndexCommand, RepairVectorIndexCommand};
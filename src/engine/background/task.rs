@@ -1,16 +1,40 @@
-use std::sync::{Arc, Weak, Mutex};
+use std::any::Any;
+use std::sync::{Arc, Weak, Mutex, RwLock};
 use std::collections::VecDeque;
 use crate::{DBImpl, DB};
+use crate::db::event_listener::BackgroundErrorReason;
 use crate::engine::mem::{ColumnFamilyId, MemTable};
+use crate::util::{Span, TraceContext};
+use crate::vector::HnswIndex;
 
 
-pub trait Command: Send + 'static {
+pub trait Command: Send + Any {
     fn execute(&self);
+
+    /// compaction 任务返回它的 CF，BackgroundWorker 按 CF 限并发、轮流调度
+    fn compaction_cf(&self) -> Option<ColumnFamilyId> {
+        None
+    }
+
+    /// `JobQueueOverflow::DropDuplicates`：key 相同的两个任务做的是同一件事
+    fn dedup_key(&self) -> Option<String> {
+        None
+    }
+
+    /// `JobQueueOverflow::CoalescePerCf`：(任务种类, CF) 相同的任务可以合成一个
+    fn coalesce_key(&self) -> Option<(&'static str, ColumnFamilyId)> {
+        None
+    }
+
+    /// 把 coalesce_key 相同的 `other` 并进这个还没开始跑的任务
+    fn coalesce(&mut self, _other: Box<dyn Command>) {}
 }
 
 pub struct FlushMemTableCommand {
     db: Weak<DBImpl>,
     memtables: VecDeque<Arc<dyn MemTable>>,
+    /// 触发这次 flush 的请求的 trace
+    trace: Option<TraceContext>,
 }
 
 impl FlushMemTableCommand {
@@ -18,6 +42,7 @@ impl FlushMemTableCommand {
         Self {
             db: Arc::downgrade(db),
             memtables,
+            trace: TraceContext::current(),
         }
     }
 }
@@ -25,10 +50,133 @@ impl FlushMemTableCommand {
 impl Command for FlushMemTableCommand {
     fn execute(&self) {
         if let Some(db) = self.db.upgrade() {
-            for mem in &self.memtables {
-                if let Err(e) = db.flush_memtable(Arc::clone(mem)) {
-                    eprintln!("Flush error: {:?}", e);
+            TraceContext::scope(self.trace, || {
+                let _span = Span::enter("flush");
+                for mem in &self.memtables {
+                    if let Err(e) = db.flush_memtable(Arc::clone(mem)) {
+                        db.report_background_error(BackgroundErrorReason::Flush, &e);
+                    }
                 }
+            });
+        }
+    }
+
+    fn dedup_key(&self) -> Option<String> {
+        let ptrs: Vec<usize> = self.memtables.iter().map(|m| Arc::as_ptr(m) as *const () as usize).collect();
+        Some(format!("flush:{:?}", ptrs))
+    }
+
+    fn coalesce_key(&self) -> Option<(&'static str, ColumnFamilyId)> {
+        self.memtables.front().map(|m| ("flush", m.cf_id()))
+    }
+
+    fn coalesce(&mut self, other: Box<dyn Command>) {
+        let Ok(other) = (other as Box<dyn Any>).downcast::<Self>() else { return };
+        // 同一个 memtable 只 flush 一次；老的在前，flush 顺序不变
+        for mem in other.memtables {
+            if !self.memtables.iter().any(|m| Arc::ptr_eq(m, &mem)) {
+                self.memtables.push_back(mem);
+            }
+        }
+    }
+}
+
+/// 后台删除不再被任何 Version 引用的 SST（例如被 drop 的 CF 的文件）
+pub struct PurgeFilesCommand {
+    db: Weak<DBImpl>,
+    file_numbers: Vec<u64>,
+}
+
+impl PurgeFilesCommand {
+    pub fn new(db: &Arc<DBImpl>, file_numbers: Vec<u64>) -> Self {
+        Self {
+            db: Arc::downgrade(db),
+            file_numbers,
+        }
+    }
+}
+
+impl Command for PurgeFilesCommand {
+    fn execute(&self) {
+        if let Some(db) = self.db.upgrade() {
+            db.purge_files(&self.file_numbers);
+        }
+    }
+}
+
+/// 用 HNSW 图里还活着的节点重建一张图，丢掉被覆盖 / 删除的节点
+pub struct CompactVectorIndexCommand {
+    index: Weak<RwLock<HnswIndex>>,
+}
+
+impl CompactVectorIndexCommand {
+    pub fn new(index: &Arc<RwLock<HnswIndex>>) -> Self {
+        Self { index: Arc::downgrade(index) }
+    }
+}
+
+impl Command for CompactVectorIndexCommand {
+    fn execute(&self) {
+        let Some(index) = self.index.upgrade() else { return };
+        let (entries, mut rebuilt) = {
+            let mut guard = index.write().unwrap();
+            guard.begin_rebuild();
+            match guard.live_entries() {
+                Ok(entries) => (entries, guard.new_like()),
+                Err(e) => {
+                    guard.abort_rebuild();
+                    log::warn!("vector index compaction failed: {:?}", e);
+                    return;
+                }
+            }
+        };
+        // 建图不持锁，期间的写入由 finish_rebuild 重放
+        for (key, vector) in entries {
+            rebuilt.insert(&key, vector);
+        }
+        if let Err(e) = rebuilt.spill() {
+            log::warn!("vector index stays in memory, spill failed: {:?}", e);
+        }
+        index.write().unwrap().finish_rebuild(rebuilt);
+    }
+}
+
+/// 把墓碑从 HNSW 图里摘掉并修补邻居
+pub struct RepairVectorIndexCommand {
+    index: Weak<RwLock<HnswIndex>>,
+}
+
+impl RepairVectorIndexCommand {
+    pub fn new(index: &Arc<RwLock<HnswIndex>>) -> Self {
+        Self { index: Arc::downgrade(index) }
+    }
+}
+
+impl Command for RepairVectorIndexCommand {
+    fn execute(&self) {
+        if let Some(index) = self.index.upgrade() {
+            index.write().unwrap().repair();
+        }
+    }
+}
+
+/// `optimize_vector_index`：从 CF 的数据全量重建
+pub struct RebuildVectorIndexCommand {
+    db: Weak<DBImpl>,
+    cf: ColumnFamilyId,
+}
+
+impl RebuildVectorIndexCommand {
+    pub fn new(db: &Arc<DBImpl>, cf: ColumnFamilyId) -> Self {
+        Self { db: Arc::downgrade(db), cf }
+    }
+}
+
+impl Command for RebuildVectorIndexCommand {
+    fn execute(&self) {
+        if let Some(db) = self.db.upgrade() {
+            if let Err(e) = db.rebuild_vector_index(self.cf) {
+                log::warn!("vector index rebuild of cf {} failed: {:?}", self.cf, e);
             }
         }
     }
@@ -41,12 +189,49 @@ pub struct CompactionCommand {
     end: Option<Vec<u8>>,
 }
 
+impl CompactionCommand {
+    pub fn new(db: &Arc<DBImpl>, cf: ColumnFamilyId, begin: Option<&[u8]>, end: Option<&[u8]>) -> Self {
+        Self {
+            db: Arc::downgrade(db),
+            cf,
+            begin: begin.map(<[u8]>::to_vec),
+            end: end.map(<[u8]>::to_vec),
+        }
+    }
+}
+
 impl Command for CompactionCommand {
     fn execute(&self) {
         if let Some(db) = self.db.upgrade() {
-            // 调用 DBImpl 的 compaction 内部方法
-            let _ = db.run_compaction(self.cf, self.begin.as_deref(), self.end.as_deref());
+            if let Err(e) = db.compact_range_now(self.cf, self.begin.as_deref(), self.end.as_deref()) {
+                db.report_background_error(BackgroundErrorReason::Compaction, &e);
+            }
         }
     }
+
+    fn compaction_cf(&self) -> Option<ColumnFamilyId> {
+        Some(self.cf)
+    }
+
+    fn dedup_key(&self) -> Option<String> {
+        Some(format!("compaction:{}:{:?}:{:?}", self.cf, self.begin, self.end))
+    }
+
+    fn coalesce_key(&self) -> Option<(&'static str, ColumnFamilyId)> {
+        Some(("compaction", self.cf))
+    }
+
+    fn coalesce(&mut self, other: Box<dyn Command>) {
+        let Ok(other) = (other as Box<dyn Any>).downcast::<Self>() else { return };
+        // 两个范围的并集；有一边不设界就不设界
+        self.begin = match (self.begin.take(), other.begin) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            _ => None,
+        };
+        self.end = match (self.end.take(), other.end) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+    }
 }
 
@@ -0,0 +1,286 @@
+//! Block access tracing for offline cache simulation.
+//!
+//! While a trace is active every data block lookup appends one line
+//! `timestamp_us,file_number,offset,size,caller,hit` to the trace file.
+//! `read_trace` parses it back and `simulate` replays it against a cache of a
+//! given capacity and eviction policy, so cache sizing can be evaluated
+//! without touching the live DB (see `src/bin/cache_sim.rs`).
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::DBError;
+
+/// Which read path touched the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockAccessCaller {
+    Get,
+    Iterator,
+    Compaction,
+}
+
+impl BlockAccessCaller {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            BlockAccessCaller::Get => "get",
+            BlockAccessCaller::Iterator => "iterator",
+            BlockAccessCaller::Compaction => "compaction",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "get" => Some(BlockAccessCaller::Get),
+            "iterator" => Some(BlockAccessCaller::Iterator),
+            "compaction" => Some(BlockAccessCaller::Compaction),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockAccessRecord {
+    pub timestamp_us: u64,
+    pub file_number: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub caller: BlockAccessCaller,
+    pub cache_hit: bool,
+}
+
+impl BlockAccessRecord {
+    fn encode(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}\n",
+            self.timestamp_us,
+            self.file_number,
+            self.offset,
+            self.size,
+            self.caller.as_str(),
+            self.cache_hit as u8
+        )
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end().split(',');
+        let record = Self {
+            timestamp_us: parts.next()?.parse().ok()?,
+            file_number: parts.next()?.parse().ok()?,
+            offset: parts.next()?.parse().ok()?,
+            size: parts.next()?.parse().ok()?,
+            caller: BlockAccessCaller::parse(parts.next()?)?,
+            cache_hit: parts.next()? == "1",
+        };
+        parts.next().is_none().then_some(record)
+    }
+}
+
+/// Shared by the TableCache and every SstReader it opens; inactive by default.
+#[derive(Default)]
+pub struct BlockTracer {
+    active: AtomicBool,
+    out: Mutex<Option<BufWriter<File>>>,
+}
+
+impl BlockTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start writing to `path`, replacing any trace already running.
+    pub fn start(&self, path: &Path) -> Result<(), DBError> {
+        let file = File::create(path)?;
+        *self.out.lock().unwrap() = Some(BufWriter::new(file));
+        self.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Stop tracing and flush the file.
+    pub fn stop(&self) -> Result<(), DBError> {
+        self.active.store(false, Ordering::Release);
+        if let Some(mut out) = self.out.lock().unwrap().take() {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, file_number: u64, offset: u64, size: u64, caller: BlockAccessCaller, cache_hit: bool) {
+        if !self.is_active() {
+            return;
+        }
+        let record = BlockAccessRecord {
+            timestamp_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0),
+            file_number,
+            offset,
+            size,
+            caller,
+            cache_hit,
+        };
+        let mut out = self.out.lock().unwrap();
+        if let Some(w) = out.as_mut() {
+            if let Err(e) = w.write_all(record.encode().as_bytes()) {
+                // 写不进去就停掉，不影响读路径
+                log::warn!("block trace write failed, stopping trace: {}", e);
+                *out = None;
+                self.active.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Parse a trace file; a torn last line is ignored.
+pub fn read_trace(path: &Path) -> Result<Vec<BlockAccessRecord>, DBError> {
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        match BlockAccessRecord::decode(&line?) {
+            Some(r) => records.push(r),
+            None => break,
+        }
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    Lru,
+    Fifo,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulationResult {
+    pub accesses: u64,
+    pub hits: u64,
+    /// 每个 caller 的 (accesses, hits)
+    pub by_caller: HashMap<BlockAccessCaller, (u64, u64)>,
+}
+
+impl SimulationResult {
+    pub fn hit_ratio(&self) -> f64 {
+        if self.accesses == 0 { 0.0 } else { self.hits as f64 / self.accesses as f64 }
+    }
+}
+
+/// Replay `records` against a cache of `capacity` bytes.
+///
+/// Compaction reads are counted but never inserted, matching a cache that
+/// doesn't fill on compaction.
+pub fn simulate(records: &[BlockAccessRecord], capacity: u64, policy: CachePolicy) -> SimulationResult {
+    let mut result = SimulationResult::default();
+    // key -> (size, 最近一次访问的 tick)
+    let mut cached: HashMap<(u64, u64), (u64, u64)> = HashMap::new();
+    // 淘汰顺序；LRU 下有过期项，出队时和 cached 里的 tick 对一下
+    let mut queue: VecDeque<((u64, u64), u64)> = VecDeque::new();
+    let mut usage = 0u64;
+
+    for (tick, r) in records.iter().enumerate() {
+        let tick = tick as u64;
+        let key = (r.file_number, r.offset);
+        let hit = cached.contains_key(&key);
+
+        result.accesses += 1;
+        let per_caller = result.by_caller.entry(r.caller).or_default();
+        per_caller.0 += 1;
+        if hit {
+            result.hits += 1;
+            per_caller.1 += 1;
+            if policy == CachePolicy::Lru {
+                cached.get_mut(&key).unwrap().1 = tick;
+                queue.push_back((key, tick));
+            }
+            continue;
+        }
+        if r.caller == BlockAccessCaller::Compaction || r.size > capacity {
+            continue;
+        }
+
+        while usage + r.size > capacity {
+            let Some((victim, stamp)) = queue.pop_front() else { break };
+            if let Some(&(size, last)) = cached.get(&victim) {
+                if last == stamp {
+                    cached.remove(&victim);
+                    usage -= size;
+                }
+            }
+        }
+        cached.insert(key, (r.size, tick));
+        queue.push_back((key, tick));
+        usage += r.size;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(file_number: u64, offset: u64, size: u64, caller: BlockAccessCaller) -> BlockAccessRecord {
+        BlockAccessRecord { timestamp_us: 0, file_number, offset, size, caller, cache_hit: false }
+    }
+
+    #[test]
+    fn trace_round_trips_through_the_file() {
+        let path = std::env::temp_dir().join(format!("vectorkv-block-trace-{}", std::process::id()));
+        let tracer = BlockTracer::new();
+        // 没开着时不记
+        tracer.record(1, 0, 10, BlockAccessCaller::Get, false);
+
+        tracer.start(&path).unwrap();
+        tracer.record(1, 0, 4096, BlockAccessCaller::Get, false);
+        tracer.record(1, 0, 4096, BlockAccessCaller::Iterator, true);
+        tracer.record(2, 8192, 100, BlockAccessCaller::Compaction, false);
+        tracer.stop().unwrap();
+        tracer.record(3, 0, 10, BlockAccessCaller::Get, false);
+
+        // 末尾写了一半的一行忽略
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(b"17,4,0");
+        std::fs::write(&path, bytes).unwrap();
+
+        let records = read_trace(&path).unwrap();
+        let seen: Vec<_> = records.iter().map(|r| (r.file_number, r.offset, r.size, r.caller, r.cache_hit)).collect();
+        assert_eq!(seen, vec![
+            (1, 0, 4096, BlockAccessCaller::Get, false),
+            (1, 0, 4096, BlockAccessCaller::Iterator, true),
+            (2, 8192, 100, BlockAccessCaller::Compaction, false),
+        ]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn simulate_lru_and_fifo() {
+        use BlockAccessCaller::*;
+        // 容量 2 个 block：a b a c a
+        let trace = vec![
+            access(1, 0, 10, Get),
+            access(1, 10, 10, Get),
+            access(1, 0, 10, Get),
+            access(1, 20, 10, Get),
+            access(1, 0, 10, Iterator),
+        ];
+        let lru = simulate(&trace, 20, CachePolicy::Lru);
+        assert_eq!((lru.accesses, lru.hits), (5, 2));
+        assert_eq!(lru.by_caller[&Iterator], (1, 1));
+
+        // FIFO 里 c 进来挤掉的是最早进来的 a
+        let fifo = simulate(&trace, 20, CachePolicy::Fifo);
+        assert_eq!((fifo.accesses, fifo.hits), (5, 1));
+        assert_eq!(fifo.hit_ratio(), 0.2);
+
+        // compaction 读不进 cache
+        let compaction = vec![access(2, 0, 10, Compaction), access(2, 0, 10, Get)];
+        assert_eq!(simulate(&compaction, 100, CachePolicy::Lru).hits, 0);
+        assert_eq!(SimulationResult::default().hit_ratio(), 0.0);
+    }
+}
@@ -0,0 +1,294 @@
+//! Golden fixtures for the on-disk formats (SST, WAL, MANIFEST).
+//!
+//! `write_golden_fixtures` encodes a fixed set of inputs with the current
+//! writers; the resulting files are checked in. `verify_layout` re-encodes the
+//! same inputs and compares byte for byte, then decodes the checked-in files
+//! with the current readers, so both "we still write the old layout" and
+//! "we can still read files written by an older release" are covered.
+//!
+//! The fixtures live in `testdata/format`. After an intentional format change,
+//! regenerate them with `cargo test regenerate_golden_fixtures -- --ignored`
+//! and check in the new files together with the change.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use crate::error::DBError;
+use crate::engine::mem::{InternalKey, ValueType};
+use crate::engine::mem::memtable_set::CfType;
+use crate::engine::sst::block::{BlockCache, IndexType};
+use crate::engine::sst::format::{ChecksumType, CURRENT_FORMAT_VERSION};
+use crate::engine::sst::table_builder::TableBuilder;
+use crate::engine::sst::SstReader;
+use crate::engine::version::VersionEdit;
+use crate::engine::wal::{decode_write_batch, encode_write_batch, WalReader, WalWriter, WriteBatch};
+use crate::util::{ColumnFamilyOptions, CompressionType};
+
+pub const GOLDEN_SST: &str = "golden.sst";
+pub const GOLDEN_WAL: &str = "golden.log";
+pub const GOLDEN_MANIFEST: &str = "golden.manifest";
+
+const GOLDEN_BASE_SEQ: u64 = 100;
+const GOLDEN_SST_ENTRIES: u64 = 64;
+
+/// One fixture that didn't match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutMismatch {
+    pub fixture: &'static str,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct LayoutReport {
+    pub checked: Vec<&'static str>,
+    pub mismatches: Vec<LayoutMismatch>,
+}
+
+impl LayoutReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    fn fail(&mut self, fixture: &'static str, reason: String) {
+        self.mismatches.push(LayoutMismatch { fixture, reason });
+    }
+}
+
+/// Encode the golden inputs with the current writers into `dir`.
+pub fn write_golden_fixtures(dir: &Path) -> Result<(), DBError> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(GOLDEN_SST), golden_sst()?)?;
+    fs::write(dir.join(GOLDEN_WAL), golden_wal()?)?;
+    fs::write(dir.join(GOLDEN_MANIFEST), golden_manifest()?)?;
+    Ok(())
+}
+
+/// Compare the current encoders and decoders against the fixtures in `dir`.
+///
+/// IO errors are returned as `Err`; format differences are collected in the report.
+pub fn verify_layout(dir: &Path) -> Result<LayoutReport, DBError> {
+    let mut report = LayoutReport::default();
+
+    // 1) 写出来的字节和 fixture 完全一致
+    let fixtures: [(&'static str, Vec<u8>); 3] = [
+        (GOLDEN_SST, golden_sst()?),
+        (GOLDEN_WAL, golden_wal()?),
+        (GOLDEN_MANIFEST, golden_manifest()?),
+    ];
+    for (name, encoded) in &fixtures {
+        let on_disk = fs::read(dir.join(name))?;
+        report.checked.push(name);
+        if let Some(reason) = compare_bytes(&on_disk, encoded) {
+            report.fail(name, reason);
+        }
+    }
+
+    // 2) 当前 reader 能读旧 fixture
+    if let Err(e) = check_sst_readable(&dir.join(GOLDEN_SST)) {
+        report.fail(GOLDEN_SST, format!("decode failed: {:?}", e));
+    }
+    if let Err(e) = check_wal_readable(&fs::read(dir.join(GOLDEN_WAL))?) {
+        report.fail(GOLDEN_WAL, format!("decode failed: {:?}", e));
+    }
+    if let Err(e) = check_manifest_readable(&fs::read(dir.join(GOLDEN_MANIFEST))?) {
+        report.fail(GOLDEN_MANIFEST, format!("decode failed: {:?}", e));
+    }
+
+    Ok(report)
+}
+
+fn compare_bytes(expected: &[u8], actual: &[u8]) -> Option<String> {
+    if let Some(pos) = expected.iter().zip(actual).position(|(a, b)| a != b) {
+        return Some(format!(
+            "first difference at byte {}: expected {:#04x}, got {:#04x}",
+            pos, expected[pos], actual[pos]
+        ));
+    }
+    if expected.len() != actual.len() {
+        return Some(format!("length differs: expected {}, got {}", expected.len(), actual.len()));
+    }
+    None
+}
+
+fn golden_key(i: u64) -> Vec<u8> {
+    let mut k = Vec::new();
+    InternalKey::new(format!("key{:05}", i).into_bytes(), GOLDEN_BASE_SEQ + i, ValueType::Put)
+        .encode_to(&mut k);
+    k
+}
+
+fn golden_value(i: u64) -> Vec<u8> {
+    format!("value{:05}", i).into_bytes()
+}
+
+/// 显式固定所有会影响布局的选项，默认值变化不应该让 fixture 漂移
+fn golden_cf_options() -> ColumnFamilyOptions {
+    let mut opts = ColumnFamilyOptions::default();
+    opts.compression = CompressionType::NoCompression;
+    opts.table_options.block_size = 256;
+    opts.table_options.restart_interval = 4;
+    opts.table_options.filter_policy = None;
+    opts.table_options.format_version = CURRENT_FORMAT_VERSION;
+    opts.table_options.checksum = ChecksumType::Crc32c;
+    opts.table_options.index_block_restart_interval = 4;
+    opts.table_options.index_type = IndexType::BinarySearchWithFirstKey;
+    opts
+}
+
+fn golden_sst() -> Result<Vec<u8>, DBError> {
+    let mut buf = Vec::new();
+    let mut builder = TableBuilder::from_options(1, &mut buf, &golden_cf_options());
+    for i in 0..GOLDEN_SST_ENTRIES {
+        builder.add(&golden_key(i), &golden_value(i))?;
+    }
+    builder.finish()?;
+    Ok(buf)
+}
+
+fn golden_batch() -> WriteBatch {
+    let mut batch = WriteBatch::new();
+    batch.put(0, b"alpha", b"1");
+    batch.put(1, b"beta", b"22");
+    batch.delete(0, b"gamma");
+    batch.put(0, b"delta", &[0xAB; 40]);
+    batch
+}
+
+fn golden_wal() -> Result<Vec<u8>, DBError> {
+    let mut w = WalWriter::new(Vec::new());
+    w.append(&encode_write_batch(GOLDEN_BASE_SEQ, &golden_batch()))?;
+    // 跨 block 的大 record，覆盖 FIRST/MIDDLE/LAST 分片
+    let mut big = WriteBatch::new();
+    big.put(0, b"big", &vec![0x5A; 70 * 1024]);
+    w.append(&encode_write_batch(GOLDEN_BASE_SEQ + 4, &big))?;
+    Ok(w.into_inner())
+}
+
+fn golden_edits() -> Vec<VersionEdit> {
+    let mut add_cf = VersionEdit::new(2, CfType::User);
+    add_cf.is_cf_add = true;
+    add_cf.cf_name = Some("golden_cf".to_string());
+
+    let mut files = VersionEdit::new(2, CfType::User);
+    files.add_file(0, 7, 4096, &golden_key(0), &golden_key(9));
+    files.add_file(1, 8, 8192, &golden_key(10), &golden_key(19));
+    files.delete_file(0, 5);
+    files.next_file_number = Some(9);
+    files.last_sequence = Some(GOLDEN_BASE_SEQ + 20);
+
+    let mut drop_cf = VersionEdit::new(2, CfType::User);
+    drop_cf.is_cf_drop = true;
+
+    vec![add_cf, files, drop_cf]
+}
+
+fn golden_manifest() -> Result<Vec<u8>, DBError> {
+    let mut w = WalWriter::new(Vec::new());
+    for edit in golden_edits() {
+        w.append(&VersionEdit::encode_version_edit(&edit))?;
+    }
+    Ok(w.into_inner())
+}
+
+fn check_sst_readable(path: &Path) -> Result<(), DBError> {
+    let cache = Arc::new(BlockCache::new(1 << 20, 1));
+    let reader = SstReader::open(1, path.to_path_buf(), cache, None)?;
+    for i in 0..GOLDEN_SST_ENTRIES {
+        let got = reader.get(&golden_key(i))?;
+        if got.as_deref() != Some(golden_value(i).as_slice()) {
+            return Err(DBError::Corruption(format!("entry {} mismatch: {:?}", i, got)));
+        }
+    }
+    Ok(())
+}
+
+fn check_wal_readable(bytes: &[u8]) -> Result<(), DBError> {
+    let mut reader = WalReader::new(bytes);
+    let first = reader.next_record()?
+        .ok_or(DBError::Corruption("missing first WAL record".into()))?;
+    let (seq, batch) = decode_write_batch(&first)?;
+    if seq != GOLDEN_BASE_SEQ || !same_entries(&batch, &golden_batch()) {
+        return Err(DBError::Corruption("first WAL batch differs".into()));
+    }
+    let second = reader.next_record()?
+        .ok_or(DBError::Corruption("missing fragmented WAL record".into()))?;
+    let (seq, batch) = decode_write_batch(&second)?;
+    if seq != GOLDEN_BASE_SEQ + 4 || batch.len() != 1 {
+        return Err(DBError::Corruption("fragmented WAL batch differs".into()));
+    }
+    Ok(())
+}
+
+fn same_entries(a: &WriteBatch, b: &WriteBatch) -> bool {
+    a.len() == b.len() && a.iter().eq(b.iter())
+}
+
+fn check_manifest_readable(bytes: &[u8]) -> Result<(), DBError> {
+    let mut reader = WalReader::new(bytes);
+    for expected in golden_edits() {
+        let rec = reader.next_record()?
+            .ok_or(DBError::Corruption("missing MANIFEST record".into()))?;
+        let edit = VersionEdit::decode_version_edit(&rec)?;
+        let same = edit.cf_id == expected.cf_id
+            && edit.is_cf_add == expected.is_cf_add
+            && edit.is_cf_drop == expected.is_cf_drop
+            && edit.cf_name == expected.cf_name
+            && edit.delete_files == expected.delete_files
+            && edit.next_file_number == expected.next_file_number
+            && edit.last_sequence == expected.last_sequence
+            && edit.add_files.len() == expected.add_files.len()
+            && edit.add_files.iter().zip(&expected.add_files).all(|((l1, f1), (l2, f2))| {
+                l1 == l2
+                    && f1.file_number == f2.file_number
+                    && f1.file_size == f2.file_size
+                    && f1.smallest_key == f2.smallest_key
+                    && f1.largest_key == f2.largest_key
+            });
+        if !same {
+            return Err(DBError::Corruption(format!("MANIFEST edit for cf {} differs", expected.cf_id)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/format")
+    }
+
+    #[test]
+    fn checked_in_fixtures_match_current_layout() {
+        let report = verify_layout(&fixture_dir()).unwrap();
+        assert_eq!(report.checked, vec![GOLDEN_SST, GOLDEN_WAL, GOLDEN_MANIFEST]);
+        assert!(report.is_ok(), "on-disk format changed: {:?}", report.mismatches);
+    }
+
+    #[test]
+    fn verify_layout_reports_a_changed_byte() {
+        let dir = std::env::temp_dir().join(format!("vectorkv-golden-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write_golden_fixtures(&dir).unwrap();
+        assert!(verify_layout(&dir).unwrap().is_ok());
+
+        let path = dir.join(GOLDEN_WAL);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[20] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let report = verify_layout(&dir).unwrap();
+        assert!(!report.is_ok());
+        assert!(report.mismatches.iter().all(|m| m.fixture == GOLDEN_WAL));
+        assert!(report.mismatches[0].reason.starts_with("first difference at byte 20"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// 有意改格式时重新生成 fixture
+    #[test]
+    #[ignore]
+    fn regenerate_golden_fixtures() {
+        write_golden_fixtures(&fixture_dir()).unwrap();
+    }
+}
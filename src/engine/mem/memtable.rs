@@ -1,14 +1,18 @@
 use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
 use crate::DBError;
-use crate::engine::mem::{ColumnFamilyId, SequenceNumber};
+use crate::engine::mem::{ColumnFamilyId, RangeTombstone, SequenceNumber};
 use super::skiplist::{Node, SkipList};
 use super::skiplist::Arena;
+use crate::util::MemoryAllocator;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ValueType {
     Put,
     Delete,
+    /// merge operand，读的时候和更老的版本一起交给 MergeOperator
+    Merge,
 }
 
 impl ValueType {
@@ -16,6 +20,7 @@ impl ValueType {
         match v {
             x if x == ValueType::Put as u8 => Some(ValueType::Put),
             x if x == ValueType::Delete as u8 => Some(ValueType::Delete),
+            x if x == ValueType::Merge as u8 => Some(ValueType::Merge),
             _ => None,
         }
     }
@@ -109,11 +114,10 @@ impl InternalKey {
     }
 
     /// 构造一个 “最大 internal key”，用于 seek(user_key) 时作为上界
+    /// 排在 user_key 所有版本之前的 internal key（seq 最大、type 最大），seek 用
     pub fn max_for_user_key(user_key: &[u8]) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(user_key.len() + 9);
-        buf.extend_from_slice(user_key);
-        buf.extend_from_slice(&u64::MAX.to_be_bytes());
-        buf.push(0); // ValueType::Value 假定=0
+        let mut buf = Vec::with_capacity(user_key.len() + 8);
+        InternalKey::new(user_key.to_vec(), u64::MAX >> 8, ValueType::Delete).encode_to(&mut buf);
         buf
     }
 }
@@ -176,34 +180,62 @@ impl<'a> Iterator for MemTableIterator<'a> {
 
 pub trait MemTable: Send + Sync {
     fn cf_id(&self) -> ColumnFamilyId;
-    fn add(&mut self, seq: SequenceNumber, user_key: &[u8], value: &[u8], value_type: ValueType);
+    /// 只有一个写线程（写路径拿着 memtable 锁）；读可以并发
+    fn add(&self, seq: SequenceNumber, user_key: &[u8], value: &[u8], value_type: ValueType);
     fn get(&self, seq: SequenceNumber, key: &[u8]) -> Option<Vec<u8>>;
+    /// 同 `get`，带上命中版本的 seq
+    fn get_entry(&self, seq: SequenceNumber, key: &[u8]) -> Option<(SequenceNumber, Vec<u8>)>;
+    /// key 在 seq 及之前的所有版本（含墓碑和 merge operand），从新到旧
+    fn versions(&self, seq: SequenceNumber, key: &[u8]) -> Vec<(SequenceNumber, ValueType, Vec<u8>)>;
+    /// DeleteRange 写下的墓碑不进 skiplist，单独存
+    fn add_range_tombstone(&self, seq: SequenceNumber, begin: &[u8], end: &[u8]);
+    fn range_tombstones(&self) -> Vec<RangeTombstone>;
     fn approximate_memory_usage(&self) -> usize;
     fn mark_immutable(&mut self);
     fn is_immutable(&self) -> bool;
     fn iter(&self) -> MemTableIterator;
+    /// 底下的跳表，给要 seek / 反向走的 `MemTableInternalIterator` 用
+    fn skiplist(&self) -> &MemSkipList;
     fn smallest_key(&self) -> &[u8];
     fn largest_key(&self) -> &[u8];
+    /// freeze 时分配的 SST 文件号。L0 按文件号排新旧，号要跟冻结的先后一致，
+    /// 不能等到 flush 时再按谁先跑到谁先拿
+    fn flush_file_number(&self) -> Option<u64>;
+    fn set_flush_file_number(&self, file_number: u64);
 }
 
 // MemTable 实现
+/// memtable 底下的跳表：按 `mvcc_comparator` 排序的 (InternalKey, value)
+pub type MemSkipList = SkipList<InternalKey, Vec<u8>, fn(&InternalKey, &InternalKey) -> std::cmp::Ordering, fn(&InternalKey, &InternalKey) -> bool>;
+
 pub struct SkipListMemTable {
     cf: ColumnFamilyId,
-    pub(crate) skiplist: SkipList<InternalKey, Vec<u8>,fn(&InternalKey, &InternalKey) -> std::cmp::Ordering,fn(&InternalKey, &InternalKey) -> bool>,
+    pub(crate) skiplist: MemSkipList,
     memory_usage: AtomicUsize,
     immutable: AtomicBool,
     frontier_seq: u64,
-    tail: Option<*const Node<InternalKey, Vec<u8>>>,
+    tail: AtomicPtr<Node<InternalKey, Vec<u8>>>,
+    range_tombstones: Mutex<Vec<RangeTombstone>>,
+    /// 0 表示还没分配
+    flush_file_number: AtomicU64,
 }
 
 
 impl SkipListMemTable {
     pub fn new(cf: ColumnFamilyId, seq: u64) -> Self {
+        Self::with_arena(cf, seq, Arena::new())
+    }
+
+    /// arena 由 MemoryAllocator 提供（可能是复用的旧 arena）
+    pub fn with_allocator(cf: ColumnFamilyId, seq: u64, allocator: &Arc<dyn MemoryAllocator>) -> Self {
+        Self::with_arena(cf, seq, Arena::from_allocator(Arc::clone(allocator)))
+    }
+
+    fn with_arena(cf: ColumnFamilyId, seq: u64, arena: Arena) -> Self {
         fn is_visible(a: &InternalKey, b: &InternalKey
         ) -> bool {
             a.user_key == b.user_key && a.seq <= b.seq && a.value_type!=ValueType::Delete
         }
-        let arena = Arena::new();
         let skiplist:SkipList<InternalKey, Vec<u8>,
             fn(&InternalKey, &InternalKey) -> std::cmp::Ordering,
             fn(&InternalKey, &InternalKey) -> bool> = SkipList::new(arena, mvcc_comparator, is_visible);
@@ -213,7 +245,9 @@ impl SkipListMemTable {
             memory_usage:AtomicUsize::new(0),
             immutable:AtomicBool::new(false),
             frontier_seq: seq,
-            tail: None,
+            tail: AtomicPtr::new(std::ptr::null_mut()),
+            range_tombstones: Mutex::new(Vec::new()),
+            flush_file_number: AtomicU64::new(0),
         }
     }
 }
@@ -224,7 +258,7 @@ impl MemTable for SkipListMemTable
         self.cf
     }
 
-    fn add(&mut self, seq: SequenceNumber, user_key: &[u8], value: &[u8], value_type: ValueType) {
+    fn add(&self, seq: SequenceNumber, user_key: &[u8], value: &[u8], value_type: ValueType) {
         // 外层 DBImpl 应该保证“只有一个写线程”在调用 add
         if self.immutable.load(AtomicOrdering::Acquire) {
             panic!("Cannot modify immutable MemTable");
@@ -243,7 +277,7 @@ impl MemTable for SkipListMemTable
 
         // 假设 SkipList::insert 是 &self + 内部原子实现
         let node_ptr = self.skiplist.insert(ikey, v);
-        self.tail = Some(node_ptr);
+        self.tail.store(node_ptr as *mut _, AtomicOrdering::Release);
     }
 
     fn get(&self, seq:SequenceNumber, key: &[u8]) -> Option<Vec<u8>> {
@@ -254,6 +288,39 @@ impl MemTable for SkipListMemTable
         self.skiplist.search(&temp_key).cloned()
     }
 
+    fn get_entry(&self, seq: SequenceNumber, key: &[u8]) -> Option<(SequenceNumber, Vec<u8>)> {
+        if seq < self.frontier_seq {
+            return None;
+        }
+        let temp_key = InternalKey::from_seq_slice(seq, key);
+        self.skiplist.search_entry(&temp_key).map(|(k, v)| (k.seq, v.clone()))
+    }
+
+    fn versions(&self, seq: SequenceNumber, key: &[u8]) -> Vec<(SequenceNumber, ValueType, Vec<u8>)> {
+        if seq < self.frontier_seq {
+            return Vec::new();
+        }
+        // 同 seq 时 type 大的排前面，用最大的 type 才不会漏掉 seq 正好相等的那条
+        let start = InternalKey::new(key.to_vec(), seq, ValueType::Merge);
+        MemTableIterator { current: self.skiplist.seek(&start) }
+            .take_while(|(k, _)| k.user_key == key)
+            .map(|(k, v)| (k.seq, k.value_type.clone(), v.clone()))
+            .collect()
+    }
+
+    fn add_range_tombstone(&self, seq: SequenceNumber, begin: &[u8], end: &[u8]) {
+        if self.immutable.load(AtomicOrdering::Acquire) {
+            panic!("Cannot modify immutable MemTable");
+        }
+        self.memory_usage
+            .fetch_add(begin.len() + end.len() + std::mem::size_of::<RangeTombstone>(), AtomicOrdering::Relaxed);
+        self.range_tombstones.lock().unwrap().push(RangeTombstone::new(begin, end, seq));
+    }
+
+    fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.lock().unwrap().clone()
+    }
+
     fn approximate_memory_usage(&self) -> usize {
         self.memory_usage.load(AtomicOrdering::Relaxed)
     }
@@ -284,21 +351,30 @@ impl MemTable for SkipListMemTable
         }
     }
 
+    fn skiplist(&self) -> &MemSkipList {
+        &self.skiplist
+    }
+
     fn smallest_key(&self) -> &[u8] {
         self.skiplist.front().map(|(k, _v)| k.as_encoded())
             .unwrap_or(b"")
     }
 
     fn largest_key(&self) -> &[u8] {
-        match self.tail {
-            Some(ptr) => unsafe {
-                // 返回 InternalKey.user_key 的字节切片
-                &(*ptr).key.user_key
-            },
-            None => {
-                // 如果 skiplist 为空，返回空切片
-                &[]
-            }
+        // 节点在 arena 里，和 memtable 活得一样久
+        match unsafe { self.tail.load(AtomicOrdering::Acquire).as_ref() } {
+            // 返回 InternalKey.user_key 的字节切片
+            Some(node) => &node.key.user_key,
+            // 如果 skiplist 为空，返回空切片
+            None => &[],
         }
     }
+
+    fn flush_file_number(&self) -> Option<u64> {
+        Some(self.flush_file_number.load(AtomicOrdering::Acquire)).filter(|&n| n != 0)
+    }
+
+    fn set_flush_file_number(&self, file_number: u64) {
+        self.flush_file_number.store(file_number, AtomicOrdering::Release);
+    }
 }
@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use crate::engine::mem::{mvcc_comparator, InternalKey, MemTable};
+use crate::engine::mem::skiplist::Node;
+use crate::engine::sst::iterator::InternalIterator;
+
+/// 一个 memtable 的 InternalIterator，和 SST iterator 一起进 MergingIterator
+///
+/// 直接在跳表上走，不拷数据：拿着 memtable 的 Arc，节点所在的 arena 在迭代期间不会被释放，
+/// 也不用一直拿着 MemTableSet 的锁。active memtable 之后的写入可能被走到，
+/// 上层按读的 sequence 过滤。
+pub struct MemTableInternalIterator {
+    table: Arc<dyn MemTable>,
+    /// 当前节点，null 表示 invalid；指向 `table` 的 arena
+    current: *const Node<InternalKey, Vec<u8>>,
+    /// 当前节点 key 的编码（user key + 8 字节 tag）
+    key: Vec<u8>,
+}
+
+impl MemTableInternalIterator {
+    pub fn new(table: Arc<dyn MemTable>) -> Self {
+        Self { table, current: std::ptr::null(), key: Vec::new() }
+    }
+
+    fn set_current(&mut self, node: Option<&Node<InternalKey, Vec<u8>>>) {
+        self.current = node.map_or(std::ptr::null(), |n| n as *const _);
+        self.key.clear();
+        if let Some(node) = node {
+            node.key.encode_to(&mut self.key);
+        }
+    }
+
+    fn node(&self) -> &Node<InternalKey, Vec<u8>> {
+        assert!(self.valid(), "memtable iterator is not positioned on an entry");
+        // table 的 Arc 还在，节点一直有效
+        unsafe { &*self.current }
+    }
+}
+
+impl InternalIterator for MemTableInternalIterator {
+    fn valid(&self) -> bool {
+        !self.current.is_null()
+    }
+
+    fn seek_to_first(&mut self) {
+        let table = Arc::clone(&self.table);
+        self.set_current(table.skiplist().find_first());
+    }
+
+    fn seek_to_last(&mut self) {
+        let table = Arc::clone(&self.table);
+        self.set_current(table.skiplist().find_last());
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let table = Arc::clone(&self.table);
+        let node = InternalKey::decode(target).ok()
+            .and_then(|target| table.skiplist().seek(&target));
+        self.set_current(node);
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        let table = Arc::clone(&self.table);
+        let node = InternalKey::decode(target).ok().and_then(|target| {
+            let skiplist = table.skiplist();
+            match skiplist.seek(&target) {
+                Some(n) if mvcc_comparator(&n.key, &target) == Ordering::Equal => Some(n),
+                _ => skiplist.find_less_than(&target),
+            }
+        });
+        self.set_current(node);
+    }
+
+    fn next(&mut self) {
+        if self.valid() {
+            let next = unsafe { self.node().next[0].load(std::sync::atomic::Ordering::Acquire).as_ref() };
+            self.set_current(next);
+        }
+    }
+
+    // 节点没有 prev 指针，每退一步从 head 重新找，O(log n)；走过第一条后 invalid
+    fn prev(&mut self) {
+        if self.valid() {
+            let table = Arc::clone(&self.table);
+            let node = table.skiplist().find_less_than(&self.node().key);
+            self.set_current(node);
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.node().value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mem::{MemTableSet, ValueType};
+
+    fn encoded(user_key: &[u8], seq: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        InternalKey::new(user_key.to_vec(), seq, ValueType::Put).encode_to(&mut buf);
+        buf
+    }
+
+    fn user_key(iter: &dyn InternalIterator) -> Vec<u8> {
+        InternalKey::decode(iter.key()).unwrap().user_key
+    }
+
+    #[test]
+    fn walks_the_skiplist_in_both_directions() {
+        let set = MemTableSet::new(0, &[0]);
+        for (seq, k) in [b"b", b"d", b"a", b"c"].into_iter().enumerate() {
+            set.insert(0, seq as u64 + 1, k, k, ValueType::Put).unwrap();
+        }
+        let mut iter = set.internal_iterators(0).pop().unwrap();
+
+        iter.seek_to_first();
+        let mut forward = Vec::new();
+        while iter.valid() {
+            forward.push(user_key(iter.as_ref()));
+            iter.next();
+        }
+        assert_eq!(forward, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+
+        iter.seek_to_last();
+        assert_eq!(user_key(iter.as_ref()), b"d");
+        iter.prev();
+        assert_eq!(iter.value(), b"c");
+
+        iter.seek(&InternalKey::max_for_user_key(b"bb"));
+        assert_eq!(user_key(iter.as_ref()), b"c");
+        iter.seek_for_prev(&encoded(b"bb", 0));
+        assert_eq!(user_key(iter.as_ref()), b"b");
+        iter.seek_to_first();
+        iter.prev();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn sees_entries_added_after_it_was_created() {
+        let set = MemTableSet::new(0, &[0]);
+        let mut iter = set.internal_iterators(0).pop().unwrap();
+        iter.seek_to_first();
+        assert!(!iter.valid());
+
+        // 不是建的时候拷出来的快照：上层按 sequence 过滤
+        set.insert(0, 1, b"k", b"v", ValueType::Put).unwrap();
+        iter.seek_to_first();
+        assert_eq!(iter.key(), encoded(b"k", 1).as_slice());
+    }
+}
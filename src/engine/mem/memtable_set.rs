@@ -1,12 +1,20 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use crate::engine::mem::ColumnFamilyId;
 use crate::error::DBError;
 use crate::engine::mem::{MemTable, SkipListMemTable, ValueType};
 use crate::engine::mem::SequenceNumber;
+use crate::engine::mem::MemTableInternalIterator;
+use crate::engine::mem::range_tombstone::{max_covering_seq, RangeTombstone};
+use crate::engine::sst::iterator::InternalIterator;
+use crate::engine::sst::LookupResult;
 use crate::engine::wal::write_batch::{WriteBatch, WriteBatchEntry};
+use crate::util::MemoryAllocator;
+use crate::util::perf_context::perf_record;
 
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CfType {
     System = 0,
     User = 1,
@@ -38,10 +46,31 @@ struct CfMemTables {
 
     /// 正在 flush 到 SST 的 memtable（后台线程使用）
     flushing: Vec<Arc<dyn MemTable>>,
+
+    /// `apply` 往 active 里写进去的字节数，切换 active 时清零
+    active_bytes: AtomicUsize,
+}
+
+impl CfMemTables {
+    fn new(active: Arc<dyn MemTable>) -> Self {
+        Self {
+            active,
+            immutables: VecDeque::new(),
+            flushing: Vec::new(),
+            active_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// 当前 active memtable 已经写进去的字节数（按每次 apply 实际记账的）
+    pub(crate) fn active_memory_usage(&self) -> usize {
+        self.active_bytes.load(Ordering::Relaxed)
+    }
 }
 
 pub struct MemTableSet {
     pub(crate) cfs: HashMap<ColumnFamilyId, CfMemTables>,
+    /// 新 memtable 的 arena 来源（None = 每次新建）
+    allocator: Option<Arc<dyn MemoryAllocator>>,
 }
 
 impl MemTableSet {
@@ -51,42 +80,85 @@ impl MemTableSet {
         let mut map = HashMap::new();
         for cf in cfs{
             let active = Arc::new(SkipListMemTable::new(*cf, seq));
-            map.insert(
-                *cf,
-                CfMemTables {
-                    active,
-                    immutables: VecDeque::new(),
-                    flushing: Vec::new(),
-                }
-            );
+            map.insert(*cf, CfMemTables::new(active));
         }
         Self {
-            cfs: map
+            cfs: map,
+            allocator: None,
+        }
+    }
+
+    pub fn with_allocator(mut self, allocator: Arc<dyn MemoryAllocator>) -> Self {
+        self.allocator = Some(allocator);
+        self
+    }
+
+    fn new_memtable(&self, cf: ColumnFamilyId, seq: SequenceNumber) -> Arc<dyn MemTable> {
+        match &self.allocator {
+            Some(a) => Arc::new(SkipListMemTable::with_allocator(cf, seq, a)),
+            None => Arc::new(SkipListMemTable::new(cf, seq)),
         }
     }
 
     // ========== 写入路径 ==========
 
-    pub fn apply(&self, base_seq: SequenceNumber, batch: WriteBatch) -> Result<(), DBError> {
+    /// 把 `batch` 写进各 CF 的 active memtable，返回这次新占的字节数
+    ///
+    /// 只借用 batch：key / value 直接从 batch 的编码里切出来交给 memtable，
+    /// 写 WAL 的同一份 batch 不用再拷贝。
+    pub fn apply(&self, base_seq: SequenceNumber, batch: &WriteBatch) -> Result<usize, DBError> {
         let mut seq = base_seq;
+        let mut added = 0;
 
-        for entry in batch.entries {
-            match entry {
-                WriteBatchEntry::Put { cf, key, value } => {
-                    self.insert(cf, seq, &key, &value, ValueType::Put)?;
-                }
+        for entry in batch {
+            added += self.apply_entry(seq, entry)?;
+            seq += 1;
+        }
+        Ok(added)
+    }
 
-                WriteBatchEntry::Delete { cf, key } => {
-                    // Delete = value_type=Delete, value=null
-                    self.insert(cf, seq, &key, &[], ValueType::Delete)?;
-                }
+    fn apply_entry(&self, seq: SequenceNumber, entry: WriteBatchEntry<'_>) -> Result<usize, DBError> {
+        match entry {
+            WriteBatchEntry::Put { cf, key, value } => self.insert(cf, seq, key, value, ValueType::Put),
+            // Delete = value_type=Delete, value=null
+            WriteBatchEntry::Delete { cf, key } => self.insert(cf, seq, key, &[], ValueType::Delete),
+            WriteBatchEntry::Merge { cf, key, value } => self.insert(cf, seq, key, value, ValueType::Merge),
+            WriteBatchEntry::DeleteRange { cf, begin, end } => self.insert_range_tombstone(cf, seq, begin, end),
+        }
+    }
+
+    /// WAL 恢复用：跳过已删除 CF 的记录，但 seq 照常递增，
+    /// 保证其他 CF 的 seq 与写入时一致
+    pub fn apply_for_recovery(
+        &self,
+        base_seq: SequenceNumber,
+        batch: &WriteBatch,
+        dropped_cfs: &HashSet<ColumnFamilyId>,
+    ) -> Result<usize, DBError> {
+        let mut seq = base_seq;
+        let mut added = 0;
+
+        for entry in batch {
+            if !dropped_cfs.contains(&entry.cf()) {
+                added += self.apply_entry(seq, entry)?;
             }
             seq += 1;
         }
-        Ok(())
+        Ok(added)
     }
 
-    /// 向当前活跃 memtable 写入
+    /// 新建的 CF：给它一个空的活跃 memtable
+    pub fn add_cf(&mut self, cf: ColumnFamilyId, seq: SequenceNumber) {
+        let active = self.new_memtable(cf, seq);
+        self.cfs.entry(cf).or_insert_with(|| CfMemTables::new(active));
+    }
+
+    /// CF 被 drop：丢弃它的所有 memtable
+    pub fn remove_cf(&mut self, cf: ColumnFamilyId) -> Option<CfMemTables> {
+        self.cfs.remove(&cf)
+    }
+
+    /// 向当前活跃 memtable 写入，返回 memtable 新占的字节数
     pub fn insert(
         &self,
         cf: ColumnFamilyId,
@@ -94,55 +166,137 @@ impl MemTableSet {
         key: &[u8],
         value: &[u8],
         value_type: ValueType,
-    ) -> Result<(), DBError> {
+    ) -> Result<usize, DBError> {
+        let cf_tables = self.cfs.get(&cf)
+            .ok_or(DBError::UnknownColumnFamily(format!(
+                "Unknown column family id: {:?}",
+                cf)))?;
+        let before = cf_tables.active.approximate_memory_usage();
+        cf_tables.active.add(seq, key, value, value_type);
+        Ok(Self::charge(cf_tables, before))
+    }
+
+    /// active 从 `before` 涨了多少，记到 active_bytes 上
+    fn charge(cf_tables: &CfMemTables, before: usize) -> usize {
+        let added = cf_tables.active.approximate_memory_usage().saturating_sub(before);
+        cf_tables.active_bytes.fetch_add(added, Ordering::Relaxed);
+        added
+    }
+
+    /// 向当前活跃 memtable 写入一条范围墓碑
+    pub fn insert_range_tombstone(
+        &self,
+        cf: ColumnFamilyId,
+        seq: SequenceNumber,
+        begin: &[u8],
+        end: &[u8],
+    ) -> Result<usize, DBError> {
         let cf_tables = self.cfs.get(&cf)
             .ok_or(DBError::UnknownColumnFamily(format!(
                 "Unknown column family id: {:?}",
                 cf)))?;
-        cf_tables.active.insert(seq, key, value, value_type)
+        let before = cf_tables.active.approximate_memory_usage();
+        cf_tables.active.add_range_tombstone(seq, begin, end);
+        Ok(Self::charge(cf_tables, before))
     }
 
     /// 冻结当前 memtable（切换 active → immutable）
-    pub fn freeze_active(&mut self, cf: ColumnFamilyId, new_seq: SequenceNumber) -> Result<VecDeque<Arc<dyn MemTable>>, DBError>{
+    /// `file_number` 是冻结的这个 memtable 将来 flush 出的 SST 的文件号
+    pub fn freeze_active(&mut self, cf: ColumnFamilyId, new_seq: SequenceNumber, file_number: u64) -> Result<(), DBError> {
+        let new_active = self.new_memtable(cf, new_seq);
         let cf_tables = self.cfs.get_mut(&cf)
             .ok_or(DBError::UnknownColumnFamily(format!(
                 "Unknown column family id: {:?}",
                 cf)))?;
         let old = std::mem::replace(
             &mut cf_tables.active,
-            Arc::new(SkipListMemTable::new(cf, new_seq)),
+            new_active,
         );
+        cf_tables.active_bytes.store(0, Ordering::Relaxed);
+        old.set_flush_file_number(file_number);
         cf_tables.immutables.push_back(old);
-        Ok(cf_tables.immutables)
+        Ok(())
     }
 
     // ========== 读取路径 ==========
 
     /// 按最新版本查询（active → immutables 逆序）
-    pub fn get(
+    ///
+    /// 最新版本是删除（或被范围墓碑盖住）时返回 Deleted：SST 里更老的版本都不可见，不用再查
+    pub fn lookup(
         &self,
         cf: ColumnFamilyId,
         seq: SequenceNumber,
         key: &[u8],
-    ) -> Option<Vec<u8>> {
-        let cf_tables = self.cfs.get(&cf)?;
-        if let Some(v) = cf_tables.active.get(seq, key) {
-            return Some(v);
+    ) -> LookupResult {
+        let Some(cf_tables) = self.cfs.get(&cf) else { return LookupResult::NotFound };
+        // 新 memtable 里的范围墓碑会盖住老 memtable 里的版本
+        let mut covered = None;
+        for table in std::iter::once(&cf_tables.active).chain(cf_tables.immutables.iter().rev()) {
+            covered = covered.max(max_covering_seq(&table.range_tombstones(), key, seq));
+            if let Some((found, value_type, v)) = table.versions(seq, key).into_iter().next() {
+                perf_record(|p| p.memtable_hit_count += 1);
+                if covered.is_some_and(|t| found < t) || value_type == ValueType::Delete {
+                    return LookupResult::Deleted;
+                }
+                return LookupResult::Found(v);
+            }
         }
+        perf_record(|p| p.memtable_miss_count += 1);
+        LookupResult::NotFound
+    }
 
-        for table in cf_tables.immutables.iter().rev() {
-            if let Some(v) = table.get(seq, key) {
-                return Some(v);
+    /// merge 读的 memtable 部分：从新到旧把 operand 收进 `operands`。
+    /// `Some(base)` 表示已经找到头（Put 的值，删除时是 None），`None` 表示还要接着查 SST
+    pub fn collect_merge_operands(
+        &self,
+        cf: ColumnFamilyId,
+        seq: SequenceNumber,
+        key: &[u8],
+        operands: &mut Vec<Vec<u8>>,
+    ) -> Option<Option<Vec<u8>>> {
+        let cf_tables = self.cfs.get(&cf)?;
+        let mut covered = None;
+        for table in std::iter::once(&cf_tables.active).chain(cf_tables.immutables.iter().rev()) {
+            covered = covered.max(max_covering_seq(&table.range_tombstones(), key, seq));
+            for (found, value_type, v) in table.versions(seq, key) {
+                if covered.is_some_and(|t| found < t) {
+                    return Some(None);
+                }
+                match value_type {
+                    ValueType::Put => return Some(Some(v)),
+                    ValueType::Delete => return Some(None),
+                    ValueType::Merge => operands.push(v),
+                }
             }
         }
+        if self.is_range_deleted(cf, seq, key) {
+            return Some(None);
+        }
         None
     }
 
+    /// `key` 是否被 memtable 里的范围墓碑删掉了（以 `seq` 读）；
+    /// memtable 里没查到值时用它判断还要不要去查 SST
+    pub fn is_range_deleted(&self, cf: ColumnFamilyId, seq: SequenceNumber, key: &[u8]) -> bool {
+        self.range_tombstones(cf).iter().any(|t| t.seq <= seq && t.contains(key))
+    }
+
+    /// cf 所有 memtable（含正在 flush 的）里的范围墓碑
+    pub fn range_tombstones(&self, cf: ColumnFamilyId) -> Vec<RangeTombstone> {
+        let Some(cf_tables) = self.cfs.get(&cf) else { return Vec::new() };
+        std::iter::once(&cf_tables.active)
+            .chain(cf_tables.immutables.iter())
+            .chain(cf_tables.flushing.iter())
+            .flat_map(|t| t.range_tombstones())
+            .collect()
+    }
+
     // ========== flush 相关 ==========
 
     /// 取出一个 immutable 交给后台 flush
     pub fn pick_flush_candidate(&mut self, cf: ColumnFamilyId) -> Option<Arc<dyn MemTable>> {
-        let Some(cf_tables) = self.cfs.get_mut(&cf)?;
+        let cf_tables = self.cfs.get_mut(&cf)?;
         if let Some(t) = cf_tables.immutables.pop_front() {
             cf_tables.flushing.push(t.clone());
             Some(t)
@@ -158,6 +312,26 @@ impl MemTableSet {
         }
     }
 
+    /// flush 失败：按冻结的先后（文件号）放回 immutables，下次 flush 再拿
+    pub fn abort_flush(&mut self, cf: ColumnFamilyId, table: &Arc<dyn MemTable>) {
+        let Some(cf_tables) = self.cfs.get_mut(&cf) else { return };
+        let before = cf_tables.flushing.len();
+        cf_tables.flushing.retain(|x| !Arc::ptr_eq(x, table));
+        if cf_tables.flushing.len() < before {
+            let at = cf_tables.immutables.iter()
+                .position(|x| x.flush_file_number() > table.flush_file_number())
+                .unwrap_or(cf_tables.immutables.len());
+            cf_tables.immutables.insert(at, Arc::clone(table));
+        }
+    }
+
+    /// 后台正在 flush 的 memtable 个数
+    pub fn num_flushing(&self, cf: ColumnFamilyId) -> usize {
+        self.cfs.get(&cf)
+            .map(|cf_tables| cf_tables.flushing.len())
+            .unwrap_or(0)
+    }
+
     // ========== 状态辅助 ==========
 
     pub fn num_immutables(&self, cf: ColumnFamilyId) -> usize {
@@ -166,9 +340,122 @@ impl MemTableSet {
             .unwrap_or(0)
     }
 
+    /// (active, immutable + flushing) memtable 占用字节
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let mut active = 0;
+        let mut immutable = 0;
+        for cf_tables in self.cfs.values() {
+            active += cf_tables.active.approximate_memory_usage();
+            immutable += cf_tables.immutables.iter()
+                .chain(cf_tables.flushing.iter())
+                .map(|t| t.approximate_memory_usage())
+                .sum::<usize>();
+        }
+        (active, immutable)
+    }
+
+    /// active / immutable / flushing 里所有以 `prefix` 开头的 user key（含已删除的）
+    ///
+    /// skiplist 没有 seek，只能整表扫
+    pub fn user_keys_with_prefix(&self, cf: ColumnFamilyId, prefix: &[u8]) -> BTreeSet<Vec<u8>> {
+        let mut keys = BTreeSet::new();
+        if let Some(cf_tables) = self.cfs.get(&cf) {
+            let tables = std::iter::once(&cf_tables.active)
+                .chain(cf_tables.immutables.iter())
+                .chain(cf_tables.flushing.iter());
+            for table in tables {
+                for (ikey, _) in table.iter() {
+                    if ikey.user_key.starts_with(prefix) {
+                        keys.insert(ikey.user_key.clone());
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// cf 还没落盘的写（含范围墓碑）里有没有落在 `[smallest, largest]` 的 user key
+    pub fn overlaps_user_range(&self, cf: ColumnFamilyId, smallest: &[u8], largest: &[u8]) -> bool {
+        let Some(cf_tables) = self.cfs.get(&cf) else { return false };
+        let mut tables = std::iter::once(&cf_tables.active)
+            .chain(cf_tables.immutables.iter())
+            .chain(cf_tables.flushing.iter());
+        tables.any(|table| {
+            table.range_tombstones().iter().any(|t| t.begin.as_slice() <= largest && smallest < t.end.as_slice())
+                || table.iter().any(|(ikey, _)| {
+                    ikey.user_key.as_slice() >= smallest && ikey.user_key.as_slice() <= largest
+                })
+        })
+    }
+
+    /// cf 的全部 memtable（active、immutable、正在 flush 的），从新到旧
+    pub fn internal_iterators(&self, cf: ColumnFamilyId) -> Vec<Box<dyn InternalIterator>> {
+        let Some(cf_tables) = self.cfs.get(&cf) else { return Vec::new() };
+        std::iter::once(&cf_tables.active)
+            .chain(cf_tables.immutables.iter().rev())
+            .chain(cf_tables.flushing.iter().rev())
+            .map(|t| Box::new(MemTableInternalIterator::new(Arc::clone(t))) as Box<dyn InternalIterator>)
+            .collect()
+    }
+
     pub fn has_flush_candidate(&self, cf: ColumnFamilyId) -> bool {
         self.cfs.get(&cf)
             .map(|cf_tables| !cf_tables.immutables.is_empty())
             .unwrap_or(false)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mem::InternalKey;
+
+    /// cf 里 (user key, seq)，按 key 排
+    fn entries(set: &MemTableSet, cf: ColumnFamilyId) -> Vec<(Vec<u8>, SequenceNumber)> {
+        let mut out = Vec::new();
+        for mut iter in set.internal_iterators(cf) {
+            iter.seek_to_first();
+            while iter.valid() {
+                let key = InternalKey::decode(iter.key()).unwrap();
+                out.push((key.user_key, key.seq));
+                iter.next();
+            }
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn apply_charges_the_bytes_added_to_each_cf() {
+        let set = MemTableSet::new(0, &[1, 2]);
+        let mut batch = WriteBatch::new();
+        batch.put(1, b"a", &[7u8; 100]);
+        batch.delete(2, b"b");
+        batch.merge(1, b"c", b"+1");
+        batch.delete_range(2, b"x", b"z");
+
+        let added = set.apply(10, &batch).unwrap();
+        let (one, two) = (set.cfs[&1].active_memory_usage(), set.cfs[&2].active_memory_usage());
+        assert!(one > 100 && two > 0);
+        assert_eq!(added, one + two);
+        assert_eq!(entries(&set, 1), vec![(b"a".to_vec(), 10), (b"c".to_vec(), 12)]);
+        assert!(set.is_range_deleted(2, 14, b"y"));
+
+        let mut unknown = WriteBatch::new();
+        unknown.put(9, b"k", b"v");
+        assert!(matches!(set.apply(20, &unknown), Err(DBError::UnknownColumnFamily(_))));
+    }
+
+    #[test]
+    fn recovery_skips_dropped_cfs_but_keeps_their_sequences() {
+        let set = MemTableSet::new(0, &[1]);
+        let mut batch = WriteBatch::new();
+        batch.put(1, b"a", b"v");
+        batch.put(3, b"gone", b"v");
+        batch.put(1, b"b", b"v");
+
+        let dropped = HashSet::from([3]);
+        set.apply_for_recovery(5, &batch, &dropped).unwrap();
+        assert_eq!(entries(&set, 1), vec![(b"a".to_vec(), 5), (b"b".to_vec(), 7)]);
+    }
+}
@@ -5,10 +5,14 @@ pub mod skiplist;
 pub mod storage;
 pub mod memtable_set;
 pub mod memtable;
+pub mod memtable_iter;
+pub mod range_tombstone;
 #[cfg(test)]
 pub mod skiplist_test;
 
 
-pub use memtable::{mvcc_comparator,raw_mvcc_compare,MemTable,SkipListMemTable,ValueType,InternalKey};
+pub use memtable::{mvcc_comparator,raw_mvcc_compare,MemSkipList,MemTable,SkipListMemTable,ValueType,InternalKey};
 pub use memtable_set::{MemTableSet};
+pub use memtable_iter::MemTableInternalIterator;
+pub use range_tombstone::RangeTombstone;
 pub use storage::Storage;
@@ -0,0 +1,54 @@
+use crate::DBError;
+use crate::engine::mem::SequenceNumber;
+use crate::engine::wal::format::{read_bytes, read_u64};
+
+/// DeleteRange 写下的墓碑：删除 seq 之前写入的、落在 [begin, end) 里的所有 key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub begin: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub fn new(begin: &[u8], end: &[u8], seq: SequenceNumber) -> Self {
+        Self { begin: begin.to_vec(), end: end.to_vec(), seq }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.begin.as_slice() && key < self.end.as_slice()
+    }
+
+    /// 以 `read_seq` 读的时候，这条墓碑是否盖住 (key, key_seq) 这个版本
+    pub fn covers(&self, key: &[u8], key_seq: SequenceNumber, read_seq: SequenceNumber) -> bool {
+        self.seq <= read_seq && key_seq < self.seq && self.contains(key)
+    }
+
+    pub fn encode_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.begin.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.begin);
+        buf.extend_from_slice(&(self.end.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.end);
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+    }
+
+    pub fn decode_from(buf: &[u8], pos: &mut usize) -> Result<Self, DBError> {
+        let begin = read_bytes(buf, pos)?;
+        let end = read_bytes(buf, pos)?;
+        let seq = read_u64(buf, pos)?;
+        Ok(Self { begin, end, seq })
+    }
+}
+
+/// 以 `read_seq` 读时盖住 `key` 的墓碑里最大的 seq：比它老的版本都不可见
+pub fn max_covering_seq<'a>(
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    read_seq: SequenceNumber,
+) -> Option<SequenceNumber> {
+    tombstones
+        .into_iter()
+        .filter(|t| t.seq <= read_seq && t.contains(key))
+        .map(|t| t.seq)
+        .max()
+}
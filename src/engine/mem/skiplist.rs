@@ -1,20 +1,32 @@
 use std::cell::UnsafeCell;
 use std::mem::MaybeUninit;
+use std::sync::Arc;
 use bumpalo::Bump;
-use std::sync::atomic::{AtomicPtr, Ordering as AtomicOrdering};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering as AtomicOrdering};
 use rand::prelude::*;
+use crate::util::MemoryAllocator;
 
 pub(crate) const MAX_HEIGHT: usize = 12;
 pub(crate) const BRANCHING: f64 = 0.25;
 
 pub struct Arena {
     bump: UnsafeCell<Bump>,
+    /// 来源 allocator；drop 时把 arena 还回去复用
+    allocator: Option<Arc<dyn MemoryAllocator>>,
 }
 
 impl Arena {
     pub fn new() -> Self {
         Self {
             bump: UnsafeCell::new(Bump::new()),
+            allocator: None,
+        }
+    }
+
+    pub fn from_allocator(allocator: Arc<dyn MemoryAllocator>) -> Self {
+        Self {
+            bump: UnsafeCell::new(allocator.new_arena()),
+            allocator: Some(allocator),
         }
     }
 
@@ -26,6 +38,15 @@ impl Arena {
     }
 }
 
+impl Drop for Arena {
+    fn drop(&mut self) {
+        if let Some(allocator) = self.allocator.take() {
+            // arena 里的 node 都属于即将释放的 skiplist，不会再被访问
+            allocator.release_arena(std::mem::replace(self.bump.get_mut(), Bump::new()));
+        }
+    }
+}
+
 unsafe impl Send for Arena {}
 unsafe impl Sync for Arena {}
 
@@ -78,7 +99,8 @@ impl<K:Default, V:Default> Node<K, V> {
 
 pub struct SkipList<K, V, C, M> {
     pub(crate) head: AtomicPtr<Node<K, V>>,
-    max_height: usize,
+    /// 只有写线程会改，读的时候看到旧值也只是少走几层
+    max_height: AtomicUsize,
     comparator: C,
     is_visible: M,
     arena: Arena,
@@ -112,28 +134,30 @@ impl<K, V, C, M> SkipList<K, V, C, M> {
     }
 
     pub fn back(&self) -> Option<(&K, &V)> {
-        let mut node_ptr = self.head.load(AtomicOrdering::Acquire);
-        if node_ptr.is_null() {
-            return None;
-        }
+        self.find_last().map(|node| (&node.key, &node.value))
+    }
 
-        let head_ref = unsafe { &*node_ptr };
-        let mut current_ptr = head_ref.next.get(0)?.load(AtomicOrdering::Acquire);
-        if current_ptr.is_null() {
+    /// 第一个节点
+    pub fn find_first(&self) -> Option<&Node<K, V>> {
+        let head = self.head.load(AtomicOrdering::Acquire);
+        unsafe { head.as_ref()?.next[0].load(AtomicOrdering::Acquire).as_ref() }
+    }
+
+    /// 最后一个节点：从最高层开始每层走到底再往下一层，不用扫整个 level 0
+    pub fn find_last(&self) -> Option<&Node<K, V>> {
+        let head = self.head.load(AtomicOrdering::Acquire);
+        if head.is_null() {
             return None;
         }
-
-        let mut last_ref = unsafe { &*current_ptr };
-
-        while let Some(next_atomic) = last_ref.next.get(0) {
-            let next_ptr = next_atomic.load(AtomicOrdering::Acquire);
-            if next_ptr.is_null() {
-                break;
+        let mut x = head;
+        unsafe {
+            for i in (0..self.max_height.load(AtomicOrdering::Relaxed)).rev() {
+                while let Some(next) = (*x).next[i].load(AtomicOrdering::Acquire).as_ref() {
+                    x = next as *const Node<K, V> as *mut Node<K, V>;
+                }
             }
-            last_ref = unsafe { &*next_ptr };
+            if x == head { None } else { x.as_ref() }
         }
-
-        Some((&last_ref.key, &last_ref.value))
     }
 }
 
@@ -149,19 +173,21 @@ where
 
         Self {
             head: head_ptr,
-            max_height: 1,
+            max_height: AtomicUsize::new(1),
             comparator: comparator,
             is_visible: is_visible,
             arena,
         }
     }
 
-    pub(crate) fn insert(&mut self, key: K, value: V) -> *const Node<K, V> {
+    /// 同一时刻只能有一个线程在 insert（由 DBImpl 的写路径保证），读可以并发
+    pub(crate) fn insert(&self, key: K, value: V) -> *const Node<K, V> {
         let mut update: [*mut Node<K, V>; MAX_HEIGHT] = [std::ptr::null_mut(); MAX_HEIGHT];
         let mut x = self.head.load(AtomicOrdering::Acquire);
+        let max_height = self.max_height.load(AtomicOrdering::Relaxed);
 
         // 查找每层前驱节点
-        for i in (0..self.max_height).rev() {
+        for i in (0..max_height).rev() {
             unsafe {
                 while let Some(next) = (*x).next[i].load(AtomicOrdering::Acquire).as_ref() {
                     if (self.comparator)(&next.key, &key) == std::cmp::Ordering::Less {
@@ -175,11 +201,11 @@ where
         }
 
         let node_height = self.random_height();
-        if node_height > self.max_height {
-            for i in self.max_height..node_height {
+        if node_height > max_height {
+            for i in max_height..node_height {
                 update[i] = self.head.load(AtomicOrdering::Acquire);
             }
-            self.max_height = node_height;
+            self.max_height.store(node_height, AtomicOrdering::Relaxed);
         }
         let new_node = self.arena.alloc_node(Node::new(key, value, node_height));
 
@@ -194,9 +220,41 @@ where
     }
 
     pub(crate) fn search(&self, key: &K) -> Option<&V> {
+        self.search_entry(key).map(|(_, v)| v)
+    }
+
+    /// 同 `search`，连命中节点的 key 一起返回
+    pub(crate) fn search_entry(&self, key: &K) -> Option<(&K, &V)> {
+        self.seek(key)
+            .filter(|node| (self.is_visible)(&node.key, key))
+            .map(|node| (&node.key, &node.value))
+    }
+
+    /// 最后一个 < key 的节点
+    ///
+    /// 节点没有 prev 指针，反向迭代每退一步都从 head 重新往下找，O(log n)
+    pub fn find_less_than(&self, key: &K) -> Option<&Node<K, V>> {
+        let head = self.head.load(AtomicOrdering::Acquire);
+        let mut x = head;
+        unsafe {
+            for i in (0..self.max_height.load(AtomicOrdering::Relaxed)).rev() {
+                while let Some(next) = (*x).next[i].load(AtomicOrdering::Acquire).as_ref() {
+                    if (self.comparator)(&next.key, key) == std::cmp::Ordering::Less {
+                        x = next as *const Node<K, V> as *mut Node<K, V>;
+                    } else {
+                        break;
+                    }
+                }
+            }
+            if x == head { None } else { x.as_ref() }
+        }
+    }
+
+    /// 第一个 >= key 的节点
+    pub(crate) fn seek(&self, key: &K) -> Option<&Node<K, V>> {
         let mut x = self.head.load(AtomicOrdering::Acquire);
         unsafe {
-            for i in (0..self.max_height).rev() {
+            for i in (0..self.max_height.load(AtomicOrdering::Relaxed)).rev() {
                 while let Some(next) = (*x).next[i].load(AtomicOrdering::Acquire).as_ref() {
                     match (self.comparator)(&next.key, key) {
                         std::cmp::Ordering::Less => x = next as *const Node<K, V> as *mut Node<K, V>,
@@ -204,14 +262,8 @@ where
                     }
                 }
             }
-            if let Some(next) = (*x).next[0].load(AtomicOrdering::Acquire).as_ref() {
-                if (self.is_visible)(&next.key, key)
-                {
-                    return Some(&next.value);
-                }
-            }
+            (*x).next[0].load(AtomicOrdering::Acquire).as_ref()
         }
-        None
     }
 }
 
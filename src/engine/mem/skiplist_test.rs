@@ -37,7 +37,7 @@ mod tests {
         // Always visible: exact key equality
         let is_visible = |a: &u64, b: &u64| a == b;
 
-        let mut sl: SkipList<u64, Vec<u8>, _, _> = SkipList::new(arena, cmp_u64, is_visible);
+        let sl: SkipList<u64, Vec<u8>, _, _> = SkipList::new(arena, cmp_u64, is_visible);
 
         sl.insert(10, b"a".to_vec());
         sl.insert(20, b"b".to_vec());
@@ -55,7 +55,7 @@ mod tests {
         let arena = Arena::new();
         let is_visible = |a: &u64, b: &u64| a == b;
 
-        let mut sl: SkipList<u64, u64, _, _> = SkipList::new(arena, cmp_u64, is_visible);
+        let sl: SkipList<u64, u64, _, _> = SkipList::new(arena, cmp_u64, is_visible);
 
         // Insert in descending order
         for i in (0..100u64).rev() {
@@ -79,7 +79,7 @@ mod tests {
         // so search should see the latest inserted value for the same key.
         let is_visible = |a: &u64, b: &u64| a == b;
 
-        let mut sl: SkipList<u64, Vec<u8>, _, _> = SkipList::new(arena, cmp_u64, is_visible);
+        let sl: SkipList<u64, Vec<u8>, _, _> = SkipList::new(arena, cmp_u64, is_visible);
 
         sl.insert(5, b"first".to_vec());
         sl.insert(5, b"second".to_vec());
@@ -111,7 +111,7 @@ mod tests {
         // Here "key" passed to search is a synthetic IK(user, snapshot_seq)
         let is_visible = |candidate: &IK, seek: &IK| candidate.user == seek.user && candidate.seq <= seek.seq;
 
-        let mut sl: SkipList<IK, Vec<u8>, _, _> = SkipList::new(arena, ik_cmp, is_visible);
+        let sl: SkipList<IK, Vec<u8>, _, _> = SkipList::new(arena, ik_cmp, is_visible);
 
         // Insert multiple versions for the same user key
         sl.insert(IK { user: 7, seq: 105 }, b"v105".to_vec());
@@ -1,12 +1,13 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::ops::Bound;
 
 pub struct Storage {
-    db: HashMap<String, String>,
+    db: BTreeMap<String, String>,
 }
 
 impl Storage {
     pub fn new() -> Self {
-        Storage { db: HashMap::new() }
+        Storage { db: BTreeMap::new() }
     }
 
     pub fn set(&mut self, key: String, value: String) {
@@ -16,4 +17,13 @@ impl Storage {
     pub fn get(&self, key: &str) -> Option<String> {
         self.db.get(key).cloned()
     }
+
+    /// 从 `start` 起（含）按 key 升序最多 `limit` 条
+    pub fn scan(&self, start: &str, limit: usize) -> Vec<(String, String)> {
+        self.db
+            .range::<str, _>((Bound::Included(start), Bound::Unbounded))
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
 }
@@ -3,6 +3,8 @@ pub(crate) mod wal;
 pub(crate) mod version;
 pub(crate) mod background;
 pub(crate) mod sst;
+pub mod format;
+pub mod block_trace;
 
 pub fn init_engine() {
     println!("Engine initialized");
@@ -61,15 +61,17 @@ impl BlockBuilder {
         self.counter += 1;
     }
 
-    pub fn finish(&mut self) -> Vec<u8> {
+    /// 返回 block 内容（借用内部 buffer），写完后调用 reset 复用 buffer
+    pub fn finish(&mut self) -> &[u8] {
         // append restarts
         for &r in &self.restarts {
             self.buf.extend_from_slice(&(r as u32).to_le_bytes());
         }
         self.buf.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
-        std::mem::take(&mut self.buf)
+        &self.buf
     }
 
+    /// 清空内容，保留 buf / restarts / last_key 的容量
     pub fn reset(&mut self) {
         self.buf.clear();
         self.restarts.clear();
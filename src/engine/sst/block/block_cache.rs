@@ -1,6 +1,8 @@
 use std::hash::{Hash, Hasher};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
 use crate::engine::sst::block::Shard;
+use crate::util::NumaTopology;
 
 /// 缓存 Key：唯一定位一个 block
 #[derive(Clone, Debug, Eq)]
@@ -22,10 +24,116 @@ impl Hash for BlockCacheKey {
     }
 }
 
+/// What happened to a cached block, reported to a `BlockCacheListener`.
+#[derive(Debug, Clone)]
+pub enum BlockCacheEvent {
+    /// Dropped to stay under capacity.
+    Evicted { key: BlockCacheKey, charge: usize },
+    /// Removed with `erase`.
+    Erased { key: BlockCacheKey, charge: usize },
+    /// Overwritten by an insert of the same key; `charge` is the old one.
+    Replaced { key: BlockCacheKey, charge: usize },
+    /// Passed over by eviction because `refs` holders outside the cache still
+    /// have it; `owner` is the caller that last fetched it.
+    PinnedSkipped { key: BlockCacheKey, charge: usize, refs: usize, owner: &'static str },
+    /// Eviction gave up with the shard still over its capacity, everything
+    /// left being pinned.
+    OverCapacity { usage: usize, capacity: usize },
+}
+
+/// Gets every `BlockCacheEvent`, after the shard lock is released, so it may
+/// call back into the cache. Runs on the reading thread; keep it cheap.
+pub trait BlockCacheListener: Send + Sync {
+    fn on_event(&self, event: &BlockCacheEvent);
+}
+
+/// A cached block that someone outside the cache still holds, see
+/// `BlockCache::pinned_blocks`.
+#[derive(Debug, Clone)]
+pub struct PinnedBlock {
+    pub key: BlockCacheKey,
+    pub charge: usize,
+    /// Holders besides the cache.
+    pub refs: usize,
+    /// Caller that last fetched or inserted the block ("get", "iterator",
+    /// "compaction"), "" when not told.
+    pub owner: &'static str,
+}
+
+/// 每次 insert 顺手从旧 shard 里清掉这么多 entry，换完 shard 数后旧 shard 迟早清空
+const RETIRED_DRAIN_PER_INSERT: usize = 4;
+
+/// 每个 NUMA 节点分到的 shard 数：2^n，至少 1 个
+fn shards_per_node(shards: usize, nodes: usize) -> usize {
+    (shards / nodes.max(1)).max(1).next_power_of_two()
+}
+
+/// 一组 shard，按节点连续排：节点 n 的是 [n * per_node, (n + 1) * per_node)，
+/// 节点内按 mask 取下标。不分节点时 nodes = 1
+struct ShardSet<V> {
+    shards: Vec<Mutex<Shard<V>>>,
+    mask: usize,
+    nodes: usize,
+}
+
+impl<V> ShardSet<V> {
+    fn new(capacity_bytes: usize, shards: usize, nodes: usize, record_events: bool) -> Self {
+        let nodes = nodes.max(1);
+        let per_node = shards_per_node(shards, nodes);
+        let per = capacity_bytes / (per_node * nodes);
+        let shards = (0..per_node * nodes)
+            .map(|_| {
+                let mut shard = Shard::new(per);
+                shard.events = record_events.then(Vec::new);
+                Mutex::new(shard)
+            })
+            .collect();
+        Self { shards, mask: per_node - 1, nodes }
+    }
+
+    /// `key` 在节点 `node` 上的 shard
+    #[inline]
+    fn shard(&self, key: &BlockCacheKey, node: usize) -> &Mutex<Shard<V>> {
+        // 一个简单、够用的混合 hash（你也可换成更强的）
+        let x = key.file_number ^ key.block_offset.rotate_left(17);
+        &self.shards[(node % self.nodes) * (self.mask + 1) + ((x as usize) & self.mask)]
+    }
+
+    /// `key` 可能在的所有 shard，本地节点的在前
+    fn candidates<'a>(&'a self, key: &'a BlockCacheKey, local: usize) -> impl Iterator<Item = &'a Mutex<Shard<V>>> + 'a {
+        (0..self.nodes).map(move |i| self.shard(key, local + i))
+    }
+}
+
+struct ShardTable<V> {
+    current: ShardSet<V>,
+    /// 换 shard 数之前的那组：还没被访问到的 entry 留在这里，
+    /// 命中时搬进 current，insert 时顺手清掉几个最旧的，清空后丢掉
+    retired: Option<ShardSet<V>>,
+    /// 总容量，均分到 current 的各个 shard
+    capacity: usize,
+}
+
 /// Sharded LRU Block Cache
+///
+/// Capacity and shard count can change at runtime (`set_capacity`,
+/// `set_num_shards`). A new shard count takes effect at once for inserts;
+/// entries in the old shards move over when they are read and are otherwise
+/// dropped a few per insert, so until then usage may exceed the capacity by
+/// what the old shards still hold.
+///
+/// Built with `new_numa`, the shards are split evenly over the NUMA nodes.
+/// A block is inserted into the shards of the node the inserting thread runs
+/// on, and lookups try the local node's shard before the other nodes'.
 pub struct BlockCache<V> {
-    shards: Vec<Mutex<Shard<V>>>,
-    shard_mask: usize, // 如果 shards 数是 2^n，mask 更快
+    table: RwLock<ShardTable<V>>,
+    /// 旧 shard 里还剩的 entry 数，到 0 就可以把它们丢掉
+    retired_entries: AtomicUsize,
+    /// 轮着清旧 shard
+    drain_cursor: AtomicUsize,
+    listener: RwLock<Option<Arc<dyn BlockCacheListener>>>,
+    /// 分节点时的拓扑；None 就是一个节点
+    numa: Option<&'static NumaTopology>,
 }
 
 impl<V> BlockCache<V>
@@ -34,66 +142,399 @@ where
 {
     /// shards 建议 16/32/64；capacity_bytes 总容量，自动均分到各 shard
     pub fn new(capacity_bytes: usize, shards: usize) -> Self {
-        assert!(shards > 0);
-        let shards_pow2 = shards.next_power_of_two();
-        let per = capacity_bytes / shards_pow2;
+        Self::with_topology(capacity_bytes, shards, None)
+    }
 
-        let mut v = Vec::with_capacity(shards_pow2);
-        for _ in 0..shards_pow2 {
-            v.push(Mutex::new(Shard::new(per)));
-        }
+    /// Like `new`, with the `shards` split over the NUMA nodes of this machine
+    /// (at least one per node). Same as `new` on a single-node machine.
+    pub fn new_numa(capacity_bytes: usize, shards: usize) -> Self {
+        let topology = NumaTopology::get();
+        Self::with_topology(capacity_bytes, shards, (topology.num_nodes() > 1).then_some(topology))
+    }
 
+    fn with_topology(capacity_bytes: usize, shards: usize, numa: Option<&'static NumaTopology>) -> Self {
+        assert!(shards > 0);
+        let nodes = numa.map_or(1, NumaTopology::num_nodes);
         Self {
-            shards: v,
-            shard_mask: shards_pow2 - 1,
+            table: RwLock::new(ShardTable {
+                current: ShardSet::new(capacity_bytes, shards, nodes, false),
+                retired: None,
+                capacity: capacity_bytes,
+            }),
+            retired_entries: AtomicUsize::new(0),
+            drain_cursor: AtomicUsize::new(0),
+            listener: RwLock::new(None),
+            numa,
         }
     }
 
+    fn num_nodes(&self) -> usize {
+        self.numa.map_or(1, NumaTopology::num_nodes)
+    }
+
+    /// 当前线程所在节点
     #[inline]
-    fn shard_index(&self, key: &BlockCacheKey) -> usize {
-        // 一个简单、够用的混合 hash（你也可换成更强的）
-        let x = key.file_number ^ key.block_offset.rotate_left(17);
-        (x as usize) & self.shard_mask
+    fn local_node(&self) -> usize {
+        self.numa.map_or(0, NumaTopology::current_node)
+    }
+
+    /// Number of NUMA nodes the shards are split over; 1 when not NUMA-aware.
+    pub fn nodes(&self) -> usize {
+        self.table.read().unwrap().current.nodes
+    }
+
+    /// Reports evictions, erases and pinned entries eviction had to skip to
+    /// `listener`; `None` stops reporting.
+    pub fn set_listener(&self, listener: Option<Arc<dyn BlockCacheListener>>) {
+        let mut current = self.listener.write().unwrap();
+        let table = self.table.read().unwrap();
+        for shard in table.all_shards() {
+            shard.lock().unwrap().events = listener.as_ref().map(|_| Vec::new());
+        }
+        *current = listener;
+    }
+
+    /// 放掉 shard 锁之后把这次攒下的事件交给 listener
+    fn notify(&self, mut shard: MutexGuard<'_, Shard<V>>) {
+        let events = match &mut shard.events {
+            Some(events) if !events.is_empty() => std::mem::take(events),
+            _ => return,
+        };
+        drop(shard);
+        if let Some(listener) = self.listener.read().unwrap().as_ref() {
+            for event in &events {
+                listener.on_event(event);
+            }
+        }
+    }
+
+    /// Blocks held outside the cache right now, which eviction can't drop.
+    /// Walks every shard under its lock; meant for debugging.
+    pub fn pinned_blocks(&self) -> Vec<PinnedBlock> {
+        let table = self.table.read().unwrap();
+        table.all_shards().flat_map(|m| m.lock().unwrap().pinned()).collect()
     }
 
     /// 获取一个 block（命中则 move-to-front）
     pub fn get(&self, key: &BlockCacheKey) -> Option<Arc<V>> {
-        let idx = self.shard_index(key);
-        let mut g = self.shards[idx].lock().unwrap();
-        g.get(key)
+        self.get_for(key, "")
+    }
+
+    /// Like `get`, remembering `owner` for `pinned_blocks`.
+    pub fn get_for(&self, key: &BlockCacheKey, owner: &'static str) -> Option<Arc<V>> {
+        let table = self.table.read().unwrap();
+        let local = self.local_node();
+        // 先本地节点，再别的节点；远端命中不搬，两边的线程都还会读它
+        for shard in table.current.candidates(key, local) {
+            if let Some(v) = shard.lock().unwrap().get_for(key, owner) {
+                return Some(v);
+            }
+        }
+        // 换过 shard 数：可能还在旧 shard 里，搬过来
+        let retired = table.retired.as_ref()?;
+        let (value, charge, old_owner) = retired
+            .candidates(key, local)
+            .find_map(|shard| shard.lock().unwrap().take(key))?;
+        self.retired_entries.fetch_sub(1, Ordering::Relaxed);
+        let owner = if owner.is_empty() { old_owner } else { owner };
+        let mut g = table.current.shard(key, local).lock().unwrap();
+        g.insert_for(key.clone(), Arc::clone(&value), charge, owner);
+        self.notify(g);
+        Some(value)
     }
 
     /// 插入/更新一个 block
     ///
     /// charge：该 block 占用字节（通常 = block_bytes.len() + overhead）
     pub fn insert(&self, key: BlockCacheKey, value: Arc<V>, charge: usize) {
-        let idx = self.shard_index(&key);
-        let mut g = self.shards[idx].lock().unwrap();
-        g.insert(key, value, charge);
+        self.insert_for(key, value, charge, "")
+    }
+
+    /// Like `insert`, remembering `owner` for `pinned_blocks`.
+    pub fn insert_for(&self, key: BlockCacheKey, value: Arc<V>, charge: usize, owner: &'static str) {
+        let drained = {
+            let table = self.table.read().unwrap();
+            let local = self.local_node();
+            if let Some(retired) = &table.retired {
+                self.take_retired(retired, &key, local);
+            }
+            // 只留一份：别的节点上的旧版本拿掉
+            for shard in table.current.candidates(&key, local).skip(1) {
+                shard.lock().unwrap().take(&key);
+            }
+            let mut g = table.current.shard(&key, local).lock().unwrap();
+            g.insert_for(key, value, charge, owner);
+            self.notify(g);
+            self.drain_retired(&table, RETIRED_DRAIN_PER_INSERT)
+        };
+        if drained {
+            self.drop_retired();
+        }
     }
 
     /// 删除一个 block（如果存在）
     pub fn erase(&self, key: &BlockCacheKey) {
-        let idx = self.shard_index(key);
-        let mut g = self.shards[idx].lock().unwrap();
-        g.erase(key);
+        let table = self.table.read().unwrap();
+        let local = self.local_node();
+        if let Some(retired) = &table.retired {
+            self.take_retired(retired, key, local);
+        }
+        for shard in table.current.candidates(key, local) {
+            let mut g = shard.lock().unwrap();
+            g.erase(key);
+            self.notify(g);
+        }
     }
 
-    /// 当前使用字节（总和）
+    /// 旧 shard 里的 `key` 拿掉（不管在哪个节点）
+    fn take_retired(&self, retired: &ShardSet<V>, key: &BlockCacheKey, local: usize) {
+        for shard in retired.candidates(key, local) {
+            if shard.lock().unwrap().take(key).is_some() {
+                self.retired_entries.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 从旧 shard 里清掉最多 `budget` 个最旧的 entry：还被 pin 住的搬进新 shard，别的丢掉。
+    /// 旧 shard 清空了返回 true
+    fn drain_retired(&self, table: &ShardTable<V>, budget: usize) -> bool {
+        let Some(retired) = &table.retired else { return false };
+        for _ in 0..budget {
+            if self.retired_entries.load(Ordering::Relaxed) == 0 {
+                break;
+            }
+            // 从游标开始找一个还有东西的旧 shard
+            let start = self.drain_cursor.fetch_add(1, Ordering::Relaxed);
+            let n = retired.shards.len();
+            let taken = (0..n).find_map(|i| retired.shards[(start + i) % n].lock().unwrap().take_lru());
+            let Some((key, value, charge, owner)) = taken else {
+                self.retired_entries.store(0, Ordering::Relaxed);
+                break;
+            };
+            self.retired_entries.fetch_sub(1, Ordering::Relaxed);
+            let mut g = table.current.shard(&key, self.local_node()).lock().unwrap();
+            if Arc::strong_count(&value) > 1 {
+                g.insert_for(key, value, charge, owner);
+            } else if let Some(events) = &mut g.events {
+                events.push(BlockCacheEvent::Evicted { key, charge });
+            }
+            self.notify(g);
+        }
+        self.retired_entries.load(Ordering::Relaxed) == 0
+    }
+
+    fn drop_retired(&self) {
+        let mut table = self.table.write().unwrap();
+        if self.retired_entries.load(Ordering::Relaxed) == 0 {
+            table.retired = None;
+        }
+    }
+
+    /// Changes the total capacity, split evenly over the shards. Shrinking
+    /// evicts right away, except for pinned blocks.
+    pub fn set_capacity(&self, capacity_bytes: usize) {
+        let mut table = self.table.write().unwrap();
+        table.capacity = capacity_bytes;
+        let per = capacity_bytes / table.current.shards.len();
+        let table = RwLockWriteGuard::downgrade(table);
+        for shard in &table.current.shards {
+            let mut g = shard.lock().unwrap();
+            g.set_capacity(per);
+            self.notify(g);
+        }
+    }
+
+    /// Rebuilds the cache with `shards` shards (rounded up to a power of two
+    /// per NUMA node). Cached blocks are not rehashed here: they move to their
+    /// new shard when read, or are dropped a few per insert.
+    pub fn set_num_shards(&self, shards: usize) {
+        let nodes = self.num_nodes();
+        let record_events = self.listener.read().unwrap().is_some();
+        let mut table = self.table.write().unwrap();
+        if shards_per_node(shards, nodes) * nodes == table.current.shards.len() {
+            return;
+        }
+        // 上一次换的还没清完：剩下的直接清掉，只留一组旧 shard
+        if table.retired.is_some() {
+            self.drain_retired(&table, usize::MAX);
+            table.retired = None;
+        }
+        let next = ShardSet::new(table.capacity, shards, nodes, record_events);
+        let old = std::mem::replace(&mut table.current, next);
+        let entries = old.shards.iter().map(|m| m.lock().unwrap().len()).sum();
+        self.retired_entries.store(entries, Ordering::Relaxed);
+        table.retired = Some(old);
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.table.read().unwrap().current.shards.len()
+    }
+
+    /// 当前使用字节（总和），含还没清完的旧 shard
     pub fn usage_bytes(&self) -> usize {
-        self.shards
-            .iter()
-            .map(|m| m.lock().unwrap().usage)
-            .sum()
+        let table = self.table.read().unwrap();
+        table.all_shards().map(|m| m.lock().unwrap().usage).sum()
+    }
+
+    /// 被外部 pin 住（淘汰不掉）的字节
+    pub fn pinned_usage_bytes(&self) -> usize {
+        let table = self.table.read().unwrap();
+        table.all_shards().map(|m| m.lock().unwrap().pinned_usage()).sum()
     }
 
     /// 总容量（总和）
     pub fn capacity_bytes(&self) -> usize {
-        self.shards
-            .iter()
-            .map(|m| m.lock().unwrap().capacity)
-            .sum()
+        let table = self.table.read().unwrap();
+        table.current.shards.iter().map(|m| m.lock().unwrap().capacity).sum()
     }
 }
 
+impl<V> ShardTable<V> {
+    fn all_shards(&self) -> impl Iterator<Item = &Mutex<Shard<V>>> {
+        self.current.shards.iter().chain(self.retired.iter().flat_map(|r| r.shards.iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u64) -> BlockCacheKey {
+        BlockCacheKey { file_number: 1, block_offset: n * 4096 }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<BlockCacheEvent>>);
+
+    impl BlockCacheListener for Recorder {
+        fn on_event(&self, event: &BlockCacheEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    impl Recorder {
+        fn evicted(&self) -> Vec<u64> {
+            self.0.lock().unwrap().iter()
+                .filter_map(|e| match e {
+                    BlockCacheEvent::Evicted { key, .. } => Some(key.block_offset / 4096),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
 
+    #[test]
+    fn listener_sees_evictions_and_erases() {
+        let cache = BlockCache::new(300, 1);
+        let recorder = Arc::new(Recorder::default());
+        cache.set_listener(Some(recorder.clone()));
+
+        for n in 0..3 {
+            cache.insert(key(n), Arc::new(n), 100);
+        }
+        // 1 最近用过，淘汰的是 0
+        cache.get(&key(0));
+        cache.insert(key(3), Arc::new(3), 100);
+        assert_eq!(recorder.evicted(), vec![1]);
+        assert!(cache.get(&key(1)).is_none());
+
+        cache.erase(&key(2));
+        let events = recorder.0.lock().unwrap();
+        assert!(matches!(events.last(), Some(BlockCacheEvent::Erased { charge: 100, .. })));
+    }
+
+    #[test]
+    fn pinned_blocks_survive_eviction() {
+        let cache = BlockCache::new(200, 1);
+        let recorder = Arc::new(Recorder::default());
+        cache.set_listener(Some(recorder.clone()));
+
+        cache.insert_for(key(0), Arc::new(0u64), 100, "iterator");
+        let held = cache.get_for(&key(0), "compaction").unwrap();
+        cache.insert(key(1), Arc::new(1), 100);
+        cache.insert(key(2), Arc::new(2), 100);
+
+        // 0 最旧但被拿着：跳过它，淘汰 1
+        assert_eq!(*cache.get(&key(0)).unwrap(), 0);
+        assert!(cache.get(&key(1)).is_none());
+        assert_eq!(recorder.evicted(), vec![1]);
+        assert!(recorder.0.lock().unwrap().iter().any(|e| matches!(
+            e, BlockCacheEvent::PinnedSkipped { refs: 1, owner: "compaction", .. }
+        )));
+
+        let pinned = cache.pinned_blocks();
+        assert_eq!(pinned.len(), 1);
+        assert_eq!((pinned[0].key.clone(), pinned[0].refs, pinned[0].owner), (key(0), 1, "compaction"));
+        assert_eq!(cache.pinned_usage_bytes(), 100);
+
+        drop(held);
+        assert!(cache.pinned_blocks().is_empty());
+        assert_eq!(cache.pinned_usage_bytes(), 0);
+    }
+
+    #[test]
+    fn shrinking_capacity_evicts_at_once() {
+        let cache = BlockCache::new(400, 2);
+        for n in 0..4 {
+            cache.insert(key(n), Arc::new(n), 50);
+        }
+        assert_eq!(cache.capacity_bytes(), 400);
+        cache.set_capacity(100);
+        assert_eq!(cache.capacity_bytes(), 100);
+        assert!(cache.usage_bytes() <= 100);
+    }
+
+    #[test]
+    fn changing_the_shard_count_keeps_cached_blocks() {
+        let cache = BlockCache::new(1 << 20, 4);
+        for n in 0..16 {
+            cache.insert(key(n), Arc::new(n), 10);
+        }
+        cache.set_num_shards(8);
+        assert_eq!(cache.num_shards(), 8);
+        // 取整到 2 的幂；数目没变就什么都不做
+        cache.set_num_shards(7);
+        assert_eq!(cache.num_shards(), 8);
+        assert_eq!(cache.capacity_bytes(), 1 << 20);
+
+        // 读到的从旧 shard 搬过来
+        for n in 0..8 {
+            assert_eq!(*cache.get(&key(n)).unwrap(), n);
+        }
+        assert_eq!(cache.usage_bytes(), 160);
+
+        // 没人读的几个一批地在 insert 时清掉
+        for n in 100..110 {
+            cache.insert(key(n), Arc::new(n), 10);
+        }
+        assert!(cache.table.read().unwrap().retired.is_none());
+        for n in 0..8 {
+            assert!(cache.get(&key(n)).is_some());
+        }
+        for n in 8..16 {
+            assert!(cache.get(&key(n)).is_none());
+        }
+        assert_eq!(cache.usage_bytes(), 180);
+    }
+
+    #[test]
+    fn shards_split_evenly_over_numa_nodes() {
+        assert_eq!(shards_per_node(16, 1), 16);
+        assert_eq!(shards_per_node(16, 2), 8);
+        // 取整到 2 的幂
+        assert_eq!(shards_per_node(12, 2), 8);
+        // 节点比 shard 多：每个节点至少一个
+        assert_eq!(shards_per_node(2, 4), 1);
+
+        let cache = BlockCache::new_numa(1 << 20, 16);
+        let nodes = cache.nodes();
+        assert_eq!(nodes, NumaTopology::get().num_nodes().max(1));
+        assert_eq!(cache.num_shards(), shards_per_node(16, nodes) * nodes);
+
+        cache.insert(key(1), Arc::new(1u64), 10);
+        assert_eq!(*cache.get(&key(1)).unwrap(), 1);
+        cache.erase(&key(1));
+        assert!(cache.get(&key(1)).is_none());
+        assert_eq!(cache.usage_bytes(), 0);
+    }
+}
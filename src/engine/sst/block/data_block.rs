@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use crate::engine::sst::block::{get_varint32, put_varint32};
 use crate::engine::sst::iterator::DataBlockIter;
 use crate::error::DBError;
@@ -16,6 +17,9 @@ pub trait BlockTrait: Send + Sync {
 pub struct DataBlock {
     pub(crate) data: Vec<u8>,
     pub(crate) restart_offsets: Vec<u32>,
+    /// 每个 restart 点的完整 key 在 data 中的范围（restart entry 的 shared 总是 0）。
+    /// from_bytes 时解析一次，seek 直接在内存里二分，不用每次 probe 都重新解 varint
+    pub(crate) restart_keys: Vec<std::ops::Range<usize>>,
 }
 
 impl BlockTrait for DataBlock {
@@ -41,99 +45,90 @@ impl DataBlock {
         }
 
         let mut restart_offsets = Vec::with_capacity(n);
+        let mut restart_keys = Vec::with_capacity(n);
         for i in 0..n {
             let off = u32::from_le_bytes(
                 data[restarts_start + i*4 .. restarts_start + (i+1)*4]
                     .try_into().unwrap()
             );
             restart_offsets.push(off);
+
+            // 空 block 只有一个指向 restart array 的 restart 点，没有 entry
+            if (off as usize) < restarts_start {
+                let mut pos = off as usize;
+                let (_, key) = read_entry_key(&data[..restarts_start], &mut pos)?;
+                let end = pos;
+                restart_keys.push(end - key.len()..end);
+            }
         }
 
-        Ok(Self { data, restart_offsets })
+        Ok(Self { data, restart_offsets, restart_keys })
     }
 
+    /// 没有 entry 的 block
+    pub fn empty() -> Self {
+        Self { data: vec![0; 4], restart_offsets: Vec::new(), restart_keys: Vec::new() }
+    }
 
-    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        // 1️⃣ 二分 restart array
-        let mut left = 0;
-        let mut right = self.restart_offsets.len();
-
-        while left < right {
-            let mid = (left + right) / 2;
-            let off = self.restart_offsets[mid] as usize;
-
-            let mut cur = off;
-            let (_, first_key) = read_entry_key(&self.data, &mut cur).ok()?;
-
-            if first_key.as_slice() < key {
-                left = mid + 1;
-            } else {
-                right = mid;
-            }
-        }
+    /// 第 i 个 restart 点的 key
+    #[inline]
+    pub(crate) fn restart_key(&self, i: usize) -> &[u8] {
+        &self.data[self.restart_keys[i].clone()]
+    }
 
-        // 2️⃣ 从 restart 点线性 scan
-        if left > 0 {
-            left -= 1;
-        }
-        let mut offset = self.restart_offsets[left] as usize;
-        let mut last_key = Vec::new();
+    /// 最后一个 restart key < target 的 restart 下标（都 >= target 时为 0）
+    pub(crate) fn find_restart_point(&self, target: &[u8]) -> usize {
+        let n = self.restart_keys.partition_point(|r| &self.data[r.clone()] < target);
+        n.saturating_sub(1)
+    }
 
-        while offset < self.data.len() {
-            let (shared, unshared, value_len, key_delta, value) =
-                read_entry(&self.data, &mut offset).ok()?;
 
-            last_key.truncate(shared);
-            last_key.extend_from_slice(&key_delta);
+    /// 点查：返回 block 内 value 的切片，不分配
+    ///
+    /// 线性 scan 时拼 key 用线程本地的 scratch buffer
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        KEY_SCRATCH.with(|scratch| self.get_with_scratch(key, &mut scratch.borrow_mut()))
+    }
 
-            match last_key.as_slice().cmp(key) {
-                std::cmp::Ordering::Equal => return Some(value),
-                std::cmp::Ordering::Greater => return None,
-                std::cmp::Ordering::Less => {}
-            }
+    /// 同 get，调用方提供拼 key 的 buffer（会被清空）
+    pub fn get_with_scratch(&self, key: &[u8], scratch: &mut Vec<u8>) -> Option<&[u8]> {
+        match self.lower_bound_entry(key, scratch)? {
+            (k, v) if k == key => Some(v),
+            _ => None,
         }
-        None
     }
 
-    /// 返回 “第一条 key >= target”的 value（Vec<u8>）
+    /// 返回 “第一条 key >= target”的 value（block 内切片）
     /// 找不到则返回 None。
     ///
     /// 这是 IndexBlock / MetaIndexBlock 的核心能力。
-    pub fn lower_bound_value(&self, target: &[u8]) -> Option<Vec<u8>> {
-        // 1) 二分 restart array，找到可能包含 target 的 restart 区间
-        let mut left = 0usize;
-        let mut right = self.restart_offsets.len();
-
-        while left < right {
-            let mid = (left + right) / 2;
-            let off = self.restart_offsets[mid] as usize;
-
-            let mut cur = off;
-            let (_, first_key) = read_entry_key(&self.data, &mut cur).ok()?;
-
-            if first_key.as_slice() < target {
-                left = mid + 1;
-            } else {
-                right = mid;
-            }
-        }
+    pub fn lower_bound_value(&self, target: &[u8]) -> Option<&[u8]> {
+        KEY_SCRATCH.with(|scratch| {
+            self.lower_bound_entry(target, &mut scratch.borrow_mut()).map(|(_, v)| v)
+        })
+    }
 
-        if left > 0 {
-            left -= 1;
+    /// 第一条 key >= target 的 (key, value)；key 拼在 scratch 里
+    fn lower_bound_entry<'s>(&self, target: &[u8], scratch: &'s mut Vec<u8>) -> Option<(&'s [u8], &[u8])> {
+        if self.restart_keys.is_empty() {
+            return None;
         }
 
+        // 1) 在缓存的 restart key 上二分，找到可能包含 target 的 restart 区间
+        let left = self.find_restart_point(target);
+
         // 2) 从该 restart 点线性 scan，找第一条 >= target
         let mut offset = self.restart_offsets[left] as usize;
-        let mut last_key = Vec::new();
+        let end = self.data_entries_end();
+        scratch.clear();
 
-        while offset < self.data_entries_end() {
-            let (shared, _unshared, _vlen, key_delta, value) = read_entry(&self.data, &mut offset).ok()?;
-            last_key.truncate(shared);
-            last_key.extend_from_slice(&key_delta);
+        while offset < end {
+            let (shared, key_delta, value) = read_entry(&self.data, &mut offset).ok()?;
+            scratch.truncate(shared);
+            scratch.extend_from_slice(key_delta);
 
-            match last_key.as_slice().cmp(target) {
-                std::cmp::Ordering::Less => continue,
-                std::cmp::Ordering::Equal | std::cmp::Ordering::Greater => return Some(value),
+            if scratch.as_slice() >= target {
+                return Some((scratch.as_slice(), value));
             }
         }
 
@@ -141,46 +136,59 @@ impl DataBlock {
     }
 
     #[inline]
-    fn data_entries_end(&self) -> usize {
+    pub(crate) fn data_entries_end(&self) -> usize {
         // entries 的结束位置 = restart array 开始位置
         let n = self.restart_offsets.len();
         self.data.len() - 4 - n * 4
     }
 
     pub fn iter(&self) -> DataBlockIter<'_> {
-        DataBlockIter {
-            block: self,
-            offset: 0,
-            key_buf:Vec::new(),
-            value_range: 0..0,
-            valid: false,
-        }
+        DataBlockIter::new(self)
     }
 }
 
-fn read_entry(
-    data: &[u8],
+impl AsRef<DataBlock> for DataBlock {
+    fn as_ref(&self) -> &DataBlock {
+        self
+    }
+}
+
+thread_local! {
+    static KEY_SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// 解析一条 entry：(shared, key_delta, value)，切片直接指向 block 数据
+fn read_entry<'a>(
+    data: &'a [u8],
     pos: &mut usize,
-) -> Result<(usize, usize, usize, Vec<u8>, Vec<u8>), DBError> {
+) -> Result<(usize, &'a [u8], &'a [u8]), DBError> {
     let shared = get_varint32(data, pos) as usize;
     let unshared = get_varint32(data, pos) as usize;
     let value_len = get_varint32(data, pos) as usize;
 
-    let key_delta = data[*pos .. *pos + unshared].to_vec();
+    if *pos + unshared + value_len > data.len() {
+        return Err(DBError::Corruption("block entry out of range".into()));
+    }
+
+    let key_delta = &data[*pos .. *pos + unshared];
     *pos += unshared;
 
-    let value = data[*pos .. *pos + value_len].to_vec();
+    let value = &data[*pos .. *pos + value_len];
     *pos += value_len;
 
-    Ok((shared, unshared, value_len, key_delta, value))
+    Ok((shared, key_delta, value))
 }
 
-fn read_entry_key(data: &[u8], pos: &mut usize) -> Result<(usize, Vec<u8>), DBError> {
+fn read_entry_key<'a>(data: &'a [u8], pos: &mut usize) -> Result<(usize, &'a [u8]), DBError> {
     let shared = get_varint32(data, pos) as usize;
     let unshared = get_varint32(data, pos) as usize;
     let _value_len = get_varint32(data, pos) as usize;
 
-    let key = data[*pos .. *pos + unshared].to_vec();
+    if *pos + unshared > data.len() {
+        return Err(DBError::Corruption("block entry out of range".into()));
+    }
+
+    let key = &data[*pos .. *pos + unshared];
     *pos += unshared;
 
     Ok((shared, key))
@@ -262,3 +270,53 @@ impl DataBlockBuilder {
         self.buf.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::sst::block::BlockBuilder;
+
+    #[test]
+    fn get_and_lower_bound_return_block_slices() {
+        let mut builder = BlockBuilder::new(2);
+        for k in ["apple", "apricot", "banana", "cherry", "date"] {
+            builder.add(k.as_bytes(), format!("v-{k}").as_bytes());
+        }
+        let block = DataBlock::from_bytes(builder.finish().to_vec()).unwrap();
+
+        assert_eq!(block.get(b"banana"), Some(&b"v-banana"[..]));
+        assert_eq!(block.get(b"date"), Some(&b"v-date"[..]));
+        assert_eq!(block.get(b"blueberry"), None);
+        assert_eq!(block.get(b"zzz"), None);
+
+        assert_eq!(block.lower_bound_value(b"b"), Some(&b"v-banana"[..]));
+        assert_eq!(block.lower_bound_value(b"a"), Some(&b"v-apple"[..]));
+        assert_eq!(block.lower_bound_value(b"e"), None);
+    }
+
+    #[test]
+    fn seek_binary_searches_the_restart_keys() {
+        let mut builder = BlockBuilder::new(3);
+        let keys: Vec<String> = (0..10).map(|i| format!("k{:02}", i * 2)).collect();
+        for k in &keys {
+            builder.add(k.as_bytes(), k.as_bytes());
+        }
+        let block = DataBlock::from_bytes(builder.finish().to_vec()).unwrap();
+
+        // 每 3 条一个 restart，restart entry 存的是完整 key
+        let restarts: Vec<&[u8]> = (0..block.restart_keys.len()).map(|i| block.restart_key(i)).collect();
+        assert_eq!(restarts, vec![&b"k00"[..], b"k06", b"k12", b"k18"]);
+
+        assert_eq!(block.find_restart_point(b"a"), 0);
+        assert_eq!(block.find_restart_point(b"k06"), 0);
+        assert_eq!(block.find_restart_point(b"k07"), 1);
+        assert_eq!(block.find_restart_point(b"z"), 3);
+
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(block.get(k.as_bytes()), Some(k.as_bytes()));
+            let odd = format!("k{:02}", i * 2 + 1);
+            assert_eq!(block.get(odd.as_bytes()), None);
+        }
+        assert_eq!(block.lower_bound_value(b"k13"), Some(&b"k14"[..]));
+    }
+}
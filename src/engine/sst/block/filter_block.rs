@@ -18,6 +18,11 @@ impl BlockTrait for FilterBlock {
 }
 
 impl FilterBlock {
+    /// 常驻内存字节数
+    pub fn memory_usage(&self) -> usize {
+        self.data.capacity() + self.offsets.capacity() * size_of::<u32>()
+    }
+
     pub fn from_bytes(data: Vec<u8>) -> Result<Self, DBError> {
         if data.len() < 5 {
             return Err(DBError::Corruption("filter block too small".into()));
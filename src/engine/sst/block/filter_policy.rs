@@ -4,6 +4,12 @@ pub trait FilterPolicy: Send + Sync {
     fn name(&self) -> &str;
     fn create_filter(&self, keys: &[&[u8]]) -> Vec<u8>;
     fn may_match(&self, key: &[u8], filter: &[u8]) -> bool;
+
+    /// Bits per key of a bloom filter, so the policy can be rebuilt from its
+    /// name when a column family's options are read back from the MANIFEST.
+    fn bits_per_key(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub struct BloomFilterPolicy {
@@ -27,6 +33,10 @@ impl FilterPolicy for BloomFilterPolicy {
         "custom.BloomFilter"
     }
 
+    fn bits_per_key(&self) -> Option<usize> {
+        Some(self.bits_per_key)
+    }
+
     fn create_filter(&self, keys: &[&[u8]]) -> Vec<u8> {
         let mut builder = BloomFilterBuilder::new(self.bits_per_key);
         for key in keys {
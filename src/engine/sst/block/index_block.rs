@@ -1,43 +1,329 @@
 use crate::DBError;
-use crate::engine::sst::block::{BlockBuilder, DataBlock};
-use crate::engine::sst::format::BlockHandle;
+use crate::engine::sst::block::{BlockBuilder, DataBlock, BLOCK_TRAILER_SIZE};
+use crate::engine::sst::format::{get_varint64, put_varint64, BlockHandle};
+use std::sync::Arc;
 use crate::engine::sst::iterator::{DataBlockIter, InternalIterator};
+use crate::engine::sst::iterator::data_block_iter::BlockRef;
+
+/// index 格式
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexType {
+    /// value = BlockHandle
+    #[default]
+    BinarySearch = 0,
+    /// value = BlockHandle + block 的 first key；
+    /// seek 时不读 data block 就能判断目标是否落在 block 开头（lazy load）
+    BinarySearchWithFirstKey = 1,
+}
+
+impl IndexType {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(IndexType::BinarySearch),
+            1 => Some(IndexType::BinarySearchWithFirstKey),
+            _ => None,
+        }
+    }
+}
+
+/// index value 编码
+///
+/// - restart 点（或未开启 delta 编码）：完整 BlockHandle = varint(offset) + varint(size)
+/// - 非 restart 点：只写 zigzag varint(size - prev.size)，
+///   offset 由上一个 handle 推出：prev.offset + prev.size + trailer
+///   （data block 在文件里连续，每个后面跟 BLOCK_TRAILER_SIZE 字节 trailer，handle.size 不含它）
+/// - BinarySearchWithFirstKey：handle 之后再跟 varint(len) + first_key
+pub fn encode_index_value(handle: &BlockHandle, prev: Option<&BlockHandle>, dst: &mut Vec<u8>) {
+    match prev {
+        None => handle.encode_to(dst),
+        Some(p) => {
+            debug_assert_eq!(handle.offset, next_block_offset(p), "data blocks must be contiguous");
+            let delta = handle.size as i64 - p.size as i64;
+            put_varint64(dst, zigzag_encode(delta));
+        }
+    }
+}
+
+pub fn decode_index_value(
+    src: &[u8],
+    pos: &mut usize,
+    prev: Option<&BlockHandle>,
+) -> Result<BlockHandle, DBError> {
+    match prev {
+        None => BlockHandle::decode_from(src, pos)
+            .ok_or(DBError::Corruption("bad index block handle".into())),
+        Some(p) => {
+            let delta = get_varint64(src, pos)
+                .map(zigzag_decode)
+                .ok_or(DBError::Corruption("bad delta index value".into()))?;
+            let size = p.size as i64 + delta;
+            if size < 0 {
+                return Err(DBError::Corruption("negative block size in index".into()));
+            }
+            Ok(BlockHandle { offset: next_block_offset(p), size: size as u64 })
+        }
+    }
+}
+
+/// 紧跟在 `h` 这个 block 及其 trailer 之后的偏移
+fn next_block_offset(h: &BlockHandle) -> u64 {
+    h.offset + h.size + BLOCK_TRAILER_SIZE as u64
+}
+
+fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn encode_first_key(first_key: &[u8], dst: &mut Vec<u8>) {
+    put_varint64(dst, first_key.len() as u64);
+    dst.extend_from_slice(first_key);
+}
+
+fn decode_first_key<'a>(src: &'a [u8], pos: &mut usize) -> Result<&'a [u8], DBError> {
+    let len = get_varint64(src, pos)
+        .ok_or(DBError::Corruption("bad index first key length".into()))? as usize;
+    if *pos + len > src.len() {
+        return Err(DBError::Corruption("index first key out of range".into()));
+    }
+    let k = &src[*pos..*pos + len];
+    *pos += len;
+    Ok(k)
+}
 
 /// 读 SST 时的 IndexBlock
 pub struct IndexBlock {
     block: DataBlock,
+    /// footer format_version >= 2 时 index value 是 delta 编码
+    value_delta_encoded: bool,
+    index_type: IndexType,
 }
 
 impl IndexBlock {
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, DBError> {
+        Self::from_bytes_with_encoding(bytes, false, IndexType::BinarySearch)
+    }
+
+    pub fn from_bytes_with_encoding(
+        bytes: Vec<u8>,
+        value_delta_encoded: bool,
+        index_type: IndexType,
+    ) -> Result<Self, DBError> {
         Ok(Self {
             block: DataBlock::from_bytes(bytes)?,
+            value_delta_encoded,
+            index_type,
         })
     }
 
+    /// 常驻内存字节数
+    pub fn memory_usage(&self) -> usize {
+        self.block.data.capacity()
+            + self.block.restart_offsets.capacity() * size_of::<u32>()
+            + self.block.restart_keys.capacity() * size_of::<std::ops::Range<usize>>()
+    }
+
     /// 给定 user_key/内部 key，找到对应 DataBlock 的 handle
     ///
     /// 约定：index entry key 是 data block 的 largest_key，
     /// 所以要找 "第一个 >= target_key 的 entry"
     pub fn find_data_block(&self, target_key: &[u8]) -> Result<Option<BlockHandle>, DBError> {
-        let mut iter = DataBlockIter::new(&self.block);
-        <DataBlockIter as InternalIterator>::seek(&mut iter, target_key);
+        Ok(self.find_data_block_entry(target_key)?.map(|(_, h)| h))
+    }
+
+    /// 同 find_data_block，额外返回命中的 index key（即该 data block 的 largest_key）
+    pub fn find_data_block_entry(&self, target_key: &[u8]) -> Result<Option<(Vec<u8>, BlockHandle)>, DBError> {
+        let mut iter = self.iter();
+        iter.seek(target_key);
         if !iter.valid() { return Ok(None); }
-        Ok(Some(BlockHandle::decode_from_bytes(&iter.value())?))
+        Ok(iter.handle().map(|h| (iter.key().to_vec(), h)))
     }
 
     pub fn raw_block(&self) -> &DataBlock {
         &self.block
     }
 
-    pub fn iter(&self) -> DataBlockIter<'_> {
-        DataBlockIter {
-            block: &self.block,
-            offset: 0,
-            key_buf:Vec::new(),
-            value_range: 0..0,
-            valid: false,
+    pub fn value_delta_encoded(&self) -> bool {
+        self.value_delta_encoded
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    /// index 迭代器：value() 总是返回完整编码的 BlockHandle，
+    /// 上层（TwoLevelIterator）不需要关心 delta 编码。
+    pub fn iter(&self) -> IndexIter<'_> {
+        self.iter_over(BlockRef::Borrowed(&self.block))
+    }
+
+    /// 同 iter，iterator 自己拿着这个 IndexBlock 的引用
+    pub fn shared_iter(self: &Arc<Self>) -> IndexIter<'static> {
+        self.iter_over(BlockRef::Shared(Arc::clone(self) as Arc<dyn AsRef<DataBlock> + Send + Sync>))
+    }
+
+    fn iter_over<'a>(&self, block: BlockRef<'a>) -> IndexIter<'a> {
+        IndexIter {
+            inner: DataBlockIter::with_block(block),
+            value_delta_encoded: self.value_delta_encoded,
+            index_type: self.index_type,
+            handle: None,
+            first_key: 0..0,
+            value_buf: Vec::with_capacity(20),
+        }
+    }
+}
+
+impl AsRef<DataBlock> for IndexBlock {
+    fn as_ref(&self) -> &DataBlock {
+        &self.block
+    }
+}
+
+/// IndexBlock 迭代器：在 DataBlockIter 之上还原 delta 编码的 handle
+pub struct IndexIter<'a> {
+    inner: DataBlockIter<'a>,
+    value_delta_encoded: bool,
+    index_type: IndexType,
+    handle: Option<BlockHandle>,
+    /// first key 在 block data 里的范围（仅 BinarySearchWithFirstKey）
+    first_key: std::ops::Range<usize>,
+    value_buf: Vec<u8>,
+}
+
+impl<'a> IndexIter<'a> {
+    pub fn handle(&self) -> Option<BlockHandle> {
+        self.handle
+    }
+
+    /// entry_offset：当前 entry 在 block 里的起始偏移，用来判断是否是 restart 点
+    fn decode_current(&mut self, entry_offset: usize) {
+        if !self.inner.valid() {
+            self.handle = None;
+            return;
+        }
+
+        let is_restart = !self.value_delta_encoded
+            || self.inner.block.restart_offsets.binary_search(&(entry_offset as u32)).is_ok();
+        let prev = if is_restart { None } else { self.handle };
+
+        let v = self.inner.value();
+        let mut pos = 0usize;
+        let mut first_key_len = 0usize;
+        let decoded = decode_index_value(v, &mut pos, prev.as_ref()).and_then(|h| {
+            if self.index_type == IndexType::BinarySearchWithFirstKey {
+                first_key_len = decode_first_key(v, &mut pos)?.len();
+            }
+            Ok(h)
+        });
+
+        // 记录 first key 在 block data 里的范围，避免拷贝
+        let end = self.inner.value_range.start + pos;
+        self.first_key = end - first_key_len..end;
+
+        match decoded {
+            Ok(h) => {
+                self.value_buf.clear();
+                h.encode_to(&mut self.value_buf);
+                self.handle = Some(h);
+            }
+            Err(_) => {
+                self.inner.valid = false;
+                self.handle = None;
+            }
+        }
+    }
+}
+
+impl<'a> InternalIterator for IndexIter<'a> {
+    fn valid(&self) -> bool {
+        self.inner.valid() && self.handle.is_some()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.inner.seek_to_first();
+        self.decode_current(0);
+    }
+
+    fn seek_to_last(&mut self) {
+        // delta 编码只能从 restart 点开始顺序还原：从最后一个 restart 点走到底
+        let Some(last) = self.inner.block.restart_keys.len().checked_sub(1) else {
+            self.inner.valid = false;
+            self.handle = None;
+            return;
+        };
+        let (last_offset, end) = (self.inner.block.restart_offsets[last] as usize, self.inner.block.data_entries_end());
+        self.inner.seek_to_restart_point(last);
+        self.decode_current(last_offset);
+        while self.valid() && self.inner.offset < end {
+            self.next();
+        }
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        if self.inner.block.data.is_empty() {
+            self.inner.valid = false;
+            self.handle = None;
+            return;
+        }
+
+        // delta 编码只能从 restart 点开始顺序还原
+        let r = self.inner.find_restart_point(target);
+        self.inner.seek_to_restart_point(r);
+        self.decode_current(self.inner.block.restart_offsets[r] as usize);
+
+        while self.valid() && self.inner.key() < target {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let entry_offset = self.inner.offset;
+        self.inner.next();
+        self.decode_current(entry_offset);
+    }
+
+    fn prev(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let current = self.inner.entry_offset;
+        let Some(r) = self.inner.restart_point_before(current) else {
+            self.inner.valid = false;
+            self.handle = None;
+            return;
+        };
+        self.inner.seek_to_restart_point(r);
+        self.decode_current(self.inner.block.restart_offsets[r] as usize);
+        while self.valid() && self.inner.offset < current {
+            self.next();
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value_buf
+    }
+
+    fn index_first_key(&self) -> Option<&[u8]> {
+        if self.index_type != IndexType::BinarySearchWithFirstKey || !self.valid() {
+            return None;
         }
+        Some(&self.inner.block.data[self.first_key.clone()])
     }
 }
 
@@ -45,18 +331,47 @@ impl IndexBlock {
 pub struct IndexBlockBuilder {
     builder: BlockBuilder,
     last_key: Vec<u8>, // 保证 key 单调递增（可选校验）
+
+    restart_interval: usize,
+    num_entries: usize,
+    value_delta_encoded: bool,
+    index_type: IndexType,
+    last_handle: Option<BlockHandle>,
 }
 
 impl IndexBlockBuilder {
     pub fn new(restart_interval: usize) -> Self {
+        Self::with_delta_encoding(restart_interval, false)
+    }
+
+    pub fn with_delta_encoding(restart_interval: usize, value_delta_encoded: bool) -> Self {
+        Self::with_index_type(restart_interval, value_delta_encoded, IndexType::BinarySearch)
+    }
+
+    pub fn with_index_type(
+        restart_interval: usize,
+        value_delta_encoded: bool,
+        index_type: IndexType,
+    ) -> Self {
+        let restart_interval = restart_interval.max(1);
         Self {
             builder: BlockBuilder::new(restart_interval),
             last_key: Vec::new(),
+            restart_interval,
+            num_entries: 0,
+            value_delta_encoded,
+            index_type,
+            last_handle: None,
         }
     }
 
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
     /// 向 index block 添加：largest_key -> BlockHandle
-    pub fn add(&mut self, largest_key_in_data_block: &[u8], handle: BlockHandle) {
+    /// first_key 只在 BinarySearchWithFirstKey 下写入
+    pub fn add(&mut self, largest_key_in_data_block: &[u8], first_key: &[u8], handle: BlockHandle) {
         // （可选）校验递增：index keys 必须严格递增
         if !self.last_key.is_empty() && largest_key_in_data_block <= self.last_key.as_slice() {
             // 工业级一般是 debug assert；你也可返回 Result
@@ -65,17 +380,73 @@ impl IndexBlockBuilder {
         self.last_key.clear();
         self.last_key.extend_from_slice(largest_key_in_data_block);
 
+        // 与 BlockBuilder 的 restart 规则保持一致：第 0, k, 2k... 条是 restart 点
+        let is_restart = self.num_entries % self.restart_interval == 0;
+        let prev = if self.value_delta_encoded && !is_restart {
+            self.last_handle.as_ref()
+        } else {
+            None
+        };
+
         let mut v = Vec::with_capacity(20);
-        handle.encode_to(&mut v);
+        encode_index_value(&handle, prev, &mut v);
+        if self.index_type == IndexType::BinarySearchWithFirstKey {
+            encode_first_key(first_key, &mut v);
+        }
         self.builder.add(largest_key_in_data_block, &v);
+
+        self.last_handle = Some(handle);
+        self.num_entries += 1;
     }
 
     /// 结束 index block 构建，返回 bytes（写入 SST 文件）
     pub fn finish(& mut self) -> Vec<u8> {
-        self.builder.finish()
+        self.builder.finish().to_vec()
     }
 
     pub fn is_empty(&self) -> bool {
         self.builder.is_empty()
     }
-}
\ No newline at end of file
+
+    pub fn reset(&mut self) {
+        self.builder.reset();
+        self.last_key.clear();
+        self.num_entries = 0;
+        self.last_handle = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_encoded_handles_round_trip_across_blocks() {
+        // 和 TableBuilder::write_block 一样排：每个 block 后面跟 trailer，大小各不相同
+        let mut handles = Vec::new();
+        let mut offset = 0u64;
+        for i in 0..10u64 {
+            let size = 4000 + (i * 37) % 300;
+            handles.push(BlockHandle { offset, size });
+            offset += size + BLOCK_TRAILER_SIZE as u64;
+        }
+
+        for index_type in [IndexType::BinarySearch, IndexType::BinarySearchWithFirstKey] {
+            let mut builder = IndexBlockBuilder::with_index_type(4, true, index_type);
+            for (i, h) in handles.iter().enumerate() {
+                builder.add(format!("key{:03}", i).as_bytes(), format!("first{:03}", i).as_bytes(), *h);
+            }
+            let index = IndexBlock::from_bytes_with_encoding(builder.finish(), true, index_type).unwrap();
+
+            let mut it = index.iter();
+            it.seek_to_first();
+            let mut decoded = Vec::new();
+            while it.valid() {
+                decoded.push(it.handle().unwrap());
+                it.next();
+            }
+            assert_eq!(decoded, handles);
+            assert_eq!(index.find_data_block(b"key007").unwrap(), Some(handles[7]));
+        }
+    }
+}
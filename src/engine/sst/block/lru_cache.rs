@@ -6,6 +6,8 @@ pub struct Node<V> {
     pub(crate) key: BlockCacheKey,
     pub(crate) value: Arc<V>,
     pub(crate) charge: usize,
+    /// 最近一次取走 / 放进这个 block 的调用方，排查 pin 住的 block 用；"" 表示没说
+    pub(crate) owner: &'static str,
     pub(crate) prev: Option<NonNull<Node<V>>>,
     pub(crate) next: Option<NonNull<Node<V>>>,
 }
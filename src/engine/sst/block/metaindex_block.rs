@@ -48,6 +48,21 @@ impl MetaIndexBlock {
         self.find(&key)
     }
 
+    /// `prefixfilter.<extractor 名>` 项：(extractor 名, handle)
+    pub fn find_prefix_filter(&self) -> Result<Option<(String, BlockHandle)>, DBError> {
+        const PREFIX_FILTER_KEY: &[u8] = b"prefixfilter.";
+        let mut iter = self.block.iter();
+        iter.seek(PREFIX_FILTER_KEY);
+        if !iter.valid() || !iter.key().starts_with(PREFIX_FILTER_KEY) {
+            return Ok(None);
+        }
+        let name = String::from_utf8_lossy(&iter.key()[PREFIX_FILTER_KEY.len()..]).into_owned();
+        let mut pos = 0usize;
+        let h = BlockHandle::decode_from(iter.value(), &mut pos)
+            .ok_or_else(|| DBError::Corruption("bad prefix filter block handle".into()))?;
+        Ok(Some((name, h)))
+    }
+
     pub fn raw_block(&self) -> &DataBlock {
         &self.block
     }
@@ -119,7 +134,7 @@ impl MetaIndexBlockBuilder {
     }
 
     pub fn finish(&mut self) -> Vec<u8> {
-        self.builder.finish()
+        self.builder.finish().to_vec()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -17,11 +17,11 @@ pub use lsm_codec::{get_varint32, get_varint64, put_varint32, put_varint64};
 pub use restart::parse_restarts;
 pub use data_block::{DataBlock,BlockTrait,BlockType};
 pub use filter_block::FilterBlock;
-pub use filter_policy::{FilterPolicy, BloomFilterBuilder};
+pub use filter_policy::{FilterPolicy, BloomFilterBuilder, BloomFilterPolicy};
 pub use lru_cache::{LruList, Node};
-pub use block_cache::{BlockCache, BlockCacheKey};
+pub use block_cache::{BlockCache, BlockCacheEvent, BlockCacheKey, BlockCacheListener, PinnedBlock};
 pub use shard_cache::Shard;
 pub use metaindex_block::{MetaIndexBlock, MetaIndexBlockBuilder};
-pub use index_block::IndexBlock;
+pub use index_block::{IndexBlock, IndexBlockBuilder, IndexIter, IndexType};
 pub use filter_block_builder::FilterBlockBuilder;
-pub use table_properties::TableProperties;
+pub use table_properties::{TableProperties, GLOBAL_SEQNO_LEN};
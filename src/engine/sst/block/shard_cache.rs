@@ -2,15 +2,21 @@ use std::collections::HashMap;
 use std::ptr::NonNull;
 use std::sync::Arc;
 use crate::engine::sst::block::{LruList, Node};
-use crate::engine::sst::block::BlockCacheKey;
+use crate::engine::sst::block::{BlockCacheEvent, BlockCacheKey, PinnedBlock};
 
 pub struct Shard<V> {
     pub(crate) map: HashMap<BlockCacheKey, NonNull<Node<V>>>,
     pub(crate) lru: LruList<V>,
     pub(crate) usage: usize,
     pub(crate) capacity: usize,
+    /// 有 listener 时攒下这次操作的事件，BlockCache 放锁之后再通知
+    pub(crate) events: Option<Vec<BlockCacheEvent>>,
 }
 
+// SAFETY: Node 只由 Shard 自己分配/释放，Shard 总是放在 Mutex 里访问，
+// 裸指针不会被外部持有；value 本身是 Arc<V>，要求 V: Send + Sync
+unsafe impl<V: Send + Sync> Send for Shard<V> {}
+
 impl<V> Shard<V> {
     pub fn new(capacity: usize) -> Self {
         Self {
@@ -18,13 +24,22 @@ impl<V> Shard<V> {
             lru: LruList::new(),
             usage: 0,
             capacity,
+            events: None,
         }
     }
 
     pub fn get(&mut self, key: &BlockCacheKey) -> Option<Arc<V>> {
-        let ptr = *self.map.get(key)?;
+        self.get_for(key, "")
+    }
+
+    /// 同 get，记下是谁拿走的
+    pub fn get_for(&mut self, key: &BlockCacheKey, owner: &'static str) -> Option<Arc<V>> {
+        let mut ptr = *self.map.get(key)?;
         // SAFETY: ptr 始终指向我们分配的 Node，且在 map 删除前不会释放
-        let node = unsafe { ptr.as_ref() };
+        let node = unsafe { ptr.as_mut() };
+        if !owner.is_empty() {
+            node.owner = owner;
+        }
 
         // move-to-front（最近使用）
         self.lru.move_to_front(ptr);
@@ -32,7 +47,17 @@ impl<V> Shard<V> {
         Some(Arc::clone(&node.value))
     }
 
+    fn record(&mut self, event: BlockCacheEvent) {
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+    }
+
     pub fn insert(&mut self, key: BlockCacheKey, value: Arc<V>, charge: usize) {
+        self.insert_for(key, value, charge, "")
+    }
+
+    pub fn insert_for(&mut self, key: BlockCacheKey, value: Arc<V>, charge: usize, owner: &'static str) {
         // 如果已存在：更新 value/charge，并 move-to-front
         if let Some(&ptr) = self.map.get(&key) {
             let mut ptr = ptr;
@@ -41,11 +66,16 @@ impl<V> Shard<V> {
 
             // usage 修正：先减旧 charge
             self.usage = self.usage.saturating_sub(node.charge);
+            let old_charge = node.charge;
 
             // SAFETY: 我们需要可变引用来更新 node 字段
             let node_mut = unsafe { ptr.as_mut() };
             node_mut.value = value;
             node_mut.charge = charge;
+            if !owner.is_empty() {
+                node_mut.owner = owner;
+            }
+            self.record(BlockCacheEvent::Replaced { key, charge: old_charge });
 
             self.usage += charge;
 
@@ -59,6 +89,7 @@ impl<V> Shard<V> {
             key: key.clone(),
             value,
             charge,
+            owner,
             prev: None,
             next: None,
         });
@@ -73,18 +104,75 @@ impl<V> Shard<V> {
     }
 
     pub fn erase(&mut self, key: &BlockCacheKey) {
-        if let Some(ptr) = self.map.remove(key) {
-            // 从 LRU 链表移除
-            self.lru.remove(ptr);
-
-            // 回收 node
-            // SAFETY: ptr 来自 Box::into_raw，且我们已经从 list/map 去掉它
-            let boxed = unsafe { Box::from_raw(ptr.as_ptr()) };
-            self.usage = self.usage.saturating_sub(boxed.charge);
-            // drop(boxed) 自动释放
+        if let Some(node) = self.remove(key) {
+            self.record(BlockCacheEvent::Erased { key: key.clone(), charge: node.charge });
         }
     }
 
+    /// 摘掉并交出一个 entry，不算淘汰；换 shard 时搬家用
+    pub(crate) fn take(&mut self, key: &BlockCacheKey) -> Option<(Arc<V>, usize, &'static str)> {
+        self.remove(key).map(|node| (node.value, node.charge, node.owner))
+    }
+
+    /// 同 take，取最久没用的那个
+    pub(crate) fn take_lru(&mut self) -> Option<(BlockCacheKey, Arc<V>, usize, &'static str)> {
+        // SAFETY: 链表里的 ptr 都有效
+        let key = unsafe { self.lru.back()?.as_ref() }.key.clone();
+        self.remove(&key).map(|node| (node.key, node.value, node.charge, node.owner))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_if_needed();
+    }
+
+    /// 摘掉一个 node 交给调用方
+    fn remove(&mut self, key: &BlockCacheKey) -> Option<Box<Node<V>>> {
+        let ptr = self.map.remove(key)?;
+        // 从 LRU 链表移除
+        self.lru.remove(ptr);
+
+        // 回收 node
+        // SAFETY: ptr 来自 Box::into_raw，且我们已经从 list/map 去掉它
+        let boxed = unsafe { Box::from_raw(ptr.as_ptr()) };
+        self.usage = self.usage.saturating_sub(boxed.charge);
+        Some(boxed)
+    }
+
+    /// 还被外部持有的 block
+    pub fn pinned(&self) -> Vec<PinnedBlock> {
+        self.map
+            .values()
+            .filter_map(|ptr| {
+                // SAFETY: map 里的 ptr 都有效
+                let node = unsafe { ptr.as_ref() };
+                let refs = Arc::strong_count(&node.value) - 1;
+                (refs > 0).then(|| PinnedBlock {
+                    key: node.key.clone(),
+                    charge: node.charge,
+                    refs,
+                    owner: node.owner,
+                })
+            })
+            .collect()
+    }
+
+    /// 仍被外部（iterator / 读请求）持有的 block 占用字节
+    pub fn pinned_usage(&self) -> usize {
+        self.map
+            .values()
+            .map(|ptr| {
+                // SAFETY: map 里的 ptr 都有效
+                let node = unsafe { ptr.as_ref() };
+                if Arc::strong_count(&node.value) > 1 { node.charge } else { 0 }
+            })
+            .sum()
+    }
+
     pub fn evict_if_needed(&mut self) {
         if self.usage <= self.capacity {
             return;
@@ -110,12 +198,36 @@ impl<V> Shard<V> {
             // pinned: 外部还持有引用，不淘汰
             if Arc::strong_count(&victim.value) > 1 {
                 // 这个对象很热但被 pin 住了；我们把它先移到 front，避免一直卡在尾部
+                let event = BlockCacheEvent::PinnedSkipped {
+                    key: victim.key.clone(),
+                    charge: victim.charge,
+                    refs: Arc::strong_count(&victim.value) - 1,
+                    owner: victim.owner,
+                };
+                self.record(event);
                 self.lru.move_to_front(victim_ptr);
                 continue;
             }
 
             let victim_key = victim.key.clone();
-            self.erase(&victim_key);
+            if let Some(node) = self.remove(&victim_key) {
+                self.record(BlockCacheEvent::Evicted { key: victim_key, charge: node.charge });
+            }
+        }
+
+        // 剩下的全被 pin 住了，只能先超着
+        if self.usage > self.capacity {
+            let (usage, capacity) = (self.usage, self.capacity);
+            self.record(BlockCacheEvent::OverCapacity { usage, capacity });
         }
     }
-}
\ No newline at end of file
+}
+impl<V> Drop for Shard<V> {
+    fn drop(&mut self) {
+        // 换 shard 数之后旧 shard 会被 drop，node 是手动分配的，要自己还
+        for (_, ptr) in self.map.drain() {
+            // SAFETY: ptr 来自 Box::into_raw，只在这里释放一次
+            drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use crate::DBError;
@@ -19,8 +19,16 @@ pub struct TableProperties {
     pub column_family_id: ColumnFamilyId,
     pub smallest_key: Mutex<Option<Vec<u8>>>,
     pub largest_key: Mutex<Option<Vec<u8>>>,
+    /// IndexType 的编码值
+    pub index_type: u8,
+    /// ingest 的文件统一使用的 seqno（0 = 未设置，用 key 自带的 seq）。
+    /// 固定 8 字节编码在 properties block 末尾，ingest 时可以原地改写。
+    pub global_seqno: AtomicU64,
 }
 
+/// global_seqno 槽位长度（位于 properties block 最后 8 字节）
+pub const GLOBAL_SEQNO_LEN: usize = 8;
+
 impl Clone for TableProperties {
     fn clone(&self) -> Self {
         Self {
@@ -32,6 +40,8 @@ impl Clone for TableProperties {
             column_family_id: self.column_family_id.clone(),
             smallest_key: Mutex::new(self.smallest_key.lock().unwrap().clone()),
             largest_key: Mutex::new(self.largest_key.lock().unwrap().clone()),
+            index_type: self.index_type,
+            global_seqno: AtomicU64::new(self.global_seqno.load(Ordering::Relaxed)),
         }
     }
 }
@@ -47,6 +57,8 @@ impl TableProperties {
             column_family_id: cf,
             smallest_key: Mutex::new(None),
             largest_key: Mutex::new(None),
+            index_type: 0,
+            global_seqno: AtomicU64::new(0),
         }
     }
 
@@ -89,6 +101,10 @@ impl TableProperties {
             None => &[],
         };
         LsmCodec::put_length_prefixed_bytes(&mut w, lk_bytes)?;
+        w.write_all(&[self.index_type])?;
+
+        // global_seqno 用定长编码，方便 ingest 时原地改写
+        w.write_all(&self.global_seqno.load(Ordering::SeqCst).to_le_bytes())?;
         Ok(())
     }
 
@@ -107,6 +123,18 @@ impl TableProperties {
         let smallest_key = LsmCodec::get_length_prefixed_bytes(&mut r)?;
         let largest_key = LsmCodec::get_length_prefixed_bytes(&mut r)?;
 
+        // 剩余部分：[扩展字段...] + global_seqno(8)
+        // 旧文件没有 global_seqno 槽位，按 0 处理
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        let (ext, global_seqno) = if rest.len() >= GLOBAL_SEQNO_LEN {
+            let (ext, slot) = rest.split_at(rest.len() - GLOBAL_SEQNO_LEN);
+            (ext, u64::from_le_bytes(slot.try_into().unwrap()))
+        } else {
+            (&rest[..0], 0)
+        };
+        let index_type = ext.first().copied().unwrap_or(0);
+
         Ok(Self {
             num_entries: AtomicU64::new(num_entries),
             data_size: AtomicU64::new(data_size),
@@ -116,6 +144,8 @@ impl TableProperties {
             column_family_id: cf,
             smallest_key: Mutex::new(Some(smallest_key)),
             largest_key: Mutex::new(Some(largest_key)),
+            index_type,
+            global_seqno: AtomicU64::new(global_seqno),
         })
     }
 
@@ -131,7 +161,7 @@ impl TableProperties {
     ) -> Result<BlockHandle, DBError> {
         // 1️⃣ 编码 TableProperties
         let mut buf = Vec::new();
-        self.encode_to(&mut buf);  // 现有方法，把最新统计信息编码到字节
+        self.encode(&mut buf)?;  // 把最新统计信息编码到字节
 
         // 2️⃣ 写入 dst
         dst.write_all(&buf)?;
@@ -144,4 +174,58 @@ impl TableProperties {
 
         Ok(handle)
     }
+
+    /// global_seqno 槽位在文件里的绝对偏移
+    pub fn global_seqno_offset(props_handle: BlockHandle) -> Result<u64, DBError> {
+        if props_handle.size < GLOBAL_SEQNO_LEN as u64 {
+            return Err(DBError::Corruption("properties block has no global_seqno slot".into()));
+        }
+        Ok(props_handle.offset + props_handle.size - GLOBAL_SEQNO_LEN as u64)
+    }
+
+    /// 原地改写 global_seqno，不需要重写整个文件
+    pub fn update_global_seqno<F: Write + Seek>(
+        file: &mut F,
+        props_handle: BlockHandle,
+        seqno: SequenceNumber,
+    ) -> Result<(), DBError> {
+        let off = Self::global_seqno_offset(props_handle)?;
+        file.seek(SeekFrom::Start(off))?;
+        file.write_all(&seqno.to_le_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn global_seqno_is_rewritten_in_place() {
+        let props = TableProperties::new(3);
+        props.record_entry(5, b"a", 10);
+        props.record_entry(9, b"z", 20);
+
+        // properties block 前面还有别的 block
+        let mut file = Cursor::new(vec![0u8; 100]);
+        file.seek(SeekFrom::End(0)).unwrap();
+        let handle = props.write_block(&mut file, 100).unwrap();
+        let read_back = |file: &Cursor<Vec<u8>>| {
+            let bytes = &file.get_ref()[handle.offset as usize..(handle.offset + handle.size) as usize];
+            TableProperties::decode(bytes).unwrap()
+        };
+        assert_eq!(read_back(&file).global_seqno.load(Ordering::SeqCst), 0);
+
+        TableProperties::update_global_seqno(&mut file, handle, 4242).unwrap();
+        let decoded = read_back(&file);
+        assert_eq!(decoded.global_seqno.load(Ordering::SeqCst), 4242);
+        // 其余字段不受影响
+        assert_eq!(decoded.column_family_id, 3);
+        assert_eq!(decoded.num_entries.load(Ordering::SeqCst), 2);
+        assert_eq!(decoded.max_sequence.load(Ordering::SeqCst), 9);
+        assert_eq!(decoded.largest_key.lock().unwrap().as_deref(), Some(&b"z"[..]));
+        assert_eq!(file.get_ref().len() as u64, handle.offset + handle.size);
+    }
 }
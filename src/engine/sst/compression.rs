@@ -0,0 +1,99 @@
+use std::borrow::Cow;
+use crate::DBError;
+use crate::util::CompressionType;
+
+/// zstd 默认级别，和 RocksDB 一致
+const ZSTD_LEVEL: i32 = 3;
+
+/// 压缩一个 block，返回写进文件的内容和实际用的压缩类型。
+///
+/// 省不到 1/8 空间的 block 原样存，trailer 里记 NoCompression（和 RocksDB 的做法一样），
+/// 读的时候少一次解压。
+pub fn compress_block(compression: CompressionType, raw: &[u8]) -> Result<(Cow<'_, [u8]>, CompressionType), DBError> {
+    let compressed = match compression {
+        CompressionType::NoCompression => return Ok((Cow::Borrowed(raw), CompressionType::NoCompression)),
+        CompressionType::SnappyCompression => snap::raw::Encoder::new()
+            .compress_vec(raw)
+            .map_err(|e| DBError::Other(format!("snappy compression failed: {}", e)))?,
+        CompressionType::Lz4Compression => lz4_flex::block::compress_prepend_size(raw),
+        CompressionType::ZstdCompression => zstd::bulk::compress(raw, ZSTD_LEVEL)?,
+        other => {
+            return Err(DBError::InvalidArgument(format!("compression {:?} is not supported", other)));
+        }
+    };
+
+    if compressed.len() >= raw.len() - raw.len() / 8 {
+        return Ok((Cow::Borrowed(raw), CompressionType::NoCompression));
+    }
+    Ok((Cow::Owned(compressed), compression))
+}
+
+/// 按 trailer 里的类型字节解压 block
+pub fn decompress_block(compression: u8, data: &[u8]) -> Result<Vec<u8>, DBError> {
+    let corrupt = |what: &str, e: &dyn std::fmt::Display| DBError::Corruption(format!("{} block: {}", what, e));
+    match CompressionType::from_u8(compression) {
+        Some(CompressionType::NoCompression) => Ok(data.to_vec()),
+        Some(CompressionType::SnappyCompression) => snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|e| corrupt("snappy", &e)),
+        Some(CompressionType::Lz4Compression) => lz4_flex::block::decompress_size_prepended(data)
+            .map_err(|e| corrupt("lz4", &e)),
+        Some(CompressionType::ZstdCompression) => zstd::stream::decode_all(data)
+            .map_err(|e| corrupt("zstd", &e)),
+        Some(other) => Err(DBError::Corruption(format!("compression {:?} is not supported", other))),
+        None => Err(DBError::Corruption(format!("unknown block compression type {}", compression))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressible() -> Vec<u8> {
+        (0..4096u32).flat_map(|i| format!("key{:08}", i % 64).into_bytes()).collect()
+    }
+
+    #[test]
+    fn every_supported_type_round_trips() {
+        let raw = compressible();
+        for compression in [
+            CompressionType::SnappyCompression,
+            CompressionType::Lz4Compression,
+            CompressionType::ZstdCompression,
+        ] {
+            let (stored, used) = compress_block(compression, &raw).unwrap();
+            assert_eq!(used, compression);
+            assert!(stored.len() < raw.len());
+            assert_eq!(decompress_block(used.to_u8(), &stored).unwrap(), raw);
+        }
+    }
+
+    #[test]
+    fn blocks_that_barely_shrink_are_stored_raw() {
+        // 伪随机字节几乎压不动
+        let mut x = 88172645463325252u64;
+        let raw: Vec<u8> = (0..4096).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect();
+        let (stored, used) = compress_block(CompressionType::ZstdCompression, &raw).unwrap();
+        assert_eq!(used, CompressionType::NoCompression);
+        assert!(matches!(stored, Cow::Borrowed(_)));
+        assert_eq!(decompress_block(used.to_u8(), &stored).unwrap(), raw);
+    }
+
+    #[test]
+    fn unsupported_and_corrupt_blocks_are_rejected() {
+        assert!(matches!(
+            compress_block(CompressionType::ZlibCompression, b"abc"),
+            Err(DBError::InvalidArgument(_))
+        ));
+        assert!(matches!(decompress_block(0xee, b"abc"), Err(DBError::Corruption(_))));
+        assert!(matches!(
+            decompress_block(CompressionType::SnappyCompression.to_u8(), &[0xff; 8]),
+            Err(DBError::Corruption(_))
+        ));
+    }
+}
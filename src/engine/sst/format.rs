@@ -1,8 +1,8 @@
 use std::io::{Read, Seek, SeekFrom};
 use crate::DBError;
-use crate::util::TABLE_MAGIC;
+use crate::util::{CompressionType, TABLE_MAGIC, TABLE_MAGIC_V2};
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct BlockHandle {
     pub offset: u64,
     pub size: u64,
@@ -30,46 +30,193 @@ impl BlockHandle {
     }
 }
 
+/// Block checksum algorithm recorded in the footer.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumType {
+    NoChecksum = 0,
+    #[default]
+    Crc32c = 1,
+}
+
+impl ChecksumType {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(ChecksumType::NoChecksum),
+            1 => Some(ChecksumType::Crc32c),
+            _ => None,
+        }
+    }
+}
+
+/// block 后面的 trailer：压缩类型 (1) + crc32c(内容 || 类型) (4)
+pub fn encode_block_trailer(contents: &[u8], compression: CompressionType, checksum: ChecksumType) -> [u8; 5] {
+    let typ = compression.to_u8();
+    let crc = match checksum {
+        ChecksumType::NoChecksum => 0,
+        ChecksumType::Crc32c => block_crc(contents, typ),
+    };
+    let mut trailer = [0u8; 5];
+    trailer[0] = typ;
+    trailer[1..].copy_from_slice(&crc.to_le_bytes());
+    trailer
+}
+
+/// 校验 `block`（内容 + 5 字节 trailer）的 crc，不对时报 Corruption，带上文件号和 block 偏移
+pub fn verify_block_trailer(block: &[u8], checksum: ChecksumType, file_number: u64, offset: u64) -> Result<(), DBError> {
+    if checksum == ChecksumType::NoChecksum {
+        return Ok(());
+    }
+    let size = block.len() - 5;
+    let stored = u32::from_le_bytes(block[size + 1..].try_into().unwrap());
+    let computed = block_crc(&block[..size], block[size]);
+    if stored != computed {
+        return Err(DBError::Corruption(format!(
+            "block checksum mismatch in sst {} at offset {}: stored {:#010x}, computed {:#010x}",
+            file_number, offset, stored, computed
+        )));
+    }
+    Ok(())
+}
+
+fn block_crc(contents: &[u8], typ: u8) -> u32 {
+    crc32c::crc32c_append(crc32c::crc32c(contents), &[typ])
+}
+
+/// 旧版 48 字节 footer 的 format_version
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+/// 从这个版本开始 index value 使用 delta 编码的 BlockHandle
+pub const DELTA_INDEX_FORMAT_VERSION: u32 = 2;
+/// 新版 footer 写出的 format_version
+pub const CURRENT_FORMAT_VERSION: u32 = DELTA_INDEX_FORMAT_VERSION;
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Footer {
     pub metaindex_handle: BlockHandle, // 可先空
     pub index_handle: BlockHandle,
+    pub checksum_type: ChecksumType,
+    pub format_version: u32,
+    pub compression: CompressionType,
 }
 
 impl Footer {
     // RocksDB/LevelDB footer 固定长度（LevelDB 是 48 bytes）
-    pub const ENCODED_LEN: usize = 48;
+    pub const LEGACY_ENCODED_LEN: usize = 48;
+
+    // 新版 footer:
+    //   checksum_type(1) + compression(1) + metaindex/index handles(40, padded)
+    //   + format_version(4) + magic(8) = 54 bytes
+    pub const ENCODED_LEN: usize = 54;
+
+    const HANDLES_LEN: usize = 40;
+
+    pub fn new(metaindex_handle: BlockHandle, index_handle: BlockHandle) -> Self {
+        Self {
+            metaindex_handle,
+            index_handle,
+            checksum_type: ChecksumType::default(),
+            format_version: CURRENT_FORMAT_VERSION,
+            compression: CompressionType::NoCompression,
+        }
+    }
+
+    /// 当前 footer 写到文件里实际占用的字节数
+    pub fn encoded_len(&self) -> usize {
+        if self.format_version == LEGACY_FORMAT_VERSION {
+            Self::LEGACY_ENCODED_LEN
+        } else {
+            Self::ENCODED_LEN
+        }
+    }
+
+    /// index block 的 value 是否是 delta 编码
+    pub fn index_value_delta_encoded(&self) -> bool {
+        self.format_version >= DELTA_INDEX_FORMAT_VERSION
+    }
+
+    /// 按 format_version 选择新/旧布局编码
+    pub fn encode(&self) -> Vec<u8> {
+        if self.format_version == LEGACY_FORMAT_VERSION {
+            return self.encode_legacy().to_vec();
+        }
 
-    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
         let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+        buf.push(self.checksum_type as u8);
+        buf.push(self.compression.to_u8());
+        self.metaindex_handle.encode_to(&mut buf);
+        self.index_handle.encode_to(&mut buf);
+
+        // handles padding 到 2 + 40 bytes
+        buf.resize(2 + Self::HANDLES_LEN, 0);
+        buf.extend_from_slice(&self.format_version.to_le_bytes());
+        buf.extend_from_slice(&TABLE_MAGIC_V2.to_le_bytes());
+        buf
+    }
+
+    pub fn encode_legacy(&self) -> [u8; Self::LEGACY_ENCODED_LEN] {
+        let mut buf = Vec::with_capacity(Self::LEGACY_ENCODED_LEN);
         self.metaindex_handle.encode_to(&mut buf);
         self.index_handle.encode_to(&mut buf);
 
         // padding 到 40 bytes，然后写 magic u64 = 8 bytes，共 48
-        if buf.len() < 40 {
-            buf.resize(40, 0);
+        if buf.len() < Self::HANDLES_LEN {
+            buf.resize(Self::HANDLES_LEN, 0);
         }
         buf.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
 
-        let mut out = [0u8; Self::ENCODED_LEN];
-        out.copy_from_slice(&buf[..Self::ENCODED_LEN]);
+        let mut out = [0u8; Self::LEGACY_ENCODED_LEN];
+        out.copy_from_slice(&buf[..Self::LEGACY_ENCODED_LEN]);
         out
     }
 
+    /// 解码 footer：根据末尾 magic 判断是新版 54 字节还是旧版 48 字节布局。
+    /// `input` 必须以 footer 结尾（可以比 footer 长）。
     pub fn decode(input: &[u8]) -> Option<Self> {
-        if input.len() != Self::ENCODED_LEN {
+        if input.len() < 8 {
             return None;
         }
-        let magic = u64::from_le_bytes(input[40..48].try_into().ok()?);
-        if magic != TABLE_MAGIC {
-            return None;
+        let magic = u64::from_le_bytes(input[input.len() - 8..].try_into().ok()?);
+        match magic {
+            TABLE_MAGIC_V2 => Self::decode_new(&input[input.len().checked_sub(Self::ENCODED_LEN)?..]),
+            TABLE_MAGIC => Self::decode_legacy(&input[input.len().checked_sub(Self::LEGACY_ENCODED_LEN)?..]),
+            _ => None,
         }
+    }
+
+    fn decode_legacy(input: &[u8]) -> Option<Self> {
         let mut pos = 0usize;
         let metaindex_handle = BlockHandle::decode_from(input, &mut pos)?;
         let index_handle = BlockHandle::decode_from(input, &mut pos)?;
-        Some(Self { metaindex_handle, index_handle })
+        Some(Self {
+            metaindex_handle,
+            index_handle,
+            // 旧布局没有记录这些信息，按 LevelDB 约定：crc32c、无压缩
+            checksum_type: ChecksumType::Crc32c,
+            format_version: LEGACY_FORMAT_VERSION,
+            compression: CompressionType::NoCompression,
+        })
     }
 
+    fn decode_new(input: &[u8]) -> Option<Self> {
+        let checksum_type = ChecksumType::from_u8(input[0])?;
+        let compression = CompressionType::from_u8(input[1])?;
+
+        let handles = &input[2..2 + Self::HANDLES_LEN];
+        let mut pos = 0usize;
+        let metaindex_handle = BlockHandle::decode_from(handles, &mut pos)?;
+        let index_handle = BlockHandle::decode_from(handles, &mut pos)?;
+
+        let v = 2 + Self::HANDLES_LEN;
+        let format_version = u32::from_le_bytes(input[v..v + 4].try_into().ok()?);
+
+        Some(Self {
+            metaindex_handle,
+            index_handle,
+            checksum_type,
+            format_version,
+            compression,
+        })
+    }
 
     pub fn read_from_file<R>(
         reader: &mut R,
@@ -78,50 +225,26 @@ impl Footer {
     where
         R: Read + Seek,
     {
-        if file_len < Self::ENCODED_LEN as u64 {
+        if file_len < Self::LEGACY_ENCODED_LEN as u64 {
             return Err(DBError::Corruption("file too short to be an sstable".to_string()));
         }
 
-        // 1️⃣ 定位到 footer 起始位置
-        reader.seek(SeekFrom::Start(
-            file_len -  Self::ENCODED_LEN  as u64,
-        ))?;
+        // 1️⃣ 按最大 footer 长度读文件尾部（旧文件可能只有 48 字节 footer）
+        let tail_len = (Self::ENCODED_LEN as u64).min(file_len);
+        reader.seek(SeekFrom::Start(file_len - tail_len))?;
 
-        // 2️⃣ 读 footer
-        let mut buf = [0u8;  Self::ENCODED_LEN ];
+        let mut buf = vec![0u8; tail_len as usize];
         reader.read_exact(&mut buf)?;
 
-        let mut pos = 0usize;
-
-        // 3️⃣ 解 metaindex block handle
-        let metaindex_handle =
-            BlockHandle::decode_from(&buf, &mut pos)
-                .ok_or_else(|| {
-                    DBError::Corruption("bad metaindex handle".to_string())
-                })?;
-
-        // 4️⃣ 解 index block handle
-        let index_handle =
-            BlockHandle::decode_from(&buf, &mut pos)
-                .ok_or_else(|| {
-                    DBError::Corruption("bad index handle".to_string())
-                })?;
-
-        // 5️⃣ 校验 magic number
-        let magic = u64::from_le_bytes(
-            buf[ Self::ENCODED_LEN  - 8..]
-                .try_into()
-                .unwrap(),
-        );
-
-        if magic != TABLE_MAGIC {
+        // 2️⃣ 校验 magic number
+        let magic = u64::from_le_bytes(buf[buf.len() - 8..].try_into().unwrap());
+        if magic != TABLE_MAGIC && magic != TABLE_MAGIC_V2 {
             return Err(DBError::Corruption("bad sstable magic number".to_string()));
         }
 
-        Ok(Footer {
-            metaindex_handle,
-            index_handle,
-        })
+        // 3️⃣ 解 footer（自动识别新旧布局）
+        Self::decode(&buf)
+            .ok_or_else(|| DBError::Corruption("bad sstable footer".to_string()))
     }
 
 }
@@ -171,3 +294,79 @@ pub fn hash64(data: &[u8], seed: u64) -> u64 {
     h ^= h >> 33;
     h
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn footer() -> Footer {
+        let mut footer = Footer::new(BlockHandle { offset: 1000, size: 37 }, BlockHandle { offset: 1042, size: 300 });
+        footer.checksum_type = ChecksumType::NoChecksum;
+        footer.compression = CompressionType::Lz4Compression;
+        footer
+    }
+
+    /// 文件前面随便垫些数据，footer 放在最后
+    fn file_with(footer_bytes: &[u8]) -> Cursor<Vec<u8>> {
+        let mut file = vec![0xAB; 200];
+        file.extend_from_slice(footer_bytes);
+        Cursor::new(file)
+    }
+
+    #[test]
+    fn footer_round_trips_through_a_file() {
+        let encoded = footer().encode();
+        assert_eq!(encoded.len(), Footer::ENCODED_LEN);
+        let mut file = file_with(&encoded);
+        let len = file.get_ref().len() as u64;
+        let decoded = Footer::read_from_file(&mut file, len).unwrap();
+        assert_eq!(decoded.metaindex_handle, BlockHandle { offset: 1000, size: 37 });
+        assert_eq!(decoded.index_handle, BlockHandle { offset: 1042, size: 300 });
+        assert_eq!(decoded.checksum_type, ChecksumType::NoChecksum);
+        assert_eq!(decoded.compression, CompressionType::Lz4Compression);
+        assert_eq!(decoded.format_version, CURRENT_FORMAT_VERSION);
+        assert!(decoded.index_value_delta_encoded());
+    }
+
+    #[test]
+    fn legacy_footer_is_read_with_leveldb_defaults() {
+        let encoded = footer().encode_legacy();
+        let mut file = file_with(&encoded);
+        let len = file.get_ref().len() as u64;
+        let decoded = Footer::read_from_file(&mut file, len).unwrap();
+        assert_eq!(decoded.index_handle, BlockHandle { offset: 1042, size: 300 });
+        assert_eq!(decoded.format_version, LEGACY_FORMAT_VERSION);
+        assert_eq!(decoded.encoded_len(), Footer::LEGACY_ENCODED_LEN);
+        // 旧布局里没有这两项
+        assert_eq!(decoded.checksum_type, ChecksumType::Crc32c);
+        assert_eq!(decoded.compression, CompressionType::NoCompression);
+        assert!(!decoded.index_value_delta_encoded());
+
+        // 只有 48 字节的文件也读得出来
+        let mut short = Cursor::new(encoded.to_vec());
+        assert!(Footer::read_from_file(&mut short, Footer::LEGACY_ENCODED_LEN as u64).is_ok());
+    }
+
+    #[test]
+    fn bad_magic_or_short_file_is_corruption() {
+        let mut encoded = footer().encode();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xFF;
+        let mut file = file_with(&encoded);
+        let len = file.get_ref().len() as u64;
+        assert!(matches!(Footer::read_from_file(&mut file, len), Err(DBError::Corruption(_))));
+        let mut tiny = Cursor::new(vec![0u8; 10]);
+        assert!(matches!(Footer::read_from_file(&mut tiny, 10), Err(DBError::Corruption(_))));
+    }
+
+    #[test]
+    fn block_trailer_catches_a_flipped_byte() {
+        let mut block = b"some block contents".to_vec();
+        block.extend_from_slice(&encode_block_trailer(&block, CompressionType::NoCompression, ChecksumType::Crc32c));
+        verify_block_trailer(&block, ChecksumType::Crc32c, 7, 0).unwrap();
+        block[3] ^= 1;
+        assert!(matches!(verify_block_trailer(&block, ChecksumType::Crc32c, 7, 0), Err(DBError::Corruption(_))));
+        verify_block_trailer(&block, ChecksumType::NoChecksum, 7, 0).unwrap();
+    }
+}
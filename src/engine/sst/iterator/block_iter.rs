@@ -20,14 +20,26 @@ impl<'a> InternalIterator for BlockIter<'a> {
         self.inner.seek_to_first()
     }
 
+    fn seek_to_last(&mut self) {
+        self.inner.seek_to_last()
+    }
+
     fn seek(&mut self, target: &[u8]) {
         self.inner.seek(target)
     }
 
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.inner.seek_for_prev(target)
+    }
+
     fn next(&mut self) {
         self.inner.next()
     }
 
+    fn prev(&mut self) {
+        self.inner.prev()
+    }
+
     fn key(&self) -> &[u8] {
         self.inner.key()
     }
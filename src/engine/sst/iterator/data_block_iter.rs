@@ -1,11 +1,33 @@
 use std::cmp::Ordering;
+use std::ops::Deref;
+use std::sync::Arc;
 use crate::engine::sst::block::{get_varint32, DataBlock};
 use crate::engine::sst::iterator::InternalIterator;
 
+/// iterator 读的 block：借来的，或者自己拿着一份引用
+/// （从 block cache 取出的 block，iterator 要比取它的那次调用活得久）
+pub(crate) enum BlockRef<'a> {
+    Borrowed(&'a DataBlock),
+    Shared(Arc<dyn AsRef<DataBlock> + Send + Sync>),
+}
+
+impl Deref for BlockRef<'_> {
+    type Target = DataBlock;
+
+    fn deref(&self) -> &DataBlock {
+        match self {
+            BlockRef::Borrowed(b) => b,
+            BlockRef::Shared(b) => (**b).as_ref(),
+        }
+    }
+}
+
 /// DataBlock 内部迭代器（prefix 解码 + 顺序/seek）
 pub struct DataBlockIter<'a> {
-    pub(crate) block: &'a DataBlock,
-    /// 当前 entry 在 data 中的偏移
+    pub(crate) block: BlockRef<'a>,
+    /// 当前 entry 的起始偏移
+    pub(crate) entry_offset: usize,
+    /// 下一个 entry 的起始偏移
     pub(crate) offset: usize,
     /// 当前完整 key
     pub(crate) key_buf: Vec<u8>,
@@ -17,21 +39,37 @@ pub struct DataBlockIter<'a> {
 
 impl<'a> DataBlockIter<'a> {
     pub fn new(block: &'a DataBlock) -> Self {
-        let mut it = Self {
+        Self::with_block(BlockRef::Borrowed(block))
+    }
+
+    pub(crate) fn with_block(block: BlockRef<'a>) -> Self {
+        Self {
             block,
+            entry_offset: 0,
             offset: 0,
             key_buf: Vec::new(),
             value_range: 0..0,
             valid: false,
-        };
-        it
+        }
     }
+}
+
+impl DataBlockIter<'static> {
+    /// 拿着 `block` 的一份引用，不借用任何东西
+    pub fn shared(block: Arc<DataBlock>) -> Self {
+        Self::with_block(BlockRef::Shared(block))
+    }
+}
+
+impl<'a> DataBlockIter<'a> {
 
     /// 解析当前 offset 对应的 entry，更新 key_buf / value_range
     fn parse_current(&mut self) {
         let data = &self.block.data;
         let mut pos = self.offset;
-        if pos >= data.len() {
+        self.entry_offset = pos;
+        // entry 之后是 restart array，不能当 entry 解
+        if pos >= self.block.data_entries_end() {
             self.valid = false;
             return;
         }
@@ -77,7 +115,7 @@ impl<'a> DataBlockIter<'a> {
     }
 
     /// 只在从某个 restart offset 开始 scan 时用
-    fn seek_to_restart_point(&mut self, restart_idx: usize) {
+    pub(crate) fn seek_to_restart_point(&mut self, restart_idx: usize) {
         assert!(restart_idx < self.block.restart_offsets.len());
         self.offset = self.block.restart_offsets[restart_idx] as usize;
         self.key_buf.clear();
@@ -86,35 +124,19 @@ impl<'a> DataBlockIter<'a> {
         self.parse_current();
     }
 
-    /// 二分 search restart array，找到包含 target 的 restart 区间
-    fn find_restart_point(&self, target: &[u8]) -> usize {
-        let restarts = &self.block.restart_offsets;
-        let data = &self.block.data;
-
-        let mut left = 0usize;
-        let mut right = restarts.len();
-
-        while left + 1 < right {
-            let mid = (left + right) / 2;
-            let mut pos = restarts[mid] as usize;
-
-            // restart 开始的 entry 总是 shared=0
-            let shared = get_varint32(data, &mut pos);
-            assert_eq!(shared, 0);
-            let non_shared = get_varint32(data, &mut pos)as usize;
-
-            if pos + non_shared > data.len() {
-                break;
-            }
-            let key = &data[pos..pos + non_shared];
-
-            match key.cmp(target) {
-                Ordering::Less => left = mid,
-                Ordering::Equal | Ordering::Greater => right = mid,
-            }
-        }
+    /// 最后一个起始偏移 < `offset` 的 restart 点；`offset` 就是第一条 entry 时为 None
+    pub(crate) fn restart_point_before(&self, offset: usize) -> Option<usize> {
+        self.block
+            .restart_offsets
+            .partition_point(|&o| (o as usize) < offset)
+            .checked_sub(1)
+    }
 
-        left
+    /// 二分 search restart array，找到包含 target 的 restart 区间
+    ///
+    /// restart key 在 DataBlock::from_bytes 时已经解析好，这里只是内存二分
+    pub(crate) fn find_restart_point(&self, target: &[u8]) -> usize {
+        self.block.find_restart_point(target)
     }
 }
 
@@ -131,6 +153,19 @@ impl<'a> InternalIterator for DataBlockIter<'a> {
         self.parse_current();
     }
 
+    fn seek_to_last(&mut self) {
+        // 从最后一个 restart 点 scan 到最后一条
+        let Some(last) = self.block.restart_keys.len().checked_sub(1) else {
+            self.valid = false;
+            return;
+        };
+        let end = self.block.data_entries_end();
+        self.seek_to_restart_point(last);
+        while self.valid && self.offset < end {
+            self.parse_current();
+        }
+    }
+
     fn seek(&mut self, target: &[u8]) {
         if self.block.data.is_empty() {
             self.valid = false;
@@ -156,6 +191,22 @@ impl<'a> InternalIterator for DataBlockIter<'a> {
         self.parse_current();
     }
 
+    // entry 是前缀压缩的，只能从前一个 restart 点往后 scan 到当前 entry 的前一条
+    fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+        let current = self.entry_offset;
+        let Some(r) = self.restart_point_before(current) else {
+            self.valid = false;
+            return;
+        };
+        self.seek_to_restart_point(r);
+        while self.valid && self.offset < current {
+            self.parse_current();
+        }
+    }
+
     fn key(&self) -> &[u8] {
         &self.key_buf
     }
@@ -1,13 +1,28 @@
-use crate::engine::mem::{InternalKey, ValueType};
+use std::sync::Arc;
+use crate::engine::mem::{InternalKey, RangeTombstone, ValueType};
 use crate::engine::sst::iterator::InternalIterator;
+use crate::engine::version::{full_merge, MergeOperator};
 
 pub trait DBIterator {
     fn valid(&self) -> bool;
     fn next(&mut self);
+    fn prev(&mut self);
     fn key(&self) -> Option<&[u8]>;
     fn value(&self) -> Option<&[u8]>;
     fn seek(&mut self, user_key: &[u8]);
+    /// 定位到 <= user_key 的最后一个 key
+    fn seek_for_prev(&mut self, user_key: &[u8]);
     fn seek_to_first(&mut self);
+    fn seek_to_last(&mut self);
+}
+
+/// inner 相对当前 key 的位置
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// inner 在当前 key 的可见版本上或之后
+    Forward,
+    /// inner 在当前 key 的所有版本之前
+    Reverse,
 }
 
 impl<I: InternalIterator> SnapshotIterator<I> {
@@ -18,11 +33,32 @@ impl<I: InternalIterator> SnapshotIterator<I> {
             current_key: Vec::new(),
             current_value: Vec::new(),
             valid: false,
+            range_tombstones: Vec::new(),
+            merge_operator: None,
+            direction: Direction::Forward,
         };
         // 不自动 seek_to_first，交给调用方
         s
     }
 
+    /// 被这些范围墓碑盖住的版本当作已删除
+    pub fn with_range_tombstones(mut self, tombstones: Vec<RangeTombstone>) -> Self {
+        self.range_tombstones = tombstones;
+        self
+    }
+
+    /// 用它把 merge operand 和更老的版本合起来；None 时只给出最新的 operand
+    pub fn with_merge_operator(mut self, op: Option<Arc<dyn MergeOperator + Send + Sync>>) -> Self {
+        self.merge_operator = op;
+        self
+    }
+
+    fn range_deleted(&self, ikey: &InternalKey) -> bool {
+        self.range_tombstones
+            .iter()
+            .any(|t| t.covers(&ikey.user_key, ikey.seq, self.snapshot_seq))
+    }
+
     fn clear_current(&mut self) {
         self.valid = false;
         self.current_key.clear();
@@ -63,7 +99,14 @@ impl<I: InternalIterator> SnapshotIterator<I> {
                 }
             }
 
-            match ikey.value_type {
+            // 被范围墓碑盖住的版本按点删除处理：更旧的版本 seq 更小，同样被盖住
+            let value_type = if self.range_deleted(&ikey) {
+                ValueType::Delete
+            } else {
+                ikey.value_type.clone()
+            };
+
+            match value_type {
                 ValueType::Delete => {
                     // 这个 key 在当前 snapshot 被删除了：
                     // 需要跳过所有同 key 的旧版本
@@ -92,11 +135,113 @@ impl<I: InternalIterator> SnapshotIterator<I> {
                     self.inner.next();
                     return;
                 }
+                ValueType::Merge => {
+                    // 往回收集同一个 key 的 operand，直到 Put / 删除；
+                    // inner 停在 base 上，下一次 find_next_user_entry 会跳过剩下的旧版本
+                    let mut operands = vec![self.inner.value().to_vec()];
+                    let mut base = None;
+                    self.inner.next();
+                    while self.inner.valid() {
+                        let Ok(older) = InternalKey::decode(self.inner.key()) else { break };
+                        if older.user_key != ikey.user_key || self.range_deleted(&older) {
+                            break;
+                        }
+                        match older.value_type {
+                            ValueType::Merge => operands.push(self.inner.value().to_vec()),
+                            ValueType::Put => {
+                                base = Some(self.inner.value().to_vec());
+                                break;
+                            }
+                            ValueType::Delete => break,
+                        }
+                        self.inner.next();
+                    }
+
+                    self.current_value = match &self.merge_operator {
+                        Some(op) => full_merge(op.as_ref(), &ikey.user_key, base.as_deref(), &operands),
+                        None => operands.swap_remove(0),
+                    };
+                    self.current_key = ikey.user_key;
+                    self.valid = true;
+                    return;
+                }
             }
         }
         // inner 已经 invalid，结束
         self.valid = false;
     }
+
+    /// 从 inner 当前位置往回找上一个对用户可见的 key，inner 停在它所有版本之前
+    ///
+    /// 往回走时同一个 user_key 的版本从旧到新出现，所以要看完它的所有版本、碰到更小的
+    /// key 才知道它最终是什么：最后一个可见版本是删除就接着往回找。
+    fn find_prev_user_entry(&mut self) {
+        self.clear_current();
+
+        let mut key: Option<Vec<u8>> = None;
+        // 最近的 Put 的值，和它之后的 merge operand（从旧到新）
+        let mut base: Option<Vec<u8>> = None;
+        let mut operands: Vec<Vec<u8>> = Vec::new();
+        let mut live = false;
+
+        while self.inner.valid() {
+            let Ok(ikey) = InternalKey::decode(self.inner.key()) else {
+                // 损坏条目，跳过
+                self.inner.prev();
+                continue;
+            };
+            if ikey.seq <= self.snapshot_seq {
+                if key.as_ref() != Some(&ikey.user_key) {
+                    if live {
+                        // 上一个 key 的版本看完了，它是可见的
+                        break;
+                    }
+                    key = Some(ikey.user_key.clone());
+                    base = None;
+                    operands.clear();
+                }
+                let value_type = if self.range_deleted(&ikey) {
+                    ValueType::Delete
+                } else {
+                    ikey.value_type.clone()
+                };
+                match value_type {
+                    ValueType::Delete => {
+                        base = None;
+                        operands.clear();
+                        live = false;
+                    }
+                    ValueType::Put => {
+                        base = Some(self.inner.value().to_vec());
+                        operands.clear();
+                        live = true;
+                    }
+                    ValueType::Merge => {
+                        operands.push(self.inner.value().to_vec());
+                        live = true;
+                    }
+                }
+            }
+            self.inner.prev();
+        }
+
+        let (Some(key), true) = (key, live) else {
+            self.valid = false;
+            return;
+        };
+        self.current_value = if operands.is_empty() {
+            base.unwrap_or_default()
+        } else {
+            // full_merge 要的 operand 是从新到旧
+            operands.reverse();
+            match &self.merge_operator {
+                Some(op) => full_merge(op.as_ref(), &key, base.as_deref(), &operands),
+                None => operands.swap_remove(0),
+            }
+        };
+        self.current_key = key;
+        self.valid = true;
+    }
 }
 
 pub struct SnapshotIterator<I: InternalIterator> {
@@ -106,6 +251,9 @@ pub struct SnapshotIterator<I: InternalIterator> {
     current_key: Vec<u8>,
     current_value: Vec<u8>,
     valid: bool,
+    range_tombstones: Vec<RangeTombstone>,
+    merge_operator: Option<Arc<dyn MergeOperator + Send + Sync>>,
+    direction: Direction,
 }
 
 
@@ -115,26 +263,70 @@ impl<I: InternalIterator> DBIterator for SnapshotIterator<I> {
     }
 
     fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
         self.inner.seek_to_first();
         self.find_next_user_entry(None);
     }
 
+    fn seek_to_last(&mut self) {
+        self.direction = Direction::Reverse;
+        self.inner.seek_to_last();
+        self.find_prev_user_entry();
+    }
+
     fn seek(&mut self, user_key: &[u8]) {
         // 构造 internal seek key = (user_key, max_seq, Value)
         let ikey = InternalKey::max_for_user_key(user_key);
+        self.direction = Direction::Forward;
         self.inner.seek(&ikey);
         self.find_next_user_entry(None);
     }
 
+    fn seek_for_prev(&mut self, user_key: &[u8]) {
+        // inner 挪到 user_key 所有版本之后的第一条，再往回一条
+        self.direction = Direction::Reverse;
+        self.inner.seek(&InternalKey::max_for_user_key(user_key));
+        while self.inner.valid() {
+            match InternalKey::decode(self.inner.key()) {
+                Ok(ikey) if ikey.user_key.as_slice() > user_key => break,
+                _ => self.inner.next(),
+            }
+        }
+        if self.inner.valid() {
+            self.inner.prev();
+        } else {
+            self.inner.seek_to_last();
+        }
+        self.find_prev_user_entry();
+    }
+
     fn next(&mut self) {
         if !self.valid {
             return;
         }
         // 记录当前 user_key，用于跳过旧版本
         let skip_key = Some(self.current_key.clone());
+        if self.direction == Direction::Reverse {
+            // inner 在当前 key 之前，先挪回它的第一个版本
+            self.inner.seek(&InternalKey::max_for_user_key(&self.current_key));
+            self.direction = Direction::Forward;
+        }
         self.find_next_user_entry(skip_key);
     }
 
+    fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+        if self.direction == Direction::Forward {
+            // inner 在当前 key 的版本中间或之后，挪到它所有版本之前
+            self.inner.seek(&InternalKey::max_for_user_key(&self.current_key));
+            self.inner.prev();
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+    }
+
     fn key(&self) -> Option<&[u8]> {
         if self.valid {
             Some(&self.current_key)
@@ -24,6 +24,11 @@ impl DBIterator for EmptyIterator {
         // Do nothing
     }
 
+    /// Move to the previous entry. No-op for empty iterator.
+    fn prev(&mut self) {
+        // Do nothing
+    }
+
     /// Return the current key. Always `None` for empty iterator.
     fn key(&self) -> Option<&[u8]> {
         None
@@ -39,8 +44,18 @@ impl DBIterator for EmptyIterator {
         // Do nothing
     }
 
+    /// Seek to the last key at or before the specified user key. No-op for empty iterator.
+    fn seek_for_prev(&mut self, _user_key: &[u8]) {
+        // Do nothing
+    }
+
     /// Seek to the first key in the column family. No-op for empty iterator.
     fn seek_to_first(&mut self) {
         // Do nothing
     }
+
+    /// Seek to the last key in the column family. No-op for empty iterator.
+    fn seek_to_last(&mut self) {
+        // Do nothing
+    }
 }
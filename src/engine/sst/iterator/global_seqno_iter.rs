@@ -0,0 +1,111 @@
+use crate::engine::mem::SequenceNumber;
+use crate::engine::sst::iterator::InternalIterator;
+
+/// ingest 进来的 SST：所有 key 的 seq 统一替换成文件的 global_seqno。
+///
+/// internal key = user_key + tag(u64 LE)，tag = (seq << 8) | value_type，
+/// 这里只改 seq，保留 value_type。
+pub struct GlobalSeqnoIterator<'a> {
+    inner: Box<dyn InternalIterator + 'a>,
+    global_seqno: SequenceNumber,
+    key_buf: Vec<u8>,
+}
+
+impl<'a> GlobalSeqnoIterator<'a> {
+    pub fn new(inner: Box<dyn InternalIterator + 'a>, global_seqno: SequenceNumber) -> Self {
+        let mut it = Self { inner, global_seqno, key_buf: Vec::new() };
+        it.rewrite_key();
+        it
+    }
+
+    fn rewrite_key(&mut self) {
+        self.key_buf.clear();
+        if !self.inner.valid() {
+            return;
+        }
+        let k = self.inner.key();
+        self.key_buf.extend_from_slice(k);
+        if k.len() < 8 {
+            return;
+        }
+
+        let n = k.len();
+        let tag = u64::from_le_bytes(k[n - 8..].try_into().unwrap());
+        let new_tag = (self.global_seqno << 8) | (tag & 0xff);
+        self.key_buf[n - 8..].copy_from_slice(&new_tag.to_le_bytes());
+    }
+}
+
+impl<'a> InternalIterator for GlobalSeqnoIterator<'a> {
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.inner.seek_to_first();
+        self.rewrite_key();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.inner.seek_to_last();
+        self.rewrite_key();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.inner.seek(target);
+        self.rewrite_key();
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.inner.seek_for_prev(target);
+        self.rewrite_key();
+    }
+
+    fn next(&mut self) {
+        self.inner.next();
+        self.rewrite_key();
+    }
+
+    fn prev(&mut self) {
+        self.inner.prev();
+        self.rewrite_key();
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key_buf
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mem::{InternalKey, ValueType};
+    use crate::engine::sst::block::{BlockBuilder, DataBlock};
+
+    fn encoded(user_key: &[u8], seq: u64, value_type: ValueType) -> Vec<u8> {
+        let mut buf = Vec::new();
+        InternalKey::new(user_key.to_vec(), seq, value_type).encode_to(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn rewrites_the_seq_and_keeps_the_value_type() {
+        let mut builder = BlockBuilder::new(16);
+        builder.add(&encoded(b"a", 0, ValueType::Put), b"1");
+        builder.add(&encoded(b"b", 0, ValueType::Delete), b"");
+        let block = DataBlock::from_bytes(builder.finish().to_vec()).unwrap();
+
+        let mut it = GlobalSeqnoIterator::new(Box::new(block.iter()), 77);
+        it.seek_to_first();
+        assert_eq!(it.key(), encoded(b"a", 77, ValueType::Put).as_slice());
+        assert_eq!(it.value(), b"1");
+        it.next();
+        assert_eq!(it.key(), encoded(b"b", 77, ValueType::Delete).as_slice());
+        it.next();
+        assert!(!it.valid());
+    }
+}
@@ -8,15 +8,37 @@ pub trait InternalIterator {
     /// 定位到第一个 entry
     fn seek_to_first(&mut self);
 
+    /// 定位到最后一个 entry
+    fn seek_to_last(&mut self);
+
     /// 定位到 >= target 的第一条记录
     fn seek(&mut self, target: &[u8]);
 
+    /// 定位到 <= target 的最后一条记录
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.seek(target);
+        if !self.valid() {
+            self.seek_to_last();
+        } else if self.key() != target {
+            self.prev();
+        }
+    }
+
     /// 前进到下一条
     fn next(&mut self);
 
+    /// 后退到上一条；已经在第一条上时变成 invalid
+    fn prev(&mut self);
+
     /// 当前 key（仅在 valid() == true 时调用）
     fn key(&self) -> &[u8];
 
     /// 当前 value（仅在 valid() == true 时调用）
     fn value(&self) -> &[u8];
+
+    /// 仅 index 迭代器：当前 entry 指向的 data block 的 first key
+    /// （BinarySearchWithFirstKey 格式才有，其他返回 None）
+    fn index_first_key(&self) -> Option<&[u8]> {
+        None
+    }
 }
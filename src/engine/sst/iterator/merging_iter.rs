@@ -1,13 +1,24 @@
 use std::cmp::Ordering;
 use crate::engine::sst::iterator::{InternalIterator,DBIterator};
+use crate::util::perf_context::perf_record;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
 
 /// 多路归并 iterator：合并多个已排序的 InternalIterator
+///
+/// 正向时各个子 iterator 都停在 >= 当前 key 的位置，取最小的；反向时都停在
+/// <= 当前 key 的位置，取最大的。换方向时把其他子 iterator 挪到当前 key 的另一侧。
 pub struct MergingIterator<'a> {
     iters: Vec<Box<dyn InternalIterator + 'a>>,
-    /// 当前指向“最小 key”的 iterator 下标
+    /// 当前指向“最小 key”（反向时是最大 key）的 iterator 下标
     current: Option<usize>,
     /// 比较函数：通常比较 InternalKey（用户传 comparator）
     cmp: fn(&[u8], &[u8]) -> Ordering,
+    direction: Direction,
 }
 
 impl<'a> MergingIterator<'a> {
@@ -24,6 +35,7 @@ impl<'a> MergingIterator<'a> {
             iters,
             current: None,
             cmp,
+            direction: Direction::Forward,
         };
         s.find_smallest();
         s
@@ -47,6 +59,20 @@ impl<'a> MergingIterator<'a> {
         }
         self.current = best;
     }
+
+    fn find_largest(&mut self) {
+        let mut best: Option<usize> = None;
+        for (i, it) in self.iters.iter().enumerate() {
+            if !it.valid() {
+                continue;
+            }
+            match best {
+                Some(bi) if (self.cmp)(it.key(), self.iters[bi].key()) != Ordering::Greater => {}
+                _ => best = Some(i),
+            }
+        }
+        self.current = best;
+    }
 }
 
 impl<'a> InternalIterator for MergingIterator<'a> {
@@ -55,26 +81,83 @@ impl<'a> InternalIterator for MergingIterator<'a> {
     }
 
     fn seek_to_first(&mut self) {
+        perf_record(|p| p.seek_child_seek_count += self.iters.len() as u64);
         for it in self.iters.iter_mut() {
             it.seek_to_first();
         }
+        self.direction = Direction::Forward;
         self.find_smallest();
     }
 
+    fn seek_to_last(&mut self) {
+        perf_record(|p| p.seek_child_seek_count += self.iters.len() as u64);
+        for it in self.iters.iter_mut() {
+            it.seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
     fn seek(&mut self, target: &[u8]) {
+        perf_record(|p| p.seek_child_seek_count += self.iters.len() as u64);
         for it in self.iters.iter_mut() {
             it.seek(target);
         }
+        self.direction = Direction::Forward;
         self.find_smallest();
     }
 
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        perf_record(|p| p.seek_child_seek_count += self.iters.len() as u64);
+        for it in self.iters.iter_mut() {
+            it.seek_for_prev(target);
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
     fn next(&mut self) {
-        if let Some(idx) = self.current {
-            self.iters[idx].next();
+        let Some(idx) = self.current else { return };
+        if self.direction == Direction::Reverse {
+            // 其他子 iterator 都在当前 key 之前，挪到它之后
+            let key = self.iters[idx].key().to_vec();
+            for (i, it) in self.iters.iter_mut().enumerate() {
+                if i == idx {
+                    continue;
+                }
+                it.seek(&key);
+                if it.valid() && (self.cmp)(it.key(), &key) == Ordering::Equal {
+                    it.next();
+                }
+            }
+            self.direction = Direction::Forward;
         }
+        self.iters[idx].next();
         self.find_smallest();
     }
 
+    fn prev(&mut self) {
+        let Some(idx) = self.current else { return };
+        if self.direction == Direction::Forward {
+            // 其他子 iterator 都在当前 key 之后（或上），挪到它之前
+            let key = self.iters[idx].key().to_vec();
+            for (i, it) in self.iters.iter_mut().enumerate() {
+                if i == idx {
+                    continue;
+                }
+                it.seek(&key);
+                if it.valid() {
+                    it.prev();
+                } else {
+                    it.seek_to_last();
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.iters[idx].prev();
+        self.find_largest();
+    }
+
     fn key(&self) -> &[u8] {
         let idx = self.current.expect("invalid MergingIterator.key()");
         self.iters[idx].key()
@@ -8,6 +8,8 @@ pub(crate) mod block_iter;
 pub(crate) mod internal_iter;
 pub(crate) mod db_iterator;
 pub(crate) mod empty_iter;
+pub(crate) mod global_seqno_iter;
+pub(crate) mod prefix_filter_iter;
 
 pub use internal_iter::InternalIterator;
 pub use data_block_iter::DataBlockIter;
@@ -15,3 +17,5 @@ pub use two_level_iter::TwoLevelIterator;
 pub use merging_iter::MergingIterator;
 pub use db_iterator::{DBIterator,SnapshotIterator};
 pub use empty_iter::EmptyIterator;
+pub use global_seqno_iter::GlobalSeqnoIterator;
+pub use prefix_filter_iter::PrefixFilterIterator;
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use crate::engine::sst::SstReader;
+use crate::engine::sst::iterator::InternalIterator;
+use crate::util::SliceTransform;
+
+/// `prefix_same_as_start` 的 SST iterator：seek 前先查这个文件的前缀 bloom，
+/// 肯定没有 target 前缀的文件直接变 invalid，一个 block 都不读。
+///
+/// 同一前缀的 key 是连续的，所以文件里 >= target 的第一条要么就是这个前缀，要么这个前缀
+/// 在 target 之后已经没有了；调用方只关心这个前缀，跳过不会漏数据。seek_to_first / last
+/// 不带前缀，照常走。
+pub struct PrefixFilterIterator<'a> {
+    inner: Box<dyn InternalIterator + 'a>,
+    reader: Arc<SstReader>,
+    extractor: Arc<dyn SliceTransform>,
+    /// 上一次 seek 被 filter 挡掉了
+    filtered: bool,
+}
+
+impl<'a> PrefixFilterIterator<'a> {
+    pub fn new(inner: Box<dyn InternalIterator + 'a>, reader: Arc<SstReader>, extractor: Arc<dyn SliceTransform>) -> Self {
+        Self { inner, reader, extractor, filtered: false }
+    }
+
+    /// target 是 internal key：user_key + 8 字节 tag
+    fn filter_out(&self, target: &[u8]) -> bool {
+        let user_key = &target[..target.len().saturating_sub(8)];
+        self.extractor.in_domain(user_key)
+            && !self.reader.prefix_may_match(self.extractor.as_ref(), self.extractor.transform(user_key))
+    }
+}
+
+impl<'a> InternalIterator for PrefixFilterIterator<'a> {
+    fn valid(&self) -> bool {
+        !self.filtered && self.inner.valid()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.filtered = false;
+        self.inner.seek_to_first();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.filtered = false;
+        self.inner.seek_to_last();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.filtered = self.filter_out(target);
+        if !self.filtered {
+            self.inner.seek(target);
+        }
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.filtered = self.filter_out(target);
+        if !self.filtered {
+            self.inner.seek_for_prev(target);
+        }
+    }
+
+    fn next(&mut self) {
+        if !self.filtered {
+            self.inner.next();
+        }
+    }
+
+    fn prev(&mut self) {
+        if !self.filtered {
+            self.inner.prev();
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+}
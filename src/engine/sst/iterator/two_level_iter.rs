@@ -1,3 +1,4 @@
+use std::cell::OnceCell;
 use crate::engine::sst::iterator::InternalIterator;
 
 /// TwoLevelIterator：
@@ -11,6 +12,11 @@ pub struct TwoLevelIterator<'a, F> {
     /// 比如：value 是 BlockHandle 编码，factory 负责 decode + 读 block + 构造 DataBlockIter。
     block_reader: F,
     valid: bool,
+
+    /// first-key index：seek 命中 block 开头时先不读 block，
+    /// key() 直接用 index 里的 first key，value()/next() 时才真正加载
+    deferred: bool,
+    deferred_block: OnceCell<Box<dyn InternalIterator + 'a>>,
 }
 
 impl<'a, F> TwoLevelIterator<'a, F>
@@ -26,9 +32,35 @@ where
             data_iter: None,
             block_reader,
             valid: false,
+            deferred: false,
+            deferred_block: OnceCell::new(),
         }
     }
 
+    fn load_current_block(&self) -> Box<dyn InternalIterator + 'a> {
+        let mut it = (self.block_reader)(self.index_iter.value());
+        it.seek_to_first();
+        it
+    }
+
+    /// 把延迟加载的 block 变成正常的 data_iter
+    fn materialize(&mut self) {
+        if !self.deferred {
+            return;
+        }
+        self.deferred = false;
+        let it = match self.deferred_block.take() {
+            Some(it) => it,
+            None => self.load_current_block(),
+        };
+        self.data_iter = Some(it);
+    }
+
+    fn clear_deferred(&mut self) {
+        self.deferred = false;
+        self.deferred_block = OnceCell::new();
+    }
+
     /// 确保当前 data_iter 指向 index_iter 当前 entry 对应的 block
     fn init_data_block(&mut self) {
         if !self.index_iter.valid() {
@@ -50,6 +82,33 @@ where
         }
     }
 
+    /// 当前 index entry 对应的 block，停在最后一条
+    fn init_data_block_at_last(&mut self) {
+        if !self.index_iter.valid() {
+            self.data_iter = None;
+            self.valid = false;
+            return;
+        }
+        let mut it = (self.block_reader)(self.index_iter.value());
+        it.seek_to_last();
+        self.valid = it.valid();
+        self.data_iter = if self.valid { Some(it) } else { None };
+    }
+
+    /// 后退到上一个非空的 data block 的最后一条
+    fn skip_empty_data_blocks_backward(&mut self) {
+        while !self.data_iter.as_ref().is_some_and(|di| di.valid()) {
+            self.index_iter.prev();
+            if !self.index_iter.valid() {
+                self.data_iter = None;
+                self.valid = false;
+                return;
+            }
+            self.init_data_block_at_last();
+        }
+        self.valid = true;
+    }
+
     /// 前进到下一个非空的 data block 的第一条
     fn skip_empty_data_blocks(&mut self) {
         loop {
@@ -84,6 +143,7 @@ where
     }
 
     fn seek_to_first(&mut self) {
+        self.clear_deferred();
         self.index_iter.seek_to_first();
         if !self.index_iter.valid() {
             self.data_iter = None;
@@ -96,16 +156,37 @@ where
         }
     }
 
+    fn seek_to_last(&mut self) {
+        self.clear_deferred();
+        self.index_iter.seek_to_last();
+        self.init_data_block_at_last();
+        if !self.valid {
+            self.skip_empty_data_blocks_backward();
+        }
+    }
+
     fn seek(&mut self, target: &[u8]) {
         // 粗略实现：直接在所有 block 上 binary seek：
         // 更优的是先在 index 上 seek，找包含 target 的 block，再 data 上 seek。
         // 这里给一个典型模式：index 按 key 上界，先 seek index，再构 block，再 seek data。
+        self.clear_deferred();
         self.index_iter.seek(target);
         if !self.index_iter.valid() {
             self.data_iter = None;
             self.valid = false;
             return;
         }
+
+        // first key >= target：结果就是这个 block 的第一条，不用读 block
+        if let Some(fk) = self.index_iter.index_first_key() {
+            if fk >= target {
+                self.data_iter = None;
+                self.deferred = true;
+                self.valid = true;
+                return;
+            }
+        }
+
         self.init_data_block();
         if !self.valid {
             self.skip_empty_data_blocks();
@@ -127,6 +208,7 @@ where
         if !self.valid {
             return;
         }
+        self.materialize();
         if let Some(di) = self.data_iter.as_mut() {
             di.next();
         }
@@ -135,11 +217,83 @@ where
         }
     }
 
+    fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+        self.materialize();
+        if let Some(di) = self.data_iter.as_mut() {
+            di.prev();
+        }
+        if self.data_iter.as_ref().map_or(true, |di| !di.valid()) {
+            self.skip_empty_data_blocks_backward();
+        }
+    }
+
     fn key(&self) -> &[u8] {
+        if self.deferred {
+            return self.index_iter.index_first_key().unwrap();
+        }
         self.data_iter.as_ref().unwrap().key()
     }
 
     fn value(&self) -> &[u8] {
+        if self.deferred {
+            return self.deferred_block.get_or_init(|| self.load_current_block()).value();
+        }
         self.data_iter.as_ref().unwrap().value()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use crate::engine::sst::block::{BlockBuilder, DataBlock, IndexBlock, IndexBlockBuilder, IndexType};
+    use crate::engine::sst::format::BlockHandle;
+
+    #[test]
+    fn first_key_index_defers_reading_the_block_until_value() {
+        let contents: [&[&str]; 3] = [&["a", "b"], &["d", "e"], &["g", "h"]];
+        let blocks: Vec<DataBlock> = contents.iter().map(|keys| {
+            let mut builder = BlockBuilder::new(16);
+            for k in keys.iter() {
+                builder.add(k.as_bytes(), format!("v{}", k).as_bytes());
+            }
+            DataBlock::from_bytes(builder.finish().to_vec()).unwrap()
+        }).collect();
+        // handle 的 offset 就是 blocks 的下标
+        let mut index_builder = IndexBlockBuilder::with_index_type(1, true, IndexType::BinarySearchWithFirstKey);
+        for (i, keys) in contents.iter().enumerate() {
+            index_builder.add(keys[1].as_bytes(), keys[0].as_bytes(), BlockHandle { offset: i as u64, size: 1 });
+        }
+        let index = IndexBlock::from_bytes_with_encoding(index_builder.finish(), true, IndexType::BinarySearchWithFirstKey).unwrap();
+
+        let loads = Cell::new(0);
+        let mut it = TwoLevelIterator::new(Box::new(index.iter()), |v: &[u8]| {
+            loads.set(loads.get() + 1);
+            let h = BlockHandle::decode_from_bytes(v).unwrap();
+            Box::new(blocks[h.offset as usize].iter()) as Box<dyn InternalIterator + '_>
+        });
+
+        // "c" 落在第二个 block 的 first key 之前：key 直接用 index 里的 first key
+        it.seek(b"c");
+        assert!(it.valid());
+        assert_eq!(it.key(), b"d");
+        assert_eq!(loads.get(), 0);
+        assert_eq!(it.value(), b"vd");
+        assert_eq!(loads.get(), 1);
+        it.next();
+        assert_eq!(it.key(), b"e");
+        assert_eq!(loads.get(), 1);
+        it.next();
+        assert_eq!(it.key(), b"g");
+
+        // target 在 block 中间：照常读 block
+        it.seek(b"e");
+        assert_eq!(it.key(), b"e");
+        assert_eq!(it.value(), b"ve");
+        it.seek(b"z");
+        assert!(!it.valid());
+    }
+}
@@ -4,8 +4,9 @@ pub(crate) mod table_reader;
 pub(crate) mod table_cache;
 pub(crate) mod sst_reader;
 pub(crate) mod block;
+pub(crate) mod compression;
 pub(crate) mod iterator;
 
-pub(crate) use format::{get_varint64, put_varint64, BlockHandle, hash64};
-pub(crate) use sst_reader::SstReader;
+pub(crate) use format::{get_varint64, put_varint64, BlockHandle, ChecksumType, Footer, hash64};
+pub(crate) use sst_reader::{LookupResult, SstReader};
 pub(crate) use table_cache::TableCache;
@@ -1,26 +1,75 @@
 // sst/table.rs
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::error::DBError;
-use crate::engine::sst::format::{Footer, BlockHandle};
-use crate::engine::sst::block::{DataBlock, FilterBlock, FilterPolicy, IndexBlock, MetaIndexBlock, BLOCK_TRAILER_SIZE};
+use crate::engine::sst::format::{verify_block_trailer, BlockHandle, ChecksumType, Footer};
+use crate::engine::sst::block::{DataBlock, FilterBlock, FilterPolicy, IndexBlock, IndexType, MetaIndexBlock, TableProperties, BLOCK_TRAILER_SIZE};
 use crate::engine::sst::block::{BlockCache, BlockCacheKey};
-use crate::engine::sst::iterator::{InternalIterator, TwoLevelIterator};
+use crate::engine::sst::iterator::{DataBlockIter, GlobalSeqnoIterator, InternalIterator, PrefixFilterIterator, TwoLevelIterator};
+use crate::engine::sst::format::DELTA_INDEX_FORMAT_VERSION;
+use crate::engine::mem::{InternalKey, SequenceNumber, ValueType};
+use crate::engine::block_trace::{BlockAccessCaller, BlockTracer};
+use crate::engine::sst::compression::decompress_block;
+use crate::util::{read_at, CfStatistics, MemoryAllocator, ReadOptions, SliceTransform, NO_COMPRESSION};
+use crate::util::perf_context::perf_record;
+
+/// 每个打开的 SstReader 分到一个新的 generation；同一个 file number 被重新打开后，
+/// 线程缓存里旧 reader 留下的 index 查找结果不会被误用
+static NEXT_READER_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// 本线程上一次 index 查找的结果：[low, high] 内的 key 都落在 `handle` 这个 data block
+struct LastIndexLookup {
+    file_number: u64,
+    generation: u64,
+    low: Vec<u8>,
+    high: Vec<u8>,
+    handle: BlockHandle,
+}
+
+thread_local! {
+    // 倾斜的负载下连续的 get 经常落在同一个 SST 的同一个 block，省掉 index 二分
+    static LAST_INDEX_LOOKUP: RefCell<Option<LastIndexLookup>> = const { RefCell::new(None) };
+}
+
+/// 一个 SST 里点查一个 key 的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupResult {
+    /// 最新版本是 Put，带着 value
+    Found(Vec<u8>),
+    /// 最新版本是删除：更老的文件、更低的 level 里的版本都不可见
+    Deleted,
+    /// 这个文件里没有这个 key，接着查更老的
+    NotFound,
+}
 
 pub struct SstReader {
     file_number: u64,
+    generation: u64,
     path: PathBuf,
+    /// reader 活着就一直开着；data block 用 pread 读，多线程共用
+    file: File,
 
     // 常驻
+    footer: Footer,
     index_block: Arc<IndexBlock>,      // 简化：用 DataBlock 表示 index（你也可以单独 IndexBlock）
     filter_block: Option<Arc<FilterBlock>>,
     filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// 前缀 bloom：(写它时的 extractor 名, filter)
+    prefix_filter: Option<(String, Vec<u8>)>,
+    // ingest 文件的 global seqno（None = 使用 key 自带的 seq）
+    global_seqno: Option<SequenceNumber>,
 
     // 共享 cache
     block_cache: Arc<BlockCache<DataBlock>>,
+    /// data block 读缓冲的分配器
+    allocator: Option<Arc<dyn MemoryAllocator>>,
+    /// block 访问轨迹（TableCache 共享，未开启时只是一次原子读）
+    tracer: Option<Arc<BlockTracer>>,
 }
 
 impl SstReader {
@@ -34,76 +83,343 @@ impl SstReader {
         let file_len = f.get_ref().metadata().map_err(DBError::Io)?.len();
         let footer = Footer::read_from_file(&mut f, file_len)?;
 
-        // 1) 读 index block
-        let index_bytes = read_block_raw(&mut f, footer.index_handle)?;
-        // TODO: 做 decode_block + CRC + 解压，这里先假设 DataBlock::from_bytes 里已经处理了
-        let index_block = Arc::new(IndexBlock::from_bytes(index_bytes)?);
+        // 1) 读 metaindex block
+        let verify = Some((file_number, footer.checksum_type));
+        let meta_bytes_raw = read_block_raw(&mut f, footer.metaindex_handle, verify)?;
+        let meta_block = MetaIndexBlock::from_bytes(meta_bytes_raw)?;
+
+        // 2) properties block → global_seqno / index_type
+        let props = match meta_block.find("properties")? {
+            Some(h) => Some(TableProperties::decode(read_block_exact(&mut f, h)?.as_slice())?),
+            None => None,
+        };
+        let global_seqno = match props.as_ref().map(|p| p.global_seqno.load(Ordering::Relaxed)) {
+            None | Some(0) => None,
+            Some(s) => Some(s),
+        };
+        let index_type = match props.as_ref() {
+            Some(p) => IndexType::from_u8(p.index_type)
+                .ok_or(DBError::Corruption(format!("unknown index type {}", p.index_type)))?,
+            None => IndexType::BinarySearch,
+        };
 
-        // 2) 读 metaindex block → 找 filter block handle → 再读 filter block
+        // 3) 读 index block
+        let index_bytes = read_block_raw(&mut f, footer.index_handle, verify)?;
+        let index_block = Arc::new(IndexBlock::from_bytes_with_encoding(
+            index_bytes,
+            footer.index_value_delta_encoded(),
+            index_type,
+        )?);
+
+        // 4) metaindex 找 filter block handle → 再读 filter block
         let mut filter_block: Option<Arc<FilterBlock>> = None;
 
         if let Some(policy) = &filter_policy {
-            // 2.1 先读 metaindex block
-            let meta_bytes_raw = read_block_raw(&mut f, footer.metaindex_handle)?;
-            let meta_block = MetaIndexBlock::from_bytes(meta_bytes_raw)?;
-
-            // 2.2 从 metaindex 找 filter block handle
             if let Some(filter_handle) =
                 MetaIndexBlock::get_filter_handle(&meta_block, policy.as_ref())?
             {
-                // 2.3 读 filter block
-                let filter_bytes_raw = read_block_raw(&mut f, filter_handle)?;
+                let filter_bytes_raw = read_block_raw(&mut f, filter_handle, verify)?;
                 let fb = FilterBlock::from_bytes(filter_bytes_raw);
                 filter_block = Some(Arc::new(fb?));
             }
         }
+        let prefix_filter = match (&filter_policy, meta_block.find_prefix_filter()?) {
+            (Some(_), Some((name, h))) => Some((name, read_block_raw(&mut f, h, verify)?)),
+            _ => None,
+        };
 
         Ok(Self {
             file_number,
+            generation: NEXT_READER_GENERATION.fetch_add(1, Ordering::Relaxed),
             path,
+            file: f.into_inner(),
+            footer,
             index_block,
             filter_block,
             filter_policy,
+            prefix_filter,
+            global_seqno,
             block_cache,
+            allocator: None,
+            tracer: None,
         })
     }
 
+    pub fn with_tracer(mut self, tracer: Option<Arc<BlockTracer>>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    pub fn with_allocator(mut self, allocator: Option<Arc<dyn MemoryAllocator>>) -> Self {
+        self.allocator = allocator;
+        self
+    }
+
+    pub fn global_seqno(&self) -> Option<SequenceNumber> {
+        self.global_seqno
+    }
+
+    /// 常驻 index block 字节数
+    pub fn index_memory_usage(&self) -> usize {
+        self.index_block.memory_usage()
+    }
+
+    /// 常驻 filter block 字节数（没加载 filter 时为 0）
+    pub fn filter_memory_usage(&self) -> usize {
+        self.filter_block.as_ref().map_or(0, |fb| fb.memory_usage())
+    }
+
+    /// 文件 footer（含 format_version / checksum / compression）
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
     /// 点查：index → data block → entry
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_with_stats(key, None)
+    }
+
+    /// 同 get，额外把 filter 命中情况记到 CF 统计里
+    pub fn get_with_stats(
+        &self,
+        key: &[u8],
+        stats: Option<&CfStatistics>,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_with_options(key, stats, &ReadOptions::default())
+    }
+
+    /// 同 get_with_stats，按 `opts` 决定是否校验读到的 block
+    pub fn get_with_options(
+        &self,
+        key: &[u8],
+        stats: Option<&CfStatistics>,
+        opts: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>, DBError> {
         // 0) 可选 bloom：先用 index 找到 data block offset，再查 filter
         let (data_handle, data_block_offset) = self.find_data_block(key)?;
+        if self.filter_rules_out(key, data_block_offset, stats) {
+            return Ok(None);
+        }
+
+        let block = self.read_data_block_cached(data_handle, BlockAccessCaller::Get, opts.verify_checksums, opts.fill_cache)?;
+        // 唯一一次拷贝：把 value 从 block 里拿出来交给调用方
+        Ok(block.get(key).map(|v| v.to_vec()))
+    }
 
-        if let (Some(fb), Some(policy)) = (&self.filter_block, &self.filter_policy) {
-            if let Some(filter) = fb.filter_for_data_block(data_block_offset) {
-                if !policy.may_match(key, filter) {
-                    return Ok(None);
+    /// 批量点查：`keys` 须已排序，落在同一个 data block 的 key 只读一次 block
+    ///
+    /// 每个 key 一个结果，含义同 lookup_with_options；读 block 出错整批返回 Err
+    pub fn multi_lookup_with_stats(
+        self: &Arc<Self>,
+        keys: &[&[u8]],
+        stats: Option<&CfStatistics>,
+        opts: &ReadOptions,
+    ) -> Result<Vec<LookupResult>, DBError> {
+        let mut out = Vec::with_capacity(keys.len());
+        let mut current: Option<(u64, Arc<DataBlock>)> = None;
+        for &key in keys {
+            let (data_handle, data_block_offset) = match self.find_data_block(key) {
+                Ok(found) => found,
+                Err(DBError::NotFound(_)) => {
+                    out.push(LookupResult::NotFound);
+                    continue;
                 }
+                Err(e) => return Err(e),
+            };
+            if self.filter_rules_out(key, data_block_offset, stats) {
+                out.push(LookupResult::NotFound);
+                continue;
             }
-        }
 
-        let block = self.read_data_block_cached(data_handle)?;
-        Ok(block.get(key))
+            let block = match &current {
+                Some((offset, block)) if *offset == data_block_offset => Arc::clone(block),
+                _ => {
+                    let block = self.read_data_block_cached(data_handle, BlockAccessCaller::Get, opts.verify_checksums, opts.fill_cache)?;
+                    current = Some((data_block_offset, Arc::clone(&block)));
+                    block
+                }
+            };
+            out.push(self.lookup_in_block(&block, key, opts)?);
+        }
+        Ok(out)
     }
 
     /// 迭代器：TwoLevel（index iter → data iter）
-    pub fn iter<'a>(self: &Arc<Self>)
-                -> TwoLevelIterator<'a, impl Fn(BlockHandle) -> Box<dyn InternalIterator + 'a>+'a> {
-        let index_iter = self.index_block.iter();
+    /// ingest 文件会再包一层 GlobalSeqnoIterator，把 key 的 seq 换成 global_seqno
+    pub fn iter<'a>(self: &Arc<Self>) -> Box<dyn InternalIterator + 'a> {
+        self.iter_for(BlockAccessCaller::Iterator)
+    }
+
+    /// 同 iter，block trace 里按 `caller` 记账（compaction 用）
+    pub fn iter_for<'a>(self: &Arc<Self>, caller: BlockAccessCaller) -> Box<dyn InternalIterator + 'a> {
+        self.iter_with(caller, true, true)
+    }
+
+    /// 同 iter，按 `opts` 决定是否校验 block、读到的 block 是否放进 block cache
+    pub fn iter_with_options<'a>(self: &Arc<Self>, opts: &ReadOptions) -> Box<dyn InternalIterator + 'a> {
+        self.iter_with(BlockAccessCaller::Iterator, opts.verify_checksums, opts.fill_cache)
+    }
+
+    /// 同 iter_with_options；seek 的 target 前缀被前缀 bloom 排除时不读这个文件。
+    /// 只能用在 `prefix_same_as_start` 的 iterator 上
+    pub fn iter_with_prefix<'a>(
+        self: &Arc<Self>,
+        opts: &ReadOptions,
+        extractor: Arc<dyn SliceTransform>,
+    ) -> Box<dyn InternalIterator + 'a> {
+        let iter = self.iter_with_options(opts);
+        if self.prefix_filter.is_none() {
+            return iter;
+        }
+        Box::new(PrefixFilterIterator::new(iter, Arc::clone(self), extractor))
+    }
+
+    /// 这个文件里可能有以 `prefix` 开头的 key；没有前缀 bloom、或者 bloom 是别的 extractor
+    /// 写的，都当作可能有
+    pub fn prefix_may_match(&self, extractor: &dyn SliceTransform, prefix: &[u8]) -> bool {
+        match (&self.prefix_filter, &self.filter_policy) {
+            (Some((name, filter)), Some(policy)) if name == extractor.name() => {
+                let may_match = policy.may_match(prefix, filter);
+                perf_record(|p| {
+                    p.bloom_sst_checked += 1;
+                    p.bloom_sst_useful += !may_match as u64;
+                });
+                may_match
+            }
+            _ => true,
+        }
+    }
+
+    fn iter_with<'a>(self: &Arc<Self>, caller: BlockAccessCaller, verify_checksums: bool, fill_cache: bool) -> Box<dyn InternalIterator + 'a> {
+        let index_iter = self.index_block.shared_iter();
         let reader = Arc::clone(self);
-        TwoLevelIterator::new(
+        let iter = TwoLevelIterator::new(
             Box::new(index_iter),
-            move |h|{
-                Box::new(reader.read_data_block_cached(h).iter())
+            move |h: &[u8]| -> Box<dyn InternalIterator + 'a> {
+                // 读不出来的 block 当成空的：iterator 没有报错的途径，要确认文件完好用 verify_checksums
+                let block = BlockHandle::decode_from(h, &mut 0)
+                    .ok_or_else(|| DBError::Corruption("bad index block handle".into()))
+                    .and_then(|h| reader.read_data_block_cached(h, caller, verify_checksums, fill_cache))
+                    .unwrap_or_else(|_| Arc::new(DataBlock::empty()));
+                Box::new(DataBlockIter::shared(block))
             },
-        )
+        );
+        match self.global_seqno {
+            Some(seqno) => Box::new(GlobalSeqnoIterator::new(Box::new(iter), seqno)),
+            None => Box::new(iter),
+        }
+    }
+
+    /// time-travel 点查：user_key 在 seq <= `seq` 范围内最新的一条
+    ///
+    /// 返回 (seq, value_type, value)；同一 user key 的多个版本全部扫一遍取最大 seq。
+    /// 读 block 出错（IO、校验和不对）原样返回，不当成没找到
+    pub fn get_as_of(
+        self: &Arc<Self>,
+        user_key: &[u8],
+        seq: SequenceNumber,
+        opts: &ReadOptions,
+    ) -> Result<Option<(SequenceNumber, ValueType, Vec<u8>)>, DBError> {
+        let (data_handle, _) = match self.find_data_block(user_key) {
+            Ok(found) => found,
+            Err(DBError::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // 和 lookup_with_options 一样先读 index 指到的 block，iterator 会把读错吞掉
+        let block = self.read_data_block_cached(data_handle, BlockAccessCaller::Get, opts.verify_checksums, opts.fill_cache)?;
+        let mut it = block.iter();
+        let found = newest_version(&mut it, user_key, seq)?;
+        if it.valid() {
+            return Ok(found);
+        }
+        // 扫到了 block 末尾，更老的版本可能接着写在下一个 block 里
+        newest_version(self.iter_with_options(opts).as_mut(), user_key, seq)
+    }
+
+    /// 从磁盘把每个 data block 读一遍、校验 trailer 里的 crc（不经过 block cache）
+    ///
+    /// iterator 读坏 block 时只会提前结束，要确认整个文件完好得用这个
+    pub fn verify_checksums(&self) -> Result<(), DBError> {
+        let verify = Some((self.file_number, self.footer.checksum_type));
+        let mut it = self.index_block.iter();
+        it.seek_to_first();
+        while let Some(h) = it.handle().filter(|_| it.valid()) {
+            let mut f = FileAt { file: &self.file, pos: 0 };
+            read_block_into(&mut f, h, vec![0u8; h.size as usize + BLOCK_TRAILER_SIZE], verify)?;
+            it.next();
+        }
+        Ok(())
+    }
+
+    /// 点查最新版本，和 get_with_options 不同的是分得清 “文件里没有这个 key” 和
+    /// “最新版本是删除”：后者说明更老的文件里的版本都不可见了，不用再往下查
+    ///
+    /// 读 block 出错（IO、校验和不对）原样返回，不当成没找到
+    pub fn lookup_with_options(
+        self: &Arc<Self>,
+        user_key: &[u8],
+        stats: Option<&CfStatistics>,
+        opts: &ReadOptions,
+    ) -> Result<LookupResult, DBError> {
+        let (data_handle, data_block_offset) = match self.find_data_block(user_key) {
+            Ok(found) => found,
+            // 比最后一个 block 还大
+            Err(DBError::NotFound(_)) => return Ok(LookupResult::NotFound),
+            Err(e) => return Err(e),
+        };
+        if self.filter_rules_out(user_key, data_block_offset, stats) {
+            return Ok(LookupResult::NotFound);
+        }
+        // 先只看 index 指到的 block，读 block 的错误在这里冒出来
+        let block = self.read_data_block_cached(data_handle, BlockAccessCaller::Get, opts.verify_checksums, opts.fill_cache)?;
+        self.lookup_in_block(&block, user_key, opts)
+    }
+
+    /// filter 判定 `key` 不在 `data_block_offset` 这个 block 里；没有 filter 时 false
+    fn filter_rules_out(&self, key: &[u8], data_block_offset: u64, stats: Option<&CfStatistics>) -> bool {
+        let (Some(fb), Some(policy)) = (&self.filter_block, &self.filter_policy) else { return false };
+        let Some(filter) = fb.filter_for_data_block(data_block_offset) else { return false };
+        let may_match = policy.may_match(key, filter);
+        if let Some(s) = stats {
+            s.record_filter_check(!may_match);
+        }
+        !may_match
+    }
+
+    /// 在 index 指到的 `block` 里找 `user_key` 的最新版本
+    fn lookup_in_block(self: &Arc<Self>, block: &DataBlock, user_key: &[u8], opts: &ReadOptions) -> Result<LookupResult, DBError> {
+        let mut it = block.iter();
+        let mut newest = newest_version(&mut it, user_key, SequenceNumber::MAX >> 8)?;
+        if !it.valid() {
+            // 扫到了 block 末尾，这个 key 的版本可能接着写在下一个 block 里
+            newest = newest_version(self.iter_with_options(opts).as_mut(), user_key, SequenceNumber::MAX >> 8)?;
+        }
+        Ok(match newest {
+            None => LookupResult::NotFound,
+            Some((_, ValueType::Delete, _)) => LookupResult::Deleted,
+            // 没有 merge operator 的 CF 里 operand 就当 value 返回，和 get 一样
+            Some((_, ValueType::Put | ValueType::Merge, v)) => LookupResult::Found(v),
+        })
     }
 
     fn find_data_block(&self, key: &[u8]) -> Result<(BlockHandle, u64), DBError> {
-        let handle_opt = self.index_block.find_data_block(key)?;
+        let cached = LAST_INDEX_LOOKUP.with(|last| match &*last.borrow() {
+            Some(l) if l.file_number == self.file_number
+                && l.generation == self.generation
+                && key >= l.low.as_slice()
+                && key <= l.high.as_slice() => Some(l.handle),
+            _ => None,
+        });
+        if let Some(h) = cached {
+            return Ok((h, h.offset));
+        }
+
+        let entry = self.index_block.find_data_block_entry(key)?;
 
         // If found, return the BlockHandle and use its offset as the sequence/snapshot marker
-        if let Some(h) = handle_opt {
-            return Ok((h.clone(), h.offset));
+        if let Some((high, h)) = entry {
+            self.remember_index_lookup(key, high, h);
+            return Ok((h, h.offset));
         }
 
         // Key not found in index is treated as an error for this API
@@ -113,31 +429,111 @@ impl SstReader {
         )))
     }
 
-    fn read_data_block_cached(&self, h: BlockHandle) -> Result<Arc<DataBlock>, DBError> {
+    /// `key` 查到的是 largest_key 为 `high` 的 block：[key, high] 内的 key 都会落到它
+    fn remember_index_lookup(&self, key: &[u8], high: Vec<u8>, handle: BlockHandle) {
+        LAST_INDEX_LOOKUP.with(|last| {
+            let mut last = last.borrow_mut();
+            match last.as_mut() {
+                // 同一个 block：往下扩大区间
+                Some(l) if l.file_number == self.file_number
+                    && l.generation == self.generation
+                    && l.handle.offset == handle.offset => {
+                    if key < l.low.as_slice() {
+                        l.low = key.to_vec();
+                    }
+                }
+                _ => {
+                    *last = Some(LastIndexLookup {
+                        file_number: self.file_number,
+                        generation: self.generation,
+                        low: key.to_vec(),
+                        high,
+                        handle,
+                    });
+                }
+            }
+        });
+    }
+
+    /// `fill_cache` 为 false 时从磁盘读到的 block 只给这一次用，不挤掉 cache 里的热 block
+    fn read_data_block_cached(&self, h: BlockHandle, caller: BlockAccessCaller, verify_checksums: bool, fill_cache: bool) -> Result<Arc<DataBlock>, DBError> {
         let k = BlockCacheKey { file_number: self.file_number, block_offset: h.offset };
-        if let Some(b) = self.block_cache.get(&k) {
+        let cached = self.block_cache.get_for(&k, caller.as_str());
+        if let Some(t) = &self.tracer {
+            t.record(self.file_number, h.offset, h.size, caller, cached.is_some());
+        }
+        if let Some(b) = cached {
+            perf_record(|p| p.block_cache_hit_count += 1);
             return Ok(b);
         }
 
-        let mut f = BufReader::new(File::open(&self.path).map_err(DBError::Io)?);
-        let bytes = read_block_raw(&mut f, h)?;
+        let mut f = FileAt { file: &self.file, pos: 0 };
+        let buf = match &self.allocator {
+            Some(a) => a.allocate_block(h.size as usize + BLOCK_TRAILER_SIZE),
+            None => vec![0u8; h.size as usize + BLOCK_TRAILER_SIZE],
+        };
+        let verify = verify_checksums.then_some((self.file_number, self.footer.checksum_type));
+        let bytes = read_block_into(&mut f, h, buf, verify)?;
+        perf_record(|p| {
+            p.block_read_count += 1;
+            p.block_read_bytes += h.size;
+        });
+        let charge = bytes.capacity();
         let b = Arc::new(DataBlock::from_bytes(bytes)?);
 
-        // 估算 charge（工业级：用 bytes.len() + overhead）
-        self.block_cache.insert(k, Arc::clone(&b), 0);
+        if fill_cache {
+            self.block_cache.insert_for(k, Arc::clone(&b), charge, caller.as_str());
+        }
         Ok(b)
     }
 }
 
+/// 按偏移读共享的 fd（pread），不动文件指针，多个线程同时读同一个 reader 互不影响
+struct FileAt<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl Read for FileAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = read_at(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for FileAt<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => self.file.metadata()?.len().checked_add_signed(d),
+        }
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start of file"))?;
+        Ok(self.pos)
+    }
+}
+
+/// 读一个 block；`verify` = Some((file_number, checksum)) 时先校验 trailer 里的 crc
 pub fn read_block_raw<R: Read + Seek>(
     r: &mut R,
     h: BlockHandle,
+    verify: Option<(u64, ChecksumType)>,
 ) -> Result<Vec<u8>, DBError> {
 
     let block_size = h.size as usize + BLOCK_TRAILER_SIZE;
+    read_block_into(r, h, vec![0u8; block_size], verify)
+}
 
-    let mut buf = vec![0u8; block_size];
-    // TODO: 校验 crc / 解压缩
+/// 同 read_block_raw，读进调用方分配好的 buf（长度 = size + trailer）
+///
+/// 返回去掉 trailer、按 trailer 里的类型解压后的 block 内容
+fn read_block_into<R: Read + Seek>(
+    r: &mut R,
+    h: BlockHandle,
+    mut buf: Vec<u8>,
+    verify: Option<(u64, ChecksumType)>,
+) -> Result<Vec<u8>, DBError> {
     // seek to offset
     r.seek(SeekFrom::Start(h.offset))
         .map_err(|e| DBError::Io(e))?;
@@ -145,5 +541,180 @@ pub fn read_block_raw<R: Read + Seek>(
     r.read_exact(&mut buf)
         .map_err(|e| DBError::Io(e))?;
 
+    if let Some((file_number, checksum)) = verify {
+        verify_block_trailer(&buf, checksum, file_number, h.offset)?;
+    }
+
+    let size = h.size as usize;
+    let compression = buf[size];
+    if compression == NO_COMPRESSION {
+        buf.truncate(size);
+        return Ok(buf);
+    }
+    decompress_block(compression, &buf[..size])
+}
+
+/// 按 handle.size 精确读取（properties block 没有 trailer）
+fn read_block_exact<R: Read + Seek>(r: &mut R, h: BlockHandle) -> Result<Vec<u8>, DBError> {
+    let mut buf = vec![0u8; h.size as usize];
+    r.seek(SeekFrom::Start(h.offset)).map_err(DBError::Io)?;
+    r.read_exact(&mut buf).map_err(DBError::Io)?;
     Ok(buf)
 }
+
+/// 给 ingest 的 SST 分配 global seqno：原地改写 properties block 里的槽位，不重写文件
+pub fn assign_global_seqno(path: &Path, seqno: SequenceNumber) -> Result<(), DBError> {
+    let mut f = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = f.metadata()?.len();
+    let footer = Footer::read_from_file(&mut f, file_len)?;
+
+    // v2 之前的 properties block 没有 global_seqno 槽位
+    if footer.format_version < DELTA_INDEX_FORMAT_VERSION {
+        return Err(DBError::InvalidArgument(format!(
+            "sst format_version {} does not support global seqno",
+            footer.format_version
+        )));
+    }
+
+    // 外部文件还没有 file number，不校验
+    let meta_bytes_raw = read_block_raw(&mut f, footer.metaindex_handle, None)?;
+    let meta_block = MetaIndexBlock::from_bytes(meta_bytes_raw)?;
+    let props_handle = meta_block
+        .find("properties")?
+        .ok_or(DBError::Corruption("sst has no properties block".into()))?;
+
+    TableProperties::update_global_seqno(&mut f, props_handle, seqno)?;
+    f.sync_data()?;
+    Ok(())
+}
+
+/// `it` 里 user_key 在 seq <= `seq` 范围内最新的一条
+///
+/// tag 是小端编码的，同一 user key 的多个版本不按 seq 排，只能全部扫一遍
+fn newest_version(
+    it: &mut dyn InternalIterator,
+    user_key: &[u8],
+    seq: SequenceNumber,
+) -> Result<Option<(SequenceNumber, ValueType, Vec<u8>)>, DBError> {
+    let mut best: Option<(SequenceNumber, ValueType, Vec<u8>)> = None;
+    it.seek(user_key);
+    while it.valid() && it.key().starts_with(user_key) {
+        let ik = InternalKey::decode(it.key())?;
+        if ik.user_key == user_key
+            && ik.seq <= seq
+            && best.as_ref().map_or(true, |(s, _, _)| ik.seq > *s)
+        {
+            best = Some((ik.seq, ik.value_type, it.value().to_vec()));
+        }
+        it.next();
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::sst::block::BloomFilterPolicy;
+    use crate::engine::sst::table_builder::TableBuilder;
+    use crate::util::{ColumnFamilyOptions, DefaultAllocator};
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    /// 小 block 的表：每个 block 只放几条，index 有很多项
+    fn build_table(name: &str, n: usize, filter: Option<Arc<dyn FilterPolicy>>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vectorkv-sst-{}-{}.sst", name, std::process::id()));
+        let mut file = std::io::BufWriter::new(File::create(&path).unwrap());
+        let mut opts = ColumnFamilyOptions::default();
+        opts.table_options.block_size = 64;
+        opts.table_options.restart_interval = 2;
+        opts.table_options.filter_policy = filter;
+        let mut builder = TableBuilder::from_options(7, &mut file, &opts);
+        for i in 0..n {
+            builder.add(&key(i * 2), format!("v{}", i * 2).as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(file);
+        path
+    }
+
+    fn last_lookup() -> Option<(u64, u64, Vec<u8>, Vec<u8>)> {
+        LAST_INDEX_LOOKUP.with(|last| {
+            last.borrow().as_ref().map(|l| (l.file_number, l.generation, l.low.clone(), l.high.clone()))
+        })
+    }
+
+    #[test]
+    fn repeated_lookups_reuse_the_last_index_entry() {
+        let path = build_table("index-lookup", 100, None);
+        let reader = SstReader::open(7, path.clone(), Arc::new(BlockCache::new(1 << 20, 1)), None).unwrap();
+
+        assert_eq!(reader.get(&key(20)).unwrap(), Some(b"v20".to_vec()));
+        let (file, generation, low, high) = last_lookup().unwrap();
+        assert_eq!((file, generation, low.as_slice()), (7, reader.generation, key(20).as_slice()));
+        assert!(high >= key(20));
+
+        // 区间内的 key 不动缓存
+        assert!(reader.get(&high).unwrap().is_some());
+        assert_eq!(last_lookup().unwrap().2, key(20));
+
+        // 同一个 block 里更小的 key：区间往下扩
+        for k in (0..20).rev() {
+            reader.get(&key(k)).unwrap();
+            let (_, _, low, h) = last_lookup().unwrap();
+            if h != high {
+                break;
+            }
+            assert_eq!(low, key(k));
+        }
+
+        for i in 0..100 {
+            assert_eq!(reader.get(&key(i * 2)).unwrap(), Some(format!("v{}", i * 2).into_bytes()));
+            if i < 99 {
+                assert_eq!(reader.get(&key(i * 2 + 1)).unwrap(), None);
+            }
+        }
+
+        // 同一个 file number 重新打开：旧 reader 留下的不算数
+        let reopened = SstReader::open(7, path.clone(), Arc::new(BlockCache::new(1 << 20, 1)), None).unwrap();
+        assert_ne!(reopened.generation, reader.generation);
+        assert_eq!(reopened.get(&key(198)).unwrap(), Some(b"v198".to_vec()));
+        assert_eq!(last_lookup().unwrap().1, reopened.generation);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn filter_checks_are_counted_per_cf() {
+        let policy: Arc<dyn FilterPolicy> = Arc::new(BloomFilterPolicy::new(10));
+        let path = build_table("bloom-stats", 100, Some(Arc::clone(&policy)));
+        let reader = SstReader::open(7, path.clone(), Arc::new(BlockCache::new(1 << 20, 1)), Some(policy)).unwrap();
+        let stats = CfStatistics::new();
+
+        for i in 0..50 {
+            assert!(reader.get_with_stats(&key(i * 2), Some(&stats)).unwrap().is_some());
+        }
+        // 存在的 key filter 一定说可能有
+        assert_eq!((stats.bloom_filter_checked(), stats.bloom_filter_useful()), (50, 0));
+
+        for i in 0..50 {
+            assert!(reader.get_with_stats(&key(i * 2 + 1), Some(&stats)).unwrap().is_none());
+        }
+        assert_eq!(stats.bloom_filter_checked(), 100);
+        assert!(stats.bloom_filter_useful() > 40);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn data_blocks_are_read_into_allocator_buffers() {
+        let path = build_table("allocator", 20, None);
+        let allocator = Arc::new(DefaultAllocator::default());
+        let reader = SstReader::open(7, path.clone(), Arc::new(BlockCache::new(1 << 20, 1)), None)
+            .unwrap()
+            .with_allocator(Some(allocator));
+        for i in 0..20 {
+            assert_eq!(reader.get(&key(i * 2)).unwrap(), Some(format!("v{}", i * 2).into_bytes()));
+        }
+        let _ = std::fs::remove_file(path);
+    }
+}
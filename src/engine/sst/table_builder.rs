@@ -3,11 +3,43 @@ use std::io::{self, Write};
 use std::sync::atomic::Ordering;
 use crate::DBError;
 use crate::engine::mem::InternalKey;
-use crate::engine::sst::block::{BlockBuilder, MetaIndexBlockBuilder, TableProperties, FilterBlockBuilder};
-use crate::engine::sst::format::{BlockHandle, Footer};
+use std::sync::Arc;
+use crate::engine::sst::block::{BlockBuilder, BLOCK_TRAILER_SIZE, IndexBlockBuilder, MetaIndexBlockBuilder, TableProperties, FilterBlockBuilder, FilterPolicy};
+use crate::engine::sst::compression::compress_block;
+use crate::engine::sst::format::{encode_block_trailer, BlockHandle, ChecksumType, Footer, CURRENT_FORMAT_VERSION, DELTA_INDEX_FORMAT_VERSION};
 use crate::engine::sst::SstReader;
 use crate::engine::version::FileMetaData;
-use crate::util::{ColumnFamilyOptions, Options};
+use crate::util::{ColumnFamilyOptions, CompressionType, Options, SliceTransform};
+
+/// 整个 SST 一个 bloom，里面是所有 user key 的前缀（`prefix_extractor` 算出来的）
+///
+/// 写在 metaindex 的 `prefixfilter.<extractor 名>` 下，读的时候 extractor 名字对不上就不用它。
+struct PrefixFilterBuilder {
+    extractor: Arc<dyn SliceTransform>,
+    policy: Arc<dyn FilterPolicy>,
+    // key 有序，同一前缀的 key 挨着，只和上一个比就能去重
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl PrefixFilterBuilder {
+    fn add_key(&mut self, internal_key: &[u8]) {
+        let Some(user_key) = internal_key.len().checked_sub(8).map(|n| &internal_key[..n]) else {
+            return;
+        };
+        if !self.extractor.in_domain(user_key) {
+            return;
+        }
+        let prefix = self.extractor.transform(user_key);
+        if self.prefixes.last().map(Vec::as_slice) != Some(prefix) {
+            self.prefixes.push(prefix.to_vec());
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        let refs: Vec<&[u8]> = self.prefixes.iter().map(Vec::as_slice).collect();
+        self.policy.create_filter(&refs)
+    }
+}
 
 pub struct TableBuilder<W: Write> {
     file_number: u64,
@@ -16,29 +48,33 @@ pub struct TableBuilder<W: Write> {
     block_size: usize,
     // Blocks
     data_block: BlockBuilder,   // Current data block
-    index_block: BlockBuilder,  // Index block
+    index_block: IndexBlockBuilder,  // Index block
     metaindex_block: MetaIndexBlockBuilder,
     // Optional filter block
     filter_block: Option<FilterBlockBuilder>,
-
-    // Pending index for delayed writing
-    pending_index_handle: Option<BlockHandle>,
-    pending_index_key:  Option<Vec<u8>>,
+    // Optional whole-table filter over key prefixes
+    prefix_filter: Option<PrefixFilterBuilder>,
 
     smallest_key: Option<Vec<u8>>,
+    block_first_key: Vec<u8>,  // first key of the current data block
     last_added_key: Option<Vec<u8>>,
     last_data_handle: Option<BlockHandle>,
 
 
 
     props: TableProperties,
+
+    // Footer 自描述信息
+    checksum_type: ChecksumType,
+    compression: CompressionType,
+    format_version: u32,
 }
 
 impl<W: Write> TableBuilder<W> {
 
     pub fn from_options(file_number:u64, dst: W, cf_opts: &ColumnFamilyOptions) -> Self {
         let table_opts = &cf_opts.table_options;
-        Self::new(
+        let mut builder = Self::new(
             file_number,
             dst,
             table_opts.block_size,
@@ -46,7 +82,23 @@ impl<W: Write> TableBuilder<W> {
             table_opts.filter_policy
                 .as_ref()
                 .map(|p| FilterBlockBuilder::new(p.clone())),
-        )
+        );
+        if let (Some(extractor), Some(policy)) = (&cf_opts.prefix_extractor, &table_opts.filter_policy) {
+            builder.prefix_filter = Some(PrefixFilterBuilder {
+                extractor: Arc::clone(extractor),
+                policy: Arc::clone(policy),
+                prefixes: Vec::new(),
+            });
+        }
+        builder.checksum_type = table_opts.checksum;
+        builder.compression = cf_opts.compression;
+        builder.format_version = table_opts.format_version;
+        builder.index_block = IndexBlockBuilder::with_index_type(
+            table_opts.index_block_restart_interval,
+            table_opts.format_version >= DELTA_INDEX_FORMAT_VERSION,
+            table_opts.index_type,
+        );
+        builder
     }
 
     pub fn new(
@@ -62,18 +114,28 @@ impl<W: Write> TableBuilder<W> {
             offset: 0,
             block_size,
             data_block: BlockBuilder::new(restart_interval),
-            index_block: BlockBuilder::new(1),       // index block restart_interval=1
+            index_block: IndexBlockBuilder::new(1),  // index block restart_interval=1
             metaindex_block: MetaIndexBlockBuilder::new(1),   // metaindex restart_interval=1
             filter_block,
-            pending_index_handle: None,
-            pending_index_key: None,
+            prefix_filter: None,
             smallest_key: None,
+            block_first_key: Vec::new(),
             last_added_key: None,
             last_data_handle: None,
             props: TableProperties::default(),
+            checksum_type: ChecksumType::default(),
+            compression: CompressionType::NoCompression,
+            format_version: CURRENT_FORMAT_VERSION,
         }
     }
 
+    /// Don't build a filter block for this table (bottommost level with
+    /// `optimize_filters_for_hits`).
+    pub fn skip_filters(&mut self) {
+        self.filter_block = None;
+        self.prefix_filter = None;
+    }
+
     /// Add a key-value pair
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<(), DBError> {
         // Check key order
@@ -87,8 +149,15 @@ impl<W: Write> TableBuilder<W> {
         if let Some(filter) = &mut self.filter_block {
             filter.add_key(key);
         }
+        if let Some(prefix_filter) = &mut self.prefix_filter {
+            prefix_filter.add_key(key);
+        }
 
         // Add to data block
+        if self.data_block.is_empty() {
+            self.block_first_key.clear();
+            self.block_first_key.extend_from_slice(key);
+        }
         self.data_block.add(key, value);
 
         // Flush if block size exceeded
@@ -111,36 +180,21 @@ impl<W: Write> TableBuilder<W> {
     }
 
     /// Flush current data block to file
-    fn flush_data_block(&mut self, next_key: &[u8]) -> Result<(), DBError> {
+    fn flush_data_block(&mut self, last_key: &[u8]) -> Result<(), DBError> {
         if self.data_block.is_empty() {
             return Ok(());
         }
 
-        // Finish block bytes
+        // Finish block bytes，按 CF 配置压缩后写出
         let block_bytes = self.data_block.finish();
-        let block_len = block_bytes.len() as u64;
-
-        // Write to dst
-        self.dst.write_all(&block_bytes)?;
-        let handle = BlockHandle {
-            offset: self.offset,
-            size: block_len,
-        };
-        self.offset += block_len;
+        let (contents, compression) = compress_block(self.compression, block_bytes)?;
+        let handle = Self::write_block(&mut self.dst, &mut self.offset, &contents, compression, self.checksum_type)?;
 
         // Update TableProperties
         self.props.num_entries.fetch_add(self.data_block.counter() as u64, Ordering::Relaxed);
 
-        // If there is a pending index, write it now
-        if let Some(pending_key) = self.pending_index_key.take() {
-            let mut handle_encoded = Vec::new();
-            put_varint64(&mut handle_encoded, handle.offset);
-            put_varint64(&mut handle_encoded, handle.size);
-            self.index_block.add(&pending_key, &handle_encoded);
-        }
-
-        // Set pending_index_key for next flush
-        self.pending_index_key = Some(next_key.to_vec());
+        // Index entry: largest key of this block -> handle
+        self.index_block.add(last_key, &self.block_first_key, handle);
         self.last_data_handle = Some(handle);
 
         self.data_block.reset();
@@ -150,38 +204,29 @@ impl<W: Write> TableBuilder<W> {
     /// Finish the SSTable
     pub fn finish(mut self) -> Result<FileMetaData, DBError> {
         // 1️⃣ flush data block
-        if !self.data_block.is_empty() {
-            let data_bytes = self.data_block.finish();
-            let offset = self.offset;
-            let len = data_bytes.len() as u64;
-            self.dst.write_all(&data_bytes)?;
-            self.last_data_handle = Some(BlockHandle { offset, size: len });
-            self.offset += len;
-        }
-
         // 2️⃣ add the last index entry
-        if let Some(pending_key) = self.pending_index_key.take() {
-            let handle = self.last_data_handle
-                .expect("pending_index_key exists but no last_data_handle");
-            let mut handle_encoded = Vec::new();
-            put_varint64(&mut handle_encoded, handle.offset);
-            put_varint64(&mut handle_encoded, handle.size);
-            self.index_block.add(&pending_key, &handle_encoded);
+        if !self.data_block.is_empty() {
+            let last_key = self.last_added_key.clone().unwrap_or_default();
+            self.flush_data_block(&last_key)?;
         }
 
         // 3️⃣ flush filter block (可选)
         let filter_handle = if let Some(filter) = &mut self.filter_block {
             let filter_bytes = filter.finish();
-            let offset = self.offset;
-            let len = filter_bytes.len() as u64;
-            self.dst.write_all(&filter_bytes)?;
-            self.offset += len;
-            Some(BlockHandle { offset, size: len })
+            Some(Self::write_block(&mut self.dst, &mut self.offset, &filter_bytes, CompressionType::NoCompression, self.checksum_type)?)
         } else {
             None
         };
+        let prefix_filter_handle = match &self.prefix_filter {
+            Some(pf) => Some((
+                format!("prefixfilter.{}", pf.extractor.name()),
+                Self::write_block(&mut self.dst, &mut self.offset, &pf.finish(), CompressionType::NoCompression, self.checksum_type)?,
+            )),
+            None => None,
+        };
 
         // 4️⃣ flush TableProperties block
+        self.props.index_type = self.index_block.index_type().to_u8();
         let props_handle = self.props.write_block(&mut self.dst, self.offset)?;
         self.offset += props_handle.size;
 
@@ -189,38 +234,33 @@ impl<W: Write> TableBuilder<W> {
         if let Some(fh) = filter_handle {
             self.metaindex_block.add_filter_block("bloomfilter", fh);
         }
+        // metaindex 的 key 要有序："filter." < "prefixfilter." < "properties"
+        if let Some((name, h)) = prefix_filter_handle {
+            self.metaindex_block.add(&name, h);
+        }
         self.metaindex_block.add_properties_block(props_handle);
 
         // 6️⃣ flush metaindex block
         let meta_bytes = self.metaindex_block.finish();
-        let meta_offset = self.offset;
-        let meta_len = meta_bytes.len() as u64;
-        self.dst.write_all(&meta_bytes)?;
-        self.offset += meta_len;
-        let meta_handle = BlockHandle {
-            offset: meta_offset,
-            size: meta_len,
-        };
+        let meta_handle = Self::write_block(&mut self.dst, &mut self.offset, &meta_bytes, CompressionType::NoCompression, self.checksum_type)?;
 
         // 7️⃣ flush index block
         let index_bytes = self.index_block.finish();
-        let index_offset = self.offset;
-        let index_len = index_bytes.len() as u64;
-        self.dst.write_all(&index_bytes)?;
-        self.offset += index_len;
-        let index_handle = BlockHandle {
-            offset: index_offset,
-            size: index_len,
-        };
+        let index_handle = Self::write_block(&mut self.dst, &mut self.offset, &index_bytes, CompressionType::NoCompression, self.checksum_type)?;
 
         // 8️⃣ write footer
         let footer = Footer {
             metaindex_handle: meta_handle,
             index_handle,
+            checksum_type: self.checksum_type,
+            format_version: self.format_version,
+            compression: self.compression,
         };
         let footer_bytes = footer.encode();
         self.dst.write_all(&footer_bytes)?;
         self.offset += footer_bytes.len() as u64;
+        // 调用方要 sync 文件，先把缓冲里的写出去
+        self.dst.flush()?;
 
         let file_size = self.offset;
         let smallest = self.smallest_key
@@ -234,10 +274,26 @@ impl<W: Write> TableBuilder<W> {
             file_size: file_size,
             smallest_key: smallest,
             largest_key: largest,
-            allowed_seeks: 1 << 30,
+            allowed_seeks: FileMetaData::initial_allowed_seeks(file_size),
+            file_checksum: None,
         })
     }
 
+    /// 写一个 block 加 5 字节 trailer；handle.size 不含 trailer
+    fn write_block(
+        dst: &mut W,
+        offset: &mut u64,
+        contents: &[u8],
+        compression: CompressionType,
+        checksum: ChecksumType,
+    ) -> Result<BlockHandle, DBError> {
+        dst.write_all(contents)?;
+        dst.write_all(&encode_block_trailer(contents, compression, checksum))?;
+        let handle = BlockHandle { offset: *offset, size: contents.len() as u64 };
+        *offset += (contents.len() + BLOCK_TRAILER_SIZE) as u64;
+        Ok(handle)
+    }
+
     pub fn reset(&mut self) {
         self.data_block.reset();
         self.index_block.reset();
@@ -245,9 +301,11 @@ impl<W: Write> TableBuilder<W> {
         if let Some(filter) = &mut self.filter_block {
             filter.reset();
         }
-        self.pending_index_handle = None;
-        self.pending_index_key = None;
+        if let Some(prefix_filter) = &mut self.prefix_filter {
+            prefix_filter.prefixes.clear();
+        }
         self.smallest_key = None;
+        self.block_first_key.clear();
         self.last_added_key = None;
         self.last_data_handle = None;
         self.props = TableProperties::default();
@@ -255,11 +313,3 @@ impl<W: Write> TableBuilder<W> {
     }
 }
 
-/// Helper: put u64 as varint (simplified)
-fn put_varint64(buf: &mut Vec<u8>, mut v: u64) {
-    while v >= 0x80 {
-        buf.push((v as u8) | 0x80);
-        v >>= 7;
-    }
-    buf.push(v as u8);
-}
@@ -1,73 +1,214 @@
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
 use crate::DBError;
+use crate::engine::block_trace::BlockTracer;
 use crate::engine::sst::block::{BlockCache, DataBlock, FilterPolicy};
 use crate::engine::sst::SstReader;
 use crate::engine::version::FileMetaData;
+use crate::util::{is_fd_exhausted, process_fd_limit, process_open_fds, DbConfig, MemoryAllocator, NON_TABLE_FILES};
 
+/// 进程 fd 用到软上限的这个比例就开始关空闲的 reader
+const FD_HIGH_WATERMARK: f64 = 0.9;
+/// 最多隔这么多次打开 reader 重新数一次进程的 fd（数 /proc/self/fd 要列目录）
+const FD_RESAMPLE_INTERVAL: usize = 64;
+
+/// 进程 fd 数的估计：上次数到的值加上之后打开的 reader 数。
+/// 估计值（不算之后关掉的）到水位线、或者攒够 `FD_RESAMPLE_INTERVAL` 次打开才重新数一次，
+/// 而不是每次 cache miss 都列一遍 /proc/self/fd
+#[derive(Default)]
+struct FdEstimate {
+    sampled: AtomicUsize,
+    opened_since: AtomicUsize,
+}
+
+impl FdEstimate {
+    /// 要打开一个 reader 了：需要的时候调 `count` 重新数，返回数到的值；否则 None
+    fn on_open(&self, high: usize, count: impl FnOnce() -> Option<usize>) -> Option<usize> {
+        let opened = self.opened_since.fetch_add(1, Ordering::Relaxed);
+        let estimate = self.sampled.load(Ordering::Relaxed) + opened;
+        if estimate < high && opened < FD_RESAMPLE_INTERVAL {
+            return None;
+        }
+        let open = count()?;
+        self.sampled.store(open, Ordering::Relaxed);
+        self.opened_since.store(0, Ordering::Relaxed);
+        Some(open)
+    }
+}
+
+struct CachedTable {
+    reader: Arc<SstReader>,
+    /// 上次被找到时的时钟，淘汰时先关最久没用的
+    last_used: u64,
+}
+
+/// 每个 reader 开着一个 fd，所以 cache 的大小受 `max_open_files` 和进程 fd 上限约束：
+/// 超了就按 LRU 关掉没人在用的 reader（iterator / compaction 还拿着的不关）
 pub struct TableCache {
-    cache: Mutex<HashMap<u64, Arc<SstReader>>>, // file_number → reader
-    db_path: PathBuf,
+    cache: Mutex<HashMap<u64, CachedTable>>, // file_number → reader
+    clock: AtomicU64,
+    /// 最多缓存这么多 reader；None 不限
+    capacity: Option<usize>,
+    /// 打开时读一次的进程 fd 软上限
+    fd_limit: Option<u64>,
+    /// 因为 fd 紧张被关掉的 reader 数
+    fd_evictions: AtomicU64,
+    fd_estimate: FdEstimate,
+    /// 按 file number 找文件路径
+    db_config: Arc<DbConfig>,
     block_cache: Arc<BlockCache<DataBlock>>,
     filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// 最底层文件不加载 filter（见 Options::optimize_filters_for_hits）
+    optimize_filters_for_hits: bool,
+    /// 交给 SstReader 分配 data block 缓冲
+    allocator: Option<Arc<dyn MemoryAllocator>>,
+    /// 所有 reader 共享的 block 访问轨迹
+    block_tracer: Arc<BlockTracer>,
 }
 
 impl TableCache {
-    pub fn new<P: AsRef<Path>>(
-        db_path: P,
+    pub fn new(
+        db_config: Arc<DbConfig>,
         block_cache: Arc<BlockCache<DataBlock>>,
         filter_policy: Option<Arc<dyn FilterPolicy>>,
     ) -> Self {
         Self {
             cache: Mutex::new(HashMap::new()),
-            db_path: db_path.as_ref().to_path_buf(),
+            clock: AtomicU64::new(0),
+            capacity: None,
+            fd_limit: process_fd_limit(),
+            fd_evictions: AtomicU64::new(0),
+            fd_estimate: FdEstimate::default(),
+            db_config,
             block_cache,
             filter_policy,
+            optimize_filters_for_hits: false,
+            allocator: None,
+            block_tracer: Arc::new(BlockTracer::new()),
         }
     }
 
-    /// 根据 file_number 找 sst reader
-    pub fn find_table_by_number(&self, file_number: u64) -> Option<Arc<SstReader>> {
-        let mut guard = self.cache.lock().unwrap();
+    pub fn with_allocator(mut self, allocator: Arc<dyn MemoryAllocator>) -> Self {
+        self.allocator = Some(allocator);
+        self
+    }
 
-        if let Some(reader) = guard.get(&file_number) {
-            return Some(reader.clone());
+    /// `Options::max_open_files`; -1 (or any value <= 0) leaves the cache unbounded.
+    pub fn with_max_open_files(mut self, max_open_files: i32) -> Self {
+        self.capacity = (max_open_files > 0)
+            .then(|| (max_open_files as usize).saturating_sub(NON_TABLE_FILES).max(1));
+        self
+    }
+
+    fn open_reader(
+        &self,
+        file_number: u64,
+        filter_policy: Option<Arc<dyn FilterPolicy>>,
+    ) -> Result<SstReader, DBError> {
+        let open = |path: PathBuf| -> Result<SstReader, DBError> {
+            Ok(SstReader::open(file_number, path, Arc::clone(&self.block_cache), filter_policy.clone())?
+                .with_allocator(self.allocator.clone())
+                .with_tracer(Some(Arc::clone(&self.block_tracer))))
+        };
+        let path = self.db_config.locate_sst(file_number)?;
+        match open(path.clone()) {
+            // fd 用光了：关掉一批空闲 reader 再试一次，而不是让读失败
+            Err(DBError::Io(e)) if is_fd_exhausted(&e) => {
+                let freed = self.evict_idle(|cache| cache.len() / 4 + 1);
+                log::warn!("out of file descriptors opening table {}, closed {} idle readers", file_number, freed);
+                open(path)
+            }
+            // resolver 缓存的路径不再 stat：文件被挪走了就忘掉旧路径重新找一次
+            Err(DBError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.db_config.file_resolver().forget(file_number);
+                open(self.db_config.locate_sst(file_number)?)
+            }
+            res => res,
         }
+    }
 
-        let path = self.db_path.join(format!("{file_number}.sst"));
+    pub fn block_tracer(&self) -> Arc<BlockTracer> {
+        Arc::clone(&self.block_tracer)
+    }
 
-        let reader = Arc::new(
-            SstReader::open(
-                file_number,
-                path,
-                self.block_cache.clone(),
-                self.filter_policy.clone(),
-            ).ok()?
-        );
+    pub fn with_optimize_filters_for_hits(mut self, enabled: bool) -> Self {
+        self.optimize_filters_for_hits = enabled;
+        self
+    }
 
-        guard.insert(file_number, reader.clone());
-        Some(reader)
+    /// 根据 file_number 找 sst reader
+    pub fn find_table_by_number(&self, file_number: u64) -> Option<Arc<SstReader>> {
+        self.find_or_open(file_number, self.filter_policy.clone())
     }
 
     pub fn find_table(&self, file: &Arc<FileMetaData>) -> Option<Arc<SstReader>> {
-        let mut cache = self.cache.lock().unwrap();
+        self.find_or_open(file.file_number, self.filter_policy.clone())
+    }
 
-        if let Some(r) = cache.get(&file.file_number) {
-            return Some(r.clone());
+    /// 按 level 打开：bottommost level 且开启 optimize_filters_for_hits 时不加载 filter block，
+    /// 这一层的查询大多会命中，filter 基本没用，还占内存
+    pub fn find_table_at_level(&self, file: &Arc<FileMetaData>, bottommost: bool) -> Option<Arc<SstReader>> {
+        if !(bottommost && self.optimize_filters_for_hits) {
+            return self.find_table(file);
         }
+        self.find_or_open(file.file_number, None)
+    }
 
-        let path = self.db_path.join(format!("{}.sst", file.file_number));
-        let reader = Arc::new(SstReader::open(
-                                                file.file_number,
-                                                path,
-                                                Arc::clone(&self.block_cache),
-                                                self.filter_policy.clone(),).ok()?);
-
-        cache.insert(file.file_number, reader.clone());
+    fn find_or_open(&self, file_number: u64, filter_policy: Option<Arc<dyn FilterPolicy>>) -> Option<Arc<SstReader>> {
+        if let Some(r) = self.lookup(file_number) {
+            return Some(r);
+        }
+        self.make_room();
+        let reader = Arc::new(self.open_reader(file_number, filter_policy).ok()?);
+        self.insert(file_number, Arc::clone(&reader));
         Some(reader)
     }
 
+    fn lookup(&self, file_number: u64) -> Option<Arc<SstReader>> {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.get_mut(&file_number)?;
+        entry.last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+        Some(Arc::clone(&entry.reader))
+    }
+
+    /// 打开新 reader 之前：cache 满了，或者进程 fd 快到上限，先关掉空闲的
+    fn make_room(&self) {
+        if let Some(capacity) = self.capacity {
+            self.evict_idle(|cache| (cache.len() + 1).saturating_sub(capacity));
+        }
+        let Some(limit) = self.fd_limit else { return };
+        let high = (limit as f64 * FD_HIGH_WATERMARK) as usize;
+        let Some(open) = self.fd_estimate.on_open(high, process_open_fds) else { return };
+        if open >= high {
+            // 多关一点，省得下一次打开又到线上
+            let freed = self.evict_idle(|cache| (open - high + 1).max(cache.len() / 8));
+            self.fd_evictions.fetch_add(freed as u64, Ordering::Relaxed);
+            log::info!("{} of {} file descriptors in use, closed {} idle table readers", open, limit, freed);
+        }
+    }
+
+    /// 按 LRU 关掉最多 `count(&cache)` 个只有 cache 自己拿着的 reader，返回关掉的个数
+    fn evict_idle(&self, count: impl FnOnce(&HashMap<u64, CachedTable>) -> usize) -> usize {
+        let mut cache = self.cache.lock().unwrap();
+        let n = count(&cache);
+        if n == 0 {
+            return 0;
+        }
+        let mut idle: Vec<(u64, u64)> = cache.iter()
+            .filter(|(_, e)| Arc::strong_count(&e.reader) == 1)
+            .map(|(&number, e)| (e.last_used, number))
+            .collect();
+        idle.sort_unstable();
+        let victims: Vec<u64> = idle.into_iter().take(n).map(|(_, number)| number).collect();
+        for number in &victims {
+            cache.remove(number);
+        }
+        victims.len()
+    }
+
     pub fn get(&self, file_number: u64, key: &[u8]) -> Result<Option<Vec<u8>>,DBError> {
         let table = self.find_table_by_number(file_number)
             .ok_or(DBError::NotFound(format!("file {} not found", file_number)))?;
@@ -75,8 +216,77 @@ impl TableCache {
     }
 
     pub fn insert(&self, file_number: u64, table: Arc<SstReader>) {
+        let last_used = self.clock.fetch_add(1, Ordering::Relaxed);
         let mut cache = self.cache.lock().unwrap();  // 获取锁
-        cache.insert(file_number, table);            // 插入或覆盖
+        // 插入或覆盖
+        cache.insert(file_number, CachedTable { reader: table, last_used });
+    }
+
+    /// 启动时预热：多线程打开文件（读 footer + index/filter block），
+    /// 打开过程不持有 cache 锁，返回成功打开的文件数
+    pub fn preload(&self, file_numbers: &[u64], threads: usize) -> usize {
+        let pending: Vec<u64> = {
+            let guard = self.cache.lock().unwrap();
+            // 预热不挤掉已经打开的 reader，放不下的不开
+            let room = self.capacity.map_or(usize::MAX, |c| c.saturating_sub(guard.len()));
+            file_numbers.iter().copied().filter(|n| !guard.contains_key(n)).take(room).collect()
+        };
+        if pending.is_empty() {
+            return 0;
+        }
+
+        let threads = threads.clamp(1, pending.len());
+        let chunk = pending.len().div_ceil(threads);
+        let loaded = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for files in pending.chunks(chunk) {
+                let loaded = &loaded;
+                s.spawn(move || {
+                    for &file_number in files {
+                        let reader = match self.open_reader(file_number, self.filter_policy.clone()) {
+                            Ok(r) => Arc::new(r),
+                            Err(e) => {
+                                log::warn!("preload table {} failed: {:?}", file_number, e);
+                                continue;
+                            }
+                        };
+                        let last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+                        self.cache.lock().unwrap().entry(file_number).or_insert(CachedTable { reader, last_used });
+                        loaded.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        loaded.into_inner()
+    }
+
+    /// 打开的 reader 数，以及它们常驻的 (index, filter) 字节数
+    pub fn memory_usage(&self) -> (usize, usize, usize) {
+        let guard = self.cache.lock().unwrap();
+        let mut index_bytes = 0;
+        let mut filter_bytes = 0;
+        for entry in guard.values() {
+            index_bytes += entry.reader.index_memory_usage();
+            filter_bytes += entry.reader.filter_memory_usage();
+        }
+        (guard.len(), index_bytes, filter_bytes)
+    }
+
+    /// 缓存着的 reader 数，每个占一个 fd
+    pub fn open_readers(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    /// 因为进程 fd 快用光被提前关掉的 reader 数
+    pub fn fd_evictions(&self) -> u64 {
+        self.fd_evictions.load(Ordering::Relaxed)
+    }
+
+    /// 文件被删除前从 cache 里移除 reader
+    pub fn evict(&self, file_number: u64) {
+        self.cache.lock().unwrap().remove(&file_number);
     }
 
     pub fn block_cache(&self) -> Arc<BlockCache<DataBlock>> {
@@ -87,3 +297,30 @@ impl TableCache {
         self.filter_policy.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn fd_count_is_resampled_only_periodically_or_near_the_limit() {
+        let estimate = FdEstimate::default();
+        let counted = Cell::new(0);
+        let count = |n: usize| { counted.set(counted.get() + 1); Some(n) };
+
+        // 低于水位线时攒够 FD_RESAMPLE_INTERVAL 次打开才数一次
+        assert_eq!(estimate.on_open(1000, || count(100)), None);
+        for _ in 0..FD_RESAMPLE_INTERVAL - 1 {
+            assert_eq!(estimate.on_open(1000, || count(100)), None);
+        }
+        assert_eq!(counted.get(), 0);
+        assert_eq!(estimate.on_open(1000, || count(150)), Some(150));
+        assert_eq!(counted.get(), 1);
+
+        // 估计值碰到水位线就立刻重新数
+        assert_eq!(estimate.on_open(151, || count(150)), None);
+        assert_eq!(estimate.on_open(151, || count(160)), Some(160));
+        assert_eq!(counted.get(), 2);
+    }
+}
@@ -14,13 +14,14 @@ impl TableReader {
     pub fn open(path: &Path) -> io::Result<Self> {
         let mut file = File::open(path)?;
         let file_len = file.metadata()?.len();
-        if file_len < Footer::ENCODED_LEN as u64 {
+        if file_len < Footer::LEGACY_ENCODED_LEN as u64 {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "sst too small"));
         }
 
-        // read footer
-        file.seek(SeekFrom::End(-(Footer::ENCODED_LEN as i64)))?;
-        let mut footer_buf = vec![0u8; Footer::ENCODED_LEN];
+        // read footer（新版 54 字节 / 旧版 48 字节，按 magic 自动识别）
+        let tail_len = (Footer::ENCODED_LEN as u64).min(file_len);
+        file.seek(SeekFrom::End(-(tail_len as i64)))?;
+        let mut footer_buf = vec![0u8; tail_len as usize];
         file.read_exact(&mut footer_buf)?;
         let footer = Footer::decode(&footer_buf)
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad footer"))?;
@@ -1,24 +1,138 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BinaryHeap};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use crate::engine::mem::{InternalKey, ValueType};
+use std::time::{Instant, SystemTime};
+use crate::db::event_listener::{CompactionJobInfo, TableFileCreationInfo, TableFileCreationReason};
+use crate::engine::block_trace::BlockAccessCaller;
+use crate::engine::mem::{mvcc_comparator, InternalKey, RangeTombstone, SequenceNumber, ValueType};
+use crate::engine::mem::range_tombstone::max_covering_seq;
+use crate::engine::sst::iterator::InternalIterator;
 use crate::engine::sst::SstReader;
 use crate::engine::sst::table_builder::TableBuilder;
 use crate::engine::version::version_set::{ColumnFamilyData, VersionBuilder};
-use crate::engine::version::{VersionEdit, VersionSet};
-use crate::util::{DbConfig, NUM_LEVELS};
+use crate::engine::version::{pick_compaction_file, pick_l0_compaction_files, pick_universal_compaction, FileMetaData, JobKind, JobRecord, VersionEdit, VersionSet};
+use crate::util::{file_checksum, sync_dir, sync_file, ColumnFamilyOptions, CompactionStyle, CpuTimer, DbConfig, Options, NUM_LEVELS};
+use crate::DBError;
 
 pub trait MergeOperator {
     fn merge(&self, key: &[u8], existing: Option<&[u8]>, value: &[u8]) -> Vec<u8>;
 }
 
+/// 把 `operands`（从新到旧）依次叠到 `base` 上，最老的先合
+pub fn full_merge(
+    op: &dyn MergeOperator,
+    key: &[u8],
+    base: Option<&[u8]>,
+    operands: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut iter = operands.iter().rev();
+    let Some(first) = iter.next() else { return base.unwrap_or_default().to_vec() };
+    let mut acc = op.merge(key, base, first);
+    for operand in iter {
+        acc = op.merge(key, Some(&acc), operand);
+    }
+    acc
+}
+
+/// compaction 时同一个 user key 上连续的 merge operand，等遇到 base（Put / Delete）
+/// 或者 key 结束再决定怎么输出
+///
+/// 比最老 snapshot 新的版本每个 snapshot 读到的合并结果都不一样，原样留着；
+/// 只有 seq <= 最老 snapshot 的那一段能合成一条 Put。
+struct MergeRun {
+    /// 从新到旧；遇到的 base 也在里面（最后一条）
+    versions: Vec<(InternalKey, Vec<u8>)>,
+    oldest_snapshot: SequenceNumber,
+    /// 以最老 snapshot 读时盖住这个 key 的范围墓碑：比它老的版本谁都读不到，当成删除
+    covered: Option<SequenceNumber>,
+    /// 最老 snapshot 之下已经遇到 Put / Delete / 范围墓碑，更老的版本可以丢掉
+    closed: bool,
+}
+
+impl MergeRun {
+    fn new(oldest_snapshot: SequenceNumber, covered: Option<SequenceNumber>) -> Self {
+        Self { versions: Vec::new(), oldest_snapshot, covered, closed: false }
+    }
+
+    fn push(&mut self, key: InternalKey, value: Vec<u8>) {
+        if self.closed {
+            return;
+        }
+        if key.seq <= self.oldest_snapshot {
+            if self.covered.is_some_and(|t| key.seq < t) {
+                self.closed = true;
+                return;
+            }
+            self.closed = key.value_type != ValueType::Merge;
+        }
+        self.versions.push((key, value));
+    }
+
+    /// 要写进输出文件的版本，从新到旧
+    ///
+    /// 最老 snapshot 之下那一段有 base 或者输出到最底层（下面不会再有更老的版本）时
+    /// 合成一条 Put，否则原样输出
+    fn resolve(
+        self,
+        op: Option<&(dyn MergeOperator + Send + Sync)>,
+        bottommost: bool,
+    ) -> Vec<(InternalKey, Vec<u8>)> {
+        let split = self.versions.iter()
+            .position(|(k, _)| k.seq <= self.oldest_snapshot)
+            .unwrap_or(self.versions.len());
+        let mut versions = self.versions;
+        let stripe = versions.split_off(split);
+        let Some((newest, _)) = stripe.first() else { return versions };
+        match op {
+            Some(op) if self.closed || bottommost => {
+                let mut operands = Vec::new();
+                let mut base = None;
+                for (k, v) in &stripe {
+                    match k.value_type {
+                        ValueType::Merge => operands.push(v.clone()),
+                        ValueType::Put => base = Some(v.as_slice()),
+                        ValueType::Delete => {}
+                    }
+                }
+                if operands.is_empty() {
+                    // 这一段只剩 base 自己，最底层的删除不用再留
+                    let (k, v) = &stripe[0];
+                    if k.value_type == ValueType::Put || !bottommost {
+                        versions.push((k.clone(), v.clone()));
+                    }
+                    return versions;
+                }
+                let merged = full_merge(op, &newest.user_key, base, &operands);
+                versions.push((InternalKey::new(newest.user_key.clone(), newest.seq, ValueType::Put), merged));
+            }
+            _ => versions.extend(stripe),
+        }
+        versions
+    }
+
+    fn finish<W: std::io::Write>(
+        self,
+        builder: &mut TableBuilder<W>,
+        op: Option<&(dyn MergeOperator + Send + Sync)>,
+        bottommost: bool,
+    ) -> Result<(), DBError> {
+        for (ikey, value) in self.resolve(op, bottommost) {
+            let mut key = Vec::new();
+            ikey.encode_to(&mut key);
+            builder.add(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
 struct HeapItem<'a> {
     key: InternalKey, // InternalKey 包含 user_key + seq + value_type
     value: Vec<u8>,
     iter_index: usize,
-    iter: Box<dyn Iterator<Item = (InternalKey, Vec<u8>)> + 'a>,
+    iter: Box<dyn InternalIterator + 'a>,
 }
 
 // PartialEq / Eq
@@ -37,8 +151,9 @@ impl<'a> PartialOrd for HeapItem<'a> {
 }
 impl<'a> Ord for HeapItem<'a> {
     fn cmp(&self, other: &Self) -> Ordering {
-        // BinaryHeap 默认是最大堆，如果你希望最小 key 在堆顶，反转 cmp
-        other.key.cmp(&self.key)
+        // BinaryHeap 默认是最大堆，反转 cmp 让最小 key 在堆顶；
+        // 同一个 user key 按 mvcc 顺序，新版本先出来
+        mvcc_comparator(&other.key, &self.key)
     }
 }
 pub struct Compactor {
@@ -56,10 +171,12 @@ impl Compactor {
     /// 自动触发所有层级 compact（多线程）
     pub fn auto_compact(&self) {
         for level in 0..NUM_LEVELS-1 {
+            let db_config = Arc::clone(&self.db_config);
+            let version_set = Arc::clone(&self.version_set);
             let cf = Arc::clone(&self.cf);
             let op = self.merge_operator.clone();
             thread::spawn(move || {
-                let comp = SingleLevelCompaction::new(self.db_config, self.version_set, cf, op);
+                let comp = SingleLevelCompaction::new(db_config, version_set, cf, op);
                 let _ = comp.compact_level(level, None, None);
             });
         }
@@ -83,6 +200,22 @@ impl SingleLevelCompaction  {
             return Err("Already top level".into());
         }
 
+        // universal / FIFO 不分层挑文件，一次看整个 CF，只在 L0 这一轮做
+        match self.cf.options(&self.db_config.options).compaction_style {
+            CompactionStyle::Level => {}
+            CompactionStyle::Universal => {
+                return if level_num == 0 && begin.is_none() && end.is_none() { self.compact_universal() } else { Ok(()) };
+            }
+            CompactionStyle::Fifo => {
+                if level_num == 0 {
+                    FifoCompaction::new(Arc::clone(&self.version_set), Arc::clone(&self.cf))
+                        .compact(self.db_config.options.as_ref())
+                        .map_err(|e| format!("{:?}", e))?;
+                }
+                return Ok(());
+            }
+        }
+
         // 1️⃣ 获取当前 Version
         let current_version = self.cf.current.as_ref().clone();
 
@@ -92,49 +225,130 @@ impl SingleLevelCompaction  {
         let level_files = &builder.levels[level_num];
 
         // 3️⃣ 选择文件
-        let files_to_compact: Vec<_> = level_files.iter()
-            .filter(|f| (begin.map_or(true, |b| f.largest_key.as_slice() >= b)) &&
-                (end.map_or(true, |e| f.smallest_key.as_slice() < e)))
-            .cloned()
-            .collect();
+        //    L0 文件互相重叠，被标记的文件连同和它们重叠的一起做；自动触发的非 L0 compaction
+        //    按 compaction_pri 只挑一个
+        let marked = self.version_set.lock().unwrap().files_marked_for_compaction(self.cf.cf_id);
+        let files_to_compact: Vec<_> = if level_num == 0 {
+            pick_l0_compaction_files(level_files, &marked, begin, end)
+        } else if begin.is_none() && end.is_none() {
+            let pri = self.cf.options(&self.db_config.options).compaction_pri;
+            pick_compaction_file(level_files, &builder.levels[level_num + 1], pri, &marked)
+                .into_iter()
+                .collect()
+        } else {
+            level_files.iter()
+                .filter(|f| (begin.map_or(true, |b| f.largest_key.as_slice() >= b)) &&
+                    (end.map_or(true, |e| f.smallest_key.as_slice() < e)))
+                .cloned()
+                .collect()
+        };
 
         if files_to_compact.is_empty() { return Ok(()); }
 
+        let inputs: Vec<_> = files_to_compact.into_iter().map(|f| (level_num, f)).collect();
+        let bottommost = current_version.is_bottommost_level(level_num + 1);
+        self.compact_files(&inputs, level_num + 1, bottommost, None)
+    }
+
+    /// size-tiered：按 `pick_universal_compaction` 合并一段相邻的 sorted run
+    pub fn compact_universal(&self) -> Result<(), String> {
+        let cf_opts = self.cf.options(&self.db_config.options);
+        let levels = self.cf.current.levels();
+        let Some(pick) = pick_universal_compaction(
+            &levels,
+            &cf_opts.universal,
+            self.db_config.options.level0_file_num_compaction_trigger,
+        ) else {
+            return Ok(());
+        };
+        self.compact_files(&pick.inputs, pick.output_level, pick.bottommost, None)
+    }
+
+    /// 用 `options` 的压缩 / filter / block 设置把 `files` 逐个重写一遍，留在原来的层
+    ///
+    /// 改了表选项之后可以一批批地迁移老文件，不用对整个 CF 做 manual compaction。
+    /// key 范围不变，L1+ 的不重叠约束还成立；L0 文件按 file_number 排新旧，换个更大的号
+    /// 就被当成比后来的文件还新，所以不接受 L0 的文件。
+    pub fn rewrite_files(&self, files: &[(usize, Arc<FileMetaData>)], options: &ColumnFamilyOptions) -> Result<(), String> {
+        if let Some((_, f)) = files.iter().find(|(level, _)| *level == 0) {
+            return Err(format!("file {} is in L0; compact L0 before rewriting it", f.file_number));
+        }
+        for (level, file) in files {
+            let bottommost = self.cf.current.is_bottommost_level(*level);
+            self.compact_files(&[(*level, Arc::clone(file))], *level, bottommost, Some(options))?;
+        }
+        Ok(())
+    }
+
+    /// 把 `inputs`（(level, file)）合并成一个文件放到 `output_level`，再写 MANIFEST
+    ///
+    /// `options` 为 None 时用 CF 自己的表选项写新文件。
+    fn compact_files(
+        &self,
+        inputs: &[(usize, Arc<FileMetaData>)],
+        output_level: usize,
+        bottommost: bool,
+        options: Option<&ColumnFamilyOptions>,
+    ) -> Result<(), String> {
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let cpu = CpuTimer::start();
+
         // 4️⃣ 打开 reader & iterator
         let mut iters = Vec::new();
-        for file in &files_to_compact {
+        for (_, file) in inputs {
             let reader = SstReader::open(
                 file.file_number,
-                self.db_config.sst_path(file.file_number),
+                self.db_config.locate_sst(file.file_number).map_err(|e| format!("{:?}", e))?,
                 self.cf.current.table_cache().block_cache(),
-                self.db_config.get_filter_policy(self.cf.cf_type).clone(),
-            )?;
+                self.cf.options(&self.db_config.options).table_options.filter_policy.clone(),
+            )
+            .map_err(|e| format!("{:?}", e))?
+            .with_tracer(Some(self.cf.current.table_cache().block_tracer()));
 
-            iters.push(reader.iter());
+            iters.push(Arc::new(reader).iter_for(BlockAccessCaller::Compaction));
         }
 
         // 5️⃣ init heap
         let mut heap = BinaryHeap::new();
-        for (idx, iter) in iters.iter_mut().enumerate() {
-            if let Some(entry) = iter.next() {
+        for (idx, mut iter) in iters.into_iter().enumerate() {
+            iter.seek_to_first();
+            if iter.valid() {
                 heap.push(HeapItem {
-                    key: entry.key,
-                    value: entry.value,
+                    key: InternalKey::decode(iter.key()).map_err(|e| format!("{:?}", e))?,
+                    value: iter.value().to_vec(),
                     iter_index: idx,
-                    iter: Box::new(iter.by_ref().map(|(k, v)| (k, v))),
+                    iter,
                 });
             }
         }
 
         // 6️⃣ 输出新 SST
-        let cf_opts = &self.db_config.get_column_family_options(self.cf.cf_type);
+        let cf_opts = options.unwrap_or_else(|| self.cf.options(&self.db_config.options));
         let file_number = {
             let vs = self.version_set.lock().unwrap();
-            vs.new_file_number()
+            vs.new_file_number().map_err(|e| format!("{:?}", e))?
         };
-        let mut builder = TableBuilder::from_options(file_number, self.new_sst_path(level_num + 1, file_number), cf_opts);
+        // 先写临时文件名：crash 时留下的半个文件不会被当成 SST，open 时清掉
+        let temp_path = self.db_config.temp_sst_path(file_number);
+        let file = File::create(&temp_path).map_err(|e| e.to_string())?;
+        let mut builder = TableBuilder::from_options(file_number, BufWriter::new(file), cf_opts);
+
+        // 输出到最底层时，大部分查询都会命中，filter 省掉
+        if self.db_config.options.optimize_filters_for_hits && bottommost {
+            builder.skip_filters();
+        }
 
         let mut last_user_key: Option<Vec<u8>> = None;
+        // 同一个 user key 上一条（更新的）版本的 seq
+        let mut last_seq_for_key = SequenceNumber::MAX;
+        let merge_operator = self.merge_operator.as_deref();
+        let mut merging: Option<MergeRun> = None;
+        // 没有 snapshot 时所有人都只看得到最新版本
+        let oldest_snapshot = self.version_set.lock().unwrap()
+            .oldest_snapshot()
+            .unwrap_or(SequenceNumber::MAX);
+        let range_tombstones = self.cf.current.range_tombstones();
 
         while let Some(item) = heap.pop() {
             let HeapItem { key, value, iter_index, mut iter } = item;
@@ -145,16 +359,47 @@ impl SingleLevelCompaction  {
                 .unwrap_or(true);
 
             if is_new_key {
-                if key.value_type == ValueType::Put {
-                    builder.add(&key, &value)?;
+                if let Some(run) = merging.take() {
+                    run.finish(&mut builder, merge_operator, bottommost).map_err(|e| format!("{:?}", e))?;
                 }
                 last_user_key = Some(key.user_key.clone());
+                last_seq_for_key = SequenceNumber::MAX;
+            }
+            // 更新的那条版本所有 snapshot 都看得到，这条谁也读不到了
+            let hidden = last_seq_for_key <= oldest_snapshot;
+            last_seq_for_key = key.seq;
+
+            if let Some(run) = merging.as_mut() {
+                run.push(key.clone(), value.clone());
+            } else if !is_new_key && hidden {
+                // 被覆盖的旧版本
+            } else if key.value_type != ValueType::Merge
+                && max_covering_seq(range_tombstones, &key.user_key, oldest_snapshot).is_some_and(|t| key.seq < t)
+            {
+                // 被范围墓碑删掉、也没有 snapshot 还看得到的版本
+            } else {
+                match key.value_type {
+                    // 最底层、且没有 snapshot 还要看它下面的版本时，墓碑才能丢
+                    ValueType::Delete if bottommost && key.seq <= oldest_snapshot => {}
+                    ValueType::Put | ValueType::Delete => {
+                        let mut encoded = Vec::new();
+                        key.encode_to(&mut encoded);
+                        builder.add(&encoded, &value).map_err(|e| format!("{:?}", e))?;
+                    }
+                    // merge operand：接着收集更老的版本
+                    ValueType::Merge => {
+                        let covered = max_covering_seq(range_tombstones, &key.user_key, oldest_snapshot);
+                        let mut run = MergeRun::new(oldest_snapshot, covered);
+                        run.push(key.clone(), value.clone());
+                        merging = Some(run);
+                    }
+                }
             }
 
             iter.next();
             if iter.valid() {
                 heap.push(HeapItem {
-                    key: InternalKey::decode(iter.key()),
+                    key: InternalKey::decode(iter.key()).map_err(|e| format!("{:?}", e))?,
                     value: iter.value().to_vec(),
                     iter_index,
                     iter,
@@ -162,30 +407,272 @@ impl SingleLevelCompaction  {
             }
         }
 
-        let new_file = builder.finish()?;
+        if let Some(run) = merging.take() {
+            run.finish(&mut builder, merge_operator, bottommost).map_err(|e| format!("{:?}", e))?;
+        }
+
+        let new_file = builder.finish().map_err(|e| format!("{:?}", e))?;
+        // sync 完再原子改名，目录也 sync 了才写 MANIFEST
+        let new_path = self.db_config.sst_path(output_level, file_number);
+        sync_file(&temp_path, self.db_config.options.use_fsync).map_err(|e| e.to_string())?;
+        std::fs::rename(&temp_path, &new_path).map_err(|e| e.to_string())?;
+        self.db_config.file_resolver().record(file_number, new_path.clone());
+        if let Some(dir) = new_path.parent() {
+            sync_dir(dir).map_err(|e| e.to_string())?;
+        }
 
         // 7️⃣ Version edit
+        let mut record = JobRecord::new(JobKind::Compaction, self.cf.cf_id, started_at, started.elapsed());
+        record.cpu_micros = cpu.elapsed().as_micros() as u64;
         let mut edit = VersionEdit::new(self.cf.cf_id, self.cf.cf_type);
-        for f in files_to_compact {
-            edit.delete_file(level_num, f.file_number);
+        if bottommost {
+            for t in self.retirable_range_tombstones(inputs, file_number, oldest_snapshot) {
+                edit.delete_range_tombstone(t);
+            }
+        }
+        for (level, f) in inputs {
+            edit.delete_file(*level, f.file_number);
+            record.input_files.push(f.file_number);
+            record.input_bytes += f.file_size;
         }
+        record.output_files.push(new_file.file_number);
+        record.output_bytes = new_file.file_size;
+        record.output_level = output_level;
         edit.add_file(
-            level_num + 1,
+            output_level,
             new_file.file_number,
             new_file.file_size,
-            new_file.smallest_key.clone(),
-            new_file.largest_key.clone(),
+            &new_file.smallest_key,
+            &new_file.largest_key,
+        );
+        edit.set_file_checksum(
+            new_file.file_number,
+            file_checksum(&new_path).map_err(|e| e.to_string())?,
         );
 
 
-        self.version_set.lock().unwrap().log_and_apply(edit)?;
+        // 和同时结束的 flush / compaction 一起写 MANIFEST
+        let queue = self.version_set.lock().unwrap().manifest_queue();
+        let result = queue.submit(&self.version_set, edit);
+        let vs = self.version_set.lock().unwrap();
+        vs.release_pending_output(file_number);
+        result.map_err(|e| format!("{:?}", e))?;
+        // 输入文件已经不在 current 里了
+        if let Err(e) = vs.delete_obsolete_files() {
+            log::warn!("failed to delete obsolete files after compaction: {:?}", e);
+        }
+        let info = CompactionJobInfo {
+            cf: self.cf.cf_id,
+            input_files: inputs.iter().map(|(level, f)| (*level, f.file_number)).collect(),
+            output_level,
+            output_files: record.output_files.clone(),
+            input_bytes: record.input_bytes,
+            output_bytes: record.output_bytes,
+            elapsed: started.elapsed(),
+        };
+        if let Some(stats) = vs.cf_statistics(self.cf.cf_id) {
+            stats.record_job(&record);
+        }
+        vs.job_log().append(record);
+        drop(vs);
+
+        // listener 可能回调 DB，放掉锁再通知
+        let listeners = self.db_config.listeners();
+        let created = TableFileCreationInfo {
+            cf: self.cf.cf_id,
+            file_number: new_file.file_number,
+            file_path: new_path,
+            file_size: new_file.file_size,
+            level: output_level,
+            reason: TableFileCreationReason::Compaction,
+        };
+        listeners.notify(|l| l.on_table_file_created(&created));
+        listeners.notify(|l| l.on_compaction_completed(&info));
 
         Ok(())
     }
 
-    pub fn new_sst_path(&self, level: usize, file_number: usize) -> PathBuf {
-        let level_dir = self.db_config.sst_dir.join(format!("L{}", level));
-        let file_name = format!("{:06}.sst", file_number);
-        level_dir.join(file_name)
+    /// 这次输出到最底层的 compaction 之后可以从 Version 里摘掉的范围墓碑
+    ///
+    /// 只看开始合并时的那些墓碑（它们盖住的版本在上面合并时已经丢了），只要：
+    /// - 没有 snapshot 还要以比它老的 seq 读；
+    /// - 当前 Version 里和它重叠的文件全是这次的输入；
+    /// - 比输出文件号小的 memtable 都已经 flush 完（还在 flush 的老 memtable 里可能有它盖住的版本）。
+    fn retirable_range_tombstones(
+        &self,
+        inputs: &[(usize, Arc<FileMetaData>)],
+        output_file_number: u64,
+        oldest_snapshot: SequenceNumber,
+    ) -> Vec<RangeTombstone> {
+        let vs = self.version_set.lock().unwrap();
+        if vs.has_pending_output_below(output_file_number) {
+            return Vec::new();
+        }
+        let current = vs.current_version(self.cf.cf_id);
+        let input_numbers: HashSet<u64> = inputs.iter().map(|(_, f)| f.file_number).collect();
+        self.cf.current.range_tombstones().iter()
+            .filter(|t| t.seq <= oldest_snapshot)
+            .filter(|t| {
+                current.levels().iter().flatten()
+                    .filter(|f| file_may_overlap(f, &t.begin, &t.end))
+                    .all(|f| input_numbers.contains(&f.file_number))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// 文件的 key 范围是否可能和 [begin, end) 重叠
+///
+/// flush 的文件首尾记的是 internal key（user key + 8 字节 tag），compaction 的是 user key；
+/// 去掉最后 8 字节当下界、原样当上界，两种都不会漏判
+fn file_may_overlap(f: &FileMetaData, begin: &[u8], end: &[u8]) -> bool {
+    let lower = &f.smallest_key[..f.smallest_key.len().saturating_sub(8)];
+    lower < end && f.largest_key.as_slice() >= begin
+}
+
+/// FIFO compaction：不合并，CF 总大小超过 `max_table_files_size` 时从最老的文件开始删
+///
+/// FIFO 的 CF 只有 flush 写文件，全在 L0，file_number 越小越老。
+pub struct FifoCompaction {
+    version_set: Arc<Mutex<VersionSet>>,
+    cf: Arc<ColumnFamilyData>,
+}
+
+impl FifoCompaction {
+    pub fn new(version_set: Arc<Mutex<VersionSet>>, cf: Arc<ColumnFamilyData>) -> Self {
+        Self { version_set, cf }
+    }
+
+    /// 删掉超出部分最老的文件，返回删掉的 file_number（文件本身由调用方 purge）
+    pub fn compact(&self, options: &Options) -> Result<Vec<u64>, DBError> {
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let max_size = self.cf.options(options).fifo.max_table_files_size;
+
+        let mut files: Vec<(usize, Arc<FileMetaData>)> = self.cf.current.levels()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(level, files)| files.into_iter().map(move |f| (level, f)))
+            .collect();
+        files.sort_by_key(|(_, f)| f.file_number);
+
+        let mut total: u64 = files.iter().map(|(_, f)| f.file_size).sum();
+        let mut edit = VersionEdit::new(self.cf.cf_id, self.cf.cf_type);
+        let mut record = JobRecord::new(JobKind::Compaction, self.cf.cf_id, started_at, started.elapsed());
+        for (level, f) in &files {
+            if total <= max_size {
+                break;
+            }
+            edit.delete_file(*level, f.file_number);
+            record.input_files.push(f.file_number);
+            record.input_bytes += f.file_size;
+            total -= f.file_size;
+        }
+        if record.input_files.is_empty() {
+            return Ok(Vec::new());
+        }
+        log::info!(
+            "fifo compaction of cf {} drops {} files ({} bytes), {} bytes left",
+            self.cf.cf_id, record.input_files.len(), record.input_bytes, total
+        );
+
+        let queue = self.version_set.lock().unwrap().manifest_queue();
+        queue.submit(&self.version_set, edit)?;
+        let dropped = record.input_files.clone();
+        let vs = self.version_set.lock().unwrap();
+        if let Some(stats) = vs.cf_statistics(self.cf.cf_id) {
+            stats.record_job(&record);
+        }
+        vs.job_log().append(record);
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 把 operand 接在后面
+    struct Append;
+
+    impl MergeOperator for Append {
+        fn merge(&self, _key: &[u8], existing: Option<&[u8]>, value: &[u8]) -> Vec<u8> {
+            let mut out = existing.unwrap_or_default().to_vec();
+            out.extend_from_slice(value);
+            out
+        }
+    }
+
+    fn version(seq: SequenceNumber, value_type: ValueType, value: &[u8]) -> (InternalKey, Vec<u8>) {
+        (InternalKey::new(b"k".to_vec(), seq, value_type), value.to_vec())
+    }
+
+    fn run(oldest_snapshot: SequenceNumber, covered: Option<SequenceNumber>, versions: &[(SequenceNumber, ValueType, &[u8])]) -> Vec<(SequenceNumber, ValueType, Vec<u8>)> {
+        let mut run = MergeRun::new(oldest_snapshot, covered);
+        for (seq, t, v) in versions {
+            let (k, v) = version(*seq, t.clone(), v);
+            run.push(k, v);
+        }
+        run.resolve(Some(&Append), false)
+            .into_iter()
+            .map(|(k, v)| (k.seq, k.value_type, v))
+            .collect()
+    }
+
+    #[test]
+    fn merge_folds_onto_put_base() {
+        let out = run(SequenceNumber::MAX, None, &[
+            (3, ValueType::Merge, b"c"),
+            (2, ValueType::Merge, b"b"),
+            (1, ValueType::Put, b"a"),
+        ]);
+        assert_eq!(out, vec![(3, ValueType::Put, b"abc".to_vec())]);
+    }
+
+    #[test]
+    fn put_under_range_tombstone_is_not_a_merge_base() {
+        // DeleteRange@5 盖住了 Put@1，合并结果里不能有它
+        let out = run(SequenceNumber::MAX, Some(5), &[
+            (7, ValueType::Merge, b"c"),
+            (6, ValueType::Merge, b"b"),
+            (1, ValueType::Put, b"a"),
+        ]);
+        assert_eq!(out, vec![(7, ValueType::Put, b"bc".to_vec())]);
+    }
+
+    #[test]
+    fn operands_newer_than_oldest_snapshot_stay_as_they_are() {
+        // snapshot@2 读到的是 "ab"，更新的 operand 不能并进去
+        let out = run(2, None, &[
+            (4, ValueType::Merge, b"d"),
+            (3, ValueType::Merge, b"c"),
+            (2, ValueType::Merge, b"b"),
+            (1, ValueType::Put, b"a"),
+        ]);
+        assert_eq!(out, vec![
+            (4, ValueType::Merge, b"d".to_vec()),
+            (3, ValueType::Merge, b"c".to_vec()),
+            (2, ValueType::Put, b"ab".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn file_overlap_check_accepts_internal_and_user_key_bounds() {
+        let file = |smallest: Vec<u8>, largest: &[u8]| FileMetaData {
+            file_number: 1,
+            file_size: 1024,
+            smallest_key: smallest,
+            largest_key: largest.to_vec(),
+            allowed_seeks: FileMetaData::initial_allowed_seeks(1024),
+            file_checksum: None,
+        };
+        let mut internal = Vec::new();
+        InternalKey::new(b"a".to_vec(), 7, ValueType::Put).encode_to(&mut internal);
+
+        // flush 出来的文件首 key 是 internal key：tag 的字节不能让它看起来比 "ab" 大
+        assert!(file_may_overlap(&file(internal, b"a"), b"a", b"ab"));
+        assert!(file_may_overlap(&file(b"c".to_vec(), b"f"), b"e", b"g"));
+        assert!(!file_may_overlap(&file(b"c".to_vec(), b"f"), b"g", b"h"));
     }
 }
@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::engine::version::FileMetaData;
+use crate::util::{CompactionPri, UniversalCompactionOptions, NUM_LEVELS};
+
+/// 从 `files`（某个非 L0 level）里挑一个做 compaction
+///
+/// - 被 `suggest_compact_range` 标记的文件优先（多个时仍按 `pri` 排序）
+/// - `next_level` 只有 `MinOverlappingRatio` 会用到
+pub fn pick_compaction_file(
+    files: &[Arc<FileMetaData>],
+    next_level: &[Arc<FileMetaData>],
+    pri: CompactionPri,
+    marked: &HashSet<u64>,
+) -> Option<Arc<FileMetaData>> {
+    let candidates: Vec<&Arc<FileMetaData>> = {
+        let marked_files: Vec<_> = files.iter().filter(|f| marked.contains(&f.file_number)).collect();
+        if marked_files.is_empty() { files.iter().collect() } else { marked_files }
+    };
+
+    let best = match pri {
+        CompactionPri::ByCompensatedSize => candidates
+            .into_iter()
+            .max_by_key(|f| (f.file_size, std::cmp::Reverse(f.file_number))),
+        CompactionPri::OldestSmallestSeqFirst => candidates
+            .into_iter()
+            .min_by_key(|f| f.file_number),
+        CompactionPri::MinOverlappingRatio => candidates
+            .into_iter()
+            .min_by(|a, b| {
+                overlapping_ratio(a, next_level)
+                    .total_cmp(&overlapping_ratio(b, next_level))
+                    .then(a.file_number.cmp(&b.file_number))
+            }),
+    };
+    best.cloned()
+}
+
+/// 从 L0 里挑一起 compact 的文件，按 file_number 排好
+///
+/// - 给了范围：和 `[begin, end]` 重叠的文件
+/// - 没给范围：有被标记的文件就只从它们开始（seek 用完的、`suggest_compact_range` 标的），否则整个 L0
+///
+/// L0 文件互相重叠，只搬走一部分时，和它们 key 区间重叠的其他 L0 文件也要一起搬，
+/// 否则留在 L0 的老版本会挡住搬到 L1 的新版本。所以选出来的区间一直往外扩到不再碰到新文件。
+pub fn pick_l0_compaction_files(
+    files: &[Arc<FileMetaData>],
+    marked: &HashSet<u64>,
+    begin: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Vec<Arc<FileMetaData>> {
+    let seeds: Vec<&Arc<FileMetaData>> = if begin.is_some() || end.is_some() {
+        files.iter()
+            .filter(|f| begin.map_or(true, |b| f.largest_key.as_slice() >= b)
+                && end.map_or(true, |e| f.smallest_key.as_slice() <= e))
+            .collect()
+    } else {
+        let marked_files: Vec<_> = files.iter().filter(|f| marked.contains(&f.file_number)).collect();
+        if marked_files.is_empty() { files.iter().collect() } else { marked_files }
+    };
+    let (Some(mut smallest), Some(mut largest)) = (
+        seeds.iter().map(|f| f.smallest_key.as_slice()).min(),
+        seeds.iter().map(|f| f.largest_key.as_slice()).max(),
+    ) else {
+        return Vec::new();
+    };
+
+    loop {
+        let overlapping = files.iter()
+            .filter(|f| f.smallest_key.as_slice() <= largest && f.largest_key.as_slice() >= smallest);
+        let lo = overlapping.clone().map(|f| f.smallest_key.as_slice()).min().unwrap_or(smallest);
+        let hi = overlapping.map(|f| f.largest_key.as_slice()).max().unwrap_or(largest);
+        if lo >= smallest && hi <= largest {
+            break;
+        }
+        smallest = smallest.min(lo);
+        largest = largest.max(hi);
+    }
+    let mut picked: Vec<Arc<FileMetaData>> = files.iter()
+        .filter(|f| f.smallest_key.as_slice() <= largest && f.largest_key.as_slice() >= smallest)
+        .cloned()
+        .collect();
+    picked.sort_by_key(|f| f.file_number);
+    picked
+}
+
+/// 下一层与 `file` 重叠的字节数 / `file` 自身大小
+fn overlapping_ratio(file: &FileMetaData, next_level: &[Arc<FileMetaData>]) -> f64 {
+    let overlap: u64 = next_level
+        .iter()
+        .filter(|f| f.smallest_key <= file.largest_key && f.largest_key >= file.smallest_key)
+        .map(|f| f.file_size)
+        .sum();
+    overlap as f64 / file.file_size.max(1) as f64
+}
+
+/// universal compaction 选出的一组 sorted run
+pub struct UniversalPick {
+    /// (level, file)，从新到旧
+    pub inputs: Vec<(usize, Arc<FileMetaData>)>,
+    pub output_level: usize,
+    /// 包含了最老的 run，输出下面没有更老的数据
+    pub bottommost: bool,
+}
+
+/// 一个 sorted run：一个 L0 文件，或者 L1+ 的一整层
+struct SortedRun {
+    level: usize,
+    files: Vec<Arc<FileMetaData>>,
+    size: u64,
+}
+
+/// universal（size-tiered）compaction 挑要合并的相邻 sorted run
+///
+/// run 从新到旧排：L0 文件按 file_number 从大到小，然后 L1、L2…每个非空层各算一个。
+/// run 数到了 `trigger` 才做：
+/// 1. 空间放大：除最老之外的 run 加起来超过最老那个的 `max_size_amplification_percent`%，全部合并
+/// 2. 否则从新往旧找第一段大小相近的 run：窗口累计大小 × (100 + size_ratio)% 还盖得住下一个就继续加
+///
+/// 输出放在窗口里最老的 run 所在的层，全是 L0 时放到下一个更老 run 的上一层，
+/// 这样读路径按层从新到旧查仍然对。所以窗口后面不能再剩 L0 文件。
+pub fn pick_universal_compaction(
+    levels: &[Vec<Arc<FileMetaData>>; NUM_LEVELS],
+    opts: &UniversalCompactionOptions,
+    trigger: usize,
+) -> Option<UniversalPick> {
+    let mut runs: Vec<SortedRun> = Vec::new();
+    let mut l0: Vec<_> = levels[0].iter().cloned().collect();
+    l0.sort_by_key(|f| std::cmp::Reverse(f.file_number));
+    for f in l0 {
+        runs.push(SortedRun { level: 0, size: f.file_size, files: vec![f] });
+    }
+    let num_l0 = runs.len();
+    for (level, files) in levels.iter().enumerate().skip(1) {
+        if !files.is_empty() {
+            runs.push(SortedRun { level, files: files.clone(), size: files.iter().map(|f| f.file_size).sum() });
+        }
+    }
+
+    let n = runs.len();
+    if n < trigger.max(2) {
+        return None;
+    }
+
+    let newer: u64 = runs[..n - 1].iter().map(|r| r.size).sum();
+    let window = if newer.saturating_mul(100) > runs[n - 1].size.saturating_mul(opts.max_size_amplification_percent) {
+        Some((0, n))
+    } else {
+        (0..n).find_map(|start| {
+            let mut acc = runs[start].size;
+            let mut end = start + 1;
+            while end < n
+                && end - start < opts.max_merge_width
+                && acc.saturating_mul(100 + opts.size_ratio) / 100 >= runs[end].size
+            {
+                acc += runs[end].size;
+                end += 1;
+            }
+            (end - start >= opts.min_merge_width.max(2) && end >= num_l0).then_some((start, end))
+        })
+    };
+    let (start, mut end) = window?;
+
+    let output_level = if runs[end - 1].level > 0 {
+        runs[end - 1].level
+    } else {
+        match runs.get(end) {
+            // 紧挨着的是 L1：并进来，输出到 L1
+            Some(next) if next.level == 1 => {
+                end += 1;
+                1
+            }
+            Some(next) => next.level - 1,
+            None => NUM_LEVELS - 1,
+        }
+    };
+
+    let inputs = runs[start..end]
+        .iter()
+        .flat_map(|r| r.files.iter().map(move |f| (r.level, Arc::clone(f))))
+        .collect();
+    Some(UniversalPick { inputs, output_level, bottommost: end == n })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(file_number: u64, smallest: &[u8], largest: &[u8]) -> Arc<FileMetaData> {
+        Arc::new(FileMetaData {
+            file_number,
+            file_size: 1024,
+            smallest_key: smallest.to_vec(),
+            largest_key: largest.to_vec(),
+            allowed_seeks: FileMetaData::initial_allowed_seeks(1024),
+            file_checksum: None,
+        })
+    }
+
+    fn numbers(files: &[Arc<FileMetaData>]) -> Vec<u64> {
+        files.iter().map(|f| f.file_number).collect()
+    }
+
+    #[test]
+    fn marked_l0_files_are_picked_with_everything_they_overlap() {
+        let l0 = vec![file(1, b"a", b"c"), file(2, b"b", b"f"), file(3, b"e", b"g"), file(4, b"x", b"z")];
+
+        // 没有标记：整个 L0
+        assert_eq!(numbers(&pick_l0_compaction_files(&l0, &HashSet::new(), None, None)), vec![1, 2, 3, 4]);
+
+        // 标了 1：经 2 连到 3，和它们都不重叠的 4 留下
+        let marked = HashSet::from([1]);
+        assert_eq!(numbers(&pick_l0_compaction_files(&l0, &marked, None, None)), vec![1, 2, 3]);
+
+        let marked = HashSet::from([4]);
+        assert_eq!(numbers(&pick_l0_compaction_files(&l0, &marked, None, None)), vec![4]);
+    }
+
+    #[test]
+    fn ranged_l0_pick_widens_to_overlapping_files() {
+        let l0 = vec![file(1, b"a", b"c"), file(2, b"b", b"f"), file(3, b"x", b"z")];
+        assert_eq!(numbers(&pick_l0_compaction_files(&l0, &HashSet::new(), Some(b"a"), Some(b"a"))), vec![1, 2]);
+        assert!(pick_l0_compaction_files(&l0, &HashSet::new(), Some(b"m"), Some(b"n")).is_empty());
+    }
+
+    fn sized(file_number: u64, file_size: u64) -> Arc<FileMetaData> {
+        Arc::new(FileMetaData {
+            file_number,
+            file_size,
+            smallest_key: b"a".to_vec(),
+            largest_key: b"z".to_vec(),
+            allowed_seeks: FileMetaData::initial_allowed_seeks(file_size),
+            file_checksum: None,
+        })
+    }
+
+    /// (level, file_number, file_size)
+    fn levels(files: &[(usize, u64, u64)]) -> [Vec<Arc<FileMetaData>>; NUM_LEVELS] {
+        let mut levels: [Vec<Arc<FileMetaData>>; NUM_LEVELS] = Default::default();
+        for &(level, n, size) in files {
+            levels[level].push(sized(n, size));
+        }
+        levels
+    }
+
+    fn picked(pick: &UniversalPick) -> Vec<(usize, u64)> {
+        pick.inputs.iter().map(|(level, f)| (*level, f.file_number)).collect()
+    }
+
+    #[test]
+    fn universal_waits_for_trigger_runs() {
+        let opts = UniversalCompactionOptions::default();
+        let l = levels(&[(0, 2, 1000), (0, 1, 1000)]);
+        assert!(pick_universal_compaction(&l, &opts, 4).is_none());
+        assert!(pick_universal_compaction(&l, &opts, 2).is_some());
+    }
+
+    #[test]
+    fn universal_space_amplification_merges_every_run() {
+        let last = NUM_LEVELS - 1;
+        let l = levels(&[(0, 4, 1000), (0, 3, 1000), (0, 2, 1000), (last, 1, 1000)]);
+        let pick = pick_universal_compaction(&l, &UniversalCompactionOptions::default(), 3).unwrap();
+        assert_eq!(picked(&pick), vec![(0, 4), (0, 3), (0, 2), (last, 1)]);
+        assert_eq!(pick.output_level, last);
+        assert!(pick.bottommost);
+    }
+
+    #[test]
+    fn universal_size_ratio_output_sits_above_the_next_run() {
+        let last = NUM_LEVELS - 1;
+        let opts = UniversalCompactionOptions::default();
+
+        // 两个小的 L0 合并，输出放在下一个 run（L3）的上一层
+        let l = levels(&[(0, 5, 1000), (0, 4, 1000), (3, 3, 100_000), (last, 1, 1_000_000)]);
+        let pick = pick_universal_compaction(&l, &opts, 3).unwrap();
+        assert_eq!(picked(&pick), vec![(0, 5), (0, 4)]);
+        assert_eq!(pick.output_level, 2);
+        assert!(!pick.bottommost);
+
+        // 紧挨着的是 L1：一起并进 L1
+        let l = levels(&[(0, 5, 1000), (0, 4, 1000), (1, 3, 100_000), (last, 1, 1_000_000)]);
+        let pick = pick_universal_compaction(&l, &opts, 3).unwrap();
+        assert_eq!(picked(&pick), vec![(0, 5), (0, 4), (1, 3)]);
+        assert_eq!(pick.output_level, 1);
+    }
+
+    #[test]
+    fn universal_window_never_leaves_older_l0_files_behind() {
+        // 5、6 大小相近，但比它们老的 4 还在 L0：输出没有合适的层，不做
+        let l = levels(&[(0, 6, 1000), (0, 5, 1000), (0, 4, 100_000), (NUM_LEVELS - 1, 1, 10_000_000)]);
+        assert!(pick_universal_compaction(&l, &UniversalCompactionOptions::default(), 3).is_none());
+    }
+
+    fn ranged(file_number: u64, file_size: u64, smallest: &[u8], largest: &[u8]) -> Arc<FileMetaData> {
+        Arc::new(FileMetaData {
+            file_size,
+            ..(*file(file_number, smallest, largest)).clone()
+        })
+    }
+
+    #[test]
+    fn compaction_pri_decides_which_file_goes_first() {
+        let files = vec![ranged(1, 100, b"a", b"c"), ranged(2, 300, b"d", b"f"), ranged(3, 200, b"g", b"i")];
+        let next = vec![ranged(10, 10, b"a", b"b"), ranged(11, 5000, b"e", b"e")];
+        let pick = |pri, marked: &HashSet<u64>| pick_compaction_file(&files, &next, pri, marked).unwrap().file_number;
+        let none = HashSet::new();
+
+        assert_eq!(pick(CompactionPri::ByCompensatedSize, &none), 2);
+        assert_eq!(pick(CompactionPri::OldestSmallestSeqFirst, &none), 1);
+        // 3 和下一层不重叠，搬下去不用重写别的文件
+        assert_eq!(pick(CompactionPri::MinOverlappingRatio, &none), 3);
+
+        // 标记过的文件优先，哪种 pri 都一样
+        let marked = HashSet::from([2]);
+        for pri in [CompactionPri::ByCompensatedSize, CompactionPri::OldestSmallestSeqFirst, CompactionPri::MinOverlappingRatio] {
+            assert_eq!(pick(pri, &marked), 2);
+        }
+        assert!(pick_compaction_file(&[], &next, CompactionPri::MinOverlappingRatio, &none).is_none());
+    }
+}
@@ -1,19 +1,51 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
 pub type FileNumber = u64;
 
-#[derive(Clone)]
+/// 每 16KiB 允许一次没找到 key 的 seek（和 LevelDB 一样：一次 seek 的代价约等于 compact 16KiB）
+const BYTES_PER_SEEK: u64 = 16 * 1024;
+const MIN_ALLOWED_SEEKS: i64 = 100;
+
 pub struct FileMetaData {
     pub file_number: FileNumber,
     pub file_size: u64,
 
     pub smallest_key: Vec<u8>,
     pub largest_key: Vec<u8>,
-    pub allowed_seeks: u32,
+    /// 还能被白查（查了没找到、接着查下一个文件）多少次；用完就标记去 compaction。
+    /// 同一个文件在各个 Version 里是同一个 `Arc`，计数跟着文件走
+    pub allowed_seeks: AtomicI64,
+    /// 整个文件的 crc32c；这个字段之前写进 MANIFEST 的文件没有
+    pub file_checksum: Option<u32>,
+}
+
+impl Clone for FileMetaData {
+    fn clone(&self) -> Self {
+        Self {
+            file_number: self.file_number,
+            file_size: self.file_size,
+            smallest_key: self.smallest_key.clone(),
+            largest_key: self.largest_key.clone(),
+            allowed_seeks: AtomicI64::new(self.allowed_seeks.load(Ordering::Relaxed)),
+            file_checksum: self.file_checksum,
+        }
+    }
 }
 
 impl FileMetaData {
+    /// 新文件的 seek 配额，按文件大小给
+    pub fn initial_allowed_seeks(file_size: u64) -> AtomicI64 {
+        AtomicI64::new(((file_size / BYTES_PER_SEEK) as i64).max(MIN_ALLOWED_SEEKS))
+    }
+
     #[inline]
     pub fn contains_key(&self, key: &[u8]) -> bool {
         key >= self.smallest_key.as_slice()
             && key <= self.largest_key.as_slice()
     }
+
+    /// 记一次白查；配额刚好用完时返回 true（只返回一次）
+    pub fn charge_seek(&self) -> bool {
+        self.allowed_seeks.fetch_sub(1, Ordering::Relaxed) == 1
+    }
 }
@@ -0,0 +1,183 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::DBError;
+use crate::engine::mem::ColumnFamilyId;
+
+/// JOBLOG 超过这个大小时，open 会把它改名为 JOBLOG.old 再新建
+const MAX_JOB_LOG_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Flush,
+    Compaction,
+}
+
+/// 一次 flush / compaction 的审计记录，JOBLOG 里每行一条 JSON
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: u64,
+    pub kind: JobKind,
+    pub cf_id: ColumnFamilyId,
+    /// 开始时间（unix 毫秒）
+    pub started_at_ms: u64,
+    pub duration_ms: u64,
+    /// 后台线程花的 CPU 时间；旧 JOBLOG 里没有这个字段
+    #[serde(default)]
+    pub cpu_micros: u64,
+    /// flush 的输入是 memtable，没有文件
+    pub input_files: Vec<u64>,
+    pub input_bytes: u64,
+    pub output_files: Vec<u64>,
+    pub output_bytes: u64,
+    pub output_level: usize,
+}
+
+impl JobRecord {
+    pub fn new(kind: JobKind, cf_id: ColumnFamilyId, started_at: SystemTime, duration: Duration) -> Self {
+        Self {
+            job_id: 0,
+            kind,
+            cf_id,
+            started_at_ms: started_at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+            duration_ms: duration.as_millis() as u64,
+            cpu_micros: 0,
+            input_files: Vec::new(),
+            input_bytes: 0,
+            output_files: Vec::new(),
+            output_bytes: 0,
+            output_level: 0,
+        }
+    }
+}
+
+/// 后台任务日志，放在 MANIFEST 旁边，重启后仍然保留
+///
+/// 只追加；写失败只打 warn，不影响 flush / compaction 本身
+pub struct JobLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    next_job_id: AtomicU64,
+}
+
+impl JobLog {
+    pub fn open(path: &Path) -> Result<Self, DBError> {
+        let existing = Self::read_all(path)?;
+        if fs::metadata(path).map(|m| m.len() > MAX_JOB_LOG_SIZE).unwrap_or(false) {
+            fs::rename(path, path.with_extension("old"))?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let next = existing.iter().map(|r| r.job_id).max().map_or(1, |id| id + 1);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            next_job_id: AtomicU64::new(next),
+        })
+    }
+
+    /// 分配 job id 并追加一行
+    pub fn append(&self, mut record: JobRecord) -> u64 {
+        record.job_id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("failed to encode job record: {}", e);
+                return record.job_id;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(&line).and_then(|_| file.flush()) {
+            log::warn!("failed to append to {:?}: {}", self.path, e);
+        }
+        record.job_id
+    }
+
+    /// 读出全部记录；崩溃时写了一半的最后一行忽略
+    pub fn read_all(path: &Path) -> Result<Vec<JobRecord>, DBError> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<JobRecord>(&line) {
+                Ok(r) => records.push(r),
+                Err(_) => break,
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vectorkv-joblog-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("JOBLOG")
+    }
+
+    fn record(kind: JobKind, output_bytes: u64) -> JobRecord {
+        let mut r = JobRecord::new(kind, 1, SystemTime::now(), Duration::from_millis(5));
+        r.output_files = vec![9];
+        r.output_bytes = output_bytes;
+        r
+    }
+
+    #[test]
+    fn job_ids_continue_after_a_reopen() {
+        let path = test_path("reopen");
+        let log = JobLog::open(&path).unwrap();
+        assert_eq!(log.append(record(JobKind::Flush, 100)), 1);
+        assert_eq!(log.append(record(JobKind::Compaction, 200)), 2);
+        drop(log);
+
+        let log = JobLog::open(&path).unwrap();
+        assert_eq!(log.append(record(JobKind::Flush, 300)), 3);
+
+        let records = JobLog::read_all(&path).unwrap();
+        let ids: Vec<_> = records.iter().map(|r| (r.job_id, r.kind, r.output_bytes)).collect();
+        assert_eq!(ids, vec![(1, JobKind::Flush, 100), (2, JobKind::Compaction, 200), (3, JobKind::Flush, 300)]);
+        assert_eq!(records[0].output_files, vec![9]);
+    }
+
+    #[test]
+    fn torn_last_line_and_old_records_are_tolerated() {
+        let path = test_path("torn");
+        assert!(JobLog::read_all(&path).unwrap().is_empty());
+
+        // 旧版本写的记录没有 cpu_micros；最后一行写了一半
+        let old = r#"{"job_id":4,"kind":"flush","cf_id":0,"started_at_ms":1,"duration_ms":2,"input_files":[],"input_bytes":0,"output_files":[7],"output_bytes":10,"output_level":0}"#;
+        fs::write(&path, format!("{}\n{{\"job_id\":5,\"ki", old)).unwrap();
+
+        let records = JobLog::read_all(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].job_id, records[0].cpu_micros), (4, 0));
+        assert_eq!(JobLog::open(&path).unwrap().append(record(JobKind::Flush, 1)), 5);
+    }
+
+    #[test]
+    fn oversized_log_is_rotated_on_open() {
+        let path = test_path("rotate");
+        fs::write(&path, vec![b'x'; MAX_JOB_LOG_SIZE as usize + 1]).unwrap();
+        let log = JobLog::open(&path).unwrap();
+        log.append(record(JobKind::Flush, 1));
+        assert!(path.with_extension("old").exists());
+        assert_eq!(JobLog::read_all(&path).unwrap().len(), 1);
+    }
+}
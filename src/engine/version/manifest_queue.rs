@@ -0,0 +1,76 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use crate::DBError;
+use crate::engine::version::{VersionEdit, VersionSet};
+
+/// MANIFEST 写入队列
+///
+/// flush / compaction 同时结束时都往这里交 edit。排在最前面的线程当 leader，
+/// 把当时排着的 edit 一起拿走，一次写进 MANIFEST（group commit），再按提交顺序
+/// 装到内存里的 Version 上；其余线程等 leader 把结果交回来。
+/// 持久化和装载在同一次 VersionSet 加锁里完成，两者的顺序总是一致的。
+pub struct ManifestWriteQueue {
+    state: Mutex<QueueState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_ticket: u64,
+    pending: VecDeque<(u64, VersionEdit)>,
+    /// 被别的 leader 写完的 ticket -> 失败时的错误信息
+    finished: HashMap<u64, Option<String>>,
+    leader_active: bool,
+}
+
+impl ManifestWriteQueue {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            done: Condvar::new(),
+        }
+    }
+
+    /// 提交一个 edit，写进 MANIFEST 并装到 `versions` 后返回。
+    ///
+    /// 调用方不能持有 `versions` 的锁。
+    pub fn submit(&self, versions: &Mutex<VersionSet>, edit: VersionEdit) -> Result<(), DBError> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push_back((ticket, edit));
+
+        loop {
+            if let Some(result) = state.finished.remove(&ticket) {
+                return match result {
+                    None => Ok(()),
+                    Some(msg) => Err(DBError::Other(format!("manifest group commit failed: {}", msg))),
+                };
+            }
+            if !state.leader_active {
+                break;
+            }
+            state = self.done.wait(state).unwrap();
+        }
+
+        // 当 leader：自己的 edit 还在队里，连同后面排着的一起写
+        state.leader_active = true;
+        let group: Vec<(u64, VersionEdit)> = state.pending.drain(..).collect();
+        drop(state);
+
+        let tickets: Vec<u64> = group.iter().map(|(t, _)| *t).collect();
+        let edits = group.into_iter().map(|(_, e)| e).collect();
+        let result = versions.lock().unwrap().log_and_apply_group(edits);
+
+        let mut state = self.state.lock().unwrap();
+        let msg = result.as_ref().err().map(|e| format!("{:?}", e));
+        for t in tickets.into_iter().filter(|t| *t != ticket) {
+            state.finished.insert(t, msg.clone());
+        }
+        state.leader_active = false;
+        drop(state);
+        self.done.notify_all();
+
+        result
+    }
+}
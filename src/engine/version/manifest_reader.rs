@@ -13,9 +13,9 @@ pub struct ManifestReader {
 
 impl ManifestReader {
 
-    /// 打开MANIFEST（用于重放）
-    pub fn open<P: AsRef<Path>>(db_path: P) -> Result<Self, DBError> {
-        let manifest_path = db_path.as_ref().join("MANIFEST");
+    /// 打开 MANIFEST 文件（用于重放）；`manifest_path` 是 CURRENT 指向的那个文件
+    pub fn open<P: AsRef<Path>>(manifest_path: P) -> Result<Self, DBError> {
+        let manifest_path = manifest_path.as_ref().to_path_buf();
 
         let f = OpenOptions::new()
             .read(true)
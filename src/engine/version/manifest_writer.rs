@@ -16,31 +16,21 @@ pub struct ManifestWriter {
 impl ManifestWriter {
     /// Create a brand new manifest file on first DB startup.
     pub fn create_new(path: &PathBuf) -> Result<Self, DBError> {
-        use std::fs::{File, OpenOptions};
-        use std::io::Write;
-        use std::path::Path;
-
         // Ensure the directory exists
         if let Some(dir) = path.as_path().parent() {
             std::fs::create_dir_all(dir).map_err(|e| DBError::Io(e))?;
         }
 
         // Create or truncate the manifest file
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
             .open(path)
             .map_err(|e| DBError::Io(e))?;
 
-        let mut buf = BufWriter::new(file);
-
-        // Write initial header or placeholder if needed
-        // (RocksDB manifest starts empty, but we can include a format version header)
-        writeln!(file, "manifest_format_version 1")
-            .map_err(|e| DBError::Io(e))?;
-
-        buf.flush().map_err(|e| DBError::Io(e))?;
+        // 不写文本头：MANIFEST 从第一个字节起就是 WAL 格式的 record，ManifestReader 直接 replay
+        let buf = BufWriter::new(file);
 
         let wal = WalWriter::new(buf);
         // Return the ManifestWriter instance
@@ -59,16 +49,17 @@ impl ManifestWriter {
         // Open the file without truncating existing content
         let file = OpenOptions::new()
             .read(true)
-            .write(true)
+            .append(true)
             .create(false) // must already exist
             .open(&path_buf)
             .map_err(|e| DBError::Io(e))?;
+        let written = file.metadata().map_err(DBError::Io)?.len();
 
         // Wrap the file in a buffered writer
         let buf_writer = BufWriter::new(file);
 
         // Wrap the buffered writer with WalWriter (assuming you have WalWriter::new)
-        let wal_writer = WalWriter::new(buf_writer);
+        let wal_writer = WalWriter::new_at(buf_writer, written);
 
         Ok(Self {
             path: path_buf,
@@ -78,10 +69,17 @@ impl ManifestWriter {
 
     /// 追加一条 VersionEdit 记录到 MANIFEST
     pub fn add_record(&mut self, edit: &VersionEdit) -> Result<(), DBError> {
-        let payload = VersionEdit::encode_version_edit(edit);
-        self.writer
-            .append(&payload)
-            .map_err(DBError::Io)?;
+        self.add_records(std::slice::from_ref(edit))
+    }
+
+    /// 追加一组 VersionEdit，最后只 flush 一次（group commit）
+    pub fn add_records(&mut self, edits: &[VersionEdit]) -> Result<(), DBError> {
+        for edit in edits {
+            let payload = VersionEdit::encode_version_edit(edit);
+            self.writer
+                .append(&payload)
+                .map_err(DBError::Io)?;
+        }
         // 是否 fsync 取决于你对元数据持久化的要求
         // self.writer.into_inner().flush()? 之类的可以在 WalWriter 里提供 flush/sync
         self.writer.flush().map_err(DBError::Io)?;
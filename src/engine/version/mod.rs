@@ -6,12 +6,21 @@ pub mod manifest;
 pub mod current;
 pub mod manifest_writer;
 pub mod manifest_reader;
+pub mod manifest_queue;
+pub mod version_ref;
 mod compaction;
+mod compaction_picker;
+pub mod job_log;
 
 pub use version_set::VersionSet;
-pub use version::Version;
+pub use version::{GetStats, Version};
+pub use compaction_picker::{pick_compaction_file, pick_l0_compaction_files, pick_universal_compaction, UniversalPick};
+pub use job_log::{JobKind, JobLog, JobRecord};
+pub use compaction::{full_merge, FifoCompaction, MergeOperator};
 pub use version_edit::VersionEdit;
 pub use file_meta::{FileMetaData, FileNumber};
 pub use manifest_writer::ManifestWriter;
 pub use manifest_reader::ManifestReader;
+pub use manifest_queue::ManifestWriteQueue;
+pub use version_ref::{VersionPins, VersionRef};
 pub use current::{read_current, write_current};
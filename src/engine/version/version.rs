@@ -1,13 +1,35 @@
+use std::collections::HashSet;
 use std::sync::Arc;
-use crate::engine::mem::{mvcc_comparator, raw_mvcc_compare};
+use crate::DBError;
+use crate::engine::mem::{mvcc_comparator, raw_mvcc_compare, RangeTombstone, SequenceNumber, ValueType};
+use crate::engine::mem::range_tombstone::max_covering_seq;
 use crate::engine::sst::iterator::{InternalIterator, MergingIterator, TwoLevelIterator, DBIterator, SnapshotIterator};
-use crate::engine::sst::{BlockHandle, TableCache};
-use crate::engine::version::{FileMetaData, VersionEdit};
-use crate::util::NUM_LEVELS;
+use crate::engine::sst::{BlockHandle, LookupResult, SstReader, TableCache};
+use crate::engine::version::{FileMetaData, MergeOperator, VersionEdit};
+use crate::util::{CfStatistics, ReadOptions, SliceTransform, NUM_LEVELS};
+
+/// 一次点查里第一个白查了的文件，见 `Version::get_with_seek_stats`
+#[derive(Default)]
+pub struct GetStats {
+    pub seek_file: Option<(usize, Arc<FileMetaData>)>,
+    last_read: Option<(usize, Arc<FileMetaData>)>,
+}
+
+impl GetStats {
+    /// 要查 `f` 了：之前查过的文件没找到 key，记下第一个
+    fn record_read(&mut self, level: usize, f: &Arc<FileMetaData>) {
+        if self.seek_file.is_none() {
+            self.seek_file = self.last_read.take();
+        }
+        self.last_read = Some((level, Arc::clone(f)));
+    }
+}
 
 #[derive(Clone)]
 pub struct Version {
     levels: [Vec<Arc<FileMetaData>>; NUM_LEVELS],
+    /// 已 flush 的范围墓碑；SST 里不存，查询时对照着过滤
+    range_tombstones: Vec<RangeTombstone>,
     table_cache: Arc<TableCache>,
 }
 
@@ -15,6 +37,7 @@ impl Version {
     pub fn new_empty(table_cache: Arc<TableCache>) -> Self {
         Self {
             levels: std::array::from_fn(|_| Vec::new()),
+            range_tombstones: Vec::new(),
             table_cache,
         }
     }
@@ -47,18 +70,184 @@ impl Version {
                 self.levels[*level].sort_by(|a, b| a.smallest_key.cmp(&b.smallest_key));
             }
         }
+
+        // 3) 范围墓碑
+        self.range_tombstones.retain(|t| !edit.deleted_range_tombstones.contains(t));
+        self.range_tombstones.extend(edit.range_tombstones.iter().cloned());
+    }
+
+    /// 把若干 Version 的文件并成一个只读 Version（全部放 L0，按 file_number 去重）
+    ///
+    /// time-travel 读用：compaction 丢掉的旧版本 key 还留在被替换前的文件里
+    pub fn union_of(versions: &[Arc<Version>], table_cache: Arc<TableCache>) -> Self {
+        let mut union = Version::new_empty(table_cache);
+        let mut seen = HashSet::new();
+        for v in versions {
+            for f in v.levels.iter().flatten() {
+                if seen.insert(f.file_number) {
+                    union.levels[0].push(Arc::clone(f));
+                }
+            }
+            for t in &v.range_tombstones {
+                if !union.range_tombstones.contains(t) {
+                    union.range_tombstones.push(t.clone());
+                }
+            }
+        }
+        union.levels[0].sort_by_key(|f| f.file_number);
+        union
+    }
+
+    /// seq <= `seq` 时 user_key 的值：所有文件里取 seq 最大的一条，墓碑返回 None
+    pub fn get_as_of(&self, user_key: &[u8], seq: SequenceNumber, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        let covered = max_covering_seq(&self.range_tombstones, user_key, seq);
+        Ok(match self.latest_entry(user_key, seq, opts)? {
+            Some((s, ValueType::Put, v)) if covered.map_or(true, |t| s >= t) => Some(v),
+            _ => None,
+        })
+    }
+
+    /// seq <= `seq` 的版本里 seq 最大的一条（含墓碑），不看范围墓碑
+    fn latest_entry(&self, user_key: &[u8], seq: SequenceNumber, opts: &ReadOptions) -> Result<Option<(SequenceNumber, ValueType, Vec<u8>)>, DBError> {
+        let mut best: Option<(SequenceNumber, ValueType, Vec<u8>)> = None;
+        for f in self.levels.iter().flatten() {
+            let reader = self.table_cache
+                .find_table(f)
+                .ok_or_else(|| DBError::Other(format!("cannot open sst {}", f.file_number)))?;
+            if let Some(found) = reader.get_as_of(user_key, seq, opts)? {
+                if best.as_ref().map_or(true, |(s, _, _)| found.0 > *s) {
+                    best = Some(found);
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// merge 读：从新到旧把 `key` 的 merge operand 收进 `operands`，
+    /// 直到遇到 Put（返回它的值）或者删除 / 没有更老的版本（返回 None）
+    pub fn collect_merge_operands(&self, key: &[u8], operands: &mut Vec<Vec<u8>>, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        let covered = max_covering_seq(&self.range_tombstones, key, SequenceNumber::MAX);
+        let mut seq = SequenceNumber::MAX >> 8;
+        while let Some((s, value_type, v)) = self.latest_entry(key, seq, opts)? {
+            if covered.is_some_and(|t| s < t) {
+                break;
+            }
+            match value_type {
+                ValueType::Put => return Ok(Some(v)),
+                ValueType::Delete => break,
+                ValueType::Merge => operands.push(v),
+            }
+            if s == 0 {
+                break;
+            }
+            seq = s - 1;
+        }
+        Ok(None)
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// 被范围墓碑盖住的 key 走慢路径：所有文件里取最新版本，再和墓碑的 seq 比
+    fn get_covered(&self, key: &[u8], tombstone_seq: SequenceNumber, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        match self.latest_entry(key, SequenceNumber::MAX >> 8, opts)? {
+            Some((s, ValueType::Put, v)) if s >= tombstone_seq => Ok(Some(v)),
+            _ => Ok(None),
+        }
+    }
+
+    /// level 上的文件数
+    pub fn num_files(&self, level: usize) -> usize {
+        self.levels.get(level).map_or(0, |files| files.len())
+    }
+
+    /// level 上所有文件的字节数
+    pub fn level_bytes(&self, level: usize) -> u64 {
+        self.levels.get(level).map_or(0, |files| files.iter().map(|f| f.file_size).sum())
+    }
+
+    /// 估算的 compaction 欠账：各 level 超出目标大小的字节数之和
+    ///
+    /// - L0：文件数到了 `l0_trigger` 就整层算欠账（要全部合进 L1）
+    /// - L1：目标 `base`；往下每层是上一层的 `multiplier` 倍
+    /// - 最底层没有下一层可合，不计
+    pub fn compaction_debt(&self, l0_trigger: usize, base: u64, multiplier: u64) -> u64 {
+        let mut debt = 0;
+        if l0_trigger > 0 && self.num_files(0) >= l0_trigger {
+            debt += self.level_bytes(0);
+        }
+        let mut target = base;
+        for level in 1..NUM_LEVELS - 1 {
+            debt += self.level_bytes(level).saturating_sub(target);
+            target = target.saturating_mul(multiplier);
+        }
+        debt
+    }
+
+    /// 当前 Version 引用的全部文件
+    pub fn all_file_numbers(&self) -> Vec<u64> {
+        self.levels.iter().flatten().map(|f| f.file_number).collect()
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+    /// 启动预热要打开的文件：
+    /// - 最热的 `hottest` 个：L0 从新到旧，然后 L1、L2...
+    /// - `bottom_level`：最底下非空 level 的全部文件
+    pub fn files_for_preload(&self, hottest: usize, bottom_level: bool) -> Vec<u64> {
+        let mut out: Vec<u64> = Vec::new();
+
+        let hot = self.levels[0].iter().rev()
+            .chain(self.levels[1..].iter().flatten())
+            .take(hottest);
+        out.extend(hot.map(|f| f.file_number));
+
+        if bottom_level {
+            if let Some(files) = self.levels.iter().rev().find(|l| !l.is_empty()) {
+                for f in files {
+                    if !out.contains(&f.file_number) {
+                        out.push(f.file_number);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// 点查：`Ok(None)` 是没有这个 key 或者已经删了；读 SST 出错返回 Err，不当成没找到
+    pub fn get(&self, key: &[u8], stats: &CfStatistics) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_with_options(key, stats, &ReadOptions::default())
+    }
+
+    /// 同 get，SST 的 block 按 `opts` 决定是否校验
+    pub fn get_with_options(&self, key: &[u8], stats: &CfStatistics, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_with_seek_stats(key, stats, opts, &mut GetStats::default())
+    }
+
+    /// 同 `get_with_options`，另外在 `seek` 里记下第一个白查了的文件（查了但 key 在后面的文件里），
+    /// 调用方用 `update_stats` 扣它的 seek 配额
+    pub fn get_with_seek_stats(
+        &self,
+        key: &[u8],
+        stats: &CfStatistics,
+        opts: &ReadOptions,
+        seek: &mut GetStats,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        if let Some(t) = max_covering_seq(&self.range_tombstones, key, SequenceNumber::MAX) {
+            return self.get_covered(key, t, opts);
+        }
+
         // ---------- 1️⃣ 查 L0 ----------
         // L0 文件可能重叠，必须按“最新 → 最旧”查
-        // 通常 file_number 越大越新
+        // 通常 file_number 越大越新；碰到删除就停，更老文件里的版本已经被它盖住了
         let l0 = &self.levels[0];
 
         for f in l0.iter().rev() {
             if f.contains_key(key) {
-                if let Some(v) = self.get_from_sst(f, key) {
-                    return Some(v);
+                seek.record_read(0, f);
+                match self.get_from_sst(0, f, key, stats, opts)? {
+                    LookupResult::Found(v) => return Ok(Some(v)),
+                    LookupResult::Deleted => return Ok(None),
+                    LookupResult::NotFound => {}
                 }
             }
         }
@@ -80,17 +269,84 @@ impl Version {
                 } else if key > f.largest_key.as_slice() {
                     left = mid + 1;
                 } else {
-                    // 命中区间
-                    if let Some(v) = self.get_from_sst(f, key) {
-                        return Some(v);
-                    } else {
-                        return None;
+                    // 命中区间；文件里没有这个 key 就去下一层
+                    seek.record_read(level, f);
+                    match self.get_from_sst(level, f, key, stats, opts)? {
+                        LookupResult::Found(v) => return Ok(Some(v)),
+                        LookupResult::Deleted => return Ok(None),
+                        LookupResult::NotFound => break,
                     }
                 }
             }
         }
 
-        None
+        Ok(None)
+    }
+
+    /// 扣掉 `seek` 记下的文件一次 seek 配额；配额刚用完时返回它（level, file_number），
+    /// 该去 compaction 了
+    pub fn update_stats(&self, seek: &GetStats) -> Option<(usize, u64)> {
+        let (level, f) = seek.seek_file.as_ref()?;
+        f.charge_seek().then_some((*level, f.file_number))
+    }
+
+    /// 批量 get：`keys` 须已排序；每个文件只打开一次，
+    /// 一个文件里的 key 按顺序查，同一个 data block 只读一次
+    ///
+    /// 和 get 一样，key 在某个文件里的最新版本是删除就不再往更老的文件查；读错了整批返回 Err。
+    /// 给了 `snapshot` 就读 seq <= snapshot 的版本，逐个 key 走 get_as_of
+    pub fn multi_get(
+        &self,
+        keys: &[&[u8]],
+        snapshot: Option<SequenceNumber>,
+        stats: &CfStatistics,
+        opts: &ReadOptions,
+    ) -> Result<Vec<Option<Vec<u8>>>, DBError> {
+        if let Some(seq) = snapshot {
+            return keys.iter().map(|key| self.get_as_of(key, seq, opts)).collect();
+        }
+        let mut out: Vec<Option<Vec<u8>>> = vec![None; keys.len()];
+        let mut found = vec![false; keys.len()];
+
+        // 被范围墓碑盖住的 key 单独查
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(t) = max_covering_seq(&self.range_tombstones, key, SequenceNumber::MAX) {
+                out[i] = self.get_covered(key, t, opts)?;
+                found[i] = true;
+            }
+        }
+
+        // L0 从新到旧；L1+ 文件不重叠，按 key 区间分给各文件
+        let files = self.levels[0]
+            .iter()
+            .rev()
+            .map(|f| (0, f))
+            .chain((1..NUM_LEVELS).flat_map(|level| self.levels[level].iter().map(move |f| (level, f))));
+        for (level, f) in files {
+            let idx: Vec<usize> = (0..keys.len())
+                .filter(|&i| !found[i] && f.contains_key(keys[i]))
+                .collect();
+            if idx.is_empty() {
+                continue;
+            }
+            let reader = self.open_table(level, f)?;
+            let file_keys: Vec<&[u8]> = idx.iter().map(|&i| keys[i]).collect();
+            let results = reader.multi_lookup_with_stats(&file_keys, Some(stats), opts)?;
+            for (i, r) in idx.into_iter().zip(results) {
+                match r {
+                    LookupResult::Found(v) => {
+                        found[i] = true;
+                        out[i] = Some(v);
+                    }
+                    LookupResult::Deleted => found[i] = true,
+                    LookupResult::NotFound => {}
+                }
+            }
+            if found.iter().all(|&f| f) {
+                break;
+            }
+        }
+        Ok(out)
     }
 
     /// 为当前 Version 中所有 SST 创建 iterator 列表（内部 iterator）
@@ -104,6 +360,8 @@ impl Version {
     pub fn new_sst_iterators<'a>(
         &'a self,
         table_cache: &'a TableCache,
+        opts: &ReadOptions,
+        prefix_extractor: Option<&Arc<dyn SliceTransform>>,
     ) -> Vec<Box<dyn InternalIterator + 'a>> {
         // ⚠️ 这里签名可以按照你自己的 iterator 体系调整，
         // 我先给一个“思路版”代码：遍历所有文件，拿到 SstReader，再调用 reader.iter()
@@ -122,9 +380,12 @@ impl Version {
                     Some(reader) => reader,
                     None => continue,
                 };
-                // 假设 SstReader::iter() 返回实现了 InternalIterator 的 TwoLevelIterator
-                let it = reader.iter();
-                iters.push(Box::new(it) as Box<dyn InternalIterator + 'a>);
+                // SstReader::iter() 已经返回 Box<dyn InternalIterator>
+                // prefix_same_as_start：前缀 bloom 排除的文件 seek 时直接跳过
+                iters.push(match prefix_extractor {
+                    Some(extractor) => reader.iter_with_prefix(opts, Arc::clone(extractor)),
+                    None => reader.iter_with_options(opts),
+                });
             }
         }
 
@@ -136,20 +397,52 @@ impl Version {
         &self,
         snapshot_seq: u64,
     ) -> Box<dyn DBIterator> {
-        let internal_iters = self.new_sst_iterators(&self.table_cache);
+        self.new_merged_iterator(Vec::new(), Vec::new(), None, snapshot_seq, &ReadOptions::default(), None)
+    }
+
+    /// `new_iterator`，再把 `mem_iters`（memtable 的 iterator）和 memtable 里的范围墓碑一起归并进来；
+    /// 有 `merge_operator` 时 merge operand 合并后再给出
+    pub fn new_merged_iterator<'a>(
+        &'a self,
+        mem_iters: Vec<Box<dyn InternalIterator + 'a>>,
+        mem_tombstones: Vec<RangeTombstone>,
+        merge_operator: Option<Arc<dyn MergeOperator + Send + Sync>>,
+        snapshot_seq: u64,
+        opts: &ReadOptions,
+        prefix_extractor: Option<&Arc<dyn SliceTransform>>,
+    ) -> Box<dyn DBIterator> {
+        let mut internal_iters = mem_iters;
+        internal_iters.extend(self.new_sst_iterators(&self.table_cache, opts, prefix_extractor));
+        let mut tombstones = mem_tombstones;
+        tombstones.extend(self.range_tombstones.iter().cloned());
         let merging =MergingIterator::new(internal_iters, raw_mvcc_compare);
-        let snap_iter =Box::new(SnapshotIterator::new(merging, snapshot_seq));
+        let snap_iter =Box::new(SnapshotIterator::new(merging, snapshot_seq).with_range_tombstones(tombstones)
+            .with_merge_operator(merge_operator));
         Box::new(snap_iter)
     }
 
 
     fn get_from_sst(
         &self,
+        level: usize,
         file: &Arc<FileMetaData>,
         key: &[u8],
-    ) -> Option<Vec<u8>> {
-        let reader = self.table_cache.find_table(file)?;
-        reader.get(key).ok()?
+        stats: &CfStatistics,
+        opts: &ReadOptions,
+    ) -> Result<LookupResult, DBError> {
+        self.open_table(level, file)?.lookup_with_options(key, Some(stats), opts)
+    }
+
+    /// 打不开的文件报错，不当成里面没有这个 key
+    fn open_table(&self, level: usize, file: &Arc<FileMetaData>) -> Result<Arc<SstReader>, DBError> {
+        self.table_cache
+            .find_table_at_level(file, self.is_bottommost_level(level))
+            .ok_or_else(|| DBError::Other(format!("cannot open sst {}", file.file_number)))
+    }
+
+    /// level 之下没有任何文件：这一层就是当前数据的最底层
+    pub fn is_bottommost_level(&self, level: usize) -> bool {
+        self.levels[level + 1..].iter().all(|files| files.is_empty())
     }
 
     pub fn levels(&self) -> [Vec<Arc<FileMetaData>>; NUM_LEVELS] {
@@ -1,5 +1,5 @@
 use crate::DBError;
-use crate::engine::mem::{ColumnFamilyId, InternalKey, SequenceNumber};
+use crate::engine::mem::{ColumnFamilyId, InternalKey, RangeTombstone, SequenceNumber};
 use crate::engine::mem::memtable_set::CfType;
 use crate::engine::version::{FileMetaData, FileNumber};
 use crate::engine::wal::{read_bytes, read_string, read_u32, read_u64};
@@ -12,17 +12,33 @@ const TAG_ADD_FILE: u8 = 4;
 const TAG_DELETE_FILE: u8 = 5;
 const TAG_NEXT_FILE_NUMBER: u8 = 6;
 const TAG_LAST_SEQUENCE: u8 = 7;
+const TAG_RESERVED_SEQUENCE: u8 = 8;
+const TAG_RANGE_TOMBSTONE: u8 = 9;
+const TAG_WAL_APPLIED_SEQUENCE: u8 = 10;
+const TAG_FILE_CHECKSUM: u8 = 11;
+const TAG_CF_OPTIONS: u8 = 12;
+const TAG_DELETE_RANGE_TOMBSTONE: u8 = 13;
 
 pub struct VersionEdit {
     pub cf_id: ColumnFamilyId,
     pub cf_type: CfType,
     pub cf_name: Option<String>,   // only CF_ADD writes this
+    /// CF_ADD 时 `create_column_family` 传进来的选项（`ColumnFamilyOptions::encode_record`）
+    pub cf_options: Option<Vec<u8>>,
     pub is_cf_add: bool,
     pub is_cf_drop: bool,
     pub add_files: Vec<(usize, FileMetaData)>,
     pub delete_files: Vec<(usize, FileNumber)>,
+    /// flush 下来的 memtable 里的范围墓碑，挂在 CF 的 Version 上
+    pub range_tombstones: Vec<RangeTombstone>,
+    /// 最底层 compaction 已经把它盖住的数据都丢掉了，从 Version 里摘掉的范围墓碑
+    pub deleted_range_tombstones: Vec<RangeTombstone>,
     pub next_file_number: Option<FileNumber>,
     pub last_sequence: Option<SequenceNumber>,
+    /// 已经预留（可能已经分配出去）的最大 sequence，重启后从这之后继续分配
+    pub reserved_sequence: Option<SequenceNumber>,
+    /// WAL 里到这个 sequence 为止的记录都已经在 SST 里了，打开时不用再 replay
+    pub wal_applied_sequence: Option<SequenceNumber>,
 }
 
 impl Default for VersionEdit {
@@ -31,13 +47,18 @@ impl Default for VersionEdit {
             cf_id: 0,
             cf_type: CfType::User,
             cf_name: None,
+            cf_options: None,
             is_cf_add: false,
             is_cf_drop: false,
 
             add_files: Vec::new(),
             delete_files: Vec::new(),
+            range_tombstones: Vec::new(),
+            deleted_range_tombstones: Vec::new(),
             next_file_number: None,
             last_sequence: None,
+            reserved_sequence: None,
+            wal_applied_sequence: None,
         }
     }
 }
@@ -48,12 +69,17 @@ impl VersionEdit {
             cf_id,
             cf_type,
             cf_name: None,
+            cf_options: None,
             is_cf_add: false,
             is_cf_drop: false,
             add_files: Vec::new(),
             delete_files: Vec::new(),
+            range_tombstones: Vec::new(),
+            deleted_range_tombstones: Vec::new(),
             next_file_number:None,
             last_sequence: None,
+            reserved_sequence: None,
+            wal_applied_sequence: None,
         }
     }
 
@@ -72,6 +98,13 @@ impl VersionEdit {
             let name_bytes = edit.cf_name.as_ref().unwrap().as_bytes();
             buf.extend_from_slice(&(name_bytes.len() as u32).to_le_bytes());
             buf.extend_from_slice(name_bytes);
+
+            // 单独一个 tag，没有自定义选项的 CF_ADD 格式不变
+            if let Some(options) = &edit.cf_options {
+                buf.push(TAG_CF_OPTIONS);
+                buf.extend_from_slice(&(options.len() as u32).to_le_bytes());
+                buf.extend_from_slice(options);
+            }
         } else if edit.is_cf_drop {
             // ---- column family drop ----
             buf.push(TAG_CF_DROP);
@@ -96,6 +129,13 @@ impl VersionEdit {
 
             buf.extend_from_slice(&(f.largest_key.len() as u32).to_le_bytes());
             buf.extend_from_slice(&f.largest_key);
+
+            // 单独一个 tag，老版本的 ADD_FILE 格式不变
+            if let Some(crc) = f.file_checksum {
+                buf.push(TAG_FILE_CHECKSUM);
+                buf.extend_from_slice(&f.file_number.to_le_bytes());
+                buf.extend_from_slice(&crc.to_le_bytes());
+            }
         }
 
         for (level, file_no) in &edit.delete_files {
@@ -104,6 +144,16 @@ impl VersionEdit {
             buf.extend_from_slice(&file_no.to_le_bytes());
         }
 
+        for t in &edit.range_tombstones {
+            buf.push(TAG_RANGE_TOMBSTONE);
+            t.encode_to(&mut buf);
+        }
+
+        for t in &edit.deleted_range_tombstones {
+            buf.push(TAG_DELETE_RANGE_TOMBSTONE);
+            t.encode_to(&mut buf);
+        }
+
         if let Some(n) = edit.next_file_number {
             buf.push(TAG_NEXT_FILE_NUMBER); // NEXT_FILE_NUMBER
             buf.extend_from_slice(&n.to_le_bytes());
@@ -114,6 +164,16 @@ impl VersionEdit {
             buf.extend_from_slice(&seq.to_le_bytes());
         }
 
+        if let Some(seq) = edit.reserved_sequence {
+            buf.push(TAG_RESERVED_SEQUENCE);
+            buf.extend_from_slice(&seq.to_le_bytes());
+        }
+
+        if let Some(seq) = edit.wal_applied_sequence {
+            buf.push(TAG_WAL_APPLIED_SEQUENCE);
+            buf.extend_from_slice(&seq.to_le_bytes());
+        }
+
         buf
     }
 
@@ -168,11 +228,22 @@ impl VersionEdit {
                             file_size,
                             smallest_key,
                             largest_key,
-                            allowed_seeks: 1 << 30,
+                            allowed_seeks: FileMetaData::initial_allowed_seeks(file_size),
+                            file_checksum: None,
                         },
                     ));
                 }
 
+                TAG_CF_OPTIONS => {
+                    edit.cf_options = Some(read_bytes(buf, &mut pos)?);
+                }
+
+                TAG_FILE_CHECKSUM => {
+                    let file_number = read_u64(buf, &mut pos)?;
+                    let crc = read_u32(buf, &mut pos)?;
+                    edit.set_file_checksum(file_number, crc);
+                }
+
                 TAG_DELETE_FILE => {
                     let level = buf[pos] as usize;
                     pos += 1;
@@ -191,6 +262,24 @@ impl VersionEdit {
                     edit.last_sequence = Some(seq);
                 }
 
+                TAG_RANGE_TOMBSTONE => {
+                    edit.range_tombstones.push(RangeTombstone::decode_from(buf, &mut pos)?);
+                }
+
+                TAG_DELETE_RANGE_TOMBSTONE => {
+                    edit.deleted_range_tombstones.push(RangeTombstone::decode_from(buf, &mut pos)?);
+                }
+
+                TAG_RESERVED_SEQUENCE => {
+                    let seq = read_u64(buf, &mut pos)?;
+                    edit.reserved_sequence = Some(seq);
+                }
+
+                TAG_WAL_APPLIED_SEQUENCE => {
+                    let seq = read_u64(buf, &mut pos)?;
+                    edit.wal_applied_sequence = Some(seq);
+                }
+
                 _ => {
                     return Err(DBError::Corruption(format!(
                         "unknown VersionEdit tag {}",
@@ -216,14 +305,35 @@ impl VersionEdit {
             file_size,
             smallest_key: smallest_key.to_vec(),
             largest_key: largest_key.to_vec(),
-            allowed_seeks: 1 << 30,
+            allowed_seeks: FileMetaData::initial_allowed_seeks(file_size),
+            file_checksum: None,
         };
 
         self.add_files.push((level, meta));
     }
 
+    /// Record the crc32c of a file added by this edit.
+    pub fn set_file_checksum(&mut self, file_number: u64, crc: u32) {
+        if let Some((_, f)) = self.add_files.iter_mut().find(|(_, f)| f.file_number == file_number) {
+            f.file_checksum = Some(crc);
+        }
+    }
+
     pub fn delete_file(&mut self, level: usize, file_number: u64) {
         self.delete_files.push((level, file_number));
     }
+
+    /// 把一条范围墓碑从 CF 的 Version 里摘掉
+    pub fn delete_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.deleted_range_tombstones.push(tombstone);
+    }
+
+    /// 有没有改 CF 的 Version（文件或者范围墓碑）；只带 sequence / WAL 进度的 edit 没有
+    pub fn touches_version(&self) -> bool {
+        !(self.add_files.is_empty()
+            && self.delete_files.is_empty()
+            && self.range_tombstones.is_empty()
+            && self.deleted_range_tombstones.is_empty())
+    }
 }
 
@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use crate::engine::version::Version;
+
+/// 被 iterator / get 读着的 Version 的引用计数
+///
+/// 每 pin 一个 Version，它的每个 SST 加一次引用。purge 时还有引用的文件先挂起，
+/// 等最后一个 `VersionRef` 放掉再交给 `deleter` 删，所以 GC 和 drop CF 删不到正在读的文件。
+/// pin 必须在持有 VersionSet 锁时做，这样它和装新 Version 之后的 purge 有先后。
+pub struct VersionPins {
+    state: Mutex<PinState>,
+    deleter: Box<dyn Fn(u64) + Send + Sync>,
+}
+
+#[derive(Default)]
+struct PinState {
+    pinned_versions: usize,
+    file_refs: HashMap<u64, usize>,
+    /// 已经过时、等引用归零再删的文件
+    deferred: HashSet<u64>,
+}
+
+impl VersionPins {
+    pub fn new(deleter: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        Self {
+            state: Mutex::new(PinState::default()),
+            deleter: Box::new(deleter),
+        }
+    }
+
+    pub fn pin(self: &Arc<Self>, version: Arc<Version>) -> VersionRef {
+        let files = version.all_file_numbers();
+        let mut state = self.state.lock().unwrap();
+        state.pinned_versions += 1;
+        for &f in &files {
+            debug_assert!(!state.deferred.contains(&f), "pinning obsolete SST {}", f);
+            *state.file_refs.entry(f).or_insert(0) += 1;
+        }
+        drop(state);
+        VersionRef { pins: Arc::clone(self), version, files }
+    }
+
+    /// 删掉已经不在任何当前 Version 里的文件；还被 pin 着的等放掉后再删
+    pub fn purge(&self, file_numbers: &[u64]) {
+        let mut now = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            for &f in file_numbers {
+                if state.file_refs.contains_key(&f) {
+                    state.deferred.insert(f);
+                } else {
+                    now.push(f);
+                }
+            }
+        }
+        for f in now {
+            (self.deleter)(f);
+        }
+    }
+
+    /// Number of Versions currently pinned by iterators and reads.
+    pub fn pinned_versions(&self) -> usize {
+        self.state.lock().unwrap().pinned_versions
+    }
+
+    /// Number of obsolete files waiting for their last reader.
+    pub fn deferred_files(&self) -> usize {
+        self.state.lock().unwrap().deferred.len()
+    }
+
+    /// 已经 pin 着的文件再加一次引用（复制 `VersionRef`），文件可能已经在等删
+    fn retain(&self, files: &[u64]) {
+        let mut state = self.state.lock().unwrap();
+        state.pinned_versions += 1;
+        for &f in files {
+            *state.file_refs.entry(f).or_insert(0) += 1;
+        }
+    }
+
+    fn unpin(&self, files: &[u64]) {
+        let mut ready = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            debug_assert!(state.pinned_versions > 0, "unpinning a Version that was never pinned");
+            state.pinned_versions -= 1;
+            for f in files {
+                match state.file_refs.get_mut(f) {
+                    Some(n) if *n > 1 => *n -= 1,
+                    Some(_) => {
+                        state.file_refs.remove(f);
+                        if state.deferred.remove(f) {
+                            ready.push(*f);
+                        }
+                    }
+                    None => debug_assert!(false, "SST {} unpinned more often than pinned", f),
+                }
+            }
+        }
+        for f in ready {
+            (self.deleter)(f);
+        }
+    }
+}
+
+/// 一个被 pin 住的 Version；drop 时放掉它的文件引用
+pub struct VersionRef {
+    pins: Arc<VersionPins>,
+    version: Arc<Version>,
+    files: Vec<u64>,
+}
+
+impl VersionRef {
+    pub fn version(&self) -> &Arc<Version> {
+        &self.version
+    }
+}
+
+impl Clone for VersionRef {
+    fn clone(&self) -> Self {
+        self.pins.retain(&self.files);
+        Self { pins: Arc::clone(&self.pins), version: Arc::clone(&self.version), files: self.files.clone() }
+    }
+}
+
+impl Deref for VersionRef {
+    type Target = Version;
+
+    fn deref(&self) -> &Version {
+        &self.version
+    }
+}
+
+impl Drop for VersionRef {
+    fn drop(&mut self) {
+        self.pins.unpin(&self.files);
+    }
+}
@@ -1,18 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 use crate::DBError;
-use crate::engine::mem::{ColumnFamilyId, InternalKey};
+use crate::engine::mem::{ColumnFamilyId, InternalKey, RangeTombstone, SequenceNumber};
 use crate::engine::mem::memtable_set::CfType;
-use crate::engine::sst::iterator::{DBIterator, EmptyIterator};
+use crate::engine::sst::iterator::{DBIterator, EmptyIterator, InternalIterator};
 use crate::engine::sst::{SstReader, TableCache};
-use crate::engine::version::{read_current, FileMetaData, ManifestReader, ManifestWriter, Version, VersionEdit};
-use crate::engine::version::compaction::{Compactor, SingleLevelCompaction};
-use crate::util::{ColumnFamilyOptions, DbConfig, Options, FIRST_MANIFEST, NUM_LEVELS, SYSTEM_COLUMN_FAMILY, USER_COLUMN_FAMILY};
+use crate::engine::version::{read_current, write_current, FileMetaData, JobLog, ManifestReader, ManifestWriteQueue, ManifestWriter, Version, VersionEdit, VersionPins};
+use crate::engine::version::compaction::{Compactor, MergeOperator, SingleLevelCompaction};
+use crate::util::{file_checksum, sync_dir, sync_file, CfStatistics, ReadOptions, ColumnFamilyOptions, DbConfig, Options, FIRST_MANIFEST, NUM_LEVELS, SYSTEM_COLUMN_FAMILY, USER_COLUMN_FAMILY};
 use crate::util::constants::{SYSTEM_COLUMN_FAMILY_ID, USER_COLUMN_FAMILY_ID};
 
+/// 每次在 MANIFEST 里预留的 file number 个数
+const FILE_NUMBER_RESERVE_BATCH: u64 = 1024;
+/// 每次在 MANIFEST 里预留的 sequence 个数
+const SEQUENCE_RESERVE_BATCH: u64 = 1 << 20;
+
 pub struct VersionSet {
     db_config: Arc<DbConfig>,
     /// Current versions of all column families
@@ -27,11 +33,93 @@ pub struct VersionSet {
     /// used for MVCC snapshots and WAL replay
     last_sequence: AtomicU64,
 
+    /// 已经写进 MANIFEST 的 file number 上界，超过之前不用再写
+    reserved_file_number: AtomicU64,
+
+    /// 已经写进 MANIFEST 的 sequence 上界，重启后从这里继续分配
+    reserved_sequence: AtomicU64,
+
+    /// WAL records up to this sequence are already in SSTs and are skipped on recovery
+    wal_applied_sequence: SequenceNumber,
+
     /// MANIFEST log writer
     manifest: Arc<Mutex<ManifestWriter>>,
 
+    /// Path of the MANIFEST in use; older ones are obsolete
+    manifest_path: PathBuf,
+
+    /// Group-commit queue in front of `log_and_apply`
+    manifest_queue: Arc<ManifestWriteQueue>,
+
     /// Table cache for SSTables
     pub table_cache: Arc<TableCache>,
+
+    /// Column families dropped in the MANIFEST; their WAL records are skipped on recovery
+    dropped_cfs: HashSet<ColumnFamilyId>,
+
+    /// Files of dropped column families still waiting to be deleted
+    obsolete_files: Vec<u64>,
+
+    /// Superseded Versions kept for time-travel reads
+    history: HashMap<ColumnFamilyId, VersionHistory>,
+
+    /// Files hinted by `suggest_compact_range`; the picker takes them first
+    marked_for_compaction: HashMap<ColumnFamilyId, HashSet<u64>>,
+
+    /// Per-CF merge operators, used by reads and compaction
+    merge_operators: HashMap<ColumnFamilyId, Arc<dyn MergeOperator + Send + Sync>>,
+
+    /// Flush / compaction job records
+    job_log: Arc<JobLog>,
+
+    /// 已经分配、还没写进 MANIFEST 的 SST（flush / compaction 正在写），GC 不能删
+    pending_outputs: Mutex<HashSet<u64>>,
+
+    /// 被读着的 Version；GC 找到的 SST 交给它，等最后一个读者放掉再删
+    version_pins: Option<Arc<VersionPins>>,
+
+    /// 正在写的 WAL，WAL 目录里的其他 log 都已经回放过了
+    live_wal: Option<PathBuf>,
+
+    /// 最老的活 snapshot 的 seq（u64::MAX 表示没有），由 DB 的 snapshot 列表维护
+    snapshot_watermark: Option<Arc<AtomicU64>>,
+}
+
+/// 一个 CF 最近 `time_travel_retention_secs` 内装过的 Version，旧的在前
+struct VersionHistory {
+    /// 比 floor 还早的 sequence 已经读不到了
+    floor: SequenceNumber,
+    versions: VecDeque<ArchivedVersion>,
+}
+
+struct ArchivedVersion {
+    /// 安装这个 Version 时的 sequence；它在 [installed_seq, 下一个的 installed_seq) 内是 current
+    installed_seq: SequenceNumber,
+    installed_at: Instant,
+    version: Arc<Version>,
+}
+
+impl VersionHistory {
+    fn new(seq: SequenceNumber, version: Arc<Version>) -> Self {
+        let mut versions = VecDeque::new();
+        versions.push_back(ArchivedVersion { installed_seq: seq, installed_at: Instant::now(), version });
+        Self { floor: seq, versions }
+    }
+
+    fn push(&mut self, seq: SequenceNumber, version: Arc<Version>, retention: Duration) {
+        self.versions.push_back(ArchivedVersion { installed_seq: seq, installed_at: Instant::now(), version });
+        // 第二个都过期了，第一个就不可能再被选为 base
+        while self.versions.len() > 1 && self.versions[1].installed_at.elapsed() > retention {
+            self.versions.pop_front();
+            self.floor = self.versions[0].installed_seq;
+        }
+    }
+
+    /// seq 时刻的 current 以及之后装过的全部 Version
+    fn versions_since(&self, seq: SequenceNumber) -> Vec<Arc<Version>> {
+        let base = self.versions.iter().rposition(|v| v.installed_seq <= seq).unwrap_or(0);
+        self.versions.range(base..).map(|v| Arc::clone(&v.version)).collect()
+    }
 }
 
 pub struct ColumnFamilyData {
@@ -40,15 +128,33 @@ pub struct ColumnFamilyData {
     pub name: String,
     pub current: Arc<Version>,
     pub builder: VersionBuilder,
+    pub stats: Arc<CfStatistics>,
+    /// `create_column_family` 传进来的选项；为 None 时按 cf_type 用全局的 system_cf / user_cf
+    pub cf_options: Option<Arc<ColumnFamilyOptions>>,
 }
 
 impl ColumnFamilyData {
-    fn options(&self, global_options: &Options) -> &ColumnFamilyOptions {
+    pub fn options<'a>(&'a self, global_options: &'a Options) -> &'a ColumnFamilyOptions {
+        if let Some(opts) = &self.cf_options {
+            return opts;
+        }
         match self.cf_type {
             CfType::User => &global_options.user_cf,
             CfType::System => &global_options.system_cf,
         }
     }
+
+    /// CF_ADD 带的选项记录，按 cf_type 以全局的 user_cf / system_cf 为底解出来
+    fn options_of_edit(edit: &VersionEdit, global_options: &Options) -> Result<Option<Arc<ColumnFamilyOptions>>, DBError> {
+        let Some(record) = &edit.cf_options else {
+            return Ok(None);
+        };
+        let base = match edit.cf_type {
+            CfType::User => &global_options.user_cf,
+            CfType::System => &global_options.system_cf,
+        };
+        ColumnFamilyOptions::decode_record(record, base).map(|opts| Some(Arc::new(opts)))
+    }
 }
 
 #[derive(Clone)]
@@ -75,6 +181,8 @@ impl VersionSet {
         let mut cf_map: HashMap<u32, Arc<ColumnFamilyData>> = HashMap::new();
         let mut last_sequence = 0u64;
         let mut next_file_number = 1u64;
+        let mut dropped_cfs: HashSet<ColumnFamilyId> = HashSet::new();
+        let mut obsolete_files: Vec<u64> = Vec::new();
 
         // If no valid manifest pointer is found, treat this as the first startup
         if manifest_file.is_none() {
@@ -84,7 +192,7 @@ impl VersionSet {
                 .join(manifest_name);
 
             // 创建 manifest
-            let manifest = ManifestWriter::create_new(&manifest_path)?;
+            let mut manifest = ManifestWriter::create_new(&manifest_path)?;
 
             // build system column family
             let system_cf = Arc::new(ColumnFamilyData {
@@ -93,6 +201,8 @@ impl VersionSet {
                 name: SYSTEM_COLUMN_FAMILY.to_string(),
                 current: Arc::new(Version::new_empty(Arc::clone(&table_cache))),
                 builder: VersionBuilder::new_from_version(&Version::new_empty(Arc::clone(&table_cache))),
+                stats: Arc::new(CfStatistics::new()),
+                cf_options: None,
             });
             cf_map.insert(USER_COLUMN_FAMILY_ID, Arc::clone(&system_cf));
 
@@ -103,32 +213,80 @@ impl VersionSet {
                 name: USER_COLUMN_FAMILY.to_string(),
                 current: Arc::new(Version::new_empty(Arc::clone(&table_cache))),
                 builder: VersionBuilder::new_from_version(&Version::new_empty(Arc::clone(&table_cache))),
+                stats: Arc::new(CfStatistics::new()),
+                cf_options: None,
             });
             cf_map.insert(SYSTEM_COLUMN_FAMILY_ID, Arc::clone(&user_cf));
 
+            // 两个默认 CF 记进 MANIFEST，重新打开时 replay 出来
+            let edits: Vec<VersionEdit> = [&system_cf, &user_cf].iter().map(|cf| {
+                let mut edit = VersionEdit::new(cf.cf_id, cf.cf_type);
+                edit.is_cf_add = true;
+                edit.cf_name = Some(cf.name.clone());
+                edit
+            }).collect();
+            manifest.add_records(&edits)?;
+            // MANIFEST 落盘之后才写 CURRENT：有 CURRENT 就说明它指的 MANIFEST 是完整的；
+            // 在这之前 crash，目录里只剩一个 bare 的第一个 MANIFEST，见 DbConfig::db_state
+            sync_file(&manifest_path, true)?;
+            write_current(&db_config.db_path, manifest_name)?;
+            sync_dir(&db_config.db_path)?;
+
             return Ok(Self {
                 db_config: Arc::new(db_config.clone()),
                 cf_map,
                 next_file_number: AtomicU64::new(1),
                 current_sequence: AtomicU64::new(0),
                 last_sequence: AtomicU64::new(0),
+                reserved_file_number: AtomicU64::new(0),
+                reserved_sequence: AtomicU64::new(0),
+                wal_applied_sequence: 0,
                 manifest: Arc::new(Mutex::new(manifest)),
+                manifest_path,
+                manifest_queue: Arc::new(ManifestWriteQueue::new()),
                 table_cache,
-            });
+                dropped_cfs,
+                obsolete_files,
+                history: HashMap::new(),
+                marked_for_compaction: HashMap::new(),
+                merge_operators: HashMap::new(),
+                job_log: Arc::new(JobLog::open(&db_config.job_log_path())?),
+                pending_outputs: Mutex::new(HashSet::new()),
+                version_pins: None,
+                live_wal: None,
+                snapshot_watermark: None,
+            }.with_history());
         }
 
         // Non-first startup: replay the manifest to rebuild CF versions and sequence/file numbers
         let manifest_name = manifest_file.unwrap();
         let manifest_path = db_config.manifest_dir.join(manifest_name);
-        let mut manifest = ManifestReader::open(manifest_path)?;
+        let mut manifest = ManifestReader::open(&manifest_path)?;
+
 
 
+        let mut reserved_sequence = 0u64;
+        let mut wal_applied_sequence = 0u64;
 
         manifest.replay(|edit| {
 
             let cf_id = edit.cf_id;
 
+            last_sequence =
+                last_sequence.max(edit.last_sequence.unwrap_or(last_sequence));
+
+            next_file_number =
+                next_file_number.max(edit.next_file_number.unwrap_or(next_file_number));
+
+            reserved_sequence =
+                reserved_sequence.max(edit.reserved_sequence.unwrap_or(reserved_sequence));
+
+            wal_applied_sequence =
+                wal_applied_sequence.max(edit.wal_applied_sequence.unwrap_or(wal_applied_sequence));
+
             if edit.is_cf_add {
+                dropped_cfs.remove(&cf_id);
+                let cf_options = ColumnFamilyData::options_of_edit(&edit, &db_config.options)?;
                 cf_map.entry(cf_id).or_insert_with(|| {
                     Arc::new(ColumnFamilyData {
                         cf_id,
@@ -136,12 +294,24 @@ impl VersionSet {
                         name: edit.cf_name.clone().unwrap_or_else(|| format!("cf_{}", cf_id)),
                         current: Arc::new(Version::new_empty(Arc::clone(&table_cache))),
                         builder: VersionBuilder::new_from_version(&Version::new_empty(Arc::clone(&table_cache))),
+                        stats: Arc::new(CfStatistics::new()),
+                        cf_options,
                     })
                 });
             }
 
             if edit.is_cf_drop {
-                cf_map.remove(&cf_id);
+                // Files of a dropped CF may survive a crash before the purge ran
+                if let Some(cfd) = cf_map.remove(&cf_id) {
+                    obsolete_files.extend(cfd.current.all_file_numbers());
+                }
+                dropped_cfs.insert(cf_id);
+                return Ok(());
+            }
+
+            // 只带预留区间的 edit 不属于任何 CF
+            if !edit.touches_version() && !edit.is_cf_add {
+                return Ok(());
             }
 
             let cfd = cf_map
@@ -152,12 +322,6 @@ impl VersionSet {
             Arc::get_mut(cfd).unwrap().current = Arc::new(ver);
             Arc::get_mut(cfd).unwrap().builder = VersionBuilder::new_from_version(&cfd.current);
 
-            last_sequence =
-                last_sequence.max(edit.last_sequence.unwrap_or(last_sequence));
-
-            next_file_number =
-                next_file_number.max(edit.next_file_number.unwrap_or(next_file_number));
-
             Ok(())
         })?;
 
@@ -168,25 +332,76 @@ impl VersionSet {
             db_config: Arc::new(db_config.clone()),
             cf_map,
             next_file_number: AtomicU64::new(next_file_number),
-            current_sequence: AtomicU64::new(0),
+            // 预留过的 sequence 可能已经发出去了（WAL 里有，MANIFEST 里还没有），
+            // 从预留上界之后继续分配，不会重复
+            current_sequence: AtomicU64::new(last_sequence.max(reserved_sequence)),
             last_sequence: AtomicU64::new(last_sequence),
+            reserved_file_number: AtomicU64::new(next_file_number),
+            reserved_sequence: AtomicU64::new(reserved_sequence),
+            wal_applied_sequence,
             manifest: Arc::new(Mutex::new(writer)),
+            manifest_path,
+            manifest_queue: Arc::new(ManifestWriteQueue::new()),
             table_cache,
-        })
+            dropped_cfs,
+            obsolete_files,
+            history: HashMap::new(),
+            marked_for_compaction: HashMap::new(),
+            merge_operators: HashMap::new(),
+            job_log: Arc::new(JobLog::open(&db_config.job_log_path())?),
+            pending_outputs: Mutex::new(HashSet::new()),
+            version_pins: None,
+            live_wal: None,
+            snapshot_watermark: None,
+        }.with_history())
+    }
+
+    /// 打开时的 current 作为历史起点，更早的 sequence 不支持 time-travel
+    fn with_history(mut self) -> Self {
+        if self.db_config.options.time_travel_retention_secs > 0 {
+            let seq = self.latest_sst_snapshot();
+            for cf in self.cf_map.values() {
+                self.history.insert(cf.cf_id, VersionHistory::new(seq, Arc::clone(&cf.current)));
+            }
+        }
+        self
     }
 
 
     /// Allocate a new SST file number.
-    /// This method does not clone any data; it simply increments the internal counter.
-    pub fn new_file_number(&self) -> u64 {
-        self.next_file_number.fetch_add(1, Ordering::Relaxed) + 1
+    /// Numbers are reserved in the MANIFEST in batches of `FILE_NUMBER_RESERVE_BATCH`,
+    /// so a crash never hands out a number that an unrecorded file may already use.
+    pub fn new_file_number(&self) -> Result<u64, DBError> {
+        let n = self.next_file_number.fetch_add(1, Ordering::SeqCst) + 1;
+        self.reserve(&self.reserved_file_number, n, FILE_NUMBER_RESERVE_BATCH, |edit, upto| {
+            edit.next_file_number = Some(upto);
+        })?;
+        // 调用方写完文件、装进 Version 后调 release_pending_output
+        self.pending_outputs.lock().unwrap().insert(n);
+        Ok(n)
+    }
+
+    /// The SST allocated by `new_file_number` is now recorded in the MANIFEST (or
+    /// was abandoned), so `delete_obsolete_files` may judge it like any other file.
+    pub fn release_pending_output(&self, file_number: u64) {
+        self.pending_outputs.lock().unwrap().remove(&file_number);
+    }
+
+    /// 有没有比 `file_number` 小、还没装进 Version 的输出文件
+    ///
+    /// 冻结的 memtable 在冻结时就分好了文件号，这里为 false 说明比 `file_number`
+    /// 早冻结的 memtable 都已经 flush 完了。
+    pub fn has_pending_output_below(&self, file_number: u64) -> bool {
+        self.pending_outputs.lock().unwrap().iter().any(|&n| n < file_number)
     }
 
     /// Return the next global monotonically increasing sequence number.
-    /// This does NOT persist anything to disk; persistence is handled via `VersionEdit` in MANIFEST.
+    /// Sequences are reserved in the MANIFEST in batches; see `new_file_number`.
     #[inline]
-    pub fn next_sequence(&self) -> u64 {
-        self.current_sequence.fetch_add(1, Ordering::Relaxed) +1
+    pub fn next_sequence(&self) -> Result<u64, DBError> {
+        let seq = self.current_sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        self.reserve_sequence(seq)?;
+        Ok(seq)
     }
 
     #[inline]
@@ -200,23 +415,117 @@ impl VersionSet {
     }
 
     /// Allocate a sequence number for a write batch.
-    /// Returns the first sequence of the batch, and advances the global sequence counter
+    /// Returns the last sequence of the batch, and advances the global sequence counter
     /// by `batch_size` entries.
-    pub fn allocate_sequence(&mut self, batch_size: u64) -> u64 {
-        self.current_sequence.fetch_add(batch_size, Ordering::Relaxed) + batch_size
+    pub fn allocate_sequence(&mut self, batch_size: u64) -> Result<u64, DBError> {
+        let seq = self.current_sequence.fetch_add(batch_size, Ordering::SeqCst) + batch_size;
+        self.reserve_sequence(seq)?;
+        Ok(seq)
+    }
+
+    /// Allocate `batch_size` consecutive sequence numbers and return the first
+    /// one; entry `i` of the batch gets `first + i`.
+    pub fn allocate_sequence_range(&mut self, batch_size: u64) -> Result<u64, DBError> {
+        let last = self.allocate_sequence(batch_size)?;
+        Ok(last + 1 - batch_size.max(1))
+    }
+
+    /// Move the sequence counter up to at least `seq`, e.g. past sequences found
+    /// in SSTs or the WAL that the MANIFEST does not account for.
+    pub fn advance_sequence(&self, seq: u64) -> Result<(), DBError> {
+        self.current_sequence.fetch_max(seq, Ordering::SeqCst);
+        self.reserve_sequence(seq)
+    }
+
+    fn reserve_sequence(&self, seq: u64) -> Result<(), DBError> {
+        self.reserve(&self.reserved_sequence, seq, SEQUENCE_RESERVE_BATCH, |edit, upto| {
+            edit.reserved_sequence = Some(upto);
+        })
+    }
+
+    /// `n` 超过已预留的上界时，先把新的上界写进 MANIFEST 再返回。
+    /// 持有 manifest 锁的时候再检查一次，并发分配只会写一条记录。
+    fn reserve(
+        &self,
+        reserved: &AtomicU64,
+        n: u64,
+        batch: u64,
+        set: impl FnOnce(&mut VersionEdit, u64),
+    ) -> Result<(), DBError> {
+        if n <= reserved.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut mf = self.manifest.lock().unwrap();
+        if n <= reserved.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let upto = n + batch - 1;
+        let mut edit = VersionEdit::default();
+        set(&mut edit, upto);
+        mf.add_record(&edit)?;
+        reserved.fetch_max(upto, Ordering::SeqCst);
+        Ok(())
     }
 
     /// Log the version edit to the manifest file and apply it to the in-memory Version.
     /// This is called during runtime when flush, compaction, or other metadata changes occur.
     pub fn log_and_apply(&mut self, edit: VersionEdit) -> Result<(), DBError> {
-        // Persist metadata to manifest (append-only)
-        {
-            let mut mf = self.manifest.lock().unwrap();
-            mf.add_record(&edit)?;
+        self.log_and_apply_group(vec![edit])
+    }
+
+    /// Log a group of edits with a single manifest flush, then apply them in order.
+    ///
+    /// The manifest lock is held until the last edit is applied, so no other
+    /// manifest record (e.g. a number reservation) lands between persisting and installing.
+    pub fn log_and_apply_group(&mut self, edits: Vec<VersionEdit>) -> Result<(), DBError> {
+        let manifest = Arc::clone(&self.manifest);
+        let mut mf = manifest.lock().unwrap();
+        mf.add_records(&edits)?;
+        for edit in &edits {
+            self.apply_to_memory(edit);
+        }
+        Ok(())
+    }
+
+    /// Shared queue that batches concurrent `log_and_apply` callers; see `ManifestWriteQueue`.
+    pub fn manifest_queue(&self) -> Arc<ManifestWriteQueue> {
+        Arc::clone(&self.manifest_queue)
+    }
+
+    fn apply_to_memory(&mut self, edit: &VersionEdit) {
+        if edit.is_cf_add && !self.cf_map.contains_key(&edit.cf_id) {
+            // create_column_family 已经解过一遍，这里失败只可能是别处来的坏记录
+            let cf_options = ColumnFamilyData::options_of_edit(edit, &self.db_config.options).unwrap_or_else(|e| {
+                log::warn!("column family {} falls back to the global options: {:?}", edit.cf_id, e);
+                None
+            });
+            let empty = Version::new_empty(Arc::clone(&self.table_cache));
+            self.dropped_cfs.remove(&edit.cf_id);
+            self.cf_map.insert(edit.cf_id, Arc::new(ColumnFamilyData {
+                cf_id: edit.cf_id,
+                cf_type: edit.cf_type,
+                name: edit.cf_name.clone().unwrap_or_else(|| format!("cf_{}", edit.cf_id)),
+                builder: VersionBuilder::new_from_version(&empty),
+                current: Arc::new(empty),
+                stats: Arc::new(CfStatistics::new()),
+                cf_options,
+            }));
+        }
+
+        if edit.is_cf_drop {
+            self.cf_map.remove(&edit.cf_id);
+            self.history.remove(&edit.cf_id);
+            self.marked_for_compaction.remove(&edit.cf_id);
+            self.merge_operators.remove(&edit.cf_id);
+            self.dropped_cfs.insert(edit.cf_id);
+            return;
         }
 
+        // 只带 sequence / WAL 进度的 edit 不动任何 CF 的 Version
+        let touches_cf = edit.touches_version();
+
         // Apply the edit to the corresponding column family version in memory
-        if let Some(cf) = self.cf_map.get(&edit.cf_id) {
+        if let Some(cf) = self.cf_map.get(&edit.cf_id).filter(|_| touches_cf) {
             let mut new_version = cf.current.as_ref().clone();
             new_version.apply_edit(&edit, &self.table_cache);
 
@@ -226,9 +535,17 @@ impl VersionSet {
                 name: cf.name.clone(),
                 current: Arc::new(new_version),
                 builder: cf.builder.clone(),
+                stats: Arc::clone(&cf.stats),
+                cf_options: cf.cf_options.clone(),
             });
 
             self.cf_map.insert(edit.cf_id, Arc::clone(&cf_data));
+            self.archive_version(edit.cf_id, Arc::clone(&cf_data.current));
+
+            // 被 compaction / 删除掉的文件不再需要标记
+            if let Some(marked) = self.marked_for_compaction.get_mut(&edit.cf_id) {
+                marked.retain(|n| !edit.delete_files.iter().any(|(_, d)| d == n));
+            }
         }
 
         // Update global sequence and file number trackers
@@ -238,8 +555,6 @@ impl VersionSet {
         self.next_file_number.fetch_max(
             edit.next_file_number.unwrap_or(self.next_file_number.load(Ordering::SeqCst)),
             Ordering::SeqCst);
-
-        Ok(())
     }
 
     /// Get a value by key from the current column family Version.
@@ -247,11 +562,11 @@ impl VersionSet {
     pub fn get(&self, cf_id: ColumnFamilyId, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
         let cf = self.cf_map.get(&cf_id)
             .ok_or(DBError::NotFound(format!("column family {} not found", cf_id)))?;
-        match cf.current.get(key) {
+        match cf.current.get(key, &cf.stats) {
             Ok(Some(v)) => Ok(Some(v)),
             Ok(None) => Ok(None),
             Err(e) => Err(DBError::InvalidColumnFamily(format!(
-                                               "Get operation failed on CF {}, key {}, error: {}",
+                                               "Get operation failed on CF {}, key {}, error: {:?}",
                                                cf_id,
                                                String::from_utf8_lossy(key), // convert &[u8] to readable text
                                                e
@@ -259,6 +574,50 @@ impl VersionSet {
         }
     }
 
+    /// Remember a newly installed Version for time-travel reads.
+    fn archive_version(&mut self, cf_id: ColumnFamilyId, version: Arc<Version>) {
+        let retention = self.db_config.options.time_travel_retention_secs;
+        if retention == 0 {
+            return;
+        }
+        let seq = self.current_sequence().max(self.latest_sst_snapshot());
+        self.history
+            .entry(cf_id)
+            .or_insert_with(|| VersionHistory::new(seq, Arc::clone(&version)))
+            .push(seq, version, Duration::from_secs(retention));
+    }
+
+    /// The files that held `cf_id`'s data as of `seq`, as one read-only Version.
+    ///
+    /// Compaction may have dropped older entries from the files installed since,
+    /// so the result covers the Version current at `seq` plus everything after it.
+    pub(crate) fn version_as_of(&self, cf_id: ColumnFamilyId, seq: SequenceNumber) -> Result<Version, DBError> {
+        let history = self.history.get(&cf_id).ok_or_else(|| {
+            if self.db_config.options.time_travel_retention_secs == 0 {
+                DBError::InvalidArgument("time-travel reads are disabled".into())
+            } else {
+                DBError::UnknownColumnFamily(cf_id.to_string())
+            }
+        })?;
+        if seq < history.floor {
+            return Err(DBError::InvalidArgument(format!(
+                "sequence {} is older than the time-travel retention window (oldest {})",
+                seq, history.floor
+            )));
+        }
+        Ok(Version::union_of(&history.versions_since(seq), Arc::clone(&self.table_cache)))
+    }
+
+    /// Read `key` from the SSTs as the column family was at `seq`.
+    pub fn get_as_of(&self, cf_id: ColumnFamilyId, key: &[u8], seq: SequenceNumber, opts: &ReadOptions) -> Result<Option<Vec<u8>>, DBError> {
+        self.version_as_of(cf_id, seq)?.get_as_of(key, seq, opts)
+    }
+
+    /// Iterate the SSTs of a column family as they were at `seq`.
+    pub fn iterator_as_of(&self, cf_id: ColumnFamilyId, seq: SequenceNumber) -> Result<Box<dyn DBIterator>, DBError> {
+        Ok(self.version_as_of(cf_id, seq)?.new_iterator(seq))
+    }
+
     /// Create a new iterator for a given column family snapshot.
     /// Uses `Arc::clone` to efficiently share ownership without deep copying.
     pub fn new_iterator(&self, cf_id: u32) -> Box<dyn DBIterator> {
@@ -269,6 +628,306 @@ impl VersionSet {
         }
     }
 
+    /// SST 加上 `mem_iters` 的合并视图，读到 `snapshot_seq` 为止；
+    /// `mem_tombstones` 是 memtable 里还没 flush 的范围墓碑
+    pub fn new_iterator_with_memtables(
+        &self,
+        cf_id: u32,
+        mem_iters: Vec<Box<dyn InternalIterator>>,
+        mem_tombstones: Vec<RangeTombstone>,
+        snapshot_seq: u64,
+        opts: &ReadOptions,
+    ) -> Box<dyn DBIterator> {
+        if let Some(cf) = self.cf_map.get(&cf_id) {
+            let prefix_extractor = cf.options(&self.db_config.options)
+                .prefix_extractor
+                .as_ref()
+                .filter(|_| opts.prefix_same_as_start);
+            cf.current.new_merged_iterator(mem_iters, mem_tombstones, self.merge_operator(cf_id), snapshot_seq, opts, prefix_extractor)
+        } else {
+            Box::new(EmptyIterator {})
+        }
+    }
+
+    /// Log a CF_DROP edit and forget the column family.
+    /// Returns the file numbers it owned so the caller can purge them in the background.
+    pub fn drop_column_family(&mut self, cf_id: ColumnFamilyId) -> Result<Vec<u64>, DBError> {
+        let cf = self.cf_map.get(&cf_id)
+            .ok_or(DBError::UnknownColumnFamily(cf_id.to_string()))?;
+        let files = cf.current.all_file_numbers();
+
+        let mut edit = VersionEdit::new(cf_id, cf.cf_type);
+        edit.is_cf_drop = true;
+        self.log_and_apply(edit)?;
+        Ok(files)
+    }
+
+    /// Register a new column family named `name` and return its id.
+    ///
+    /// Ids are never reused: the new id is one past every live or dropped CF.
+    /// `options` overrides the global `user_cf` / `system_cf` options for this CF.
+    /// They are recorded in the MANIFEST with the CF and survive a reopen; a
+    /// filter policy or prefix extractor that isn't built in comes back as the
+    /// global one.
+    pub fn create_column_family(
+        &mut self,
+        name: &str,
+        cf_type: CfType,
+        options: Option<ColumnFamilyOptions>,
+    ) -> Result<ColumnFamilyId, DBError> {
+        if self.column_family_by_name(name).is_some() {
+            return Err(DBError::InvalidArgument(format!("column family {:?} already exists", name)));
+        }
+        let cf_id = self.cf_map.keys()
+            .chain(self.dropped_cfs.iter())
+            .copied()
+            .max()
+            .map_or(0, |id| id + 1);
+
+        let mut edit = VersionEdit::new(cf_id, cf_type);
+        edit.is_cf_add = true;
+        edit.cf_name = Some(name.to_string());
+        edit.cf_options = options.map(|opts| opts.encode_record());
+        // 先解一遍：写进 MANIFEST 的记录重新打开时一定读得回来
+        ColumnFamilyData::options_of_edit(&edit, &self.db_config.options)?;
+        self.log_and_apply(edit)?;
+        Ok(cf_id)
+    }
+
+    pub fn column_family_by_name(&self, name: &str) -> Option<&ColumnFamilyData> {
+        self.cf_map.values().find(|cf| cf.name == name).map(|arc| arc.as_ref())
+    }
+
+    /// Drop every SST of `cf_id` that lies entirely inside `[begin, end)` with one VersionEdit.
+    ///
+    /// `None` means unbounded on that side. Returns the removed files that no archived
+    /// Version still references, i.e. the ones safe to delete from disk.
+    pub fn delete_files_in_range(
+        &mut self,
+        cf_id: ColumnFamilyId,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<Vec<u64>, DBError> {
+        let cf = self.cf_map.get(&cf_id)
+            .ok_or(DBError::UnknownColumnFamily(cf_id.to_string()))?;
+
+        let mut edit = VersionEdit::new(cf_id, cf.cf_type);
+        let mut removed = Vec::new();
+        for (level, files) in cf.current.levels().iter().enumerate() {
+            for f in files {
+                let inside = begin.map_or(true, |b| f.smallest_key.as_slice() >= b)
+                    && end.map_or(true, |e| f.largest_key.as_slice() < e);
+                if inside {
+                    edit.delete_file(level, f.file_number);
+                    removed.push(f.file_number);
+                }
+            }
+        }
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+
+        self.log_and_apply(edit)?;
+
+        let archived = self.archived_file_numbers(cf_id);
+        removed.retain(|n| !archived.contains(n));
+        Ok(removed)
+    }
+
+    /// Mark the files of `cf_id` overlapping `[begin, end]` for compaction and return how many.
+    ///
+    /// Nothing is compacted here; the next level compaction picks marked files first.
+    pub fn suggest_compact_range(
+        &mut self,
+        cf_id: ColumnFamilyId,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<usize, DBError> {
+        let cf = self.cf_map.get(&cf_id)
+            .ok_or(DBError::UnknownColumnFamily(cf_id.to_string()))?;
+        let marked = self.marked_for_compaction.entry(cf_id).or_default();
+        let before = marked.len();
+        for f in cf.current.levels().iter().flatten() {
+            let overlaps = begin.map_or(true, |b| f.largest_key.as_slice() >= b)
+                && end.map_or(true, |e| f.smallest_key.as_slice() <= e);
+            if overlaps {
+                marked.insert(f.file_number);
+            }
+        }
+        Ok(marked.len() - before)
+    }
+
+    /// Mark one file of `cf_id` for compaction, e.g. after it used up its allowed seeks.
+    pub fn mark_file_for_compaction(&mut self, cf_id: ColumnFamilyId, file_number: u64) {
+        self.marked_for_compaction.entry(cf_id).or_default().insert(file_number);
+    }
+
+    /// Log of finished flush / compaction jobs.
+    pub fn job_log(&self) -> Arc<JobLog> {
+        Arc::clone(&self.job_log)
+    }
+
+    /// Install the merge operator of `cf`; `DB::merge` is rejected until one is set.
+    pub fn set_merge_operator(&mut self, cf_id: ColumnFamilyId, op: Arc<dyn MergeOperator + Send + Sync>) {
+        self.merge_operators.insert(cf_id, op);
+    }
+
+    pub fn merge_operator(&self, cf_id: ColumnFamilyId) -> Option<Arc<dyn MergeOperator + Send + Sync>> {
+        self.merge_operators.get(&cf_id).cloned()
+    }
+
+    /// Files of `cf_id` currently marked by `suggest_compact_range`.
+    pub fn files_marked_for_compaction(&self, cf_id: ColumnFamilyId) -> HashSet<u64> {
+        self.marked_for_compaction.get(&cf_id).cloned().unwrap_or_default()
+    }
+
+    /// Files still referenced by the time-travel history of a column family.
+    fn archived_file_numbers(&self, cf_id: ColumnFamilyId) -> HashSet<u64> {
+        self.history
+            .get(&cf_id)
+            .map(|h| h.versions.iter().flat_map(|v| v.version.all_file_numbers()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Sequence up to which the WAL is known to be applied to SSTs.
+    pub fn wal_applied_sequence(&self) -> SequenceNumber {
+        self.wal_applied_sequence
+    }
+
+    /// Record in the MANIFEST that every WAL record up to `seq` lives in SSTs,
+    /// so the WAL can be truncated.
+    pub fn mark_wal_applied(&mut self, seq: SequenceNumber) -> Result<(), DBError> {
+        let mut edit = VersionEdit::default();
+        edit.wal_applied_sequence = Some(seq);
+        edit.last_sequence = Some(self.current_sequence());
+        self.log_and_apply(edit)?;
+        self.wal_applied_sequence = self.wal_applied_sequence.max(seq);
+        Ok(())
+    }
+
+    /// Column families dropped according to the MANIFEST.
+    pub fn dropped_column_families(&self) -> &HashSet<ColumnFamilyId> {
+        &self.dropped_cfs
+    }
+
+    /// Files left behind by dropped column families, found while replaying the MANIFEST.
+    pub fn take_obsolete_files(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.obsolete_files)
+    }
+
+    /// Route SSTs found by `delete_obsolete_files` through `pins`, so files still
+    /// read through a pinned Version are deleted only after their last reader.
+    pub fn set_version_pins(&mut self, pins: Arc<VersionPins>) {
+        self.version_pins = Some(pins);
+    }
+
+    /// Seq of the oldest live snapshot, kept up to date by the DB's snapshot list.
+    pub fn set_snapshot_watermark(&mut self, watermark: Arc<AtomicU64>) {
+        self.snapshot_watermark = Some(watermark);
+    }
+
+    /// Oldest sequence a live snapshot reads at. Compaction keeps every version a
+    /// snapshot at or after it can see; `None` means no snapshot is open.
+    pub fn oldest_snapshot(&self) -> Option<SequenceNumber> {
+        self.snapshot_watermark
+            .as_ref()
+            .map(|w| w.load(Ordering::Acquire))
+            .filter(|&seq| seq != u64::MAX)
+    }
+
+    /// The WAL being written; every other log in the WAL directory is obsolete.
+    pub fn set_live_wal(&mut self, path: PathBuf) {
+        self.live_wal = Some(path);
+    }
+
+    /// 删掉不再被引用的文件
+    ///
+    /// 活着的 SST 是所有 CF 的 current、time-travel 历史里的 Version 和还没写进 MANIFEST
+    /// 的输出；SST 目录里其余的 `NNNNNN.sst` 交给 `VersionPins::purge`，还被 pin 着的
+    /// Version 读的文件会等到放掉再删。MANIFEST 目录里不是当前 MANIFEST 的 `MANIFEST-*`、
+    /// WAL 目录里不是当前 WAL 的 `*.log` 直接删。临时文件（`.sst.tmp`）不碰，open 时另有清理。
+    ///
+    /// 返回删掉（或等待删除）的文件数。
+    pub fn delete_obsolete_files(&self) -> Result<usize, DBError> {
+        let mut live: HashSet<u64> = self.pending_outputs.lock().unwrap().clone();
+        for (&cf_id, cf) in &self.cf_map {
+            live.extend(cf.current.all_file_numbers());
+            live.extend(self.archived_file_numbers(cf_id));
+        }
+
+        let mut obsolete_ssts = Vec::new();
+        // tier 目录可能和别的 DB 共用，只扫自己的 sst_dir
+        for dir in self.db_config.sst_dirs() {
+            for entry in std::fs::read_dir(&dir)? {
+                let name = entry?.file_name();
+                let Some(number) = name.to_str()
+                    .and_then(|n| n.strip_suffix(".sst"))
+                    .and_then(|n| n.parse::<u64>().ok()) else { continue };
+                if !live.contains(&number) {
+                    obsolete_ssts.push(number);
+                }
+            }
+        }
+
+        let mut obsolete_paths = Vec::new();
+        for entry in std::fs::read_dir(&self.db_config.manifest_dir)? {
+            let path = entry?.path();
+            let is_manifest = path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("MANIFEST-"));
+            if is_manifest && path != self.manifest_path {
+                obsolete_paths.push(path);
+            }
+        }
+        // WAL 目录就是 WAL 文件本身时没有别的 log
+        if let Some(live_wal) = &self.live_wal {
+            if self.db_config.wal_dir.is_dir() {
+                for entry in std::fs::read_dir(&self.db_config.wal_dir)? {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|e| e == "log") && &path != live_wal {
+                        obsolete_paths.push(path);
+                    }
+                }
+            }
+        }
+
+        let removed = obsolete_ssts.len() + obsolete_paths.len();
+        if !obsolete_ssts.is_empty() {
+            log::info!("deleting {} obsolete SST files: {:?}", obsolete_ssts.len(), obsolete_ssts);
+        }
+        match &self.version_pins {
+            Some(pins) => pins.purge(&obsolete_ssts),
+            None => {
+                for &number in &obsolete_ssts {
+                    self.table_cache.evict(number);
+                    let resolver = self.db_config.file_resolver();
+                    let Ok(path) = resolver.resolve(number) else { continue };
+                    resolver.forget(number);
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        log::warn!("failed to delete {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+        for path in obsolete_paths {
+            log::info!("deleting obsolete file {:?}", path);
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("failed to delete {:?}: {}", path, e);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Path of the MANIFEST being appended to.
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// Statistics of a column family; shared across its Versions.
+    pub fn cf_statistics(&self, cf_id: ColumnFamilyId) -> Option<Arc<CfStatistics>> {
+        self.cf_map.get(&cf_id).map(|cf| Arc::clone(&cf.stats))
+    }
+
     /// Return the current Version of a column family.
     /// This is an O(1) pointer clone (reference count increment), no data copy.
     pub fn current_version(&self, cf_id: u32) -> Arc<Version> {
@@ -279,10 +938,44 @@ impl VersionSet {
     }
 
 
+    /// 所有 CF 的预热文件列表（见 Version::files_for_preload）
+    pub fn files_for_preload(&self, hottest: usize, bottom_level: bool) -> Vec<u64> {
+        self.cf_map
+            .values()
+            .flat_map(|cf| cf.current.files_for_preload(hottest, bottom_level))
+            .collect()
+    }
+
     pub fn column_families(&self) -> Vec<ColumnFamilyId>  {
         self.cf_map.values().map(|cf| cf.cf_id.clone()).collect()
     }
 
+    /// 当前状态写成一组 edit：每个 CF 一个（建 CF，加上它所有的文件和范围墓碑），第一个
+    /// 还带着文件号和 sequence。只写这些的新 MANIFEST 打开后就是同样的 VersionSet（fork 用）
+    pub fn snapshot_edits(&self) -> Vec<VersionEdit> {
+        let mut cfs: Vec<&Arc<ColumnFamilyData>> = self.cf_map.values().collect();
+        cfs.sort_by_key(|cf| cf.cf_id);
+        let seq = self.current_sequence();
+        let mut edits: Vec<VersionEdit> = cfs.into_iter().map(|cf| {
+            let mut edit = VersionEdit::new(cf.cf_id, cf.cf_type);
+            edit.is_cf_add = true;
+            edit.cf_name = Some(cf.name.clone());
+            edit.cf_options = cf.cf_options.as_ref().map(|opts| opts.encode_record());
+            for (level, files) in cf.current.levels().iter().enumerate() {
+                edit.add_files.extend(files.iter().map(|f| (level, (**f).clone())));
+            }
+            edit.range_tombstones = cf.current.range_tombstones().to_vec();
+            edit
+        }).collect();
+        if let Some(first) = edits.first_mut() {
+            first.next_file_number = Some(self.next_file_number.load(Ordering::Relaxed));
+            first.last_sequence = Some(seq);
+            first.reserved_sequence = Some(seq);
+            first.wal_applied_sequence = Some(seq);
+        }
+        edits
+    }
+
     pub fn column_family_by_id(&self, cf_id: ColumnFamilyId) -> Result<&ColumnFamilyData, DBError> {
         self.cf_map
             .get(&cf_id)
@@ -290,17 +983,21 @@ impl VersionSet {
             .ok_or_else(|| DBError::InvalidColumnFamily(format!("CF id {} not found", cf_id)))
     }
 
-    pub fn install_table(
-        &mut self,
+    /// Open a freshly flushed L0 table into the table cache and build the edit that
+    /// installs it; the caller commits the edit through `manifest_queue`.
+    pub fn new_table_edit(
+        &self,
         cf: ColumnFamilyId,
         cf_type: CfType,
         file_number: u64,
         file_path: &Path,
         smallest: &[u8],
         largest: &[u8],
-    ) -> Result<(), DBError> {
+        range_tombstones: Vec<RangeTombstone>,
+    ) -> Result<VersionEdit, DBError> {
         // 1️⃣ 构造 VersionEdit
         let mut edit = VersionEdit::new(cf, cf_type);
+        edit.range_tombstones = range_tombstones;
         let metadata = std::fs::metadata(file_path)?;
         let file_size = metadata.len();
 
@@ -311,42 +1008,93 @@ impl VersionSet {
             smallest,
             largest,
         );
+        edit.set_file_checksum(file_number, file_checksum(file_path)?);
 
         // 2️⃣（可选）预热 table cache
         let table = SstReader::open(file_number,
                         file_path.to_path_buf(),
                         self.table_cache.block_cache(),
-                        self.table_cache.filter_policy())?;
+                        self.table_cache.filter_policy())?
+            .with_tracer(Some(self.table_cache.block_tracer()));
         self.table_cache.insert(file_number, Arc::new(table));
 
-        // 3️⃣ 写 MANIFEST + 安装新 Versio n
-        self.log_and_apply(edit)?;
-
-        Ok(())
+        Ok(edit)
     }
 
-    pub fn auto_compact(self: &Arc<Mutex<Self>>) {
-        let vs = self.lock().unwrap();
-        let cf_map =vs.cf_map.clone();
-        let db_config = vs.db_config.clone();
-        for cf in cf_map.values() {
-            let cf_clone = Arc::clone(cf);
-            let vs_arc_mutex = Arc::new(Mutex::new(Arc::clone(self)));
-            thread::spawn(move || {
-                let compactor = Compactor::new(
-                    db_config,
-                    Arc::clone(self),
-                    cf_clone,
-                    None);
-                compactor.auto_compact();
-            });
+    /// 每个 CF 起一个线程，对各层做一轮自动 compaction（见 `Compactor::auto_compact`）
+    pub fn auto_compact(version_set: &Arc<Mutex<Self>>) {
+        let compactors: Vec<Compactor> = {
+            let vs = version_set.lock().unwrap();
+            vs.cf_map
+                .values()
+                .map(|cf| Compactor::new(
+                    vs.db_config.clone(),
+                    Arc::clone(version_set),
+                    Arc::clone(cf),
+                    vs.merge_operator(cf.cf_id),
+                ))
+                .collect()
+        };
+        for compactor in compactors {
+            thread::spawn(move || compactor.auto_compact());
         }
     }
 
-    pub fn compact_level(&self, cf_id: u32, level: usize) -> Result<(), String> {
-        let cf = self.cf_map.get(&cf_id).ok_or("Unknown CF")?;
-        let compactor = Compactor::new(Arc::clone(cf), None);
-        compactor.compact_level(level, None, None)
+    /// 用 `options` 重写 `cf_id` 里编号为 `file_numbers` 的 SST（见 `SingleLevelCompaction::rewrite_files`）
+    pub fn rewrite_files(
+        version_set: &Arc<Mutex<Self>>,
+        cf_id: ColumnFamilyId,
+        file_numbers: &[u64],
+        options: &ColumnFamilyOptions,
+    ) -> Result<(), DBError> {
+        let (compaction, files) = {
+            let vs = version_set.lock().unwrap();
+            let cf = vs.cf_map.get(&cf_id)
+                .ok_or_else(|| DBError::UnknownColumnFamily(format!("CF id {} not found", cf_id)))?;
+            let levels = vs.current_version(cf_id).levels();
+            let mut files = Vec::with_capacity(file_numbers.len());
+            for &n in file_numbers {
+                let found = levels.iter().enumerate().find_map(|(level, fs)| {
+                    fs.iter().find(|f| f.file_number == n).map(|f| (level, Arc::clone(f)))
+                });
+                files.push(found.ok_or_else(|| DBError::NotFound(format!("SST {} is not live in CF {}", n, cf_id)))?);
+            }
+            let compaction = SingleLevelCompaction::new(
+                vs.db_config.clone(),
+                Arc::clone(version_set),
+                Arc::clone(cf),
+                vs.merge_operator(cf_id),
+            );
+            (compaction, files)
+        };
+        compaction.rewrite_files(&files, options).map_err(DBError::Other)
+    }
+
+    /// 在当前线程把 `cf_id` 的 `level` 里和 [begin, end] 重叠的文件合并到下一层
+    pub fn compact_level_range(
+        version_set: &Arc<Mutex<Self>>,
+        cf_id: ColumnFamilyId,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<(), DBError> {
+        let compaction = {
+            let vs = version_set.lock().unwrap();
+            let cf = vs.cf_map.get(&cf_id)
+                .ok_or_else(|| DBError::UnknownColumnFamily(format!("CF id {} not found", cf_id)))?;
+            SingleLevelCompaction::new(
+                vs.db_config.clone(),
+                Arc::clone(version_set),
+                Arc::clone(cf),
+                vs.merge_operator(cf_id),
+            )
+        };
+        compaction.compact_level(level, begin, end).map_err(DBError::Other)
+    }
+
+    /// 把 `cf_id` 的 `level` 整层合并到下一层
+    pub fn compact_level(version_set: &Arc<Mutex<Self>>, cf_id: ColumnFamilyId, level: usize) -> Result<(), DBError> {
+        Self::compact_level_range(version_set, cf_id, level, None, None)
     }
 }
 
@@ -1,6 +1,6 @@
 use crc32fast::Hasher;
-use crate::engine::wal::{WriteBatch, WriteBatchEntry};
-use crate::engine::mem::{ColumnFamilyId, SequenceNumber};
+use crate::engine::wal::WriteBatch;
+use crate::engine::mem::SequenceNumber;
 use crate::error::DBError; // 你已有的 error
 
 pub const BLOCK_SIZE: usize = 32 * 1024;
@@ -39,79 +39,16 @@ pub fn record_crc32c(typ: RecordType, payload: &[u8]) -> u32 {
 
 pub const RECORD_WRITE_BATCH: u8 = 1;
 
+/// `batch` 的编码，起始 seq 换成 `base_seq`；写 WAL 直接用 `WriteBatch::data`，这里只给要一份拷贝的地方用
 pub fn encode_write_batch(base_seq: SequenceNumber, batch: &WriteBatch) -> Vec<u8> {
-    let mut buf = Vec::new();
-
-    buf.push(RECORD_WRITE_BATCH);
-    buf.extend_from_slice(&base_seq.to_le_bytes());
-
-    let count = batch.entries.len() as u32;
-    buf.extend_from_slice(&count.to_le_bytes());
-
-    for e in &batch.entries {
-        match e {
-            WriteBatchEntry::Put { cf, key, value } => {
-                buf.push(1u8); // PUT
-                buf.extend_from_slice(&cf.to_le_bytes());
-
-                buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
-                buf.extend_from_slice(key);
-
-                buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
-                buf.extend_from_slice(value);
-            }
-            WriteBatchEntry::Delete { cf, key } => {
-                buf.push(2u8); // DELETE
-                buf.extend_from_slice(&cf.to_le_bytes());
-
-                buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
-                buf.extend_from_slice(key);
-            }
-        }
-    }
-
+    let mut buf = batch.data().to_vec();
+    buf[1..9].copy_from_slice(&base_seq.to_le_bytes());
     buf
 }
 
 pub fn decode_write_batch(buf: &[u8]) -> Result<(SequenceNumber, WriteBatch), DBError> {
-    let mut pos = 0;
-
-    let tag = read_u8(buf, &mut pos)?;
-    if tag != RECORD_WRITE_BATCH {
-        return Err(DBError::Corruption(format!("unknown record tag: {}", tag)));
-    }
-
-    let base_seq = read_u64(buf, &mut pos)?;
-    let count = read_u32(buf, &mut pos)? as usize;
-
-    let mut batch = WriteBatch::new();
-
-    for _ in 0..count {
-        let entry_tag = read_u8(buf, &mut pos)?;
-        let cf: ColumnFamilyId = read_u32(buf, &mut pos)?;
-
-        match entry_tag {
-            1 => {
-                let klen = read_u32(buf, &mut pos)? as usize;
-                let key = read_vec(buf, &mut pos, klen)?;
-
-                let vlen = read_u32(buf, &mut pos)? as usize;
-                let value = read_vec(buf, &mut pos, vlen)?;
-
-                batch.entries.push(WriteBatchEntry::Put { cf, key, value });
-            }
-            2 => {
-                let klen = read_u32(buf, &mut pos)? as usize;
-                let key = read_vec(buf, &mut pos, klen)?;
-                batch.entries.push(WriteBatchEntry::Delete { cf, key });
-            }
-            other => {
-                return Err(DBError::Corruption(format!("unknown entry tag: {}", other)));
-            }
-        }
-    }
-
-    Ok((base_seq, batch))
+    let batch = WriteBatch::from_data(buf.to_vec())?;
+    Ok((batch.sequence(), batch))
 }
 
 fn need(buf: &[u8], pos: usize, n: usize) -> Result<(), DBError> {
@@ -142,13 +79,6 @@ pub(crate) fn read_u64(buf: &[u8], pos: &mut usize) -> Result<u64, DBError> {
     Ok(v)
 }
 
-fn read_vec(buf: &[u8], pos: &mut usize, n: usize) -> Result<Vec<u8>, DBError> {
-    need(buf, *pos, n)?;
-    let out = buf[*pos..*pos + n].to_vec();
-    *pos += n;
-    Ok(out)
-}
-
 pub(crate) fn read_bytes(buf: &[u8], pos: &mut usize) -> Result<Vec<u8>, DBError> {
     need(buf, *pos, 4)?;
     let len = u32::from_le_bytes(buf[*pos..*pos + 4].try_into().unwrap()) as usize;
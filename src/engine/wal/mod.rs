@@ -2,6 +2,7 @@ pub(crate)mod wal_writer;
 pub(crate)mod wal_reader;
 pub(crate)mod format;
 pub(crate) mod wal_manager;
+pub mod wal_archive;
 pub mod write_batch;
 
 pub use format::{encode_write_batch, decode_write_batch};
@@ -9,4 +10,5 @@ pub use write_batch::{WriteBatchEntry, WriteBatch};
 pub use wal_reader::{WalReader,WalReadResult};
 pub use wal_writer::{WalWriter};
 pub use wal_manager::{WalManager};
+pub use wal_archive::WalArchive;
 pub(crate) use format::{read_bytes, read_u32, read_u64,read_string};
\ No newline at end of file
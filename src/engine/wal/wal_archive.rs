@@ -0,0 +1,188 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::DBError;
+use crate::engine::mem::SequenceNumber;
+use crate::engine::wal::{WalReader, WriteBatch};
+use crate::util::sync_dir;
+
+/// 归档目录里的 seq → 时间索引
+const SEQ_TIME_FILE: &str = "SEQ_TIME";
+/// 每条 16 字节：seq、unix 毫秒，都是小端
+const SEQ_TIME_ENTRY_SIZE: usize = 16;
+
+fn unix_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// WAL 归档
+///
+/// WAL 截断前整个文件拷进归档目录，文件名是里面最大的 seq（`{:020}.log`），按名字排就是写入顺序。
+/// 另外每秒最多记一条时间索引：这一秒第一个写入的 batch 的起始 seq 和当时的时间，
+/// 按时间点恢复时用它找到对应的 seq，精度到秒。
+pub struct WalArchive {
+    dir: PathBuf,
+    /// 时间索引文件和上一条索引所在的秒
+    index: Mutex<(File, u64)>,
+}
+
+impl WalArchive {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, DBError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(SEQ_TIME_FILE))?;
+        Ok(Self { dir, index: Mutex::new((file, 0)) })
+    }
+
+    /// 在 `base_seq` 开头的 batch 写进 WAL 之前调用；这一秒还没记过就记一条
+    pub fn note_write(&self, base_seq: SequenceNumber) -> Result<(), DBError> {
+        let now = unix_ms(SystemTime::now());
+        let mut index = self.index.lock().unwrap();
+        if now / 1000 == index.1 {
+            return Ok(());
+        }
+        let mut entry = [0u8; SEQ_TIME_ENTRY_SIZE];
+        entry[..8].copy_from_slice(&base_seq.to_le_bytes());
+        entry[8..].copy_from_slice(&now.to_le_bytes());
+        index.0.write_all(&entry)?;
+        index.1 = now / 1000;
+        Ok(())
+    }
+
+    /// 把 `wal_path` 拷进归档；空文件不归档
+    pub fn archive(&self, wal_path: &Path) -> Result<(), DBError> {
+        let Some(last_seq) = last_sequence(wal_path)? else {
+            return Ok(());
+        };
+        let dest = self.dir.join(format!("{:020}.log", last_seq));
+        let tmp = self.dir.join(format!("{:020}.log.tmp", last_seq));
+        fs::copy(wal_path, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &dest)?;
+        self.index.lock().unwrap().0.sync_all()?;
+        sync_dir(&self.dir)?;
+        log::info!("archived WAL up to sequence {} to {:?}", last_seq, dest);
+        Ok(())
+    }
+}
+
+/// 读一个 WAL 文件，依次交出 (起始 seq, batch)
+pub fn read_wal_file<F>(path: &Path, mut f: F) -> Result<(), DBError>
+where
+    F: FnMut(SequenceNumber, WriteBatch) -> Result<(), DBError>,
+{
+    let mut r = WalReader::new(BufReader::new(File::open(path)?));
+    while let Some(payload) = r.next_record()? {
+        let batch = WriteBatch::from_data(payload)?;
+        f(batch.sequence(), batch)?;
+    }
+    Ok(())
+}
+
+fn last_sequence(wal_path: &Path) -> Result<Option<SequenceNumber>, DBError> {
+    let mut last = None;
+    read_wal_file(wal_path, |base_seq, batch| {
+        if !batch.is_empty() {
+            last = Some(base_seq + batch.len() as u64 - 1);
+        }
+        Ok(())
+    })?;
+    Ok(last)
+}
+
+/// 归档里的 WAL 文件，按写入顺序
+pub fn archived_segments(dir: &Path) -> Result<Vec<PathBuf>, DBError> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "log") {
+            segments.push(path);
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// 时刻 `ts` 时已经写入的最大 seq；`ts` 之后没再写过时返回 None
+///
+/// 时间索引只到秒：和 `ts` 同一秒里、在 `ts` 之后的写入也算在内。
+pub fn sequence_at(dir: &Path, ts: SystemTime) -> Result<Option<SequenceNumber>, DBError> {
+    let ts = unix_ms(ts);
+    let mut buf = Vec::new();
+    File::open(dir.join(SEQ_TIME_FILE))?.read_to_end(&mut buf)?;
+    for entry in buf.chunks_exact(SEQ_TIME_ENTRY_SIZE) {
+        let seq = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let written_at = u64::from_le_bytes(entry[8..].try_into().unwrap());
+        if written_at / 1000 > ts / 1000 {
+            return Ok(Some(seq.saturating_sub(1)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::engine::wal::WalManager;
+    use crate::util::test_util::test_dir;
+
+    fn batch(seq: SequenceNumber, keys: &[&[u8]]) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        for k in keys {
+            batch.put(0, k, b"v");
+        }
+        batch.set_sequence(seq);
+        batch
+    }
+
+    #[test]
+    fn truncate_archives_the_wal_under_its_last_sequence() {
+        let dir = test_dir("wal-archive");
+        fs::create_dir_all(&dir).unwrap();
+        let archive_dir = dir.join("archive");
+        let before = SystemTime::now() - Duration::from_secs(5);
+        let wal = WalManager::open_with_archive(dir.join("wal.log"), Some(&archive_dir)).unwrap();
+        wal.append_sync(&batch(1, &[b"a", b"b"])).unwrap();
+        wal.append_sync(&batch(3, &[b"c"])).unwrap();
+        wal.truncate().unwrap();
+        // WAL 已经空了，再截断不会多出一个归档
+        wal.truncate().unwrap();
+
+        let segments = archived_segments(&archive_dir).unwrap();
+        assert_eq!(segments, vec![archive_dir.join(format!("{:020}.log", 3))]);
+        let mut seqs = Vec::new();
+        read_wal_file(&segments[0], |seq, batch| {
+            seqs.push((seq, batch.len()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(seqs, vec![(1, 2), (3, 1)]);
+
+        // 5 秒前一条都还没写；之后没有再写
+        assert_eq!(sequence_at(&archive_dir, before).unwrap(), Some(0));
+        assert_eq!(sequence_at(&archive_dir, SystemTime::now() + Duration::from_secs(5)).unwrap(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sequence_at_resolves_to_the_second() {
+        let dir = test_dir("wal-seq-time");
+        fs::create_dir_all(&dir).unwrap();
+        let mut index = Vec::new();
+        for (seq, ms) in [(1u64, 1_000u64), (10, 5_000), (20, 9_500)] {
+            index.extend_from_slice(&seq.to_le_bytes());
+            index.extend_from_slice(&ms.to_le_bytes());
+        }
+        fs::write(dir.join(SEQ_TIME_FILE), index).unwrap();
+        let at = |ms: u64| sequence_at(&dir, UNIX_EPOCH + Duration::from_millis(ms)).unwrap();
+
+        assert_eq!(at(500), Some(0));
+        assert_eq!(at(3_000), Some(9));
+        // 和索引同一秒：这一秒里的写都算
+        assert_eq!(at(5_000), Some(19));
+        assert_eq!(at(9_000), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -8,7 +8,7 @@ use std::time::Duration;
 use crate::{DBError, DB};
 use crate::engine::wal::WriteBatch;
 use crate::engine::mem::SequenceNumber;
-use crate::engine::wal::{WalWriter, WalReader, encode_write_batch, decode_write_batch};
+use crate::engine::wal::{WalArchive, WalWriter, WalReader};
 
 pub struct WalManager {
     path: PathBuf,
@@ -25,11 +25,20 @@ pub struct WalManager {
     // 等待 fsync 完成
     sync_mu: Mutex<()>,
     sync_cv: Condvar,
+
+    // 截断前把 WAL 留一份，按时间点恢复用
+    archive: Option<WalArchive>,
 }
 
 impl WalManager {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, DBError> {
+        Self::open_with_archive(path, None)
+    }
+
+    /// Like `open`; with `archive_dir` set, the log is copied there before every truncate.
+    pub fn open_with_archive<P: AsRef<Path>>(path: P, archive_dir: Option<&Path>) -> Result<Arc<Self>, DBError> {
         let path = path.as_ref().to_path_buf();
+        let archive = archive_dir.map(WalArchive::open).transpose()?;
 
         // 追加打开（不存在则创建）
         let f = OpenOptions::new().create(true).append(true).open(&path).map_err(DBError::Io)?;
@@ -42,6 +51,7 @@ impl WalManager {
             synced_seq: AtomicU64::new(0),
             sync_mu: Mutex::new(()),
             sync_cv: Condvar::new(),
+            archive,
         });
 
         // 启动唯一 sync 线程
@@ -82,18 +92,22 @@ impl WalManager {
         });
     }
 
-    pub fn append_sync(&self, base_seq: SequenceNumber, batch: &WriteBatch) -> Result<(),DBError> {
+    pub fn append_sync(&self, batch: &WriteBatch) -> Result<(),DBError> {
         if batch.is_empty() {
             return Ok(());
         }
 
-        let payload = encode_write_batch(base_seq, batch);
+        // batch 已经填好了起始 seq，编码原样写进 WAL
+        let base_seq = batch.sequence();
         let end_seq = base_seq + (batch.len() as u64) - 1;
 
         // 1) WAL append + flush（进入内核 page cache）
         {
             let mut w = self.writer.lock().unwrap();
-            w.append(&payload).map_err(DBError::Io)?;
+            if let Some(archive) = &self.archive {
+                archive.note_write(base_seq)?;
+            }
+            w.append(batch.data()).map_err(DBError::Io)?;
             w.flush().map_err(DBError::Io)?;
         }
 
@@ -110,16 +124,20 @@ impl WalManager {
     }
 
     /// 非强一致：只写 + flush，不等 fsync（crash 可能丢最后一小段）
-    pub fn append_no_sync(&self, base_seq: SequenceNumber, batch: &WriteBatch) -> Result<(), DBError> {
+    pub fn append_no_sync(&self, batch: &WriteBatch) -> Result<(), DBError> {
         if batch.is_empty() {
             return Ok(());
         }
-        let payload = encode_write_batch(base_seq, batch);
+        // batch 已经填好了起始 seq，编码原样写进 WAL
+        let base_seq = batch.sequence();
         let end_seq = base_seq + (batch.len() as u64) - 1;
 
         {
             let mut w = self.writer.lock().unwrap();
-            w.append(&payload).map_err(DBError::Io)?;
+            if let Some(archive) = &self.archive {
+                archive.note_write(base_seq)?;
+            }
+            w.append(batch.data()).map_err(DBError::Io)?;
             w.flush().map_err(DBError::Io)?;
         }
 
@@ -127,6 +145,42 @@ impl WalManager {
         Ok(())
     }
 
+    /// 分段写一条 batch record：`write_entries` 把记录字节一段段交给传进去的回调，
+    /// 整个 batch 不用在内存里拼好（spill 过的大事务提交用）。仍是一条 WAL record，
+    /// 重放时要么整个都在、要么整个不在。
+    pub fn append_streamed(
+        &self,
+        base_seq: SequenceNumber,
+        count: u32,
+        sync: bool,
+        write_entries: impl FnOnce(&mut dyn FnMut(&[u8]) -> Result<(), DBError>) -> Result<(), DBError>,
+    ) -> Result<(), DBError> {
+        if count == 0 {
+            return Ok(());
+        }
+        let end_seq = base_seq + count as u64 - 1;
+        {
+            let mut w = self.writer.lock().unwrap();
+            if let Some(archive) = &self.archive {
+                archive.note_write(base_seq)?;
+            }
+            w.begin_record();
+            w.append_part(&WriteBatch::encode_header(base_seq, count)).map_err(DBError::Io)?;
+            write_entries(&mut |part| w.append_part(part).map_err(DBError::Io))?;
+            w.end_record().map_err(DBError::Io)?;
+            w.flush().map_err(DBError::Io)?;
+        }
+
+        self.publish_pending(end_seq);
+        if sync {
+            let mut g = self.sync_mu.lock().unwrap();
+            while self.synced_seq.load(Ordering::Acquire) < end_seq {
+                g = self.sync_cv.wait(g).unwrap();
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     fn publish_pending(&self, end_seq: u64) {
         // pending_seq = max(pending_seq, end_seq)
@@ -144,6 +198,43 @@ impl WalManager {
         }
     }
 
+    /// 开着的 fd：writer，加上归档的索引文件
+    pub fn open_files(&self) -> usize {
+        1 + self.archive.is_some() as usize
+    }
+
+    /// Path of the log file being written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 把 WAL 里已经写下的记录拷到 `dest`（flush 后拷，拷的时候没人能追加），返回字节数
+    pub fn copy_to(&self, dest: &Path) -> Result<u64, DBError> {
+        let mut w = self.writer.lock().unwrap();
+        w.flush().map_err(DBError::Io)?;
+        let n = std::fs::copy(&self.path, dest).map_err(DBError::Io)?;
+        File::open(dest).and_then(|f| f.sync_all()).map_err(DBError::Io)?;
+        Ok(n)
+    }
+
+    /// 清空 WAL：里面的记录都已经落到 SST、并在 MANIFEST 里记过了；开了归档的先拷一份
+    pub fn truncate(&self) -> Result<(), DBError> {
+        let mut w = self.writer.lock().unwrap();
+        w.flush().map_err(DBError::Io)?;
+        if let Some(archive) = &self.archive {
+            archive.archive(&self.path)?;
+        }
+
+        let f = OpenOptions::new().write(true).open(&self.path).map_err(DBError::Io)?;
+        f.set_len(0).map_err(DBError::Io)?;
+        f.sync_all().map_err(DBError::Io)?;
+
+        // 换一个新的 writer，block_offset 从 0 开始
+        let f = OpenOptions::new().create(true).append(true).open(&self.path).map_err(DBError::Io)?;
+        *w = WalWriter::new(BufWriter::new(f));
+        Ok(())
+    }
+
     pub fn replay<F>(&self, mut f: F) -> Result<(),DBError>
     where
         F: FnMut(Vec<u8>) -> Result<(), DBError>,
@@ -162,8 +253,8 @@ impl WalManager {
         F: FnMut(SequenceNumber, WriteBatch) -> Result<(), DBError>,
     {
         self.replay(|payload| {
-            let (base_seq, batch) = decode_write_batch(&payload)?;
-            apply(base_seq, batch)
+            let batch = WriteBatch::from_data(payload)?;
+            apply(batch.sequence(), batch)
         })
     }
 }
@@ -10,6 +10,10 @@ pub struct WalReader<R: Read> {
     block: [u8; BLOCK_SIZE],
     block_len: usize,
     block_pos: usize,
+    /// 当前 block 在文件里的起始偏移
+    block_offset: u64,
+    /// 接着上次的位置读时，第一个 block 里要跳过的字节
+    skip: usize,
 
     assembling: Vec<u8>,
     assembling_active: bool,
@@ -22,11 +26,28 @@ impl<R: Read> WalReader<R> {
             block: [0u8; BLOCK_SIZE],
             block_len: 0,
             block_pos: 0,
+            block_offset: 0,
+            skip: 0,
             assembling: Vec::new(),
             assembling_active: false,
         }
     }
 
+    /// Resumes reading at `offset`, a value previously returned by
+    /// [`offset`](Self::offset). `r` must already be positioned at the start
+    /// of the block containing `offset`.
+    pub fn resume_at(r: R, offset: u64) -> Self {
+        let mut reader = Self::new(r);
+        reader.block_offset = offset - offset % BLOCK_SIZE as u64;
+        reader.skip = (offset % BLOCK_SIZE as u64) as usize;
+        reader
+    }
+
+    /// The file offset just past the last record returned by `next_record`.
+    pub fn offset(&self) -> u64 {
+        self.block_offset + self.block_pos.max(self.skip) as u64
+    }
+
     /// 读取下一条完整 record 的 payload（已拼接 FIRST/MIDDLE/LAST）
     pub fn next_record(&mut self) -> WalReadResult<Option<Vec<u8>>> {
         loop {
@@ -119,7 +140,13 @@ impl<R: Read> WalReader<R> {
         }
     }
 
+    /// 读到一半的分片 record：文件还在被写时，尾部的 record 可能还没写完
+    pub fn in_fragmented_record(&self) -> bool {
+        self.assembling_active
+    }
+
     fn read_next_block(&mut self) -> WalReadResult<bool> {
+        self.block_offset += self.block_len as u64;
         self.block_pos = 0;
         self.block_len = 0;
 
@@ -130,12 +157,12 @@ impl<R: Read> WalReader<R> {
                 .map_err(|_| DBError::Corruption("read error".to_string()))?;
             if n == 0 { break; }
             off += n;
-            // 小优化：如果底层是文件，read 往往一次就能读很多；不强求读满
-            if off > 0 && off < BLOCK_SIZE { break; }
         }
 
+        // 只有最后一个 block 会不满；block 没读满时后面的 offset 就不再是 block 对齐的了
         self.block_len = off;
-        Ok(self.block_len > 0)
+        self.block_pos = std::mem::take(&mut self.skip).min(off);
+        Ok(self.block_pos < self.block_len)
     }
 
     fn skip_rest_of_block(&mut self) {
@@ -4,11 +4,18 @@ use crate::engine::wal::format::{BLOCK_SIZE, HEADER_SIZE, RecordType, record_crc
 pub struct WalWriter<W: Write> {
     w: W,
     block_offset: usize,
+    /// 分段写的 record：还没写出去的数据，和是否还没写过 fragment
+    stream: Option<(Vec<u8>, bool)>,
 }
 
 impl<W: Write> WalWriter<W> {
     pub fn new(w: W) -> Self {
-        Self { w, block_offset: 0 }
+        Self { w, block_offset: 0, stream: None }
+    }
+
+    /// 接着已经有 `written` 字节的文件往后写（`w` 须是 append 打开的）
+    pub fn new_at(w: W, written: u64) -> Self {
+        Self { w, block_offset: (written % BLOCK_SIZE as u64) as usize, stream: None }
     }
 
     pub fn into_inner(self) -> W { self.w }
@@ -46,6 +53,46 @@ impl<W: Write> WalWriter<W> {
         Ok(())
     }
 
+    /// 分段写一条逻辑 record：`begin_record`，若干次 `append_part`，最后 `end_record`
+    ///
+    /// 手里最多攒一个 block 的数据，整条 record 不用先在内存里拼好。
+    /// 没有 `end_record` 的半条 record 读的时候会被丢掉。
+    pub fn begin_record(&mut self) {
+        self.stream = Some((Vec::new(), true));
+    }
+
+    pub fn append_part(&mut self, part: &[u8]) -> io::Result<()> {
+        let (mut pending, mut first) = self.stream.take().expect("append_part outside of a record");
+        pending.extend_from_slice(part);
+        // 后面肯定还有数据时才写出填满 block 的 fragment，最后一段留给 end_record 标成 Last
+        loop {
+            let avail = BLOCK_SIZE - self.block_offset;
+            if avail < HEADER_SIZE {
+                self.pad_to_block_end(avail)?;
+            }
+            let avail_payload = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            if pending.len() <= avail_payload {
+                break;
+            }
+            let typ = if first { RecordType::First } else { RecordType::Middle };
+            self.write_fragment(typ, &pending[..avail_payload])?;
+            pending.drain(..avail_payload);
+            first = false;
+        }
+        self.stream = Some((pending, first));
+        Ok(())
+    }
+
+    pub fn end_record(&mut self) -> io::Result<()> {
+        let (pending, first) = self.stream.take().expect("end_record outside of a record");
+        let avail = BLOCK_SIZE - self.block_offset;
+        if avail < HEADER_SIZE {
+            self.pad_to_block_end(avail)?;
+        }
+        let typ = if first { RecordType::Full } else { RecordType::Last };
+        self.write_fragment(typ, &pending)
+    }
+
     fn pad_to_block_end(&mut self, bytes: usize) -> io::Result<()> {
         if bytes > 0 {
             // 这里 pad 0 是 LevelDB/RocksDB 兼容做法
@@ -1,61 +1,455 @@
-use crate::engine::mem::ColumnFamilyId;
+use crate::DBError;
+use crate::engine::mem::{ColumnFamilyId, SequenceNumber};
+use crate::engine::wal::format::{read_u32, read_u8, RECORD_WRITE_BATCH};
 
-#[derive(Debug)]
-pub enum WriteBatchEntry {
+/// rep 头：record tag(1) + base seq(8, LE) + count(4, LE)
+pub(crate) const WRITE_BATCH_HEADER: usize = 13;
+
+const TAG_PUT: u8 = 1;
+const TAG_DELETE: u8 = 2;
+const TAG_DELETE_RANGE: u8 = 3;
+const TAG_MERGE: u8 = 4;
+
+/// One record of a `WriteBatch`, borrowing its bytes from the batch's encoded
+/// representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteBatchEntry<'a> {
     Put {
         cf: ColumnFamilyId,
-        key: Vec<u8>,
-        value: Vec<u8>,
+        key: &'a [u8],
+        value: &'a [u8],
     },
     Delete {
         cf: ColumnFamilyId,
-        key: Vec<u8>,
+        key: &'a [u8],
+    },
+    /// 交给 CF 的 MergeOperator 和已有的值合并
+    Merge {
+        cf: ColumnFamilyId,
+        key: &'a [u8],
+        value: &'a [u8],
     },
+    /// 删除 [begin, end) 里的所有 key
+    DeleteRange {
+        cf: ColumnFamilyId,
+        begin: &'a [u8],
+        end: &'a [u8],
+    },
+}
+
+impl WriteBatchEntry<'_> {
+    pub fn cf(&self) -> ColumnFamilyId {
+        match self {
+            WriteBatchEntry::Put { cf, .. } => *cf,
+            WriteBatchEntry::Delete { cf, .. } => *cf,
+            WriteBatchEntry::Merge { cf, .. } => *cf,
+            WriteBatchEntry::DeleteRange { cf, .. } => *cf,
+        }
+    }
+
+    /// 用户数据字节数（key + value）
+    pub fn data_size(&self) -> usize {
+        match self {
+            WriteBatchEntry::Put { key, value, .. } => key.len() + value.len(),
+            WriteBatchEntry::Delete { key, .. } => key.len(),
+            WriteBatchEntry::Merge { key, value, .. } => key.len() + value.len(),
+            WriteBatchEntry::DeleteRange { begin, end, .. } => begin.len() + end.len(),
+        }
+    }
+}
+
+/// 客户端给 batch 打的幂等标记：同一个 client 的 request_id 递增分配，
+/// 服务端在 system CF 里给每个 client 记一个 `IdempotencyWindow`，重试时去重。
+/// 并发的请求可以乱序到达，只要不比见过的最大 id 落后 `IDEMPOTENCY_WINDOW` 以上
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdempotencyToken {
+    pub client_id: u64,
+    pub request_id: u64,
+}
+
+const IDEMPOTENCY_KEY_PREFIX: &[u8] = b"__idempotency__/";
+
+impl IdempotencyToken {
+    /// system CF 里记录该 client 最后 request_id 的 key
+    pub fn record_key(&self) -> Vec<u8> {
+        let mut k = Vec::with_capacity(IDEMPOTENCY_KEY_PREFIX.len() + 8);
+        k.extend_from_slice(IDEMPOTENCY_KEY_PREFIX);
+        k.extend_from_slice(&self.client_id.to_be_bytes());
+        k
+    }
+}
+
+/// 最大 request_id 之前还逐个记着是否应用过的 id 个数
+pub const IDEMPOTENCY_WINDOW: u64 = 64;
+
+/// 一个 client 应用过的 request_id：见过的最大 id，加上它前面 `IDEMPOTENCY_WINDOW`
+/// 个 id 的位图（第 i 位是 `highest - 1 - i`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IdempotencyWindow {
+    highest: u64,
+    seen_below: u64,
+}
+
+impl IdempotencyWindow {
+    pub(crate) fn new(request_id: u64) -> Self {
+        Self { highest: request_id, seen_below: 0 }
+    }
+
+    /// 旧版本只记了 8 字节的最大 id，当时更小的 id 一律算应用过
+    pub(crate) fn decode(v: &[u8]) -> Option<Self> {
+        match v.len() {
+            8 => Some(Self { highest: u64::from_le_bytes(v.try_into().unwrap()), seen_below: u64::MAX }),
+            16 => Some(Self {
+                highest: u64::from_le_bytes(v[..8].try_into().unwrap()),
+                seen_below: u64::from_le_bytes(v[8..].try_into().unwrap()),
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(&self) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        out[..8].copy_from_slice(&self.highest.to_le_bytes());
+        out[8..].copy_from_slice(&self.seen_below.to_le_bytes());
+        out
+    }
+
+    /// 应用过没有；比窗口还旧的判断不了，返回 None
+    pub(crate) fn contains(&self, request_id: u64) -> Option<bool> {
+        if request_id >= self.highest {
+            return Some(request_id == self.highest);
+        }
+        let d = self.highest - request_id;
+        (d <= IDEMPOTENCY_WINDOW).then(|| self.seen_below & (1 << (d - 1)) != 0)
+    }
+
+    pub(crate) fn insert(&mut self, request_id: u64) {
+        if request_id > self.highest {
+            let shift = request_id - self.highest;
+            let kept = if shift >= 64 { 0 } else { self.seen_below << shift };
+            // 原来的最大 id 挪到位图里
+            self.seen_below = if shift > IDEMPOTENCY_WINDOW { 0 } else { kept | 1 << (shift - 1) };
+            self.highest = request_id;
+        } else if request_id < self.highest {
+            let d = self.highest - request_id;
+            if d <= IDEMPOTENCY_WINDOW {
+                self.seen_below |= 1 << (d - 1);
+            }
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+/// 一个 batch 只有一份编码：`rep` 就是 WAL record 的 payload（头 + 逐条记录），
+/// 写 WAL 时原样写出去，写 memtable 时直接在 `rep` 上迭代，中间不再拷贝 / 重新编码。
+///
+/// 每条记录：tag(1) + cf(4, LE) + key_len(4, LE) + key [+ value_len(4, LE) + value]，
+/// DeleteRange 的 key / value 是 begin / end。
+#[derive(Debug, Clone)]
 pub struct WriteBatch {
-    pub entries: Vec<WriteBatchEntry>,
+    rep: Vec<u8>,
     pub involved_cfs: Vec<ColumnFamilyId>,
+    pub idempotency: Option<IdempotencyToken>,
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WriteBatch {
     pub fn new() -> Self {
-        Self { entries: Vec::new(),
+        let mut rep = Vec::with_capacity(WRITE_BATCH_HEADER);
+        rep.push(RECORD_WRITE_BATCH);
+        rep.resize(WRITE_BATCH_HEADER, 0);
+        Self { rep,
             involved_cfs: Vec::new(),
+            idempotency: None,
         }
     }
 
-    pub fn put(&mut self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) {
+    /// 从 WAL payload 构造，不拷贝；每条记录都要能完整解出来
+    pub fn from_data(rep: Vec<u8>) -> Result<Self, DBError> {
+        let mut pos = 0;
+        let tag = read_u8(&rep, &mut pos)?;
+        if tag != RECORD_WRITE_BATCH {
+            return Err(DBError::Corruption(format!("unknown record tag: {}", tag)));
+        }
+        if rep.len() < WRITE_BATCH_HEADER {
+            return Err(DBError::Corruption("unexpected eof".into()));
+        }
+        let mut batch = Self { rep, involved_cfs: Vec::new(), idempotency: None };
+        let mut pos = WRITE_BATCH_HEADER;
+        for _ in 0..batch.len() {
+            let entry = decode_entry(&batch.rep, &mut pos)?;
+            if !batch.involved_cfs.contains(&entry.cf()) {
+                batch.involved_cfs.push(entry.cf());
+            }
+        }
+        if pos != batch.rep.len() {
+            return Err(DBError::Corruption(format!("{} trailing bytes in write batch", batch.rep.len() - pos)));
+        }
+        Ok(batch)
+    }
+
+    /// 编码后的 batch，就是写进 WAL 的 payload
+    pub fn data(&self) -> &[u8] {
+        &self.rep
+    }
+
+    /// header 之后的记录部分；和 `encode_header` 拼起来就是一个 batch 的编码
+    pub(crate) fn entries_data(&self) -> &[u8] {
+        &self.rep[WRITE_BATCH_HEADER..]
+    }
+
+    /// 从 `seq` 开始、有 `count` 条记录的 batch 的 header，分段写大 batch 时用
+    pub(crate) fn encode_header(seq: SequenceNumber, count: u32) -> [u8; WRITE_BATCH_HEADER] {
+        let mut header = [0u8; WRITE_BATCH_HEADER];
+        header[0] = RECORD_WRITE_BATCH;
+        header[1..9].copy_from_slice(&seq.to_le_bytes());
+        header[9..13].copy_from_slice(&count.to_le_bytes());
+        header
+    }
+
+    pub fn sequence(&self) -> SequenceNumber {
+        SequenceNumber::from_le_bytes(self.rep[1..9].try_into().unwrap())
+    }
+
+    /// 写 WAL 前填上分到的起始 seq
+    pub fn set_sequence(&mut self, seq: SequenceNumber) {
+        self.rep[1..9].copy_from_slice(&seq.to_le_bytes());
+    }
+
+    fn set_count(&mut self, count: u32) {
+        self.rep[9..13].copy_from_slice(&count.to_le_bytes());
+    }
+
+    /// 标记这个 batch 来自 (client_id, request_id)，重复提交只会应用一次。
+    /// 比这个 client 见过的最大 request_id 落后超过 `IDEMPOTENCY_WINDOW` 的写会被拒绝
+    pub fn set_idempotency_token(&mut self, client_id: u64, request_id: u64) {
+        self.idempotency = Some(IdempotencyToken { client_id, request_id });
+    }
+
+    fn push_record(&mut self, tag: u8, cf: ColumnFamilyId, key: &[u8], value: Option<&[u8]>) {
         if !self.involved_cfs.contains(&cf) {
             self.involved_cfs.push(cf);
         }
-        self.entries.push(WriteBatchEntry::Put {
-            cf,
-            key: key.to_vec(),
-            value: value.to_vec(),
-        });
+        self.rep.push(tag);
+        self.rep.extend_from_slice(&cf.to_le_bytes());
+        self.rep.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.rep.extend_from_slice(key);
+        if let Some(value) = value {
+            self.rep.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            self.rep.extend_from_slice(value);
+        }
+        let count = self.len() as u32 + 1;
+        self.set_count(count);
+    }
+
+    pub fn put(&mut self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) {
+        self.push_record(TAG_PUT, cf, key, Some(value));
     }
 
     pub fn delete(&mut self, cf: ColumnFamilyId, key: &[u8]) {
-        if !self.involved_cfs.contains(&cf) {
-            self.involved_cfs.push(cf);
+        self.push_record(TAG_DELETE, cf, key, None);
+    }
+
+    /// 写一个 merge operand，读 / compaction 时由 CF 的 MergeOperator 合并
+    pub fn merge(&mut self, cf: ColumnFamilyId, key: &[u8], value: &[u8]) {
+        self.push_record(TAG_MERGE, cf, key, Some(value));
+    }
+
+    /// 删除 [begin, end) 里的所有 key，只占一条 WAL / memtable 记录
+    pub fn delete_range(&mut self, cf: ColumnFamilyId, begin: &[u8], end: &[u8]) {
+        self.push_record(TAG_DELETE_RANGE, cf, begin, Some(end));
+    }
+
+    /// 把 `other` 的记录接到后面（写组 leader 拼 batch 用），幂等 token 不合并
+    pub fn append(&mut self, other: WriteBatch) {
+        for cf in &other.involved_cfs {
+            if !self.involved_cfs.contains(cf) {
+                self.involved_cfs.push(*cf);
+            }
         }
-        self.entries.push(WriteBatchEntry::Delete {
-            cf,
-            key: key.to_vec(),
-        });
+        let count = (self.len() + other.len()) as u32;
+        self.rep.extend_from_slice(&other.rep[WRITE_BATCH_HEADER..]);
+        self.set_count(count);
+    }
+
+    /// 只留前 `len` 条记录（事务回滚到 savepoint 用）
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len() {
+            return;
+        }
+        let mut iter = self.iter();
+        for _ in 0..len {
+            iter.next();
+        }
+        let offset = iter.pos;
+        self.rep.truncate(offset);
+        self.set_count(len as u32);
+        self.rebuild_involved_cfs();
+    }
+
+    /// 按记录重算 involved_cfs
+    pub fn rebuild_involved_cfs(&mut self) {
+        let mut cfs = Vec::new();
+        for entry in self.iter() {
+            if !cfs.contains(&entry.cf()) {
+                cfs.push(entry.cf());
+            }
+        }
+        self.involved_cfs = cfs;
+    }
+
+    /// 按写入顺序遍历记录，key / value 直接借用 `rep`
+    pub fn iter(&self) -> WriteBatchIter<'_> {
+        WriteBatchIter { rep: &self.rep, pos: WRITE_BATCH_HEADER, remaining: self.len() }
+    }
+
+    /// 所有记录的 key + value 字节数
+    pub fn data_size(&self) -> usize {
+        self.iter().map(|e| e.data_size()).sum()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.entries.is_empty()
+        self.len() == 0
     }
 
     pub fn len(&self) -> usize {
-        self.entries.len()
+        u32::from_le_bytes(self.rep[9..13].try_into().unwrap()) as usize
     }
 
     pub fn involved_cfs(&self) -> &[ColumnFamilyId] {
         &self.involved_cfs
     }
 }
+
+impl<'a> IntoIterator for &'a WriteBatch {
+    type Item = WriteBatchEntry<'a>;
+    type IntoIter = WriteBatchIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterates the records of a `WriteBatch` in write order.
+pub struct WriteBatchIter<'a> {
+    rep: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for WriteBatchIter<'a> {
+    type Item = WriteBatchEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // rep 只能由 put / delete / ... 或校验过的 from_data 产生，这里不会解不出来
+        Some(decode_entry(self.rep, &mut self.pos).expect("write batch rep is validated"))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for WriteBatchIter<'_> {}
+
+fn read_slice<'a>(rep: &'a [u8], pos: &mut usize) -> Result<&'a [u8], DBError> {
+    let len = read_u32(rep, pos)? as usize;
+    let out = rep.get(*pos..*pos + len).ok_or_else(|| DBError::Corruption("unexpected eof".into()))?;
+    *pos += len;
+    Ok(out)
+}
+
+fn decode_entry<'a>(rep: &'a [u8], pos: &mut usize) -> Result<WriteBatchEntry<'a>, DBError> {
+    let tag = read_u8(rep, pos)?;
+    let cf: ColumnFamilyId = read_u32(rep, pos)?;
+    let key = read_slice(rep, pos)?;
+    Ok(match tag {
+        TAG_PUT => WriteBatchEntry::Put { cf, key, value: read_slice(rep, pos)? },
+        TAG_DELETE => WriteBatchEntry::Delete { cf, key },
+        TAG_MERGE => WriteBatchEntry::Merge { cf, key, value: read_slice(rep, pos)? },
+        TAG_DELETE_RANGE => WriteBatchEntry::DeleteRange { cf, begin: key, end: read_slice(rep, pos)? },
+        other => return Err(DBError::Corruption(format!("unknown entry tag: {}", other))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::wal::{decode_write_batch, encode_write_batch};
+
+    fn sample() -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        batch.put(1, b"k1", b"v1");
+        batch.delete(2, b"k2");
+        batch.merge(1, b"k3", b"+1");
+        batch.delete_range(3, b"a", b"m");
+        batch
+    }
+
+    #[test]
+    fn records_round_trip_through_the_encoded_rep() {
+        let mut batch = sample();
+        batch.set_sequence(42);
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.involved_cfs(), &[1, 2, 3]);
+        assert_eq!(batch.data_size(), 4 + 2 + 4 + 2);
+
+        // WAL 里写的就是 rep，读回来不用重新编码
+        let decoded = WriteBatch::from_data(batch.data().to_vec()).unwrap();
+        assert_eq!(decoded.sequence(), 42);
+        assert_eq!(decoded.involved_cfs(), batch.involved_cfs());
+        let entries: Vec<_> = decoded.iter().collect();
+        assert_eq!(entries, vec![
+            WriteBatchEntry::Put { cf: 1, key: b"k1", value: b"v1" },
+            WriteBatchEntry::Delete { cf: 2, key: b"k2" },
+            WriteBatchEntry::Merge { cf: 1, key: b"k3", value: b"+1" },
+            WriteBatchEntry::DeleteRange { cf: 3, begin: b"a", end: b"m" },
+        ]);
+
+        let (seq, via_format) = decode_write_batch(&encode_write_batch(7, &batch)).unwrap();
+        assert_eq!(seq, 7);
+        assert_eq!(via_format.entries_data(), batch.entries_data());
+
+        // 分段写大 batch：header 和记录拼起来就是一个 batch
+        let mut split = WriteBatch::encode_header(42, 4).to_vec();
+        split.extend_from_slice(batch.entries_data());
+        assert_eq!(split, batch.data());
+    }
+
+    #[test]
+    fn append_and_truncate_keep_count_and_cfs_in_step() {
+        let mut batch = WriteBatch::new();
+        batch.put(5, b"x", b"1");
+        batch.append(sample());
+        assert_eq!(batch.len(), 5);
+        assert_eq!(batch.involved_cfs(), &[5, 1, 2, 3]);
+
+        batch.truncate(2);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.involved_cfs(), &[5, 1]);
+        assert_eq!(WriteBatch::from_data(batch.data().to_vec()).unwrap().len(), 2);
+        batch.truncate(10);
+        assert_eq!(batch.len(), 2);
+    }
+
+    #[test]
+    fn malformed_reps_are_rejected() {
+        let rep = sample().data().to_vec();
+        assert!(matches!(WriteBatch::from_data(rep[..rep.len() - 1].to_vec()), Err(DBError::Corruption(_))));
+        let mut trailing = rep.clone();
+        trailing.push(0);
+        assert!(matches!(WriteBatch::from_data(trailing), Err(DBError::Corruption(_))));
+        let mut bad_tag = rep.clone();
+        bad_tag[WRITE_BATCH_HEADER] = 0xee;
+        assert!(matches!(WriteBatch::from_data(bad_tag), Err(DBError::Corruption(_))));
+        assert!(matches!(WriteBatch::from_data(vec![RECORD_WRITE_BATCH, 0, 0]), Err(DBError::Corruption(_))));
+    }
+}
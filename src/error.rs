@@ -12,9 +12,62 @@ pub enum DBError {
     UnknownColumnFamily(String),
     NotFound(String),
     InvalidColumnFamily(String),
+    /// A fencing token was rejected: this node hasn't seen the token's term
+    /// (or was deposed), or hasn't applied up to it.
+    Fenced(String),
+    /// Temporarily rejected (e.g. quota exhausted); the caller may retry later.
+    Busy(String),
+    /// Writes are stopped until flush / compaction catch up; retry after the hint.
+    WriteStall { retry_after_ms: u64, reason: String },
+    /// An iterator outlived `max_iterator_age_secs` and was invalidated.
+    Expired(String),
+    /// The DB directory is not in the state the open asked for: missing
+    /// without `create_if_missing`, existing with `error_if_exists`, or only
+    /// partly there.
+    InvalidDBState(String),
     Other(String),
 }
 
+impl DBError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DBError::Busy(_) | DBError::WriteStall { .. })
+    }
+
+    /// How long the caller should back off before retrying, when the DB knows.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            DBError::WriteStall { retry_after_ms, .. } => Some(std::time::Duration::from_millis(*retry_after_ms)),
+            _ => None,
+        }
+    }
+}
+
+/// `io::Error` 和 `ConfigError` 不能 clone，复制出来的只保留 kind 和消息；
+/// 其余变体原样复制，`WriteStall` / `Busy` 传给 write group 的每个写者后照样能重试
+impl Clone for DBError {
+    fn clone(&self) -> Self {
+        match self {
+            DBError::Io(e) => DBError::Io(io::Error::new(e.kind(), e.to_string())),
+            DBError::Config(e) => DBError::Config(ConfigError::Message(e.to_string())),
+            DBError::InvalidKeyOrder(s) => DBError::InvalidKeyOrder(s.clone()),
+            DBError::EmptyTable(s) => DBError::EmptyTable(s.clone()),
+            DBError::Corruption(s) => DBError::Corruption(s.clone()),
+            DBError::InvalidArgument(s) => DBError::InvalidArgument(s.clone()),
+            DBError::UnknownColumnFamily(s) => DBError::UnknownColumnFamily(s.clone()),
+            DBError::NotFound(s) => DBError::NotFound(s.clone()),
+            DBError::InvalidColumnFamily(s) => DBError::InvalidColumnFamily(s.clone()),
+            DBError::Fenced(s) => DBError::Fenced(s.clone()),
+            DBError::Busy(s) => DBError::Busy(s.clone()),
+            DBError::WriteStall { retry_after_ms, reason } => {
+                DBError::WriteStall { retry_after_ms: *retry_after_ms, reason: reason.clone() }
+            }
+            DBError::Expired(s) => DBError::Expired(s.clone()),
+            DBError::InvalidDBState(s) => DBError::InvalidDBState(s.clone()),
+            DBError::Other(s) => DBError::Other(s.clone()),
+        }
+    }
+}
+
 impl From<std::io::Error> for DBError {
     fn from(e: std::io::Error) -> Self {
         DBError::Io(e)
@@ -3,7 +3,9 @@ pub mod engine;
 pub mod network;
 pub mod util;
 pub mod error;
+pub mod vector;
 
 pub use crate::db::db_trait::{DB};
 pub use crate::db::db_impl::DBImpl;
+pub use crate::db::fencing::FencingToken;
 pub use crate::error::DBError;
@@ -0,0 +1,462 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::MissedTickBehavior;
+use crate::DBError;
+use crate::network::resp;
+
+/// 每个 server 在环上放多少个虚拟节点，节点增减时迁移的 key 更均匀
+const VIRTUAL_NODES_PER_SERVER: usize = 160;
+
+/// 一致性哈希环：hash -> server 地址
+#[derive(Debug, Default, Clone)]
+pub struct HashRing {
+    ring: BTreeMap<u32, String>,
+    nodes: Vec<String>,
+}
+
+impl HashRing {
+    pub fn new(nodes: &[String]) -> Self {
+        let mut ring = BTreeMap::new();
+        for node in nodes {
+            for i in 0..VIRTUAL_NODES_PER_SERVER {
+                ring.insert(ring_hash(format!("{}#{}", node, i).as_bytes()), node.clone());
+            }
+        }
+        Self { ring, nodes: nodes.to_vec() }
+    }
+
+    /// 顺时针找第一个 >= hash(key) 的虚拟节点，绕回环头
+    pub fn node_for(&self, key: &[u8]) -> Option<&str> {
+        let h = ring_hash(key);
+        self.ring
+            .range(h..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+}
+
+fn ring_hash(data: &[u8]) -> u32 {
+    crc32c::crc32c(data)
+}
+
+/// server 的一条回复
+enum Reply {
+    Status(String),
+    Bulk(Option<String>),
+    Array(Vec<Option<String>>),
+}
+
+/// 到单个 server 的连接；命令按 RESP 数组发（见 `network::resp`），key、value 不用转义
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn connect(addr: &str) -> Result<Self, DBError> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self { stream: BufReader::new(stream) })
+    }
+
+    async fn request(&mut self, args: &[&[u8]]) -> Result<Reply, DBError> {
+        self.stream.get_mut().write_all(&resp::encode_array(args)).await?;
+        let line = self.read_line().await?;
+        match line.as_bytes().first() {
+            Some(b'+') => Ok(Reply::Status(line[1..].to_string())),
+            Some(b'*') => {
+                let n: usize = line[1..]
+                    .parse()
+                    .map_err(|_| DBError::Corruption(format!("bad array length: {}", line)))?;
+                let mut items = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let item = self.read_line().await?;
+                    items.push(self.read_bulk(&item).await?);
+                }
+                Ok(Reply::Array(items))
+            }
+            _ => Ok(Reply::Bulk(self.read_bulk(&line).await?)),
+        }
+    }
+
+    /// 一行回复，去掉结尾的 "\r\n"
+    async fn read_line(&mut self) -> Result<String, DBError> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err(DBError::Other("connection closed by server".into()));
+        }
+        line.truncate(line.trim_end().len());
+        Ok(line)
+    }
+
+    /// `line` 是 `$<len>` 头（或者错误）；读出后面的 value
+    async fn read_bulk(&mut self, line: &str) -> Result<Option<String>, DBError> {
+        match line.as_bytes().first() {
+            Some(b'-') => Err(resp::parse_error(&line[1..])),
+            Some(b'$') => {
+                let len: i64 = line[1..]
+                    .parse()
+                    .map_err(|_| DBError::Corruption(format!("bad bulk length: {}", line)))?;
+                if len < 0 {
+                    return Ok(None);
+                }
+                // value + "\r\n"
+                let mut buf = vec![0u8; len as usize + 2];
+                self.stream.read_exact(&mut buf).await?;
+                buf.truncate(len as usize);
+                let v = String::from_utf8(buf)
+                    .map_err(|_| DBError::Corruption("non utf-8 value".into()))?;
+                Ok(Some(v))
+            }
+            _ => Err(DBError::Corruption(format!("unexpected reply: {}", line))),
+        }
+    }
+
+    async fn get(&mut self, key: &str) -> Result<Option<String>, DBError> {
+        match self.request(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(v) => Ok(v),
+            _ => Err(DBError::Corruption("unexpected reply to GET".into())),
+        }
+    }
+
+    async fn set(&mut self, key: &str, value: &str) -> Result<(), DBError> {
+        match self.request(&[b"SET", key.as_bytes(), value.as_bytes()]).await? {
+            Reply::Status(_) => Ok(()),
+            _ => Err(DBError::Corruption("unexpected reply to SET".into())),
+        }
+    }
+
+    async fn scan(&mut self, start: &str, limit: usize) -> Result<Vec<(String, String)>, DBError> {
+        let limit = limit.to_string();
+        let items = match self.request(&[b"SCAN", start.as_bytes(), limit.as_bytes()]).await? {
+            Reply::Array(items) if items.len() % 2 == 0 => items,
+            _ => return Err(DBError::Corruption("unexpected reply to SCAN".into())),
+        };
+        let mut items = items.into_iter();
+        let mut out = Vec::with_capacity(items.len() / 2);
+        while let (Some(Some(k)), Some(Some(v))) = (items.next(), items.next()) {
+            out.push((k, v));
+        }
+        Ok(out)
+    }
+}
+
+type SharedConnection = Arc<Mutex<Connection>>;
+
+/// 集群客户端：一致性哈希把 key 分到多个 vectorkv server，
+/// multi_get / scan 按 server 分组并发请求，结果按 key 排序合并
+pub struct ClusterClient {
+    ring: RwLock<HashRing>,
+    conns: Mutex<HashMap<String, SharedConnection>>,
+}
+
+impl ClusterClient {
+    pub fn new(nodes: Vec<String>) -> Self {
+        Self {
+            ring: RwLock::new(HashRing::new(&nodes)),
+            conns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 拓扑变化（扩缩容 / 节点替换）时调用：重建哈希环，关掉已移除节点的连接
+    pub async fn update_topology(&self, nodes: Vec<String>) {
+        *self.ring.write().unwrap() = HashRing::new(&nodes);
+        self.conns.lock().await.retain(|addr, _| nodes.contains(addr));
+    }
+
+    /// Keeps the topology current without the caller pushing it: every
+    /// `interval`, `fetch` (run on the blocking pool, so it may read a file or
+    /// ask a config service) returns the node list, and the ring is rebuilt
+    /// when it changed. A failed fetch keeps the current ring. The task ends
+    /// once the client is dropped.
+    pub fn spawn_topology_refresh<F>(self: &Arc<Self>, interval: Duration, fetch: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Result<Vec<String>, DBError> + Send + Sync + 'static,
+    {
+        let client = Arc::downgrade(self);
+        let fetch = Arc::new(fetch);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let fetch = fetch.clone();
+                let nodes = match tokio::task::spawn_blocking(move || fetch()).await {
+                    Ok(Ok(nodes)) => nodes,
+                    Ok(Err(e)) => {
+                        log::warn!("cluster topology refresh failed: {:?}", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        log::warn!("cluster topology refresh panicked: {}", e);
+                        continue;
+                    }
+                };
+                let Some(client) = client.upgrade() else { return };
+                if client.nodes() != nodes {
+                    log::info!("cluster topology changed to {:?}", nodes);
+                    client.update_topology(nodes).await;
+                }
+            }
+        })
+    }
+
+    pub fn nodes(&self) -> Vec<String> {
+        self.ring.read().unwrap().nodes().to_vec()
+    }
+
+    fn route(&self, key: &str) -> Result<String, DBError> {
+        self.ring
+            .read()
+            .unwrap()
+            .node_for(key.as_bytes())
+            .map(|n| n.to_string())
+            .ok_or_else(|| DBError::Other("cluster has no nodes".into()))
+    }
+
+    /// 连的时候不拿着 `conns`，一个慢节点不会卡住发往别的节点的请求；
+    /// 两个请求同时连同一个节点时留先放进去的那条
+    async fn connection(&self, addr: &str) -> Result<SharedConnection, DBError> {
+        if let Some(c) = self.conns.lock().await.get(addr) {
+            return Ok(c.clone());
+        }
+        let c = Arc::new(Mutex::new(Connection::connect(addr).await?));
+        Ok(self.conns.lock().await.entry(addr.to_string()).or_insert(c).clone())
+    }
+
+    /// IO 出错的连接丢掉，下次请求重连
+    async fn drop_connection(&self, addr: &str) {
+        self.conns.lock().await.remove(addr);
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, DBError> {
+        let addr = self.route(key)?;
+        let conn = self.connection(&addr).await?;
+        let res = conn.lock().await.get(key).await;
+        if matches!(res, Err(DBError::Io(_))) {
+            self.drop_connection(&addr).await;
+        }
+        res
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> Result<(), DBError> {
+        let addr = self.route(key)?;
+        let conn = self.connection(&addr).await?;
+        let res = conn.lock().await.set(key, value).await;
+        if matches!(res, Err(DBError::Io(_))) {
+            self.drop_connection(&addr).await;
+        }
+        res
+    }
+
+    /// 按 server 分组并发查询，返回结果按 key 升序；重复的 key 每次出现都有一条
+    pub async fn multi_get(&self, keys: &[&str]) -> Result<Vec<(String, Option<String>)>, DBError> {
+        let mut by_node: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            by_node.entry(self.route(key)?).or_default().push((i, key.to_string()));
+        }
+
+        let mut tasks = JoinSet::new();
+        for (addr, node_keys) in by_node {
+            let conn = self.connection(&addr).await?;
+            tasks.spawn(async move {
+                let mut conn = conn.lock().await;
+                let mut out = Vec::with_capacity(node_keys.len());
+                for (i, key) in node_keys {
+                    match conn.get(&key).await {
+                        Ok(v) => out.push((i, v)),
+                        Err(e) => return (addr, Err(e)),
+                    }
+                }
+                (addr, Ok(out))
+            });
+        }
+
+        let mut values = vec![None; keys.len()];
+        self.join_all(tasks, |found| {
+            for (i, v) in found {
+                values[i] = v;
+            }
+        })
+        .await?;
+        let mut merged: Vec<(String, Option<String>)> = keys.iter().map(|k| k.to_string()).zip(values).collect();
+        merged.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(merged)
+    }
+
+    /// 所有 server 上 key >= `start` 的前 `limit` 条，按 key 升序合并。
+    /// 每个 server 各取前 `limit` 条就够，合起来的前 `limit` 条一定在里面
+    pub async fn scan(&self, start: &str, limit: usize) -> Result<Vec<(String, String)>, DBError> {
+        let mut tasks = JoinSet::new();
+        for addr in self.nodes() {
+            let conn = self.connection(&addr).await?;
+            let start = start.to_string();
+            tasks.spawn(async move {
+                let res = conn.lock().await.scan(&start, limit).await;
+                (addr, res)
+            });
+        }
+
+        let mut merged = Vec::new();
+        self.join_all(tasks, |kvs| merged.extend(kvs)).await?;
+        merged.sort_by(|a, b| a.0.cmp(&b.0));
+        merged.truncate(limit);
+        Ok(merged)
+    }
+
+    /// 等所有节点的请求做完，成功的交给 `on_ok`；有失败就返回第一个错，
+    /// IO 出错的连接丢掉
+    async fn join_all<T: 'static>(
+        &self,
+        mut tasks: JoinSet<(String, Result<T, DBError>)>,
+        mut on_ok: impl FnMut(T),
+    ) -> Result<(), DBError> {
+        let mut first_err = None;
+        while let Some(joined) = tasks.join_next().await {
+            let (addr, res) = joined.map_err(|e| DBError::Other(e.to_string()))?;
+            match res {
+                Ok(v) => on_ok(v),
+                Err(e) => {
+                    if matches!(e, DBError::Io(_)) {
+                        self.drop_connection(&addr).await;
+                    }
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mem::Storage;
+    use crate::network::server::Server;
+
+    fn nodes(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("10.0.0.{}:6379", i)).collect()
+    }
+
+    #[test]
+    fn ring_spreads_keys_and_moves_few_on_growth() {
+        assert_eq!(HashRing::new(&[]).node_for(b"k"), None);
+
+        let three = HashRing::new(&nodes(3));
+        let four = HashRing::new(&nodes(4));
+        let keys: Vec<String> = (0..3000).map(|i| format!("key{}", i)).collect();
+
+        let mut per_node: HashMap<&str, usize> = HashMap::new();
+        let mut moved = 0;
+        for k in &keys {
+            let before = three.node_for(k.as_bytes()).unwrap();
+            assert_eq!(three.node_for(k.as_bytes()), Some(before));
+            *per_node.entry(before).or_default() += 1;
+            let after = four.node_for(k.as_bytes()).unwrap();
+            if after != before {
+                // 只会挪到新节点上
+                assert_eq!(after, "10.0.0.3:6379");
+                moved += 1;
+            }
+        }
+        assert_eq!(per_node.len(), 3);
+        assert!(per_node.values().all(|&n| n > 600));
+        // 大约 1/4 的 key 挪走
+        assert!(moved > 400 && moved < 1200, "{}", moved);
+        assert_eq!(four.nodes().len(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn routes_keys_and_merges_multi_get() {
+        let mut servers = Vec::new();
+        for _ in 0..3 {
+            let storage = Arc::new(Mutex::new(Storage::new()));
+            servers.push(Server::start("127.0.0.1:0", storage, None).await.unwrap());
+        }
+        let addrs: Vec<String> = servers.iter().map(|s| s.local_addr().to_string()).collect();
+        let client = ClusterClient::new(addrs[..2].to_vec());
+
+        for i in 0..20 {
+            client.set(&format!("k{:02}", i), &format!("v{}", i)).await.unwrap();
+        }
+        assert_eq!(client.get("k07").await.unwrap(), Some("v7".to_string()));
+        assert_eq!(client.get("missing").await.unwrap(), None);
+
+        let got = client.multi_get(&["k03", "k11", "k01", "nope", "k03"]).await.unwrap();
+        let keys: Vec<&str> = got.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["k01", "k03", "k03", "k11", "nope"]);
+        assert_eq!(got[1].1.as_deref(), Some("v3"));
+        assert_eq!(got[2].1.as_deref(), Some("v3"));
+        assert_eq!(got[3].1.as_deref(), Some("v11"));
+        assert_eq!(got[4].1, None);
+
+        // key、value 里的空格和换行原样存取
+        client.set("spaced key", "two words\r\nand a line").await.unwrap();
+        assert_eq!(client.get("spaced key").await.unwrap().as_deref(), Some("two words\r\nand a line"));
+
+        // scan 从每个节点取，按 key 合并
+        let scanned = client.scan("k05", 4).await.unwrap();
+        let expected: Vec<(String, String)> = (5..9).map(|i| (format!("k{:02}", i), format!("v{}", i))).collect();
+        assert_eq!(scanned, expected);
+        assert_eq!(client.scan("k18", 10).await.unwrap().len(), 2);
+
+        // 加一个空节点：分到它上面的 key 查不到了，别的不受影响
+        client.update_topology(addrs.clone()).await;
+        assert_eq!(client.nodes(), addrs);
+        let ring = HashRing::new(&addrs);
+        for i in 0..20 {
+            let key = format!("k{:02}", i);
+            let expected = (ring.node_for(key.as_bytes()) != Some(addrs[2].as_str())).then(|| format!("v{}", i));
+            assert_eq!(client.get(&key).await.unwrap(), expected);
+        }
+
+        client.update_topology(Vec::new()).await;
+        assert!(client.get("k01").await.is_err());
+        assert!(client.scan("", 10).await.unwrap().is_empty());
+        for server in &servers {
+            server.drain().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn topology_refresh_follows_the_source() {
+        let client = Arc::new(ClusterClient::new(nodes(1)));
+        let source = Arc::new(std::sync::Mutex::new(Some(nodes(1))));
+        let fetch = {
+            let source = source.clone();
+            move || source.lock().unwrap().clone().ok_or_else(|| DBError::Other("config service down".into()))
+        };
+        let task = client.spawn_topology_refresh(Duration::from_millis(10), fetch);
+
+        async fn wait_for(client: &ClusterClient, want: Vec<String>) {
+            for _ in 0..200 {
+                if client.nodes() == want {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("topology stayed at {:?}", client.nodes());
+        }
+
+        *source.lock().unwrap() = Some(nodes(3));
+        wait_for(&client, nodes(3)).await;
+        // 拉取失败时保留现在的拓扑
+        *source.lock().unwrap() = None;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.nodes(), nodes(3));
+        *source.lock().unwrap() = Some(nodes(2));
+        wait_for(&client, nodes(2)).await;
+
+        // client 没了任务就退出
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,623 @@
+//! HTTP/JSON API for debugging and light integrations.
+//!
+//! Column families can be named or given by id; keys are percent-decoded
+//! path segments and values are raw request/response bodies.
+//!
+//! ```text
+//! GET    /kv/<cf>/<key>          200 value | 404
+//! PUT    /kv/<cf>/<key>          204, body is the value
+//! DELETE /kv/<cf>/<key>          204
+//! POST   /flush?cf=<cf>          202, flush runs in the background
+//! POST   /compact?cf=<cf>[&begin=<key>][&end=<key>]   202
+//! GET    /stats                  200 JSON
+//! GET    /cf                     200 JSON list of {id, name}
+//! PUT    /cf/<name>              201 JSON {id}, default column family options
+//! DELETE /cf/<name>              204
+//! POST   /knn/<cf>               200 JSON, body is a JSON search request
+//! ```
+//!
+//! A `/knn` request carries the same options as the `KNN` command of the text
+//! protocol (see `vector_api`); only `vector` and `k` are required:
+//!
+//! ```text
+//! {"vector": [0.1, 0.2], "k": 10, "metric": "cosine", "threshold": 0.5,
+//!  "filter": "prefix:doc:", "page": "<token>", "payload": true, "index": "img",
+//!  "ef": 64, "nprobe": 8, "exhaustive": false, "fallback": true}
+//! ```
+//!
+//! and is answered with `{"hits": [{"key", "score", "payload"}], "next_page"}`;
+//! keys and payloads are decoded as UTF-8 (lossily), `next_page` is null on
+//! the last page.
+//!
+//! Errors are `{"error": "..."}` with a status from `status_of`; write stalls
+//! are 503 with `Retry-After`, rejected quotas 429. Only what a debugging
+//! client needs of HTTP/1.1 is understood: `Content-Length` bodies (no
+//! chunked encoding) and keep-alive.
+//!
+//! The server listens on loopback unless told otherwise. When
+//! `HttpOptions::auth_token` is set every request, reads included, needs
+//! `Authorization: Bearer <token>`, and a non-loopback address is refused
+//! without one. There is no TLS: put a TLS-terminating proxy in
+//! front of a server reachable from other hosts.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use crate::{DBImpl, DB};
+use crate::engine::mem::ColumnFamilyId;
+use crate::error::DBError;
+use crate::util::{ColumnFamilyOptions, ReadOptions, WriteOptions};
+use crate::vector::{KeyFilter, KnnRequest, KnnResponse, Metric};
+
+/// 请求体上限，防止一个 Content-Length 让服务端分配一大块内存
+const MAX_BODY_LEN: usize = 64 << 20;
+const MAX_HEADER_LINES: usize = 100;
+
+/// Settings for `HttpServer`.
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// Address to listen on; loopback by default.
+    pub addr: String,
+    /// Bearer token every request must carry. Must be set to listen on a
+    /// non-loopback address.
+    pub auth_token: Option<String>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self { addr: "127.0.0.1:8080".to_string(), auth_token: None }
+    }
+}
+
+impl HttpOptions {
+    pub fn with_addr(mut self, addr: impl Into<String>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+}
+
+pub struct HttpServer {
+    local_addr: SocketAddr,
+    shutdown_tx: watch::Sender<bool>,
+    accept_loop: tokio::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HttpServer {
+    /// Binds `options.addr` and serves `db` on the current runtime.
+    ///
+    /// Fails with `InvalidArgument` when the address is not loopback and no
+    /// `auth_token` is set.
+    pub async fn start(db: Arc<DBImpl>, options: HttpOptions) -> Result<Self, DBError> {
+        let listener = TcpListener::bind(&options.addr).await?;
+        let local_addr = listener.local_addr()?;
+        if options.auth_token.is_none() && !local_addr.ip().is_loopback() {
+            return Err(DBError::InvalidArgument(format!(
+                "HTTP server on non-loopback address {} needs an auth token",
+                local_addr
+            )));
+        }
+        let auth_token: Option<Arc<str>> = options.auth_token.map(Into::into);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let accept_loop = tokio::spawn(accept_loop(listener, shutdown_rx, db, auth_token));
+        Ok(Self { local_addr, shutdown_tx, accept_loop: tokio::sync::Mutex::new(Some(accept_loop)) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting and waits for open connections to finish the request
+    /// they are serving. Idempotent.
+    pub async fn shutdown(&self) {
+        let _ = self.shutdown_tx.send(true);
+        if let Some(handle) = self.accept_loop.lock().await.take() {
+            let _ = handle.await;
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    mut shutdown_rx: watch::Receiver<bool>,
+    db: Arc<DBImpl>,
+    auth_token: Option<Arc<str>>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((socket, _)) = accepted {
+                    connections.spawn(handle_connection(socket, Arc::clone(&db), auth_token.clone(), shutdown_rx.clone()));
+                }
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown_rx.changed() => break,
+        }
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+}
+
+struct Request {
+    method: String,
+    /// 已经按 '/' 切开并 percent-decode 过
+    path: Vec<Vec<u8>>,
+    query: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    keep_alive: bool,
+    /// `Authorization` 头，原样
+    authorization: Option<String>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&[u8]> {
+        self.query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_slice())
+    }
+
+    /// 带的 bearer token 是 `token`；逐字节比完，不因为前缀对上的长短泄露时间
+    fn has_token(&self, token: &str) -> bool {
+        let Some(given) = self.authorization.as_deref().and_then(|a| a.strip_prefix("Bearer ")) else { return false };
+        given.len() == token.len()
+            && given.bytes().zip(token.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    retry_after_secs: Option<u64>,
+}
+
+impl Response {
+    fn empty(status: u16) -> Self {
+        Self { status, content_type: "text/plain", body: Vec::new(), retry_after_secs: None }
+    }
+
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        Self { status, content_type: "application/json", body: value.to_string().into_bytes(), retry_after_secs: None }
+    }
+
+    fn error(e: &DBError) -> Self {
+        let mut resp = Self::json(status_of(e), json!({ "error": format!("{:?}", e) }));
+        // Retry-After 只能是整秒，向上取整
+        resp.retry_after_secs = e.retry_after().map(|d| d.as_millis().div_ceil(1000) as u64);
+        resp
+    }
+}
+
+/// HTTP status for a DB error.
+pub fn status_of(e: &DBError) -> u16 {
+    match e {
+        DBError::InvalidArgument(_) | DBError::InvalidKeyOrder(_) => 400,
+        DBError::NotFound(_) | DBError::UnknownColumnFamily(_) | DBError::InvalidColumnFamily(_) => 404,
+        DBError::Expired(_) => 410,
+        DBError::InvalidDBState(_) | DBError::Fenced(_) => 409,
+        DBError::Busy(_) => 429,
+        DBError::WriteStall { .. } => 503,
+        _ => 500,
+    }
+}
+
+async fn handle_connection(
+    socket: TcpStream,
+    db: Arc<DBImpl>,
+    auth_token: Option<Arc<str>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        if *shutdown.borrow() {
+            return;
+        }
+        let request = tokio::select! {
+            request = read_request(&mut reader) => request,
+            // 空闲连接直接关
+            _ = shutdown.changed() => return,
+        };
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                let _ = write_response(&mut writer, &Response::error(&e), false).await;
+                return;
+            }
+        };
+        let keep_alive = request.keep_alive;
+        // 设了 token 就所有路由都要：读接口一样能把数据拿走
+        if let Some(token) = &auth_token {
+            if !request.has_token(token) {
+                let response = Response::json(401, json!({ "error": "missing or wrong bearer token" }));
+                if write_response(&mut writer, &response, keep_alive).await.is_err() || !keep_alive {
+                    return;
+                }
+                continue;
+            }
+        }
+        let db = Arc::clone(&db);
+        // DB 调用都是阻塞的
+        let response = tokio::task::spawn_blocking(move || route(&db, &request))
+            .await
+            .unwrap_or_else(|e| Response::error(&DBError::Other(e.to_string())));
+        if write_response(&mut writer, &response, keep_alive).await.is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+/// 连接关了（还没读到请求行）返回 None
+async fn read_request<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Request>, DBError> {
+    let bad = |what: &str| DBError::InvalidArgument(format!("bad HTTP request: {}", what));
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v)) => (m.to_string(), t.to_string(), v.to_string()),
+        _ => return Err(bad("request line")),
+    };
+
+    let mut content_length = 0usize;
+    let mut authorization = None;
+    // HTTP/1.1 默认 keep-alive，1.0 默认关
+    let mut keep_alive = version == "HTTP/1.1";
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(bad("connection closed in headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else { return Err(bad("header")) };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse().map_err(|_| bad("Content-Length"))?,
+            "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+            "transfer-encoding" => return Err(bad("chunked bodies are not supported")),
+            "authorization" => authorization = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        return Err(bad("body too large"));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let path = path.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect();
+    let query = query
+        .split('&')
+        .filter(|s| !s.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (String::from_utf8_lossy(&percent_decode(k)).into_owned(), percent_decode(v))
+        })
+        .collect();
+    Ok(Some(Request { method, path, query, body, keep_alive, authorization }))
+}
+
+async fn write_response<W: tokio::io::AsyncWrite + Unpin>(
+    writer: &mut W,
+    resp: &Response,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        resp.status,
+        reason(resp.status),
+        resp.content_type,
+        resp.body.len()
+    );
+    if let Some(secs) = resp.retry_after_secs {
+        head.push_str(&format!("Retry-After: {}\r\n", secs));
+    }
+    if resp.status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str(if keep_alive { "Connection: keep-alive\r\n\r\n" } else { "Connection: close\r\n\r\n" });
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&resp.body).await?;
+    writer.flush().await
+}
+
+fn route(db: &Arc<DBImpl>, req: &Request) -> Response {
+    let segments: Vec<&[u8]> = req.path.iter().map(Vec::as_slice).collect();
+    let result = match (req.method.as_str(), segments.as_slice()) {
+        ("GET", [b"kv", cf, key]) => resolve_cf(db, cf).and_then(|cf| {
+            db.get(&ReadOptions::default(), cf, key).map(|value| match value {
+                Some(value) => Response { status: 200, content_type: "application/octet-stream", body: value, retry_after_secs: None },
+                None => Response::empty(404),
+            })
+        }),
+        ("PUT", [b"kv", cf, key]) => resolve_cf(db, cf)
+            .and_then(|cf| db.put(&WriteOptions::default(), cf, key, &req.body))
+            .map(|_| Response::empty(204)),
+        ("DELETE", [b"kv", cf, key]) => resolve_cf(db, cf)
+            .and_then(|cf| db.delete(&WriteOptions::default(), cf, key))
+            .map(|_| Response::empty(204)),
+        ("POST", [b"flush"]) => cf_param(db, req)
+            .and_then(|cf| db.flush(cf))
+            .map(|_| Response::empty(202)),
+        ("POST", [b"compact"]) => cf_param(db, req)
+            .and_then(|cf| db.compact_range(cf, req.param("begin"), req.param("end")))
+            .map(|_| Response::empty(202)),
+        ("GET", [b"stats"]) => Ok(Response::json(200, stats(db))),
+        ("GET", [b"cf"]) => {
+            let cfs: Vec<_> = db.list_column_families()
+                .into_iter()
+                .map(|(id, name)| json!({ "id": id, "name": name }))
+                .collect();
+            Ok(Response::json(200, json!(cfs)))
+        }
+        ("PUT", [b"cf", name]) => utf8(name)
+            .and_then(|name| db.create_column_family(name, ColumnFamilyOptions::default()))
+            .map(|id| Response::json(201, json!({ "id": id }))),
+        ("DELETE", [b"cf", name]) => utf8(name)
+            .and_then(|name| db.drop_column_family(name))
+            .map(|_| Response::empty(204)),
+        ("POST", [b"knn", cf]) => resolve_cf(db, cf)
+            .and_then(|cf| db.knn_search(cf, &parse_knn_request(&req.body)?))
+            .map(|response| Response::json(200, knn_json(&response))),
+        (_, [b"kv", ..] | [b"flush"] | [b"compact"] | [b"stats"] | [b"cf", ..] | [b"knn", ..]) => Ok(Response::empty(405)),
+        _ => Ok(Response::empty(404)),
+    };
+    result.unwrap_or_else(|e| Response::error(&e))
+}
+
+fn stats(db: &DBImpl) -> serde_json::Value {
+    let memory = db.memory_usage();
+    json!({
+        "latest_sequence": db.latest_sequence_number(),
+        "memory": {
+            "total": memory.total(),
+            "active_memtables": memory.active_memtables,
+            "immutable_memtables": memory.immutable_memtables,
+            "block_cache_usage": memory.block_cache_usage,
+            "block_cache_capacity": memory.block_cache_capacity,
+            "pinned_blocks": memory.pinned_blocks,
+            "table_readers": memory.table_readers,
+            "index_blocks": memory.index_blocks,
+            "filter_blocks": memory.filter_blocks,
+        },
+        "open_files": db.open_files(),
+        "column_families": db.list_column_families().len(),
+    })
+}
+
+/// `/knn` 的请求体，见模块文档
+fn parse_knn_request(body: &[u8]) -> Result<KnnRequest, DBError> {
+    let bad = |what: &str| DBError::InvalidArgument(format!("knn: {}", what));
+    let body: serde_json::Value = serde_json::from_slice(body).map_err(|e| bad(&e.to_string()))?;
+    let query = body["vector"]
+        .as_array()
+        .ok_or_else(|| bad("missing vector"))?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| bad("bad vector"))?;
+    let k = body["k"].as_u64().ok_or_else(|| bad("missing k"))? as usize;
+
+    let mut request = KnnRequest::new(query, k);
+    let text = |name: &str| match &body[name] {
+        serde_json::Value::Null => Ok(None),
+        v => v.as_str().map(Some).ok_or_else(|| bad(&format!("{} must be a string", name))),
+    };
+    let number = |name: &str| match &body[name] {
+        serde_json::Value::Null => Ok(None),
+        v => v.as_u64().map(|n| Some(n as usize)).ok_or_else(|| bad(&format!("{} must be a number", name))),
+    };
+    let flag = |name: &str| match &body[name] {
+        serde_json::Value::Null => Ok(false),
+        v => v.as_bool().ok_or_else(|| bad(&format!("{} must be a boolean", name))),
+    };
+    request.index = text("index")?.map(str::to_string);
+    if let Some(metric) = text("metric")? {
+        request.metric = Some(Metric::parse(metric).ok_or_else(|| bad("unknown metric"))?);
+    }
+    request.score_threshold = match &body["threshold"] {
+        serde_json::Value::Null => None,
+        v => Some(v.as_f64().ok_or_else(|| bad("threshold must be a number"))? as f32),
+    };
+    if let Some(filter) = text("filter")? {
+        request.filter = Some(KeyFilter::parse(filter)?);
+    }
+    request.page_token = text("page")?.map(str::to_string);
+    request.with_payload = flag("payload")?;
+    request.search.ef_search = number("ef")?;
+    request.search.nprobe = number("nprobe")?;
+    request.search.exhaustive = flag("exhaustive")?;
+    request.search.exhaustive_fallback = flag("fallback")?;
+    Ok(request)
+}
+
+fn knn_json(response: &KnnResponse) -> serde_json::Value {
+    let hits: Vec<_> = response.hits
+        .iter()
+        .map(|hit| json!({
+            "key": String::from_utf8_lossy(&hit.key),
+            "score": hit.score,
+            "payload": hit.payload.as_deref().map(String::from_utf8_lossy),
+        }))
+        .collect();
+    json!({ "hits": hits, "next_page": response.next_page_token })
+}
+
+/// 数字当 id，否则按名字找
+fn resolve_cf(db: &DBImpl, segment: &[u8]) -> Result<ColumnFamilyId, DBError> {
+    let name = utf8(segment)?;
+    match name.parse::<ColumnFamilyId>() {
+        Ok(id) => Ok(id),
+        Err(_) => db.column_family_id(name),
+    }
+}
+
+fn cf_param(db: &DBImpl, req: &Request) -> Result<ColumnFamilyId, DBError> {
+    let cf = req.param("cf").ok_or_else(|| DBError::InvalidArgument("missing cf parameter".into()))?;
+    resolve_cf(db, cf)
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, DBError> {
+    std::str::from_utf8(bytes).map_err(|_| DBError::InvalidArgument("column family name is not UTF-8".into()))
+}
+
+/// "%2F" 这种解出来；不成对的 '%' 原样保留，'+' 不当空格（只在 path 和 query 值里用）
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        410 => "Gone",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_util::open_db;
+
+    /// 发一个请求（Connection: close），返回状态码
+    async fn status(addr: SocketAddr, request: &str) -> u16 {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_endpoint_needs_the_bearer_token() {
+        let (db, dir) = open_db("http-auth");
+        let options = HttpOptions::default().with_addr("127.0.0.1:0").with_auth_token("s3cret");
+        let server = HttpServer::start(Arc::clone(&db), options).await.unwrap();
+        let addr = server.local_addr();
+
+        let put = "PUT /kv/cf_user/k HTTP/1.1\r\nContent-Length: 1\r\nConnection: close\r\n";
+        assert_eq!(status(addr, &format!("{}\r\nv", put)).await, 401);
+        assert_eq!(status(addr, &format!("{}Authorization: Bearer wrong!\r\n\r\nv", put)).await, 401);
+        assert_eq!(status(addr, &format!("{}Authorization: Bearer s3cret\r\n\r\nv", put)).await, 204);
+        assert_eq!(status(addr, "DELETE /cf/cf_user HTTP/1.1\r\nConnection: close\r\n\r\n").await, 401);
+        assert_eq!(status(addr, "POST /compact?cf=cf_user HTTP/1.1\r\nConnection: close\r\n\r\n").await, 401);
+        // 读也要 token
+        let get = "GET /kv/cf_user/k HTTP/1.1\r\nConnection: close\r\n";
+        assert_eq!(status(addr, &format!("{}\r\n", get)).await, 401);
+        assert_eq!(status(addr, "GET /stats HTTP/1.1\r\nConnection: close\r\n\r\n").await, 401);
+        assert_eq!(status(addr, &format!("{}Authorization: Bearer s3cret\r\n\r\n", get)).await, 200);
+
+        server.shutdown().await;
+        db.close().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// 发一个请求（Connection: close），返回状态码和 body
+    async fn call(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let request = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method, path, body.len(), body
+        );
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn knn_route_searches_with_options_and_pages() {
+        let (db, dir) = open_db("http-knn");
+        let mut options = ColumnFamilyOptions::default();
+        options.vector.default.dimension = 2;
+        let cf = db.create_column_family("vectors", options).unwrap();
+        for (key, x) in [("a", 1.0), ("b", 2.0), ("c", 3.0), ("skip:d", 1.5)] {
+            db.put_vector(cf, key.as_bytes(), &[x, 0.0], format!("p-{}", key).as_bytes()).unwrap();
+        }
+        let server = HttpServer::start(Arc::clone(&db), HttpOptions::default().with_addr("127.0.0.1:0")).await.unwrap();
+        let addr = server.local_addr();
+
+        let keys = |body: &serde_json::Value| -> Vec<String> {
+            body["hits"].as_array().unwrap().iter().map(|h| h["key"].as_str().unwrap().to_string()).collect()
+        };
+        let (status, body) = call(addr, "POST", "/knn/vectors", r#"{"vector": [0.9, 0], "k": 2, "payload": true, "exhaustive": true}"#).await;
+        assert_eq!(status, 200, "{}", body);
+        let first: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(keys(&first), vec!["a", "skip:d"]);
+        assert_eq!(first["hits"][0]["payload"], "p-a");
+        let page = first["next_page"].as_str().unwrap();
+
+        // 下一页接着上一页，filter 跳过 skip: 开头的 key
+        let request = json!({ "vector": [0.9, 0], "k": 2, "page": page, "filter": "range:a..c", "exhaustive": true });
+        let (status, body) = call(addr, "POST", &format!("/knn/{}", cf), &request.to_string()).await;
+        assert_eq!(status, 200, "{}", body);
+        let second: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(keys(&second), vec!["b"]);
+        assert_eq!(second["hits"][0]["payload"], serde_json::Value::Null);
+
+        assert_eq!(call(addr, "POST", "/knn/vectors", r#"{"k": 2}"#).await.0, 400);
+        assert_eq!(call(addr, "POST", "/knn/vectors", r#"{"vector": [1, 0], "k": 2, "metric": "manhattan"}"#).await.0, 400);
+        assert_eq!(call(addr, "POST", "/knn/nope", r#"{"vector": [1, 0], "k": 2}"#).await.0, 404);
+        assert_eq!(call(addr, "GET", "/knn/vectors", "").await.0, 405);
+
+        server.shutdown().await;
+        db.close().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refuses_a_non_loopback_address_without_a_token() {
+        let (db, dir) = open_db("http-bind");
+        assert_eq!(HttpOptions::default().addr, "127.0.0.1:8080");
+
+        let open = HttpOptions::default().with_addr("0.0.0.0:0");
+        assert!(matches!(HttpServer::start(Arc::clone(&db), open).await, Err(DBError::InvalidArgument(_))));
+        let server = HttpServer::start(Arc::clone(&db), HttpOptions::default().with_addr("0.0.0.0:0").with_auth_token("t"))
+            .await
+            .unwrap();
+        server.shutdown().await;
+
+        db.close().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
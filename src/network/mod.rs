@@ -1 +1,12 @@
 mod worker;
+pub mod vector_api;
+pub mod cluster_client;
+pub mod resp;
+pub mod server;
+pub mod replication;
+pub mod http;
+
+pub use cluster_client::{ClusterClient, HashRing};
+pub use http::{HttpOptions, HttpServer};
+pub use replication::{FollowerStatus, ReplicationFollower, ReplicationLeader, ReplicationOptions};
+pub use server::Server;
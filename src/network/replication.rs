@@ -0,0 +1,853 @@
+//! Leader/follower replication of committed WAL batches.
+//!
+//! A follower connects to the leader and says which sequence it has applied.
+//! The leader then streams every WAL batch after it (read with
+//! `DBImpl::get_updates_since`) and the follower applies them with
+//! `DBImpl::apply_replicated`, acknowledging the last sequence of each.
+//!
+//! When the leader's WAL no longer reaches back to the follower's sequence, or
+//! the follower is more than `max_catchup_lag` sequences behind, the leader
+//! ships a checkpoint instead; the follower replaces its DB with it, reopens and
+//! connects again.
+//!
+//! Frames on the wire are `tag: u8, len: u32 LE, payload`:
+//!
+//! ```text
+//! HELLO          follower -> leader   applied sequence (u64 LE), term (u64 LE)
+//! TERM           leader -> follower   the leader's term (u64 LE), right after HELLO
+//! ACK            follower -> leader   last applied sequence (u64 LE)
+//! BATCH          leader -> follower   WriteBatch encoding, sequence included
+//! SNAPSHOT_CHUNK leader -> follower   name_len: u16 LE, name, bytes
+//! SNAPSHOT_END   leader -> follower   sequence of the checkpoint (u64 LE)
+//! ```
+//!
+//! Writes made with `disable_wal` are not replicated.
+//!
+//! # Fencing
+//!
+//! Every leader takes a term one above the highest its DB has seen (see
+//! `crate::db::fencing`), and followers adopt the term of the leader they
+//! follow, so a follower promoted to leader outranks the leader it replaced.
+//! `ReplicationLeader::write` acknowledges a write with a `FencingToken`.
+//! Clients pass the newest token they hold to `validate_token` before trusting
+//! a read. A leader shown a token from a later term, or greeted by a follower
+//! from one, knows it was deposed and fences all further writes and reads, so
+//! it can't serve state older than what clients were acknowledged.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use crate::{DBImpl, DB};
+use crate::db::fencing::{check_token, FencingToken};
+use crate::engine::mem::SequenceNumber;
+use crate::engine::wal::WriteBatch;
+use crate::error::DBError;
+use crate::util::WriteOptions;
+
+const HELLO: u8 = 1;
+const ACK: u8 = 2;
+const BATCH: u8 = 3;
+const SNAPSHOT_CHUNK: u8 = 4;
+const SNAPSHOT_END: u8 = 5;
+const TERM: u8 = 6;
+
+/// 单帧上限，防止坏数据让对面分配一大块内存
+const MAX_FRAME_LEN: usize = 64 << 20;
+const SNAPSHOT_CHUNK_SIZE: usize = 1 << 20;
+
+/// Settings shared by `ReplicationLeader` and `ReplicationFollower`.
+#[derive(Debug, Clone)]
+pub struct ReplicationOptions {
+    /// How long the leader waits before reading the WAL again once a follower
+    /// has caught up.
+    pub poll_interval: Duration,
+    /// Batches read from the WAL per round before sending them.
+    pub max_batches_per_poll: usize,
+    /// A follower further behind than this many sequences gets a checkpoint
+    /// instead of the batches. 0: only when the WAL doesn't reach back far
+    /// enough.
+    pub max_catchup_lag: u64,
+    /// Where the leader stages checkpoints; the system temp dir by default.
+    pub snapshot_dir: Option<PathBuf>,
+    /// How long the follower waits before reconnecting after the connection
+    /// drops.
+    pub reconnect_interval: Duration,
+}
+
+impl Default for ReplicationOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(100),
+            max_batches_per_poll: 1024,
+            max_catchup_lag: 0,
+            snapshot_dir: None,
+            reconnect_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ReplicationOptions {
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn with_max_batches_per_poll(mut self, n: usize) -> Self {
+        self.max_batches_per_poll = n;
+        self
+    }
+
+    pub fn with_max_catchup_lag(mut self, lag: u64) -> Self {
+        self.max_catchup_lag = lag;
+        self
+    }
+
+    pub fn with_snapshot_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.snapshot_dir = Some(dir.into());
+        self
+    }
+
+    pub fn with_reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval = interval;
+        self
+    }
+}
+
+/// Progress of one connected follower, see `ReplicationLeader::followers`.
+#[derive(Debug, Clone, Default)]
+pub struct FollowerStatus {
+    /// Last sequence sent to the follower.
+    pub sent_sequence: SequenceNumber,
+    /// Last sequence the follower acknowledged as applied.
+    pub acked_sequence: SequenceNumber,
+    pub snapshots_sent: u64,
+}
+
+type FollowerMap = Arc<Mutex<HashMap<SocketAddr, FollowerStatus>>>;
+
+/// Serves the DB's WAL to followers; see the module docs.
+pub struct ReplicationLeader {
+    local_addr: SocketAddr,
+    db: Arc<DBImpl>,
+    term: u64,
+    /// 见到了更高的 term：不再接写、不再接读
+    deposed: Arc<AtomicBool>,
+    followers: FollowerMap,
+    shutdown_tx: watch::Sender<bool>,
+    accept_loop: tokio::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ReplicationLeader {
+    /// Binds `addr` and starts accepting followers on the current runtime,
+    /// leading a new term one above any the DB has seen.
+    pub async fn start(addr: &str, db: Arc<DBImpl>, options: ReplicationOptions) -> Result<Self, DBError> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let db2 = Arc::clone(&db);
+        let term = tokio::task::spawn_blocking(move || db2.next_replication_term())
+            .await
+            .map_err(|e| DBError::Other(e.to_string()))??;
+        let deposed = Arc::new(AtomicBool::new(false));
+        let followers = FollowerMap::default();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let accept_loop = tokio::spawn(leader_accept_loop(
+            listener,
+            Arc::clone(&db),
+            Leadership { term, deposed: Arc::clone(&deposed) },
+            Arc::new(options),
+            Arc::clone(&followers),
+            shutdown_rx,
+        ));
+        Ok(Self {
+            local_addr,
+            db,
+            term,
+            deposed,
+            followers,
+            shutdown_tx,
+            accept_loop: tokio::sync::Mutex::new(Some(accept_loop)),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The term this leader was started in.
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// A later term was seen, from a follower or a client's token; writes and
+    /// token checks fail with `DBError::Fenced` from then on.
+    pub fn is_deposed(&self) -> bool {
+        self.deposed.load(Ordering::Acquire)
+    }
+
+    /// Writes `batch` and returns the token acknowledging it.
+    pub fn write(&self, opts: &WriteOptions, batch: WriteBatch) -> Result<FencingToken, DBError> {
+        self.fencing_token()?;
+        self.db.write(opts, batch)?;
+        self.fencing_token()
+    }
+
+    /// Token covering every write acknowledged so far.
+    pub fn fencing_token(&self) -> Result<FencingToken, DBError> {
+        if self.is_deposed() {
+            return Err(DBError::Fenced(format!("leader of term {} was deposed", self.term)));
+        }
+        Ok(FencingToken { term: self.term, sequence: self.db.last_wal_sequence() })
+    }
+
+    /// Checks that a read served by this leader is at least as new as `token`.
+    /// A token from a later term deposes this leader.
+    pub fn validate_token(&self, token: &FencingToken) -> Result<(), DBError> {
+        if token.term > self.term && !self.deposed.swap(true, Ordering::AcqRel) {
+            log::warn!("leader of term {} saw a token from term {}, stepping down", self.term, token.term);
+        }
+        check_token(token, self.term, self.db.latest_sequence_number(), self.is_deposed())
+    }
+
+    /// Followers connected right now.
+    pub fn followers(&self) -> Vec<(SocketAddr, FollowerStatus)> {
+        self.followers.lock().unwrap().iter().map(|(a, s)| (*a, s.clone())).collect()
+    }
+
+    /// Lowest sequence acknowledged by every connected follower; `None` with no
+    /// followers.
+    pub fn min_acked_sequence(&self) -> Option<SequenceNumber> {
+        self.followers.lock().unwrap().values().map(|s| s.acked_sequence).min()
+    }
+
+    /// Stops accepting, closes every follower connection and waits for them.
+    /// Idempotent.
+    pub async fn shutdown(&self) {
+        let _ = self.shutdown_tx.send(true);
+        if let Some(handle) = self.accept_loop.lock().await.take() {
+            let _ = handle.await;
+        }
+    }
+}
+
+/// leader 的 term，和它有没有被更高的 term 取代
+#[derive(Clone)]
+struct Leadership {
+    term: u64,
+    deposed: Arc<AtomicBool>,
+}
+
+async fn leader_accept_loop(
+    listener: TcpListener,
+    db: Arc<DBImpl>,
+    leadership: Leadership,
+    options: Arc<ReplicationOptions>,
+    followers: FollowerMap,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((socket, addr)) = accepted {
+                    let (db, leadership, options, followers, shutdown_rx) = (
+                        Arc::clone(&db), leadership.clone(), Arc::clone(&options), Arc::clone(&followers), shutdown_rx.clone(),
+                    );
+                    connections.spawn(async move {
+                        if let Err(e) = serve_follower(socket, addr, db, &leadership, options, &followers, shutdown_rx).await {
+                            log::warn!("replication to {} stopped: {:?}", addr, e);
+                        }
+                        followers.lock().unwrap().remove(&addr);
+                    });
+                }
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown_rx.changed() => break,
+        }
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+}
+
+async fn serve_follower(
+    socket: TcpStream,
+    addr: SocketAddr,
+    db: Arc<DBImpl>,
+    leadership: &Leadership,
+    options: Arc<ReplicationOptions>,
+    followers: &FollowerMap,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Result<(), DBError> {
+    socket.set_nodelay(true)?;
+    let (mut reader, mut writer) = socket.into_split();
+    let (applied, follower_term) = match read_frame(&mut reader).await? {
+        Some((HELLO, payload)) if payload.len() == 16 => (decode_u64(&payload[..8])?, decode_u64(&payload[8..])?),
+        _ => return Err(DBError::Corruption(format!("{} did not start with HELLO", addr))),
+    };
+    // follower 已经跟过更新的 leader：自己被取代了，不再发任何东西
+    if follower_term > leadership.term {
+        leadership.deposed.store(true, Ordering::Release);
+        return Err(DBError::Fenced(format!(
+            "follower {} is at term {}, this leader is at term {}", addr, follower_term, leadership.term
+        )));
+    }
+    if leadership.deposed.load(Ordering::Acquire) {
+        return Err(DBError::Fenced(format!("leader of term {} was deposed", leadership.term)));
+    }
+    write_frame(&mut writer, TERM, &leadership.term.to_le_bytes()).await?;
+    followers.lock().unwrap().insert(addr, FollowerStatus {
+        sent_sequence: applied,
+        acked_sequence: applied,
+        snapshots_sent: 0,
+    });
+
+    if needs_snapshot(&db, applied, &options).await? {
+        log::info!("follower {} at sequence {} is too far behind, sending a checkpoint", addr, applied);
+        let seq = send_snapshot(&mut writer, &db, &options).await?;
+        if let Some(s) = followers.lock().unwrap().get_mut(&addr) {
+            s.sent_sequence = seq;
+            s.snapshots_sent += 1;
+        }
+        // follower 装好 checkpoint 后重连，从它自己的 seq 接着要
+        return Ok(());
+    }
+
+    // ack 单独收，发送这边不用等
+    let ack_followers = Arc::clone(followers);
+    let mut acks = tokio::spawn(async move {
+        while let Some((tag, payload)) = read_frame(&mut reader).await? {
+            if tag != ACK {
+                return Err(DBError::Corruption(format!("unexpected frame {} from follower", tag)));
+            }
+            let seq = decode_u64(&payload)?;
+            if let Some(s) = ack_followers.lock().unwrap().get_mut(&addr) {
+                s.acked_sequence = s.acked_sequence.max(seq);
+            }
+        }
+        Ok::<(), DBError>(())
+    });
+
+    let result = stream_batches(&mut writer, addr, &db, &options, followers, &mut acks, &mut shutdown_rx, applied + 1).await;
+    acks.abort();
+    result
+}
+
+/// 从 `next` 开始一直往 follower 发，直到关机或者 follower 断开
+#[allow(clippy::too_many_arguments)]
+async fn stream_batches<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    addr: SocketAddr,
+    db: &Arc<DBImpl>,
+    options: &ReplicationOptions,
+    followers: &FollowerMap,
+    acks: &mut JoinHandle<Result<(), DBError>>,
+    shutdown_rx: &mut watch::Receiver<bool>,
+    mut next: SequenceNumber,
+) -> Result<(), DBError> {
+    // 每个 follower 一个 iterator，读到 WAL 末尾后下次从停下的位置接着读
+    let db2 = Arc::clone(db);
+    let mut log = tokio::task::spawn_blocking(move || db2.get_updates_since(next))
+        .await
+        .map_err(|e| DBError::Other(e.to_string()))??;
+    loop {
+        let max = options.max_batches_per_poll.max(1);
+        let (returned, batches) = tokio::task::spawn_blocking(move || {
+            let batches: Result<Vec<(SequenceNumber, WriteBatch)>, DBError> = log.by_ref().take(max).collect();
+            (log, batches)
+        })
+        .await
+        .map_err(|e| DBError::Other(e.to_string()))?;
+        log = returned;
+        let batches = batches?;
+
+        if batches.is_empty() {
+            tokio::select! {
+                _ = tokio::time::sleep(options.poll_interval) => continue,
+                res = &mut *acks => return res.map_err(|e| DBError::Other(e.to_string()))?,
+                _ = shutdown_rx.changed() => return Ok(()),
+            }
+        }
+        for (seq, batch) in batches {
+            let last = seq + batch.len().max(1) as u64 - 1;
+            if last < next {
+                continue;
+            }
+            write_frame(writer, BATCH, batch.data()).await?;
+            next = last + 1;
+        }
+        writer.flush().await?;
+        if let Some(s) = followers.lock().unwrap().get_mut(&addr) {
+            s.sent_sequence = next - 1;
+        }
+        if *shutdown_rx.borrow() {
+            return Ok(());
+        }
+    }
+}
+
+/// WAL 只保证 `wal_applied_sequence` 之后的都在；更早的要靠归档，归档接不上就只能发 checkpoint
+async fn needs_snapshot(db: &Arc<DBImpl>, applied: SequenceNumber, options: &ReplicationOptions) -> Result<bool, DBError> {
+    let latest = db.latest_sequence_number();
+    if applied >= latest {
+        return Ok(false);
+    }
+    if options.max_catchup_lag > 0 && latest - applied > options.max_catchup_lag {
+        return Ok(true);
+    }
+    if applied >= db.wal_applied_sequence() {
+        return Ok(false);
+    }
+    let db = Arc::clone(db);
+    tokio::task::spawn_blocking(move || -> Result<bool, DBError> {
+        match db.get_updates_since(applied + 1)?.next() {
+            Some(first) => Ok(first?.0 > applied + 1),
+            None => Ok(true),
+        }
+    })
+    .await
+    .map_err(|e| DBError::Other(e.to_string()))?
+}
+
+/// 写一个 checkpoint，逐个文件分块发过去，返回 checkpoint 时的 seq
+async fn send_snapshot<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    db: &Arc<DBImpl>,
+    options: &ReplicationOptions,
+) -> Result<SequenceNumber, DBError> {
+    static SNAPSHOT_ID: AtomicU64 = AtomicU64::new(0);
+    let staging = options.snapshot_dir.clone()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("vectorkv-replication-{}-{}", std::process::id(), SNAPSHOT_ID.fetch_add(1, Ordering::Relaxed)));
+    let seq = db.latest_sequence_number();
+    let db2 = Arc::clone(db);
+    let dir = staging.clone();
+    let files = tokio::task::spawn_blocking(move || -> Result<Vec<PathBuf>, DBError> {
+        db2.create_checkpoint(&dir)?;
+        let mut files = Vec::new();
+        collect_files(&dir, &dir, &mut files)?;
+        Ok(files)
+    })
+    .await
+    .map_err(|e| DBError::Other(e.to_string()))?;
+
+    let result = async {
+        for rel in files? {
+            let name = rel.to_str()
+                .ok_or_else(|| DBError::Other(format!("non utf-8 checkpoint file {:?}", rel)))?;
+            let mut file = tokio::fs::File::open(staging.join(&rel)).await?;
+            let mut buf = vec![0u8; SNAPSHOT_CHUNK_SIZE];
+            // 空文件也发一块，follower 那边才会建出来
+            let mut first = true;
+            loop {
+                let n = file.read(&mut buf).await?;
+                if n == 0 && !first {
+                    break;
+                }
+                let mut payload = Vec::with_capacity(2 + name.len() + n);
+                payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                payload.extend_from_slice(name.as_bytes());
+                payload.extend_from_slice(&buf[..n]);
+                write_frame(writer, SNAPSHOT_CHUNK, &payload).await?;
+                if n == 0 {
+                    break;
+                }
+                first = false;
+            }
+        }
+        write_frame(writer, SNAPSHOT_END, &seq.to_le_bytes()).await?;
+        writer.flush().await?;
+        Ok(seq)
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    result
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), DBError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, out)?;
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            out.push(rel.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Keeps a DB in sync with a `ReplicationLeader`; see the module docs.
+///
+/// The follower owns its DB: read through `db()`, and don't write to it.
+/// After a checkpoint is installed `db()` returns the reopened DB.
+pub struct ReplicationFollower {
+    db: Arc<RwLock<Arc<DBImpl>>>,
+    applied: Arc<AtomicU64>,
+    shutdown_tx: watch::Sender<bool>,
+    task: tokio::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ReplicationFollower {
+    /// Opens the DB at `db_path` and starts following `leader_addr` on the
+    /// current runtime, reconnecting whenever the connection drops.
+    pub async fn start(leader_addr: &str, db_path: &str, options: ReplicationOptions) -> Result<Self, DBError> {
+        let path = db_path.to_string();
+        let db = tokio::task::spawn_blocking(move || DBImpl::open(&path))
+            .await
+            .map_err(|e| DBError::Other(e.to_string()))??;
+        let applied = Arc::new(AtomicU64::new(db.replicated_sequence()));
+        let db = Arc::new(RwLock::new(db));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let task = tokio::spawn(follower_loop(
+            leader_addr.to_string(),
+            PathBuf::from(db_path),
+            Arc::clone(&db),
+            Arc::clone(&applied),
+            options,
+            shutdown_rx,
+        ));
+        Ok(Self { db, applied, shutdown_tx, task: tokio::sync::Mutex::new(Some(task)) })
+    }
+
+    pub fn db(&self) -> Arc<DBImpl> {
+        Arc::clone(&self.db.read().unwrap())
+    }
+
+    /// Last sequence applied from the leader.
+    pub fn applied_sequence(&self) -> SequenceNumber {
+        self.applied.load(Ordering::Acquire)
+    }
+
+    /// Term of the newest leader this follower has followed.
+    pub fn term(&self) -> u64 {
+        self.db().replication_term()
+    }
+
+    /// Checks that a read served by this follower is at least as new as
+    /// `token`: the follower has seen its term and applied up to its sequence.
+    pub fn validate_token(&self, token: &FencingToken) -> Result<(), DBError> {
+        check_token(token, self.term(), self.applied_sequence(), false)
+    }
+
+    /// Disconnects from the leader and waits for the batch being applied.
+    /// The DB stays open. Idempotent.
+    pub async fn stop(&self) {
+        let _ = self.shutdown_tx.send(true);
+        if let Some(handle) = self.task.lock().await.take() {
+            let _ = handle.await;
+        }
+    }
+}
+
+async fn follower_loop(
+    leader_addr: String,
+    db_path: PathBuf,
+    db: Arc<RwLock<Arc<DBImpl>>>,
+    applied: Arc<AtomicU64>,
+    options: ReplicationOptions,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let session = follow_once(&leader_addr, &db_path, &db, &applied);
+        tokio::select! {
+            res = session => match res {
+                Ok(()) => continue,
+                Err(e) => log::warn!("replication from {} interrupted: {:?}", leader_addr, e),
+            },
+            _ = shutdown_rx.changed() => return,
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(options.reconnect_interval) => {}
+            _ = shutdown_rx.changed() => return,
+        }
+    }
+}
+
+/// 一次连接：收 batch 直到断开；收完 checkpoint 装好后返回 Ok，外面马上重连
+async fn follow_once(
+    leader_addr: &str,
+    db_path: &Path,
+    db: &Arc<RwLock<Arc<DBImpl>>>,
+    applied: &Arc<AtomicU64>,
+) -> Result<(), DBError> {
+    let socket = TcpStream::connect(leader_addr).await?;
+    socket.set_nodelay(true)?;
+    let (mut reader, mut writer) = socket.into_split();
+    let (current, known_term) = {
+        let db = db.read().unwrap();
+        (db.replicated_sequence(), db.replication_term())
+    };
+    let mut hello = current.to_le_bytes().to_vec();
+    hello.extend_from_slice(&known_term.to_le_bytes());
+    write_frame(&mut writer, HELLO, &hello).await?;
+    writer.flush().await?;
+
+    // 不跟比自己见过的 term 还旧的 leader，它已经被取代了
+    let term = match read_frame(&mut reader).await? {
+        Some((TERM, payload)) => decode_u64(&payload)?,
+        Some((tag, _)) => return Err(DBError::Corruption(format!("expected TERM from leader, got frame {}", tag))),
+        None => return Err(DBError::Other("connection closed by leader".into())),
+    };
+    if term < known_term {
+        return Err(DBError::Fenced(format!("leader is at term {}, this follower has seen term {}", term, known_term)));
+    }
+    let target = Arc::clone(&db.read().unwrap());
+    tokio::task::spawn_blocking(move || target.observe_replication_term(term))
+        .await
+        .map_err(|e| DBError::Other(e.to_string()))??;
+
+    let staging = snapshot_staging_dir(db_path);
+    let mut snapshot_files: HashMap<PathBuf, std::fs::File> = HashMap::new();
+    loop {
+        let Some((tag, payload)) = read_frame(&mut reader).await? else {
+            return Err(DBError::Other("connection closed by leader".into()));
+        };
+        match tag {
+            BATCH => {
+                let batch = WriteBatch::from_data(payload)?;
+                let last = batch.sequence() + batch.len().max(1) as u64 - 1;
+                let target = Arc::clone(&db.read().unwrap());
+                tokio::task::spawn_blocking(move || target.apply_replicated(batch))
+                    .await
+                    .map_err(|e| DBError::Other(e.to_string()))??;
+                applied.fetch_max(last, Ordering::AcqRel);
+                write_frame(&mut writer, ACK, &last.to_le_bytes()).await?;
+                writer.flush().await?;
+            }
+            SNAPSHOT_CHUNK => {
+                if snapshot_files.is_empty() && staging.exists() {
+                    std::fs::remove_dir_all(&staging)?;
+                }
+                let (name, data) = decode_chunk(&payload)?;
+                let file = match snapshot_files.entry(name.clone()) {
+                    std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+                    std::collections::hash_map::Entry::Vacant(e) => {
+                        let path = staging.join(&name);
+                        if let Some(parent) = path.parent() {
+                            std::fs::create_dir_all(parent)?;
+                        }
+                        e.insert(std::fs::File::create(path)?)
+                    }
+                };
+                std::io::Write::write_all(file, data)?;
+            }
+            SNAPSHOT_END => {
+                let seq = decode_u64(&payload)?;
+                for (_, file) in snapshot_files.drain() {
+                    file.sync_all()?;
+                }
+                let (db, applied, db_path) = (Arc::clone(db), Arc::clone(applied), db_path.to_path_buf());
+                tokio::task::spawn_blocking(move || install_snapshot(&db_path, &staging, &db, &applied, seq, term))
+                    .await
+                    .map_err(|e| DBError::Other(e.to_string()))??;
+                log::info!("installed replication checkpoint at sequence {}", seq);
+                return Ok(());
+            }
+            _ => return Err(DBError::Corruption(format!("unexpected frame {} from leader", tag))),
+        }
+    }
+}
+
+fn snapshot_staging_dir(db_path: &Path) -> PathBuf {
+    let mut name = db_path.as_os_str().to_os_string();
+    name.push(".replica-snapshot");
+    PathBuf::from(name)
+}
+
+/// 关掉旧 DB，用 checkpoint 换掉整个目录再打开；checkpoint 里没有配置文件，按默认布局打开
+fn install_snapshot(
+    db_path: &Path,
+    staging: &Path,
+    db: &RwLock<Arc<DBImpl>>,
+    applied: &AtomicU64,
+    seq: SequenceNumber,
+    term: u64,
+) -> Result<(), DBError> {
+    let path = db_path.to_str()
+        .ok_or_else(|| DBError::Other(format!("non utf-8 DB path {:?}", db_path)))?;
+    let mut guard = db.write().unwrap();
+    guard.close()?;
+    std::fs::remove_dir_all(db_path)?;
+    std::fs::rename(staging, db_path)?;
+    if let Some(parent) = db_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        crate::util::sync_dir(parent)?;
+    }
+    let reopened = DBImpl::open(path)?;
+    reopened.set_replicated_sequence(seq)?;
+    // checkpoint 里没有 TERM，换目录后重新记下
+    reopened.observe_replication_term(term)?;
+    applied.store(seq, Ordering::Release);
+    *guard = reopened;
+    Ok(())
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, tag: u8, payload: &[u8]) -> Result<(), DBError> {
+    let mut header = [0u8; 5];
+    header[0] = tag;
+    header[1..].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    writer.write_all(&header).await?;
+    writer.write_all(payload).await?;
+    Ok(())
+}
+
+/// 对面正常关连接时返回 None
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<(u8, Vec<u8>)>, DBError> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(DBError::Corruption(format!("replication frame of {} bytes", len)));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some((header[0], payload)))
+}
+
+fn decode_u64(payload: &[u8]) -> Result<u64, DBError> {
+    payload.try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_| DBError::Corruption(format!("expected 8 bytes, got {}", payload.len())))
+}
+
+fn decode_chunk(payload: &[u8]) -> Result<(PathBuf, &[u8]), DBError> {
+    let bad = || DBError::Corruption("truncated snapshot chunk".into());
+    let name_len = u16::from_le_bytes(payload.get(..2).ok_or_else(bad)?.try_into().unwrap()) as usize;
+    let name = payload.get(2..2 + name_len).ok_or_else(bad)?;
+    let name = std::str::from_utf8(name).map_err(|_| bad())?;
+    let rel = PathBuf::from(name);
+    // 只能写到 staging 目录里面
+    if rel.is_absolute() || rel.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+        return Err(DBError::Corruption(format!("snapshot file name {:?} escapes the DB dir", name)));
+    }
+    Ok((rel, &payload[2 + name_len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::constants::USER_COLUMN_FAMILY_ID;
+    use crate::util::ReadOptions;
+    use crate::util::test_util::{open_db, test_dir};
+
+    fn put(key: &[u8]) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        batch.put(USER_COLUMN_FAMILY_ID, key, b"v");
+        batch
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fencing_token_of_a_new_term_deposes_the_old_leader() {
+        let ((db_a, dir_a), dir_b) = (open_db("fence-a"), test_dir("fence-b"));
+        let options = ReplicationOptions::default().with_poll_interval(Duration::from_millis(10));
+        let old = ReplicationLeader::start("127.0.0.1:0", Arc::clone(&db_a), options.clone()).await.unwrap();
+        assert_eq!(old.term(), 1);
+        let t1 = old.write(&WriteOptions::default(), put(b"a")).unwrap();
+        assert_eq!(FencingToken::decode(&t1.encode()).unwrap(), t1);
+
+        let follower = ReplicationFollower::start(&old.local_addr().to_string(), dir_b.to_str().unwrap(), options.clone())
+            .await
+            .unwrap();
+        // 没追上之前读会被拒
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while follower.validate_token(&t1).is_err() {
+            assert!(tokio::time::Instant::now() < deadline, "follower never caught up");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(follower.term(), 1);
+        let ahead = FencingToken { term: 1, sequence: t1.sequence + 100 };
+        assert!(matches!(follower.validate_token(&ahead), Err(DBError::Fenced(_))));
+
+        // follower 升为 leader，term 比旧 leader 大
+        follower.stop().await;
+        let new = ReplicationLeader::start("127.0.0.1:0", follower.db(), options).await.unwrap();
+        assert_eq!(new.term(), 2);
+        let t2 = new.write(&WriteOptions::default(), put(b"b")).unwrap();
+        new.validate_token(&t1).unwrap();
+
+        // 旧 leader 见到新 term 的 token：读写都被挡住
+        assert!(matches!(old.validate_token(&t2), Err(DBError::Fenced(_))));
+        assert!(old.is_deposed());
+        assert!(matches!(old.validate_token(&t1), Err(DBError::Fenced(_))));
+        assert!(matches!(old.write(&WriteOptions::default(), put(b"c")), Err(DBError::Fenced(_))));
+
+        old.shutdown().await;
+        new.shutdown().await;
+        db_a.close().unwrap();
+        follower.db().close().unwrap();
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lagging_follower_catches_up_from_a_checkpoint_then_streams() {
+        let ((db_a, dir_a), dir_b) = (open_db("catchup-a"), test_dir("catchup-b"));
+        let options = ReplicationOptions::default()
+            .with_poll_interval(Duration::from_millis(10))
+            .with_max_catchup_lag(5)
+            .with_snapshot_dir(test_dir("catchup-staging"));
+        let leader = ReplicationLeader::start("127.0.0.1:0", Arc::clone(&db_a), options.clone()).await.unwrap();
+        for i in 0..20u32 {
+            leader.write(&WriteOptions::default(), put(format!("k{:02}", i).as_bytes())).unwrap();
+        }
+
+        // 新 follower 落后 20 个 seq，超过 max_catchup_lag：先收 checkpoint
+        let follower = ReplicationFollower::start(&leader.local_addr().to_string(), dir_b.to_str().unwrap(), options)
+            .await
+            .unwrap();
+        let token = leader.write(&WriteOptions::default(), put(b"tail")).unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while follower.validate_token(&token).is_err() {
+            assert!(tokio::time::Instant::now() < deadline, "follower never caught up");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let read = |key: &[u8]| follower.db().get(&ReadOptions::default(), USER_COLUMN_FAMILY_ID, key).unwrap();
+        assert_eq!(read(b"k00"), Some(b"v".to_vec()));
+        assert_eq!(read(b"tail"), Some(b"v".to_vec()));
+        let snapshots: u64 = leader.followers().iter().map(|(_, s)| s.snapshots_sent).sum();
+        assert_eq!(snapshots, 1);
+
+        // 追上之后只发 batch
+        let token = leader.write(&WriteOptions::default(), put(b"after")).unwrap();
+        while follower.validate_token(&token).is_err() {
+            assert!(tokio::time::Instant::now() < deadline, "follower stopped streaming");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(read(b"after"), Some(b"v".to_vec()));
+        assert!(leader.min_acked_sequence().is_some());
+        let snapshots: u64 = leader.followers().iter().map(|(_, s)| s.snapshots_sent).sum();
+        assert_eq!(snapshots, 1);
+
+        follower.stop().await;
+        leader.shutdown().await;
+        db_a.close().unwrap();
+        follower.db().close().unwrap();
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    #[test]
+    fn snapshot_chunks_cannot_name_files_outside_the_db() {
+        let chunk = |name: &str| {
+            let mut payload = (name.len() as u16).to_le_bytes().to_vec();
+            payload.extend_from_slice(name.as_bytes());
+            payload.extend_from_slice(b"data");
+            payload
+        };
+        let good = chunk("sst/000007.sst");
+        let (rel, data) = decode_chunk(&good).unwrap();
+        assert_eq!(rel, PathBuf::from("sst/000007.sst"));
+        assert_eq!(data, b"data");
+        assert!(matches!(decode_chunk(&chunk("../escape")), Err(DBError::Corruption(_))));
+        assert!(matches!(decode_chunk(&chunk("/etc/passwd")), Err(DBError::Corruption(_))));
+        assert!(matches!(decode_chunk(&[9, 0, b'x']), Err(DBError::Corruption(_))));
+    }
+}
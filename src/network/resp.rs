@@ -0,0 +1,131 @@
+//! Framing and error replies of the text protocol.
+//!
+//! Commands are either inline (`SET k v`, split on whitespace) or RESP arrays
+//! of bulk strings, which carry keys and values with spaces or line breaks:
+//!
+//! ```text
+//! *3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nv a l\r\n
+//! ```
+//!
+//! Errors are `-ERR <message>`, except write stalls, which tell the client how
+//! long to back off:
+//!
+//! ```text
+//! -TRYAGAIN retry-after-ms=<n> <reason>
+//! ```
+
+use crate::error::DBError;
+
+/// 单个 bulk string 的上限，和 Redis 一样 512MiB
+const MAX_BULK_LEN: usize = 512 << 20;
+
+/// RESP 数组：命令和多值回复（SCAN）都这么编
+pub fn encode_array(items: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        out.extend_from_slice(format!("${}\r\n", item.len()).as_bytes());
+        out.extend_from_slice(item);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// 从 `buf` 开头解出一条 RESP 数组命令，返回参数和用掉的字节数；
+/// 还没收全时返回 None
+pub fn parse_command(buf: &[u8]) -> Result<Option<(Vec<Vec<u8>>, usize)>, DBError> {
+    let Some((count, mut pos)) = parse_header(buf, b'*')? else { return Ok(None) };
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let Some((len, start)) = parse_header(&buf[pos..], b'$')? else { return Ok(None) };
+        if len > MAX_BULK_LEN {
+            return Err(DBError::InvalidArgument(format!("bulk string of {} bytes", len)));
+        }
+        let start = pos + start;
+        if buf.len() < start + len + 2 {
+            return Ok(None);
+        }
+        if &buf[start + len..start + len + 2] != b"\r\n" {
+            return Err(DBError::InvalidArgument("bulk string not terminated by CRLF".into()));
+        }
+        args.push(buf[start..start + len].to_vec());
+        pos = start + len + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+/// `<tag><n>\r\n`：返回 n 和这一行之后的位置
+fn parse_header(buf: &[u8], tag: u8) -> Result<Option<(usize, usize)>, DBError> {
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else { return Ok(None) };
+    let line = &buf[..end];
+    if line.first() != Some(&tag) {
+        return Err(DBError::InvalidArgument(format!("expected '{}', got {:?}", tag as char, String::from_utf8_lossy(line))));
+    }
+    let n = std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| DBError::InvalidArgument(format!("bad length: {:?}", String::from_utf8_lossy(line))))?;
+    Ok(Some((n, end + 2)))
+}
+
+pub fn encode_error(e: &DBError) -> String {
+    match e {
+        DBError::WriteStall { retry_after_ms, reason } => {
+            format!("-TRYAGAIN retry-after-ms={} {}\r\n", retry_after_ms, reason)
+        }
+        _ => format!("-ERR {:?}\r\n", e),
+    }
+}
+
+/// `line` 不含开头的 '-' 和结尾的 "\r\n"
+pub fn parse_error(line: &str) -> DBError {
+    if let Some(rest) = line.strip_prefix("TRYAGAIN ") {
+        let (hint, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+        if let Some(Ok(ms)) = hint.strip_prefix("retry-after-ms=").map(str::parse) {
+            return DBError::WriteStall { retry_after_ms: ms, reason: reason.to_string() };
+        }
+    }
+    DBError::Other(line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_round_trip_as_resp_arrays() {
+        let cmd = encode_array(&[b"SET", b"a key", b"line\r\nbreak"]);
+        assert_eq!(&cmd[..8], b"*3\r\n$3\r\n");
+        let (args, used) = parse_command(&cmd).unwrap().unwrap();
+        assert_eq!(args, vec![b"SET".to_vec(), b"a key".to_vec(), b"line\r\nbreak".to_vec()]);
+        assert_eq!(used, cmd.len());
+
+        // 没收全的每个前缀都等下一次读；后面跟着的下一条不算进来
+        for cut in 0..cmd.len() {
+            assert!(parse_command(&cmd[..cut]).unwrap().is_none(), "{}", cut);
+        }
+        let mut two = cmd.clone();
+        two.extend_from_slice(&encode_array(&[b"PING"]));
+        assert_eq!(parse_command(&two).unwrap().unwrap().1, cmd.len());
+
+        assert!(parse_command(b"*1\r\n$x\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$1\r\nab\r\n").is_err());
+        assert!(parse_command(b"*1\r\n+OK\r\n").is_err());
+    }
+
+    #[test]
+    fn write_stalls_round_trip_as_tryagain() {
+        let stall = DBError::WriteStall { retry_after_ms: 250, reason: "too many L0 files".into() };
+        let line = encode_error(&stall);
+        assert_eq!(line, "-TRYAGAIN retry-after-ms=250 too many L0 files\r\n");
+        match parse_error(line.trim_start_matches('-').trim_end()) {
+            DBError::WriteStall { retry_after_ms, reason } => {
+                assert_eq!((retry_after_ms, reason.as_str()), (250, "too many L0 files"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(encode_error(&DBError::Other("boom".into())).starts_with("-ERR "));
+        assert!(matches!(parse_error("ERR boom"), DBError::Other(_)));
+        assert!(matches!(parse_error("TRYAGAIN retry-after-ms=soon"), DBError::Other(_)));
+    }
+}
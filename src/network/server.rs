@@ -0,0 +1,175 @@
+//! Lifecycle of the text protocol server.
+//!
+//! - `rebind` moves the server to another address without dropping the
+//!   connections already open on the old one.
+//! - `drain` stops accepting, lets every connection finish the request it is
+//!   serving, then closes them.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use crate::DBImpl;
+use crate::error::DBError;
+use crate::network::worker::{handle_connection, SharedStorage};
+
+pub struct Server {
+    local_addr: Mutex<SocketAddr>,
+    rebind_tx: mpsc::Sender<TcpListener>,
+    drain_tx: watch::Sender<bool>,
+    accept_loop: tokio::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Server {
+    /// Binds `addr` and starts accepting on the current runtime.
+    pub async fn start(addr: &str, storage: SharedStorage, db: Option<Arc<DBImpl>>) -> Result<Self, DBError> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (rebind_tx, rebind_rx) = mpsc::channel(1);
+        let (drain_tx, drain_rx) = watch::channel(false);
+        let accept_loop = tokio::spawn(accept_loop(listener, rebind_rx, drain_rx, storage, db));
+        Ok(Self {
+            local_addr: Mutex::new(local_addr),
+            rebind_tx,
+            drain_tx,
+            accept_loop: tokio::sync::Mutex::new(Some(accept_loop)),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        *self.local_addr.lock().unwrap()
+    }
+
+    /// Starts accepting on `addr` and closes the old listener. Connections
+    /// accepted on the old address keep being served. Bind errors leave the
+    /// server on its old address.
+    pub async fn rebind(&self, addr: &str) -> Result<SocketAddr, DBError> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        self.rebind_tx
+            .send(listener)
+            .await
+            .map_err(|_| DBError::Other("server is draining".into()))?;
+        *self.local_addr.lock().unwrap() = local_addr;
+        Ok(local_addr)
+    }
+
+    /// Stops accepting new connections and waits until every open connection
+    /// has finished its in-flight request and closed. Idempotent.
+    pub async fn drain(&self) {
+        let _ = self.drain_tx.send(true);
+        if let Some(handle) = self.accept_loop.lock().await.take() {
+            let _ = handle.await;
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.drain_tx.borrow()
+    }
+}
+
+async fn accept_loop(
+    mut listener: TcpListener,
+    mut rebind_rx: mpsc::Receiver<TcpListener>,
+    mut drain_rx: watch::Receiver<bool>,
+    storage: SharedStorage,
+    db: Option<Arc<DBImpl>>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                // accept 出错（比如 fd 用光）不退出，继续等下一个
+                if let Ok((socket, _)) = accepted {
+                    connections.spawn(handle_connection(socket, storage.clone(), db.clone(), drain_rx.clone()));
+                }
+            }
+            Some(new_listener) = rebind_rx.recv() => {
+                // 旧 listener 在这里 drop，端口释放
+                listener = new_listener;
+            }
+            // 回收已经断开的连接
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = drain_rx.changed() => break,
+        }
+    }
+    drop(listener);
+    rebind_rx.close();
+
+    // 连接看到 drain 信号后，处理完手上的请求就退出
+    while connections.join_next().await.is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use crate::engine::mem::Storage;
+
+    async fn request(socket: &mut TcpStream, cmd: &str) -> String {
+        socket.write_all(cmd.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 256];
+        let n = socket.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    fn storage() -> SharedStorage {
+        Arc::new(tokio::sync::Mutex::new(Storage::new()))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rebind_keeps_connections_on_the_old_address() {
+        let server = Server::start("127.0.0.1:0", storage(), None).await.unwrap();
+        let old = server.local_addr();
+        let mut kept = TcpStream::connect(old).await.unwrap();
+        assert_eq!(request(&mut kept, "SET k v").await, "+OK\r\n");
+
+        let new = server.rebind("127.0.0.1:0").await.unwrap();
+        assert_ne!(new, old);
+        assert_eq!(server.local_addr(), new);
+        let mut moved = TcpStream::connect(new).await.unwrap();
+        assert_eq!(request(&mut moved, "GET k").await, "$1\r\nv\r\n");
+        assert_eq!(request(&mut kept, "PING").await, "+PONG\r\n");
+
+        // 绑不上就留在原地址
+        assert!(server.rebind("256.0.0.1:0").await.is_err());
+        assert_eq!(server.local_addr(), new);
+        server.drain().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resp_commands_may_arrive_in_pieces() {
+        let server = Server::start("127.0.0.1:0", storage(), None).await.unwrap();
+        let mut socket = TcpStream::connect(server.local_addr()).await.unwrap();
+        let cmd = crate::network::resp::encode_array(&[b"SET", b"a key", b"a\r\nvalue"]);
+        let (head, tail) = cmd.split_at(cmd.len() / 2);
+        socket.write_all(head).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(request(&mut socket, std::str::from_utf8(tail).unwrap()).await, "+OK\r\n");
+
+        let get = crate::network::resp::encode_array(&[b"GET", b"a key"]);
+        assert_eq!(request(&mut socket, std::str::from_utf8(&get).unwrap()).await, "$8\r\na\r\nvalue\r\n");
+        server.drain().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drain_closes_idle_connections_and_stops_accepting() {
+        let server = Server::start("127.0.0.1:0", storage(), None).await.unwrap();
+        let addr = server.local_addr();
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(request(&mut idle, "PING").await, "+PONG\r\n");
+
+        assert!(!server.is_draining());
+        server.drain().await;
+        assert!(server.is_draining());
+        // drain 返回时连接都已经关了
+        let mut buf = [0u8; 16];
+        assert_eq!(idle.read(&mut buf).await.unwrap_or(0), 0);
+        assert!(TcpStream::connect(addr).await.is_err());
+        assert!(server.rebind("127.0.0.1:0").await.is_err());
+        // 再 drain 一次什么都不做
+        server.drain().await;
+    }
+}
@@ -0,0 +1,85 @@
+//! `KNN` command of the text protocol.
+//!
+//! ```text
+//! KNN <cf> <k> <x1,x2,...> [metric=l2|cosine|ip] [threshold=<f32>]
+//!     [filter=prefix:<p>|range:<a>..<b>] [page=<token>] [payload=1]
+//!     [ef=<n>] [nprobe=<n>] [exhaustive=1] [fallback=1] [index=<name>]
+//! ```
+//!
+//! Reply is an array with one `[key, score, payload]` entry per hit (payload is
+//! a null bulk unless requested), followed by the next page token (null bulk
+//! on the last page).
+
+use crate::db::db_impl::DBImpl;
+use crate::error::DBError;
+use crate::engine::mem::ColumnFamilyId;
+use crate::network::resp;
+use crate::vector::{KeyFilter, KnnRequest, KnnResponse, Metric};
+
+pub fn parse_knn_command(args: &[&str]) -> Result<(ColumnFamilyId, KnnRequest), DBError> {
+    let bad = |what: &str| DBError::InvalidArgument(format!("KNN: {}", what));
+    if args.len() < 3 {
+        return Err(bad("usage: KNN <cf> <k> <x1,x2,...> [options]"));
+    }
+    let cf: ColumnFamilyId = args[0].parse().map_err(|_| bad("bad column family id"))?;
+    let k: usize = args[1].parse().map_err(|_| bad("bad k"))?;
+    let query = args[2]
+        .split(',')
+        .map(|x| x.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| bad("bad query vector"))?;
+
+    let mut request = KnnRequest::new(query, k);
+    for opt in &args[3..] {
+        let (name, value) = opt.split_once('=').ok_or_else(|| bad(&format!("bad option {}", opt)))?;
+        match name.to_ascii_lowercase().as_str() {
+            "index" => request.index = Some(value.to_string()),
+            "metric" => request.metric = Some(Metric::parse(value).ok_or_else(|| bad("unknown metric"))?),
+            "threshold" => request.score_threshold = Some(value.parse().map_err(|_| bad("bad threshold"))?),
+            "filter" => request.filter = Some(KeyFilter::parse(value)?),
+            "page" => request.page_token = Some(value.to_string()),
+            "payload" => request.with_payload = flag(value),
+            "ef" => request.search.ef_search = Some(value.parse().map_err(|_| bad("bad ef"))?),
+            "nprobe" => request.search.nprobe = Some(value.parse().map_err(|_| bad("bad nprobe"))?),
+            "exhaustive" => request.search.exhaustive = flag(value),
+            "fallback" => request.search.exhaustive_fallback = flag(value),
+            _ => return Err(bad(&format!("unknown option {}", name))),
+        }
+    }
+    Ok((cf, request))
+}
+
+fn flag(value: &str) -> bool {
+    value == "1" || value.eq_ignore_ascii_case("true")
+}
+
+fn bulk(out: &mut String, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(b) => {
+            out.push_str(&format!("${}\r\n", b.len()));
+            out.push_str(&String::from_utf8_lossy(b));
+            out.push_str("\r\n");
+        }
+        None => out.push_str("$-1\r\n"),
+    }
+}
+
+pub fn encode_knn_response(response: &KnnResponse) -> String {
+    let mut out = format!("*{}\r\n", response.hits.len() + 1);
+    for hit in &response.hits {
+        out.push_str("*3\r\n");
+        bulk(&mut out, Some(&hit.key));
+        bulk(&mut out, Some(hit.score.to_string().as_bytes()));
+        bulk(&mut out, hit.payload.as_deref());
+    }
+    bulk(&mut out, response.next_page_token.as_deref().map(str::as_bytes));
+    out
+}
+
+pub fn handle_knn(db: &DBImpl, args: &[&str]) -> String {
+    let result = parse_knn_command(args).and_then(|(cf, request)| db.knn_search(cf, &request));
+    match result {
+        Ok(response) => encode_knn_response(&response),
+        Err(e) => resp::encode_error(&e),
+    }
+}
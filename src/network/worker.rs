@@ -1,65 +1,128 @@
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
 use std::sync::Arc;
+use crate::DBImpl;
 use crate::engine::mem::Storage;
+use crate::network::server::Server;
+use crate::network::{resp, vector_api};
+use crate::util::TraceContext;
 
-type SharedStorage = Arc<tokio::sync::Mutex<Storage>>;
+pub type SharedStorage = Arc<tokio::sync::Mutex<Storage>>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:6379").await?;
     let storage = Arc::new(tokio::sync::Mutex::new(Storage::new()));
+    let server = Server::start("0.0.0.0:6379", storage, None)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
 
-    loop {
-        let (socket, _) = listener.accept().await?;
-        let storage_clone = storage.clone();
-        tokio::spawn(async move {
-            handle_connection(socket, storage_clone).await;
-        });
-    }
+    // Ctrl-C：不再接新连接，等正在处理的请求做完再退出
+    tokio::signal::ctrl_c().await?;
+    server.drain().await;
+    Ok(())
 }
 
-// 连接处理
-async fn handle_connection(mut socket: TcpStream, storage: SharedStorage) {
+// 连接处理；`draining` 变 true 后处理完当前请求就关连接
+pub(crate) async fn handle_connection(
+    mut socket: TcpStream,
+    storage: SharedStorage,
+    db: Option<Arc<DBImpl>>,
+    mut draining: watch::Receiver<bool>,
+) {
     let mut buf = [0u8; 1024];
+    let mut pending = Vec::new();
     loop {
-        let n = match socket.read(&mut buf).await {
-            Ok(0) => return, // connection closed
-            Ok(n) => n,
-            Err(_) => return,
+        if *draining.borrow() {
+            return;
+        }
+        let n = tokio::select! {
+            read = socket.read(&mut buf) => match read {
+                Ok(0) => return, // connection closed
+                Ok(n) => n,
+                Err(_) => return,
+            },
+            // 空闲连接直接关
+            _ = draining.changed() => return,
         };
 
-        // 简单 RESP parser demo (这里只是伪解析)
-        let command = String::from_utf8_lossy(&buf[..n]);
-        let response = process_command(command.to_string(), storage.clone()).await;
+        pending.extend_from_slice(&buf[..n]);
 
-        let _ = socket.write_all(response.as_bytes()).await;
+        // RESP 数组可能分几次读到，收全了再处理；inline 命令还是一次读到的就是一条
+        while !pending.is_empty() {
+            let tokens: Vec<String> = if pending[0] == b'*' {
+                match resp::parse_command(&pending) {
+                    Ok(Some((args, used))) => {
+                        pending.drain(..used);
+                        args.iter().map(|a| String::from_utf8_lossy(a).into_owned()).collect()
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // framing 坏了就没法找到下一条命令的开头，直接断开
+                        let _ = socket.write_all(resp::encode_error(&e).as_bytes()).await;
+                        return;
+                    }
+                }
+            } else {
+                let line = String::from_utf8_lossy(&pending).into_owned();
+                pending.clear();
+                line.split_whitespace().map(str::to_string).collect()
+            };
+            let response = process_command(tokens, storage.clone(), db.clone()).await;
+            let _ = socket.write_all(&response).await;
+        }
     }
 }
 
-async fn process_command(cmd: String, storage: SharedStorage) -> String {
-    let tokens: Vec<&str> = cmd.trim().split_whitespace().collect();
+// 命令前可以带 `traceparent=<W3C traceparent>`，DB 里的操作会挂到这个 trace 下
+async fn process_command(mut tokens: Vec<String>, storage: SharedStorage, db: Option<Arc<DBImpl>>) -> Vec<u8> {
+    let mut trace = None;
+    if let Some(tp) = tokens.first().and_then(|t| t.strip_prefix("traceparent=")) {
+        match TraceContext::parse(tp) {
+            Ok(ctx) => trace = Some(ctx),
+            Err(e) => return resp::encode_error(&e).into_bytes(),
+        }
+        tokens.remove(0);
+    }
     if tokens.is_empty() {
-        return "-ERR empty command\r\n".to_string();
+        return b"-ERR empty command\r\n".to_vec();
     }
 
-    match tokens[0].to_uppercase().as_str() {
+    let reply = match tokens[0].to_uppercase().as_str() {
         "PING" => "+PONG\r\n".to_string(),
         "SET" => {
-            if tokens.len() < 3 { return "-ERR SET needs key value\r\n".to_string(); }
-            let key = tokens[1].to_string();
-            let value = tokens[2].to_string();
+            if tokens.len() < 3 { return b"-ERR SET needs key value\r\n".to_vec(); }
+            let value = tokens.swap_remove(2);
+            let key = tokens.swap_remove(1);
             storage.lock().await.set(key, value);
             "+OK\r\n".to_string()
         },
         "GET" => {
-            if tokens.len() < 2 { return "-ERR GET needs key\r\n".to_string(); }
-            let key = tokens[1];
-            match storage.lock().await.get(key) {
+            if tokens.len() < 2 { return b"-ERR GET needs key\r\n".to_vec(); }
+            match storage.lock().await.get(&tokens[1]) {
                 Some(v) => format!("${}\r\n{}\r\n", v.len(), v),
                 None => "$-1\r\n".to_string()
             }
         },
+        // SCAN <start> <count>：key >= start 的前 count 条，回 key、value 交替的数组
+        "SCAN" => {
+            let count = tokens.get(2).and_then(|c| c.parse::<usize>().ok());
+            let Some(count) = count else { return b"-ERR SCAN needs start count\r\n".to_vec() };
+            let kvs = storage.lock().await.scan(&tokens[1], count);
+            let items: Vec<&[u8]> = kvs.iter().flat_map(|(k, v)| [k.as_bytes(), v.as_bytes()]).collect();
+            return resp::encode_array(&items);
+        },
+        // 向量检索要走 DBImpl，内存 Storage 不支持
+        "KNN" => match db {
+            Some(db) => tokio::task::spawn_blocking(move || {
+                let args: Vec<&str> = tokens[1..].iter().map(String::as_str).collect();
+                TraceContext::scope(trace, || vector_api::handle_knn(&db, &args))
+            })
+            .await
+            .unwrap_or_else(|e| format!("-ERR {}\r\n", e)),
+            None => "-ERR KNN needs a DB-backed server\r\n".to_string(),
+        },
         _ => "-ERR unknown command\r\n".to_string()
-    }
+    };
+    reply.into_bytes()
 }
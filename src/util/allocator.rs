@@ -0,0 +1,111 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bumpalo::Bump;
+
+/// Memory source for block cache buffers and memtable arenas.
+///
+/// The default implementation uses the global allocator and keeps a small
+/// pool of freed memtable arenas; a custom implementation can route blocks
+/// and arenas to dedicated allocator arenas (e.g. jemalloc `arena.<i>`).
+pub trait MemoryAllocator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Zeroed buffer of `len` bytes for a block read from disk.
+    fn allocate_block(&self, len: usize) -> Vec<u8>;
+
+    /// Arena for a new memtable.
+    fn new_arena(&self) -> Bump;
+
+    /// Arena of a dropped memtable; may be kept for reuse.
+    fn release_arena(&self, arena: Bump);
+}
+
+/// Global allocator + reuse of freed memtable arenas.
+///
+/// Under flush churn memtables are created and dropped continuously; handing
+/// the (reset) arena chunks to the next memtable avoids returning large
+/// blocks to the allocator only to request them again right away.
+pub struct DefaultAllocator {
+    free_arenas: Mutex<Vec<Bump>>,
+    max_free_arenas: usize,
+    reused_arenas: AtomicU64,
+}
+
+impl DefaultAllocator {
+    pub fn new(max_free_arenas: usize) -> Self {
+        Self {
+            free_arenas: Mutex::new(Vec::new()),
+            max_free_arenas,
+            reused_arenas: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of memtables that got a recycled arena.
+    pub fn reused_arenas(&self) -> u64 {
+        self.reused_arenas.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for DefaultAllocator {
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl MemoryAllocator for DefaultAllocator {
+    fn name(&self) -> &'static str {
+        "default"
+    }
+
+    fn allocate_block(&self, len: usize) -> Vec<u8> {
+        vec![0u8; len]
+    }
+
+    fn new_arena(&self) -> Bump {
+        match self.free_arenas.lock().unwrap().pop() {
+            Some(bump) => {
+                self.reused_arenas.fetch_add(1, Ordering::Relaxed);
+                bump
+            }
+            None => Bump::new(),
+        }
+    }
+
+    fn release_arena(&self, mut arena: Bump) {
+        let mut free = self.free_arenas.lock().unwrap();
+        if free.len() < self.max_free_arenas {
+            // reset 只保留最大的 chunk，其余还给系统
+            arena.reset();
+            free.push(arena);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_arenas_are_reused_up_to_the_limit() {
+        let allocator = DefaultAllocator::new(1);
+        assert_eq!(allocator.allocate_block(16), vec![0u8; 16]);
+
+        let arena = allocator.new_arena();
+        arena.alloc_slice_fill_copy(4096, 7u8);
+        let second = allocator.new_arena();
+        assert_eq!(allocator.reused_arenas(), 0);
+
+        // 只留一个，多的还给系统
+        allocator.release_arena(arena);
+        allocator.release_arena(second);
+        assert_eq!(allocator.free_arenas.lock().unwrap().len(), 1);
+
+        let mut reused = allocator.new_arena();
+        assert_eq!(allocator.reused_arenas(), 1);
+        // chunk 还在，但 reset 过，里面是空的
+        assert!(reused.allocated_bytes() >= 4096);
+        assert!(reused.iter_allocated_chunks().all(|c| c.is_empty()));
+        let _ = allocator.new_arena();
+        assert_eq!(allocator.reused_arenas(), 1);
+    }
+}
@@ -9,6 +9,10 @@ pub const NUM_LEVELS: usize = 7;
 pub const MIN_BLOCK_SIZE: usize = 1024;
 pub const BLOCK_TRAILER_SIZE: usize = 5;
 pub const NO_COMPRESSION: u8 = 0;
+/// 除了 SST 以外 DB 还要开着的文件（WAL、MANIFEST、归档索引……），从 max_open_files 里先扣掉
+pub const NON_TABLE_FILES: usize = 10;
 // RocksDB/LevelDB magic（不同实现可能不同；你可以先用固定 magic）
 // 这里用 LevelDB 的 classic magic 示例；你也可以换成 RocksDB 的。
-pub const TABLE_MAGIC: u64 = 0xdb4775248b80fb57;
\ No newline at end of file
+pub const TABLE_MAGIC: u64 = 0xdb4775248b80fb57;
+// 新版（self-describing）footer 使用的 magic，与旧 48 字节布局区分
+pub const TABLE_MAGIC_V2: u64 = 0x88e2_41b7_85f4_cff7;
@@ -1,24 +1,36 @@
 use std::{fs, io};
 use std::io::{Read, Write};
 use config::{Config, File, FileFormat};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use crate::DBError;
+use crate::db::event_listener::EventListeners;
+use crate::db::snapshot::Snapshot;
 use crate::engine::mem::memtable_set::CfType;
-use crate::engine::sst::block::FilterPolicy;
-use crate::util::Options;
-use crate::util::options::{CompressionType, OpenOptions, OptionsFile};
+use crate::engine::sst::block::{BloomFilterPolicy, FilterPolicy, IndexType};
+use crate::engine::sst::format::{ChecksumType, CURRENT_FORMAT_VERSION};
+use crate::engine::version::ManifestReader;
+use crate::util::{slice_transform_from_name, Options, SliceTransform};
+use crate::vector::{HnswParams, Metric, VectorIndexType};
+use crate::util::constants::{FIRST_MANIFEST, NUM_LEVELS};
+use crate::util::file_resolver::FileResolver;
+use crate::util::options::{CompactionPri, CompactionStyle, CompressionType, FifoCompactionOptions, OpenOptions, OptionsFile, SstLayout, UniversalCompactionOptions};
 
 #[derive(Debug, Deserialize, Default)]
 pub struct DbConfigFile {
     // open 行为
     pub create_if_missing: Option<bool>,
+    pub error_if_exists: Option<bool>,
 
     // 路径
     pub wal_dir: Option<PathBuf>,
     pub sst_dir: Option<PathBuf>,
     pub manifest_dir: Option<PathBuf>,
+    pub wal_archive_dir: Option<PathBuf>,
+    pub sst_layout: Option<SstLayout>,
+    pub sst_tier_dirs: Option<Vec<PathBuf>>,
 
     // Options 覆盖
     pub options: Option<OptionsFile>,
@@ -32,6 +44,18 @@ pub struct DbConfigFile {
 }
 
 
+/// What `DbConfig::db_state` found on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbState {
+    /// No DB files; the directories may not exist or be empty, or hold only
+    /// the first MANIFEST of a creation that crashed before writing CURRENT.
+    Missing,
+    /// CURRENT names a MANIFEST that is there.
+    Existing,
+    /// DB files without a usable CURRENT -> MANIFEST; says what was found.
+    Partial(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct DbConfig {
     /// DB 根目录
@@ -46,6 +70,20 @@ pub struct DbConfig {
     /// Manifest 文件目录
     pub manifest_dir: PathBuf,
 
+    /// 归档 WAL 的目录；None 表示不归档
+    pub wal_archive_dir: Option<PathBuf>,
+
+    /// 新 SST 放在 sst_dir 下的什么位置
+    pub sst_layout: SstLayout,
+
+    /// 按 file number 找已有 SST，sst_dir 找不到再去这些 tier 目录
+    pub sst_tier_dirs: Vec<PathBuf>,
+
+    file_resolver: Arc<FileResolver>,
+
+    /// `Options::listeners` 加上之后 `DBImpl::add_listener` 注册的
+    listeners: Arc<EventListeners>,
+
     pub options: Arc<Options>,
 }
 
@@ -53,6 +91,158 @@ pub struct DbConfig {
 pub struct WriteOptions {
     /// Require strong consistency WAL writes (wait for sync thread to advance).
     pub sync: bool,
+    /// Skip the WAL; the write is lost if the process dies before its memtable is flushed.
+    #[serde(default)]
+    pub disable_wal: bool,
+    /// Id of a `lock_range` lease this write holds; with `enforce_range_locks`
+    /// it may write into that lease's range.
+    #[serde(default)]
+    pub range_lease: Option<u64>,
+}
+
+impl WriteOptions {
+    pub fn with_range_lease(mut self, lease_id: u64) -> Self {
+        self.range_lease = Some(lease_id);
+        self
+    }
+}
+
+/// Settings of a single read.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Read as of this snapshot instead of the latest sequence.
+    pub snapshot: Option<Snapshot>,
+    /// Put data blocks read from disk into the block cache. Turn off for
+    /// one-off scans so they don't evict the hot blocks.
+    pub fill_cache: bool,
+    /// Verify the crc32c trailer of each SST block read from disk. Blocks
+    /// served from the block cache are not checked again.
+    pub verify_checksums: bool,
+    /// Iterators skip keys below this bound (inclusive).
+    pub iterate_lower_bound: Option<Vec<u8>>,
+    /// Iterators stop before this bound (exclusive).
+    pub iterate_upper_bound: Option<Vec<u8>>,
+    /// After a seek, iterators only return keys with the same prefix (per the
+    /// CF's `prefix_extractor`) as the seek target, and skip SSTs whose prefix
+    /// filter rules the prefix out. No effect on CFs without an extractor.
+    pub prefix_same_as_start: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            snapshot: None,
+            fill_cache: true,
+            verify_checksums: true,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+            prefix_same_as_start: false,
+        }
+    }
+}
+
+impl ReadOptions {
+    pub fn with_snapshot(mut self, snapshot: &Snapshot) -> Self {
+        self.snapshot = Some(snapshot.clone());
+        self
+    }
+
+    pub fn with_fill_cache(mut self, fill_cache: bool) -> Self {
+        self.fill_cache = fill_cache;
+        self
+    }
+
+    pub fn with_verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    pub fn with_iterate_bounds(mut self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Self {
+        self.iterate_lower_bound = lower.map(<[u8]>::to_vec);
+        self.iterate_upper_bound = upper.map(<[u8]>::to_vec);
+        self
+    }
+
+    pub fn with_prefix_same_as_start(mut self, prefix_same_as_start: bool) -> Self {
+        self.prefix_same_as_start = prefix_same_as_start;
+        self
+    }
+}
+
+/// Per column family quotas. 0 means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QuotaOptions {
+    pub write_ops_per_sec: u64,
+    pub write_bytes_per_sec: u64,
+    pub read_ops_per_sec: u64,
+    pub read_bytes_per_sec: u64,
+}
+
+impl QuotaOptions {
+    pub fn is_unlimited(&self) -> bool {
+        self.write_ops_per_sec == 0
+            && self.write_bytes_per_sec == 0
+            && self.read_ops_per_sec == 0
+            && self.read_bytes_per_sec == 0
+    }
+}
+
+/// Settings of one vector index.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VectorIndexOptions {
+    /// Metric used when a query doesn't override it.
+    pub metric: Metric,
+    /// Expected dimension; writes with other sizes are rejected. 0 accepts any.
+    pub dimension: usize,
+    /// `flat` scans every vector; `hnsw` keeps an in-memory graph.
+    pub index: VectorIndexType,
+    pub hnsw: HnswParams,
+    /// Rebuild the HNSW graph in the background once this fraction of its
+    /// nodes are overwritten or deleted. 0 disables automatic rebuilds.
+    pub rebuild_deleted_ratio: f64,
+    /// Relink the graph around deleted nodes in the background after this many
+    /// deletes or overwrites. 0 leaves them to the next rebuild.
+    pub repair_after_deletes: usize,
+    /// Keep a built HNSW graph on disk and read it through the vector graph
+    /// cache instead of holding it in memory. Requires a fixed dimension.
+    pub on_disk: bool,
+}
+
+impl Default for VectorIndexOptions {
+    fn default() -> Self {
+        Self {
+            metric: Metric::default(),
+            dimension: 0,
+            index: VectorIndexType::default(),
+            hnsw: HnswParams::default(),
+            rebuild_deleted_ratio: 0.2,
+            repair_after_deletes: 256,
+            on_disk: false,
+        }
+    }
+}
+
+/// Vector search settings of a column family.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VectorOptions {
+    /// The unnamed index over values written with `put_vector`.
+    #[serde(flatten)]
+    pub default: VectorIndexOptions,
+    /// Named indexes over the columns of values written with `put_vectors`,
+    /// keyed by column name.
+    pub indexes: BTreeMap<String, VectorIndexOptions>,
+}
+
+impl VectorOptions {
+    /// "" is the unnamed index.
+    pub fn index(&self, name: &str) -> Option<&VectorIndexOptions> {
+        match name {
+            "" => Some(&self.default),
+            _ => self.indexes.get(name),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -65,15 +255,175 @@ pub struct ColumnFamilyOptions {
 
     pub table_options: TableOptions,
 
+    /// Splits keys into prefixes. With a filter policy, SSTs also get a bloom
+    /// filter over the prefixes for `ReadOptions::prefix_same_as_start` scans.
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+
     // Compression
     pub compression: CompressionType,
+
+    /// ops/sec and bytes/sec limits enforced by `DBImpl::write`/`get`
+    pub quota: QuotaOptions,
+
+    /// Which file a level compaction picks first.
+    pub compaction_pri: CompactionPri,
+
+    /// Leveled, universal (size-tiered) or FIFO compaction.
+    pub compaction_style: CompactionStyle,
+
+    /// Used when `compaction_style` is `Universal`.
+    pub universal: UniversalCompactionOptions,
+
+    /// Used when `compaction_style` is `Fifo`.
+    pub fifo: FifoCompactionOptions,
+
+    /// Defaults for `put_vector` / `knn_search`.
+    pub vector: VectorOptions,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct TableOptions {
     pub block_size: usize,
     pub restart_interval: usize,
     pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+
+    /// Footer format version; `LEGACY_FORMAT_VERSION` writes the old 48-byte footer.
+    pub format_version: u32,
+
+    /// Checksum algorithm recorded in the footer.
+    pub checksum: ChecksumType,
+
+    /// Restart interval of the index block. Index values between restart
+    /// points are delta-encoded when `format_version >= 2`.
+    pub index_block_restart_interval: usize,
+
+    /// Index layout. `BinarySearchWithFirstKey` also stores each data block's
+    /// first key so seeks can skip loading the block.
+    pub index_type: IndexType,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4 * 1024,
+            restart_interval: 16,
+            filter_policy: None,
+            format_version: CURRENT_FORMAT_VERSION,
+            checksum: ChecksumType::Crc32c,
+            index_block_restart_interval: 16,
+            index_type: IndexType::BinarySearch,
+        }
+    }
+}
+
+/// `ColumnFamilyOptions` as `create_column_family` records them in the MANIFEST.
+///
+/// The filter policy and prefix extractor are kept by name. One that can't be
+/// rebuilt from its name comes back as the global CF options' one.
+#[derive(Serialize, Deserialize)]
+struct CfOptionsRecord {
+    level_compaction_dynamic_size: bool,
+    target_file_size: u64,
+    block_size: usize,
+    restart_interval: usize,
+    filter_policy: Option<String>,
+    bloom_bits_per_key: Option<usize>,
+    format_version: u32,
+    checksum: u8,
+    index_block_restart_interval: usize,
+    index_type: u8,
+    prefix_extractor: Option<String>,
+    compression: u8,
+    quota: QuotaOptions,
+    compaction_pri: CompactionPri,
+    compaction_style: CompactionStyle,
+    universal: UniversalCompactionOptions,
+    fifo: FifoCompactionOptions,
+    vector: VectorOptions,
+}
+
+impl ColumnFamilyOptions {
+    /// Encodes the options for a CF_ADD record; read back with `decode_record`.
+    pub fn encode_record(&self) -> Vec<u8> {
+        let t = &self.table_options;
+        let record = CfOptionsRecord {
+            level_compaction_dynamic_size: self.level_compaction_dynamic_size,
+            target_file_size: self.target_file_size,
+            block_size: t.block_size,
+            restart_interval: t.restart_interval,
+            filter_policy: t.filter_policy.as_ref().map(|p| p.name().to_string()),
+            bloom_bits_per_key: t.filter_policy.as_ref().and_then(|p| p.bits_per_key()),
+            format_version: t.format_version,
+            checksum: t.checksum as u8,
+            index_block_restart_interval: t.index_block_restart_interval,
+            index_type: t.index_type.to_u8(),
+            prefix_extractor: self.prefix_extractor.as_ref().map(|p| p.name().to_string()),
+            compression: self.compression.to_u8(),
+            quota: self.quota.clone(),
+            compaction_pri: self.compaction_pri,
+            compaction_style: self.compaction_style,
+            universal: self.universal.clone(),
+            fifo: self.fifo.clone(),
+            vector: self.vector.clone(),
+        };
+        // 全是普通字段和 String key 的 map，序列化不会失败
+        serde_json::to_vec(&record).expect("column family options serialize to JSON")
+    }
+
+    /// Decodes `encode_record` output. `base` (the global options of the CF's
+    /// type) supplies trait objects that can't be rebuilt from their names.
+    pub fn decode_record(data: &[u8], base: &ColumnFamilyOptions) -> Result<Self, DBError> {
+        let r: CfOptionsRecord = serde_json::from_slice(data)
+            .map_err(|e| DBError::Corruption(format!("bad column family options record: {}", e)))?;
+        let bad = |what: &str, v: u8| DBError::Corruption(format!("bad {} {} in column family options", what, v));
+
+        let base_filter = base.table_options.filter_policy.as_ref();
+        let filter_policy = match (r.filter_policy.as_deref(), r.bloom_bits_per_key) {
+            (None, _) => None,
+            (Some(name), bits) if base_filter.is_some_and(|p| p.name() == name && p.bits_per_key() == bits) => {
+                base_filter.cloned()
+            }
+            (Some("custom.BloomFilter"), Some(bits)) => {
+                Some(Arc::new(BloomFilterPolicy::new(bits)) as Arc<dyn FilterPolicy>)
+            }
+            (Some(name), _) => {
+                log::warn!("filter policy {:?} can't be rebuilt, using the global one", name);
+                base_filter.cloned()
+            }
+        };
+
+        let base_prefix = base.prefix_extractor.as_ref();
+        let prefix_extractor = match r.prefix_extractor.as_deref() {
+            None => None,
+            Some(name) if base_prefix.is_some_and(|p| p.name() == name) => base_prefix.cloned(),
+            Some(name) => slice_transform_from_name(name).or_else(|| {
+                log::warn!("prefix extractor {:?} can't be rebuilt, using the global one", name);
+                base_prefix.cloned()
+            }),
+        };
+
+        Ok(Self {
+            level_compaction_dynamic_size: r.level_compaction_dynamic_size,
+            target_file_size: r.target_file_size,
+            table_options: TableOptions {
+                block_size: r.block_size,
+                restart_interval: r.restart_interval,
+                filter_policy,
+                format_version: r.format_version,
+                checksum: ChecksumType::from_u8(r.checksum).ok_or_else(|| bad("checksum type", r.checksum))?,
+                index_block_restart_interval: r.index_block_restart_interval,
+                index_type: IndexType::from_u8(r.index_type).ok_or_else(|| bad("index type", r.index_type))?,
+            },
+            prefix_extractor,
+            compression: CompressionType::from_u8(r.compression).ok_or_else(|| bad("compression type", r.compression))?,
+            quota: r.quota,
+            compaction_pri: r.compaction_pri,
+            compaction_style: r.compaction_style,
+            universal: r.universal,
+            fifo: r.fifo,
+            vector: r.vector,
+        })
+    }
 }
 
 pub fn load_db_config(db_path: &PathBuf) -> Result<DbConfigFile, DBError> {
@@ -98,6 +448,10 @@ pub fn load_db_config(db_path: &PathBuf) -> Result<DbConfigFile, DBError> {
     cfg.try_deserialize().map_err(|e| DBError::Config(e))
 }
 
+pub fn sst_file_name(file_number: u64) -> String {
+    format!("{:06}.sst", file_number)
+}
+
 impl DbConfigFile {
     pub fn to_open_options(self) -> OpenOptions {
         let mut open = OpenOptions::default();
@@ -105,10 +459,20 @@ impl DbConfigFile {
         if let Some(v) = self.create_if_missing {
             open.create_if_missing = v;
         }
+        if let Some(v) = self.error_if_exists {
+            open.error_if_exists = v;
+        }
 
         open.wal_dir = self.wal_dir;
         open.sst_dir = self.sst_dir;
         open.manifest_dir = self.manifest_dir;
+        open.wal_archive_dir = self.wal_archive_dir;
+        if let Some(layout) = self.sst_layout {
+            open.sst_layout = layout;
+        }
+        if let Some(dirs) = self.sst_tier_dirs {
+            open.sst_tier_dirs = dirs;
+        }
 
         if let Some(w) = self.write {
             let o = &mut open.options;
@@ -130,14 +494,46 @@ impl DbConfigFile {
             apply!(max_write_buffer_number);
             apply!(allow_concurrent_memtable_write);
             apply!(level0_file_num_compaction_trigger);
+            apply!(level0_slowdown_writes_trigger);
+            apply!(level0_stop_writes_trigger);
+            apply!(max_bytes_for_level_base);
+            apply!(max_bytes_for_level_multiplier);
             apply!(max_background_compactions);
+            apply!(max_compactions_per_cf);
             apply!(max_background_flushes);
+            apply!(max_background_queue_len);
+            apply!(pin_background_threads);
+            apply!(background_queue_overflow);
+            apply!(auto_tune_options);
+            apply!(auto_tune_interval_secs);
+            apply!(auto_tune_min_write_buffer_size);
+            apply!(auto_tune_max_write_buffer_size);
+            apply!(auto_tune_max_level0_stop_writes_trigger);
             apply!(compression);
             apply!(block_cache_size);
+            apply!(numa_aware_block_cache);
             apply!(optimize_filters_for_hits);
             apply!(enable_write_ahead_log);
+            apply!(avoid_flush_during_shutdown);
+            apply!(repair_sequence_numbers);
+            apply!(use_fsync);
             apply!(max_open_files);
+            apply!(max_file_opening_threads);
+            apply!(preload_tables_on_open);
+            apply!(preload_bottom_level_on_open);
             apply!(max_manifest_file_size);
+            apply!(time_travel_retention_secs);
+            apply!(max_snapshots);
+            apply!(snapshot_warn_age_secs);
+            apply!(max_iterator_age_secs);
+            apply!(read_sample_rate);
+            apply!(ttl_sweep_interval_secs);
+            apply!(txn_expiration_sweep_interval_ms);
+            apply!(enforce_range_locks);
+            apply!(embedding_threads);
+            apply!(write_stall_retry_after_ms);
+            apply!(warmup_on_open);
+            apply!(vector_graph_cache_size);
         }
 
         if let Some(cf) = self.system_cf {
@@ -172,11 +568,19 @@ impl DbConfig {
             .unwrap_or_else(|| db_path.join("manifest"));
 
         let options = open.to_options();
+        let file_resolver = Arc::new(FileResolver::new(
+            std::iter::once(sst_dir.clone()).chain(open.sst_tier_dirs.iter().cloned()).collect(),
+        ));
         Self {
             db_path,
             wal_dir,
             sst_dir,
             manifest_dir,
+            wal_archive_dir: open.wal_archive_dir.clone(),
+            sst_layout: open.sst_layout,
+            sst_tier_dirs: open.sst_tier_dirs.clone(),
+            file_resolver,
+            listeners: Arc::new(EventListeners::new(open.options.listeners.clone())),
             options: Arc::new(options),
         }
     }
@@ -185,7 +589,15 @@ impl DbConfig {
         fs::create_dir_all(&self.db_path)?;
         fs::create_dir_all(&self.wal_dir)?;
         fs::create_dir_all(&self.sst_dir)?;
+        if self.sst_layout == SstLayout::PerLevel {
+            for level in 0..NUM_LEVELS {
+                fs::create_dir_all(self.level_dir(level))?;
+            }
+        }
         fs::create_dir_all(&self.manifest_dir)?;
+        if let Some(dir) = &self.wal_archive_dir {
+            fs::create_dir_all(dir)?;
+        }
         Ok(())
     }
 
@@ -193,8 +605,77 @@ impl DbConfig {
         self.wal_dir.join(format!("{:06}.log", log_number))
     }
 
-    pub fn sst_path(&self, file_number: u64) -> PathBuf {
-        self.sst_dir.join(format!("{:06}.sst", file_number))
+    /// Where a new SST for `level` is written, per `sst_layout`.
+    pub fn sst_path(&self, level: usize, file_number: u64) -> PathBuf {
+        match self.sst_layout {
+            SstLayout::Flat => self.sst_dir.join(sst_file_name(file_number)),
+            SstLayout::PerLevel => self.level_dir(level).join(sst_file_name(file_number)),
+        }
+    }
+
+    /// Path of an existing SST when only its number is known, in either
+    /// layout or any of `sst_tier_dirs`; see `FileResolver`.
+    pub fn locate_sst(&self, file_number: u64) -> Result<PathBuf, DBError> {
+        self.file_resolver.resolve(file_number)
+    }
+
+    /// `sst_dir` and, when present, its per-level subdirectories.
+    ///
+    /// The tier directories are left out: they may be shared with other DBs, so
+    /// nothing scans them for files this DB does not know about.
+    pub fn sst_dirs(&self) -> Vec<PathBuf> {
+        std::iter::once(self.sst_dir.clone())
+            .chain((0..NUM_LEVELS).map(|level| self.level_dir(level)).filter(|d| d.is_dir()))
+            .collect()
+    }
+
+    /// Finds existing SSTs by number, across layouts and tiers.
+    pub fn file_resolver(&self) -> &FileResolver {
+        &self.file_resolver
+    }
+
+    pub fn listeners(&self) -> &EventListeners {
+        &self.listeners
+    }
+
+    fn level_dir(&self, level: usize) -> PathBuf {
+        self.sst_dir.join(format!("L{}", level))
+    }
+
+    /// Temp file holding the spilled write set of a large transaction.
+    pub fn txn_spill_path(&self, txn_id: u64) -> PathBuf {
+        self.db_path.join(format!("txn-{:06}.spill", txn_id))
+    }
+
+    /// Delete transaction spill files left by a crash; returns how many.
+    pub fn remove_txn_spill_files(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.db_path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "spill") {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Where compaction writes an output before renaming it to `sst_path`.
+    pub fn temp_sst_path(&self, file_number: u64) -> PathBuf {
+        self.sst_dir.join(format!("{:06}.sst.tmp", file_number))
+    }
+
+    /// Delete compaction outputs a crash left behind under their temp names; returns how many.
+    pub fn remove_temp_sst_files(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.sst_dir)? {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(".sst.tmp") {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
     }
 
     pub fn manifest_path(&self, manifest_number: u64) -> PathBuf {
@@ -202,6 +683,17 @@ impl DbConfig {
             .join(format!("MANIFEST-{:06}", manifest_number))
     }
 
+    /// Flush / compaction audit log, next to the MANIFEST.
+    pub fn job_log_path(&self) -> PathBuf {
+        self.manifest_dir.join("JOBLOG")
+    }
+
+    /// Spilled HNSW graphs. Rebuilt from the column families on demand, so the
+    /// directory is emptied on open.
+    pub fn vector_graph_dir(&self) -> PathBuf {
+        self.db_path.join("vector")
+    }
+
     pub fn current_path(&self) -> PathBuf {
         self.db_path.join("CURRENT")
     }
@@ -223,6 +715,21 @@ impl DbConfig {
         Ok(())
     }
 
+    /// Last sequence applied from a replication leader, kept apart from the
+    /// DB's own sequence counter which flushes also advance.
+    pub fn replicated_sequence_path(&self) -> PathBuf {
+        self.db_path.join("REPLICATED")
+    }
+
+    /// `None` when the DB never applied a replicated batch.
+    pub fn read_replicated_sequence(&self) -> io::Result<Option<u64>> {
+        self.read_u64_file("REPLICATED")
+    }
+
+    pub fn write_replicated_sequence(&self, seq: u64) -> io::Result<()> {
+        self.write_u64_file("REPLICATED", seq)
+    }
+
     pub fn read_current_manifest(&self) -> io::Result<PathBuf> {
         let mut s = String::new();
         let mut f = fs::File::open(self.current_path())?;
@@ -232,9 +739,106 @@ impl DbConfig {
         Ok(self.manifest_dir.join(name))
     }
 
+    /// Highest term (leader epoch) this DB has led or followed, the term of
+    /// the fencing tokens it hands out or accepts.
+    pub fn replication_term_path(&self) -> PathBuf {
+        self.db_path.join("TERM")
+    }
+
+    /// `None` when the DB never took part in replication.
+    pub fn read_replication_term(&self) -> io::Result<Option<u64>> {
+        self.read_u64_file("TERM")
+    }
+
+    pub fn write_replication_term(&self, term: u64) -> io::Result<()> {
+        self.write_u64_file("TERM", term)
+    }
+
+    /// db_path 下只存一个数字的小文件
+    fn read_u64_file(&self, name: &str) -> io::Result<Option<u64>> {
+        let s = match fs::read_to_string(self.db_path.join(name)) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        s.trim().parse().map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bad {} file {:?}", name, s)))
+    }
+
+    /// 先写 tmp 再 rename，crash 后要么是旧值要么是新值
+    fn write_u64_file(&self, name: &str, value: u64) -> io::Result<()> {
+        let tmp = self.db_path.join(format!("{}.tmp", name));
+        {
+            let mut f = fs::File::create(&tmp)?;
+            f.write_all(format!("{}\n", value).as_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(tmp, self.db_path.join(name))?;
+        Ok(())
+    }
+
+    /// Anything of a DB is here, whole or not; for refusing to write a new DB
+    /// over it.
     pub fn looks_like_existing_db(&self) -> bool {
-        self.current_path().exists()
-            && self.manifest_dir.exists()
+        self.db_state() != DbState::Missing
+    }
+
+    /// Whether the directories hold a DB that can be opened, nothing, or the
+    /// remains of one (a crash during creation, a lost CURRENT, a MANIFEST
+    /// that was deleted).
+    pub fn db_state(&self) -> DbState {
+        if self.current_path().exists() {
+            return match self.read_current_manifest() {
+                Ok(manifest) if manifest.is_file() => DbState::Existing,
+                Ok(manifest) if manifest == self.manifest_dir => DbState::Partial("CURRENT is empty".into()),
+                Ok(manifest) => DbState::Partial(format!("CURRENT names {:?}, which does not exist", manifest)),
+                Err(e) => DbState::Partial(format!("CURRENT is unreadable: {}", e)),
+            };
+        }
+
+        // 没有 CURRENT：空目录（比如上次建到一半时只建了目录）当作没有；有别的文件就是坏的
+        let mut found = Vec::new();
+        let names = |dir: &PathBuf| -> Vec<String> {
+            fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        };
+        let manifests: Vec<String> = names(&self.manifest_dir).into_iter().filter(|n| n.starts_with("MANIFEST-")).collect();
+        let ssts: usize = self.sst_dirs().iter()
+            .map(|dir| names(dir).into_iter().filter(|n| n.ends_with(".sst")).count())
+            .sum();
+        if ssts > 0 {
+            found.push(format!("{} SST files", ssts));
+        }
+        // WAL 目录本身可能就是 WAL 文件；空的 WAL 里没有数据，不算
+        let non_empty = |p: &Path| fs::metadata(p).is_ok_and(|m| m.len() > 0);
+        let wals = if self.wal_dir.is_file() {
+            usize::from(non_empty(&self.wal_dir))
+        } else {
+            names(&self.wal_dir).into_iter()
+                .filter(|n| n.ends_with(".log") && non_empty(&self.wal_dir.join(n)))
+                .count()
+        };
+        if wals > 0 {
+            found.push(format!("{} WAL files", wals));
+        }
+        // 建库时 CURRENT 写之前 crash：只剩一个只建了默认 CF 的第一个 MANIFEST，重新建就是
+        let bare = found.is_empty()
+            && manifests.len() == 1
+            && manifests[0] == FIRST_MANIFEST
+            && is_bare_manifest(&self.manifest_dir.join(FIRST_MANIFEST));
+        if !manifests.is_empty() && !bare {
+            found.push(format!("{} MANIFEST files", manifests.len()));
+        }
+
+        if found.is_empty() {
+            DbState::Missing
+        } else {
+            DbState::Partial(format!("no CURRENT file, but {}", found.join(", ")))
+        }
     }
 
     pub fn get_table_options(&self, cf_type: CfType) -> &TableOptions {
@@ -256,5 +860,19 @@ impl DbConfig {
     }
 }
 
-
-
+/// MANIFEST 里只有建 CF 的 edit，没有文件、墓碑、预留号：没有记过任何数据
+///
+/// 尾部写了一半读不出来的也算，前面读出来的 edit 符合就行
+fn is_bare_manifest(path: &Path) -> bool {
+    let Ok(mut reader) = ManifestReader::open(path) else { return false };
+    let mut bare = true;
+    let _ = reader.replay(|edit| {
+        bare &= edit.is_cf_add
+            && !edit.is_cf_drop
+            && !edit.touches_version()
+            && edit.next_file_number.is_none()
+            && edit.reserved_sequence.is_none();
+        Ok(())
+    });
+    bare
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use crate::DBError;
+use crate::util::constants::NUM_LEVELS;
+use crate::util::db_config_file::sst_file_name;
+
+/// Finds an existing SST by file number.
+///
+/// SSTs keep the path they were written to: flat in `sst_dir` or under its
+/// `L<level>/` subdirectories depending on the `sst_layout` at the time, or in
+/// one of the `sst_tier_dirs` they were moved to. Everything that opens or
+/// deletes an SST by number (table cache, compaction, obsolete file GC) goes
+/// through here instead of rebuilding the path, so a file is found wherever it
+/// sits. Found paths are cached and trusted without touching the filesystem;
+/// a file moved behind the DB's back is searched for again after `forget`.
+#[derive(Debug)]
+pub struct FileResolver {
+    /// `sst_dir` 在前，然后是各个 tier
+    roots: Vec<PathBuf>,
+    cache: Mutex<HashMap<u64, PathBuf>>,
+}
+
+impl FileResolver {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots, cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// Path of SST `file_number`; `NotFound` naming the searched directories
+    /// when it is nowhere.
+    pub fn resolve(&self, file_number: u64) -> Result<PathBuf, DBError> {
+        if let Some(path) = self.cache.lock().unwrap().get(&file_number) {
+            return Ok(path.clone());
+        }
+        // 找文件要 stat 好几个目录，不拿着锁，别的 file number 的查询不用等
+        let name = sst_file_name(file_number);
+        let path = self.candidate_dirs()
+            .map(|dir| dir.join(&name))
+            .find(|p| p.exists())
+            .ok_or_else(|| DBError::NotFound(format!("SST {} in none of {:?}", name, self.roots)))?;
+        self.cache.lock().unwrap().insert(file_number, path.clone());
+        Ok(path)
+    }
+
+    /// Remembers where a new SST was written, saving the first lookup.
+    pub fn record(&self, file_number: u64, path: PathBuf) {
+        self.cache.lock().unwrap().insert(file_number, path);
+    }
+
+    /// Drops the cached path of a deleted SST.
+    pub fn forget(&self, file_number: u64) {
+        self.cache.lock().unwrap().remove(&file_number);
+    }
+
+    /// 每个 root 先看本身，再看 L0..L6
+    fn candidate_dirs(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.roots.iter().flat_map(|root| {
+            std::iter::once(root.clone())
+                .chain((0..NUM_LEVELS).map(move |level| root.join(format!("L{}", level))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn trusts_the_cached_path_until_forgotten() {
+        let root = std::env::temp_dir().join(format!("vectorkv-resolver-{}", std::process::id()));
+        let tier = root.join("tier");
+        fs::create_dir_all(root.join("L2")).unwrap();
+        fs::create_dir_all(&tier).unwrap();
+        fs::write(root.join("L2").join(sst_file_name(7)), b"").unwrap();
+        let resolver = FileResolver::new(vec![root.clone(), tier.clone()]);
+
+        assert_eq!(resolver.resolve(7).unwrap(), root.join("L2").join(sst_file_name(7)));
+        // 挪到 tier 之后缓存里还是旧路径，不去 stat
+        fs::rename(root.join("L2").join(sst_file_name(7)), tier.join(sst_file_name(7))).unwrap();
+        assert_eq!(resolver.resolve(7).unwrap(), root.join("L2").join(sst_file_name(7)));
+        resolver.forget(7);
+        assert_eq!(resolver.resolve(7).unwrap(), tier.join(sst_file_name(7)));
+
+        assert!(matches!(resolver.resolve(8), Err(DBError::NotFound(_))));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
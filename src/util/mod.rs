@@ -1,8 +1,31 @@
 pub(crate) mod constants;
 mod db_config_file;
 mod options;
+mod options_builder;
+mod statistics;
+mod allocator;
+mod trace;
+mod fs;
+mod slice_transform;
+mod file_resolver;
+mod open_files;
+pub(crate) mod perf_context;
+mod numa;
+#[cfg(test)]
+pub(crate) mod test_util;
 
-pub use constants::{BLOCK_TRAILER_SIZE, FIRST_MANIFEST, MIN_BLOCK_SIZE, NO_COMPRESSION, NUM_LEVELS,
-                    SYSTEM_COLUMN_FAMILY, TABLE_MAGIC, USER_COLUMN_FAMILY};
-pub use db_config_file::{DbConfig, load_db_config, ColumnFamilyOptions, DbConfigFile, WriteOptions};
-pub use options::{Options,OpenOptions};
+pub use constants::{BLOCK_TRAILER_SIZE, FIRST_MANIFEST, MIN_BLOCK_SIZE, NO_COMPRESSION, NON_TABLE_FILES, NUM_LEVELS,
+                    SYSTEM_COLUMN_FAMILY, TABLE_MAGIC, TABLE_MAGIC_V2, USER_COLUMN_FAMILY};
+pub use db_config_file::{DbConfig, DbState, load_db_config, sst_file_name, ColumnFamilyOptions, DbConfigFile, QuotaOptions, ReadOptions, VectorIndexOptions, VectorOptions, WriteOptions};
+pub use options_builder::OptionsBuilder;
+pub use options::{Options,OpenOptions,CompressionType,CompactionPri,CompactionStyle,FifoCompactionOptions,JobQueueOverflow,SstLayout,UniversalCompactionOptions};
+pub use statistics::{properties, CfStatistics, CpuTimer};
+pub use allocator::{DefaultAllocator, MemoryAllocator};
+pub use trace::{Span, TraceContext};
+pub use fs::{file_checksum, sync_dir, sync_file};
+pub(crate) use fs::{read_at, read_exact_at};
+pub use file_resolver::FileResolver;
+pub use numa::{current_cpu, pin_current_thread, NumaTopology};
+pub use perf_context::{perf_level, set_perf_level, PerfContext, PerfLevel};
+pub use open_files::{is_fd_exhausted, process_fd_limit, process_open_fds, OpenFiles};
+pub use slice_transform::{slice_transform_from_name, DelimitedPrefixTransform, FixedPrefixTransform, SliceTransform};
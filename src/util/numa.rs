@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use once_cell::sync::Lazy;
+
+static TOPOLOGY: Lazy<NumaTopology> = Lazy::new(NumaTopology::detect);
+
+/// CPUs of each NUMA node, read from `/sys/devices/system/node`.
+///
+/// Machines without that directory (or not Linux) look like a single node
+/// holding every CPU, so NUMA-aware code degrades to its plain form there.
+#[derive(Debug, Clone)]
+pub struct NumaTopology {
+    nodes: Vec<Vec<usize>>,
+    /// 下标是 CPU 号
+    cpu_node: Vec<usize>,
+}
+
+impl NumaTopology {
+    /// The topology of this machine, detected once.
+    pub fn get() -> &'static NumaTopology {
+        &TOPOLOGY
+    }
+
+    pub fn detect() -> Self {
+        let mut nodes: Vec<(usize, Vec<usize>)> = fs::read_dir("/sys/devices/system/node")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let id = entry.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+                let cpus = parse_cpulist(fs::read_to_string(entry.path().join("cpulist")).ok()?.trim());
+                Some((id, cpus))
+            })
+            // 只有内存没有 CPU 的节点（比如 CXL 内存）用不上
+            .filter(|(_, cpus)| !cpus.is_empty())
+            .collect();
+        nodes.sort();
+        if nodes.is_empty() {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            return Self::from_nodes(vec![(0..cpus).collect()]);
+        }
+        Self::from_nodes(nodes.into_iter().map(|(_, cpus)| cpus).collect())
+    }
+
+    pub fn from_nodes(nodes: Vec<Vec<usize>>) -> Self {
+        let max_cpu = nodes.iter().flatten().copied().max().unwrap_or(0);
+        let mut cpu_node = vec![0; max_cpu + 1];
+        for (node, cpus) in nodes.iter().enumerate() {
+            for &cpu in cpus {
+                cpu_node[cpu] = node;
+            }
+        }
+        Self { nodes, cpu_node }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len().max(1)
+    }
+
+    pub fn cpus(&self, node: usize) -> &[usize] {
+        self.nodes.get(node).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn node_of_cpu(&self, cpu: usize) -> usize {
+        self.cpu_node.get(cpu).copied().unwrap_or(0)
+    }
+
+    /// Node the calling thread runs on right now; 0 when unknown.
+    pub fn current_node(&self) -> usize {
+        if self.nodes.len() <= 1 {
+            return 0;
+        }
+        current_cpu().map_or(0, |cpu| self.node_of_cpu(cpu))
+    }
+}
+
+/// "0-3,8,10-11" -> [0, 1, 2, 3, 8, 10, 11]
+fn parse_cpulist(s: &str) -> Vec<usize> {
+    s.split(',')
+        .filter(|r| !r.is_empty())
+        .flat_map(|range| {
+            let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+            match (lo.trim().parse::<usize>(), hi.trim().parse::<usize>()) {
+                (Ok(lo), Ok(hi)) => lo..hi + 1,
+                _ => 0..0,
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn sched_getcpu() -> i32;
+    fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+}
+
+/// CPU the calling thread runs on.
+pub fn current_cpu() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        // 走 vDSO，不进内核
+        let cpu = unsafe { sched_getcpu() };
+        if cpu >= 0 {
+            return Some(cpu as usize);
+        }
+    }
+    None
+}
+
+/// Restricts the calling thread to `cpus`.
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let Some(&max) = cpus.iter().max() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no CPUs to pin to"));
+        };
+        let mut mask = vec![0u64; max / 64 + 1];
+        for &cpu in cpus {
+            mask[cpu / 64] |= 1 << (cpu % 64);
+        }
+        // pid 0 = 当前线程
+        let rc = unsafe { sched_setaffinity(0, mask.len() * 8, mask.as_ptr()) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = cpus;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "thread pinning needs Linux"))
+    }
+}
@@ -0,0 +1,44 @@
+use std::fs;
+use serde::Serialize;
+
+/// File descriptors held by the DB, by owner, next to the process totals;
+/// see `properties::OPEN_FILES`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OpenFiles {
+    /// Cached SST readers, one descriptor each.
+    pub table_readers: usize,
+    /// The WAL writer and, with `wal_archive_dir`, the archive index.
+    pub wal: usize,
+    pub manifest: usize,
+    /// Descriptors open in the whole process; `None` where it can't be read.
+    pub process_open: Option<usize>,
+    /// Soft `RLIMIT_NOFILE` of the process.
+    pub process_limit: Option<u64>,
+    /// Idle table readers closed early because the process neared its limit.
+    pub readers_closed_for_limit: u64,
+}
+
+impl OpenFiles {
+    pub fn total(&self) -> usize {
+        self.table_readers + self.wal + self.manifest
+    }
+}
+
+/// 进程当前打开的 fd 数；只有 Linux 的 /proc 能读
+pub fn process_open_fds() -> Option<usize> {
+    fs::read_dir("/proc/self/fd").ok().map(|d| d.count())
+}
+
+/// 进程 fd 软上限，"Max open files  <soft>  <hard>  files"
+pub fn process_fd_limit() -> Option<u64> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    limits.lines()
+        .find_map(|l| l.strip_prefix("Max open files"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|soft| soft.parse().ok())
+}
+
+/// EMFILE / ENFILE：进程或者系统的 fd 用光了
+pub fn is_fd_exhausted(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(23) | Some(24))
+}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
-use serde::Deserialize;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::db::event_listener::EventListener;
 use crate::util::{ColumnFamilyOptions, WriteOptions};
 
 #[derive(Debug, Clone)]
@@ -11,41 +13,121 @@ pub struct Options {
 
     // Compaction
     pub level0_file_num_compaction_trigger: usize,
+    /// L0 file count at which writes are flagged as delayed and clients get a retry-after hint.
+    pub level0_slowdown_writes_trigger: usize,
+    /// L0 file count at which writes are rejected with `DBError::WriteStall`.
+    pub level0_stop_writes_trigger: usize,
+    /// Target size of L1; used to estimate compaction debt.
+    pub max_bytes_for_level_base: u64,
+    /// Each level below L1 targets this many times the size of the one above.
+    pub max_bytes_for_level_multiplier: u64,
     pub max_background_compactions: usize,
+    /// At most this many compactions of one column family run at once, so a busy column family cannot take every compaction thread.
+    pub max_compactions_per_cf: usize,
     pub max_background_flushes: usize,
+    /// Flush / compaction jobs that may wait in the background queue; beyond that `background_queue_overflow` applies. 0 means unbounded.
+    pub max_background_queue_len: usize,
+    /// Pin each flush/compaction thread to the CPUs of one NUMA node, taking the nodes in turn, so its memory stays node-local. No effect on single-node machines.
+    pub pin_background_threads: bool,
+    /// What happens to a job submitted to a full (or already covering) background queue.
+    pub background_queue_overflow: JobQueueOverflow,
+    /// Let the DB nudge the write buffer size and L0 triggers from observed flushes, stalls and compaction debt, within the `auto_tune_*` bounds.
+    pub auto_tune_options: bool,
+    /// How often the auto-tuner looks at the workload.
+    pub auto_tune_interval_secs: u64,
+    /// Lower bound for the auto-tuned write buffer size.
+    pub auto_tune_min_write_buffer_size: usize,
+    /// Upper bound for the auto-tuned write buffer size.
+    pub auto_tune_max_write_buffer_size: usize,
+    /// Upper bound for the auto-tuned L0 stop trigger; the slowdown trigger keeps its distance below it.
+    pub auto_tune_max_level0_stop_writes_trigger: usize,
 
     // SST / Compression
     pub compression: CompressionType,
 
     // Cache / Table
     pub block_cache_size: usize,
+    /// Split the block cache into one part per NUMA node; lookups try the local node first. No effect on single-node machines.
+    pub numa_aware_block_cache: bool,
     pub optimize_filters_for_hits: bool,
 
     // WAL
     pub enable_write_ahead_log: bool,
+    /// Skip flushing memtables in `DBImpl::close`; the WAL is then kept and replayed on the next open.
+    pub avoid_flush_during_shutdown: bool,
+    /// Repair the sequence problems found on open (see `RecoveryInfo`) instead of only reporting them.
+    pub repair_sequence_numbers: bool,
+    /// Sync new SST files with fsync instead of fdatasync. Either way the file and its directory are synced before the MANIFEST records it.
+    pub use_fsync: bool,
 
     pub write_sync: bool,
 
     // Files
+    /// Table readers kept open (one descriptor each), less a few for the WAL
+    /// and MANIFEST; the least recently used idle reader is closed past it.
+    /// -1: no limit, readers are only closed when the process nears its
+    /// descriptor limit.
     pub max_open_files: i32,
+    /// Threads used to open table files (footer + index) in parallel at startup.
+    pub max_file_opening_threads: usize,
+    /// Number of hottest table files (L0 newest first, then upper levels) to open on startup; 0 disables.
+    pub preload_tables_on_open: usize,
+    /// Also open every bottommost-level table file on startup.
+    pub preload_bottom_level_on_open: bool,
 
     // Manifest
     pub max_manifest_file_size: u64,
+    /// Keep superseded Versions this long for get_as_of / iterator_as_of; 0 disables time-travel reads.
+    pub time_travel_retention_secs: u64,
+    /// Upper bound on live snapshots; `try_get_snapshot` returns Busy beyond it. 0 means unlimited.
+    pub max_snapshots: usize,
+    /// Warn when the oldest live snapshot is older than this (it holds back tombstone GC). 0 disables.
+    pub snapshot_warn_age_secs: u64,
+    /// Iterators and snapshots older than this are invalidated (iterators fail with `DBError::Expired`, snapshots stop pinning old versions). 0 disables.
+    pub max_iterator_age_secs: u64,
+    /// Sample one in this many gets and iterator seeks for the `read-tuning-report` property. 0 disables sampling.
+    pub read_sample_rate: u32,
+    /// How often the background sweeper deletes keys written with `put_with_ttl` whose TTL has passed. 0 disables the sweeper.
+    pub ttl_sweep_interval_secs: u64,
+    /// How often locks of expired transactions are reclaimed. 0 disables the sweeper; expired locks are then only taken over by waiters.
+    pub txn_expiration_sweep_interval_ms: u64,
+    /// Reject writes into a range leased by `lock_range` unless they carry the lease.
+    pub enforce_range_locks: bool,
+    /// Threads used by `put_documents` to run the column family's Embedder in parallel.
+    pub embedding_threads: usize,
+    /// Base retry-after hint returned while writes are stalled; doubled while stopped.
+    pub write_stall_retry_after_ms: u64,
+    /// After open, reject sync writes with `DBError::WriteStall` until compactions have paid off the compaction debt.
+    pub warmup_on_open: bool,
+    /// Capacity in bytes of the page cache used by on-disk vector graphs.
+    pub vector_graph_cache_size: usize,
 
     // Column Families
     pub system_cf: ColumnFamilyOptions,
     pub user_cf: ColumnFamilyOptions,
+
+    /// Called on flush, compaction, SST creation/deletion and background errors.
+    pub listeners: Vec<Arc<dyn EventListener>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct OpenOptions {
     // open
     pub create_if_missing: bool,
+    /// Fail instead of opening a DB that already exists.
+    pub error_if_exists: bool,
 
     // Path override
     pub wal_dir: Option<PathBuf>,
     pub sst_dir: Option<PathBuf>,
     pub manifest_dir: Option<PathBuf>,
+    /// Keep WAL contents here instead of discarding them, for point-in-time restore.
+    pub wal_archive_dir: Option<PathBuf>,
+    /// Where new SSTs go under `sst_dir`. Files written under the other layout are still found.
+    pub sst_layout: SstLayout,
+    /// More directories searched for existing SSTs after `sst_dir`, e.g. a
+    /// slower storage tier files were moved to. Nothing new is written there.
+    pub sst_tier_dirs: Vec<PathBuf>,
 
     // ===== Block cache（open-only）=====
     pub block_cache_capacity: Option<usize>,
@@ -62,28 +144,79 @@ pub struct OptionsFile {
     pub allow_concurrent_memtable_write: Option<bool>,
 
     pub level0_file_num_compaction_trigger: Option<usize>,
+    pub level0_slowdown_writes_trigger: Option<usize>,
+    pub level0_stop_writes_trigger: Option<usize>,
+    pub max_bytes_for_level_base: Option<u64>,
+    pub max_bytes_for_level_multiplier: Option<u64>,
     pub max_background_compactions: Option<usize>,
+    pub max_compactions_per_cf: Option<usize>,
     pub max_background_flushes: Option<usize>,
+    pub max_background_queue_len: Option<usize>,
+    pub pin_background_threads: Option<bool>,
+    pub background_queue_overflow: Option<JobQueueOverflow>,
+    pub auto_tune_options: Option<bool>,
+    pub auto_tune_interval_secs: Option<u64>,
+    pub auto_tune_min_write_buffer_size: Option<usize>,
+    pub auto_tune_max_write_buffer_size: Option<usize>,
+    pub auto_tune_max_level0_stop_writes_trigger: Option<usize>,
 
     pub compression: Option<CompressionType>,
     pub block_cache_size: Option<usize>,
+    pub numa_aware_block_cache: Option<bool>,
     pub optimize_filters_for_hits: Option<bool>,
 
     pub enable_write_ahead_log: Option<bool>,
+    pub avoid_flush_during_shutdown: Option<bool>,
+    pub repair_sequence_numbers: Option<bool>,
+    pub use_fsync: Option<bool>,
     pub write_sync: Option<bool>,
     pub max_open_files: Option<i32>,
+    pub max_file_opening_threads: Option<usize>,
+    pub preload_tables_on_open: Option<usize>,
+    pub preload_bottom_level_on_open: Option<bool>,
     pub max_manifest_file_size: Option<u64>,
+    pub time_travel_retention_secs: Option<u64>,
+    pub max_snapshots: Option<usize>,
+    pub snapshot_warn_age_secs: Option<u64>,
+    pub max_iterator_age_secs: Option<u64>,
+    pub read_sample_rate: Option<u32>,
+    pub ttl_sweep_interval_secs: Option<u64>,
+    pub txn_expiration_sweep_interval_ms: Option<u64>,
+    pub enforce_range_locks: Option<bool>,
+    pub embedding_threads: Option<usize>,
+    pub write_stall_retry_after_ms: Option<u64>,
+    pub warmup_on_open: Option<bool>,
+    pub vector_graph_cache_size: Option<usize>,
 }
 
-/// 压缩类型对应 C++ CompressionType
-#[derive(Debug, Clone, Copy)]
+/// 压缩类型对应 C++ CompressionType（取值与 RocksDB 的 block trailer 保持一致）
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompressionType {
-    NoCompression,
-    SnappyCompression,
-    ZlibCompression,
-    Bz2Compression,
-    Lz4Compression,
-    ZstdCompression,
+    NoCompression = 0,
+    SnappyCompression = 1,
+    ZlibCompression = 2,
+    Bz2Compression = 3,
+    Lz4Compression = 4,
+    ZstdCompression = 7,
+}
+
+impl CompressionType {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(CompressionType::NoCompression),
+            1 => Some(CompressionType::SnappyCompression),
+            2 => Some(CompressionType::ZlibCompression),
+            3 => Some(CompressionType::Bz2Compression),
+            4 => Some(CompressionType::Lz4Compression),
+            7 => Some(CompressionType::ZstdCompression),
+            _ => None,
+        }
+    }
 }
 
 impl Default for CompressionType {
@@ -92,13 +225,113 @@ impl Default for CompressionType {
     }
 }
 
+/// 非 L0 自动 compaction 时先挑哪个文件（对应 RocksDB 的 CompactionPri）
+///
+/// 被 `suggest_compact_range` 标记过的文件总是排在最前面
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionPri {
+    /// 最大的文件优先
+    ByCompensatedSize,
+    /// 数据最老的文件优先。FileMetaData 不记 seqno，按 file_number（即落盘顺序）近似
+    OldestSmallestSeqFirst,
+    /// 和下一层重叠字节数 / 自身大小 最小的优先，写放大最小
+    #[default]
+    MinOverlappingRatio,
+}
+
+/// 后台任务队列的溢出策略
+///
+/// 队列满了（`max_background_queue_len`）时提交的线程都会等；后两种先把重复的请求吸收掉，
+/// 能吸收的就不占队列位置。后台线程自己提交的任务不等，免得线程池互相卡死。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobQueueOverflow {
+    /// 只限长度，满了就等
+    #[default]
+    Block,
+    /// 和队列里某个任务做同一件事（同一批 memtable、同一个 CF 的同一范围）的请求直接丢掉
+    DropDuplicates,
+    /// 同一个 CF 排着的 flush / compaction 合成一个：flush 并上 memtable，compaction 并上范围
+    CoalescePerCf,
+}
+
+/// How SST files are laid out under `sst_dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SstLayout {
+    /// Every SST directly in `sst_dir`.
+    #[default]
+    Flat,
+    /// SSTs in `sst_dir/L<level>/` by the level they were written for.
+    PerLevel,
+}
+
+/// 一个 CF 的 compaction 方式（对应 RocksDB 的 CompactionStyle）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionStyle {
+    /// 分层：L0 往下一层一层合，读放大和空间放大小
+    #[default]
+    Level,
+    /// 分级（size-tiered）：大小相近的 sorted run 合在一起，写放大小，适合写多的负载
+    Universal,
+    /// 不合并，总大小超了就删最老的文件；适合只追加、过期即丢的日志 / 监控数据
+    Fifo,
+}
+
+/// Settings of `CompactionStyle::Universal`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UniversalCompactionOptions {
+    /// The next older run joins a size-ratio compaction while
+    /// `window_size * (100 + size_ratio) / 100 >= run_size`.
+    pub size_ratio: u64,
+    /// Fewest runs merged by a size-ratio compaction.
+    pub min_merge_width: usize,
+    /// Most runs merged by a size-ratio compaction.
+    pub max_merge_width: usize,
+    /// Merge every run once all but the oldest add up to more than this
+    /// percentage of the oldest.
+    pub max_size_amplification_percent: u64,
+}
+
+impl Default for UniversalCompactionOptions {
+    fn default() -> Self {
+        Self {
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: usize::MAX,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+/// Settings of `CompactionStyle::Fifo`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FifoCompactionOptions {
+    /// Oldest SST files are deleted once the column family's files add up to more than this.
+    pub max_table_files_size: u64,
+}
+
+impl Default for FifoCompactionOptions {
+    fn default() -> Self {
+        Self { max_table_files_size: 1 << 30 }
+    }
+}
+
 impl Default for OpenOptions {
     fn default() -> Self {
         Self {
             create_if_missing: true,
+            error_if_exists: false,
             wal_dir: None,
             sst_dir: None,
             manifest_dir: None,
+            wal_archive_dir: None,
+            sst_layout: SstLayout::Flat,
+            sst_tier_dirs: Vec::new(),
 
             block_cache_capacity: None,
             block_cache_shards: None,
@@ -109,22 +342,56 @@ impl Default for OpenOptions {
                 allow_concurrent_memtable_write: true,
 
                 level0_file_num_compaction_trigger: 4,
+                level0_slowdown_writes_trigger: 20,
+                level0_stop_writes_trigger: 36,
+                max_bytes_for_level_base: 256 * 1024 * 1024,
+                max_bytes_for_level_multiplier: 10,
                 max_background_compactions: 4,
+                max_compactions_per_cf: 1,
                 max_background_flushes: 2,
+                max_background_queue_len: 0,
+                pin_background_threads: false,
+                background_queue_overflow: JobQueueOverflow::Block,
+                auto_tune_options: false,
+                auto_tune_interval_secs: 60,
+                auto_tune_min_write_buffer_size: 16 << 20,
+                auto_tune_max_write_buffer_size: 256 << 20,
+                auto_tune_max_level0_stop_writes_trigger: 64,
 
                 compression: CompressionType::SnappyCompression,
 
                 block_cache_size: 256 << 20,
+                numa_aware_block_cache: false,
                 optimize_filters_for_hits: true,
 
                 enable_write_ahead_log: true,
+                avoid_flush_during_shutdown: false,
+                repair_sequence_numbers: false,
+                use_fsync: false,
                 write_sync:true,
                 max_open_files: 1024,
+                max_file_opening_threads: 16,
+                preload_tables_on_open: 0,
+                preload_bottom_level_on_open: false,
 
                 max_manifest_file_size: 64 << 20,
+                time_travel_retention_secs: 0,
+                max_snapshots: 0,
+                snapshot_warn_age_secs: 0,
+                max_iterator_age_secs: 0,
+                read_sample_rate: 0,
+                ttl_sweep_interval_secs: 0,
+                txn_expiration_sweep_interval_ms: 1000,
+                enforce_range_locks: false,
+                embedding_threads: 4,
+                write_stall_retry_after_ms: 100,
+                warmup_on_open: false,
+                vector_graph_cache_size: 64 * 1024 * 1024,
 
                 system_cf: ColumnFamilyOptions::default(),
                 user_cf: ColumnFamilyOptions::default(),
+
+                listeners: Vec::new(),
             },
         }
     }
@@ -133,40 +400,6 @@ impl Default for OpenOptions {
 impl OpenOptions {
     /// Consume open-only information and produce runtime Options
     pub fn to_options(&self) -> Options {
-        Options {
-            // ===== MemTable =====
-            write_buffer_size: self.options.write_buffer_size,
-            max_write_buffer_number: self.options.max_write_buffer_number,
-            allow_concurrent_memtable_write: self.options.allow_concurrent_memtable_write,
-
-            // ===== Compaction =====
-            level0_file_num_compaction_trigger:
-            self.options.level0_file_num_compaction_trigger,
-            max_background_compactions:
-            self.options.max_background_compactions,
-            max_background_flushes:
-            self.options.max_background_flushes,
-
-            // ===== Compression =====
-            compression: self.options.compression,
-
-            // ===== Cache / Table =====
-            block_cache_size: self.options.block_cache_size,
-            optimize_filters_for_hits: self.options.optimize_filters_for_hits,
-
-            // ===== WAL =====
-            enable_write_ahead_log: self.options.enable_write_ahead_log,
-            write_sync: self.options.write_sync,
-
-            // ===== Files =====
-            max_open_files: self.options.max_open_files,
-
-            // ===== Manifest =====
-            max_manifest_file_size: self.options.max_manifest_file_size,
-
-            // ===== Column Families =====
-            system_cf: self.options.system_cf.clone(),
-            user_cf: self.options.user_cf.clone(),
-        }
+        self.options.clone()
     }
 }
@@ -0,0 +1,284 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::DBError;
+use crate::db::event_listener::EventListener;
+use crate::util::{ColumnFamilyOptions, CompressionType, OpenOptions, Options, SstLayout, NON_TABLE_FILES};
+
+/// Fluent construction of the options a DB is opened with.
+///
+/// ```ignore
+/// let open = Options::builder()
+///     .write_buffer_size(128 << 20)
+///     .level0_triggers(4, 20, 36)
+///     .block_cache(512 << 20, 32)
+///     .build()?;
+/// let db = DBImpl::open_with_options("/data/db", open)?;
+/// ```
+///
+/// Setters only record values; `build` checks them together (see
+/// `OpenOptions::validate`) and reports every problem at once.
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+    open: OpenOptions,
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+impl OptionsBuilder {
+    /// Starts from `open` instead of the defaults, e.g. what a config file gave.
+    pub fn from_open_options(open: OpenOptions) -> Self {
+        Self { open }
+    }
+
+    pub fn create_if_missing(mut self, v: bool) -> Self {
+        self.open.create_if_missing = v;
+        self
+    }
+
+    pub fn error_if_exists(mut self, v: bool) -> Self {
+        self.open.error_if_exists = v;
+        self
+    }
+
+    pub fn wal_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.open.wal_dir = Some(dir.into());
+        self
+    }
+
+    pub fn sst_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.open.sst_dir = Some(dir.into());
+        self
+    }
+
+    pub fn manifest_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.open.manifest_dir = Some(dir.into());
+        self
+    }
+
+    pub fn wal_archive_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.open.wal_archive_dir = Some(dir.into());
+        self
+    }
+
+    pub fn sst_layout(mut self, layout: SstLayout) -> Self {
+        self.open.sst_layout = layout;
+        self
+    }
+
+    pub fn sst_tier_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.open.sst_tier_dirs.push(dir.into());
+        self
+    }
+
+    /// Block cache capacity in bytes and number of shards.
+    pub fn block_cache(mut self, capacity_bytes: usize, shards: usize) -> Self {
+        self.open.block_cache_capacity = Some(capacity_bytes);
+        self.open.block_cache_shards = Some(shards);
+        self
+    }
+
+    pub fn write_buffer_size(mut self, bytes: usize) -> Self {
+        self.open.options.write_buffer_size = bytes;
+        self
+    }
+
+    pub fn max_write_buffer_number(mut self, n: usize) -> Self {
+        self.open.options.max_write_buffer_number = n;
+        self
+    }
+
+    /// L0 file counts that start a compaction, slow writes down and stop them.
+    pub fn level0_triggers(mut self, compaction: usize, slowdown: usize, stop: usize) -> Self {
+        let o = &mut self.open.options;
+        o.level0_file_num_compaction_trigger = compaction;
+        o.level0_slowdown_writes_trigger = slowdown;
+        o.level0_stop_writes_trigger = stop;
+        self
+    }
+
+    pub fn max_bytes_for_level(mut self, base: u64, multiplier: u64) -> Self {
+        self.open.options.max_bytes_for_level_base = base;
+        self.open.options.max_bytes_for_level_multiplier = multiplier;
+        self
+    }
+
+    pub fn background_jobs(mut self, flushes: usize, compactions: usize) -> Self {
+        self.open.options.max_background_flushes = flushes;
+        self.open.options.max_background_compactions = compactions;
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionType) -> Self {
+        self.open.options.compression = compression;
+        self
+    }
+
+    pub fn max_open_files(mut self, n: i32) -> Self {
+        self.open.options.max_open_files = n;
+        self
+    }
+
+    pub fn use_fsync(mut self, v: bool) -> Self {
+        self.open.options.use_fsync = v;
+        self
+    }
+
+    pub fn enable_write_ahead_log(mut self, v: bool) -> Self {
+        self.open.options.enable_write_ahead_log = v;
+        self
+    }
+
+    pub fn system_cf(mut self, cf: ColumnFamilyOptions) -> Self {
+        self.open.options.system_cf = cf;
+        self
+    }
+
+    pub fn user_cf(mut self, cf: ColumnFamilyOptions) -> Self {
+        self.open.options.user_cf = cf;
+        self
+    }
+
+    /// Adds a listener for flush, compaction and SST file events.
+    pub fn listener(mut self, listener: Arc<dyn EventListener>) -> Self {
+        self.open.options.listeners.push(listener);
+        self
+    }
+
+    /// Any other option without a setter of its own.
+    pub fn with(mut self, f: impl FnOnce(&mut Options)) -> Self {
+        f(&mut self.open.options);
+        self
+    }
+
+    pub fn build(self) -> Result<OpenOptions, DBError> {
+        self.open.validate()?;
+        Ok(self.open)
+    }
+}
+
+impl OpenOptions {
+    /// Checks the options against each other. All problems go into one
+    /// `InvalidArgument`, separated by "; ".
+    pub fn validate(&self) -> Result<(), DBError> {
+        let o = &self.options;
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, msg: String| {
+            if !ok {
+                problems.push(msg);
+            }
+        };
+
+        // ===== MemTable =====
+        check(o.write_buffer_size > 0, "write_buffer_size must be > 0".into());
+        check(o.max_write_buffer_number >= 1, "max_write_buffer_number must be >= 1".into());
+
+        // ===== Compaction =====
+        check(o.level0_file_num_compaction_trigger >= 1, "level0_file_num_compaction_trigger must be >= 1".into());
+        check(
+            o.level0_file_num_compaction_trigger <= o.level0_slowdown_writes_trigger
+                && o.level0_slowdown_writes_trigger <= o.level0_stop_writes_trigger,
+            format!(
+                "L0 triggers must satisfy compaction ({}) <= slowdown ({}) <= stop ({})",
+                o.level0_file_num_compaction_trigger, o.level0_slowdown_writes_trigger, o.level0_stop_writes_trigger
+            ),
+        );
+        check(o.max_bytes_for_level_base > 0, "max_bytes_for_level_base must be > 0".into());
+        check(o.max_bytes_for_level_multiplier >= 2, format!(
+            "max_bytes_for_level_multiplier is {}, levels would not grow", o.max_bytes_for_level_multiplier
+        ));
+        if o.auto_tune_options {
+            check(o.auto_tune_min_write_buffer_size <= o.auto_tune_max_write_buffer_size, format!(
+                "auto_tune_min_write_buffer_size ({}) > auto_tune_max_write_buffer_size ({})",
+                o.auto_tune_min_write_buffer_size, o.auto_tune_max_write_buffer_size
+            ));
+            check(o.auto_tune_max_level0_stop_writes_trigger >= o.level0_stop_writes_trigger, format!(
+                "auto_tune_max_level0_stop_writes_trigger ({}) < level0_stop_writes_trigger ({})",
+                o.auto_tune_max_level0_stop_writes_trigger, o.level0_stop_writes_trigger
+            ));
+        }
+
+        // ===== Block cache =====
+        // 每个 shard 至少要放得下一个 block，不然每次插入都立刻被淘汰
+        let capacity = self.block_cache_capacity.unwrap_or(o.block_cache_size);
+        let shards = self.block_cache_shards.unwrap_or(16);
+        check(shards > 0, "block_cache_shards must be > 0".into());
+        for (name, cf) in [("system_cf", &o.system_cf), ("user_cf", &o.user_cf)] {
+            let block_size = cf.table_options.block_size;
+            check(block_size > 0, format!("{}.table_options.block_size must be > 0", name));
+            check(cf.table_options.restart_interval > 0, format!("{}.table_options.restart_interval must be > 0", name));
+            check(shards == 0 || capacity / shards.next_power_of_two() >= block_size, format!(
+                "block cache of {} bytes over {} shards holds less than one {}-byte block of {} per shard",
+                capacity, shards, block_size, name
+            ));
+        }
+
+        // ===== Files =====
+        // <= 0 是不限；正数要比 WAL / MANIFEST 这些常开的文件多
+        check(o.max_open_files <= 0 || o.max_open_files as usize > NON_TABLE_FILES, format!(
+            "max_open_files ({}) leaves no descriptors for SSTs; use -1 for unbounded", o.max_open_files
+        ));
+        check(o.max_background_flushes + o.max_background_compactions > 0,
+            "max_background_flushes + max_background_compactions must be > 0".into());
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(DBError::InvalidArgument(format!("invalid options: {}", problems.join("; "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(builder: OptionsBuilder) -> String {
+        match builder.build() {
+            Err(DBError::InvalidArgument(msg)) => msg,
+            other => panic!("expected InvalidArgument, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn defaults_pass_validation() {
+        let open = Options::builder().build().unwrap();
+        assert!(open.create_if_missing);
+        let open = Options::builder().error_if_exists(true).write_buffer_size(1 << 20).build().unwrap();
+        assert!(open.error_if_exists);
+        assert_eq!(open.options.write_buffer_size, 1 << 20);
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let msg = problems(
+            Options::builder()
+                .level0_triggers(10, 5, 20)
+                .max_bytes_for_level(1 << 20, 1)
+                .max_open_files(3),
+        );
+        assert!(msg.contains("compaction (10) <= slowdown (5)"), "{}", msg);
+        assert!(msg.contains("multiplier is 1"), "{}", msg);
+        assert!(msg.contains("max_open_files (3)"), "{}", msg);
+        assert_eq!(msg.matches("; ").count(), 2);
+        // -1 是不限
+        Options::builder().max_open_files(-1).build().unwrap();
+    }
+
+    #[test]
+    fn cache_shards_must_hold_a_block_and_tuner_bounds_only_matter_when_enabled() {
+        let msg = problems(Options::builder().block_cache(64 << 10, 64));
+        assert!(msg.contains("less than one 4096-byte block of system_cf"), "{}", msg);
+
+        let inverted = |o: &mut Options| {
+            o.auto_tune_min_write_buffer_size = 64 << 20;
+            o.auto_tune_max_write_buffer_size = 16 << 20;
+        };
+        Options::builder().with(inverted).build().unwrap();
+        let msg = problems(Options::builder().with(inverted).with(|o| o.auto_tune_options = true));
+        assert!(msg.contains("auto_tune_min_write_buffer_size"), "{}", msg);
+    }
+}
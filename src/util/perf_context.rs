@@ -0,0 +1,154 @@
+//! Per-thread counters of what one read did, for finding out why a `get` or
+//! a scan was slow.
+//!
+//! Counting is off by default and costs one thread-local read per counted
+//! event. Turn it on for the calling thread, run the operation and read the
+//! counters:
+//!
+//! ```ignore
+//! set_perf_level(PerfLevel::Enabled);
+//! PerfContext::reset();
+//! db.get(&ReadOptions::default(), cf, b"key")?;
+//! println!("{:?}", PerfContext::current());
+//! ```
+//!
+//! or in one go with `PerfContext::measure`. Work the operation hands to other
+//! threads (background flushes, parallel table opens) is not counted.
+
+use std::cell::Cell;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PerfLevel {
+    #[default]
+    Disabled,
+    Enabled,
+}
+
+/// Counters since the last `PerfContext::reset` on this thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct PerfContext {
+    /// Data blocks read from SST files, i.e. block cache misses.
+    pub block_read_count: u64,
+    pub block_read_bytes: u64,
+    pub block_cache_hit_count: u64,
+    /// SST filter probes (whole-key and prefix).
+    pub bloom_sst_checked: u64,
+    /// Probes where the filter ruled the SST out.
+    pub bloom_sst_useful: u64,
+    /// Lookups that found a version of the key in the memtables.
+    pub memtable_hit_count: u64,
+    /// Lookups that found no version of the key in the memtables.
+    pub memtable_miss_count: u64,
+    /// Child iterators positioned by seeks of merging iterators, one per
+    /// memtable and SST (or level) iterator per seek.
+    pub seek_child_seek_count: u64,
+}
+
+thread_local! {
+    static LEVEL: Cell<PerfLevel> = const { Cell::new(PerfLevel::Disabled) };
+    static CONTEXT: Cell<PerfContext> = const { Cell::new(PerfContext::ZERO) };
+}
+
+/// Turns counting on or off for the calling thread.
+pub fn set_perf_level(level: PerfLevel) {
+    LEVEL.with(|l| l.set(level));
+}
+
+pub fn perf_level() -> PerfLevel {
+    LEVEL.with(Cell::get)
+}
+
+impl PerfContext {
+    const ZERO: PerfContext = PerfContext {
+        block_read_count: 0,
+        block_read_bytes: 0,
+        block_cache_hit_count: 0,
+        bloom_sst_checked: 0,
+        bloom_sst_useful: 0,
+        memtable_hit_count: 0,
+        memtable_miss_count: 0,
+        seek_child_seek_count: 0,
+    };
+
+    /// The counters of the calling thread.
+    pub fn current() -> Self {
+        CONTEXT.with(Cell::get)
+    }
+
+    pub fn reset() {
+        CONTEXT.with(|c| c.set(Self::ZERO));
+    }
+
+    /// Runs `f` with counting on and returns what it did; the previous level
+    /// and counters of the thread are put back afterwards.
+    pub fn measure<R>(f: impl FnOnce() -> R) -> (R, PerfContext) {
+        let (level, saved) = (perf_level(), Self::current());
+        set_perf_level(PerfLevel::Enabled);
+        Self::reset();
+        let result = f();
+        let measured = Self::current();
+        set_perf_level(level);
+        CONTEXT.with(|c| c.set(saved));
+        (result, measured)
+    }
+}
+
+/// 开着才记；关着只是一次 thread-local 读
+#[inline]
+pub(crate) fn perf_record(f: impl FnOnce(&mut PerfContext)) {
+    if perf_level() == PerfLevel::Disabled {
+        return;
+    }
+    CONTEXT.with(|c| {
+        let mut ctx = c.get();
+        f(&mut ctx);
+        c.set(ctx);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_while_enabled() {
+        set_perf_level(PerfLevel::Disabled);
+        PerfContext::reset();
+        perf_record(|p| p.block_read_count += 1);
+        assert_eq!(PerfContext::current(), PerfContext::default());
+
+        set_perf_level(PerfLevel::Enabled);
+        perf_record(|p| p.block_read_count += 1);
+        perf_record(|p| p.block_read_bytes += 4096);
+        let ctx = PerfContext::current();
+        assert_eq!((ctx.block_read_count, ctx.block_read_bytes), (1, 4096));
+
+        PerfContext::reset();
+        assert_eq!(PerfContext::current(), PerfContext::default());
+        set_perf_level(PerfLevel::Disabled);
+    }
+
+    #[test]
+    fn measure_puts_the_level_and_counters_back() {
+        set_perf_level(PerfLevel::Disabled);
+        PerfContext::reset();
+        let (answer, ctx) = PerfContext::measure(|| {
+            perf_record(|p| p.memtable_hit_count += 2);
+            42
+        });
+        assert_eq!(answer, 42);
+        assert_eq!(ctx.memtable_hit_count, 2);
+        assert_eq!(perf_level(), PerfLevel::Disabled);
+        assert_eq!(PerfContext::current(), PerfContext::default());
+
+        // 别的线程不受影响
+        set_perf_level(PerfLevel::Enabled);
+        std::thread::spawn(|| {
+            assert_eq!(perf_level(), PerfLevel::Disabled);
+            perf_record(|p| p.seek_child_seek_count += 1);
+            assert_eq!(PerfContext::current(), PerfContext::default());
+        }).join().unwrap();
+        set_perf_level(PerfLevel::Disabled);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Maps a user key to its prefix, for prefix bloom filters and
+/// `ReadOptions::prefix_same_as_start` iterators.
+///
+/// Keys sharing a prefix must be contiguous in key order, i.e. the prefix of a
+/// key is also a prefix of the key's bytes.
+pub trait SliceTransform: Send + Sync + Debug {
+    /// Recorded in each SST next to its prefix filter; a table written with a
+    /// different extractor is not filtered.
+    fn name(&self) -> &str;
+
+    /// Prefix of `key`. Only called when `in_domain(key)`.
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    /// Keys outside the domain have no prefix: they are not added to the
+    /// prefix filter and a seek to them is not bounded.
+    fn in_domain(&self, key: &[u8]) -> bool;
+}
+
+/// Rebuilds one of the built-in transforms from its `name()`; None for any other name.
+pub fn slice_transform_from_name(name: &str) -> Option<Arc<dyn SliceTransform>> {
+    if let Some(len) = name.strip_prefix("vectorkv.FixedPrefix.") {
+        return Some(Arc::new(FixedPrefixTransform::new(len.parse().ok()?)));
+    }
+    let rest = name.strip_prefix("vectorkv.DelimitedPrefix.")?;
+    let (delimiter, n) = rest.split_once('.')?;
+    Some(Arc::new(DelimitedPrefixTransform::new(delimiter.parse().ok()?, n.parse().ok()?)))
+}
+
+/// 前 `len` 字节；比 `len` 短的 key 不在 domain 里
+#[derive(Debug, Clone)]
+pub struct FixedPrefixTransform {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixTransform {
+    pub fn new(len: usize) -> Self {
+        Self { len, name: format!("vectorkv.FixedPrefix.{}", len) }
+    }
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+}
+
+/// 到第 `n` 个 `delimiter` 为止（含）；`user:123:` 这类分段 key 用 `new(b':', 2)`
+#[derive(Debug, Clone)]
+pub struct DelimitedPrefixTransform {
+    delimiter: u8,
+    n: usize,
+    name: String,
+}
+
+impl DelimitedPrefixTransform {
+    pub fn new(delimiter: u8, n: usize) -> Self {
+        Self { delimiter, n, name: format!("vectorkv.DelimitedPrefix.{}.{}", delimiter, n) }
+    }
+
+    fn prefix_len(&self, key: &[u8]) -> Option<usize> {
+        key.iter()
+            .enumerate()
+            .filter(|(_, b)| **b == self.delimiter)
+            .nth(self.n.checked_sub(1)?)
+            .map(|(i, _)| i + 1)
+    }
+}
+
+impl SliceTransform for DelimitedPrefixTransform {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.prefix_len(key).unwrap_or(key.len())]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        self.prefix_len(key).is_some()
+    }
+}
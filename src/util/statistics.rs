@@ -0,0 +1,204 @@
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::engine::version::{JobKind, JobRecord};
+use crate::util::perf_context::perf_record;
+
+/// Property names understood by `DB::get_property`.
+pub mod properties {
+    /// Number of SST point lookups that consulted a bloom filter.
+    pub const BLOOM_FILTER_CHECKED: &str = "vectorkv.bloom.filter.checked";
+    /// Number of filter checks that ruled the key out (a data block read avoided).
+    pub const BLOOM_FILTER_USEFUL: &str = "vectorkv.bloom.filter.useful";
+    /// Number of live snapshots (DB-wide).
+    pub const NUM_SNAPSHOTS: &str = "vectorkv.num-snapshots";
+    /// Age in seconds of the oldest live snapshot, 0 if none (DB-wide).
+    pub const OLDEST_SNAPSHOT_TIME: &str = "vectorkv.oldest-snapshot-time";
+    /// Sequence number of the oldest live snapshot, 0 if none (DB-wide).
+    pub const OLDEST_SNAPSHOT_SEQUENCE: &str = "vectorkv.oldest-snapshot-sequence";
+    /// JSON array with one entry per vector index of the column family.
+    pub const VECTOR_INDEX_STATS: &str = "vectorkv.vector-index-stats";
+    /// Estimated bytes compaction has to rewrite to bring every level under its target size.
+    pub const COMPACTION_DEBT_BYTES: &str = "vectorkv.compaction-debt-bytes";
+    /// JSON array of the oldest open iterators and snapshots with their age and
+    /// call site, oldest first (DB-wide).
+    pub const OLDEST_ITERATORS: &str = "vectorkv.oldest-iterators";
+    /// Number of Versions pinned by open iterators and in-flight reads (DB-wide).
+    pub const NUM_PINNED_VERSIONS: &str = "vectorkv.num-pinned-versions";
+    /// Number of obsolete SSTs whose deletion waits for a pinned Version (DB-wide).
+    pub const NUM_DEFERRED_OBSOLETE_FILES: &str = "vectorkv.num-deferred-obsolete-files";
+    /// Flush / compaction jobs waiting in the background queue (DB-wide).
+    pub const BACKGROUND_QUEUE_DEPTH: &str = "vectorkv.background-queue-depth";
+    /// Background jobs dropped as duplicates of a queued one since open (DB-wide).
+    pub const BACKGROUND_JOBS_DROPPED: &str = "vectorkv.background-jobs-dropped";
+    /// Background jobs merged into a queued job of the same column family since open (DB-wide).
+    pub const BACKGROUND_JOBS_COALESCED: &str = "vectorkv.background-jobs-coalesced";
+    /// JSON `ReadTuningReport` of the column family: prefix length, bloom bits per
+    /// key and block size suggested from reads sampled with `read_sample_rate`.
+    pub const READ_TUNING_REPORT: &str = "vectorkv.read-tuning-report";
+    /// File descriptors held by the DB: table readers, WAL and MANIFEST (DB-wide).
+    pub const NUM_OPEN_FILES: &str = "vectorkv.num-open-files";
+    /// JSON `OpenFiles`: descriptors by owner next to the process count and
+    /// limit (DB-wide).
+    pub const OPEN_FILES: &str = "vectorkv.open-files";
+    /// Compactions of the column family waiting in the background queue.
+    pub const NUM_QUEUED_COMPACTIONS: &str = "vectorkv.num-queued-compactions";
+    /// Number of flushes of the column family.
+    pub const FLUSH_COUNT: &str = "vectorkv.flush.count";
+    /// CPU time spent flushing the column family, in microseconds.
+    pub const FLUSH_CPU_MICROS: &str = "vectorkv.flush.cpu-micros";
+    /// Bytes of SST written by flushes of the column family.
+    pub const FLUSH_BYTES_WRITTEN: &str = "vectorkv.flush.bytes-written";
+    /// Number of compactions of the column family.
+    pub const COMPACTION_COUNT: &str = "vectorkv.compaction.count";
+    /// CPU time spent compacting the column family, in microseconds.
+    pub const COMPACTION_CPU_MICROS: &str = "vectorkv.compaction.cpu-micros";
+    /// Bytes of SST read by compactions of the column family.
+    pub const COMPACTION_BYTES_READ: &str = "vectorkv.compaction.bytes-read";
+    /// Bytes of SST written by compactions of the column family.
+    pub const COMPACTION_BYTES_WRITTEN: &str = "vectorkv.compaction.bytes-written";
+}
+
+/// CPU time of the current thread, for attributing background work.
+///
+/// Reads `/proc/thread-self/schedstat`; where that is unavailable it falls back
+/// to wall-clock time, which over-counts time spent blocked on IO.
+pub struct CpuTimer {
+    cpu_start: Option<Duration>,
+    wall_start: Instant,
+}
+
+impl CpuTimer {
+    pub fn start() -> Self {
+        Self { cpu_start: thread_cpu_time(), wall_start: Instant::now() }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match (self.cpu_start, thread_cpu_time()) {
+            (Some(start), Some(now)) => now.saturating_sub(start),
+            _ => self.wall_start.elapsed(),
+        }
+    }
+}
+
+/// schedstat 第一个字段：线程在 CPU 上跑过的纳秒数
+fn thread_cpu_time() -> Option<Duration> {
+    let s = fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let ns = s.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(ns))
+}
+
+/// Per column family counters. Cumulative since the DB was opened.
+#[derive(Debug, Default)]
+pub struct CfStatistics {
+    bloom_filter_checked: AtomicU64,
+    bloom_filter_useful: AtomicU64,
+    flush_count: AtomicU64,
+    flush_cpu_micros: AtomicU64,
+    flush_bytes_written: AtomicU64,
+    compaction_count: AtomicU64,
+    compaction_cpu_micros: AtomicU64,
+    compaction_bytes_read: AtomicU64,
+    compaction_bytes_written: AtomicU64,
+}
+
+impl CfStatistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one filter probe; `useful` when the filter said "definitely not here".
+    pub fn record_filter_check(&self, useful: bool) {
+        self.bloom_filter_checked.fetch_add(1, Ordering::Relaxed);
+        if useful {
+            self.bloom_filter_useful.fetch_add(1, Ordering::Relaxed);
+        }
+        perf_record(|p| {
+            p.bloom_sst_checked += 1;
+            p.bloom_sst_useful += useful as u64;
+        });
+    }
+
+    pub fn bloom_filter_checked(&self) -> u64 {
+        self.bloom_filter_checked.load(Ordering::Relaxed)
+    }
+
+    pub fn bloom_filter_useful(&self) -> u64 {
+        self.bloom_filter_useful.load(Ordering::Relaxed)
+    }
+
+    /// Charge a finished flush / compaction to this column family.
+    pub fn record_job(&self, record: &JobRecord) {
+        let (count, cpu, written) = match record.kind {
+            JobKind::Flush => (&self.flush_count, &self.flush_cpu_micros, &self.flush_bytes_written),
+            JobKind::Compaction => {
+                self.compaction_bytes_read.fetch_add(record.input_bytes, Ordering::Relaxed);
+                (&self.compaction_count, &self.compaction_cpu_micros, &self.compaction_bytes_written)
+            }
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        cpu.fetch_add(record.cpu_micros, Ordering::Relaxed);
+        written.fetch_add(record.output_bytes, Ordering::Relaxed);
+    }
+
+    /// Look up a counter by property name.
+    pub fn get_property(&self, name: &str) -> Option<u64> {
+        let counter = match name {
+            properties::BLOOM_FILTER_CHECKED => &self.bloom_filter_checked,
+            properties::BLOOM_FILTER_USEFUL => &self.bloom_filter_useful,
+            properties::FLUSH_COUNT => &self.flush_count,
+            properties::FLUSH_CPU_MICROS => &self.flush_cpu_micros,
+            properties::FLUSH_BYTES_WRITTEN => &self.flush_bytes_written,
+            properties::COMPACTION_COUNT => &self.compaction_count,
+            properties::COMPACTION_CPU_MICROS => &self.compaction_cpu_micros,
+            properties::COMPACTION_BYTES_READ => &self.compaction_bytes_read,
+            properties::COMPACTION_BYTES_WRITTEN => &self.compaction_bytes_written,
+            _ => return None,
+        };
+        Some(counter.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn jobs_are_charged_by_kind() {
+        let stats = CfStatistics::new();
+        let mut flush = JobRecord::new(JobKind::Flush, 0, SystemTime::now(), Duration::ZERO);
+        flush.cpu_micros = 30;
+        flush.output_bytes = 1000;
+        stats.record_job(&flush);
+
+        let mut compaction = JobRecord::new(JobKind::Compaction, 0, SystemTime::now(), Duration::ZERO);
+        compaction.cpu_micros = 70;
+        compaction.input_bytes = 3000;
+        compaction.output_bytes = 2500;
+        stats.record_job(&compaction);
+        stats.record_job(&compaction);
+
+        let get = |name| stats.get_property(name).unwrap();
+        assert_eq!(get(properties::FLUSH_COUNT), 1);
+        assert_eq!(get(properties::FLUSH_CPU_MICROS), 30);
+        assert_eq!(get(properties::FLUSH_BYTES_WRITTEN), 1000);
+        assert_eq!(get(properties::COMPACTION_COUNT), 2);
+        assert_eq!(get(properties::COMPACTION_CPU_MICROS), 140);
+        assert_eq!(get(properties::COMPACTION_BYTES_READ), 6000);
+        assert_eq!(get(properties::COMPACTION_BYTES_WRITTEN), 5000);
+        assert_eq!(stats.get_property(properties::NUM_SNAPSHOTS), None);
+    }
+
+    #[test]
+    fn cpu_timer_only_moves_forward() {
+        let timer = CpuTimer::start();
+        let mut x = 0u64;
+        for i in 0..1_000_000u64 {
+            x = x.wrapping_mul(31).wrapping_add(i);
+        }
+        std::hint::black_box(x);
+        let first = timer.elapsed();
+        assert!(timer.elapsed() >= first);
+    }
+}
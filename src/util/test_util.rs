@@ -0,0 +1,27 @@
+//! 测试共用的 fixture：临时目录、打开 DB、写一条再 flush 成一个 L0 文件
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::db::db_impl::DBImpl;
+use crate::db::db_trait::DB;
+use crate::engine::mem::ColumnFamilyId;
+use crate::util::WriteOptions;
+
+/// 临时目录下这个测试进程独占的空目录（不创建，只清掉上次留下的）
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vectorkv-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// 在 `test_dir(name)` 上打开一个新 DB
+pub(crate) fn open_db(name: &str) -> (Arc<DBImpl>, PathBuf) {
+    let dir = test_dir(name);
+    (DBImpl::open(dir.to_str().unwrap()).unwrap(), dir)
+}
+
+/// 写一条再 flush，落成一个 L0 文件
+pub(crate) fn put_flushed(db: &DBImpl, cf: ColumnFamilyId, key: &[u8], value: &[u8]) {
+    db.put(&WriteOptions::default(), cf, key, value).unwrap();
+    db.flush_memtables_of(&[cf]).unwrap();
+}
@@ -0,0 +1,181 @@
+//! W3C trace context (`traceparent`) propagation.
+//!
+//! The server parses the caller's `traceparent` and installs it on the thread
+//! serving the request; engine operations then open child spans with
+//! `Span::enter`. Finished spans are logged under the `vectorkv::trace` target
+//! with their own `traceparent`, their parent span id and duration, so a log
+//! collector can stitch them into the caller's distributed trace. Background
+//! work started by a request (e.g. the flush a write triggers) carries the
+//! context of that request.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use crate::error::DBError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<TraceContext>> = const { Cell::new(None) };
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+/// 随机 span id：进程内计数器过一遍随机种子的 hasher，不会是全 0
+fn new_span_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (h.finish() | 1).to_be_bytes()
+}
+
+impl TraceContext {
+    /// Parses `00-<32 hex trace id>-<16 hex span id>-<2 hex flags>`.
+    pub fn parse(traceparent: &str) -> Result<Self, DBError> {
+        let bad = || DBError::InvalidArgument(format!("bad traceparent: {}", traceparent));
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        // 只认 version 00；更高版本按规范可以有更多字段，前四段格式不变
+        let [version, trace_id, span_id, flags, ..] = parts[..] else { return Err(bad()) };
+        if version == "ff" || unhex::<1>(version).is_none() || (version == "00" && parts.len() != 4) {
+            return Err(bad());
+        }
+        let trace_id = unhex::<16>(trace_id).ok_or_else(bad)?;
+        let span_id = unhex::<8>(span_id).ok_or_else(bad)?;
+        let [flags] = unhex::<1>(flags).ok_or_else(bad)?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return Err(bad());
+        }
+        Ok(Self { trace_id, span_id, sampled: flags & 1 == 1 })
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", hex(&self.trace_id), hex(&self.span_id), self.sampled as u8)
+    }
+
+    /// Same trace, new span id.
+    pub fn child(&self) -> Self {
+        Self { span_id: new_span_id(), ..*self }
+    }
+
+    /// The context installed on this thread.
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|c| c.get())
+    }
+
+    /// Runs `f` with `ctx` installed on this thread, restoring the previous
+    /// context afterwards.
+    pub fn scope<R>(ctx: Option<Self>, f: impl FnOnce() -> R) -> R {
+        let prev = CURRENT.with(|c| c.replace(ctx));
+        let out = f();
+        CURRENT.with(|c| c.set(prev));
+        out
+    }
+}
+
+/// A child span of the current context; logged when dropped. No-op when the
+/// thread has no (sampled) context.
+pub struct Span {
+    name: &'static str,
+    ctx: TraceContext,
+    parent: TraceContext,
+    start: Instant,
+}
+
+impl Span {
+    pub fn enter(name: &'static str) -> Option<Span> {
+        let parent = TraceContext::current().filter(|p| p.sampled)?;
+        let ctx = parent.child();
+        CURRENT.with(|c| c.set(Some(ctx)));
+        Some(Span { name, ctx, parent, start: Instant::now() })
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        log::debug!(
+            target: "vectorkv::trace",
+            "span={} traceparent={} parent_span={} duration_us={}",
+            self.name,
+            self.ctx.to_traceparent(),
+            hex(&self.parent.span_id),
+            self.start.elapsed().as_micros(),
+        );
+        CURRENT.with(|c| c.set(Some(self.parent)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn traceparent_round_trip() {
+        let ctx = TraceContext::parse(PARENT).unwrap();
+        assert!(ctx.sampled);
+        assert_eq!(ctx.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+        assert_eq!(ctx.to_traceparent(), PARENT);
+        assert!(!TraceContext::parse(&PARENT.replace("-01", "-00")).unwrap().sampled);
+        // 更高版本可以多带字段
+        assert!(TraceContext::parse(&format!("{}-extra", PARENT.replacen("00", "01", 1))).is_ok());
+
+        for bad in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            &format!("{}-extra", PARENT),
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e47zz-00f067aa0ba902b7-01",
+        ] {
+            assert!(matches!(TraceContext::parse(bad), Err(DBError::InvalidArgument(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn spans_nest_under_the_installed_context() {
+        let parent = TraceContext::parse(PARENT).unwrap();
+        assert!(Span::enter("no-context").is_none());
+
+        TraceContext::scope(Some(parent), || {
+            let outer = Span::enter("outer").unwrap();
+            let current = TraceContext::current().unwrap();
+            assert_eq!(current.trace_id, parent.trace_id);
+            assert_ne!(current.span_id, parent.span_id);
+            {
+                let _inner = Span::enter("inner").unwrap();
+                assert_ne!(TraceContext::current().unwrap().span_id, current.span_id);
+            }
+            assert_eq!(TraceContext::current(), Some(current));
+            drop(outer);
+            assert_eq!(TraceContext::current(), Some(parent));
+        });
+        assert_eq!(TraceContext::current(), None);
+
+        // 没采样的不开 span
+        let unsampled = TraceContext { sampled: false, ..parent };
+        TraceContext::scope(Some(unsampled), || assert!(Span::enter("skipped").is_none()));
+    }
+}
@@ -0,0 +1,130 @@
+//! Recall / latency calibration for approximate search.
+//!
+//! Every sample query is answered once exhaustively and then once per
+//! candidate `ef_search`; recall is the fraction of the exact top-k that the
+//! approximate search returned. The suggestion is the smallest `ef_search`
+//! whose mean recall reaches the target, or an exhaustive search if none does.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use crate::error::DBError;
+use crate::vector::{KnnRequest, KnnResponse, SearchOptions};
+
+pub const DEFAULT_EF_CANDIDATES: &[usize] = &[16, 32, 64, 128, 256, 512];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationPoint {
+    pub ef_search: usize,
+    /// 所有样本的平均召回，[0, 1]
+    pub recall: f64,
+    pub mean_latency: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationReport {
+    /// 按 ef_search 升序
+    pub points: Vec<CalibrationPoint>,
+    /// 全量扫描的平均耗时，作为对照
+    pub exhaustive_latency: Duration,
+    pub suggested: SearchOptions,
+}
+
+/// Measure recall of `search` at each of `ef_candidates` against exhaustive search.
+pub fn calibrate<F>(
+    queries: &[Vec<f32>],
+    k: usize,
+    target_recall: f64,
+    ef_candidates: &[usize],
+    mut search: F,
+) -> Result<CalibrationReport, DBError>
+where
+    F: FnMut(&KnnRequest) -> Result<KnnResponse, DBError>,
+{
+    if queries.is_empty() {
+        return Err(DBError::InvalidArgument("calibration needs at least one query".into()));
+    }
+
+    // 1) 精确结果
+    let mut exact: Vec<HashSet<Vec<u8>>> = Vec::with_capacity(queries.len());
+    let mut exhaustive_total = Duration::ZERO;
+    for q in queries {
+        let mut request = KnnRequest::new(q.clone(), k);
+        request.search.exhaustive = true;
+        let start = Instant::now();
+        let response = search(&request)?;
+        exhaustive_total += start.elapsed();
+        exact.push(response.hits.into_iter().map(|h| h.key).collect());
+    }
+
+    // 2) 每个 ef 跑一遍
+    let mut candidates: Vec<usize> = ef_candidates.to_vec();
+    candidates.sort_unstable();
+    candidates.dedup();
+    let mut points = Vec::with_capacity(candidates.len());
+    for ef in candidates {
+        let mut recall_sum = 0.0;
+        let mut total = Duration::ZERO;
+        for (q, truth) in queries.iter().zip(&exact) {
+            let mut request = KnnRequest::new(q.clone(), k);
+            request.search.ef_search = Some(ef);
+            let start = Instant::now();
+            let response = search(&request)?;
+            total += start.elapsed();
+            recall_sum += match truth.len() {
+                0 => 1.0,
+                n => response.hits.iter().filter(|h| truth.contains(&h.key)).count() as f64 / n as f64,
+            };
+        }
+        points.push(CalibrationPoint {
+            ef_search: ef,
+            recall: recall_sum / queries.len() as f64,
+            mean_latency: total / queries.len() as u32,
+        });
+    }
+
+    let suggested = match points.iter().find(|p| p.recall >= target_recall) {
+        Some(p) => SearchOptions { ef_search: Some(p.ef_search), ..SearchOptions::default() },
+        None => SearchOptions { exhaustive: true, ..SearchOptions::default() },
+    };
+    Ok(CalibrationReport {
+        points,
+        exhaustive_latency: exhaustive_total / queries.len() as u32,
+        suggested,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::KnnHit;
+
+    /// 精确结果是 0..k；近似搜索 ef 每多 16 多找对一个
+    fn fake_search(request: &KnnRequest) -> Result<KnnResponse, DBError> {
+        let right = match request.search.ef_search {
+            _ if request.search.exhaustive => request.k,
+            Some(ef) => (ef / 16).min(request.k),
+            None => 0,
+        };
+        let hits = (0..request.k)
+            .map(|i| {
+                let key = if i < right { i } else { 100 + i };
+                KnnHit { key: vec![key as u8], score: 0.0, payload: None }
+            })
+            .collect();
+        Ok(KnnResponse { hits, next_page_token: None })
+    }
+
+    #[test]
+    fn suggests_the_smallest_ef_reaching_the_target() {
+        let queries = vec![vec![0.0], vec![1.0]];
+        let report = calibrate(&queries, 4, 0.75, &[64, 16, 32, 32], fake_search).unwrap();
+        let recalls: Vec<_> = report.points.iter().map(|p| (p.ef_search, p.recall)).collect();
+        assert_eq!(recalls, vec![(16, 0.25), (32, 0.5), (64, 1.0)]);
+        assert_eq!(report.suggested, SearchOptions { ef_search: Some(64), ..SearchOptions::default() });
+
+        // 哪个都不够：建议全量扫描
+        let report = calibrate(&queries, 4, 0.9, &[16, 32], fake_search).unwrap();
+        assert!(report.suggested.exhaustive);
+        assert!(calibrate(&[], 4, 0.9, DEFAULT_EF_CANDIDATES, fake_search).is_err());
+    }
+}
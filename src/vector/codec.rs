@@ -0,0 +1,110 @@
+//! 向量 CF 的 value 布局：dim(u32 LE) + dim 个 f32 LE + payload（原样保存的任意字节）
+
+pub fn encode_vector(vector: &[f32], payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + vector.len() * 4 + payload.len());
+    buf.extend_from_slice(&(vector.len() as u32).to_le_bytes());
+    for x in vector {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// -> (vector, payload)；不是向量 value 时返回 None
+pub fn decode_vector(value: &[u8]) -> Option<(Vec<f32>, &[u8])> {
+    let dim = u32::from_le_bytes(value.get(..4)?.try_into().ok()?) as usize;
+    let end = 4usize.checked_add(dim.checked_mul(4)?)?;
+    let raw = value.get(4..end)?;
+    let vector = raw
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+        .collect();
+    Some((vector, &value[end..]))
+}
+
+/// 多列向量（宽列实体）的 value：
+/// ENTITY_MARKER(u32 LE) + 列数(u32 LE) + 每列 [name_len(u16 LE) + name + dim(u32 LE) + dim 个 f32 LE] + payload
+///
+/// ENTITY_MARKER 作为单向量布局的 dim 永远放不下，所以 `decode_vector` 对它返回 None
+const ENTITY_MARKER: u32 = u32::MAX;
+
+pub fn encode_vector_columns(columns: &[(&str, &[f32])], payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&ENTITY_MARKER.to_le_bytes());
+    buf.extend_from_slice(&(columns.len() as u32).to_le_bytes());
+    for (name, vector) in columns {
+        buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(&(vector.len() as u32).to_le_bytes());
+        for x in *vector {
+            buf.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// -> (column 列的向量, payload)；不是多列 value 或没有这一列时返回 None
+pub fn decode_vector_column<'a>(value: &'a [u8], column: &str) -> Option<(Vec<f32>, &'a [u8])> {
+    let read_u32 = |pos: usize| -> Option<u32> { Some(u32::from_le_bytes(value.get(pos..pos + 4)?.try_into().ok()?)) };
+    if read_u32(0)? != ENTITY_MARKER {
+        return None;
+    }
+    let count = read_u32(4)?;
+    let mut pos = 8usize;
+    let mut found = None;
+    for _ in 0..count {
+        let name_len = u16::from_le_bytes(value.get(pos..pos + 2)?.try_into().ok()?) as usize;
+        let name = value.get(pos + 2..pos + 2 + name_len)?;
+        pos += 2 + name_len;
+        let dim = read_u32(pos)? as usize;
+        let end = (pos + 4).checked_add(dim.checked_mul(4)?)?;
+        let raw = value.get(pos + 4..end)?;
+        if name == column.as_bytes() {
+            found = Some(raw.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect());
+        }
+        pos = end;
+    }
+    Some((found?, &value[pos..]))
+}
+
+/// `index` 为 "" 时读单向量 value，否则读同名的列
+pub fn decode_indexed_vector<'a>(value: &'a [u8], index: &str) -> Option<(Vec<f32>, &'a [u8])> {
+    match index {
+        "" => decode_vector(value),
+        _ => decode_vector_column(value, index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_vector_round_trip() {
+        let value = encode_vector(&[1.0, -2.5, 3.25], b"payload");
+        let (vector, payload) = decode_vector(&value).unwrap();
+        assert_eq!(vector, vec![1.0, -2.5, 3.25]);
+        assert_eq!(payload, b"payload");
+        assert_eq!(decode_indexed_vector(&value, "").unwrap().0, vector);
+
+        // 截断的 value 不是向量
+        assert!(decode_vector(&value[..10]).is_none());
+        assert!(decode_vector(b"ab").is_none());
+    }
+
+    #[test]
+    fn vector_columns_round_trip() {
+        let text = [0.5f32, 0.25];
+        let image = [1.0f32, 2.0, 3.0];
+        let value = encode_vector_columns(&[("text", &text), ("image", &image)], b"doc");
+
+        assert_eq!(decode_vector_column(&value, "image").unwrap(), (image.to_vec(), &b"doc"[..]));
+        assert_eq!(decode_indexed_vector(&value, "text").unwrap().0, text.to_vec());
+        assert!(decode_vector_column(&value, "audio").is_none());
+        // 单向量读法不会把多列 value 误认成向量
+        assert!(decode_vector(&value).is_none());
+        assert!(decode_vector_column(&encode_vector(&text, b""), "text").is_none());
+        assert!(decode_vector_column(&value[..value.len() - 8], "image").is_none());
+    }
+}
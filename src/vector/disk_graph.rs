@@ -0,0 +1,398 @@
+//! Disk-resident HNSW graph.
+//!
+//! File layout (integers little endian):
+//! ```text
+//! header  magic u32 | dim u32 | max_degree u32 | nodes_per_page u32
+//!         node_count u64 | entry u64 | upper_offset u64 | keys_offset u64
+//! slots   node i at HEADER_SIZE + i * slot_size:
+//!         key_offset u64 | key_len u32 | degree u32 | max_degree x u32 | dim x f32
+//! upper   count u64, then per node above level 0:
+//!         id u32 | levels u32 | per level: len u32 | len x u32
+//! keys    all keys back to back
+//! ```
+//! Only the header and the upper levels (about 1/m of the nodes) stay in
+//! memory. Level-0 adjacency and vectors are read a page (`nodes_per_page`
+//! slots) at a time through a `BlockCache`, so memory is bounded by the cache
+//! capacity rather than the graph size; keys are read directly for results.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::error::DBError;
+use crate::util::read_exact_at;
+use crate::engine::sst::block::{BlockCache, BlockCacheKey};
+use crate::vector::Metric;
+use crate::vector::hnsw::Scored;
+
+const MAGIC: u32 = 0x5647_5246;
+const HEADER_SIZE: u64 = 48;
+const PAGE_TARGET: usize = 4096;
+const NO_ENTRY: u64 = u64::MAX;
+
+/// cache key 里的 file_number：图文件不归 VersionSet 管，单独编号
+static NEXT_GRAPH_ID: AtomicU64 = AtomicU64::new(1);
+
+pub type GraphPageCache = BlockCache<GraphPage>;
+
+/// 连续 `nodes_per_page` 个节点槽位
+pub struct GraphPage {
+    data: Vec<u8>,
+}
+
+/// 写盘的一个节点；邻居 id 是节点在数组里的下标
+pub struct GraphNode<'a> {
+    pub key: &'a [u8],
+    pub vector: &'a [f32],
+    pub neighbors: Vec<Vec<u32>>,
+}
+
+pub struct DiskGraph {
+    id: u64,
+    path: PathBuf,
+    file: File,
+    metric: Metric,
+    dim: usize,
+    max_degree: usize,
+    nodes_per_page: usize,
+    node_count: usize,
+    entry: Option<u32>,
+    /// upper[id][l - 1] = 第 l 层的邻居
+    upper: HashMap<u32, Vec<Vec<u32>>>,
+    cache: Arc<GraphPageCache>,
+}
+
+fn slot_size(dim: usize, max_degree: usize) -> usize {
+    16 + max_degree * 4 + dim * 4
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+impl DiskGraph {
+    /// Write `nodes` to `path` and open the result.
+    pub fn create(
+        path: &Path,
+        metric: Metric,
+        max_degree: usize,
+        nodes: &[GraphNode<'_>],
+        entry: Option<u32>,
+        cache: Arc<GraphPageCache>,
+    ) -> Result<Self, DBError> {
+        let dim = nodes.first().map_or(0, |n| n.vector.len());
+        if nodes.iter().any(|n| n.vector.len() != dim) {
+            return Err(DBError::InvalidArgument("on-disk vector index needs vectors of a single dimension".into()));
+        }
+        let slot = slot_size(dim, max_degree);
+        let nodes_per_page = (PAGE_TARGET / slot).max(1);
+        let upper_offset = HEADER_SIZE + (nodes.len() * slot) as u64;
+
+        // 1) 上层邻接表
+        let mut upper = Vec::new();
+        let upper_nodes: Vec<(usize, &GraphNode)> =
+            nodes.iter().enumerate().filter(|(_, n)| n.neighbors.len() > 1).collect();
+        upper.extend_from_slice(&(upper_nodes.len() as u64).to_le_bytes());
+        for (id, n) in &upper_nodes {
+            upper.extend_from_slice(&(*id as u32).to_le_bytes());
+            upper.extend_from_slice(&((n.neighbors.len() - 1) as u32).to_le_bytes());
+            for level in &n.neighbors[1..] {
+                upper.extend_from_slice(&(level.len() as u32).to_le_bytes());
+                for id in level {
+                    upper.extend_from_slice(&id.to_le_bytes());
+                }
+            }
+        }
+        let keys_offset = upper_offset + upper.len() as u64;
+
+        let mut w = BufWriter::new(File::create(path)?);
+
+        // 2) header
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&MAGIC.to_le_bytes());
+        header.extend_from_slice(&(dim as u32).to_le_bytes());
+        header.extend_from_slice(&(max_degree as u32).to_le_bytes());
+        header.extend_from_slice(&(nodes_per_page as u32).to_le_bytes());
+        header.extend_from_slice(&(nodes.len() as u64).to_le_bytes());
+        header.extend_from_slice(&entry.map_or(NO_ENTRY, u64::from).to_le_bytes());
+        header.extend_from_slice(&upper_offset.to_le_bytes());
+        header.extend_from_slice(&keys_offset.to_le_bytes());
+        w.write_all(&header)?;
+
+        // 3) 节点槽位
+        let mut key_offset = keys_offset;
+        let mut buf = Vec::with_capacity(slot);
+        for n in nodes {
+            buf.clear();
+            buf.extend_from_slice(&key_offset.to_le_bytes());
+            buf.extend_from_slice(&(n.key.len() as u32).to_le_bytes());
+            let level0 = &n.neighbors[0][..n.neighbors[0].len().min(max_degree)];
+            buf.extend_from_slice(&(level0.len() as u32).to_le_bytes());
+            for i in 0..max_degree {
+                buf.extend_from_slice(&level0.get(i).copied().unwrap_or(0).to_le_bytes());
+            }
+            for x in n.vector {
+                buf.extend_from_slice(&x.to_le_bytes());
+            }
+            w.write_all(&buf)?;
+            key_offset += n.key.len() as u64;
+        }
+
+        // 4) 上层 + keys
+        w.write_all(&upper)?;
+        for n in nodes {
+            w.write_all(n.key)?;
+        }
+        w.into_inner().map_err(|e| DBError::Io(e.into_error()))?.sync_all()?;
+
+        Self::open(path, metric, cache)
+    }
+
+    pub fn open(path: &Path, metric: Metric, cache: Arc<GraphPageCache>) -> Result<Self, DBError> {
+        let file = File::open(path)?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        read_exact_at(&file, &mut header, 0)?;
+        if u32_at(&header, 0) != MAGIC {
+            return Err(DBError::Corruption(format!("{} is not a vector graph file", path.display())));
+        }
+        let dim = u32_at(&header, 4) as usize;
+        let max_degree = u32_at(&header, 8) as usize;
+        let nodes_per_page = (u32_at(&header, 12) as usize).max(1);
+        let node_count = u64_at(&header, 16) as usize;
+        let entry = match u64_at(&header, 24) {
+            NO_ENTRY => None,
+            e => Some(e as u32),
+        };
+        let upper_offset = u64_at(&header, 32);
+        let keys_offset = u64_at(&header, 40);
+
+        let mut raw = vec![0u8; keys_offset.saturating_sub(upper_offset) as usize];
+        read_exact_at(&file, &mut raw, upper_offset)?;
+        let corrupt = || DBError::Corruption(format!("truncated upper levels in {}", path.display()));
+        let mut pos = 0usize;
+        let take_u32 = |pos: &mut usize| -> Result<u32, DBError> {
+            let v = raw.get(*pos..*pos + 4).ok_or_else(corrupt)?;
+            *pos += 4;
+            Ok(u32::from_le_bytes(v.try_into().unwrap()))
+        };
+        let count = {
+            let lo = take_u32(&mut pos)? as u64;
+            let hi = take_u32(&mut pos)? as u64;
+            lo | (hi << 32)
+        };
+        let mut upper = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = take_u32(&mut pos)?;
+            let levels = take_u32(&mut pos)? as usize;
+            let mut per_level = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = take_u32(&mut pos)? as usize;
+                let mut ids = Vec::with_capacity(len);
+                for _ in 0..len {
+                    ids.push(take_u32(&mut pos)?);
+                }
+                per_level.push(ids);
+            }
+            upper.insert(id, per_level);
+        }
+
+        Ok(Self {
+            id: NEXT_GRAPH_ID.fetch_add(1, Ordering::Relaxed),
+            path: path.to_path_buf(),
+            file,
+            metric,
+            dim,
+            max_degree,
+            nodes_per_page,
+            node_count,
+            entry,
+            upper,
+            cache,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.node_count == 0
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 常驻内存的部分：上层邻接表
+    pub fn resident_bytes(&self) -> usize {
+        self.upper.values().flatten().map(|l| l.len() * 4 + std::mem::size_of::<Vec<u32>>()).sum()
+    }
+
+    fn slot_size(&self) -> usize {
+        slot_size(self.dim, self.max_degree)
+    }
+
+    fn page_count(&self) -> usize {
+        self.node_count.div_ceil(self.nodes_per_page)
+    }
+
+    fn read_page(&self, page: usize) -> Result<GraphPage, DBError> {
+        let first = page * self.nodes_per_page;
+        let count = self.nodes_per_page.min(self.node_count - first);
+        let mut data = vec![0u8; count * self.slot_size()];
+        read_exact_at(&self.file, &mut data, HEADER_SIZE + (first * self.slot_size()) as u64)?;
+        Ok(GraphPage { data })
+    }
+
+    fn cached_page(&self, page: usize) -> Result<Arc<GraphPage>, DBError> {
+        let key = BlockCacheKey { file_number: self.id, block_offset: page as u64 };
+        if let Some(p) = self.cache.get(&key) {
+            return Ok(p);
+        }
+        let p = Arc::new(self.read_page(page)?);
+        self.cache.insert(key, Arc::clone(&p), p.data.len());
+        Ok(p)
+    }
+
+    /// (level-0 邻居, 向量, key 位置)
+    fn decode_slot(&self, page: &GraphPage, id: u32) -> (Vec<u32>, Vec<f32>, (u64, usize)) {
+        let slot = self.slot_size();
+        let start = (id as usize % self.nodes_per_page) * slot;
+        let s = &page.data[start..start + slot];
+        let key_at = (u64_at(s, 0), u32_at(s, 8) as usize);
+        let degree = (u32_at(s, 12) as usize).min(self.max_degree);
+        let neighbors = (0..degree).map(|i| u32_at(s, 16 + i * 4)).collect();
+        let vstart = 16 + self.max_degree * 4;
+        let vector = s[vstart..]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        (neighbors, vector, key_at)
+    }
+
+    fn node(&self, id: u32) -> Result<(Vec<u32>, Vec<f32>, (u64, usize)), DBError> {
+        let page = self.cached_page(id as usize / self.nodes_per_page)?;
+        Ok(self.decode_slot(&page, id))
+    }
+
+    fn read_key(&self, (offset, len): (u64, usize)) -> Result<Vec<u8>, DBError> {
+        let mut key = vec![0u8; len];
+        read_exact_at(&self.file, &mut key, offset)?;
+        Ok(key)
+    }
+
+    fn score(&self, query: &[f32], vector: &[f32]) -> f32 {
+        self.metric.score(query, vector).unwrap_or(f32::NEG_INFINITY)
+    }
+
+    /// 至多 k 个 (key, vector, score)，score 降序；`skip(key)` 为 true 的节点不返回
+    pub fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        skip: impl Fn(&[u8]) -> bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<f32>, f32)>, DBError> {
+        let Some(mut ep) = self.entry else { return Ok(Vec::new()) };
+        let mut ep_score = self.score(query, &self.node(ep)?.1);
+
+        // 1) 上层（邻接表在内存，向量走 cache）贪心下降
+        let top = self.upper.get(&ep).map_or(0, |l| l.len());
+        for level in (1..=top).rev() {
+            loop {
+                let mut changed = false;
+                let links = self.upper.get(&ep).and_then(|l| l.get(level - 1)).cloned().unwrap_or_default();
+                for n in links {
+                    let s = self.score(query, &self.node(n)?.1);
+                    if s > ep_score {
+                        ep_score = s;
+                        ep = n;
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+
+        // 2) 第 0 层 best-first
+        let ef = ef.max(k);
+        let mut visited: HashSet<u32> = HashSet::from([ep]);
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::from([Scored { score: ep_score, id: ep }]);
+        let mut results: BinaryHeap<Reverse<Scored>> = BinaryHeap::from([Reverse(Scored { score: ep_score, id: ep })]);
+        let mut vectors: HashMap<u32, (Vec<f32>, (u64, usize))> = HashMap::new();
+
+        while let Some(c) = candidates.pop() {
+            let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.score);
+            if results.len() >= ef && c.score < worst {
+                break;
+            }
+            let (links, vector, key_at) = self.node(c.id)?;
+            vectors.insert(c.id, (vector, key_at));
+            for n in links {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let (_, v, key_at) = self.node(n)?;
+                let s = Scored { score: self.score(query, &v), id: n };
+                vectors.insert(n, (v, key_at));
+                let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.score);
+                if results.len() < ef || s.score > worst {
+                    candidates.push(s);
+                    results.push(Reverse(s));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut ranked: Vec<Scored> = results.into_iter().map(|r| r.0).collect();
+        ranked.sort_by(|a, b| b.cmp(a));
+        let mut out = Vec::with_capacity(k);
+        for c in ranked {
+            if out.len() == k {
+                break;
+            }
+            let Some((vector, key_at)) = vectors.remove(&c.id) else { continue };
+            let key = self.read_key(key_at)?;
+            if !skip(&key) {
+                out.push((key, vector, c.score));
+            }
+        }
+        Ok(out)
+    }
+
+    /// 顺序读出所有节点（不进 cache，避免重建把热页挤掉）
+    pub fn for_each(&self, mut f: impl FnMut(Vec<u8>, Vec<f32>)) -> Result<(), DBError> {
+        for page in 0..self.page_count() {
+            let p = self.read_page(page)?;
+            let first = page * self.nodes_per_page;
+            for i in 0..p.data.len() / self.slot_size() {
+                let (_, vector, key_at) = self.decode_slot(&p, (first + i) as u32);
+                f(self.read_key(key_at)?, vector);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskGraph {
+    /// 图文件只被这一个实例引用：被新图替换、最后一个查询结束后删掉
+    fn drop(&mut self) {
+        for page in 0..self.page_count() {
+            self.cache.erase(&BlockCacheKey { file_number: self.id, block_offset: page as u64 });
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("failed to remove vector graph {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::thread;
+use crate::error::DBError;
+
+/// 把文档转成向量，按 CF 注册（见 `DBImpl::register_embedder`）
+///
+/// 可能被多个线程同时调用
+pub trait Embedder: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn embed(&self, document: &[u8]) -> Result<Vec<f32>, DBError>;
+}
+
+/// 用 `threads` 个线程并行 embed，结果顺序和输入一致；任何一个失败整体失败
+pub fn embed_all(
+    embedder: &dyn Embedder,
+    documents: &[&[u8]],
+    threads: usize,
+) -> Result<Vec<Vec<f32>>, DBError> {
+    if threads <= 1 || documents.len() <= 1 {
+        return documents.iter().map(|d| embedder.embed(d)).collect();
+    }
+
+    let chunk = documents.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = documents
+            .chunks(chunk)
+            .map(|part| s.spawn(move || part.iter().map(|d| embedder.embed(d)).collect::<Result<Vec<_>, _>>()))
+            .collect();
+
+        let mut out = Vec::with_capacity(documents.len());
+        for h in handles {
+            let part = h.join().map_err(|_| DBError::Other(format!("embedder {} panicked", embedder.name())))??;
+            out.extend(part);
+        }
+        Ok(out)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 向量 = [文档长度]；"bad" 失败
+    struct Length;
+
+    impl Embedder for Length {
+        fn name(&self) -> &str {
+            "length"
+        }
+
+        fn embed(&self, document: &[u8]) -> Result<Vec<f32>, DBError> {
+            if document == b"bad" {
+                return Err(DBError::InvalidArgument("bad document".into()));
+            }
+            Ok(vec![document.len() as f32])
+        }
+    }
+
+    #[test]
+    fn embed_all_keeps_the_input_order() {
+        let documents: Vec<Vec<u8>> = (0..10).map(|i| vec![b'x'; i]).collect();
+        let refs: Vec<&[u8]> = documents.iter().map(|d| d.as_slice()).collect();
+        let expected: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32]).collect();
+        assert_eq!(embed_all(&Length, &refs, 1).unwrap(), expected);
+        assert_eq!(embed_all(&Length, &refs, 3).unwrap(), expected);
+        assert_eq!(embed_all(&Length, &refs, 32).unwrap(), expected);
+    }
+
+    #[test]
+    fn one_failure_fails_the_batch() {
+        let refs: Vec<&[u8]> = vec![b"a", b"bb", b"bad", b"c"];
+        assert!(embed_all(&Length, &refs, 2).is_err());
+        assert!(embed_all(&Length, &refs, 1).is_err());
+    }
+}
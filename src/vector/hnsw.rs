@@ -0,0 +1,780 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::error::DBError;
+use crate::vector::Metric;
+use crate::vector::disk_graph::{DiskGraph, GraphNode, GraphPageCache};
+
+/// 层数上限，避免极小概率的随机层数把图拉得很高
+const MAX_LEVEL: usize = 16;
+
+/// 向量索引类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexType {
+    /// 不建索引，查询时全量扫描
+    #[default]
+    Flat,
+    Hnsw,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HnswParams {
+    /// 每层的邻居数（第 0 层允许 2 * m）
+    pub m: usize,
+    /// 插入时的候选队列长度
+    pub ef_construction: usize,
+    /// 查询时的候选队列长度（不小于 k）
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self { m: 16, ef_construction: 200, ef_search: 64 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Scored {
+    pub(crate) score: f32,
+    pub(crate) id: u32,
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+struct Node {
+    key: Vec<u8>,
+    vector: Vec<f32>,
+    /// neighbors[l] = 第 l 层的邻居，len() - 1 就是节点的层数
+    neighbors: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+impl Node {
+    fn level(&self) -> usize {
+        self.neighbors.len() - 1
+    }
+}
+
+/// `vectorkv.vector-index-stats` 里一个 HNSW 图的统计
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HnswStats {
+    /// 活节点数
+    pub nodes: usize,
+    /// 还占着内存的墓碑数（下一次重建回收）
+    pub deleted: usize,
+    pub unrepaired: usize,
+    /// 内存里活节点第 0 层的平均出度
+    pub avg_degree: f64,
+    /// level_histogram[l] = 内存里最高层为 l 的活节点数
+    pub level_histogram: Vec<usize>,
+    /// 估算值：向量、key、邻居表和 key 映射，加上落盘图常驻内存的上层；不含页 cache
+    pub memory_bytes: usize,
+    /// 上一次（重）建完成的时间，unix 毫秒
+    pub built_at_ms: u64,
+    pub rebuilding: bool,
+    /// 落盘部分的节点数，0 = 全在内存
+    pub on_disk_nodes: usize,
+    /// 落盘之后被覆盖 / 删除过的 key
+    pub superseded: usize,
+}
+
+/// 图落盘的位置和读盘用的 cache
+#[derive(Clone)]
+pub struct SpillTarget {
+    pub dir: PathBuf,
+    /// 文件名前缀，区分不同 CF / 索引
+    pub prefix: String,
+    pub cache: Arc<GraphPageCache>,
+}
+
+static NEXT_SPILL_FILE: AtomicU64 = AtomicU64::new(1);
+
+/// 重建期间发生的修改：(key, Some(vector)) = 插入，(key, None) = 删除
+type PendingOps = Vec<(Vec<u8>, Option<Vec<f32>>)>;
+
+/// HNSW 图，一个索引一个
+///
+/// - 覆盖写 / 删除只把旧节点标记为 deleted（墓碑），搜索立即跳过，但仍可经过它
+/// - `repair` 把墓碑从邻居表里摘掉，并用墓碑的邻居补位，保持连通性
+/// - deleted 比例高了以后由后台重建（见 `begin_rebuild` / `finish_rebuild`）
+/// - 配了 `SpillTarget` 时，建好的图 `spill` 到磁盘（`base`），内存里只剩之后写入的增量；
+///   base 里被覆盖 / 删除的 key 记在 `superseded`，搜索时过滤掉
+pub struct HnswIndex {
+    metric: Metric,
+    params: HnswParams,
+    nodes: Vec<Node>,
+    by_key: HashMap<Vec<u8>, u32>,
+    entry: Option<u32>,
+    deleted: usize,
+    /// 还没 repair 过的墓碑数
+    unrepaired: usize,
+    /// 已经排了 repair 任务，还没执行
+    repair_scheduled: bool,
+    rng: u64,
+    /// Some = 正在后台重建，期间的修改记下来重放到新图上
+    pending: Option<PendingOps>,
+    built_at: SystemTime,
+    spill: Option<SpillTarget>,
+    base: Option<Arc<DiskGraph>>,
+    superseded: HashSet<Vec<u8>>,
+}
+
+impl HnswIndex {
+    pub fn new(metric: Metric, params: HnswParams) -> Self {
+        Self {
+            metric,
+            params,
+            nodes: Vec::new(),
+            by_key: HashMap::new(),
+            entry: None,
+            deleted: 0,
+            unrepaired: 0,
+            repair_scheduled: false,
+            rng: 0x9E37_79B9_7F4A_7C15,
+            pending: None,
+            built_at: SystemTime::now(),
+            spill: None,
+            base: None,
+            superseded: HashSet::new(),
+        }
+    }
+
+    /// 建好后可以 `spill` 到 `target`
+    pub fn with_spill(mut self, target: SpillTarget) -> Self {
+        self.spill = Some(target);
+        self
+    }
+
+    /// 同样配置的空图，重建用
+    pub fn new_like(&self) -> Self {
+        let index = HnswIndex::new(self.metric, self.params.clone());
+        match &self.spill {
+            Some(t) => index.with_spill(t.clone()),
+            None => index,
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn params(&self) -> &HnswParams {
+        &self.params
+    }
+
+    /// 活着的向量数；有落盘部分时是估算（superseded 里也可能有新 key）
+    pub fn len(&self) -> usize {
+        let on_disk = self.base.as_ref().map_or(0, |b| b.len());
+        (self.nodes.len() - self.deleted) + on_disk.saturating_sub(self.superseded.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn deleted_ratio(&self) -> f64 {
+        let total = self.nodes.len() + self.base.as_ref().map_or(0, |b| b.len());
+        let dead = self.deleted + self.superseded.len();
+        if total == 0 { 0.0 } else { dead as f64 / total as f64 }
+    }
+
+    pub fn built_at(&self) -> SystemTime {
+        self.built_at
+    }
+
+    pub fn unrepaired(&self) -> usize {
+        self.unrepaired
+    }
+
+    /// 标记 repair 已排队；已经排过时返回 false，避免重复排任务
+    pub fn request_repair(&mut self) -> bool {
+        !std::mem::replace(&mut self.repair_scheduled, true)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.by_key.contains_key(key)
+    }
+
+    pub fn is_rebuilding(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn insert(&mut self, key: &[u8], vector: Vec<f32>) {
+        if let Some(p) = self.pending.as_mut() {
+            p.push((key.to_vec(), Some(vector.clone())));
+        }
+        self.mark_deleted(key);
+        if self.base.is_some() {
+            self.superseded.insert(key.to_vec());
+        }
+
+        let id = self.nodes.len() as u32;
+        let level = self.random_level();
+        self.nodes.push(Node {
+            key: key.to_vec(),
+            vector,
+            neighbors: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.by_key.insert(key.to_vec(), id);
+
+        let Some(mut ep) = self.entry else {
+            self.entry = Some(id);
+            return;
+        };
+        let top = self.nodes[ep as usize].level();
+        let query = self.nodes[id as usize].vector.clone();
+
+        // 1) 新节点层数以上：贪心下降找入口
+        for l in (level + 1..=top).rev() {
+            ep = self.greedy_closest(&query, ep, l);
+        }
+
+        // 2) 新节点所在的每一层：找 ef_construction 个候选，连 m 个最近的
+        let mut entry_points = vec![ep];
+        for l in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, self.params.ef_construction, l);
+            // 墓碑可以经过，但不能当新邻居，否则 repair 之后又被连回来
+            let chosen: Vec<u32> = candidates
+                .iter()
+                .filter(|c| c.id != id && !self.nodes[c.id as usize].deleted)
+                .take(self.params.m)
+                .map(|c| c.id)
+                .collect();
+
+            let max_degree = self.max_degree(l);
+            for &n in &chosen {
+                self.nodes[n as usize].neighbors[l].push(id);
+                if self.nodes[n as usize].neighbors[l].len() > max_degree {
+                    self.prune(n, l, max_degree);
+                }
+            }
+            self.nodes[id as usize].neighbors[l] = chosen;
+            entry_points = candidates.iter().map(|c| c.id).collect();
+        }
+
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    /// 返回 key 是否在内存部分里
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        if let Some(p) = self.pending.as_mut() {
+            p.push((key.to_vec(), None));
+        }
+        if self.base.is_some() {
+            self.superseded.insert(key.to_vec());
+        }
+        self.mark_deleted(key)
+    }
+
+    /// 最相似的（至多）k 个活节点：(key, vector, score)，score 降序
+    ///
+    /// 落盘部分读盘出错时返回 Err
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Result<Vec<(Vec<u8>, Vec<f32>, f32)>, DBError> {
+        let mut hits: Vec<(Vec<u8>, Vec<f32>, f32)> = Vec::new();
+        if let Some(mut ep) = self.entry {
+            for l in (1..=self.nodes[ep as usize].level()).rev() {
+                ep = self.greedy_closest(query, ep, l);
+            }
+            hits.extend(
+                self.search_layer(query, &[ep], ef.max(k), 0)
+                    .into_iter()
+                    .filter(|c| !self.nodes[c.id as usize].deleted)
+                    .take(k)
+                    .map(|c| {
+                        let n = &self.nodes[c.id as usize];
+                        (n.key.clone(), n.vector.clone(), c.score)
+                    }),
+            );
+        }
+        if let Some(base) = &self.base {
+            hits.extend(base.search(query, k, ef, |key| self.superseded.contains(key))?);
+            hits.sort_by(|a, b| b.2.total_cmp(&a.2));
+            hits.truncate(k);
+        }
+        Ok(hits)
+    }
+
+    /// 把当前图写成磁盘图，内存里只留空的增量图。没配 `SpillTarget` 时返回 false
+    pub fn spill(&mut self) -> Result<bool, DBError> {
+        let Some(target) = self.spill.clone() else { return Ok(false) };
+        if self.base.is_some() {
+            return Err(DBError::InvalidArgument("vector graph is already on disk; rebuild it first".into()));
+        }
+
+        // 只写活节点，id 重新编号，指向墓碑的边丢掉
+        let live: Vec<u32> = (0..self.nodes.len() as u32).filter(|&i| !self.nodes[i as usize].deleted).collect();
+        let remap: HashMap<u32, u32> = live.iter().enumerate().map(|(new, &old)| (old, new as u32)).collect();
+        let nodes: Vec<GraphNode> = live
+            .iter()
+            .map(|&old| {
+                let n = &self.nodes[old as usize];
+                GraphNode {
+                    key: &n.key,
+                    vector: &n.vector,
+                    neighbors: n
+                        .neighbors
+                        .iter()
+                        .map(|l| l.iter().filter_map(|id| remap.get(id).copied()).collect())
+                        .collect(),
+                }
+            })
+            .collect();
+        let entry = match self.entry {
+            Some(e) if !self.nodes[e as usize].deleted => Some(e),
+            _ => live.iter().copied().max_by_key(|&i| self.nodes[i as usize].level()),
+        }
+        .and_then(|e| remap.get(&e).copied());
+
+        std::fs::create_dir_all(&target.dir)?;
+        let path = target.dir.join(format!(
+            "{}-{:06}.vgraph",
+            target.prefix,
+            NEXT_SPILL_FILE.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let graph = DiskGraph::create(&path, self.metric, self.max_degree(0), &nodes, entry, target.cache)?;
+        drop(nodes);
+
+        self.nodes.clear();
+        self.by_key.clear();
+        self.entry = None;
+        self.deleted = 0;
+        self.unrepaired = 0;
+        self.base = Some(Arc::new(graph));
+        Ok(true)
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// 把墓碑从所有活节点的邻居表里摘掉，用墓碑自己的（活）邻居补位后再裁剪，
+    /// 返回处理掉的墓碑数。之后墓碑不可达，内存要等下一次重建才回收
+    pub fn repair(&mut self) -> usize {
+        self.repair_scheduled = false;
+        if self.unrepaired == 0 {
+            return 0;
+        }
+
+        // 1) 入口点是墓碑：换成层数最高的活节点
+        if self.entry.is_some_and(|e| self.nodes[e as usize].deleted) {
+            self.entry = (0..self.nodes.len() as u32)
+                .filter(|&i| !self.nodes[i as usize].deleted)
+                .max_by_key(|&i| self.nodes[i as usize].level());
+        }
+
+        // 2) 指向墓碑的边：换成墓碑的邻居
+        for id in 0..self.nodes.len() as u32 {
+            if self.nodes[id as usize].deleted {
+                continue;
+            }
+            for l in 0..self.nodes[id as usize].neighbors.len() {
+                let links = &self.nodes[id as usize].neighbors[l];
+                if !links.iter().any(|&n| self.nodes[n as usize].deleted) {
+                    continue;
+                }
+                let mut relinked: Vec<u32> = Vec::with_capacity(links.len());
+                let mut seen: HashSet<u32> = HashSet::new();
+                for &n in links {
+                    let node = &self.nodes[n as usize];
+                    let replacement: &[u32] = if node.deleted { &node.neighbors[l] } else { std::slice::from_ref(&n) };
+                    for &r in replacement {
+                        if r != id && !self.nodes[r as usize].deleted && seen.insert(r) {
+                            relinked.push(r);
+                        }
+                    }
+                }
+                self.nodes[id as usize].neighbors[l] = relinked;
+                let max_degree = self.max_degree(l);
+                if self.nodes[id as usize].neighbors[l].len() > max_degree {
+                    self.prune(id, l, max_degree);
+                }
+            }
+        }
+
+        // 3) 墓碑的出边清空：已经不可达，也不会再被当作补位来源
+        for node in self.nodes.iter_mut().filter(|n| n.deleted) {
+            node.neighbors.iter_mut().for_each(Vec::clear);
+        }
+
+        std::mem::take(&mut self.unrepaired)
+    }
+
+    pub fn stats(&self) -> HnswStats {
+        let mut level_histogram = Vec::new();
+        let mut degree_sum = 0usize;
+        let mut memory_bytes = 0usize;
+        for node in &self.nodes {
+            memory_bytes += std::mem::size_of::<Node>()
+                + node.key.len()
+                + node.vector.len() * 4
+                + node.neighbors.iter().map(|l| std::mem::size_of::<Vec<u32>>() + l.len() * 4).sum::<usize>();
+            if node.deleted {
+                continue;
+            }
+            if level_histogram.len() <= node.level() {
+                level_histogram.resize(node.level() + 1, 0);
+            }
+            level_histogram[node.level()] += 1;
+            degree_sum += node.neighbors[0].len();
+        }
+        memory_bytes += self.by_key.keys().map(|k| k.len() + std::mem::size_of::<(Vec<u8>, u32)>()).sum::<usize>();
+        memory_bytes += self.superseded.iter().map(|k| k.len() + std::mem::size_of::<Vec<u8>>()).sum::<usize>();
+        memory_bytes += self.base.as_ref().map_or(0, |b| b.resident_bytes());
+
+        let in_memory = self.nodes.len() - self.deleted;
+        HnswStats {
+            nodes: self.len(),
+            deleted: self.deleted,
+            unrepaired: self.unrepaired,
+            avg_degree: if in_memory == 0 { 0.0 } else { degree_sum as f64 / in_memory as f64 },
+            level_histogram,
+            memory_bytes,
+            built_at_ms: self.built_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            rebuilding: self.is_rebuilding(),
+            on_disk_nodes: self.base.as_ref().map_or(0, |b| b.len()),
+            superseded: self.superseded.len(),
+        }
+    }
+
+    /// 开始后台重建：之后的修改会被记下，`finish_rebuild` 时重放
+    pub fn begin_rebuild(&mut self) {
+        self.pending = Some(Vec::new());
+    }
+
+    /// 活节点的拷贝，用来在锁外建新图；落盘部分要整个读一遍
+    pub fn live_entries(&self) -> Result<Vec<(Vec<u8>, Vec<f32>)>, DBError> {
+        let mut out: Vec<(Vec<u8>, Vec<f32>)> = Vec::with_capacity(self.len());
+        if let Some(base) = &self.base {
+            base.for_each(|key, vector| {
+                if !self.superseded.contains(&key) {
+                    out.push((key, vector));
+                }
+            })?;
+        }
+        out.extend(
+            self.nodes
+                .iter()
+                .filter(|n| !n.deleted)
+                .map(|n| (n.key.clone(), n.vector.clone())),
+        );
+        Ok(out)
+    }
+
+    /// 用重建好的图替换自己，并补上重建期间的修改
+    pub fn finish_rebuild(&mut self, mut rebuilt: HnswIndex) {
+        for (key, op) in self.pending.take().unwrap_or_default() {
+            match op {
+                Some(vector) => rebuilt.insert(&key, vector),
+                None => {
+                    rebuilt.remove(&key);
+                }
+            }
+        }
+        *self = rebuilt;
+    }
+
+    /// 放弃重建（比如扫描出错），丢掉记下的修改
+    pub fn abort_rebuild(&mut self) {
+        self.pending = None;
+    }
+
+    fn mark_deleted(&mut self, key: &[u8]) -> bool {
+        match self.by_key.remove(key) {
+            Some(id) => {
+                self.nodes[id as usize].deleted = true;
+                self.deleted += 1;
+                self.unrepaired += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn max_degree(&self, level: usize) -> usize {
+        if level == 0 { self.params.m * 2 } else { self.params.m }
+    }
+
+    fn score(&self, query: &[f32], id: u32) -> f32 {
+        self.metric
+            .score(query, &self.nodes[id as usize].vector)
+            .unwrap_or(f32::NEG_INFINITY)
+    }
+
+    /// 层数服从几何分布：P(level >= l) = (1/m)^l
+    fn random_level(&mut self) -> usize {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let r = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        let u = ((r >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.params.m.max(2) as f64).ln();
+        ((-u.ln() * ml) as usize).min(MAX_LEVEL)
+    }
+
+    fn greedy_closest(&self, query: &[f32], mut ep: u32, level: usize) -> u32 {
+        let mut best = self.score(query, ep);
+        loop {
+            let mut changed = false;
+            for &n in &self.nodes[ep as usize].neighbors[level] {
+                let s = self.score(query, n);
+                if s > best {
+                    best = s;
+                    ep = n;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return ep;
+            }
+        }
+    }
+
+    /// 单层 best-first 搜索，返回至多 ef 个候选，score 降序（含 deleted 节点）
+    fn search_layer(&self, query: &[f32], entry_points: &[u32], ef: usize, level: usize) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        let mut results: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+
+        for &ep in entry_points {
+            if visited.insert(ep) {
+                let s = Scored { score: self.score(query, ep), id: ep };
+                candidates.push(s);
+                results.push(Reverse(s));
+                if results.len() > ef {
+                    results.pop();
+                }
+            }
+        }
+
+        while let Some(c) = candidates.pop() {
+            let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.score);
+            if results.len() >= ef && c.score < worst {
+                break;
+            }
+            for &n in &self.nodes[c.id as usize].neighbors[level] {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let s = Scored { score: self.score(query, n), id: n };
+                let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.score);
+                if results.len() < ef || s.score > worst {
+                    candidates.push(s);
+                    results.push(Reverse(s));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut out: Vec<Scored> = results.into_iter().map(|r| r.0).collect();
+        out.sort_by(|a, b| b.cmp(a));
+        out
+    }
+
+    /// 邻居超过上限时只保留离 `node` 最近的 `max_degree` 个
+    fn prune(&mut self, node: u32, level: usize, max_degree: usize) {
+        let base = self.nodes[node as usize].vector.clone();
+        let mut scored: Vec<Scored> = self.nodes[node as usize].neighbors[level]
+            .iter()
+            .map(|&n| Scored { score: self.score(&base, n), id: n })
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(max_degree);
+        self.nodes[node as usize].neighbors[level] = scored.into_iter().map(|s| s.id).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 确定性的伪随机点
+    fn points(n: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x1234_5678u64;
+        (0..n)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        (state >> 40) as f32 / (1u64 << 24) as f32
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("v{:04}", i).into_bytes()
+    }
+
+    fn build(points: &[Vec<f32>]) -> HnswIndex {
+        let mut index = HnswIndex::new(Metric::L2, HnswParams { m: 8, ef_construction: 64, ef_search: 32 });
+        for (i, p) in points.iter().enumerate() {
+            index.insert(&key(i), p.clone());
+        }
+        index
+    }
+
+    fn exact(points: &[Vec<f32>], query: &[f32], k: usize, skip: impl Fn(usize) -> bool) -> Vec<Vec<u8>> {
+        let mut scored: Vec<(f32, usize)> = (0..points.len())
+            .filter(|&i| !skip(i))
+            .map(|i| (Metric::L2.score(query, &points[i]).unwrap(), i))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(k).map(|(_, i)| key(i)).collect()
+    }
+
+    fn recall(index: &HnswIndex, points: &[Vec<f32>], skip: impl Fn(usize) -> bool + Copy) -> f64 {
+        let queries = &points[..20];
+        let mut found = 0;
+        for q in queries {
+            let truth = exact(points, q, 10, skip);
+            let hits = index.search(q, 10, 64).unwrap();
+            found += hits.iter().filter(|(k, _, _)| truth.contains(k)).count();
+        }
+        found as f64 / (queries.len() * 10) as f64
+    }
+
+    #[test]
+    fn search_finds_the_nearest_neighbours() {
+        let points = points(500, 8);
+        let index = build(&points);
+        assert_eq!(index.len(), 500);
+        assert!(recall(&index, &points, |_| false) > 0.9);
+
+        let hits = index.search(&points[7], 3, 32).unwrap();
+        assert_eq!(hits[0].0, key(7));
+        assert_eq!(hits[0].2, 0.0);
+        assert!(hits.windows(2).all(|w| w[0].2 >= w[1].2));
+    }
+
+    #[test]
+    fn deleted_and_overwritten_keys_are_skipped_and_repaired() {
+        let points = points(300, 8);
+        let mut index = build(&points);
+        for i in (0..300).step_by(3) {
+            assert!(index.remove(&key(i)));
+        }
+        assert!(!index.remove(b"missing"));
+        // 覆盖写：旧节点变墓碑
+        index.insert(&key(1), vec![100.0; 8]);
+        assert_eq!((index.len(), index.stats().deleted), (200, 101));
+        assert!((index.deleted_ratio() - 101.0 / 301.0).abs() < 1e-9);
+
+        let skip = |i: usize| i % 3 == 0 || i == 1;
+        let hits = index.search(&points[0], 10, 64).unwrap();
+        assert!(hits.iter().all(|(k, _, _)| *k != key(0)));
+        assert_eq!(index.search(&[100.0; 8], 1, 16).unwrap()[0].0, key(1));
+
+        assert!(index.request_repair());
+        assert!(!index.request_repair());
+        assert_eq!(index.repair(), 101);
+        assert_eq!(index.unrepaired(), 0);
+        assert!(recall(&index, &points, skip) > 0.85);
+        assert!(index.request_repair());
+    }
+
+    #[test]
+    fn writes_during_a_rebuild_are_replayed() {
+        let points = points(100, 4);
+        let mut index = build(&points);
+        index.remove(&key(0));
+        index.begin_rebuild();
+        assert!(index.is_rebuilding());
+
+        let entries = index.live_entries().unwrap();
+        assert_eq!(entries.len(), 99);
+        let mut rebuilt = index.new_like();
+        for (k, v) in entries {
+            rebuilt.insert(&k, v);
+        }
+
+        // 重建期间的写
+        index.insert(b"new", vec![9.0; 4]);
+        index.remove(&key(5));
+        index.finish_rebuild(rebuilt);
+
+        assert!(!index.is_rebuilding());
+        assert_eq!(index.stats().deleted, 1);
+        assert_eq!(index.len(), 99);
+        assert!(index.contains(b"new") && !index.contains(&key(5)) && !index.contains(&key(0)));
+
+        index.begin_rebuild();
+        index.insert(b"lost", vec![1.0; 4]);
+        index.abort_rebuild();
+        assert!(!index.is_rebuilding());
+        assert!(index.contains(b"lost"));
+    }
+
+    #[test]
+    fn stats_describe_the_graph() {
+        let points = points(200, 4);
+        let mut index = build(&points);
+        index.remove(&key(3));
+        let stats = index.stats();
+        assert_eq!((stats.nodes, stats.deleted, stats.unrepaired), (199, 1, 1));
+        assert_eq!(stats.level_histogram.iter().sum::<usize>(), 199);
+        assert!(stats.level_histogram[0] > stats.level_histogram.get(1).copied().unwrap_or(0));
+        assert!(stats.avg_degree > 1.0 && stats.avg_degree <= 16.0);
+        assert!(stats.memory_bytes > 200 * 4 * 4);
+        assert!(!stats.rebuilding);
+        assert_eq!((stats.on_disk_nodes, stats.superseded), (0, 0));
+        assert_eq!(HnswIndex::new(Metric::Cosine, HnswParams::default()).stats().avg_degree, 0.0);
+    }
+
+    #[test]
+    fn spilled_graph_is_searched_with_the_delta() {
+        let dir = std::env::temp_dir().join(format!("vectorkv-hnsw-spill-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let points = points(300, 8);
+        let target = SpillTarget { dir: dir.clone(), prefix: "cf0".into(), cache: Arc::new(GraphPageCache::new(64 * 1024, 4)) };
+        let mut index = build(&points).with_spill(target);
+        index.remove(&key(0));
+
+        assert!(index.spill().unwrap());
+        assert!(index.is_spilled());
+        assert!(index.spill().is_err());
+        let stats = index.stats();
+        assert_eq!((stats.on_disk_nodes, stats.nodes), (299, 299));
+        assert!(recall(&index, &points, |i| i == 0) > 0.85);
+
+        // 落盘之后的写进增量图，盘上的旧版本被过滤掉
+        index.insert(&key(10), vec![50.0; 8]);
+        index.remove(&key(20));
+        assert_eq!(index.stats().superseded, 2);
+        assert_eq!(index.search(&[50.0; 8], 1, 16).unwrap()[0].0, key(10));
+        let near_old = index.search(&points[10], 5, 64).unwrap();
+        assert!(near_old.iter().all(|(k, v, _)| *k != key(20) && (*k != key(10) || v[0] == 50.0)));
+
+        let entries = index.live_entries().unwrap();
+        assert_eq!(entries.len(), 298);
+        assert_eq!(entries.iter().filter(|(k, _)| *k == key(10)).count(), 1);
+
+        // 没配落盘的图不 spill
+        assert!(!build(&points[..10]).spill().unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// 向量相似度。score 越大越相似，score_threshold 按 `>=` 过滤
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// score = -欧氏距离
+    #[default]
+    L2,
+    /// score = 余弦相似度，[-1, 1]
+    Cosine,
+    /// score = 内积
+    InnerProduct,
+}
+
+impl Metric {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "l2" => Some(Metric::L2),
+            "cosine" => Some(Metric::Cosine),
+            "ip" | "inner_product" => Some(Metric::InnerProduct),
+            _ => None,
+        }
+    }
+
+    /// 维度不同时返回 None
+    pub fn score(self, a: &[f32], b: &[f32]) -> Option<f32> {
+        if a.len() != b.len() {
+            return None;
+        }
+        Some(match self {
+            Metric::L2 => {
+                let d: f32 = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum();
+                -d.sqrt()
+            }
+            Metric::Cosine => {
+                let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+                let na: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+                let nb: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if na == 0.0 || nb == 0.0 { 0.0 } else { dot / (na * nb) }
+            }
+            Metric::InnerProduct => a.iter().zip(b).map(|(x, y)| x * y).sum(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_grow_with_similarity() {
+        let (a, b) = ([1.0f32, 0.0], [0.0f32, 2.0]);
+        assert_eq!(Metric::L2.score(&a, &a), Some(0.0));
+        assert_eq!(Metric::L2.score(&[0.0, 0.0], &[3.0, 4.0]), Some(-5.0));
+        assert_eq!(Metric::Cosine.score(&a, &b), Some(0.0));
+        assert_eq!(Metric::Cosine.score(&b, &[0.0, 5.0]), Some(1.0));
+        assert_eq!(Metric::Cosine.score(&a, &[0.0, 0.0]), Some(0.0));
+        assert_eq!(Metric::InnerProduct.score(&[1.0, 2.0], &[3.0, 4.0]), Some(11.0));
+        assert_eq!(Metric::L2.score(&a, &[1.0]), None);
+    }
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(Metric::parse("L2"), Some(Metric::L2));
+        assert_eq!(Metric::parse("cosine"), Some(Metric::Cosine));
+        assert_eq!(Metric::parse("ip"), Some(Metric::InnerProduct));
+        assert_eq!(Metric::parse("inner_product"), Some(Metric::InnerProduct));
+        assert_eq!(Metric::parse("hamming"), None);
+    }
+}
@@ -0,0 +1,15 @@
+mod metric;
+mod codec;
+mod search;
+mod embedder;
+mod hnsw;
+mod disk_graph;
+mod calibrate;
+
+pub use metric::Metric;
+pub use codec::{decode_indexed_vector, decode_vector, decode_vector_column, encode_vector, encode_vector_columns};
+pub use embedder::{embed_all, Embedder};
+pub use hnsw::{HnswIndex, HnswParams, HnswStats, SpillTarget, VectorIndexType};
+pub use disk_graph::{DiskGraph, GraphPage, GraphPageCache};
+pub use search::{KeyFilter, KnnHit, KnnRequest, KnnResponse, PageToken, SearchOptions, TopK};
+pub use calibrate::{calibrate, CalibrationPoint, CalibrationReport, DEFAULT_EF_CANDIDATES};
@@ -0,0 +1,301 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use crate::error::DBError;
+use crate::vector::Metric;
+
+/// 候选 key 的过滤条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyFilter {
+    Prefix(Vec<u8>),
+    /// [start, end)
+    Range { start: Vec<u8>, end: Vec<u8> },
+}
+
+impl KeyFilter {
+    /// `prefix:<p>` 或 `range:<start>..<end>`
+    pub fn parse(expr: &str) -> Result<Self, DBError> {
+        if let Some(p) = expr.strip_prefix("prefix:") {
+            return Ok(KeyFilter::Prefix(p.as_bytes().to_vec()));
+        }
+        if let Some(r) = expr.strip_prefix("range:") {
+            if let Some((start, end)) = r.split_once("..") {
+                return Ok(KeyFilter::Range {
+                    start: start.as_bytes().to_vec(),
+                    end: end.as_bytes().to_vec(),
+                });
+            }
+        }
+        Err(DBError::InvalidArgument(format!("bad filter expression: {}", expr)))
+    }
+
+    pub fn matches(&self, key: &[u8]) -> bool {
+        match self {
+            KeyFilter::Prefix(p) => key.starts_with(p),
+            KeyFilter::Range { start, end } => key >= start.as_slice() && key < end.as_slice(),
+        }
+    }
+
+    /// 扫描时可以只看这个前缀下的 key
+    pub fn scan_prefix(&self) -> &[u8] {
+        match self {
+            KeyFilter::Prefix(p) => p,
+            KeyFilter::Range { .. } => b"",
+        }
+    }
+}
+
+/// 单次查询的召回 / 延迟参数，None = 用 CF 的配置
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    /// HNSW 候选队列长度：越大召回越高，也越慢
+    pub ef_search: Option<usize>,
+    /// IVF 探测的簇数；目前只有 flat / hnsw，这个值会被忽略
+    pub nprobe: Option<usize>,
+    /// 不走索引，全量扫描出精确结果
+    pub exhaustive: bool,
+    /// 索引给出的结果不足 k 条（过滤条件 / 阈值太严）时退回全量扫描
+    pub exhaustive_fallback: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct KnnRequest {
+    pub query: Vec<f32>,
+    pub k: usize,
+    /// 按名字选索引（`VectorOptions::indexes`），None = 默认索引
+    pub index: Option<String>,
+    /// None = 用索引配置的 metric
+    pub metric: Option<Metric>,
+    /// 只返回 score >= threshold 的结果
+    pub score_threshold: Option<f32>,
+    pub filter: Option<KeyFilter>,
+    /// 上一页返回的 `next_page_token`
+    pub page_token: Option<String>,
+    pub with_payload: bool,
+    pub search: SearchOptions,
+}
+
+impl KnnRequest {
+    pub fn new(query: Vec<f32>, k: usize) -> Self {
+        Self {
+            query,
+            k,
+            index: None,
+            metric: None,
+            score_threshold: None,
+            filter: None,
+            page_token: None,
+            with_payload: false,
+            search: SearchOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnnHit {
+    pub key: Vec<u8>,
+    pub score: f32,
+    pub payload: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KnnResponse {
+    /// score 降序，score 相同按 key 升序
+    pub hits: Vec<KnnHit>,
+    /// 还有下一页时非空
+    pub next_page_token: Option<String>,
+}
+
+/// 分页游标：上一页最后一条的 (score, key)，hex 编码
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageToken {
+    pub score: f32,
+    pub key: Vec<u8>,
+}
+
+impl PageToken {
+    pub fn encode(&self) -> String {
+        let mut bytes = self.score.to_bits().to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.key);
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(token: &str) -> Result<Self, DBError> {
+        let bad = || DBError::InvalidArgument(format!("bad page token: {}", token));
+        if token.len() % 2 != 0 || token.len() < 8 {
+            return Err(bad());
+        }
+        let bytes = (0..token.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&token[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| bad())?;
+        Ok(Self {
+            score: f32::from_bits(u32::from_be_bytes(bytes[..4].try_into().unwrap())),
+            key: bytes[4..].to_vec(),
+        })
+    }
+
+    /// 结果顺序里 (score, key) 是否排在游标之后
+    fn is_after(&self, score: f32, key: &[u8]) -> bool {
+        score < self.score || (score == self.score && key > self.key.as_slice())
+    }
+}
+
+/// 结果顺序：score 大的在前，相同 score 时 key 小的在前
+fn rank(a: &KnnHit, b: &KnnHit) -> Ordering {
+    b.score.total_cmp(&a.score).then_with(|| a.key.cmp(&b.key))
+}
+
+struct Ranked(KnnHit);
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        rank(&self.0, &other.0) == Ordering::Equal
+    }
+}
+impl Eq for Ranked {}
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Ranked {
+    // 堆顶 = 当前 top-k 里排最后的那个
+    fn cmp(&self, other: &Self) -> Ordering {
+        rank(&self.0, &other.0)
+    }
+}
+
+/// 暴力 top-k：逐个喂候选，保留 k + 1 个用来判断是否还有下一页
+pub struct TopK {
+    k: usize,
+    metric: Metric,
+    threshold: Option<f32>,
+    after: Option<PageToken>,
+    heap: BinaryHeap<Ranked>,
+}
+
+impl TopK {
+    pub fn new(request: &KnnRequest, metric: Metric) -> Result<Self, DBError> {
+        if request.k == 0 {
+            return Err(DBError::InvalidArgument("k must be > 0".into()));
+        }
+        let after = match &request.page_token {
+            Some(t) => Some(PageToken::decode(t)?),
+            None => None,
+        };
+        Ok(Self {
+            k: request.k,
+            metric,
+            threshold: request.score_threshold,
+            after,
+            heap: BinaryHeap::with_capacity(request.k + 1),
+        })
+    }
+
+    /// 维度不匹配的向量直接跳过
+    pub fn offer(&mut self, query: &[f32], key: &[u8], vector: &[f32], payload: Option<&[u8]>) {
+        let Some(score) = self.metric.score(query, vector) else { return };
+        if self.threshold.is_some_and(|t| score < t) {
+            return;
+        }
+        if self.after.as_ref().is_some_and(|a| !a.is_after(score, key)) {
+            return;
+        }
+        let hit = KnnHit { key: key.to_vec(), score, payload: payload.map(|p| p.to_vec()) };
+        if self.heap.len() == self.k + 1 {
+            match self.heap.peek() {
+                Some(worst) if rank(&hit, &worst.0) == Ordering::Less => {
+                    self.heap.pop();
+                }
+                _ => return,
+            }
+        }
+        self.heap.push(Ranked(hit));
+    }
+
+    pub fn finish(self) -> KnnResponse {
+        let mut hits: Vec<KnnHit> = self.heap.into_iter().map(|r| r.0).collect();
+        hits.sort_by(rank);
+        let has_more = hits.len() > self.k;
+        hits.truncate(self.k);
+        let next_page_token = match hits.last() {
+            Some(last) if has_more => Some(PageToken { score: last.score, key: last.key.clone() }.encode()),
+            _ => None,
+        };
+        KnnResponse { hits, next_page_token }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(response: &KnnResponse) -> Vec<&[u8]> {
+        response.hits.iter().map(|h| h.key.as_slice()).collect()
+    }
+
+    /// 一维向量，key i 在 i 处：离 0 越近 score 越高
+    fn run(request: &KnnRequest) -> KnnResponse {
+        let mut top = TopK::new(request, Metric::L2).unwrap();
+        for i in 0..10u8 {
+            let key = [b'a' + i];
+            top.offer(&request.query, &key, &[i as f32], Some(&key));
+        }
+        // 维度不对的跳过
+        top.offer(&request.query, b"z", &[0.0, 0.0], None);
+        top.finish()
+    }
+
+    #[test]
+    fn key_filters() {
+        let prefix = KeyFilter::parse("prefix:user:").unwrap();
+        assert!(prefix.matches(b"user:1") && !prefix.matches(b"item:1"));
+        assert_eq!(prefix.scan_prefix(), b"user:");
+
+        let range = KeyFilter::parse("range:b..d").unwrap();
+        assert!(range.matches(b"b") && range.matches(b"cz") && !range.matches(b"d"));
+        assert_eq!(range.scan_prefix(), b"");
+
+        assert!(matches!(KeyFilter::parse("range:bd"), Err(DBError::InvalidArgument(_))));
+        assert!(KeyFilter::parse("suffix:x").is_err());
+    }
+
+    #[test]
+    fn top_k_pages_through_the_results() {
+        let mut request = KnnRequest::new(vec![0.0], 4);
+        request.with_payload = true;
+        let first = run(&request);
+        assert_eq!(keys(&first), vec![b"a", b"b", b"c", b"d"]);
+        assert_eq!(first.hits[1].payload.as_deref(), Some(&b"b"[..]));
+
+        request.page_token = first.next_page_token.clone();
+        let second = run(&request);
+        assert_eq!(keys(&second), vec![b"e", b"f", b"g", b"h"]);
+
+        request.page_token = second.next_page_token;
+        let last = run(&request);
+        assert_eq!(keys(&last), vec![b"i", b"j"]);
+        assert_eq!(last.next_page_token, None);
+    }
+
+    #[test]
+    fn ties_threshold_and_bad_requests() {
+        // 同分按 key 升序
+        let mut request = KnnRequest::new(vec![4.5], 2);
+        assert_eq!(keys(&run(&request)), vec![b"e", b"f"]);
+
+        request.k = 10;
+        request.score_threshold = Some(-1.0);
+        assert_eq!(keys(&run(&request)), vec![b"e", b"f"]);
+
+        request.k = 0;
+        assert!(TopK::new(&request, Metric::L2).is_err());
+        request.k = 1;
+        request.page_token = Some("xyz".into());
+        assert!(TopK::new(&request, Metric::L2).is_err());
+
+        let token = PageToken { score: -1.5, key: b"k\x00".to_vec() };
+        assert_eq!(PageToken::decode(&token.encode()).unwrap(), token);
+    }
+}